@@ -21,9 +21,12 @@ class CommandMaker:
         return 'cargo build --quiet --release --features benchmark'
 
     @staticmethod
-    def generate_key(filename):
+    def generate_key(filename, seed=None, index=0):
         assert isinstance(filename, str)
-        return f'./node generate_keys --filename {filename}'
+        assert seed is None or isinstance(seed, int)
+        assert isinstance(index, int)
+        seed = '' if seed is None else f' --seed {seed} --index {index}'
+        return f'./node generate_keys --filename {filename}{seed}'
 
     @staticmethod
     def run_primary(keys, committee, store, parameters, debug=False):
@@ -57,7 +60,8 @@ class CommandMaker:
 
     @staticmethod
     def kill():
-        return 'tmux kill-server'
+        # Let the nodes flush their store (on SIGTERM) before tearing down their sessions.
+        return 'pkill -TERM -f "^./node .* run " ; sleep 1 ; tmux kill-server'
 
     @staticmethod
     def alias_binaries(origin):
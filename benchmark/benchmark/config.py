@@ -216,6 +216,9 @@ class BenchParameters:
             self.duration = int(json['duration'])
 
             self.runs = int(json['runs']) if 'runs' in json else 1
+
+            # Derive the keys from this seed to get the same committee across runs.
+            self.seed = int(json['seed']) if 'seed' in json else None
         except KeyError as e:
             raise ConfigError(f'Malformed bench parameters: missing key {e}')
 
@@ -30,8 +30,8 @@ class LocalBench:
 
     def _kill_nodes(self):
         try:
-            cmd = CommandMaker.kill().split()
-            subprocess.run(cmd, stderr=subprocess.DEVNULL)
+            cmd = CommandMaker.kill()
+            subprocess.run(cmd, shell=True, stderr=subprocess.DEVNULL)
         except subprocess.SubprocessError as e:
             raise BenchError('Failed to kill testbed', e)
 
@@ -62,8 +62,8 @@ class LocalBench:
             # 4. Generate configuration files.
             keys = []
             key_files = [PathMaker.key_file(i) for i in range(nodes)]
-            for filename in key_files:
-                cmd = CommandMaker.generate_key(filename).split()
+            for i, filename in enumerate(key_files):
+                cmd = CommandMaker.generate_key(filename, self.seed, i).split()
                 subprocess.run(cmd, check=True)
                 keys += [Key.from_file(filename)]
 
@@ -0,0 +1,311 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commits::{CommitInfo, CommitWatcher, Watch, NO_REPLAY};
+use crate::error::{ClientError, ClientResult};
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::{SplitSink, SplitStream, StreamExt as _};
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::convert::TryInto as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/client_tests.rs"]
+pub mod client_tests;
+
+/// The first frame we send to a worker, asking it to acknowledge our transactions. It must match
+/// `worker::TRANSACTION_BANNER` (we do not depend on the worker to keep this crate light).
+pub const TRANSACTION_BANNER: &[u8] = b"transactions";
+
+/// The first frame we send to a worker, asking it to advertise its window and to acknowledge our
+/// transactions. It must match `worker::WINDOWED_TRANSACTION_BANNER`.
+pub const WINDOWED_TRANSACTION_BANNER: &[u8] = b"windowed-transactions";
+
+/// The default number of submissions awaiting their acknowledgement.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1_000;
+
+/// How long to wait for a worker to advertise its window (in ms).
+const WINDOW_TIMEOUT: u64 = 5_000;
+
+type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+type Reader = SplitStream<Framed<TcpStream, LengthDelimitedCodec>>;
+type Submission = (Bytes, oneshot::Sender<ClientResult<SubmitAck>>);
+
+/// Acknowledges that a worker queued a transaction for its next batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmitAck {
+    /// The address of the worker that acknowledged the transaction.
+    pub worker: SocketAddr,
+}
+
+/// Connects to the workers of an authority.
+pub struct NarwhalClient;
+
+impl NarwhalClient {
+    /// Connects to the first reachable worker of the list, allowing `DEFAULT_MAX_IN_FLIGHT`
+    /// in-flight submissions.
+    pub async fn connect(addresses: Vec<SocketAddr>) -> ClientResult<Client> {
+        Self::connect_with_limit(addresses, DEFAULT_MAX_IN_FLIGHT).await
+    }
+
+    /// Connects to the first reachable worker of the list. Submissions wait (rather than pile up)
+    /// while `max_in_flight` transactions await their acknowledgement.
+    pub async fn connect_with_limit(
+        addresses: Vec<SocketAddr>,
+        max_in_flight: usize,
+    ) -> ClientResult<Client> {
+        if addresses.is_empty() {
+            return Err(ClientError::NoAddress);
+        }
+        if max_in_flight == 0 {
+            return Err(ClientError::InvalidLimit);
+        }
+
+        let (tx_submission, rx_submission) = channel(max_in_flight);
+        let mut connection = Connection {
+            addresses,
+            next: 0,
+            rx_submission,
+            link: None,
+            pending: VecDeque::new(),
+        };
+        connection.reconnect().await?;
+        tokio::spawn(async move {
+            connection.run().await;
+        });
+
+        Ok(Client {
+            tx_submission,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            tx_watch: None,
+        })
+    }
+}
+
+/// Submits transactions through a single connection to a worker, failing over to the other
+/// workers of the list when it breaks. Transactions not yet acknowledged when a connection breaks
+/// are sent again to the next worker: they are delivered at least once. We never have more
+/// transactions awaiting their acknowledgement than the window the worker advertises: the others
+/// wait for a credit. Cloning the client shares its connection (and its limit of in-flight
+/// submissions).
+#[derive(Clone)]
+pub struct Client {
+    tx_submission: Sender<Submission>,
+    in_flight: Arc<Semaphore>,
+    /// Asks the `CommitWatcher` to tell us when a transaction is committed (if we follow a commit
+    /// stream).
+    tx_watch: Option<Sender<Watch>>,
+}
+
+impl Client {
+    /// Submits a transaction and waits until a worker acknowledges it. Submissions are pipelined:
+    /// concurrent calls do not wait for each other's acknowledgements.
+    pub async fn submit(&self, transaction: Bytes) -> ClientResult<SubmitAck> {
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .map_err(|_| ClientError::Closed)?;
+        let (sender, receiver) = oneshot::channel();
+        self.tx_submission
+            .send((transaction, sender))
+            .await
+            .map_err(|_| ClientError::Closed)?;
+        receiver.await.map_err(|_| ClientError::Closed)?
+    }
+
+    /// Follows the commit stream of a node (the `--commits` address of its primary), to learn when
+    /// our transactions are committed. Clones made afterwards share the subscription.
+    pub async fn watch_commits(mut self, address: SocketAddr) -> ClientResult<Self> {
+        let subscription = CommitWatcher::subscribe(address, NO_REPLAY)
+            .await
+            .map_err(|_| ClientError::CommitStreamUnreachable(address))?;
+        let (tx_watch, rx_watch) = channel(DEFAULT_MAX_IN_FLIGHT);
+        CommitWatcher::spawn(address, subscription, rx_watch);
+        self.tx_watch = Some(tx_watch);
+        Ok(self)
+    }
+
+    /// Submits a transaction and waits until consensus commits it. The client must follow a commit
+    /// stream (see `watch_commits`). Transactions are identified by their content, but each call
+    /// waits for a commit following its own submission: submitting twice the same transaction
+    /// resolves the calls with distinct commits.
+    pub async fn submit_and_wait(
+        &self,
+        transaction: Bytes,
+        timeout_delay: Duration,
+    ) -> ClientResult<CommitInfo> {
+        let tx_watch = self.tx_watch.as_ref().ok_or(ClientError::NoCommitStream)?;
+        let wait = async {
+            // Watch the transaction before submitting it, so that we cannot miss its commit.
+            let (sender, receiver) = oneshot::channel();
+            tx_watch
+                .send((transaction.clone(), sender))
+                .await
+                .map_err(|_| ClientError::Closed)?;
+            self.submit(transaction).await?;
+            receiver.await.map_err(|_| ClientError::Closed)
+        };
+        timeout(timeout_delay, wait)
+            .await
+            .map_err(|_| ClientError::Timeout(timeout_delay))?
+    }
+}
+
+/// An open connection to a worker.
+struct Link {
+    address: SocketAddr,
+    writer: Writer,
+    reader: Reader,
+    /// How many transactions the worker lets us send before waiting for their acknowledgements.
+    window: usize,
+    /// How many of the pending submissions (from the oldest) we sent over this connection.
+    sent: usize,
+}
+
+/// Owns the connection to the current worker.
+struct Connection {
+    /// The workers we may submit to.
+    addresses: Vec<SocketAddr>,
+    /// The index of the next worker to try.
+    next: usize,
+    /// Receives the submissions of the clients.
+    rx_submission: Receiver<Submission>,
+    /// The connection to the current worker (if any).
+    link: Option<Link>,
+    /// The submissions awaiting their acknowledgement, in the order we received them. The oldest
+    /// ones were sent to the worker, the others wait for the window to admit them.
+    pending: VecDeque<Submission>,
+}
+
+impl Connection {
+    /// Connects to the next reachable worker (trying each of them once) and sends it the
+    /// transactions still awaiting their acknowledgement.
+    async fn reconnect(&mut self) -> ClientResult<()> {
+        self.link = None;
+        for _ in 0..self.addresses.len() {
+            let address = self.addresses[self.next];
+            self.next = (self.next + 1) % self.addresses.len();
+            match Self::open(address, &self.pending).await {
+                Ok(link) => {
+                    debug!("Connected to worker {}", address);
+                    self.link = Some(link);
+                    return Ok(());
+                }
+                Err(e) => warn!("Failed to connect to worker {}: {}", address, e),
+            }
+        }
+        Err(ClientError::Unreachable(self.addresses.clone()))
+    }
+
+    /// Opens a connection, learns the window of the worker, and (re)sends the pending transactions
+    /// it admits.
+    async fn open(address: SocketAddr, pending: &VecDeque<Submission>) -> io::Result<Link> {
+        let stream = TcpStream::connect(address).await?;
+        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        writer
+            .send(Bytes::from_static(WINDOWED_TRANSACTION_BANNER))
+            .await?;
+        let window = match timeout(Duration::from_millis(WINDOW_TIMEOUT), reader.next()).await {
+            Ok(Some(Ok(frame))) => frame[..].try_into().map(u64::from_be_bytes).ok(),
+            Ok(Some(Err(e))) => return Err(e),
+            _ => None,
+        };
+        let window = match window {
+            Some(window) if window > 0 => window as usize,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The worker did not advertise a window",
+                ))
+            }
+        };
+        let sent = std::cmp::min(window, pending.len());
+        for (transaction, _) in pending.iter().take(sent) {
+            writer.send(transaction.clone()).await?;
+        }
+        Ok(Link {
+            address,
+            writer,
+            reader,
+            window,
+            sent,
+        })
+    }
+
+    /// Fails all pending submissions.
+    fn fail_pending(&mut self) {
+        for (_, reply) in self.pending.drain(..) {
+            let _ = reply.send(Err(ClientError::Unreachable(self.addresses.clone())));
+        }
+    }
+
+    /// Main loop sending transactions and matching acknowledgements.
+    async fn run(&mut self) {
+        let mut closed = false;
+        loop {
+            // Without connection, wait for a submission before trying again.
+            let link = match &mut self.link {
+                Some(link) => link,
+                None => {
+                    match self.rx_submission.recv().await {
+                        Some(submission) => self.pending.push_back(submission),
+                        None => return,
+                    }
+                    if self.reconnect().await.is_err() {
+                        self.fail_pending();
+                    }
+                    continue;
+                }
+            };
+
+            // New submissions wait while the window is full.
+            let admit = !closed && self.pending.len() < link.window;
+            let mut broken = false;
+            tokio::select! {
+                submission = self.rx_submission.recv(), if admit => match submission {
+                    Some((transaction, reply)) => {
+                        broken = link.writer.send(transaction.clone()).await.is_err();
+                        link.sent += 1;
+                        self.pending.push_back((transaction, reply));
+                    }
+                    None => closed = true,
+                },
+                frame = link.reader.next() => match frame {
+                    Some(Ok(_)) if link.sent > 0 => {
+                        link.sent -= 1;
+                        if let Some((_, reply)) = self.pending.pop_front() {
+                            let _ = reply.send(Ok(SubmitAck { worker: link.address }));
+                        }
+
+                        // The acknowledgement gives us a credit back: send the next transaction
+                        // the window held back (if any).
+                        if let Some((transaction, _)) = self.pending.get(link.sent) {
+                            broken = link.writer.send(transaction.clone()).await.is_err();
+                            link.sent += 1;
+                        }
+                    }
+                    Some(Ok(_)) => warn!("Unexpected acknowledgement from worker {}", link.address),
+                    _ => broken = true,
+                },
+            }
+
+            if broken {
+                warn!("Lost connection to worker {}", link.address);
+                if self.reconnect().await.is_err() {
+                    self.fail_pending();
+                }
+            }
+            if closed && self.pending.is_empty() {
+                return;
+            }
+        }
+    }
+}
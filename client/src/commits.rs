@@ -0,0 +1,198 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use tokio::time::{interval, sleep, Duration, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/commits_tests.rs"]
+pub mod commits_tests;
+
+/// Subscribing from this consensus index only streams the new commits (see
+/// `CommitWatcher::subscribe`).
+pub(crate) const NO_REPLAY: u64 = u64::MAX;
+
+/// How long to wait before subscribing again after losing the commit stream (in ms).
+const RESUBSCRIBE_DELAY: u64 = 500;
+
+/// How often to forget the watches whose caller gave up (in ms).
+const PRUNE_PERIOD: u64 = 1_000;
+
+pub(crate) type Watch = (Bytes, oneshot::Sender<CommitInfo>);
+
+/// A batch committed by consensus, as nodes stream it to their subscribers (one per frame).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommittedBatch {
+    /// The round of the certificate that committed the batch.
+    pub round: u64,
+    /// The consensus index of the first transaction of the batch. The transactions of the batch
+    /// follow it in order.
+    pub index: u64,
+    /// The digest of the batch.
+    pub digest: [u8; 32],
+    /// The transactions of the batch.
+    pub transactions: Vec<Bytes>,
+}
+
+/// Where consensus ordered a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// The consensus index of the transaction.
+    pub index: u64,
+    /// The round of the certificate that committed the transaction.
+    pub round: u64,
+    /// The digest of the batch holding the transaction.
+    pub batch: [u8; 32],
+}
+
+/// Follows the commit stream of a node and tells the callers when their transactions are
+/// committed. Transactions are identified by their content, and callers watch them before
+/// submitting them: each commit of a transaction resolves the oldest watch registered before it.
+/// A commit thus never resolves a later submission of the same transaction, which waits for its
+/// own commit.
+pub(crate) struct CommitWatcher {
+    /// The address of the commit stream.
+    address: SocketAddr,
+    /// Receives the transactions to watch.
+    rx_watch: Receiver<Watch>,
+    /// The callers waiting for each transaction, from the oldest.
+    waiting: HashMap<Bytes, VecDeque<oneshot::Sender<CommitInfo>>>,
+    /// The consensus index following the last transaction we processed (if any).
+    next: Option<u64>,
+}
+
+impl CommitWatcher {
+    /// Subscribes to the commit stream, and returns the subscription. The node first replays its
+    /// recent batches holding transactions from consensus index `from` on, and then streams the
+    /// new ones.
+    pub(crate) async fn subscribe(
+        address: SocketAddr,
+        from: u64,
+    ) -> std::io::Result<Framed<TcpStream, LengthDelimitedCodec>> {
+        let stream = TcpStream::connect(address).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport
+            .send(Bytes::copy_from_slice(&from.to_be_bytes()))
+            .await?;
+        Ok(transport)
+    }
+
+    pub(crate) fn spawn(
+        address: SocketAddr,
+        subscription: Framed<TcpStream, LengthDelimitedCodec>,
+        rx_watch: Receiver<Watch>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                address,
+                rx_watch,
+                waiting: HashMap::new(),
+                next: None,
+            }
+            .run(subscription)
+            .await;
+        });
+    }
+
+    /// Main loop matching committed transactions with the watches. When the commit stream breaks,
+    /// we subscribe again from the last commit we processed: the node replays its recent commits,
+    /// so we only miss the commits of long outages.
+    async fn run(&mut self, subscription: Framed<TcpStream, LengthDelimitedCodec>) {
+        let mut subscription = Some(subscription);
+        let delay = Duration::from_millis(RESUBSCRIBE_DELAY);
+        let retry = sleep(delay);
+        tokio::pin!(retry);
+        let mut prune = interval(Duration::from_millis(PRUNE_PERIOD));
+        loop {
+            tokio::select! {
+                watch = self.rx_watch.recv() => match watch {
+                    Some((transaction, reply)) => self.watch(transaction, reply),
+                    None => return,
+                },
+                frame = async { subscription.as_mut()?.next().await },
+                    if subscription.is_some() => {
+                    match frame {
+                        Some(Ok(frame)) => self.process(frame.freeze()),
+                        _ => {
+                            warn!("Lost the commit stream of {}", self.address);
+                            subscription = None;
+                            retry.as_mut().reset(Instant::now() + delay);
+                        }
+                    }
+                },
+                () = &mut retry, if subscription.is_none() => {
+                    let from = self.next.unwrap_or(NO_REPLAY);
+                    match Self::subscribe(self.address, from).await {
+                        Ok(x) => {
+                            debug!("Subscribed again to the commit stream of {}", self.address);
+                            subscription = Some(x);
+                        }
+                        Err(e) => {
+                            warn!("Failed to subscribe to {}: {}", self.address, e);
+                            retry.as_mut().reset(Instant::now() + delay);
+                        }
+                    }
+                },
+                _ = prune.tick() => self.waiting.retain(|_, replies| {
+                    replies.retain(|reply| !reply.is_closed());
+                    !replies.is_empty()
+                }),
+            }
+        }
+    }
+
+    /// Registers a watch. Only the commits processed from now on resolve it.
+    fn watch(&mut self, transaction: Bytes, reply: oneshot::Sender<CommitInfo>) {
+        self.waiting
+            .entry(transaction)
+            .or_default()
+            .push_back(reply);
+    }
+
+    /// Notifies the callers waiting for the transactions of a committed batch.
+    fn process(&mut self, frame: Bytes) {
+        let batch: CommittedBatch = match bincode::deserialize(&frame) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to deserialize committed batch: {}", e);
+                return;
+            }
+        };
+        for (i, transaction) in batch.transactions.into_iter().enumerate() {
+            let index = batch.index + i as u64;
+
+            // Ignore the commits replayed after subscribing again.
+            if self.next.map_or(false, |next| index < next) {
+                continue;
+            }
+            self.next = Some(index + 1);
+
+            // Each commit resolves a single watch: the oldest one whose caller is still waiting.
+            let replies = match self.waiting.get_mut(&transaction) {
+                Some(x) => x,
+                None => continue,
+            };
+            let info = CommitInfo {
+                index,
+                round: batch.round,
+                batch: batch.digest,
+            };
+            while let Some(reply) = replies.pop_front() {
+                if reply.send(info).is_ok() {
+                    break;
+                }
+            }
+            if replies.is_empty() {
+                self.waiting.remove(&transaction);
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("No worker address to connect to")]
+    NoAddress,
+
+    #[error("The limit of in-flight submissions must be positive")]
+    InvalidLimit,
+
+    #[error("Failed to connect to any of the workers {0:?}")]
+    Unreachable(Vec<SocketAddr>),
+
+    #[error("Failed to subscribe to the commit stream {0}")]
+    CommitStreamUnreachable(SocketAddr),
+
+    #[error("The client does not follow any commit stream")]
+    NoCommitStream,
+
+    #[error("Worker {0} does not issue receipts")]
+    ReceiptsUnavailable(SocketAddr),
+
+    #[error("Lost the connection to worker {0}")]
+    Disconnected(SocketAddr),
+
+    #[error("The transaction was not committed within {0:?}")]
+    Timeout(Duration),
+
+    #[error("The client stopped")]
+    Closed,
+}
@@ -0,0 +1,126 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commits::CommittedBatch;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+#[path = "tests/latency_tests.rs"]
+pub mod latency_tests;
+
+/// The current time, in microseconds since the UNIX epoch.
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_micros() as u64)
+}
+
+/// The percentiles of the submission-to-commit latency of a set of transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyPercentiles {
+    /// Computes the percentiles (by nearest rank) of the latencies, if there are any.
+    pub fn new(latencies: &[Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        let percentile = |p: usize| {
+            let rank = (sorted.len() * p + 99) / 100;
+            sorted[rank.saturating_sub(1)]
+        };
+        Some(Self {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+/// Measures the submission-to-commit latency of our tagged transactions, following the batches of
+/// a commit stream. The tag of a transaction is whatever `parse` reads from it (e.g., an id the
+/// submitter embeds in its first bytes): the transactions without tag, or whose tag we were not
+/// told about, are someone else's. Times are in microseconds since the UNIX epoch (see
+/// `now_micros`), so that submitters may embed them in their transactions.
+pub struct LatencyRecorder<K> {
+    /// Reads the tag of a transaction.
+    parse: fn(&[u8]) -> Option<K>,
+    /// How long (in us) a transaction may take to commit before we count it as lost.
+    deadline: u64,
+    /// The submission time of our transactions not yet committed.
+    pending: HashMap<K, u64>,
+    /// The tag, submission time, and latency (in us) of our committed transactions, in commit
+    /// order.
+    committed: Vec<(K, u64, u64)>,
+    /// The number of transactions that did not commit before the deadline.
+    lost: usize,
+}
+
+impl<K: Hash + Eq> LatencyRecorder<K> {
+    pub fn new(parse: fn(&[u8]) -> Option<K>, deadline: Duration) -> Self {
+        Self {
+            parse,
+            deadline: deadline.as_micros() as u64,
+            pending: HashMap::new(),
+            committed: Vec::new(),
+            lost: 0,
+        }
+    }
+
+    /// Records the submission of a tagged transaction.
+    pub fn submitted(&mut self, tag: K, timestamp: u64) {
+        self.pending.insert(tag, timestamp);
+    }
+
+    /// Records the latency of our transactions in a batch committed at `timestamp`. Only the first
+    /// commit of a transaction counts.
+    pub fn committed(&mut self, batch: &CommittedBatch, timestamp: u64) {
+        for tag in batch.transactions.iter().filter_map(|x| (self.parse)(x)) {
+            if let Some(sent) = self.pending.remove(&tag) {
+                let latency = timestamp.saturating_sub(sent);
+                self.committed.push((tag, sent, latency));
+            }
+        }
+    }
+
+    /// Gives up on the transactions that are past their deadline at `timestamp`.
+    pub fn expire(&mut self, timestamp: u64) {
+        let deadline = self.deadline;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, sent| timestamp.saturating_sub(*sent) < deadline);
+        self.lost += before - self.pending.len();
+    }
+
+    /// The tag, submission time, and latency (in us) of our committed transactions, in commit
+    /// order.
+    pub fn commits(&self) -> &[(K, u64, u64)] {
+        &self.committed
+    }
+
+    /// The number of transactions that did not commit before the deadline.
+    pub fn lost(&self) -> usize {
+        self.lost
+    }
+
+    /// The number of transactions not yet committed (nor lost).
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The latency percentiles of our committed transactions (if any).
+    pub fn percentiles(&self) -> Option<LatencyPercentiles> {
+        let latencies: Vec<_> = self
+            .committed
+            .iter()
+            .map(|(_, _, x)| Duration::from_micros(*x))
+            .collect();
+        LatencyPercentiles::new(&latencies)
+    }
+}
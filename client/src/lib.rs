@@ -0,0 +1,42 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! Submits transactions to the workers of a Narwhal authority.
+//!
+//! ```no_run
+//! # async fn example() -> client::ClientResult<()> {
+//! use bytes::Bytes;
+//! use client::NarwhalClient;
+//!
+//! // Any of these workers (of the same authority) may receive our transactions.
+//! let addresses = vec!["127.0.0.1:3003".parse().unwrap(), "127.0.0.1:3007".parse().unwrap()];
+//! let client = NarwhalClient::connect(addresses).await?;
+//!
+//! // Resolves once a worker queued the transaction for its next batch.
+//! let ack = client.submit(Bytes::from("transaction")).await?;
+//! println!("Transaction accepted by {}", ack.worker);
+//!
+//! // Following the commit stream of a node, we can also wait until consensus orders a transaction.
+//! let client = client.watch_commits("127.0.0.1:3010".parse().unwrap()).await?;
+//! let timeout = std::time::Duration::from_secs(10);
+//! let commit = client.submit_and_wait(Bytes::from("another"), timeout).await?;
+//! println!("Transaction committed at index {}", commit.index);
+//! # Ok(())
+//! # }
+//! ```
+mod client;
+mod commits;
+mod error;
+mod latency;
+mod receipts;
+
+pub use crate::client::{
+    Client, NarwhalClient, SubmitAck, DEFAULT_MAX_IN_FLIGHT, TRANSACTION_BANNER,
+    WINDOWED_TRANSACTION_BANNER,
+};
+pub use crate::commits::{CommitInfo, CommittedBatch};
+pub use crate::error::{ClientError, ClientResult};
+pub use crate::latency::{now_micros, LatencyPercentiles, LatencyRecorder};
+pub use crate::receipts::{Receipt, ReceiptClient, RECEIPT_BANNER, TAGGED_TRANSACTION_BANNER};
+
+#[cfg(test)]
+#[path = "tests/common.rs"]
+mod common;
@@ -0,0 +1,106 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::client::SubmitAck;
+use crate::error::{ClientError, ClientResult};
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// The first frame of the connections sending tagged transactions. It must match
+/// `worker::TAGGED_TRANSACTION_BANNER`.
+pub const TAGGED_TRANSACTION_BANNER: &[u8] = b"tagged-transactions";
+
+/// The first frame of the connections receiving receipts. It must match `worker::RECEIPT_BANNER`.
+pub const RECEIPT_BANNER: &[u8] = b"receipts";
+
+type Transport = Framed<TcpStream, LengthDelimitedCodec>;
+
+/// Tells that consensus committed a tagged transaction. It must match `worker::Receipt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The correlation id we tagged the transaction with.
+    pub id: u64,
+    /// The digest of the batch holding the transaction.
+    pub batch: [u8; 32],
+    /// The round of the certificate that committed the batch.
+    pub round: u64,
+}
+
+/// Submits transactions tagged with a correlation id to a worker, and receives a receipt for each
+/// of them once consensus commits it. The worker must follow the commit stream of its primary
+/// (see `Parameters::commit_stream`). Unlike `Client`, it sticks to a single worker (the one that
+/// batches our transactions is the one that issues their receipts) and does not pipeline
+/// submissions.
+pub struct ReceiptClient {
+    /// The address of the worker.
+    address: SocketAddr,
+    /// The key of our receipt channel, prefixing each transaction along with its correlation id.
+    key: u64,
+    /// Sends our tagged transactions, and receives their acknowledgements.
+    transactions: Transport,
+    /// Receives our receipts.
+    receipts: Transport,
+}
+
+impl ReceiptClient {
+    /// Opens a receipt channel with a worker, and a connection to send it tagged transactions.
+    pub async fn connect(address: SocketAddr) -> ClientResult<Self> {
+        let unreachable = |_| ClientError::Unreachable(vec![address]);
+        let mut receipts = Self::open(address, RECEIPT_BANNER)
+            .await
+            .map_err(unreachable)?;
+        let key = match receipts.next().await {
+            Some(Ok(frame)) => frame[..]
+                .try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| ClientError::ReceiptsUnavailable(address))?,
+            _ => return Err(ClientError::ReceiptsUnavailable(address)),
+        };
+        let transactions = Self::open(address, TAGGED_TRANSACTION_BANNER)
+            .await
+            .map_err(unreachable)?;
+        Ok(Self {
+            address,
+            key,
+            transactions,
+            receipts,
+        })
+    }
+
+    /// Opens a connection starting with the banner.
+    async fn open(address: SocketAddr, banner: &'static [u8]) -> std::io::Result<Transport> {
+        let stream = TcpStream::connect(address).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(Bytes::from_static(banner)).await?;
+        Ok(transport)
+    }
+
+    /// Submits a transaction tagged with a correlation id, and waits until the worker acknowledges
+    /// it. Its receipt carries the same id.
+    pub async fn submit(&mut self, id: u64, transaction: Bytes) -> ClientResult<SubmitAck> {
+        let tagged = [&self.key.to_be_bytes()[..], &id.to_be_bytes(), &transaction].concat();
+        if self.transactions.send(Bytes::from(tagged)).await.is_err() {
+            return Err(ClientError::Disconnected(self.address));
+        }
+        match self.transactions.next().await {
+            Some(Ok(_)) => Ok(SubmitAck {
+                worker: self.address,
+            }),
+            _ => Err(ClientError::Disconnected(self.address)),
+        }
+    }
+
+    /// Waits for the next receipt, in commit order.
+    pub async fn receipt(&mut self) -> ClientResult<Receipt> {
+        match self.receipts.next().await {
+            Some(Ok(frame)) => {
+                bincode::deserialize(&frame).map_err(|_| ClientError::Disconnected(self.address))
+            }
+            _ => Err(ClientError::Disconnected(self.address)),
+        }
+    }
+}
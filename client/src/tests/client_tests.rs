@@ -0,0 +1,115 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{silent_worker, spawn_worker, transaction};
+use crate::receipts::{ReceiptClient, RECEIPT_BANNER, TAGGED_TRANSACTION_BANNER};
+use futures::future::join_all;
+use tokio::time::{sleep, timeout, Duration};
+
+#[test]
+fn banner_matches_worker() {
+    assert_eq!(TRANSACTION_BANNER, worker::TRANSACTION_BANNER);
+    assert_eq!(
+        WINDOWED_TRANSACTION_BANNER,
+        worker::WINDOWED_TRANSACTION_BANNER
+    );
+    assert_eq!(TAGGED_TRANSACTION_BANNER, worker::TAGGED_TRANSACTION_BANNER);
+    assert_eq!(RECEIPT_BANNER, worker::RECEIPT_BANNER);
+}
+
+#[tokio::test]
+async fn submit_to_worker() {
+    let address = spawn_worker(15_000);
+    sleep(Duration::from_millis(50)).await;
+
+    // Pipeline many submissions: all of them are acknowledged by the worker.
+    let client = NarwhalClient::connect(vec![address]).await.unwrap();
+    let acks = join_all((0..100).map(|_| client.submit(transaction()))).await;
+    for ack in acks {
+        assert_eq!(ack.unwrap(), SubmitAck { worker: address });
+    }
+}
+
+#[tokio::test]
+async fn failover_to_next_worker() {
+    // The first worker drops the connection after receiving our banner and a transaction.
+    let broken = "127.0.0.1:15100".parse::<SocketAddr>().unwrap();
+    let (mut rx_frame, _tx_reply) =
+        silent_worker(broken, /* frames */ 2, /* window */ 10);
+    let address = spawn_worker(15_200);
+    sleep(Duration::from_millis(50)).await;
+
+    // The transaction is sent again to (and acknowledged by) the next worker.
+    let client = NarwhalClient::connect(vec![broken, address]).await.unwrap();
+    let ack = client.submit(transaction()).await.unwrap();
+    assert_eq!(ack, SubmitAck { worker: address });
+    assert_eq!(rx_frame.recv().await.unwrap(), WINDOWED_TRANSACTION_BANNER);
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+}
+
+#[tokio::test]
+async fn bound_in_flight_submissions() {
+    let address = "127.0.0.1:15101".parse::<SocketAddr>().unwrap();
+    let (mut rx_frame, _tx_reply) =
+        silent_worker(address, /* frames */ usize::MAX, /* window */ 10);
+    sleep(Duration::from_millis(50)).await;
+
+    // Without acknowledgements, only two of the three submissions reach the worker.
+    let client = NarwhalClient::connect_with_limit(vec![address], 2)
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let client = client.clone();
+        tokio::spawn(async move { client.submit(transaction()).await });
+    }
+    assert_eq!(rx_frame.recv().await.unwrap(), WINDOWED_TRANSACTION_BANNER);
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+    let next = timeout(Duration::from_millis(200), rx_frame.recv()).await;
+    assert!(next.is_err(), "Too many in-flight submissions");
+}
+
+#[tokio::test]
+async fn hold_submissions_to_window() {
+    let address = "127.0.0.1:15103".parse::<SocketAddr>().unwrap();
+    let (mut rx_frame, tx_reply) =
+        silent_worker(address, /* frames */ usize::MAX, /* window */ 2);
+    sleep(Duration::from_millis(50)).await;
+
+    // Only the two submissions the window admits reach the worker.
+    let client = NarwhalClient::connect(vec![address]).await.unwrap();
+    for _ in 0..3 {
+        let client = client.clone();
+        tokio::spawn(async move { client.submit(transaction()).await });
+    }
+    assert_eq!(rx_frame.recv().await.unwrap(), WINDOWED_TRANSACTION_BANNER);
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+    let next = timeout(Duration::from_millis(200), rx_frame.recv()).await;
+    assert!(next.is_err(), "The client exceeded the window");
+
+    // Each acknowledgement admits one more.
+    tx_reply.send(Bytes::from("Ack")).await.unwrap();
+    assert_eq!(rx_frame.recv().await.unwrap(), transaction());
+    let next = timeout(Duration::from_millis(200), rx_frame.recv()).await;
+    assert!(next.is_err(), "The client exceeded the window");
+}
+
+#[tokio::test]
+async fn unreachable_workers() {
+    let address = "127.0.0.1:15102".parse::<SocketAddr>().unwrap();
+    match NarwhalClient::connect(vec![address]).await {
+        Err(ClientError::Unreachable(addresses)) => assert_eq!(addresses, vec![address]),
+        _ => panic!("Unexpected connection"),
+    }
+}
+
+#[tokio::test]
+async fn receipts_unavailable() {
+    // The worker does not follow any commit stream.
+    let address = spawn_worker(15_800);
+    sleep(Duration::from_millis(50)).await;
+    match ReceiptClient::connect(address).await {
+        Err(ClientError::ReceiptsUnavailable(x)) => assert_eq!(x, address),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+    }
+}
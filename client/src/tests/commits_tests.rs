@@ -0,0 +1,78 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn watcher() -> CommitWatcher {
+    CommitWatcher {
+        address: "127.0.0.1:0".parse().unwrap(),
+        rx_watch: channel(1).1,
+        waiting: HashMap::new(),
+        next: None,
+    }
+}
+
+// Fixture
+fn frame(round: u64, index: u64, transactions: &[&'static str]) -> Bytes {
+    let batch = CommittedBatch {
+        round,
+        index,
+        digest: [round as u8; 32],
+        transactions: transactions.iter().map(|x| Bytes::from(*x)).collect(),
+    };
+    Bytes::from(bincode::serialize(&batch).unwrap())
+}
+
+#[test]
+fn notify_waiting_callers() {
+    let mut watcher = watcher();
+    let (sender, mut receiver) = oneshot::channel();
+    watcher.watch(Bytes::from("b"), sender);
+    assert!(receiver.try_recv().is_err());
+
+    watcher.process(frame(2, 7, &["a", "b"]));
+    let expected = CommitInfo {
+        index: 8,
+        round: 2,
+        batch: [2; 32],
+    };
+    assert_eq!(receiver.try_recv().unwrap(), expected);
+    assert!(watcher.waiting.is_empty());
+}
+
+#[test]
+fn resolve_one_watch_per_commit() {
+    // A transaction submitted twice waits for two commits, each resolving one submission.
+    let mut watcher = watcher();
+    let (first, mut first_receiver) = oneshot::channel();
+    let (second, mut second_receiver) = oneshot::channel();
+    watcher.watch(Bytes::from("a"), first);
+    watcher.watch(Bytes::from("a"), second);
+
+    watcher.process(frame(2, 0, &["a"]));
+    assert_eq!(first_receiver.try_recv().unwrap().index, 0);
+    assert!(second_receiver.try_recv().is_err());
+    watcher.process(frame(4, 1, &["a"]));
+    assert_eq!(second_receiver.try_recv().unwrap().index, 1);
+    assert!(watcher.waiting.is_empty());
+}
+
+#[test]
+fn ignore_earlier_commits() {
+    // A transaction committed before we watch it (e.g., an earlier submission of the same
+    // transaction) does not resolve the watch, even when the commit stream replays it.
+    let mut watcher = watcher();
+    watcher.process(frame(2, 7, &["a", "b"]));
+    let (sender, mut receiver) = oneshot::channel();
+    watcher.watch(Bytes::from("b"), sender);
+    watcher.process(frame(2, 7, &["a", "b"]));
+    assert!(receiver.try_recv().is_err());
+
+    watcher.process(frame(4, 9, &["b"]));
+    let expected = CommitInfo {
+        index: 9,
+        round: 4,
+        batch: [4; 32],
+    };
+    assert_eq!(receiver.try_recv().unwrap(), expected);
+}
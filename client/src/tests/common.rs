@@ -0,0 +1,75 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use config::{Committee, KeyPair, Parameters};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use std::net::SocketAddr;
+use store::Database;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use worker::{Worker, WorkerOptions};
+
+// Fixture
+pub fn transaction() -> Bytes {
+    Bytes::from(vec![0; 100])
+}
+
+// Fixture
+pub fn spawn_worker(base_port: u16) -> SocketAddr {
+    let committee = Committee::new_for_test(4, base_port, /* seed */ 0);
+    let name = KeyPair::new_for_test(/* seed */ 0, 0).name;
+    let address = committee.worker(&name, &0).unwrap().transactions;
+    Worker::spawn(
+        name,
+        /* id */ 0,
+        committee,
+        Parameters::default(),
+        Database::new_in_memory(),
+        WorkerOptions::default(),
+    )
+    .unwrap();
+    address
+}
+
+// Fixture: a worker that advertises a window and never acknowledges anything on its own. It
+// forwards the frames it receives, writes the frames it is given, and drops the connection after
+// receiving `frames` frames.
+pub fn silent_worker(
+    address: SocketAddr,
+    frames: usize,
+    window: u64,
+) -> (Receiver<Bytes>, Sender<Bytes>) {
+    let (tx_frame, rx_frame) = channel(100);
+    let (tx_reply, mut rx_reply) = channel::<Bytes>(100);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut received = 0;
+        let mut replies = true;
+        while received < frames {
+            tokio::select! {
+                frame = transport.next() => match frame {
+                    Some(Ok(frame)) => {
+                        // Reply to the banner with our window.
+                        if received == 0 {
+                            let window = Bytes::copy_from_slice(&window.to_be_bytes());
+                            let _ = transport.send(window).await;
+                        }
+                        received += 1;
+                        tx_frame.send(frame.freeze()).await.unwrap();
+                    }
+                    _ => break,
+                },
+                reply = rx_reply.recv(), if replies => match reply {
+                    Some(reply) => {
+                        let _ = transport.send(reply).await;
+                    }
+                    None => replies = false,
+                },
+            }
+        }
+    });
+    (rx_frame, tx_reply)
+}
@@ -0,0 +1,52 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use bytes::Bytes;
+use std::convert::TryInto as _;
+
+// Fixture: a transaction tagged with an id (in its first 8 bytes).
+fn tagged(id: u64) -> Bytes {
+    let mut transaction = id.to_be_bytes().to_vec();
+    transaction.extend_from_slice(&[0; 92]);
+    Bytes::from(transaction)
+}
+
+// Fixture
+fn parse_tag(transaction: &[u8]) -> Option<u64> {
+    transaction
+        .get(..8)?
+        .try_into()
+        .ok()
+        .map(u64::from_be_bytes)
+}
+
+#[test]
+fn nearest_rank_percentiles() {
+    let latencies: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+    let expected = LatencyPercentiles {
+        p50: Duration::from_millis(50),
+        p90: Duration::from_millis(90),
+        p99: Duration::from_millis(99),
+    };
+    assert_eq!(LatencyPercentiles::new(&latencies), Some(expected));
+    assert_eq!(LatencyPercentiles::new(&[]), None);
+}
+
+#[test]
+fn ignore_foreign_and_expired_transactions() {
+    let mut recorder = LatencyRecorder::new(parse_tag, Duration::from_millis(10));
+    recorder.submitted(1, 1_000);
+    recorder.submitted(2, 1_000);
+    recorder.expire(12_000);
+    assert_eq!(recorder.lost(), 2);
+
+    // The transactions were not ours (anymore): nothing is measured.
+    let batch = CommittedBatch {
+        round: 2,
+        index: 0,
+        digest: [0; 32],
+        transactions: vec![tagged(1), Bytes::from("short")],
+    };
+    recorder.committed(&batch, 13_000);
+    assert!(recorder.commits().is_empty());
+    assert_eq!(recorder.percentiles(), None);
+}
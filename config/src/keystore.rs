@@ -0,0 +1,198 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{ConfigError, Export, Import, KeyPair};
+use aes_gcm::aead::{Aead as _, KeyInit as _, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use crypto::{PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::fs;
+use std::io::BufRead as _;
+use zeroize::Zeroizing;
+
+#[cfg(test)]
+#[path = "tests/keystore_tests.rs"]
+pub mod keystore_tests;
+
+/// The default scrypt cost (log2 of the number of iterations) of new key files.
+const DEFAULT_LOG_N: u8 = 15;
+
+/// A keypair file whose secret key is encrypted with a passphrase (scrypt + AES-256-GCM). The
+/// public key is kept in clear and authenticated as associated data.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedKeyPair {
+    /// The node's public key (and identifier).
+    pub name: PublicKey,
+    /// The encrypted secret key.
+    pub encrypted: EncryptedSecret,
+}
+
+impl Import for EncryptedKeyPair {}
+impl Export for EncryptedKeyPair {}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    /// The scrypt parameters used to derive the encryption key.
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// The scrypt salt (base64).
+    pub salt: String,
+    /// The AES-GCM nonce (base64).
+    pub nonce: String,
+    /// The encrypted secret key (base64).
+    pub ciphertext: String,
+}
+
+impl KeyPair {
+    /// Encrypt the keypair with a passphrase.
+    pub fn encrypt(&self, passphrase: &str) -> EncryptedKeyPair {
+        self.encrypt_with_cost(passphrase, DEFAULT_LOG_N)
+    }
+
+    fn encrypt_with_cost(&self, passphrase: &str, log_n: u8) -> EncryptedKeyPair {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+
+        let (r, p) = (8, 1);
+        let key = derive_key(passphrase, &salt, log_n, r, p).expect("Invalid scrypt parameters");
+        let plaintext = Zeroizing::new(self.secret.encode_base64());
+        let ciphertext = Aes256Gcm::new_from_slice(&key[..])
+            .expect("Invalid key length")
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: &self.name.0,
+                },
+            )
+            .expect("Failed to encrypt secret key");
+
+        EncryptedKeyPair {
+            name: self.name,
+            encrypted: EncryptedSecret {
+                log_n,
+                r,
+                p,
+                salt: base64::encode(salt),
+                nonce: base64::encode(nonce),
+                ciphertext: base64::encode(ciphertext),
+            },
+        }
+    }
+
+    /// Load a keypair file, either plaintext or encrypted (the format is detected automatically).
+    /// The passphrase is only requested if the file is encrypted.
+    pub fn import_with_passphrase<F>(path: &str, passphrase: F) -> Result<Self, ConfigError>
+    where
+        F: FnOnce() -> Result<Zeroizing<String>, ConfigError>,
+    {
+        /// The possible formats of a keypair file.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum KeyFile {
+            Encrypted(EncryptedKeyPair),
+            Plaintext(KeyPair),
+        }
+
+        let import_error = |message: String| ConfigError::ImportError {
+            file: path.to_string(),
+            message,
+        };
+        let data = Zeroizing::new(fs::read(path).map_err(|e| import_error(e.to_string()))?);
+        match serde_json::from_slice(&data).map_err(|e| import_error(e.to_string()))? {
+            KeyFile::Plaintext(keypair) => Ok(keypair),
+            KeyFile::Encrypted(encrypted) => encrypted.decrypt(&passphrase()?),
+        }
+    }
+}
+
+impl EncryptedKeyPair {
+    /// Decrypt the keypair. Fails cleanly if the passphrase is wrong.
+    pub fn decrypt(&self, passphrase: &str) -> Result<KeyPair, ConfigError> {
+        let malformed = || ConfigError::MalformedKeyFile;
+        let salt = base64::decode(&self.encrypted.salt).map_err(|_| malformed())?;
+        let nonce = base64::decode(&self.encrypted.nonce).map_err(|_| malformed())?;
+        let ciphertext = base64::decode(&self.encrypted.ciphertext).map_err(|_| malformed())?;
+        let nonce: [u8; 12] = nonce.try_into().map_err(|_| malformed())?;
+
+        let EncryptedSecret { log_n, r, p, .. } = self.encrypted;
+        let key = derive_key(passphrase, &salt, log_n, r, p).ok_or_else(malformed)?;
+        let plaintext = Aes256Gcm::new_from_slice(&key[..])
+            .map_err(|_| malformed())?
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &self.name.0,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| ConfigError::WrongPassphrase)?;
+
+        let encoded = std::str::from_utf8(&plaintext).map_err(|_| malformed())?;
+        let secret = SecretKey::decode_base64(encoded).map_err(|_| malformed())?;
+        Ok(KeyPair {
+            name: self.name,
+            secret,
+        })
+    }
+}
+
+/// Derive a 256-bit encryption key from a passphrase.
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Option<Zeroizing<[u8; 32]>> {
+    let params = scrypt::Params::new(log_n, r, p, 32).ok()?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key[..]).ok()?;
+    Some(key)
+}
+
+/// Where to read the passphrase of an encrypted keypair file from.
+pub enum PassphraseSource {
+    /// An environment variable.
+    Environment(String),
+    /// The first line readable from an (inherited) file descriptor.
+    FileDescriptor(i32),
+    /// An interactive prompt on the terminal.
+    Prompt,
+}
+
+impl PassphraseSource {
+    pub fn read(&self) -> Result<Zeroizing<String>, ConfigError> {
+        let error = |e: std::io::Error| ConfigError::PassphraseUnavailable(e.to_string());
+        match self {
+            Self::Environment(variable) => std::env::var(variable)
+                .map(Zeroizing::new)
+                .map_err(|e| ConfigError::PassphraseUnavailable(e.to_string())),
+            #[cfg(unix)]
+            Self::FileDescriptor(fd) => {
+                use std::os::unix::io::FromRawFd as _;
+                // SAFETY: The caller hands us ownership of the file descriptor.
+                let file = unsafe { fs::File::from_raw_fd(*fd) };
+                let mut line = Zeroizing::new(String::new());
+                std::io::BufReader::new(file)
+                    .read_line(&mut line)
+                    .map_err(error)?;
+                Ok(Zeroizing::new(
+                    line.trim_end_matches(['\r', '\n']).to_string(),
+                ))
+            }
+            #[cfg(not(unix))]
+            Self::FileDescriptor(_) => Err(ConfigError::PassphraseUnavailable(
+                "File descriptors are only supported on unix".to_string(),
+            )),
+            Self::Prompt => rpassword::prompt_password("Key passphrase: ")
+                .map(Zeroizing::new)
+                .map_err(error),
+        }
+    }
+}
@@ -1,15 +1,28 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crypto::{generate_production_keypair, PublicKey, SecretKey};
+use crypto::{
+    generate_keypair_from_seed, generate_keypair_with_scheme, HashAlgorithm, PublicKey, Scheme,
+    SecretKey,
+};
 use log::info;
+use network::{ConcurrencyParameters, IpRules};
+use rand::rngs::OsRng;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, OpenOptions};
 use std::io::BufWriter;
 use std::io::Write as _;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
+mod keystore;
+
+pub use crate::keystore::{EncryptedKeyPair, EncryptedSecret, PassphraseSource};
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -18,6 +31,30 @@ pub enum ConfigError {
     #[error("Unknown worker id {0}")]
     UnknownWorker(WorkerId),
 
+    #[error("Signature scheme {0} is not supported by this build")]
+    UnsupportedScheme(Scheme),
+
+    #[error("Key uses signature scheme {key} but the committee uses {committee}")]
+    SchemeMismatch { key: Scheme, committee: Scheme },
+
+    #[error("Committee uses hash algorithm {committee} but this build uses {compiled}")]
+    HashAlgorithmMismatch {
+        committee: HashAlgorithm,
+        compiled: HashAlgorithm,
+    },
+
+    #[error("Authenticating workers requires the secret key of the authority")]
+    MissingWorkerSecret,
+
+    #[error("Wrong passphrase for the encrypted key file")]
+    WrongPassphrase,
+
+    #[error("Malformed encrypted key file")]
+    MalformedKeyFile,
+
+    #[error("Failed to read the key passphrase: {0}")]
+    PassphraseUnavailable(String),
+
     #[error("Failed to read config file '{file}': {message}")]
     ImportError { file: String, message: String },
 
@@ -25,6 +62,30 @@ pub enum ConfigError {
     ExportError { file: String, message: String },
 }
 
+/// Why the stake distribution of a committee does not admit BFT quorums.
+#[derive(Debug, Error, PartialEq)]
+pub enum QuorumError {
+    #[error("The committee has no authority")]
+    Empty,
+
+    #[error("Authority {0} has no stake")]
+    ZeroStake(PublicKey),
+
+    #[error("The total stake of the committee overflows")]
+    StakeOverflow,
+
+    #[error("Two quorums of {quorum} out of {total} stake may not share an honest authority")]
+    NoIntersection { quorum: Stake, total: Stake },
+
+    #[error("Authority {name} holds {stake} of {total} stake, more than the {faults} the committee tolerates to fail")]
+    DominantAuthority {
+        name: PublicKey,
+        stake: Stake,
+        total: Stake,
+        faults: Stake,
+    },
+}
+
 pub trait Import: DeserializeOwned {
     fn import(path: &str) -> Result<Self, ConfigError> {
         let reader = || -> Result<Self, std::io::Error> {
@@ -41,7 +102,11 @@ pub trait Import: DeserializeOwned {
 pub trait Export: Serialize {
     fn export(&self, path: &str) -> Result<(), ConfigError> {
         let writer = || -> Result<(), std::io::Error> {
-            let file = OpenOptions::new().create(true).write(true).open(path)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)?;
             let mut writer = BufWriter::new(file);
             let data = serde_json::to_string_pretty(self).unwrap();
             writer.write_all(data.as_ref())?;
@@ -57,8 +122,15 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+pub type Epoch = u64;
+pub type ProtocolVersion = u32;
+
+/// The first protocol version whose batch digests are the Merkle roots of their transactions
+/// (rather than the hash of the serialized batch).
+pub const MERKLE_BATCHES_VERSION: ProtocolVersion = 1;
 
 #[derive(Deserialize, Clone)]
+#[serde(default)]
 pub struct Parameters {
     /// The preferred header size. The primary creates a new header when it has enough parents and
     /// enough batches' digests to reach `header_size`. Denominated in bytes.
@@ -79,6 +151,73 @@ pub struct Parameters {
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// If set, workers compress the frames they send to each other when they are at least this
+    /// large and the peer supports compression. Denominated in bytes.
+    pub compression_threshold: Option<usize>,
+    /// The delay after which workers give up writing a reply to a peer that does not read it, and
+    /// close the connection. Denominated in ms.
+    pub write_timeout: u64,
+    /// The backlog of the listener receiving client transactions: how many connections the kernel
+    /// completes before the worker accepts them. Raise it (along with `net.core.somaxconn`) for
+    /// workloads where many clients connect at once.
+    pub listen_backlog: u32,
+    /// If set, workers close the connections of peers that send them batches or batch requests
+    /// from an IP address that is not one of the committee. Observers, previews and clients are
+    /// still served.
+    pub reject_non_committee: bool,
+    /// If set, workers prove their identity (the key of their authority) to each other when they
+    /// connect, and close the connections of peers sending them batches or batch requests without
+    /// proving they are in the committee. Clients and observers need not authenticate.
+    pub authenticate_workers: bool,
+    /// If set, nodes offer to encode the messages they send to other primaries and workers in
+    /// (versioned) protobuf rather than bincode. Peers that do not accept it keep receiving bincode.
+    pub proto_encoding: bool,
+    /// If not zero, workers hold the ACKs of the transactions they receive from a client for up to
+    /// this long (or until `ack_flush_size` are held) and write them together, which saves many
+    /// small writes for clients sending bursts of small transactions. Denominated in ms.
+    pub ack_flush_window: u64,
+    /// How many ACKs workers hold at most before writing them (with an `ack_flush_window`).
+    pub ack_flush_size: usize,
+    /// How many transactions the clients opening a windowed transaction channel may send to the
+    /// workers before waiting for their ACKs.
+    pub transaction_window: usize,
+    /// If not zero, workers hold the transactions of the clients for up to this long after the
+    /// first of a burst (or until `coalesce_size` are held) and hand them off to batching together,
+    /// which makes fuller batches out of bursty traffic. Denominated in ms.
+    pub coalesce_window: u64,
+    /// How many transactions workers hold at most before handing them off (with a
+    /// `coalesce_window`).
+    pub coalesce_size: usize,
+    /// If not zero, workers log one in this many of the frames they receive (with the peer, the
+    /// channel, the size of the frame, and where it was routed). Frames they reject are always
+    /// logged. The rate can be adjusted at runtime through the backlog of the worker.
+    pub request_log_rate: u64,
+    /// Bounds on the messages nodes accept from their peers.
+    pub limits: MessageLimits,
+    /// Decides which peers (by IP) may connect to the transaction and worker channels of the
+    /// workers. This is enforced before reading anything from the peers, and independently of
+    /// `reject_non_committee`.
+    pub ip_rules: IpRules,
+    /// Bounds the transactions that workers hold to release them in nonce order (only used by
+    /// workers configured with a transaction parser).
+    pub mempool: MempoolParameters,
+    /// The number of leaders consensus commits between two checkpoints (for nodes configured to
+    /// write checkpoints).
+    pub checkpoint_interval: u64,
+    /// How many times consensus retries to output a certificate (or sub-dag) while the channel of
+    /// its consumer is closed, before giving up on it.
+    pub output_retries: usize,
+    /// The delay between two attempts to output a certificate (or sub-dag). Denominated in ms.
+    pub output_retry_delay: u64,
+    /// If set, workers adapt how many client connections they serve at once to how fast they
+    /// handle the transactions (otherwise they serve every connection).
+    pub adaptive_concurrency: Option<ConcurrencyParameters>,
+    /// The budget of batch requests of each peer of the workers.
+    pub request_budget: RequestBudget,
+    /// If set, workers follow the commit stream of their primary on this address (its `--commits`
+    /// address), and send receipts to the clients that tagged their transactions once consensus
+    /// commits them.
+    pub commit_stream: Option<SocketAddr>,
 }
 
 impl Default for Parameters {
@@ -91,6 +230,27 @@ impl Default for Parameters {
             sync_retry_nodes: 3,
             batch_size: 500_000,
             max_batch_delay: 100,
+            compression_threshold: None,
+            write_timeout: 5_000,
+            listen_backlog: 1_024,
+            reject_non_committee: false,
+            authenticate_workers: false,
+            proto_encoding: false,
+            ack_flush_window: 0,
+            ack_flush_size: 100,
+            transaction_window: 1_000,
+            coalesce_window: 0,
+            coalesce_size: 1_000,
+            request_log_rate: 0,
+            limits: MessageLimits::default(),
+            ip_rules: IpRules::default(),
+            mempool: MempoolParameters::default(),
+            checkpoint_interval: 100,
+            output_retries: 3,
+            output_retry_delay: 100,
+            adaptive_concurrency: None,
+            request_budget: RequestBudget::default(),
+            commit_stream: None,
         }
     }
 }
@@ -106,6 +266,187 @@ impl Parameters {
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        match self.compression_threshold {
+            Some(threshold) => info!("Compression threshold set to {} B", threshold),
+            None => info!("Compression disabled"),
+        }
+        info!("Write timeout set to {} ms", self.write_timeout);
+        info!("Listen backlog set to {} connections", self.listen_backlog);
+        if self.reject_non_committee {
+            info!("Rejecting worker messages from outside the committee");
+        }
+        if self.authenticate_workers {
+            info!("Authenticating the connections between workers");
+        }
+        if self.proto_encoding {
+            info!("Offering protobuf encoding to peers");
+        }
+        match self.ack_flush_window {
+            0 => info!("ACKs flushed immediately"),
+            window => info!(
+                "ACKs flushed every {} ms or {} ACKs",
+                window, self.ack_flush_size
+            ),
+        }
+        info!(
+            "Transaction window set to {} transactions",
+            self.transaction_window
+        );
+        match self.coalesce_window {
+            0 => info!("Transactions handed off immediately"),
+            window => info!(
+                "Transactions coalesced for {} ms or {} transactions",
+                window, self.coalesce_size
+            ),
+        }
+        match self.request_log_rate {
+            0 => info!("Request log only records rejected frames"),
+            rate => info!("Request log records 1 in {} frames", rate),
+        }
+        info!(
+            "Max worker message size set to {} B",
+            self.limits.max_worker_message_size
+        );
+        info!(
+            "Max primary message size set to {} B",
+            self.limits.max_primary_message_size
+        );
+        info!(
+            "Max batch transactions set to {}",
+            self.limits.max_batch_transactions
+        );
+        info!("Max sync digests set to {}", self.limits.max_sync_digests);
+        info!(
+            "Checkpoint interval set to {} committed leaders",
+            self.checkpoint_interval
+        );
+        info!(
+            "Consensus output retried {} times every {} ms",
+            self.output_retries, self.output_retry_delay
+        );
+        info!(
+            "Batch requests budget set to {} per second (burst {}) and {} B per second (burst {} B)",
+            self.request_budget.requests_per_second,
+            self.request_budget.request_burst,
+            self.request_budget.bytes_per_second,
+            self.request_budget.byte_burst
+        );
+        if let Some(address) = self.commit_stream {
+            info!("Receipts sent for the commits streamed on {}", address);
+        }
+        if let Some(concurrency) = &self.adaptive_concurrency {
+            info!(
+                "Client connections adaptively capped between {} and {} (target latency {} ms)",
+                concurrency.min_connections,
+                concurrency.max_connections,
+                concurrency.target_latency
+            );
+        }
+        if !self.ip_rules.allows_all() {
+            info!(
+                "Workers allow {:?} and deny {:?} (other peers {})",
+                self.ip_rules.allow,
+                self.ip_rules.deny,
+                if self.ip_rules.default_allow {
+                    "allowed"
+                } else {
+                    "denied"
+                }
+            );
+        }
+    }
+}
+
+/// Bounds on the messages nodes deserialize from their peers. Messages beyond them are rejected
+/// (and the connection of their sender closed) before they can make us allocate much memory.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct MessageLimits {
+    /// The maximum size of the messages between workers (batches and batch requests). It must
+    /// exceed the size of the largest batch (see `batch_size`). Denominated in bytes.
+    pub max_worker_message_size: u64,
+    /// The maximum size of the messages between primaries, and between primaries and their
+    /// workers. Denominated in bytes.
+    pub max_primary_message_size: u64,
+    /// The maximum number of transactions of a batch.
+    pub max_batch_transactions: usize,
+    /// The maximum number of digests of a request to synchronize batches or certificates.
+    pub max_sync_digests: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_worker_message_size: 16_000_000,
+            max_primary_message_size: 4_000_000,
+            max_batch_transactions: 1_000_000,
+            max_sync_digests: 10_000,
+        }
+    }
+}
+
+/// What workers do when they receive a transaction with the sender and nonce of a transaction they
+/// hold, but different bytes.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplacementPolicy {
+    /// Keep the transaction received first (and drop the other).
+    KeepFirst,
+    /// Replace it with the transaction received last.
+    KeepLatest,
+}
+
+/// Bounds on the transactions workers hold until the transactions of the same sender with lower
+/// nonces arrive.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct MempoolParameters {
+    /// The maximum number of transactions held for a single sender. Transactions whose nonce is
+    /// further ahead of the next nonce of their sender are dropped.
+    pub max_pending_per_sender: usize,
+    /// The maximum number of transactions held overall.
+    pub max_pending: usize,
+    /// How long transactions may wait for their turn before being dropped. Denominated in ms.
+    pub ttl: u64,
+    /// Decides between two transactions with the same sender and nonce.
+    pub replacement: ReplacementPolicy,
+}
+
+impl Default for MempoolParameters {
+    fn default() -> Self {
+        Self {
+            max_pending_per_sender: 64,
+            max_pending: 100_000,
+            ttl: 10_000,
+            replacement: ReplacementPolicy::KeepFirst,
+        }
+    }
+}
+
+/// The budget of batch requests each peer may send to a worker: two token buckets, one for the
+/// requests and one for the bytes of the batches we send back. Requests over budget are throttled.
+/// The defaults leave plenty of room for a worker catching up.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct RequestBudget {
+    /// The requests a peer may send per second, on average.
+    pub requests_per_second: u64,
+    /// The requests a peer may send at once.
+    pub request_burst: u64,
+    /// The bytes we may send a peer per second, on average.
+    pub bytes_per_second: u64,
+    /// The bytes we may send a peer at once.
+    pub byte_burst: u64,
+}
+
+impl Default for RequestBudget {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 100,
+            request_burst: 1_000,
+            bytes_per_second: 50_000_000,
+            byte_burst: 500_000_000,
+        }
     }
 }
 
@@ -140,11 +481,93 @@ pub struct Authority {
 #[derive(Clone, Deserialize)]
 pub struct Committee {
     pub authorities: BTreeMap<PublicKey, Authority>,
+    /// The epoch of the committee: it changes whenever the authorities (or their stake) do.
+    #[serde(default)]
+    pub epoch: Epoch,
+    /// The version of the protocol run by all authorities during the epoch. It changes the digests
+    /// of batches, so it may only change along with the epoch.
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    /// The signature scheme used by all authorities.
+    #[serde(default)]
+    pub scheme: Scheme,
+    /// The hash algorithm used by all authorities to compute digests.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
 }
 
 impl Import for Committee {}
 
 impl Committee {
+    /// Checks that the committee's signature scheme is compiled in and matches the scheme of
+    /// the node's key.
+    pub fn check_scheme(&self, scheme: Scheme) -> Result<(), ConfigError> {
+        if !self.scheme.is_supported() {
+            return Err(ConfigError::UnsupportedScheme(self.scheme));
+        }
+        if scheme != self.scheme {
+            return Err(ConfigError::SchemeMismatch {
+                key: scheme,
+                committee: self.scheme,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the committee's hash algorithm is the one this binary was compiled with.
+    pub fn check_hash_algorithm(&self) -> Result<(), ConfigError> {
+        let compiled = HashAlgorithm::compiled();
+        if self.hash_algorithm != compiled {
+            return Err(ConfigError::HashAlgorithmMismatch {
+                committee: self.hash_algorithm,
+                compiled,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the digests of batches are the Merkle roots of their transactions.
+    pub fn merkle_batches(&self) -> bool {
+        self.protocol_version >= MERKLE_BATCHES_VERSION
+    }
+
+    /// Deterministically builds a committee of `n` authorities with unit stake, keys derived from
+    /// `seed` (see `KeyPair::new_for_test`), and a single worker each. Every address is on
+    /// localhost and ports are assigned sequentially from `base_port`: first the `n` primary-to-
+    /// primary ports, then the `n` worker-to-primary ports, and so on (`5 * n` ports in total).
+    pub fn new_for_test(n: usize, base_port: u16, seed: u64) -> Self {
+        let address = |kind: usize, i: usize| {
+            let port = base_port as usize + kind * n + i;
+            SocketAddr::from(([127, 0, 0, 1], port as u16))
+        };
+        let authorities = (0..n)
+            .map(|i| {
+                let primary = PrimaryAddresses {
+                    primary_to_primary: address(0, i),
+                    worker_to_primary: address(1, i),
+                };
+                let worker = WorkerAddresses {
+                    primary_to_worker: address(2, i),
+                    transactions: address(3, i),
+                    worker_to_worker: address(4, i),
+                };
+                let authority = Authority {
+                    stake: 1,
+                    primary,
+                    workers: [(0, worker)].iter().cloned().collect(),
+                };
+                (KeyPair::new_for_test(seed, i).name, authority)
+            })
+            .collect();
+        Self {
+            authorities,
+            epoch: 0,
+            protocol_version: 0,
+            scheme: Scheme::Ed25519,
+            hash_algorithm: HashAlgorithm::compiled(),
+        }
+    }
+
     /// Returns the number of authorities.
     pub fn size(&self) -> usize {
         self.authorities.len()
@@ -152,7 +575,7 @@ impl Committee {
 
     /// Return the stake of a specific authority.
     pub fn stake(&self, name: &PublicKey) -> Stake {
-        self.authorities.get(name).map_or_else(|| 0, |x| x.stake)
+        self.authorities.get(name).map_or(0, |x| x.stake)
     }
 
     /// Returns the stake of all authorities except `myself`.
@@ -177,7 +600,54 @@ impl Committee {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (N + 2) / 3 = f + 1 + k/3 = f + 1
         let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
-        (total_votes + 2) / 3
+        total_votes.div_ceil(3)
+    }
+
+    /// Returns the stake the committee tolerates to fail (f). Committees of one or two authorities
+    /// tolerate no failure: their quorum is the whole committee, and a single authority (holding
+    /// f+1 stake) makes a certificate available.
+    pub fn fault_tolerance(&self) -> Stake {
+        let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
+        total_votes.saturating_sub(self.quorum_threshold())
+    }
+
+    /// Checks that the stake distribution admits BFT quorums: any two quorums share more stake
+    /// than the committee tolerates to fail (so they share an honest authority), and no single
+    /// authority holds more than that stake (otherwise its failure alone prevents any quorum).
+    /// Committees tolerating no failure at all (e.g., of one or two authorities) are valid.
+    pub fn check_quorums(&self) -> Result<(), QuorumError> {
+        if self.authorities.is_empty() {
+            return Err(QuorumError::Empty);
+        }
+        let mut total: Stake = 0;
+        for (name, authority) in &self.authorities {
+            if authority.stake == 0 {
+                return Err(QuorumError::ZeroStake(*name));
+            }
+            total = total
+                .checked_add(authority.stake)
+                .ok_or(QuorumError::StakeOverflow)?;
+        }
+        total.checked_mul(2).ok_or(QuorumError::StakeOverflow)?;
+
+        let quorum = self.quorum_threshold();
+        let faults = total.saturating_sub(quorum);
+        if quorum > total || 2 * quorum - total <= faults {
+            return Err(QuorumError::NoIntersection { quorum, total });
+        }
+        match self
+            .authorities
+            .iter()
+            .find(|(_, x)| faults > 0 && x.stake > faults)
+        {
+            Some((name, authority)) => Err(QuorumError::DominantAuthority {
+                name: *name,
+                stake: authority.stake,
+                total,
+                faults,
+            }),
+            None => Ok(()),
+        }
     }
 
     /// Returns a leader node in a round-robin fashion.
@@ -192,7 +662,7 @@ impl Committee {
         self.authorities
             .get(to)
             .map(|x| x.primary.clone())
-            .ok_or_else(|| ConfigError::NotInCommittee(*to))
+            .ok_or(ConfigError::NotInCommittee(*to))
     }
 
     /// Returns the addresses of all primaries except `myself`.
@@ -210,12 +680,12 @@ impl Committee {
             .iter()
             .find(|(name, _)| name == &to)
             .map(|(_, authority)| authority)
-            .ok_or_else(|| ConfigError::NotInCommittee(*to))?
+            .ok_or(ConfigError::NotInCommittee(*to))?
             .workers
             .iter()
             .find(|(worker_id, _)| worker_id == &id)
             .map(|(_, worker)| worker.clone())
-            .ok_or_else(|| ConfigError::NotInCommittee(*to))
+            .ok_or(ConfigError::NotInCommittee(*to))
     }
 
     /// Returns the addresses of all our workers.
@@ -224,7 +694,7 @@ impl Committee {
             .iter()
             .find(|(name, _)| name == &myself)
             .map(|(_, authority)| authority)
-            .ok_or_else(|| ConfigError::NotInCommittee(*myself))?
+            .ok_or(ConfigError::NotInCommittee(*myself))?
             .workers
             .values()
             .cloned()
@@ -232,6 +702,21 @@ impl Committee {
             .collect()
     }
 
+    /// Returns the IP addresses of all the machines of the committee (primaries and workers).
+    pub fn ips(&self) -> HashSet<IpAddr> {
+        let mut ips = HashSet::new();
+        for authority in self.authorities.values() {
+            ips.insert(authority.primary.primary_to_primary.ip());
+            ips.insert(authority.primary.worker_to_primary.ip());
+            for addresses in authority.workers.values() {
+                ips.insert(addresses.transactions.ip());
+                ips.insert(addresses.worker_to_worker.ip());
+                ips.insert(addresses.primary_to_worker.ip());
+            }
+        }
+        ips
+    }
+
     /// Returns the addresses of all workers with a specific id except the ones of the authority
     /// specified by `myself`.
     pub fn others_workers(
@@ -266,9 +751,29 @@ impl Export for KeyPair {}
 
 impl KeyPair {
     pub fn new() -> Self {
-        let (name, secret) = generate_production_keypair();
+        Self::new_with_scheme(Scheme::default())
+    }
+
+    /// Generates a fresh keypair for the specified signature scheme.
+    pub fn new_with_scheme(scheme: Scheme) -> Self {
+        let (name, secret) = generate_keypair_with_scheme(scheme, &mut OsRng);
         Self { name, secret }
     }
+
+    /// Deterministically derives an (ed25519) keypair from a seed. Only use this for tests and
+    /// local benchmarks.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let (name, secret) = generate_keypair_from_seed(seed);
+        Self { name, secret }
+    }
+
+    /// The keypair of the `index`-th authority of `Committee::new_for_test(_, _, seed)`.
+    pub fn new_for_test(seed: u64, index: usize) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        bytes[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+        Self::from_seed(bytes)
+    }
 }
 
 impl Default for KeyPair {
@@ -0,0 +1,81 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn legacy_committee_uses_sha512() {
+    let committee: Committee = serde_json::from_str(r#"{ "authorities": {} }"#).unwrap();
+    assert_eq!(committee.hash_algorithm, HashAlgorithm::Sha512);
+}
+
+#[test]
+fn accept_compiled_hash_algorithm() {
+    let json = format!(
+        r#"{{ "authorities": {{}}, "hash_algorithm": "{}" }}"#,
+        HashAlgorithm::compiled()
+    );
+    let committee: Committee = serde_json::from_str(&json).unwrap();
+    assert!(committee.check_hash_algorithm().is_ok());
+}
+
+#[test]
+fn reject_other_hash_algorithm() {
+    let other = match HashAlgorithm::compiled() {
+        HashAlgorithm::Sha512 => "blake3",
+        HashAlgorithm::Blake3 => "sha512",
+    };
+    let json = format!(
+        r#"{{ "authorities": {{}}, "hash_algorithm": "{}" }}"#,
+        other
+    );
+    let committee: Committee = serde_json::from_str(&json).unwrap();
+    match committee.check_hash_algorithm() {
+        Err(ConfigError::HashAlgorithmMismatch {
+            committee,
+            compiled,
+        }) => {
+            assert_eq!(committee.to_string(), other);
+            assert_eq!(compiled, HashAlgorithm::compiled());
+        }
+        _ => panic!("Unexpected result"),
+    }
+}
+
+// Pins the committee generated for seed 0: changing the generator silently changes every test fixture.
+#[test]
+fn new_for_test_snapshot() {
+    let expected = [
+        ("IP26ybELdYe7p7W8FjvOaeeW1x5O1EwQ/LRIhon3oUQ=", 0),
+        ("PfNMft8JzpYR6G/iwqHR8d31wlqPJV2hc+kcAii4j4E=", 1),
+        ("cD2S8Gvbn4oDOrYq3q7NlOxt4jehBYQ/OQ1f3myiKAs=", 2),
+        ("sTvnU2xxOXoiT4COPC8hl8q9nWIIKvYqg+PfcrqFfDI=", 3),
+    ];
+    let committee = Committee::new_for_test(4, 3_000, 0);
+    assert_eq!(committee.size(), expected.len());
+    for (name, i) in expected {
+        let name = PublicKey::decode_base64(name).unwrap();
+        assert_eq!(KeyPair::new_for_test(0, i).name, name);
+
+        let authority = &committee.authorities[&name];
+        assert_eq!(authority.stake, 1);
+        let port = |kind: u16| format!("127.0.0.1:{}", 3_000 + kind * 4 + i as u16);
+        assert_eq!(authority.primary.primary_to_primary.to_string(), port(0));
+        assert_eq!(authority.primary.worker_to_primary.to_string(), port(1));
+        let worker = &authority.workers[&0];
+        assert_eq!(worker.primary_to_worker.to_string(), port(2));
+        assert_eq!(worker.transactions.to_string(), port(3));
+        assert_eq!(worker.worker_to_worker.to_string(), port(4));
+    }
+    assert!(committee.check_hash_algorithm().is_ok());
+}
+
+#[test]
+fn seeded_keys_are_deterministic() {
+    let keypair = KeyPair::new_for_test(1, 2);
+    let other = KeyPair::new_for_test(1, 2);
+    assert_eq!(keypair.name, other.name);
+    assert_eq!(keypair.secret.encode_base64(), other.secret.encode_base64());
+
+    // Different seeds or indices give different keys.
+    assert_ne!(KeyPair::new_for_test(2, 2).name, keypair.name);
+    assert_ne!(KeyPair::new_for_test(1, 3).name, keypair.name);
+}
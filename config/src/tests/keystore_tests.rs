@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Keep the tests fast: the default scrypt cost is only meant for production.
+const TEST_LOG_N: u8 = 4;
+
+fn passphrase(s: &str) -> impl FnOnce() -> Result<Zeroizing<String>, ConfigError> + '_ {
+    move || Ok(Zeroizing::new(s.to_string()))
+}
+
+#[test]
+fn encrypt_decrypt() {
+    let keypair = KeyPair::new();
+    let encrypted = keypair.encrypt_with_cost("correct horse", TEST_LOG_N);
+    let decrypted = encrypted.decrypt("correct horse").unwrap();
+    assert_eq!(decrypted.name, keypair.name);
+    assert_eq!(
+        decrypted.secret.encode_base64(),
+        keypair.secret.encode_base64()
+    );
+}
+
+#[test]
+fn wrong_passphrase() {
+    let encrypted = KeyPair::new().encrypt_with_cost("correct horse", TEST_LOG_N);
+    match encrypted.decrypt("battery staple") {
+        Err(ConfigError::WrongPassphrase) => (),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn import_encrypted_file() {
+    let path = ".test_encrypted_keys.json";
+    let keypair = KeyPair::new();
+    keypair
+        .encrypt_with_cost("correct horse", TEST_LOG_N)
+        .export(path)
+        .unwrap();
+
+    let imported = KeyPair::import_with_passphrase(path, passphrase("correct horse")).unwrap();
+    assert_eq!(imported.name, keypair.name);
+
+    let result = KeyPair::import_with_passphrase(path, passphrase("battery staple"));
+    assert!(matches!(result, Err(ConfigError::WrongPassphrase)));
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn import_plaintext_file() {
+    let path = ".test_plaintext_keys.json";
+    let keypair = KeyPair::new();
+    keypair.export(path).unwrap();
+
+    // The passphrase is not requested for legacy plaintext files.
+    let imported = KeyPair::import_with_passphrase(path, || panic!("Unexpected prompt")).unwrap();
+    assert_eq!(imported.name, keypair.name);
+    let _ = fs::remove_file(path);
+}
@@ -0,0 +1,325 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{certificate_cmp, EvidencePool, LeaderExclusions, State};
+use config::{Committee, Epoch, Stake};
+use crypto::Hash as _;
+use crypto::{CryptoError, Digest, PublicKey, SecretKey, Signature};
+use log::{debug, info, warn};
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc::{channel, Sender};
+
+/// How many checkpoints wait to be written at most. Checkpoints taken while the writer is busy
+/// are skipped (the next one supersedes them anyway).
+const CHECKPOINT_QUEUE: usize = 1;
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Failed to access checkpoint: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to (de)serialize checkpoint: {0}")]
+    Serialization(#[from] Box<bincode::ErrorKind>),
+
+    #[error("The digest of the checkpoint does not match its manifest")]
+    InvalidDigest,
+
+    #[error("Checkpoint author {0} is not a committee member")]
+    UnknownAuthor(PublicKey),
+
+    #[error("Invalid checkpoint signature: {0}")]
+    InvalidSignature(#[from] CryptoError),
+
+    #[error("Checkpoint of epoch {0}, but the committee is at epoch {1}")]
+    EpochMismatch(Epoch, Epoch),
+
+    #[error("The authorities of the checkpoint do not match the committee")]
+    AuthorityMismatch,
+
+    #[error("Invalid certificate {0} in checkpoint: {1}")]
+    InvalidCertificate(Digest, String),
+
+    #[error("Checkpoint holds several certificates of {0} at round {1}")]
+    DuplicateCertificate(PublicKey, Round),
+
+    #[error("Checkpoint holds certificate {0} below its garbage collection round {1}")]
+    BelowGcRound(Digest, Round),
+}
+
+/// The consensus state at a given commit, along with the certificates of the rounds it did not
+/// garbage collect yet: enough to resume consensus without replaying the dag from genesis.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The epoch of the committee.
+    pub epoch: Epoch,
+    /// The authorities of the committee, and their stake.
+    pub authorities: BTreeMap<PublicKey, Stake>,
+    /// The number of leaders committed so far.
+    pub index: u64,
+    /// All rounds below this one were garbage collected.
+    pub gc_round: Round,
+    /// The last committed round of each authority.
+    pub last_committed: BTreeMap<PublicKey, Round>,
+    /// The certificates of the dag, sorted by round and author.
+    pub certificates: Vec<Certificate>,
+    /// The digests of the certificates cleaned up from the dag after being committed, by round.
+    pub pruned: BTreeMap<Round, BTreeSet<Digest>>,
+    /// The committed leaders of the rounds that were not garbage collected, sorted by round.
+    pub leaders: Vec<Certificate>,
+}
+
+impl Manifest {
+    /// Returns the hash of the (serialized) manifest.
+    pub fn digest(&self) -> Digest {
+        let bytes = bincode::serialize(self).expect("Failed to serialize manifest");
+        crypto::hash(&bytes)
+    }
+}
+
+/// A manifest signed by the node that took it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub manifest: Manifest,
+    /// The hash of the manifest.
+    pub digest: Digest,
+    /// The node that took the checkpoint.
+    pub author: PublicKey,
+    /// The signature of the author over `digest`.
+    pub signature: Signature,
+}
+
+impl Checkpoint {
+    pub fn new(manifest: Manifest, author: PublicKey, secret: &SecretKey) -> Self {
+        let digest = manifest.digest();
+        let signature = Signature::new(&digest, secret);
+        Self {
+            manifest,
+            digest,
+            author,
+            signature,
+        }
+    }
+
+    /// Checks the checkpoint against the committee: its digest and signature, its authorities, and
+    /// every certificate it holds.
+    pub fn verify(&self, committee: &Committee) -> Result<(), CheckpointError> {
+        if self.manifest.digest() != self.digest {
+            return Err(CheckpointError::InvalidDigest);
+        }
+        if committee.stake(&self.author) == 0 {
+            return Err(CheckpointError::UnknownAuthor(self.author));
+        }
+        self.signature.verify(&self.digest, &self.author)?;
+
+        let manifest = &self.manifest;
+        if manifest.epoch != committee.epoch {
+            return Err(CheckpointError::EpochMismatch(
+                manifest.epoch,
+                committee.epoch,
+            ));
+        }
+        let authorities = committee
+            .authorities
+            .iter()
+            .map(|(name, authority)| (*name, authority.stake));
+        if !authorities.eq(manifest.authorities.iter().map(|(x, y)| (*x, *y)))
+            || !manifest
+                .last_committed
+                .keys()
+                .eq(manifest.authorities.keys())
+        {
+            return Err(CheckpointError::AuthorityMismatch);
+        }
+
+        let mut seen = BTreeSet::new();
+        for certificate in &manifest.certificates {
+            if certificate.round() < manifest.gc_round {
+                return Err(CheckpointError::BelowGcRound(
+                    certificate.digest(),
+                    manifest.gc_round,
+                ));
+            }
+            if !seen.insert((certificate.round(), certificate.origin())) {
+                return Err(CheckpointError::DuplicateCertificate(
+                    certificate.origin(),
+                    certificate.round(),
+                ));
+            }
+        }
+        for certificate in manifest.certificates.iter().chain(&manifest.leaders) {
+            certificate.verify(committee).map_err(|e| {
+                CheckpointError::InvalidCertificate(certificate.digest(), e.to_string())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the checkpoint to a file. The file is written under a temporary name first, so
+    /// that a crash never leaves a partial checkpoint behind.
+    pub fn write(&self, path: &Path) -> Result<(), CheckpointError> {
+        let bytes = bincode::serialize(self)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Reads a checkpoint from a file (without verifying it).
+    pub fn read(path: &Path) -> Result<Self, CheckpointError> {
+        let bytes = fs::read(path)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+}
+
+impl State {
+    /// Returns the number of leaders committed so far.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Takes a checkpoint of the state.
+    pub fn checkpoint(&self, committee: &Committee) -> Manifest {
+        let mut certificates: Vec<_> = self
+            .dag
+            .values()
+            .flat_map(|x| x.values().map(|(_, certificate)| certificate.clone()))
+            .collect();
+        certificates.sort_by(certificate_cmp);
+        Manifest {
+            epoch: committee.epoch,
+            authorities: committee
+                .authorities
+                .iter()
+                .map(|(name, authority)| (*name, authority.stake))
+                .collect(),
+            index: self.index,
+            gc_round: self.gc_round,
+            last_committed: self.sorted_last_committed(),
+            certificates,
+            pruned: self
+                .pruned
+                .iter()
+                .map(|(round, digests)| (*round, digests.iter().cloned().collect()))
+                .collect(),
+            leaders: self
+                .committed_leaders
+                .range(self.gc_round..)
+                .map(|(_, x)| x.clone())
+                .collect(),
+        }
+    }
+
+    /// Restores the state from a checkpoint, after verifying it against the committee. The
+    /// evidence of equivocations is not part of checkpoints: the restored state starts without.
+    pub fn restore(committee: &Committee, checkpoint: Checkpoint) -> Result<Self, CheckpointError> {
+        checkpoint.verify(committee)?;
+        let manifest = checkpoint.manifest;
+
+        let mut dag: crate::Dag = HashMap::new();
+        for certificate in manifest.certificates {
+            dag.entry(certificate.round())
+                .or_default()
+                .insert(certificate.origin(), (certificate.digest(), certificate));
+        }
+        Ok(Self {
+            last_committed_round: manifest
+                .last_committed
+                .values()
+                .max()
+                .cloned()
+                .unwrap_or_default(),
+            last_committed: manifest.last_committed.into_iter().collect(),
+            dag,
+            committed_leaders: manifest
+                .leaders
+                .into_iter()
+                .map(|x| (x.round(), x))
+                .collect(),
+            leader_retention: None,
+            leaders_floor: manifest.gc_round,
+            pruned: manifest
+                .pruned
+                .into_iter()
+                .map(|(round, digests)| (round, digests.into_iter().collect()))
+                .collect(),
+            gc_round: manifest.gc_round,
+            index: manifest.index,
+            equivocations: BTreeMap::new(),
+            evidence: EvidencePool::default(),
+            exclusions: LeaderExclusions::default(),
+            certificate_budget: None,
+            admissions: HashMap::new(),
+            capacity: None,
+            tx_handoffs: None,
+            observed_leader: None,
+            verifier: None,
+        })
+    }
+}
+
+/// Takes a checkpoint of the consensus state every `interval` committed leaders. Taking the
+/// checkpoint only copies the state: a separate task signs it and writes it (on a blocking
+/// thread), so that it never holds up the commit path.
+pub struct Checkpointer {
+    /// The number of committed leaders between two checkpoints.
+    interval: u64,
+    /// The index of the last checkpoint.
+    last: u64,
+    /// Hands the checkpoints to the writer task.
+    tx_manifest: Sender<Manifest>,
+}
+
+impl Checkpointer {
+    /// Spawns the task writing the checkpoints (signed by `name`) to files named
+    /// `checkpoint-<index>` in the specified directory.
+    pub fn spawn(name: PublicKey, secret: SecretKey, directory: PathBuf, interval: u64) -> Self {
+        let (tx_manifest, mut rx_manifest) = channel::<Manifest>(CHECKPOINT_QUEUE);
+        let secret = Arc::new(secret);
+        tokio::spawn(async move {
+            while let Some(manifest) = rx_manifest.recv().await {
+                let index = manifest.index;
+                let path = directory.join(format!("checkpoint-{}", index));
+                let secret = secret.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    fs::create_dir_all(path.parent().expect("Checkpoint files have a parent"))?;
+                    Checkpoint::new(manifest, name, &secret).write(&path)
+                })
+                .await
+                .expect("Failed to write checkpoint");
+                match result {
+                    Ok(()) => info!("Wrote checkpoint {}", index),
+                    Err(e) => warn!("Failed to write checkpoint {}: {}", index, e),
+                }
+            }
+        });
+        Self {
+            interval: interval.max(1),
+            last: 0,
+            tx_manifest,
+        }
+    }
+
+    /// Starts counting committed leaders from the index of the specified state.
+    pub(crate) fn start_from(&mut self, state: &State) {
+        self.last = state.index;
+    }
+
+    /// Takes a checkpoint of the state if it committed enough leaders since the last one.
+    pub(crate) fn observe(&mut self, state: &State, committee: &Committee) {
+        if state.index < self.last + self.interval {
+            return;
+        }
+        self.last = state.index;
+        if self
+            .tx_manifest
+            .try_send(state.checkpoint(committee))
+            .is_err()
+        {
+            debug!("Skipping checkpoint {}: writer busy", state.index);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, LeaderExclusions, State};
+use config::{Committee, Stake};
+use crypto::Hash as _;
+use crypto::PublicKey;
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CommitProofError {
+    #[error("Round {0} is not a leader round")]
+    NotLeaderRound(Round),
+
+    #[error("Leader of round {round} should be {expected}, found {found}")]
+    WrongLeader {
+        round: Round,
+        expected: PublicKey,
+        found: PublicKey,
+    },
+
+    #[error("Supporting certificate of {0} is not from round {1}")]
+    WrongRound(PublicKey, Round),
+
+    #[error("Supporting certificate of {0} does not reference the leader")]
+    NotSupporting(PublicKey),
+
+    #[error("Supporting certificate of {0} is not from a committee member")]
+    UnknownAuthority(PublicKey),
+
+    #[error("Proof holds several supporting certificates of {0}")]
+    DuplicateOrigin(PublicKey),
+
+    #[error("Leader has {0} stake of support, below the threshold of {1}")]
+    NotEnoughSupport(Stake, Stake),
+}
+
+/// Proves that a leader was committed: the leader's certificate along with certificates of the
+/// next round referencing it, from authorities holding (at least) f+1 stake. This is the direct
+/// commit rule of consensus, so verifying it does not require the dag.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommitProof {
+    /// The committed leader.
+    pub leader: Certificate,
+    /// The certificates supporting the leader, sorted by authority.
+    pub support: Vec<Certificate>,
+}
+
+impl State {
+    /// Returns the minimal proof that the leader is committed: the fewest certificates of our dag
+    /// supporting it whose stake reaches the commit threshold. Returns `None` if we do not hold
+    /// the leader, or if it does not have enough support.
+    pub fn commit_proof(&self, leader: &Certificate, committee: &Committee) -> Option<CommitProof> {
+        let digest = leader.digest();
+        let (held, _) = self.dag.get(&leader.round())?.get(&leader.origin())?;
+        if held != &digest {
+            return None;
+        }
+
+        // Pick the supporters with the highest stake first.
+        let mut supporters: Vec<_> = self.supporters(&digest, leader.round()).collect();
+        supporters.sort_by_key(|x| (std::cmp::Reverse(committee.stake(&x.origin())), x.origin()));
+
+        let threshold = committee.validity_threshold();
+        let mut stake = 0;
+        let mut support = Vec::new();
+        for certificate in supporters {
+            if stake >= threshold {
+                break;
+            }
+            stake += committee.stake(&certificate.origin());
+            support.push(certificate.clone());
+        }
+        if stake < threshold {
+            return None;
+        }
+        support.sort_by_key(|x| x.origin());
+        Some(CommitProof {
+            leader: leader.clone(),
+            support,
+        })
+    }
+}
+
+/// Checks a commit proof against the committee: the leader must be the elected leader of its
+/// (even) round, and distinct committee members holding f+1 stake must reference it from the next
+/// round. This does not verify the certificates' signatures, use `Certificate::verify` for that.
+/// Leaders are elected without exclusions (see `LeaderExclusions`).
+pub fn verify_commit_proof(
+    proof: &CommitProof,
+    committee: &Committee,
+) -> Result<(), CommitProofError> {
+    let round = proof.leader.round();
+    if !round.is_multiple_of(2) || round < 2 {
+        return Err(CommitProofError::NotLeaderRound(round));
+    }
+    let expected = elect_leader(committee, round, &LeaderExclusions::default());
+    if proof.leader.origin() != expected {
+        return Err(CommitProofError::WrongLeader {
+            round,
+            expected,
+            found: proof.leader.origin(),
+        });
+    }
+
+    let digest = proof.leader.digest();
+    let mut origins = HashSet::new();
+    let mut stake = 0;
+    for certificate in &proof.support {
+        let origin = certificate.origin();
+        if certificate.round() != round + 1 {
+            return Err(CommitProofError::WrongRound(origin, round + 1));
+        }
+        if !certificate.header.parents.contains(&digest) {
+            return Err(CommitProofError::NotSupporting(origin));
+        }
+        if !committee.authorities.contains_key(&origin) {
+            return Err(CommitProofError::UnknownAuthority(origin));
+        }
+        if !origins.insert(origin) {
+            return Err(CommitProofError::DuplicateOrigin(origin));
+        }
+        stake += committee.stake(&origin);
+    }
+
+    let threshold = committee.validity_threshold();
+    if stake < threshold {
+        return Err(CommitProofError::NotEnoughSupport(stake, threshold));
+    }
+    Ok(())
+}
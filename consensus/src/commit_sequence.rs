@@ -0,0 +1,183 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, State};
+use config::{Committee, Stake};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use primary::{Certificate, Round};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// The first reason why a sequence of certificates is not a valid commit order. Certificates are
+/// designated by their position in the sequence.
+#[derive(Debug, Error, PartialEq)]
+pub enum CommitViolation {
+    #[error("Certificate {1} at position {0} is unknown")]
+    UnknownCertificate(usize, Digest),
+
+    #[error("Certificate at position {0} precedes its parent {1}")]
+    OutOfOrder(usize, Digest),
+
+    #[error("Sub-dag ending at position {0} ends at round {1}, which is not a leader round")]
+    NotLeaderRound(usize, Round),
+
+    #[error("Leader of round {round} (position {index}) should be {expected}, found {found}")]
+    WrongLeader {
+        index: usize,
+        round: Round,
+        expected: PublicKey,
+        found: PublicKey,
+    },
+
+    #[error("Leader at position {0} has {1} stake of support, below the threshold of {2}, and no later leader links to it")]
+    NotEnoughSupport(usize, Stake, Stake),
+}
+
+impl State {
+    /// Checks that a sequence of certificates (e.g., received from another node or an archive) is
+    /// a valid commit order according to our state, and returns the first violation otherwise:
+    /// - every certificate is in our dag, or was cleaned up from it, or is a leader we committed;
+    /// - every certificate comes after its parents that the sequence holds;
+    /// - the sequence is made of sub-dags, each ending with the elected leader of its (even)
+    ///   round, which has f+1 support from the next round or is linked to the next leader.
+    ///
+    /// Sub-dags are sorted by round and their leader is alone in its round, so a sub-dag ends
+    /// where the rounds of the sequence decrease, or where the elected leader of a round is
+    /// followed by another certificate of the same round (or at the end of the sequence).
+    /// Support and links are looked up in the sequence and in our dag. Leaders are elected with
+    /// our current exclusions.
+    pub fn verify_commit_sequence(
+        &self,
+        sequence: &[Certificate],
+        committee: &Committee,
+    ) -> Result<(), CommitViolation> {
+        let digests: Vec<_> = sequence.iter().map(|x| x.digest()).collect();
+        let positions: HashMap<_, _> = digests.iter().enumerate().map(|(i, x)| (x, i)).collect();
+
+        // The certificates we can follow links through.
+        let mut certificates: HashMap<&Digest, &Certificate> = self
+            .dag
+            .values()
+            .flat_map(|x| x.values())
+            .map(|(digest, certificate)| (digest, certificate))
+            .collect();
+        certificates.extend(digests.iter().zip(sequence));
+
+        let is_leader_of_round = |certificate: &Certificate| {
+            let round = certificate.round();
+            round.is_multiple_of(2)
+                && round >= 2
+                && certificate.origin() == elect_leader(committee, round, &self.exclusions)
+        };
+        let leaders: Vec<_> = (0..sequence.len())
+            .filter(|i| match sequence.get(i + 1) {
+                None => true,
+                Some(next) => {
+                    let round = sequence[*i].round();
+                    next.round() < round
+                        || (next.round() == round && is_leader_of_round(&sequence[*i]))
+                }
+            })
+            .collect();
+        let mut next_leaders = leaders.iter().skip(1);
+
+        let mut leaders = leaders.iter().peekable();
+        for (index, certificate) in sequence.iter().enumerate() {
+            let digest = &digests[index];
+            let round = certificate.round();
+            if !self.knows(digest, certificate) {
+                return Err(CommitViolation::UnknownCertificate(index, digest.clone()));
+            }
+            if let Some(parent) = certificate
+                .header
+                .parents
+                .iter()
+                .find(|x| positions.get(x).is_some_and(|i| *i > index))
+            {
+                return Err(CommitViolation::OutOfOrder(index, parent.clone()));
+            }
+
+            if leaders.next_if_eq(&&index).is_none() {
+                continue;
+            }
+            if !round.is_multiple_of(2) || round < 2 {
+                return Err(CommitViolation::NotLeaderRound(index, round));
+            }
+            let expected = elect_leader(committee, round, &self.exclusions);
+            if certificate.origin() != expected {
+                return Err(CommitViolation::WrongLeader {
+                    index,
+                    round,
+                    expected,
+                    found: certificate.origin(),
+                });
+            }
+
+            // The leader is committed directly (through its support) or through the next leader.
+            let supporters: HashSet<_> = certificates
+                .values()
+                .filter(|x| x.round() == round + 1 && x.header.parents.contains(digest))
+                .map(|x| x.origin())
+                .collect();
+            let stake: Stake = supporters.iter().map(|x| committee.stake(x)).sum();
+            let threshold = committee.validity_threshold();
+            let linked = next_leaders
+                .next()
+                .is_some_and(|i| linked(&sequence[*i], digest, round, &certificates));
+            if stake < threshold && !linked {
+                return Err(CommitViolation::NotEnoughSupport(index, stake, threshold));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the certificate is in our dag, was cleaned up from it, or is a leader we
+    /// committed.
+    fn knows(&self, digest: &Digest, certificate: &Certificate) -> bool {
+        let round = certificate.round();
+        self.dag
+            .get(&round)
+            .is_some_and(|x| x.values().any(|(x, _)| x == digest))
+            || self.pruned.get(&round).is_some_and(|x| x.contains(digest))
+            || self.committed_leaders.get(&round) == Some(certificate)
+    }
+}
+
+/// Checks that every certificate of a commit sequence comes after its parents that the sequence
+/// holds (the others were committed before), and returns the position of the first certificate
+/// preceding one of its parents otherwise. Unlike `State::verify_commit_sequence`, it needs no
+/// state: consensus checks its own output with it (in debug builds).
+pub fn verify_causal_order(sequence: &[Certificate]) -> Result<(), usize> {
+    let positions: HashMap<_, _> = sequence
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.digest(), i))
+        .collect();
+    match sequence.iter().enumerate().find(|(index, certificate)| {
+        certificate
+            .header
+            .parents
+            .iter()
+            .any(|x| positions.get(x).is_some_and(|i| i > index))
+    }) {
+        Some((index, _)) => Err(index),
+        None => Ok(()),
+    }
+}
+
+/// Checks if there is a path from a leader to the certificate of the specified digest and round.
+fn linked(
+    leader: &Certificate,
+    digest: &Digest,
+    round: Round,
+    certificates: &HashMap<&Digest, &Certificate>,
+) -> bool {
+    let mut parents: HashSet<_> = leader.header.parents.iter().collect();
+    for _ in (round + 1..leader.round()).rev() {
+        parents = parents
+            .iter()
+            .filter_map(|x| certificates.get(x))
+            .flat_map(|x| x.header.parents.iter())
+            .collect();
+    }
+    parents.contains(digest)
+}
@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::State;
+use crypto::{Digest, PublicKey};
+use primary::Round;
+use std::collections::BTreeMap;
+
+/// How the certificates of a round differ between two dags. Every list is sorted by authority.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoundDiff {
+    /// The certificates only the first dag holds.
+    pub only_left: Vec<(PublicKey, Digest)>,
+    /// The certificates only the second dag holds.
+    pub only_right: Vec<(PublicKey, Digest)>,
+    /// The authorities for which both dags hold a certificate, but a different one (which means
+    /// that the authority equivocated): the digest held by the first dag, then by the second.
+    pub conflicting: Vec<(PublicKey, Digest, Digest)>,
+}
+
+impl RoundDiff {
+    fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty() && self.conflicting.is_empty()
+    }
+}
+
+/// The differences between two dags, by increasing round. Rounds holding the same certificates in
+/// both dags are omitted, so identical dags have an empty diff.
+pub type DagDiff = BTreeMap<Round, RoundDiff>;
+
+impl State {
+    /// Compares our dag with the dag of another state, to find where two nodes diverge.
+    pub fn diff(&self, other: &State) -> DagDiff {
+        let left = self.sorted_dag();
+        let mut right = other.sorted_dag();
+
+        let mut diff = DagDiff::new();
+        for (round, certificates) in left {
+            let mut others = right.remove(&round).unwrap_or_default();
+            let mut round_diff = RoundDiff::default();
+            for (name, digest) in certificates {
+                match others.remove(&name) {
+                    Some(x) if x == digest => (),
+                    Some(x) => round_diff.conflicting.push((name, digest, x)),
+                    None => round_diff.only_left.push((name, digest)),
+                }
+            }
+            round_diff.only_right.extend(others);
+            if !round_diff.is_empty() {
+                diff.insert(round, round_diff);
+            }
+        }
+        for (round, certificates) in right {
+            let round_diff = RoundDiff {
+                only_right: certificates.into_iter().collect(),
+                ..RoundDiff::default()
+            };
+            diff.insert(round, round_diff);
+        }
+        diff
+    }
+}
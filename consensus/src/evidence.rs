@@ -0,0 +1,81 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::Equivocation;
+use primary::Round;
+use std::collections::BTreeMap;
+
+/// The default number of equivocations we retain once their round left the dag.
+pub const DEFAULT_EVIDENCE_CAPACITY: usize = 1_000;
+
+/// The default number of rounds (behind the last committed round) we retain evidence for.
+pub const DEFAULT_EVIDENCE_RETENTION: Round = 100_000;
+
+/// Retains the evidence of equivocations after their round is cleaned up from the dag: slashing
+/// may need it long after the round is committed. The pool is bounded both in size (the evidence
+/// of the oldest rounds goes first) and in age.
+pub struct EvidencePool {
+    /// The maximum number of equivocations we retain.
+    capacity: usize,
+    /// We forget the evidence of rounds this far behind the last committed round.
+    retention: Round,
+    /// The equivocations, by round.
+    evidence: BTreeMap<Round, Vec<Equivocation>>,
+    /// The number of equivocations in `evidence`.
+    size: usize,
+}
+
+impl EvidencePool {
+    pub fn new(capacity: usize, retention: Round) -> Self {
+        Self {
+            capacity,
+            retention,
+            evidence: BTreeMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Retains the evidence of an equivocation, evicting the oldest evidence if the pool is full.
+    pub fn insert(&mut self, round: Round, equivocation: Equivocation) {
+        self.evidence.entry(round).or_default().push(equivocation);
+        self.size += 1;
+        while self.size > self.capacity {
+            let mut oldest = match self.evidence.first_entry() {
+                Some(x) => x,
+                None => break,
+            };
+            oldest.get_mut().remove(0);
+            self.size -= 1;
+            if oldest.get().is_empty() {
+                oldest.remove();
+            }
+        }
+    }
+
+    /// Forgets the evidence that is too old.
+    pub fn prune(&mut self, last_committed_round: Round) {
+        let oldest = last_committed_round.saturating_sub(self.retention);
+        let retained = self.evidence.split_off(&oldest);
+        self.size -= self.evidence.values().map(|x| x.len()).sum::<usize>();
+        self.evidence = retained;
+    }
+
+    /// Returns the retained equivocations and their round, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (Round, &Equivocation)> {
+        self.evidence
+            .iter()
+            .flat_map(|(round, x)| x.iter().map(move |equivocation| (*round, equivocation)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl Default for EvidencePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVIDENCE_CAPACITY, DEFAULT_EVIDENCE_RETENTION)
+    }
+}
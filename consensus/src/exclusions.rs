@@ -0,0 +1,103 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, scheduled_leader, State};
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use primary::Round;
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ExclusionError {
+    #[error("Cannot exclude {0}: not a committee member")]
+    UnknownAuthority(PublicKey),
+
+    #[error("Cannot exclude {0} stake from leader election: at most {1} may be faulty")]
+    TooMuchStake(Stake, Stake),
+
+    #[error("The exclusions change the leader we committed at round {0}")]
+    ConflictsWithCommit(Round),
+
+    #[error("Cannot change the leaders of the rounds below {0}: we cleaned up their commits")]
+    BelowRetention(Round),
+}
+
+/// The authorities that leader election skips over, from given rounds on (e.g., authorities known
+/// to be down). Leader rounds whose scheduled leader is excluded fall through to the next authority
+/// of the round-robin that is not. Every node must apply the same exclusions: they must be derived
+/// from committed data, with an activation round that every node reaches after applying them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LeaderExclusions {
+    /// The excluded authorities from each round on (until the next entry).
+    schedule: BTreeMap<Round, BTreeSet<PublicKey>>,
+}
+
+impl LeaderExclusions {
+    /// Returns the authorities excluded at the specified round.
+    pub fn at(&self, round: Round) -> Option<&BTreeSet<PublicKey>> {
+        self.schedule.range(..=round).next_back().map(|(_, x)| x)
+    }
+
+    /// Returns the first authority of the round-robin from the scheduled leader of the seed that is
+    /// not excluded at the specified round.
+    pub(crate) fn substitute(&self, committee: &Committee, round: Round, seed: Round) -> PublicKey {
+        let scheduled = scheduled_leader(committee, seed);
+        let excluded = match self.at(round) {
+            Some(x) => x,
+            None => return scheduled,
+        };
+        (0..committee.size() as Round)
+            .map(|i| scheduled_leader(committee, seed + i))
+            .find(|x| !excluded.contains(x))
+            .unwrap_or(scheduled)
+    }
+}
+
+impl State {
+    /// Returns the authorities excluded from leader election.
+    pub fn leader_exclusions(&self) -> &LeaderExclusions {
+        &self.exclusions
+    }
+
+    /// Excludes authorities from leader election from the specified round on (an empty set lifts
+    /// the previous exclusions). The excluded authorities may hold at most f stake, so that leader
+    /// election always falls through to a live authority. The exclusions must not change the
+    /// leaders we already committed: nodes may apply again the exclusions they derive after a
+    /// restart, but never change the past.
+    pub fn exclude_leaders(
+        &mut self,
+        committee: &Committee,
+        from_round: Round,
+        authorities: BTreeSet<PublicKey>,
+    ) -> Result<(), ExclusionError> {
+        let mut stake: Stake = 0;
+        for name in &authorities {
+            match committee.stake(name) {
+                0 => return Err(ExclusionError::UnknownAuthority(*name)),
+                x => stake = stake.saturating_add(x),
+            }
+        }
+        let faults = committee.fault_tolerance();
+        if stake > faults {
+            return Err(ExclusionError::TooMuchStake(stake, faults));
+        }
+
+        if from_round < self.leaders_floor {
+            return Err(ExclusionError::BelowRetention(self.leaders_floor));
+        }
+
+        let mut exclusions = self.exclusions.clone();
+        exclusions.schedule.insert(from_round, authorities);
+        if let Some(round) = self
+            .committed_leaders
+            .range(from_round..)
+            .find(|(round, leader)| {
+                elect_leader(committee, **round, &exclusions) != leader.origin()
+            })
+            .map(|(round, _)| *round)
+        {
+            return Err(ExclusionError::ConflictsWithCommit(round));
+        }
+        self.exclusions = exclusions;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::scheduled_leader;
+use config::{Committee, Export, Import, Stake};
+use crypto::PublicKey;
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+#[cfg(test)]
+#[path = "tests/leader_vector_tests.rs"]
+pub mod leader_vector_tests;
+
+/// The leader elected for a round.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderElection {
+    pub round: Round,
+    pub leader: PublicKey,
+}
+
+/// A change of the next leader (see `State::next_leader`): workers may hand the transactions they
+/// hold for the previous leader over to the next one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaderHandoff {
+    /// The round of the next leader.
+    pub round: Round,
+    pub previous: PublicKey,
+    pub next: PublicKey,
+}
+
+/// The leaders production nodes elect over a range of rounds, for other implementations to check
+/// their leader election against. Tusk elects a single leader for every even round (from round 2).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LeaderVector {
+    /// The authorities of the committee, with their stake.
+    pub authorities: BTreeMap<PublicKey, Stake>,
+    /// The leader of each leader round of the range, by increasing round.
+    pub elections: Vec<LeaderElection>,
+}
+
+impl LeaderVector {
+    pub fn new(committee: &Committee, rounds: RangeInclusive<Round>) -> Self {
+        Self {
+            authorities: committee
+                .authorities
+                .iter()
+                .map(|(name, authority)| (*name, authority.stake))
+                .collect(),
+            elections: rounds
+                .filter(|round| round.is_multiple_of(2) && *round >= 2)
+                .map(|round| LeaderElection {
+                    round,
+                    leader: scheduled_leader(committee, round),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Import for LeaderVector {}
+impl Export for LeaderVector {}
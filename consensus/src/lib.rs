@@ -1,22 +1,142 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use config::{Committee, Stake};
+use config::{Committee, QuorumError, Stake};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use log::{debug, info, log_enabled, warn};
 use primary::{Certificate, Round};
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Borrow;
+use std::cmp::{max, Ordering};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use thiserror::Error;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::instrument;
 
 #[cfg(test)]
 #[path = "tests/consensus_tests.rs"]
 pub mod consensus_tests;
 
+#[cfg(test)]
+#[path = "tests/simulation_tests.rs"]
+pub mod simulation_tests;
+
+mod checkpoint;
+mod commit_proof;
+mod commit_sequence;
+mod diff;
+mod evidence;
+mod exclusions;
+mod leader_vector;
+mod memory;
+mod output;
+mod replay;
+mod snapshot;
+mod soft_commit;
+
+pub use crate::checkpoint::{Checkpoint, CheckpointError, Checkpointer, Manifest};
+pub use crate::commit_proof::{verify_commit_proof, CommitProof, CommitProofError};
+pub use crate::commit_sequence::{verify_causal_order, CommitViolation};
+pub use crate::diff::{DagDiff, RoundDiff};
+pub use crate::evidence::{EvidencePool, DEFAULT_EVIDENCE_CAPACITY, DEFAULT_EVIDENCE_RETENTION};
+pub use crate::exclusions::{ExclusionError, LeaderExclusions};
+pub use crate::leader_vector::{LeaderElection, LeaderHandoff, LeaderVector};
+pub use crate::memory::{MemoryReport, RoundMemory};
+pub use crate::output::{
+    Delivery, RetryingSender, DEFAULT_OUTPUT_RETRIES, DEFAULT_OUTPUT_RETRY_DELAY,
+};
+pub use crate::replay::ReplayError;
+pub use crate::snapshot::{verify_snapshot, SnapshotError, StateSnapshot};
+pub use crate::soft_commit::SoftCommitStatus;
+
 /// The representation of the DAG in memory.
 type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
 
+/// Evidence that an authority produced two different certificates for the same round.
+#[derive(Clone, Debug)]
+pub struct Equivocation {
+    /// The certificate we received first (and kept in the dag).
+    pub existing: Certificate,
+    /// The conflicting certificate.
+    pub incoming: Certificate,
+}
+
+/// The certificates committed along with a leader, in commit order (the leader is last).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubDag {
+    /// The committed leader.
+    pub leader: Certificate,
+    /// The certificates committed with the leader (including it).
+    pub certificates: Vec<Certificate>,
+}
+
+/// The outcome of looking up the certificate of the leader of a round.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeaderLookup<'a> {
+    /// We hold no certificate of the round yet: its leader may still show up.
+    RoundAbsent,
+    /// We hold certificates of the round, but not from its leader (who may be late or crashed).
+    LeaderMissing,
+    /// The certificate of the leader, and its digest.
+    Leader(&'a (Digest, Certificate)),
+}
+
+impl<'a> LeaderLookup<'a> {
+    /// Returns the certificate of the leader (and its digest), if we hold it.
+    pub fn certificate(self) -> Option<&'a (Digest, Certificate)> {
+        match self {
+            Self::Leader(leader) => Some(leader),
+            _ => None,
+        }
+    }
+}
+
+/// What `State::try_add` did with a certificate it accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// The certificate is new: we added it to the dag.
+    Added,
+    /// We already hold the certificate: nothing changed.
+    AlreadyPresent,
+}
+
+/// Why `State::try_add` rejected a certificate.
+#[derive(Debug, Error)]
+pub enum AdmissionError {
+    #[error("Authority {} equivocated at round {}", .0.existing.origin(), .0.existing.round())]
+    Equivocation(Box<Equivocation>),
+
+    #[error("Certificate of {0} for round {1} is at or below the cleanup frontier (round {2})")]
+    TooOld(PublicKey, Round, Round),
+
+    #[error("Authority {0} exceeded its budget of {2} certificates for round {1}")]
+    RateLimited(PublicKey, Round, u64),
+
+    #[error("Dag holds {0} certificates, its capacity")]
+    AtCapacity(usize),
+
+    #[error("Certificate of {0} for round {1} is invalid: {2}")]
+    InvalidCertificate(PublicKey, Round, String),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GenesisError {
+    #[error("Genesis certificate of {0} is at round {1}")]
+    NotGenesisRound(PublicKey, Round),
+
+    #[error("Genesis certificate of {0} is not from a committee member")]
+    UnknownAuthority(PublicKey),
+
+    #[error("Genesis holds several certificates of {0}")]
+    DuplicateOrigin(PublicKey),
+
+    #[error("Genesis misses the certificate of {0}")]
+    MissingAuthority(PublicKey),
+
+    #[error("Invalid committee: {0}")]
+    InvalidCommittee(#[from] QuorumError),
+}
+
 /// The state that needs to be persisted for crash-recovery.
-struct State {
+pub struct State {
     /// The last committed round.
     last_committed_round: Round,
     // Keeps the last committed round for each authority. This map is used to clean up the dag and
@@ -25,43 +145,441 @@ struct State {
     /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
     /// must be regularly cleaned up through the function `update`.
     dag: Dag,
+    /// The leaders we committed, by round, from `leaders_floor` on (there is at most one every two
+    /// rounds). We keep them beyond the dag so that we can serve snapshots to light clients.
+    committed_leaders: BTreeMap<Round, Certificate>,
+    /// How many rounds below the garbage collection round we keep the committed leaders for (all
+    /// of them if `None`).
+    leader_retention: Option<Round>,
+    /// The leaders committed below this round were cleaned up.
+    leaders_floor: Round,
+    /// The digests of the certificates `update` removed from the dag, by round. We need them to
+    /// tell certificates we cleaned up from certificates we never received.
+    pruned: HashMap<Round, HashSet<Digest>>,
+    /// All rounds below this one were garbage collected.
+    gc_round: Round,
+    /// The number of leaders we committed.
+    index: u64,
+    /// The equivocations we detected, by round, while their round is in the dag.
+    equivocations: BTreeMap<Round, Vec<Equivocation>>,
+    /// The equivocations whose round was cleaned up from the dag.
+    evidence: EvidencePool,
+    /// The authorities leader election skips over.
+    exclusions: LeaderExclusions,
+    /// How many certificates `try_add` considers from each authority and round (all if `None`).
+    certificate_budget: Option<u64>,
+    /// How many certificates `try_add` considered, by round and authority (while we have a budget).
+    admissions: HashMap<Round, HashMap<PublicKey, u64>>,
+    /// How many certificates the dag may hold (any number if `None`).
+    capacity: Option<usize>,
+    /// Where to announce the changes of the next leader (if anywhere).
+    tx_handoffs: Option<Sender<LeaderHandoff>>,
+    /// The next leader we observed last.
+    observed_leader: Option<LeaderElection>,
+    /// The committee against which `try_add` verifies certificates (if it verifies them).
+    verifier: Option<Committee>,
 }
 
 impl State {
-    fn new(genesis: Vec<Certificate>) -> Self {
-        let genesis = genesis
-            .into_iter()
-            .map(|x| (x.origin(), (x.digest(), x)))
-            .collect::<HashMap<_, _>>();
+    /// Makes the state from the genesis certificates, which must hold exactly one certificate of
+    /// round 0 from every authority of the committee. The stake distribution of the committee must
+    /// admit BFT quorums: consensus would otherwise silently never commit.
+    pub fn new(committee: &Committee, genesis: Vec<Certificate>) -> Result<Self, GenesisError> {
+        committee.check_quorums()?;
+        let mut certificates = HashMap::new();
+        for certificate in genesis {
+            let origin = certificate.origin();
+            if certificate.round() != 0 {
+                return Err(GenesisError::NotGenesisRound(origin, certificate.round()));
+            }
+            if !committee.authorities.contains_key(&origin) {
+                return Err(GenesisError::UnknownAuthority(origin));
+            }
+            let digest = certificate.digest();
+            if certificates.insert(origin, (digest, certificate)).is_some() {
+                return Err(GenesisError::DuplicateOrigin(origin));
+            }
+        }
+        if let Some(name) = committee
+            .authorities
+            .keys()
+            .find(|name| !certificates.contains_key(*name))
+        {
+            return Err(GenesisError::MissingAuthority(*name));
+        }
 
-        Self {
+        Ok(Self {
             last_committed_round: 0,
-            last_committed: genesis.iter().map(|(x, (_, y))| (*x, y.round())).collect(),
-            dag: [(0, genesis)].iter().cloned().collect(),
+            last_committed: certificates.keys().map(|x| (*x, 0)).collect(),
+            dag: [(0, certificates)].iter().cloned().collect(),
+            committed_leaders: BTreeMap::new(),
+            leader_retention: None,
+            leaders_floor: 0,
+            pruned: HashMap::new(),
+            gc_round: 0,
+            index: 0,
+            equivocations: BTreeMap::new(),
+            evidence: EvidencePool::default(),
+            exclusions: LeaderExclusions::default(),
+            certificate_budget: None,
+            admissions: HashMap::new(),
+            capacity: None,
+            tx_handoffs: None,
+            observed_leader: None,
+            verifier: None,
+        })
+    }
+
+    /// Bounds how many certificates `try_add` considers from each authority and round: beyond the
+    /// budget, it rejects them before even hashing them. Honest authorities make one certificate
+    /// per round (re-deliveries of the certificates we hold are not counted). `None` removes the
+    /// bound.
+    pub fn set_certificate_budget(&mut self, budget: Option<u64>) {
+        self.certificate_budget = budget;
+        self.admissions.clear();
+    }
+
+    /// Bounds how many certificates the dag holds: once full, `try_add` rejects new certificates
+    /// until commits clean it up. This is a safety valve for long stalls (e.g., of synchronization)
+    /// rather than a flow control: we never evict the certificates we may still have to commit, so
+    /// consensus stops making progress if it misses the certificates it rejected. `None` removes
+    /// the bound.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// Bounds how long we keep the committed leaders: `update` cleans up those more than
+    /// `retention` rounds below the garbage collection round, so that the state does not grow with
+    /// the length of the chain. Snapshots, replays (see `committed_order`), and the checks of
+    /// leader exclusions and soft commits only cover the leaders we keep. `None` keeps them all.
+    pub fn set_leader_retention(&mut self, retention: Option<Round>) {
+        self.leader_retention = retention;
+    }
+
+    /// Makes `try_add` verify the certificates it has not seen yet against the committee (the
+    /// signatures of their header and votes, and that the votes form a quorum) before looking at
+    /// them further. The primary verifies the certificates it hands to consensus, so consensus
+    /// does not need this: it is meant for states fed from untrusted sources (e.g., standalone
+    /// verifiers replaying a dag). `None` disables verification.
+    pub fn set_verification(&mut self, committee: Option<Committee>) {
+        self.verifier = committee;
+    }
+
+    /// Announces the changes of the next leader on the specified channel (see `observe_leader`).
+    /// Announcements are dropped while the channel is full.
+    pub fn set_handoff_channel(&mut self, tx_handoffs: Option<Sender<LeaderHandoff>>) {
+        self.tx_handoffs = tx_handoffs;
+    }
+
+    /// Returns the change of the next leader since the last call (if any), and announces it on
+    /// the handoff channel. Consensus calls it whenever it adds a certificate to the dag.
+    pub fn observe_leader(&mut self, committee: &Committee) -> Option<LeaderHandoff> {
+        let next = self.next_leader(committee);
+        let previous = self.observed_leader.replace(next.clone())?;
+        if previous.leader == next.leader {
+            return None;
         }
+        let handoff = LeaderHandoff {
+            round: next.round,
+            previous: previous.leader,
+            next: next.leader,
+        };
+        if let Some(tx_handoffs) = &self.tx_handoffs {
+            if let Err(e) = tx_handoffs.try_send(handoff.clone()) {
+                warn!("Failed to announce leader handoff: {}", e);
+            }
+        }
+        Some(handoff)
+    }
+
+    /// Add a certificate to the dag. Re-deliveries of a certificate we hold are cheap no-ops. If we
+    /// already hold a different certificate from the same origin and round, the first one is kept
+    /// and the equivocation is retained (as evidence) and returned. Certificates at or below the
+    /// cleanup frontier (the last committed round of their origin, or below the garbage collection
+    /// round) are rejected: they could never be committed, and adding them would resurrect state
+    /// that `update` already cleaned up. So are those beyond the budget of their origin and round
+    /// (see `set_certificate_budget`), new certificates while the dag is full (see `set_capacity`),
+    /// and invalid certificates if we verify them (see `set_verification`).
+    pub fn try_add(&mut self, certificate: Certificate) -> Result<Admission, AdmissionError> {
+        let round = certificate.round();
+        let origin = certificate.origin();
+
+        // Certificates are often delivered several times (e.g., during synchronization): we tell
+        // the ones we hold without even hashing them.
+        let existing = self.dag.get(&round).and_then(|x| x.get(&origin));
+        if existing.is_some_and(|(_, x)| x == &certificate) {
+            return Ok(Admission::AlreadyPresent);
+        }
+
+        // Forged certificates must neither consume budget nor pass for evidence of equivocation.
+        if let Some(committee) = &self.verifier {
+            certificate
+                .verify(committee)
+                .map_err(|e| AdmissionError::InvalidCertificate(origin, round, e.to_string()))?;
+        }
+
+        if let Some(budget) = self.certificate_budget {
+            let admitted = self
+                .admissions
+                .entry(round)
+                .or_default()
+                .entry(origin)
+                .or_default();
+            if *admitted >= budget {
+                return Err(AdmissionError::RateLimited(origin, round, budget));
+            }
+            *admitted += 1;
+        }
+
+        let digest = certificate.digest();
+        if let Some((existing_digest, existing)) = self.dag.get(&round).and_then(|x| x.get(&origin))
+        {
+            if existing_digest == &digest {
+                return Ok(Admission::AlreadyPresent);
+            }
+            let equivocation = Equivocation {
+                existing: existing.clone(),
+                incoming: certificate,
+            };
+            let evidence = self.equivocations.entry(round).or_default();
+            if !evidence.iter().any(|x| x.incoming == equivocation.incoming) {
+                evidence.push(equivocation.clone());
+            }
+            return Err(AdmissionError::Equivocation(Box::new(equivocation)));
+        }
+
+        let frontier = self
+            .last_committed
+            .get(&origin)
+            .cloned()
+            .unwrap_or_default();
+        if round <= frontier || round < self.gc_round {
+            return Err(AdmissionError::TooOld(
+                origin,
+                round,
+                frontier.max(self.gc_round),
+            ));
+        }
+        if let Some(capacity) = self.capacity {
+            let size: usize = self.dag.values().map(|x| x.len()).sum();
+            if size >= capacity {
+                return Err(AdmissionError::AtCapacity(size));
+            }
+        }
+        self.dag
+            .entry(round)
+            .or_default()
+            .insert(origin, (digest, certificate));
+        Ok(Admission::Added)
     }
 
     /// Update and clean up internal state base on committed certificates.
     fn update(&mut self, certificate: &Certificate, gc_depth: Round) {
-        self.last_committed
-            .entry(certificate.origin())
-            .and_modify(|r| *r = max(*r, certificate.round()))
-            .or_insert_with(|| certificate.round());
-
-        let last_committed_round = *self.last_committed.values().max().unwrap();
+        let last_committed_round = record_commit(&mut self.last_committed, certificate);
         self.last_committed_round = last_committed_round;
 
         // TODO: This cleanup is dangerous: we need to ensure consensus can receive idempotent replies
         // from the primary. Here we risk cleaning up a certificate and receiving it again later.
+        let pruned = &mut self.pruned;
         for (name, round) in &self.last_committed {
             self.dag.retain(|r, authorities| {
-                authorities.retain(|n, _| n != name || r >= round);
+                authorities.retain(|n, (digest, _)| {
+                    let keep = n != name || r >= round;
+                    if !keep {
+                        pruned.entry(*r).or_default().insert(digest.clone());
+                    }
+                    keep
+                });
                 !authorities.is_empty() && r + gc_depth >= last_committed_round
             });
         }
+        self.gc_round = last_committed_round.saturating_sub(gc_depth);
+        let gc_round = self.gc_round;
+        self.pruned.retain(|r, _| r >= &gc_round);
+        self.admissions.retain(|r, _| r >= &gc_round);
+        if let Some(retention) = self.leader_retention {
+            let floor = gc_round.saturating_sub(retention);
+            if floor > self.leaders_floor {
+                self.committed_leaders = self.committed_leaders.split_off(&floor);
+                self.leaders_floor = floor;
+            }
+        }
+
+        // Unlike the certificates, the evidence of equivocations outlives its round.
+        let dag = &self.dag;
+        let (retired, live) = std::mem::take(&mut self.equivocations)
+            .into_iter()
+            .partition(|(r, _)| r < &gc_round || !dag.contains_key(r));
+        self.equivocations = live;
+        for (round, equivocations) in retired {
+            for equivocation in equivocations {
+                self.evidence.insert(round, equivocation);
+            }
+        }
+        self.evidence.prune(last_committed_round);
+    }
+
+    /// Returns the evidence of the equivocations we detected (and did not forget yet) along with
+    /// their round: the evidence retained after cleanup first, then that of the rounds of the dag.
+    pub fn pending_evidence(&self) -> impl Iterator<Item = (Round, &Equivocation)> {
+        self.evidence.iter().chain(
+            self.equivocations
+                .iter()
+                .flat_map(|(round, x)| x.iter().map(move |equivocation| (*round, equivocation))),
+        )
+    }
+
+    /// Checks that we hold the full causal history of the certificates of the specified round:
+    /// every certificate they (transitively) reference is either in the dag or was cleaned up
+    /// after being committed or garbage collected. Committing past a gap would produce an
+    /// incomplete commit sequence.
+    pub fn is_complete_to(&self, round: Round) -> bool {
+        let mut digests: HashSet<_> = match self.dag.get(&round) {
+            Some(x) => x.values().map(|(digest, _)| digest.clone()).collect(),
+            None => return round < self.gc_round || self.pruned.contains_key(&round),
+        };
+        for r in (self.gc_round..=round).rev() {
+            let mut parents = HashSet::new();
+            for digest in &digests {
+                let certificate = self
+                    .dag
+                    .get(&r)
+                    .and_then(|x| x.values().find(|(x, _)| x == digest));
+                match certificate {
+                    Some((_, certificate)) => {
+                        parents.extend(certificate.header.parents.iter().cloned())
+                    }
+                    None if self.pruned.get(&r).is_some_and(|x| x.contains(digest)) => (),
+                    None => return false,
+                }
+            }
+            if r == 0 {
+                break;
+            }
+            digests = parents;
+        }
+        true
+    }
+
+    /// Returns the certificates of our dag referencing the specified certificate from the next
+    /// round.
+    fn supporters<'a>(
+        &'a self,
+        digest: &'a Digest,
+        round: Round,
+    ) -> impl Iterator<Item = &'a Certificate> {
+        self.dag
+            .get(&(round + 1))
+            .into_iter()
+            .flat_map(|children| children.values())
+            .filter(move |(_, x)| x.header.parents.contains(digest))
+            .map(|(_, x)| x)
+    }
+
+    /// Returns a deterministic view of the dag: rounds are sorted in increasing order and the
+    /// certificates' digests of each round are sorted by author. Two nodes holding the same dag
+    /// produce identical views regardless of the order in which they received the certificates.
+    pub fn sorted_dag(&self) -> BTreeMap<Round, BTreeMap<PublicKey, Digest>> {
+        self.dag
+            .iter()
+            .map(|(round, certificates)| {
+                let certificates = certificates
+                    .iter()
+                    .map(|(name, (digest, _))| (*name, digest.clone()))
+                    .collect();
+                (*round, certificates)
+            })
+            .collect()
+    }
+
+    /// Returns the last committed round of each authority, sorted by authority.
+    pub fn sorted_last_committed(&self) -> BTreeMap<PublicKey, Round> {
+        self.last_committed
+            .iter()
+            .map(|(name, round)| (*name, *round))
+            .collect()
+    }
+
+    /// Returns the highest round of the dag at which each authority has a certificate (0 if it has
+    /// none above the cleanup frontier), e.g., to spot the authorities falling behind.
+    pub fn frontier(&self) -> HashMap<PublicKey, Round> {
+        let mut frontier: HashMap<_, _> = self.last_committed.keys().map(|x| (*x, 0)).collect();
+        for (round, certificates) in &self.dag {
+            for name in certificates.keys() {
+                let highest = frontier.entry(*name).or_default();
+                *highest = max(*highest, *round);
+            }
+        }
+        frontier
+    }
+
+    /// Exports the leaders committed up to (and including) the specified round that we still keep
+    /// (see `set_leader_retention`), along with the authorities (and their stake) in charge at
+    /// that round.
+    pub fn snapshot(&self, at_round: Round, committee: &Committee) -> StateSnapshot {
+        StateSnapshot {
+            round: at_round,
+            leaders: self
+                .committed_leaders
+                .range(..=at_round)
+                .map(|(_, x)| x.clone())
+                .collect(),
+            authorities: committee
+                .authorities
+                .iter()
+                .map(|(name, authority)| (*name, authority.stake))
+                .collect(),
+        }
+    }
+
+    /// Returns the leader of the first leader round above the highest round of the dag: the
+    /// authority whose next header consensus will try to commit, and thus where clients should
+    /// route their transactions. It is the leader that consensus elects for that round (unless
+    /// the exclusions change in the meantime).
+    pub fn next_leader(&self, committee: &Committee) -> LeaderElection {
+        let highest = self
+            .dag
+            .keys()
+            .copied()
+            .max()
+            .unwrap_or(self.last_committed_round);
+        next_leader(committee, highest, &self.exclusions)
     }
 }
 
+/// Returns the leader of the first leader round above the specified round (Tusk elects a leader
+/// for every even round, from round 2).
+pub fn next_leader(
+    committee: &Committee,
+    round: Round,
+    exclusions: &LeaderExclusions,
+) -> LeaderElection {
+    let round = max(2, round + 1 + (round + 1) % 2);
+    LeaderElection {
+        round,
+        leader: elect_leader(committee, round, exclusions),
+    }
+}
+
+/// Returns the name of the leader of the specified round, skipping over the excluded authorities.
+/// Unit tests always schedule the same leader.
+fn elect_leader(committee: &Committee, round: Round, exclusions: &LeaderExclusions) -> PublicKey {
+    #[cfg(not(test))]
+    let seed = round;
+    #[cfg(test)]
+    let seed = 0;
+
+    exclusions.substitute(committee, round, seed)
+}
+
+/// Returns the name of the leader that production nodes elect for the specified round.
+fn scheduled_leader(committee: &Committee, round: Round) -> PublicKey {
+    // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
+    // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
+    // compute the coin). We currently just use round-robin.
+    committee.leader(round as usize)
+}
+
 pub struct Consensus {
     /// The committee information.
     committee: Committee,
@@ -74,20 +592,41 @@ pub struct Consensus {
     /// Outputs the sequence of ordered certificates to the primary (for cleanup and feedback).
     tx_primary: Sender<Certificate>,
     /// Outputs the sequence of ordered certificates to the application layer.
-    tx_output: Sender<Certificate>,
+    tx_output: RetryingSender<Certificate>,
 
     /// The genesis certificates.
     genesis: Vec<Certificate>,
 }
 
+/// The optional parts of consensus. The default starts from genesis, takes no checkpoints, and
+/// only outputs the committed sequence certificate by certificate.
+#[derive(Default)]
+pub struct ConsensusOptions {
+    /// The state to start from (e.g., restored from a checkpoint) rather than genesis (if set).
+    pub state: Option<State>,
+    /// Takes checkpoints of the state as consensus commits (if set).
+    pub checkpointer: Option<Checkpointer>,
+    /// Also outputs the committed sequence by sub-dag (if set), e.g., to stream it to execution
+    /// engines.
+    pub tx_sub_dags: Option<RetryingSender<SubDag>>,
+}
+
 impl Consensus {
+    /// Spawns consensus. Its outputs retry while their channel is closed, and dead-letter what they
+    /// fail to send (see `RetryingSender`).
     pub fn spawn(
         committee: Committee,
         gc_depth: Round,
         rx_primary: Receiver<Certificate>,
         tx_primary: Sender<Certificate>,
-        tx_output: Sender<Certificate>,
+        tx_output: RetryingSender<Certificate>,
+        options: ConsensusOptions,
     ) {
+        let ConsensusOptions {
+            state,
+            checkpointer,
+            tx_sub_dags,
+        } = options;
         tokio::spawn(async move {
             Self {
                 committee: committee.clone(),
@@ -97,85 +636,36 @@ impl Consensus {
                 tx_output,
                 genesis: Certificate::genesis(&committee),
             }
-            .run()
+            .run(state, checkpointer, tx_sub_dags)
             .await;
         });
     }
 
-    async fn run(&mut self) {
+    async fn run(
+        &mut self,
+        state: Option<State>,
+        mut checkpointer: Option<Checkpointer>,
+        tx_sub_dags: Option<RetryingSender<SubDag>>,
+    ) {
         // The consensus state (everything else is immutable).
-        let mut state = State::new(self.genesis.clone());
+        let mut state = state.unwrap_or_else(|| {
+            State::new(&self.committee, self.genesis.clone())
+                .unwrap_or_else(|e| panic!("Failed to start consensus: {}", e))
+        });
+        state.set_leader_retention(Some(self.gc_depth));
+        if let Some(checkpointer) = &mut checkpointer {
+            checkpointer.start_from(&state);
+        }
 
         // Listen to incoming certificates.
         while let Some(certificate) = self.rx_primary.recv().await {
-            debug!("Processing {:?}", certificate);
-            let round = certificate.round();
-
-            // Add the new certificate to the local storage.
-            state
-                .dag
-                .entry(round)
-                .or_insert_with(HashMap::new)
-                .insert(certificate.origin(), (certificate.digest(), certificate));
-
-            // Try to order the dag to commit. Start from the previous round and check if it is a leader round.
-            let r = round - 1;
-
-            // We only elect leaders for even round numbers.
-            if r % 2 != 0 || r < 2 {
-                continue;
-            }
-
-            // Get the certificate's digest of the leader. If we already ordered this leader, there is nothing to do.
-            let leader_round = r;
-            if leader_round <= state.last_committed_round {
-                continue;
-            }
-            let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
-                Some(x) => x,
-                None => continue,
-            };
-
-            // Check if the leader has f+1 support from its children (ie. round r-1).
-            let stake: Stake = state
-                .dag
-                .get(&round)
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(_, x)| x.header.parents.contains(leader_digest))
-                .map(|(_, x)| self.committee.stake(&x.origin()))
-                .sum();
-
-            // If it is the case, we can commit the leader. But first, we need to recursively go back to
-            // the last committed leader, and commit all preceding leaders in the right order. Committing
-            // a leader block means committing all its dependencies.
-            if stake < self.committee.validity_threshold() {
-                debug!("Leader {:?} does not have enough support", leader);
-                continue;
-            }
-
-            // Get an ordered list of past leaders that are linked to the current leader.
-            debug!("Leader {:?} has enough support", leader);
-            let mut sequence = Vec::new();
-            for leader in self.order_leaders(leader, &state).iter().rev() {
-                // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
-                for x in self.order_dag(leader, &state) {
-                    // Update and clean up internal state.
-                    state.update(&x, self.gc_depth);
-
-                    // Add the certificate to the sequence.
-                    sequence.push(x);
-                }
-            }
-
-            // Log the latest committed round of every authority (for debug).
-            if log_enabled!(log::Level::Debug) {
-                for (name, round) in &state.last_committed {
-                    debug!("Latest commit of {}: Round {}", name, round);
-                }
+            let sequence = self.process_certificate(&mut state, certificate);
+            if let Some(checkpointer) = &mut checkpointer {
+                checkpointer.observe(&state, &self.committee);
             }
 
             // Output the sequence in the right order.
+            let mut sub_dag = Vec::new();
             for certificate in sequence {
                 #[cfg(not(feature = "benchmark"))]
                 info!("Committed {}", certificate.header);
@@ -191,29 +681,177 @@ impl Consensus {
                     .await
                     .expect("Failed to send certificate to primary");
 
-                if let Err(e) = self.tx_output.send(certificate).await {
-                    warn!("Failed to output certificate: {}", e);
+                if let Some(tx_sub_dags) = &tx_sub_dags {
+                    // The sequence of each committed leader ends with the leader.
+                    let round = certificate.round();
+                    sub_dag.push(certificate.clone());
+                    if state.committed_leaders.get(&round) == Some(&certificate) {
+                        let sub_dag = SubDag {
+                            leader: certificate.clone(),
+                            certificates: std::mem::take(&mut sub_dag),
+                        };
+                        tx_sub_dags.send_or_warn(sub_dag, "sub-dag").await;
+                    }
                 }
+
+                self.tx_output
+                    .send_or_warn(certificate, "certificate")
+                    .await;
             }
         }
     }
 
+    /// Adds a certificate to the dag and returns the (possibly empty) sequence of certificates it
+    /// allows us to commit, in commit order.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(digest = ?certificate.digest(), header = ?certificate.header.id, round = certificate.round())
+    )]
+    fn process_certificate(&self, state: &mut State, certificate: Certificate) -> Vec<Certificate> {
+        debug!("Processing {:?}", certificate);
+        let round = certificate.round();
+
+        // Add the new certificate to the local storage. We keep the first certificate we saw from
+        // each authority and round; conflicting ones are evidence of equivocation. Certificates
+        // arriving after we cleaned up their round are useless.
+        match state.try_add(certificate) {
+            Ok(Admission::Added) => {
+                state.observe_leader(&self.committee);
+            }
+            Ok(Admission::AlreadyPresent) => return Vec::new(),
+            Err(AdmissionError::Equivocation(equivocation)) => {
+                warn!(
+                    "Authority {} equivocated at round {}: kept {:?}, rejected {:?}",
+                    equivocation.existing.origin(),
+                    round,
+                    equivocation.existing,
+                    equivocation.incoming
+                );
+                return Vec::new();
+            }
+            Err(e @ (AdmissionError::AtCapacity(_) | AdmissionError::InvalidCertificate(..))) => {
+                warn!("Rejected certificate of round {}: {}", round, e);
+                return Vec::new();
+            }
+            Err(e) => {
+                debug!("{}", e);
+                return Vec::new();
+            }
+        }
+
+        // Commit every leader this certificate made committable.
+        let sequence = self.commit_all_ready(state);
+
+        // Log the latest committed round of every authority (for debug).
+        if !sequence.is_empty() && log_enabled!(log::Level::Debug) {
+            for (name, round) in state.sorted_last_committed() {
+                debug!("Latest commit of {}: Round {}", name, round);
+            }
+        }
+        sequence
+    }
+
+    /// Commits every leader that is ready to be committed and returns the resulting sequence of
+    /// certificates, in commit order. Leaders are committed oldest first, so a backlog of several
+    /// committable rounds (for instance after receiving a missing ancestor) drains in a single call.
+    fn commit_all_ready(&self, state: &mut State) -> Vec<Certificate> {
+        let mut sequence = Vec::new();
+        while let Some(leader) = self.next_committable_leader(state) {
+            sequence.extend(self.commit_leader(&leader, state));
+        }
+        debug_assert_eq!(
+            verify_causal_order(&sequence),
+            Ok(()),
+            "Committed a certificate before its parent"
+        );
+        sequence
+    }
+
+    /// Returns the oldest uncommitted leader that has f+1 support from its children and whose
+    /// causal history we fully hold (if any).
+    fn next_committable_leader(&self, state: &State) -> Option<Certificate> {
+        // We need the children of a leader to commit it, so the highest round cannot be committed.
+        let highest_round = *state.dag.keys().max()?;
+
+        // We only elect leaders for even round numbers.
+        (state.last_committed_round + 1..highest_round)
+            .filter(|r| r.is_multiple_of(2) && *r >= 2)
+            .find_map(|leader_round| {
+                let (leader_digest, leader) = match self.lookup_leader(leader_round, state) {
+                    LeaderLookup::Leader(x) => x,
+                    LeaderLookup::LeaderMissing => {
+                        debug!("No certificate from the leader of round {}", leader_round);
+                        return None;
+                    }
+                    LeaderLookup::RoundAbsent => return None,
+                };
+
+                // Check if the leader has f+1 support from its children (ie. round r+1).
+                let stake: Stake = state
+                    .supporters(leader_digest, leader_round)
+                    .map(|x| self.committee.stake(&x.origin()))
+                    .sum();
+                if stake < self.committee.validity_threshold() {
+                    debug!("Leader {:?} does not have enough support", leader);
+                    return None;
+                }
+
+                // We cannot commit the leader if we miss some of its ancestors (the primary should
+                // never let this happen).
+                if !state.is_complete_to(leader_round) {
+                    warn!("Cannot commit {:?}: missing ancestors", leader);
+                    return None;
+                }
+
+                debug!("Leader {:?} has enough support", leader);
+                Some(leader.clone())
+            })
+    }
+
+    /// Commits a leader. We first need to recursively go back to the last committed leader, and
+    /// commit all preceding leaders in the right order. Committing a leader block means committing
+    /// all its dependencies.
+    #[instrument(level = "debug", skip_all, fields(leader = ?leader.digest(), round = leader.round()))]
+    fn commit_leader(&self, leader: &Certificate, state: &mut State) -> Vec<Certificate> {
+        // Get an ordered list of past leaders that are linked to the current leader.
+        let mut sequence = Vec::new();
+        for leader in self.order_leaders(leader, state).iter().rev() {
+            // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
+            for x in self.order_dag(leader, state) {
+                // Update and clean up internal state.
+                state.update(&x, self.gc_depth);
+
+                // Add the certificate to the sequence.
+                sequence.push(x);
+            }
+            state
+                .committed_leaders
+                .insert(leader.round(), leader.clone());
+            state.index += 1;
+        }
+        sequence
+    }
+
     /// Returns the certificate (and the certificate's digest) originated by the leader of the
     /// specified round (if any).
-    fn leader<'a>(&self, round: Round, dag: &'a Dag) -> Option<&'a (Digest, Certificate)> {
-        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
-        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
-        // compute the coin). We currently just use round-robin.
-        #[cfg(test)]
-        let seed = 0;
-        #[cfg(not(test))]
-        let seed = round;
+    fn leader<'a>(&self, round: Round, state: &'a State) -> Option<&'a (Digest, Certificate)> {
+        self.lookup_leader(round, state).certificate()
+    }
 
+    /// Looks up the certificate originated by the leader of the specified round, telling apart
+    /// rounds we hold nothing of from rounds whose leader produced no certificate (so far).
+    fn lookup_leader<'a>(&self, round: Round, state: &'a State) -> LeaderLookup<'a> {
         // Elect the leader.
-        let leader = self.committee.leader(seed as usize);
+        let leader = elect_leader(&self.committee, round, &state.exclusions);
 
         // Return its certificate and the certificate's digest.
-        dag.get(&round).map(|x| x.get(&leader)).flatten()
+        match state.dag.get(&round).filter(|x| !x.is_empty()) {
+            None => LeaderLookup::RoundAbsent,
+            Some(x) => x
+                .get(&leader)
+                .map_or(LeaderLookup::LeaderMissing, LeaderLookup::Leader),
+        }
     }
 
     /// Order the past leaders that we didn't already commit.
@@ -225,7 +863,7 @@ impl Consensus {
             .step_by(2)
         {
             // Get the certificate proposed by the previous leader.
-            let (_, prev_leader) = match self.leader(r, &state.dag) {
+            let (_, prev_leader) = match self.leader(r, state) {
                 Some(x) => x,
                 None => continue,
             };
@@ -254,47 +892,96 @@ impl Consensus {
         parents.contains(&prev_leader)
     }
 
-    /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
-    /// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
+    /// Flatten the dag referenced by the input certificate (see `flatten_sub_dag`).
     fn order_dag(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
         debug!("Processing sub-dag of {:?}", leader);
-        let mut ordered = Vec::new();
-        let mut already_ordered = HashSet::new();
-
-        let mut buffer = vec![leader];
-        while let Some(x) = buffer.pop() {
-            debug!("Sequencing {:?}", x);
-            ordered.push(x.clone());
-            for parent in &x.header.parents {
-                let (digest, certificate) = match state
+        flatten_sub_dag(
+            leader,
+            &state.last_committed,
+            state.last_committed_round,
+            self.gc_depth,
+            |round, digest| {
+                state
                     .dag
-                    .get(&(x.round() - 1))
-                    .map(|x| x.values().find(|(x, _)| x == parent))
-                    .flatten()
-                {
-                    Some(x) => x,
-                    None => continue, // We already ordered or GC up to here.
-                };
+                    .get(&round)
+                    .and_then(|x| x.values().find(|(x, _)| x == digest))
+                    .map(|(_, certificate)| certificate)
+            },
+        )
+    }
+}
 
-                // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
-                // committed for this authority.
-                let mut skip = already_ordered.contains(&digest);
-                skip |= state
-                    .last_committed
-                    .get(&certificate.origin())
-                    .map_or_else(|| false, |r| r == &certificate.round());
-                if !skip {
-                    buffer.push(certificate);
-                    already_ordered.insert(digest);
-                }
+/// Records that a certificate was committed, and returns the last committed round (of all
+/// authorities).
+fn record_commit(
+    last_committed: &mut HashMap<PublicKey, Round>,
+    certificate: &Certificate,
+) -> Round {
+    last_committed
+        .entry(certificate.origin())
+        .and_modify(|r| *r = max(*r, certificate.round()))
+        .or_insert_with(|| certificate.round());
+    *last_committed.values().max().unwrap()
+}
+
+/// The canonical order of certificates: by round, then by author, then by digest (which only
+/// breaks ties between the certificates of an equivocating author). Every ordered output of the
+/// consensus sorts certificates this way, so that all nodes (and all outputs) agree on the order
+/// of the certificates they hold.
+pub fn certificate_cmp(a: &Certificate, b: &Certificate) -> Ordering {
+    a.round()
+        .cmp(&b.round())
+        .then_with(|| a.origin().cmp(&b.origin()))
+        .then_with(|| a.digest().cmp(&b.digest()))
+}
+
+/// Flatten the dag referenced by a leader. This is a classic depth-first search (pre-order):
+/// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
+/// The parents of a certificate are looked up (by round and digest) with `parent`, which only
+/// returns the certificates that were not cleaned up yet.
+fn flatten_sub_dag<C, F>(
+    leader: C,
+    last_committed: &HashMap<PublicKey, Round>,
+    last_committed_round: Round,
+    gc_depth: Round,
+    mut parent: F,
+) -> Vec<Certificate>
+where
+    C: Borrow<Certificate>,
+    F: FnMut(Round, &Digest) -> Option<C>,
+{
+    let mut ordered = Vec::new();
+    let mut already_ordered = HashSet::new();
+
+    let mut buffer = vec![leader];
+    while let Some(x) = buffer.pop() {
+        let x = x.borrow();
+        debug!("Sequencing {:?}", x);
+        ordered.push(x.clone());
+        for digest in &x.header.parents {
+            let certificate = match parent(x.round() - 1, digest) {
+                Some(x) => x,
+                None => continue, // We already ordered or GC up to here.
+            };
+
+            // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
+            // committed for this authority.
+            let mut skip = already_ordered.contains(digest);
+            skip |= last_committed
+                .get(&certificate.borrow().origin())
+                .map_or_else(|| false, |r| r == &certificate.borrow().round());
+            if !skip {
+                buffer.push(certificate);
+                already_ordered.insert(digest.clone());
             }
         }
+    }
 
-        // Ensure we do not commit garbage collected certificates.
-        ordered.retain(|x| x.round() + self.gc_depth >= state.last_committed_round);
+    // Ensure we do not commit garbage collected certificates.
+    ordered.retain(|x| x.round() + gc_depth >= last_committed_round);
 
-        // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
-        ordered.sort_by_key(|x| x.round());
-        ordered
-    }
+    // Ordering the output by round is not really necessary but it makes the commit sequence prettier
+    // (and identical on all nodes, see `certificate_cmp`).
+    ordered.sort_by(certificate_cmp);
+    ordered
 }
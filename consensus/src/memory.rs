@@ -0,0 +1,90 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::State;
+use crypto::{Digest, PublicKey};
+use primary::{Certificate, Round};
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+/// The memory the certificates of a round of the dag hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoundMemory {
+    /// The number of certificates of the round.
+    pub certificates: usize,
+    /// Their estimated size (in bytes).
+    pub bytes: usize,
+}
+
+/// Estimates the memory the consensus state holds, to tell whether cleanup keeps up with the dag
+/// (e.g., when the memory of a node grows). Certificates are counted for their serialized size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The memory of each round of the dag.
+    pub rounds: BTreeMap<Round, RoundMemory>,
+    /// The estimated size of the last committed round of each authority (in bytes).
+    pub last_committed: usize,
+    /// The estimated size of the committed leaders (in bytes).
+    pub committed_leaders: usize,
+    /// The estimated size of the digests of the certificates cleaned up from the dag (in bytes).
+    pub pruned: usize,
+    /// The estimated size of the equivocations of the rounds of the dag (in bytes).
+    pub equivocations: usize,
+}
+
+impl MemoryReport {
+    /// Returns the estimated size of the dag (in bytes).
+    pub fn dag(&self) -> usize {
+        self.rounds.values().map(|x| x.bytes).sum()
+    }
+
+    /// Returns the estimated size of the whole state (in bytes).
+    pub fn total(&self) -> usize {
+        self.dag() + self.last_committed + self.committed_leaders + self.pruned + self.equivocations
+    }
+}
+
+/// Returns the estimated size of a certificate (in bytes).
+fn certificate_size(certificate: &Certificate) -> usize {
+    bincode::serialized_size(certificate).map_or(0, |x| x as usize)
+}
+
+impl State {
+    /// Reports the memory the state holds. This traverses the whole state.
+    pub fn memory_report(&self) -> MemoryReport {
+        let entry = size_of::<PublicKey>() + size_of::<Digest>();
+        MemoryReport {
+            rounds: self
+                .dag
+                .iter()
+                .map(|(round, certificates)| {
+                    let bytes = certificates
+                        .values()
+                        .map(|(_, x)| entry + certificate_size(x))
+                        .sum();
+                    let memory = RoundMemory {
+                        certificates: certificates.len(),
+                        bytes,
+                    };
+                    (*round, memory)
+                })
+                .collect(),
+            last_committed: self.last_committed.len()
+                * (size_of::<PublicKey>() + size_of::<Round>()),
+            committed_leaders: self
+                .committed_leaders
+                .values()
+                .map(|x| size_of::<Round>() + certificate_size(x))
+                .sum(),
+            pruned: self
+                .pruned
+                .values()
+                .map(|x| x.len() * size_of::<Digest>())
+                .sum(),
+            equivocations: self
+                .equivocations
+                .values()
+                .flatten()
+                .map(|x| certificate_size(&x.existing) + certificate_size(&x.incoming))
+                .sum(),
+        }
+    }
+}
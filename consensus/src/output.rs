@@ -0,0 +1,126 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::warn;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+use tokio::time::{sleep, Duration};
+
+#[cfg(test)]
+#[path = "tests/output_tests.rs"]
+pub mod output_tests;
+
+/// The default number of times we retry sending an item to a closed output.
+pub const DEFAULT_OUTPUT_RETRIES: usize = 3;
+
+/// The default delay between two attempts to send an item to a closed output (in ms).
+pub const DEFAULT_OUTPUT_RETRY_DELAY: u64 = 100;
+
+/// What became of an item sent to an output.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// The output received the item.
+    Delivered,
+    /// The output stayed closed: the item went to the dead-letter channel.
+    DeadLettered,
+    /// The output stayed closed and we have no (open) dead-letter channel: the item is lost.
+    Dropped,
+}
+
+/// Sends the output of consensus to a channel that may be closed for a while (e.g., while its
+/// consumer is replaced during reconfiguration): items sent while it is closed are retried a few
+/// times, giving the consumer the time to `replace` the channel. Items that still cannot be sent
+/// go to the dead-letter channel (if any) for later inspection, rather than being silently lost.
+/// Clones share the same channel.
+pub struct RetryingSender<T> {
+    /// The current channel.
+    sender: Arc<Mutex<Sender<T>>>,
+    /// How many times we retry sending an item after the first attempt.
+    retries: usize,
+    /// The delay between two attempts.
+    retry_delay: Duration,
+    /// Receives the items we failed to send (if set).
+    dead_letters: Option<Sender<T>>,
+}
+
+impl<T> Clone for RetryingSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+            dead_letters: self.dead_letters.clone(),
+        }
+    }
+}
+
+impl<T> From<Sender<T>> for RetryingSender<T> {
+    fn from(sender: Sender<T>) -> Self {
+        Self::new(sender)
+    }
+}
+
+impl<T> RetryingSender<T> {
+    /// Sends to the channel, with the default retries and no dead-letter channel.
+    pub fn new(sender: Sender<T>) -> Self {
+        Self {
+            sender: Arc::new(Mutex::new(sender)),
+            retries: DEFAULT_OUTPUT_RETRIES,
+            retry_delay: Duration::from_millis(DEFAULT_OUTPUT_RETRY_DELAY),
+            dead_letters: None,
+        }
+    }
+
+    /// Retries sending an item `retries` times, waiting `retry_delay` ms between two attempts.
+    pub fn with_retries(self, retries: usize, retry_delay: u64) -> Self {
+        Self {
+            retries,
+            retry_delay: Duration::from_millis(retry_delay),
+            ..self
+        }
+    }
+
+    /// Sends the items we fail to send to the dead-letter channel.
+    pub fn with_dead_letters(self, dead_letters: Sender<T>) -> Self {
+        Self {
+            dead_letters: Some(dead_letters),
+            ..self
+        }
+    }
+
+    /// Replaces the channel (for all clones), e.g., when its consumer is restarted.
+    pub fn replace(&self, sender: Sender<T>) {
+        *self.sender.lock().unwrap() = sender;
+    }
+
+    /// Sends an item, retrying while the channel is closed.
+    pub async fn send(&self, mut item: T) -> Delivery {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                sleep(self.retry_delay).await;
+            }
+            let sender = self.sender.lock().unwrap().clone();
+            match sender.send(item).await {
+                Ok(()) => return Delivery::Delivered,
+                Err(e) => item = e.0,
+            }
+        }
+        match &self.dead_letters {
+            Some(dead_letters) if dead_letters.send(item).await.is_ok() => Delivery::DeadLettered,
+            _ => Delivery::Dropped,
+        }
+    }
+
+    /// Sends an item (see `send`), and warns if it is not delivered.
+    pub async fn send_or_warn(&self, item: T, what: &str) {
+        match self.send(item).await {
+            Delivery::Delivered => (),
+            Delivery::DeadLettered => warn!(
+                "Failed to output {} after {} retries: sent to the dead letters",
+                what, self.retries
+            ),
+            Delivery::Dropped => warn!(
+                "Failed to output {} after {} retries: dropped",
+                what, self.retries
+            ),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{flatten_sub_dag, record_commit, State};
+use crypto::Digest;
+use primary::{Certificate, Round};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ReplayError {
+    #[error("Missing certificate {0} (round {1})")]
+    MissingCertificate(Digest, Round),
+
+    #[error("The leaders committed below round {0} were cleaned up")]
+    LeadersCleanedUp(Round),
+}
+
+impl State {
+    /// Reconstructs the sequence of certificates we committed, in commit order, from the leaders we
+    /// committed and their ancestors (looked up by digest with `certificate`, e.g., in the store of
+    /// the primary). It replays the commits of the leaders, oldest first, with the same garbage
+    /// collection depth as consensus: the sequence is the one consensus output, provided that it
+    /// never received certificates below the rounds it already cleaned up.
+    pub fn committed_order<F>(
+        &self,
+        gc_depth: Round,
+        mut certificate: F,
+    ) -> Result<Vec<Certificate>, ReplayError>
+    where
+        F: FnMut(&Digest) -> Option<Certificate>,
+    {
+        // The replay starts from genesis: it needs every leader we committed.
+        if self.leaders_floor > 0 {
+            return Err(ReplayError::LeadersCleanedUp(self.leaders_floor));
+        }
+
+        // Genesis certificates are never committed.
+        let mut last_committed: HashMap<_, _> =
+            self.last_committed.keys().map(|x| (*x, 0)).collect();
+        let mut last_committed_round = 0;
+
+        let mut sequence = Vec::new();
+        for leader in self.committed_leaders.values() {
+            let mut missing = None;
+            let ordered = flatten_sub_dag(
+                leader.clone(),
+                &last_committed,
+                last_committed_round,
+                gc_depth,
+                |round, digest| {
+                    // Consensus only looks up the certificates it did not clean up yet: those
+                    // above the garbage collection round and the last commit of their authority.
+                    if round == 0 || round + gc_depth < last_committed_round {
+                        return None;
+                    }
+                    match certificate(digest) {
+                        Some(x)
+                            if last_committed
+                                .get(&x.origin())
+                                .is_some_and(|r| x.round() < *r) =>
+                        {
+                            None
+                        }
+                        Some(x) => Some(x),
+                        None => {
+                            missing.get_or_insert_with(|| (digest.clone(), round));
+                            None
+                        }
+                    }
+                },
+            );
+            if let Some((digest, round)) = missing {
+                return Err(ReplayError::MissingCertificate(digest, round));
+            }
+            for x in ordered {
+                last_committed_round = record_commit(&mut last_committed, &x);
+                sequence.push(x);
+            }
+        }
+        Ok(sequence)
+    }
+}
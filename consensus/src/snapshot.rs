@@ -0,0 +1,91 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, LeaderExclusions};
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SnapshotError {
+    #[error("The authorities of the snapshot do not match the committee")]
+    AuthorityMismatch,
+
+    #[error("Leader of round {0} is after the snapshot round {1}")]
+    LeaderAfterSnapshot(Round, Round),
+
+    #[error("Round {0} is not a leader round")]
+    NotLeaderRound(Round),
+
+    #[error("Leaders are not sorted by increasing round (round {0})")]
+    UnorderedLeaders(Round),
+
+    #[error("Leader of round {0} is not a committee member: {1}")]
+    UnknownAuthority(Round, PublicKey),
+
+    #[error("Leader of round {round} should be {expected}, found {found}")]
+    WrongLeader {
+        round: Round,
+        expected: PublicKey,
+        found: PublicKey,
+    },
+}
+
+/// A compact view of the committed state at a given round, suitable for light clients. It holds
+/// the committed leaders (in commit order) rather than the full dag.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// The round at which the snapshot was taken.
+    pub round: Round,
+    /// The leaders committed up to `round`, sorted by round.
+    pub leaders: Vec<Certificate>,
+    /// The authorities (and their stake) in charge at `round`.
+    pub authorities: BTreeMap<PublicKey, Stake>,
+}
+
+/// Checks the internal consistency of a snapshot against the committee: the authority set must
+/// match, and every leader must be the elected leader of its (even) round. This does not verify
+/// the certificates' signatures, use `Certificate::verify` for that. Leaders are elected without
+/// exclusions (see `LeaderExclusions`).
+pub fn verify_snapshot(
+    snapshot: &StateSnapshot,
+    committee: &Committee,
+) -> Result<(), SnapshotError> {
+    let authorities = committee
+        .authorities
+        .iter()
+        .map(|(name, authority)| (*name, authority.stake));
+    if !authorities.eq(snapshot.authorities.iter().map(|(x, y)| (*x, *y))) {
+        return Err(SnapshotError::AuthorityMismatch);
+    }
+
+    let mut previous = 0;
+    for leader in &snapshot.leaders {
+        let round = leader.round();
+        if round > snapshot.round {
+            return Err(SnapshotError::LeaderAfterSnapshot(round, snapshot.round));
+        }
+        if !round.is_multiple_of(2) || round < 2 {
+            return Err(SnapshotError::NotLeaderRound(round));
+        }
+        if round <= previous {
+            return Err(SnapshotError::UnorderedLeaders(round));
+        }
+        previous = round;
+
+        let origin = leader.origin();
+        if !snapshot.authorities.contains_key(&origin) {
+            return Err(SnapshotError::UnknownAuthority(round, origin));
+        }
+        let expected = elect_leader(committee, round, &LeaderExclusions::default());
+        if origin != expected {
+            return Err(SnapshotError::WrongLeader {
+                round,
+                expected,
+                found: origin,
+            });
+        }
+    }
+    Ok(())
+}
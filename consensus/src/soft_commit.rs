@@ -0,0 +1,53 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, State};
+use config::{Committee, Stake};
+use primary::{Certificate, Round};
+
+/// What became of a soft-committed leader (see `State::soft_committable`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoftCommitStatus {
+    /// Its round was not committed yet: the soft commit may still be confirmed or revoked.
+    Pending,
+    /// The leader was (hard) committed.
+    Confirmed,
+    /// A later leader was committed without it: the leader will never be committed.
+    Revoked,
+    /// The committed leaders of its round were cleaned up (see `State::set_leader_retention`): we
+    /// no longer know what became of it.
+    Forgotten,
+}
+
+impl State {
+    /// Returns the oldest uncommitted leader that some of its children support, but that we
+    /// cannot commit yet: either it lacks f+1 support in our dag, or we miss some of its
+    /// ancestors. Such a leader is likely to commit (other nodes may already hold f+1 of its
+    /// children), so consumers may act on it optimistically, as a soft commit. Unlike the commits
+    /// of consensus, soft commits carry no guarantee: they are confirmed once consensus commits the
+    /// leader, and revoked if it commits a later leader without it (see `soft_commit_status`).
+    pub fn soft_committable(&self, committee: &Committee) -> Option<(Round, Certificate)> {
+        let highest_round = *self.dag.keys().max()?;
+        (self.last_committed_round + 1..highest_round)
+            .filter(|r| r.is_multiple_of(2) && *r >= 2)
+            .find_map(|round| {
+                let leader = elect_leader(committee, round, &self.exclusions);
+                let (digest, certificate) = self.dag.get(&round)?.get(&leader)?;
+                let stake: Stake = self
+                    .supporters(digest, round)
+                    .map(|x| committee.stake(&x.origin()))
+                    .sum();
+                let committable =
+                    stake >= committee.validity_threshold() && self.is_complete_to(round);
+                (stake > 0 && !committable).then(|| (round, certificate.clone()))
+            })
+    }
+
+    /// Returns what became of the soft commit of the leader of the specified round.
+    pub fn soft_commit_status(&self, round: Round) -> SoftCommitStatus {
+        match self.committed_leaders.get(&round) {
+            Some(_) => SoftCommitStatus::Confirmed,
+            None if round < self.leaders_floor => SoftCommitStatus::Forgotten,
+            None if round < self.last_committed_round => SoftCommitStatus::Revoked,
+            None => SoftCommitStatus::Pending,
+        }
+    }
+}
@@ -1,43 +1,31 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use config::{Authority, PrimaryAddresses};
-use crypto::{generate_keypair, SecretKey};
+use config::KeyPair;
+use crypto::{SecretKey, Signature};
 use primary::Header;
-use rand::rngs::StdRng;
-use rand::SeedableRng as _;
 use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::path::Path;
 use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration};
 
 // Fixture
 fn keys() -> Vec<(PublicKey, SecretKey)> {
-    let mut rng = StdRng::from_seed([0; 32]);
-    (0..4).map(|_| generate_keypair(&mut rng)).collect()
+    (0..4)
+        .map(|i| {
+            let keypair = KeyPair::new_for_test(/* seed */ 0, i);
+            (keypair.name, keypair.secret)
+        })
+        .collect()
 }
 
 // Fixture
 pub fn mock_committee() -> Committee {
-    Committee {
-        authorities: keys()
-            .iter()
-            .map(|(id, _)| {
-                (
-                    *id,
-                    Authority {
-                        stake: 1,
-                        primary: PrimaryAddresses {
-                            primary_to_primary: "0.0.0.0:0".parse().unwrap(),
-                            worker_to_primary: "0.0.0.0:0".parse().unwrap(),
-                        },
-                        workers: HashMap::default(),
-                    },
-                )
-            })
-            .collect(),
-    }
+    Committee::new_for_test(4, /* base_port */ 0, /* seed */ 0)
 }
 
 // Fixture
-fn mock_certificate(
+pub fn mock_certificate(
     origin: PublicKey,
     round: Round,
     parents: BTreeSet<Digest>,
@@ -79,6 +67,44 @@ fn make_certificates(
     (certificates, next_parents)
 }
 
+// Like `make_certificates`, but the headers are signed by their author and the certificates by
+// all authorities (so that they pass verification).
+fn make_signed_certificates(
+    start: Round,
+    stop: Round,
+    initial_parents: &BTreeSet<Digest>,
+    keys: &[(PublicKey, SecretKey)],
+) -> (VecDeque<Certificate>, BTreeSet<Digest>) {
+    let mut certificates = VecDeque::new();
+    let mut parents = initial_parents.clone();
+    for round in start..=stop {
+        let mut next_parents = BTreeSet::new();
+        for (name, secret) in keys {
+            let mut header = Header {
+                author: *name,
+                round,
+                parents: parents.clone(),
+                ..Header::default()
+            };
+            header.id = header.digest();
+            header.signature = Signature::new(&header.id, secret);
+            let mut certificate = Certificate {
+                header,
+                votes: Vec::new(),
+            };
+            let digest = certificate.digest();
+            certificate.votes = keys
+                .iter()
+                .map(|(name, secret)| (*name, Signature::new(&digest, secret)))
+                .collect();
+            certificates.push_back(certificate);
+            next_parents.insert(digest);
+        }
+        parents = next_parents;
+    }
+    (certificates, parents)
+}
+
 // Run for 2 dag rounds in ideal conditions (all nodes reference all other nodes). We should commit
 // the leader of round 2.
 #[tokio::test]
@@ -106,7 +132,8 @@ async fn commit_one() {
         /* gc_depth */ 50,
         rx_waiter,
         tx_primary,
-        tx_output,
+        tx_output.into(),
+        ConsensusOptions::default(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -126,6 +153,52 @@ async fn commit_one() {
     assert_eq!(certificate.round(), 2);
 }
 
+// Run for 4 dag rounds in ideal conditions. The leaders of rounds 2 and 4 are committed, each in a
+// sub-dag ending with the leader and holding the same sequence as the certificates' output.
+#[tokio::test]
+async fn output_sub_dags() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents.clone());
+    certificates.push_back(certificate);
+    let (_, certificate) = mock_certificate(keys[1], 5, next_parents);
+    certificates.push_back(certificate);
+
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(100);
+    let (tx_sub_dags, mut rx_sub_dags) = channel(100);
+    let options = ConsensusOptions {
+        tx_sub_dags: Some(tx_sub_dags.into()),
+        ..ConsensusOptions::default()
+    };
+    Consensus::spawn(
+        mock_committee(),
+        /* gc_depth */ 50,
+        rx_waiter,
+        tx_primary,
+        tx_output.into(),
+        options,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+
+    for round in [2, 4].iter() {
+        let sub_dag = rx_sub_dags.recv().await.unwrap();
+        assert_eq!(sub_dag.leader.round(), *round);
+        assert_eq!(sub_dag.certificates.last(), Some(&sub_dag.leader));
+        for certificate in sub_dag.certificates {
+            assert_eq!(rx_output.recv().await.unwrap(), certificate);
+        }
+    }
+}
+
 // Run for 8 dag rounds with one dead node node (that is not a leader). We should commit the leaders of
 // rounds 2, 4, 6, and 8.
 #[tokio::test]
@@ -151,7 +224,8 @@ async fn dead_node() {
         /* gc_depth */ 50,
         rx_waiter,
         tx_primary,
-        tx_output,
+        tx_output.into(),
+        ConsensusOptions::default(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -241,7 +315,8 @@ async fn not_enough_support() {
         /* gc_depth */ 50,
         rx_waiter,
         tx_primary,
-        tx_output,
+        tx_output.into(),
+        ConsensusOptions::default(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -306,7 +381,8 @@ async fn missing_leader() {
         /* gc_depth */ 50,
         rx_waiter,
         tx_primary,
-        tx_output,
+        tx_output.into(),
+        ConsensusOptions::default(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -332,3 +408,1622 @@ async fn missing_leader() {
     let certificate = rx_output.recv().await.unwrap();
     assert_eq!(certificate.round(), 4);
 }
+
+// Two states fed the same certificates in different orders have identical sorted views.
+#[test]
+fn deterministic_sorted_views() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 3, &parents, &keys);
+
+    let mut state_1 = State::new(&mock_committee(), genesis.clone()).unwrap();
+    for certificate in certificates.iter() {
+        state_1.try_add(certificate.clone()).unwrap();
+    }
+    state_1.update(&certificates[0], 50);
+
+    let mut state_2 = State::new(&mock_committee(), genesis).unwrap();
+    for certificate in certificates.iter().rev() {
+        state_2.try_add(certificate.clone()).unwrap();
+    }
+    state_2.update(&certificates[0], 50);
+
+    assert_eq!(state_1.sorted_dag(), state_2.sorted_dag());
+    assert_eq!(
+        state_1.sorted_last_committed(),
+        state_2.sorted_last_committed()
+    );
+    assert_eq!(
+        bincode::serialize(&state_1.sorted_dag()).unwrap(),
+        bincode::serialize(&state_2.sorted_dag()).unwrap()
+    );
+}
+
+// An authority producing two different certificates for the same round is reported, and the first
+// certificate is retained.
+#[test]
+fn detect_equivocation() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let mut state = State::new(&mock_committee(), genesis).unwrap();
+
+    // The first certificate is accepted, and re-adding it is harmless.
+    let (first_digest, first) = mock_certificate(keys[0], 1, parents);
+    assert_eq!(state.try_add(first.clone()).unwrap(), Admission::Added);
+    assert_eq!(
+        state.try_add(first.clone()).unwrap(),
+        Admission::AlreadyPresent
+    );
+
+    // A conflicting certificate (for a different header) from the same authority and round.
+    let mut second = first.clone();
+    second.header.id = Digest([1; 32]);
+    match state.try_add(second.clone()) {
+        Err(AdmissionError::Equivocation(equivocation)) => {
+            assert_eq!(equivocation.existing, first);
+            assert_eq!(equivocation.incoming, second);
+        }
+        x => panic!("Equivocation not detected: {:?}", x),
+    }
+    assert_eq!(state.sorted_dag()[&1][&keys[0]], first_digest);
+}
+
+// Re-deliveries of a certificate are no-ops that do not even count against the budget of its
+// origin, while conflicting certificates are still evidence of equivocation.
+#[test]
+fn ignore_redelivered_certificates() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 4, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+    state.set_certificate_budget(Some(2));
+
+    let (_, certificate) = mock_certificate(keys[0], 1, parents);
+    assert_eq!(
+        state.try_add(certificate.clone()).unwrap(),
+        Admission::Added
+    );
+    let dag = state.sorted_dag();
+    for _ in 0..10 {
+        assert_eq!(
+            state.try_add(certificate.clone()).unwrap(),
+            Admission::AlreadyPresent
+        );
+    }
+    assert_eq!(state.sorted_dag(), dag);
+
+    // The budget is still there for a conflicting certificate (the equivocation path).
+    let mut conflicting = certificate;
+    conflicting.header.id = Digest([1; 32]);
+    assert!(matches!(
+        state.try_add(conflicting),
+        Err(AdmissionError::Equivocation(_))
+    ));
+
+    // Re-delivering the certificates consensus processed commits nothing more.
+    let mut state = State::new(&committee, Certificate::genesis(&committee)).unwrap();
+    let sequence: Vec<_> = certificates
+        .iter()
+        .flat_map(|x| consensus.process_certificate(&mut state, x.clone()))
+        .collect();
+    assert!(!sequence.is_empty());
+    for certificate in certificates.iter().filter(|x| x.round() > 2) {
+        assert!(consensus
+            .process_certificate(&mut state, certificate.clone())
+            .is_empty());
+    }
+}
+
+// An authority floods round 1 with conflicting certificates. Beyond its budget, they are rejected
+// without being looked at: only those within the budget are retained as evidence.
+#[test]
+fn rate_limit_certificates() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let mut state = State::new(&mock_committee(), genesis).unwrap();
+    state.set_certificate_budget(Some(3));
+
+    let (_, first) = mock_certificate(keys[0], 1, parents.clone());
+    state.try_add(first.clone()).unwrap();
+    let mut rate_limited = 0;
+    for i in 1..=100 {
+        let mut conflicting = first.clone();
+        conflicting.header.id = Digest([i; 32]);
+        match state.try_add(conflicting) {
+            Err(AdmissionError::Equivocation(_)) => (),
+            Err(AdmissionError::RateLimited(name, 1, 3)) if name == keys[0] => rate_limited += 1,
+            x => panic!("Unexpected admission: {:?}", x),
+        }
+    }
+    assert_eq!(rate_limited, 98);
+    assert_eq!(state.pending_evidence().count(), 2);
+
+    // Other authorities, and the next rounds of the flooding one, are unaffected.
+    let (_, other) = mock_certificate(keys[1], 1, parents.clone());
+    state.try_add(other).unwrap();
+    let (_, next) = mock_certificate(keys[0], 2, parents);
+    state.try_add(next).unwrap();
+
+    // Without a budget, every certificate is looked at.
+    state.set_certificate_budget(None);
+    let mut conflicting = first;
+    conflicting.header.id = Digest([255; 32]);
+    assert!(matches!(
+        state.try_add(conflicting),
+        Err(AdmissionError::Equivocation(_))
+    ));
+}
+
+// With verification enabled, certificates with an invalid signature or without a quorum of votes
+// are rejected before being looked at (they are not even evidence of equivocation). Without it,
+// they are added to the dag.
+#[test]
+fn verify_certificates() {
+    let keys = keys();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_signed_certificates(1, 1, &parents, &keys);
+    let valid = certificates.pop_front().unwrap();
+    let mut forged = certificates.pop_front().unwrap();
+    forged.votes[1].1 = Signature::new(&Digest([0; 32]), &keys[1].1);
+    let mut no_quorum = certificates.pop_front().unwrap();
+    no_quorum.votes.truncate(2);
+
+    let mut state = State::new(&committee, genesis.clone()).unwrap();
+    state.set_verification(Some(committee.clone()));
+    assert_eq!(state.try_add(valid.clone()).unwrap(), Admission::Added);
+    for certificate in [&forged, &no_quorum] {
+        match state.try_add(certificate.clone()) {
+            Err(AdmissionError::InvalidCertificate(name, 1, _)) => {
+                assert_eq!(name, certificate.origin())
+            }
+            x => panic!("Unexpected admission: {:?}", x),
+        }
+    }
+
+    // A forged certificate conflicting with one we hold is not evidence of equivocation.
+    let mut conflicting = valid;
+    conflicting.header.id = Digest([1; 32]);
+    assert!(matches!(
+        state.try_add(conflicting),
+        Err(AdmissionError::InvalidCertificate(..))
+    ));
+    assert_eq!(state.pending_evidence().count(), 0);
+
+    // Without verification, the structure of the certificates is all that matters.
+    let mut state = State::new(&committee, genesis).unwrap();
+    state.set_verification(None);
+    assert_eq!(state.try_add(forged).unwrap(), Admission::Added);
+    assert_eq!(state.try_add(no_quorum).unwrap(), Admission::Added);
+}
+
+// An authority equivocates at round 1. Committing up to round 6 (with a small gc depth) cleans up
+// the certificates of round 1, but the evidence of the equivocation survives.
+#[test]
+fn evidence_survives_cleanup() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 7, &parents, &keys);
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee: committee.clone(),
+        gc_depth: 2,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    let original = certificates[1].clone();
+    let mut conflicting = original.clone();
+    conflicting.header.id = Digest([1; 32]);
+    for certificate in certificates {
+        let equivocated = certificate == original;
+        consensus.process_certificate(&mut state, certificate);
+        if equivocated {
+            let sequence = consensus.process_certificate(&mut state, conflicting.clone());
+            assert!(sequence.is_empty());
+        }
+    }
+    assert_eq!(state.last_committed_round, 6);
+    assert!(!state.sorted_dag().contains_key(&1));
+
+    let evidence: Vec<_> = state.pending_evidence().collect();
+    assert_eq!(evidence.len(), 1);
+    let (round, equivocation) = evidence[0];
+    assert_eq!(round, 1);
+    assert_eq!(equivocation.existing, original);
+    assert_eq!(equivocation.incoming, conflicting);
+}
+
+// Commit the leader of round 6, which cleans up rounds 1 to 4 and the round 5 certificate of the
+// leader. Certificates arriving late for those rounds are rejected and do not resurrect them.
+#[test]
+fn reject_certificates_below_cleanup_frontier() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 7, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates.clone() {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    assert_eq!(state.last_committed_round, 6);
+    let dag = state.sorted_dag();
+    assert!(!dag.contains_key(&3));
+
+    // A new certificate for a round cleaned up entirely.
+    let (_, old) = mock_certificate(keys[1], 3, BTreeSet::new());
+    match state.try_add(old) {
+        Err(AdmissionError::TooOld(name, 3, _)) => assert_eq!(name, keys[1]),
+        x => panic!("Unexpected admission: {:?}", x),
+    }
+
+    // The certificate of the leader of round 5 is redelivered after its cleanup.
+    let leader = committee.leader(0);
+    let redelivered = certificates
+        .iter()
+        .find(|x| x.round() == 5 && x.origin() == leader)
+        .unwrap();
+    match state.try_add(redelivered.clone()) {
+        Err(AdmissionError::TooOld(name, 5, 6)) => assert_eq!(name, leader),
+        x => panic!("Unexpected admission: {:?}", x),
+    }
+    assert!(consensus
+        .process_certificate(&mut state, redelivered.clone())
+        .is_empty());
+
+    // Neither resurrected the state we cleaned up.
+    assert_eq!(state.sorted_dag(), dag);
+}
+
+// The evidence pool drops the oldest evidence once full, and the evidence past its retention.
+#[test]
+fn evidence_pool_bounds() {
+    let (_, certificate) = mock_certificate(keys()[0].0, 1, BTreeSet::new());
+    let equivocation = Equivocation {
+        existing: certificate.clone(),
+        incoming: certificate,
+    };
+    let mut pool = EvidencePool::new(/* capacity */ 2, /* retention */ 10);
+    for round in 1..=3 {
+        pool.insert(round, equivocation.clone());
+    }
+    let rounds: Vec<_> = pool.iter().map(|(round, _)| round).collect();
+    assert_eq!(rounds, vec![2, 3]);
+
+    pool.prune(/* last_committed_round */ 13);
+    let rounds: Vec<_> = pool.iter().map(|(round, _)| round).collect();
+    assert_eq!(rounds, vec![3]);
+    assert_eq!(pool.len(), 1);
+
+    pool.prune(/* last_committed_round */ 20);
+    assert!(pool.is_empty());
+}
+
+// Two states holding the same dag but for one certificate: the diff pinpoints it, whether it is
+// missing from one of the states or conflicting with the certificate of the other.
+#[test]
+fn diff_dags() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 3, &parents, &keys);
+
+    let mut state_1 = State::new(&mock_committee(), genesis.clone()).unwrap();
+    let mut state_2 = State::new(&mock_committee(), genesis.clone()).unwrap();
+    for certificate in certificates.iter() {
+        state_1.try_add(certificate.clone()).unwrap();
+        state_2.try_add(certificate.clone()).unwrap();
+    }
+    assert!(state_1.diff(&state_2).is_empty());
+
+    // The second state also holds a certificate of round 4.
+    let (digest, certificate) = mock_certificate(keys[1], 4, BTreeSet::new());
+    state_2.try_add(certificate).unwrap();
+    let expected = RoundDiff {
+        only_right: vec![(keys[1], digest.clone())],
+        ..RoundDiff::default()
+    };
+    assert_eq!(
+        state_1.diff(&state_2),
+        [(4, expected)].iter().cloned().collect()
+    );
+    let expected = RoundDiff {
+        only_left: vec![(keys[1], digest)],
+        ..RoundDiff::default()
+    };
+    assert_eq!(
+        state_2.diff(&state_1),
+        [(4, expected)].iter().cloned().collect()
+    );
+
+    // The third state holds a different certificate from the same authority at round 2.
+    let original = certificates
+        .iter()
+        .find(|x| x.round() == 2 && x.origin() == keys[2])
+        .unwrap();
+    let mut conflicting = original.clone();
+    conflicting.header.id = Digest([1; 32]);
+    let mut state_3 = State::new(&mock_committee(), genesis).unwrap();
+    for certificate in certificates.iter().filter(|x| *x != original) {
+        state_3.try_add(certificate.clone()).unwrap();
+    }
+    state_3.try_add(conflicting.clone()).unwrap();
+    let expected = RoundDiff {
+        conflicting: vec![(keys[2], original.digest(), conflicting.digest())],
+        ..RoundDiff::default()
+    };
+    assert_eq!(
+        state_1.diff(&state_3),
+        [(2, expected)].iter().cloned().collect()
+    );
+}
+
+// Commit the leaders of rounds 2, 4, and 6, then snapshot the state at round 4. The snapshot survives
+// serialization, verifies against the committee, and holds exactly the leaders of rounds 2 and 4.
+#[test]
+fn snapshot_round_trip() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 7, &parents, &keys);
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee: committee.clone(),
+        gc_depth: 50,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    let mut committed = Vec::new();
+    for certificate in certificates {
+        committed.extend(consensus.process_certificate(&mut state, certificate));
+    }
+    let leader = committee.leader(0);
+    let expected: Vec<_> = committed
+        .into_iter()
+        .filter(|x| x.origin() == leader && x.round().is_multiple_of(2) && x.round() <= 4)
+        .collect();
+    assert_eq!(expected.len(), 2);
+
+    let snapshot = state.snapshot(4, &committee);
+    let bytes = bincode::serialize(&snapshot).unwrap();
+    let received: StateSnapshot = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(received, snapshot);
+    assert!(verify_snapshot(&received, &committee).is_ok());
+    assert_eq!(received.leaders, expected);
+
+    // A snapshot claiming a leader from the wrong authority is rejected.
+    let mut forged = received;
+    forged.leaders[0].header.author = keys.iter().find(|x| **x != leader).cloned().unwrap();
+    match verify_snapshot(&forged, &committee) {
+        Err(SnapshotError::WrongLeader { round, .. }) => assert_eq!(round, 2),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+// Build rounds 1 and 2, then deliver the certificates of round 3 one by one. The leader of round 2
+// is committed with the second one (f+1 support), from which point its commit proof holds exactly
+// two supporting certificates, even once more arrive.
+#[test]
+fn minimal_commit_proof() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 2, &parents, &keys);
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee: committee.clone(),
+        gc_depth: 50,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates.iter().cloned() {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    let leader = certificates
+        .iter()
+        .find(|x| x.round() == 2 && x.origin() == committee.leader(0))
+        .cloned()
+        .unwrap();
+    assert!(state.commit_proof(&leader, &committee).is_none());
+
+    let mut sorted_keys = keys.clone();
+    sorted_keys.sort();
+    let children: Vec<_> = sorted_keys
+        .iter()
+        .map(|name| mock_certificate(*name, 3, next_parents.clone()).1)
+        .collect();
+
+    // One supporting certificate is not enough.
+    assert!(consensus
+        .process_certificate(&mut state, children[0].clone())
+        .is_empty());
+    assert!(state.commit_proof(&leader, &committee).is_none());
+
+    // The second one commits the leader, and the proof holds both.
+    let sequence = consensus.process_certificate(&mut state, children[1].clone());
+    assert_eq!(sequence.last(), Some(&leader));
+    let proof = state.commit_proof(&leader, &committee).unwrap();
+    assert_eq!(proof.leader, leader);
+    assert_eq!(proof.support, children[..2].to_vec());
+    assert_eq!(verify_commit_proof(&proof, &committee), Ok(()));
+
+    // More support does not grow the proof.
+    for child in &children[2..] {
+        consensus.process_certificate(&mut state, child.clone());
+    }
+    let proof = state.commit_proof(&leader, &committee).unwrap();
+    assert_eq!(proof.support.len(), 2);
+    assert_eq!(verify_commit_proof(&proof, &committee), Ok(()));
+
+    // Proofs missing support, or holding unrelated certificates, are rejected.
+    let mut forged = proof.clone();
+    forged.support.pop();
+    assert_eq!(
+        verify_commit_proof(&forged, &committee),
+        Err(CommitProofError::NotEnoughSupport(1, 2))
+    );
+    let mut forged = proof;
+    forged.support[1] = mock_certificate(forged.support[1].origin(), 3, BTreeSet::new()).1;
+    assert_eq!(
+        verify_commit_proof(&forged, &committee),
+        Err(CommitProofError::NotSupporting(forged.support[1].origin()))
+    );
+}
+
+// Drop one certificate of round 1: the dag is complete up to round 1, but every later round
+// references the missing certificate. Consensus refuses to commit past the gap until it is filled.
+#[test]
+fn missing_ancestor() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 4, &parents, &keys);
+    let missing = certificates.remove(1).unwrap();
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee,
+        gc_depth: 50,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut state = State::new(&consensus.committee, genesis).unwrap();
+    for certificate in certificates {
+        assert!(consensus
+            .process_certificate(&mut state, certificate)
+            .is_empty());
+    }
+    assert!(state.is_complete_to(0));
+    assert!(state.is_complete_to(1));
+    assert!(!state.is_complete_to(2));
+    assert!(!state.is_complete_to(4));
+
+    state.try_add(missing).unwrap();
+    assert!(state.is_complete_to(4));
+}
+
+// Add rounds 1 to 7 to the dag without processing them (as if they piled up while consensus was
+// stalled). A single call commits the leaders of rounds 2, 4, and 6, in the same order as
+// processing the certificates one by one.
+#[test]
+fn commit_all_ready() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 7, &parents, &keys);
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee: committee.clone(),
+        gc_depth: 50,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut expected = Vec::new();
+    let mut state = State::new(&committee, genesis.clone()).unwrap();
+    for certificate in certificates.clone() {
+        expected.extend(consensus.process_certificate(&mut state, certificate));
+    }
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates {
+        state.try_add(certificate).unwrap();
+    }
+    let sequence = consensus.commit_all_ready(&mut state);
+    assert_eq!(sequence, expected);
+    assert!(consensus.commit_all_ready(&mut state).is_empty());
+
+    let leader = committee.leader(0);
+    let leaders: Vec<_> = sequence
+        .iter()
+        .filter(|x| x.origin() == leader && x.round().is_multiple_of(2))
+        .map(|x| x.round())
+        .collect();
+    assert_eq!(leaders, vec![2, 4, 6]);
+    assert_eq!(state.last_committed_round, 6);
+}
+
+// Drop one certificate of round 1 and deliver it last: nothing can be committed until the gap is
+// filled, and then the leaders of rounds 2 and 4 are committed at once.
+#[test]
+fn commit_backlog_after_missing_ancestor() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 5, &parents, &keys);
+    let missing = certificates.remove(1).unwrap();
+
+    let (_tx_primary, rx_primary) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let consensus = Consensus {
+        committee: committee.clone(),
+        gc_depth: 50,
+        rx_primary,
+        tx_primary,
+        tx_output: tx_output.into(),
+        genesis: genesis.clone(),
+    };
+
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates {
+        assert!(consensus
+            .process_certificate(&mut state, certificate)
+            .is_empty());
+    }
+
+    let sequence = consensus.process_certificate(&mut state, missing);
+    let leader = committee.leader(0);
+    let leaders: Vec<_> = sequence
+        .iter()
+        .filter(|x| x.origin() == leader && x.round().is_multiple_of(2))
+        .map(|x| x.round())
+        .collect();
+    assert_eq!(leaders, vec![2, 4]);
+    assert_eq!(
+        sequence.len(),
+        /* rounds 1 to 3 */ 4 * 3 + /* leader 4 */ 1
+    );
+    assert_eq!(state.last_committed_round, 4);
+}
+
+#[test]
+fn valid_genesis() {
+    let committee = mock_committee();
+    let state = State::new(&committee, Certificate::genesis(&committee)).unwrap();
+    assert_eq!(state.dag[&0].len(), committee.size());
+    assert!(state.last_committed.values().all(|x| *x == 0));
+}
+
+#[test]
+fn genesis_missing_authority() {
+    let committee = mock_committee();
+    let mut genesis = Certificate::genesis(&committee);
+    let missing = genesis.pop().unwrap().origin();
+    assert_eq!(
+        State::new(&committee, genesis).err(),
+        Some(GenesisError::MissingAuthority(missing))
+    );
+}
+
+#[test]
+fn genesis_duplicate_origin() {
+    let committee = mock_committee();
+    let mut genesis = Certificate::genesis(&committee);
+    let duplicate = genesis[0].clone();
+    genesis.push(duplicate.clone());
+    assert_eq!(
+        State::new(&committee, genesis).err(),
+        Some(GenesisError::DuplicateOrigin(duplicate.origin()))
+    );
+}
+
+#[test]
+fn genesis_inconsistent_certificates() {
+    let committee = mock_committee();
+
+    // A certificate from a later round.
+    let mut genesis = Certificate::genesis(&committee);
+    let (_, late) = mock_certificate(genesis[0].origin(), 1, BTreeSet::new());
+    genesis[0] = late;
+    let origin = genesis[0].origin();
+    assert_eq!(
+        State::new(&committee, genesis).err(),
+        Some(GenesisError::NotGenesisRound(origin, 1))
+    );
+
+    // A certificate from an authority outside the committee.
+    let mut genesis = Certificate::genesis(&committee);
+    let stranger = KeyPair::new_for_test(/* seed */ 1, 0).name;
+    let (_, certificate) = mock_certificate(stranger, 0, BTreeSet::new());
+    genesis.push(certificate);
+    assert_eq!(
+        State::new(&committee, genesis).err(),
+        Some(GenesisError::UnknownAuthority(stranger))
+    );
+}
+
+// Fixture
+fn committee_with_stakes(stakes: &[Stake]) -> Committee {
+    let committee =
+        Committee::new_for_test(stakes.len(), /* base_port */ 0, /* seed */ 0);
+    Committee {
+        authorities: committee
+            .authorities
+            .into_iter()
+            .zip(stakes)
+            .map(|((name, authority), stake)| {
+                let authority = config::Authority {
+                    stake: *stake,
+                    ..authority
+                };
+                (name, authority)
+            })
+            .collect(),
+        ..committee
+    }
+}
+
+#[test]
+fn valid_committee_quorums() {
+    for stakes in [&[1, 1, 1, 1][..], &[3, 2, 2, 2, 1], &[1], &[1, 1]] {
+        let committee = committee_with_stakes(stakes);
+        assert!(State::new(&committee, Certificate::genesis(&committee)).is_ok());
+    }
+}
+
+#[test]
+fn flag_dominant_authority() {
+    // The failure of the first authority (2 of 5 stake) alone prevents any quorum of 4.
+    let committee = committee_with_stakes(&[2, 1, 1, 1]);
+    let name = *committee
+        .authorities
+        .iter()
+        .find(|(_, x)| x.stake == 2)
+        .unwrap()
+        .0;
+    assert_eq!(
+        State::new(&committee, Certificate::genesis(&committee)).err(),
+        Some(GenesisError::InvalidCommittee(
+            QuorumError::DominantAuthority {
+                name,
+                stake: 2,
+                total: 5,
+                faults: 1
+            }
+        ))
+    );
+}
+
+// A single authority supports its own leaders: every round but the last two is committed, each
+// leader along with the certificate of the round before it.
+#[test]
+fn single_authority_commits_every_round() {
+    let committee = committee_with_stakes(&[1]);
+    let keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &parents, &keys);
+
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+    let mut committed = Vec::new();
+    for certificate in certificates.iter().cloned() {
+        let sequence = consensus.process_certificate(&mut state, certificate);
+        if let Some(leader) = sequence.last() {
+            assert_eq!(leader.origin(), keys[0]);
+            assert_eq!(sequence.len(), 2);
+        }
+        committed.extend(sequence);
+    }
+    let rounds: Vec<_> = committed.iter().map(|x| x.round()).collect();
+    assert_eq!(rounds, (1..=8).collect::<Vec<_>>());
+    assert_eq!(state.committed_leaders.len(), 4);
+}
+
+// Two authorities tolerate no failure: both are needed for a quorum, either one makes a certificate
+// available (and thus commits a leader), and no authority may be excluded from leader election.
+#[test]
+fn two_authorities_quorums() {
+    let committee = committee_with_stakes(&[1, 1]);
+    assert_eq!(committee.quorum_threshold(), 2);
+    assert_eq!(committee.validity_threshold(), 1);
+    assert_eq!(committee.fault_tolerance(), 0);
+    assert_eq!(committee_with_stakes(&[1]).fault_tolerance(), 0);
+    assert_eq!(mock_committee().fault_tolerance(), 1);
+
+    // The leader of round 2 is committed with the support of the other authority alone.
+    let leader = committee.leader(0);
+    let other = *committee
+        .authorities
+        .keys()
+        .find(|x| **x != leader)
+        .unwrap();
+    let genesis = Certificate::genesis(&committee);
+    let keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, parents) = make_certificates(1, 2, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates {
+        assert!(consensus
+            .process_certificate(&mut state, certificate)
+            .is_empty());
+    }
+    let (_, child) = mock_certificate(other, 3, parents);
+    let sequence = consensus.process_certificate(&mut state, child);
+    assert_eq!(
+        sequence.last().map(|x| (x.origin(), x.round())),
+        Some((leader, 2))
+    );
+
+    assert_eq!(
+        state.exclude_leaders(&committee, 4, [other].into_iter().collect()),
+        Err(ExclusionError::TooMuchStake(1, 0))
+    );
+}
+
+#[test]
+fn reject_degenerate_committees() {
+    let committee = committee_with_stakes(&[]);
+    assert_eq!(
+        State::new(&committee, Vec::new()).err(),
+        Some(GenesisError::InvalidCommittee(QuorumError::Empty))
+    );
+
+    let committee = committee_with_stakes(&[1, 0]);
+    let name = *committee
+        .authorities
+        .iter()
+        .find(|(_, x)| x.stake == 0)
+        .unwrap()
+        .0;
+    assert_eq!(committee.check_quorums(), Err(QuorumError::ZeroStake(name)));
+
+    let committee = committee_with_stakes(&[Stake::MAX, 1]);
+    assert_eq!(committee.check_quorums(), Err(QuorumError::StakeOverflow));
+}
+
+// Fixture
+pub fn mock_consensus(committee: &Committee) -> Consensus {
+    Consensus {
+        committee: committee.clone(),
+        gc_depth: 50,
+        rx_primary: channel(1).1,
+        tx_primary: channel(1).0,
+        tx_output: channel(1).0.into(),
+        genesis: Certificate::genesis(committee),
+    }
+}
+
+// Fixture: the dag holding rounds 1 and 2 from all authorities but the excluded one.
+fn mock_state(committee: &Committee, excluded: Option<PublicKey>) -> State {
+    let keys: Vec<_> = keys()
+        .into_iter()
+        .map(|(x, _)| x)
+        .filter(|x| Some(*x) != excluded)
+        .collect();
+    let genesis = Certificate::genesis(committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 2, &parents, &keys);
+    let mut state = State::new(committee, genesis).unwrap();
+    for certificate in certificates {
+        state.try_add(certificate).unwrap();
+    }
+    state
+}
+
+#[test]
+fn lookup_leader_of_absent_round() {
+    let committee = mock_committee();
+    let consensus = mock_consensus(&committee);
+    let state = mock_state(&committee, None);
+    assert_eq!(
+        consensus.lookup_leader(4, &state),
+        LeaderLookup::RoundAbsent
+    );
+    assert!(consensus.leader(4, &state).is_none());
+}
+
+#[test]
+fn lookup_missing_leader() {
+    let committee = mock_committee();
+    let consensus = mock_consensus(&committee);
+    let state = mock_state(&committee, Some(committee.leader(0)));
+    assert_eq!(
+        consensus.lookup_leader(2, &state),
+        LeaderLookup::LeaderMissing
+    );
+    assert!(consensus.leader(2, &state).is_none());
+}
+
+#[test]
+fn lookup_present_leader() {
+    let committee = mock_committee();
+    let consensus = mock_consensus(&committee);
+    let state = mock_state(&committee, None);
+    let leader = committee.leader(0);
+    let expected = &state.dag[&2][&leader];
+    assert_eq!(
+        consensus.lookup_leader(2, &state),
+        LeaderLookup::Leader(expected)
+    );
+    assert_eq!(consensus.leader(2, &state), Some(expected));
+}
+
+// Exclude the scheduled leader from round 2 on. Every node, whatever the order in which it receives
+// the certificates, commits the next authority of the round-robin in its place.
+#[test]
+fn exclude_scheduled_leader() {
+    let committee = mock_committee();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 3, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let scheduled = committee.leader(0);
+    let substitute = committee.leader(1);
+
+    let mut sequences = Vec::new();
+    for rotation in 0..keys.len() {
+        let mut state = State::new(&committee, genesis.clone()).unwrap();
+        let excluded: BTreeSet<_> = [scheduled].iter().cloned().collect();
+        state.exclude_leaders(&committee, 2, excluded).unwrap();
+        assert!(state.leader_exclusions().at(1).is_none());
+
+        // Each node receives the certificates of each round in a different order.
+        let mut sequence = Vec::new();
+        for round in Vec::from(certificates.clone()).chunks(keys.len()) {
+            let mut round = round.to_vec();
+            round.rotate_left(rotation);
+            for certificate in round {
+                sequence.extend(consensus.process_certificate(&mut state, certificate));
+            }
+        }
+        assert_eq!(state.committed_leaders[&2].origin(), substitute);
+        assert_eq!(sequence.last().unwrap().origin(), substitute);
+        sequences.push(sequence);
+    }
+    assert!(sequences.windows(2).all(|x| x[0] == x[1]));
+}
+
+#[test]
+fn reject_invalid_exclusions() {
+    let committee = mock_committee();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 3, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+
+    // At most f stake may be excluded, and only from committee members.
+    let excluded: BTreeSet<_> = keys[..2].iter().cloned().collect();
+    assert_eq!(
+        state.exclude_leaders(&committee, 2, excluded),
+        Err(ExclusionError::TooMuchStake(2, 1))
+    );
+    let unknown = KeyPair::new_for_test(/* seed */ 1, 0).name;
+    assert_eq!(
+        state.exclude_leaders(&committee, 2, [unknown].iter().cloned().collect()),
+        Err(ExclusionError::UnknownAuthority(unknown))
+    );
+
+    // Commit the leader of round 2 in place of the excluded one.
+    let excluded: BTreeSet<_> = [committee.leader(0)].iter().cloned().collect();
+    state
+        .exclude_leaders(&committee, 2, excluded.clone())
+        .unwrap();
+    for certificate in certificates {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    assert_eq!(state.committed_leaders[&2].origin(), committee.leader(1));
+
+    // The exclusions may be applied again, or lifted for later rounds, but not for committed ones.
+    assert_eq!(state.exclude_leaders(&committee, 2, excluded), Ok(()));
+    assert_eq!(
+        state.exclude_leaders(&committee, 4, BTreeSet::new()),
+        Ok(())
+    );
+    assert_eq!(
+        state.exclude_leaders(&committee, 2, BTreeSet::new()),
+        Err(ExclusionError::ConflictsWithCommit(2))
+    );
+    assert_eq!(state.leader_exclusions().at(4), Some(&BTreeSet::new()));
+}
+
+// Commit the leaders of rounds 2 to 10 (that of round 4 only through its successor), then
+// reconstruct the commit sequence from the committed leaders and the certificates.
+#[test]
+fn replay_committed_order() {
+    let committee = mock_committee();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 4, &parents, &keys);
+
+    // The leader of round 4 lacks support: a single certificate of round 5 references it.
+    let leader = certificates
+        .iter()
+        .find(|x| x.round() == 4 && x.origin() == committee.leader(0))
+        .unwrap()
+        .digest();
+    let supporter = keys.iter().find(|x| **x != committee.leader(0)).unwrap();
+    for name in &keys {
+        let mut parents = parents.clone();
+        if name != supporter {
+            parents.remove(&leader);
+        }
+        certificates.push_back(mock_certificate(*name, 5, parents).1);
+    }
+    let parents = certificates.iter().skip(16).map(|x| x.digest()).collect();
+    let (more, _) = make_certificates(6, 11, &parents, &keys);
+    certificates.extend(more);
+
+    for gc_depth in [50, 2] {
+        let mut consensus = mock_consensus(&committee);
+        consensus.gc_depth = gc_depth;
+        let mut state = State::new(&committee, genesis.clone()).unwrap();
+        let mut live = Vec::new();
+        for certificate in certificates.clone() {
+            live.extend(consensus.process_certificate(&mut state, certificate));
+        }
+        assert!(live.iter().any(|x| x.digest() == leader));
+        assert_eq!(state.committed_leaders.len(), 5);
+
+        // Replay from the certificates (as stored by the primary).
+        let store: HashMap<_, _> = certificates
+            .iter()
+            .map(|x| (x.digest(), x.clone()))
+            .collect();
+        let replayed = state.committed_order(gc_depth, |x| store.get(x).cloned());
+        assert_eq!(replayed, Ok(live));
+
+        // We need all the committed certificates.
+        let missing = certificates[0].digest();
+        let result = state.committed_order(gc_depth, |x| match x == &missing {
+            true => None,
+            false => store.get(x).cloned(),
+        });
+        assert_eq!(result, Err(ReplayError::MissingCertificate(missing, 1)));
+    }
+}
+
+// With a retention of 2 rounds, the leaders more than 2 rounds below the garbage collection round
+// are cleaned up, and so is everything we can tell about them.
+#[test]
+fn bounded_leader_retention() {
+    let committee = committee_with_stakes(&[1]);
+    let keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &parents, &keys);
+
+    let mut consensus = mock_consensus(&committee);
+    consensus.gc_depth = 2;
+    let mut state = State::new(&committee, genesis).unwrap();
+    state.set_leader_retention(Some(2));
+    for certificate in certificates {
+        consensus.process_certificate(&mut state, certificate);
+    }
+
+    // The leader of round 8 is the last one committed: the garbage collection round is 6.
+    let rounds: Vec<_> = state.committed_leaders.keys().copied().collect();
+    assert_eq!(rounds, vec![4, 6, 8]);
+    let snapshot = state.snapshot(8, &committee);
+    assert_eq!(snapshot.leaders.len(), 3);
+    assert_eq!(state.soft_commit_status(2), SoftCommitStatus::Forgotten);
+    assert_eq!(state.soft_commit_status(4), SoftCommitStatus::Confirmed);
+    assert_eq!(
+        state.committed_order(2, |_| None),
+        Err(ReplayError::LeadersCleanedUp(4))
+    );
+    assert_eq!(
+        state.exclude_leaders(&committee, 2, BTreeSet::new()),
+        Err(ExclusionError::BelowRetention(4))
+    );
+}
+
+// Fixture
+/// Runs consensus (from the specified state, if any) over the certificates, and returns the
+/// certificates it committed once it processed them all.
+async fn run_consensus(
+    state: Option<State>,
+    checkpointer: Option<Checkpointer>,
+    certificates: &[Certificate],
+    gc_depth: Round,
+) -> Vec<Certificate> {
+    let (tx_waiter, rx_waiter) = channel(100);
+    let (tx_primary, mut rx_primary) = channel(100);
+    let (tx_output, mut rx_output) = channel(100);
+    let options = ConsensusOptions {
+        state,
+        checkpointer,
+        ..ConsensusOptions::default()
+    };
+    Consensus::spawn(
+        mock_committee(),
+        gc_depth,
+        rx_waiter,
+        tx_primary,
+        tx_output.into(),
+        options,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    for certificate in certificates {
+        tx_waiter.send(certificate.clone()).await.unwrap();
+    }
+    drop(tx_waiter);
+    let mut committed = Vec::new();
+    while let Some(certificate) = rx_output.recv().await {
+        committed.push(certificate);
+    }
+    committed
+}
+
+// Commit the leaders of rounds 2 to 6 while writing a checkpoint every 3 leaders, then drop that
+// consensus, restore a new one from the checkpoint, and keep committing. The two runs together
+// commit the same sequence as a single run over the whole dag.
+#[tokio::test]
+async fn restore_from_checkpoint() {
+    let committee = mock_committee();
+    let keys = keys();
+    let genesis = Certificate::genesis(&committee)
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_signed_certificates(1, 12, &genesis, &keys);
+    let certificates: Vec<_> = certificates.into_iter().collect();
+    let gc_depth = 4;
+    let expected = run_consensus(None, None, &certificates, gc_depth).await;
+
+    // The checkpoint of the third leader (of round 6) is taken when the certificates of round 7
+    // commit it; consensus holds until round 8 but does not commit more.
+    let directory = ".test_checkpoints";
+    let _ = fs::remove_dir_all(directory);
+    let (name, secret) = &keys[0];
+    let checkpointer = Checkpointer::spawn(*name, secret.duplicate(), directory.into(), 3);
+    let first = run_consensus(None, Some(checkpointer), &certificates[..32], gc_depth).await;
+    assert_eq!(first.last().map(|x| x.round()), Some(6));
+
+    let path = Path::new(directory).join("checkpoint-3");
+    let wait = async {
+        while !path.exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("Timed out waiting for the checkpoint");
+
+    // Restore consensus from the checkpoint, and resume from round 7.
+    let checkpoint = Checkpoint::read(&path).unwrap();
+    assert_eq!(checkpoint.manifest.index, 3);
+    let state = State::restore(&committee, checkpoint).unwrap();
+    let second = run_consensus(Some(state), None, &certificates[24..], gc_depth).await;
+    assert_eq!([first, second].concat(), expected);
+    let _ = fs::remove_dir_all(directory);
+}
+
+#[test]
+fn reject_tampered_checkpoint() {
+    let committee = mock_committee();
+    let keys = keys();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_signed_certificates(1, 5, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    let manifest = state.checkpoint(&committee);
+    let (name, secret) = &keys[0];
+    let checkpoint = Checkpoint::new(manifest.clone(), *name, secret);
+    assert!(checkpoint.verify(&committee).is_ok());
+
+    // The manifest does not match its digest.
+    let mut tampered = checkpoint.clone();
+    tampered.manifest.index += 1;
+    assert!(matches!(
+        State::restore(&committee, tampered),
+        Err(CheckpointError::InvalidDigest)
+    ));
+
+    // The digest matches, but not the signature.
+    let mut tampered = checkpoint.clone();
+    tampered.manifest.last_committed.insert(*name, 5);
+    tampered.digest = tampered.manifest.digest();
+    assert!(matches!(
+        tampered.verify(&committee),
+        Err(CheckpointError::InvalidSignature(_))
+    ));
+
+    // The checkpoint is signed by a node outside the committee.
+    let outsider = KeyPair::new_for_test(/* seed */ 1, 0);
+    let tampered = Checkpoint::new(manifest.clone(), outsider.name, &outsider.secret);
+    assert!(matches!(
+        tampered.verify(&committee),
+        Err(CheckpointError::UnknownAuthor(_))
+    ));
+
+    // A member signed a checkpoint holding a forged certificate.
+    let mut forged = manifest.clone();
+    let certificate = &mut forged.certificates[0];
+    *certificate = mock_certificate(certificate.origin(), certificate.round(), BTreeSet::new()).1;
+    let tampered = Checkpoint::new(forged, *name, secret);
+    assert!(matches!(
+        tampered.verify(&committee),
+        Err(CheckpointError::InvalidCertificate(..))
+    ));
+
+    // The checkpoint is from another epoch.
+    let next = Committee {
+        epoch: 1,
+        ..committee
+    };
+    assert!(matches!(
+        checkpoint.verify(&next),
+        Err(CheckpointError::EpochMismatch(0, 1))
+    ));
+}
+
+#[test]
+fn next_leader_matches_elected_leader() {
+    let committee = mock_committee();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, parents) = make_certificates(1, 3, &parents, &keys);
+    let consensus = mock_consensus(&committee);
+    let mut state = State::new(&committee, genesis).unwrap();
+
+    // The first leader round is round 2.
+    let expected = LeaderElection {
+        round: 2,
+        leader: committee.leader(0),
+    };
+    assert_eq!(state.next_leader(&committee), expected);
+
+    // After round 3, the next leader round is round 4 and its election accounts for exclusions.
+    for certificate in certificates {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    let excluded: BTreeSet<_> = [committee.leader(0)].iter().cloned().collect();
+    state.exclude_leaders(&committee, 4, excluded).unwrap();
+    let next = state.next_leader(&committee);
+    assert_eq!(next.round, 4);
+    assert_eq!(next.leader, committee.leader(1));
+
+    // Consensus commits the certificate of that leader.
+    let (certificates, _) = make_certificates(4, 5, &parents, &keys);
+    for certificate in certificates {
+        consensus.process_certificate(&mut state, certificate);
+    }
+    assert_eq!(state.committed_leaders[&4].origin(), next.leader);
+}
+
+#[test]
+fn report_memory_per_round() {
+    let committee = mock_committee();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 2, &parents, &keys);
+    let mut state = State::new(&committee, genesis).unwrap();
+    for certificate in certificates.iter().cloned() {
+        state.try_add(certificate).unwrap();
+    }
+
+    // The dag holds the genesis and the two rounds we added, each with a certificate per authority.
+    let report = state.memory_report();
+    assert_eq!(report.rounds.keys().copied().collect::<Vec<_>>(), [0, 1, 2]);
+    assert!(report.rounds.values().all(|x| x.certificates == keys.len()));
+
+    // Each certificate counts (at least) for its serialized size.
+    let serialized: usize = certificates
+        .iter()
+        .filter(|x| x.round() == 2)
+        .map(|x| bincode::serialized_size(x).unwrap() as usize)
+        .sum();
+    let round = report.rounds[&2].bytes;
+    assert!(round >= serialized && round < 2 * serialized);
+    assert_eq!(report.committed_leaders, 0);
+    assert!(report.total() > report.dag());
+}
+
+// Fixture: commits the leaders of rounds 2 to 6, that of round 4 only through its successor (a
+// single certificate of round 5 references it). Returns the committed sequence, along with a state
+// holding the same certificates but that did not commit them yet (as when reconciling with a node
+// ahead of us).
+fn commit_with_weak_leader(committee: &Committee) -> (State, Vec<Certificate>) {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 4, &parents, &keys);
+    let leader = certificates
+        .iter()
+        .find(|x| x.round() == 4 && x.origin() == committee.leader(0))
+        .unwrap()
+        .digest();
+    let supporter = keys.iter().find(|x| **x != committee.leader(0)).unwrap();
+    for name in &keys {
+        let mut parents = parents.clone();
+        if name != supporter {
+            parents.remove(&leader);
+        }
+        certificates.push_back(mock_certificate(*name, 5, parents).1);
+    }
+    let parents = certificates.iter().skip(16).map(|x| x.digest()).collect();
+    let (more, _) = make_certificates(6, 7, &parents, &keys);
+    certificates.extend(more);
+
+    let consensus = mock_consensus(committee);
+    let mut state = State::new(committee, genesis.clone()).unwrap();
+    let mut sequence = Vec::new();
+    for certificate in certificates.iter().cloned() {
+        sequence.extend(consensus.process_certificate(&mut state, certificate));
+    }
+    assert_eq!(state.committed_leaders.len(), 3);
+
+    let mut state = State::new(committee, genesis).unwrap();
+    for certificate in certificates {
+        state.try_add(certificate).unwrap();
+    }
+    (state, sequence)
+}
+
+// The sequence consensus committed is valid, as is any prefix of it ending with a leader with
+// enough support.
+#[test]
+fn verify_commit_sequence() {
+    let committee = mock_committee();
+    let (state, sequence) = commit_with_weak_leader(&committee);
+    assert_eq!(state.verify_commit_sequence(&sequence, &committee), Ok(()));
+    assert_eq!(state.verify_commit_sequence(&[], &committee), Ok(()));
+
+    let end = sequence
+        .iter()
+        .position(|x| x.round() == 2 && x.origin() == committee.leader(0))
+        .unwrap();
+    assert_eq!(
+        state.verify_commit_sequence(&sequence[..=end], &committee),
+        Ok(())
+    );
+}
+
+// Each kind of violation is reported, at the position of the offending certificate.
+#[test]
+fn reject_invalid_commit_sequences() {
+    let committee = mock_committee();
+    let (state, sequence) = commit_with_weak_leader(&committee);
+    let position = |round| {
+        sequence
+            .iter()
+            .position(|x| x.round() == round && x.origin() == committee.leader(0))
+            .unwrap()
+    };
+
+    // A certificate we never saw.
+    let (digest, unknown) = mock_certificate(committee.leader(0), 1, BTreeSet::new());
+    let mut forged = sequence.clone();
+    forged.insert(0, unknown);
+    assert_eq!(
+        state.verify_commit_sequence(&forged, &committee),
+        Err(CommitViolation::UnknownCertificate(0, digest))
+    );
+
+    // A leader before its parents.
+    let mut forged = sequence.clone();
+    let leader = forged.remove(position(2));
+    let parent = leader
+        .header
+        .parents
+        .iter()
+        .find(|x| forged.iter().any(|y| &y.digest() == *x))
+        .cloned()
+        .unwrap();
+    forged.insert(0, leader);
+    assert_eq!(
+        state.verify_commit_sequence(&forged, &committee),
+        Err(CommitViolation::OutOfOrder(0, parent))
+    );
+
+    // A sub-dag ending at an odd round.
+    let end = sequence.iter().position(|x| x.round() == 3).unwrap();
+    assert_eq!(
+        state.verify_commit_sequence(&sequence[..=end], &committee),
+        Err(CommitViolation::NotLeaderRound(end, 3))
+    );
+
+    // A sub-dag ending with a certificate that is not from the leader.
+    let end = position(2) + 1;
+    let found = sequence[end].origin();
+    assert_eq!(sequence[end].round(), 2);
+    assert_eq!(
+        state.verify_commit_sequence(&sequence[..=end], &committee),
+        Err(CommitViolation::WrongLeader {
+            index: end,
+            round: 2,
+            expected: committee.leader(0),
+            found
+        })
+    );
+
+    // The leader of round 4 is only committed through the leader of round 6.
+    let end = position(4);
+    assert_eq!(
+        state.verify_commit_sequence(&sequence[..=end], &committee),
+        Err(CommitViolation::NotEnoughSupport(end, 1, 2))
+    );
+}
+
+// Authorities at different rounds: the frontier holds the highest round of each of them.
+#[test]
+fn authority_frontier() {
+    let committee = mock_committee();
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    let genesis = Certificate::genesis(&committee);
+    let mut state = State::new(&committee, genesis.clone()).unwrap();
+    assert!(state.frontier().values().all(|x| *x == 0));
+
+    // Everyone reaches round 2, the first three authorities round 3, and the first two round 4.
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, parents) = make_certificates(1, 2, &parents, &keys);
+    let (more, parents) = make_certificates(3, 3, &parents, &keys[..3]);
+    let (last, _) = make_certificates(4, 4, &parents, &keys[..2]);
+    for certificate in certificates.into_iter().chain(more).chain(last) {
+        state.try_add(certificate).unwrap();
+    }
+    let frontier = state.frontier();
+    assert_eq!(frontier.len(), 4);
+    assert_eq!(frontier[&keys[0]], 4);
+    assert_eq!(frontier[&keys[1]], 4);
+    assert_eq!(frontier[&keys[2]], 3);
+    assert_eq!(frontier[&keys[3]], 2);
+}
+
+#[test]
+fn verify_causal_order_of_sequence() {
+    let committee = mock_committee();
+    let (_, sequence) = commit_with_weak_leader(&committee);
+    assert_eq!(verify_causal_order(&sequence), Ok(()));
+
+    // Emitting the leader of round 2 before its parents of round 1 (in front of the sequence).
+    let mut misordered = sequence.clone();
+    let leader = misordered
+        .iter()
+        .position(|x| x.round() == 2 && x.origin() == committee.leader(0))
+        .unwrap();
+    let certificate = misordered.remove(leader);
+    misordered.insert(0, certificate);
+    assert_eq!(verify_causal_order(&misordered), Err(0));
+
+    // Swapping a certificate of round 3 with one of its children is caught at the child.
+    let mut misordered = sequence;
+    let parent = misordered.iter().position(|x| x.round() == 3).unwrap();
+    let digest = misordered[parent].digest();
+    let child = misordered
+        .iter()
+        .position(|x| x.header.parents.contains(&digest))
+        .unwrap();
+    misordered.swap(parent, child);
+    assert_eq!(verify_causal_order(&misordered), Err(parent));
+}
+
+// A dag full of the certificates of rounds 0 and 1 rejects those of round 2, without evicting
+// anything, until its capacity is raised.
+#[test]
+fn reject_certificates_at_capacity() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let committee = mock_committee();
+    let genesis = Certificate::genesis(&committee);
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 2, &parents, &keys);
+    let mut state = State::new(&committee, genesis).unwrap();
+    state.set_capacity(Some(8));
+
+    let (round_1, round_2): (Vec<_>, Vec<_>) =
+        certificates.into_iter().partition(|x| x.round() == 1);
+    for certificate in round_1.iter().cloned() {
+        state.try_add(certificate).unwrap();
+    }
+    let dag = state.sorted_dag();
+    let last_committed = state.sorted_last_committed();
+    for certificate in round_2.iter().cloned() {
+        match state.try_add(certificate) {
+            Err(AdmissionError::AtCapacity(8)) => (),
+            x => panic!("Unexpected admission: {:?}", x),
+        }
+    }
+    assert_eq!(state.sorted_dag(), dag);
+    assert_eq!(state.sorted_last_committed(), last_committed);
+
+    // Certificates we already hold are still recognized.
+    state.try_add(round_1[0].clone()).unwrap();
+
+    // With more room, the dag grows again.
+    state.set_capacity(Some(12));
+    for certificate in round_2 {
+        state.try_add(certificate).unwrap();
+    }
+    assert_eq!(state.sorted_dag()[&2].len(), 4);
+}
+
+// Excluding the next leader hands its round over to the next authority of the round-robin.
+#[test]
+fn announce_leader_handoff() {
+    let committee = mock_committee();
+    let mut state = mock_state(&committee, None);
+    let (tx_handoffs, mut rx_handoffs) = channel(10);
+    state.set_handoff_channel(Some(tx_handoffs));
+
+    // The first observation has nothing to compare to, and the leader does not change as rounds
+    // advance (unit tests always elect the same leader).
+    let leader = committee.leader(0);
+    assert_eq!(state.next_leader(&committee).leader, leader);
+    assert_eq!(state.observe_leader(&committee), None);
+    let parents = state.dag[&2].values().map(|(x, _)| x.clone()).collect();
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let (certificates, _) = make_certificates(3, 4, &parents, &keys);
+    for certificate in certificates {
+        state.try_add(certificate).unwrap();
+        assert_eq!(state.observe_leader(&committee), None);
+    }
+    assert!(rx_handoffs.try_recv().is_err());
+
+    // The leader of round 6 on is excluded.
+    let excluded = [leader].iter().cloned().collect();
+    state.exclude_leaders(&committee, 6, excluded).unwrap();
+    let next = state.next_leader(&committee);
+    assert_ne!(next.leader, leader);
+    let expected = LeaderHandoff {
+        round: 6,
+        previous: leader,
+        next: next.leader,
+    };
+    assert_eq!(state.observe_leader(&committee), Some(expected.clone()));
+    assert_eq!(rx_handoffs.try_recv().unwrap(), expected);
+    assert_eq!(state.observe_leader(&committee), None);
+}
+
+// The canonical certificate order is a total order, so sorting any permutation of the same
+// certificates gives the same sequence.
+#[test]
+fn canonical_certificate_order() {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom as _;
+    use rand::SeedableRng as _;
+
+    // Several rounds, with an equivocating authority (to exercise the tie-break on digests).
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let parents = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 3, &parents, &keys);
+    let mut certificates: Vec<_> = certificates.into_iter().collect();
+    for parents in [BTreeSet::new(), parents] {
+        let mut certificate = mock_certificate(keys[0], 2, parents).1;
+        certificate.header.id = certificate.header.digest();
+        certificates.push(certificate);
+    }
+
+    // The order is total: reflexive, antisymmetric, and transitive.
+    for a in &certificates {
+        assert_eq!(certificate_cmp(a, a), Ordering::Equal);
+        for b in &certificates {
+            let ab = certificate_cmp(a, b);
+            assert_eq!(ab, certificate_cmp(b, a).reverse());
+            assert_eq!(ab == Ordering::Equal, a == b);
+            for c in &certificates {
+                if ab != Ordering::Greater && certificate_cmp(b, c) != Ordering::Greater {
+                    assert_ne!(certificate_cmp(a, c), Ordering::Greater);
+                }
+            }
+        }
+    }
+
+    // Sorting shuffled certificates always gives the same sequence, by increasing round.
+    let mut expected = certificates.clone();
+    expected.sort_by(certificate_cmp);
+    assert!(expected.windows(2).all(|x| x[0].round() <= x[1].round()));
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..100 {
+        certificates.shuffle(&mut rng);
+        let mut sorted = certificates.clone();
+        sorted.sort_by(certificate_cmp);
+        assert_eq!(sorted, expected);
+    }
+}
+
+// A leader supported by fewer than f+1 of its children is soft-committable, but not committable.
+// Once f+1 children support it, consensus commits it, which confirms the soft commit.
+#[test]
+fn soft_commit_leader() {
+    let committee = mock_committee();
+    let consensus = mock_consensus(&committee);
+    let mut state = mock_state(&committee, None);
+    assert_eq!(state.soft_committable(&committee), None);
+
+    // Only one child supports the leader of round 2.
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let leader = committee.leader(0);
+    let (leader_digest, leader_certificate) = state.dag[&2][&leader].clone();
+    let parents: BTreeSet<_> = state.dag[&2].values().map(|(x, _)| x.clone()).collect();
+    let mut others = parents.clone();
+    others.remove(&leader_digest);
+    let supporter = *keys.iter().find(|x| **x != leader).unwrap();
+    for name in keys.iter().filter(|x| **x != leader) {
+        let parents = match *name == supporter {
+            true => parents.clone(),
+            false => others.clone(),
+        };
+        state
+            .try_add(mock_certificate(*name, 3, parents).1)
+            .unwrap();
+    }
+    assert_eq!(
+        state.soft_committable(&committee),
+        Some((2, leader_certificate.clone()))
+    );
+    assert_eq!(state.soft_commit_status(2), SoftCommitStatus::Pending);
+    assert_eq!(consensus.next_committable_leader(&state), None);
+
+    // A second child supports it: consensus commits it.
+    state
+        .try_add(mock_certificate(leader, 3, parents).1)
+        .unwrap();
+    let committable = consensus.next_committable_leader(&state).unwrap();
+    assert_eq!(committable, leader_certificate);
+    assert_eq!(state.soft_committable(&committee), None);
+    consensus.commit_leader(&committable, &mut state);
+    assert_eq!(state.soft_commit_status(2), SoftCommitStatus::Confirmed);
+}
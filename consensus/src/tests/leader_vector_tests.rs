@@ -0,0 +1,29 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Fixture
+fn committee() -> Committee {
+    Committee::new_for_test(4, /* base_port */ 0, /* seed */ 0)
+}
+
+#[test]
+fn round_robin_over_sorted_keys() {
+    let committee = committee();
+    let vector = LeaderVector::new(&committee, 0..=9);
+    let rounds: Vec<_> = vector.elections.iter().map(|x| x.round).collect();
+    assert_eq!(rounds, vec![2, 4, 6, 8]);
+
+    let mut keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    keys.sort();
+    for election in &vector.elections {
+        assert_eq!(election.leader, keys[election.round as usize % keys.len()]);
+    }
+}
+
+#[test]
+fn matches_golden_vector() {
+    // Changing the leader election breaks compatibility with other implementations: regenerate the
+    // golden file (`node leader_vector`) only on purpose.
+    let golden: LeaderVector = serde_json::from_str(include_str!("leader_vector.json")).unwrap();
+    assert_eq!(LeaderVector::new(&committee(), 0..=20), golden);
+}
@@ -0,0 +1,38 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn deliver_after_replacing_closed_channel() {
+    // The consumer went away: the first attempt fails.
+    let (tx_closed, rx_closed) = channel(1);
+    drop(rx_closed);
+    let sender = RetryingSender::new(tx_closed).with_retries(3, 50);
+
+    // Its replacement shows up while we retry.
+    let (tx_output, mut rx_output) = channel(1);
+    let replacer = sender.clone();
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(20)).await;
+        replacer.replace(tx_output);
+    });
+
+    assert_eq!(sender.send(7).await, Delivery::Delivered);
+    assert_eq!(rx_output.recv().await, Some(7));
+}
+
+#[tokio::test]
+async fn dead_letter_undelivered_items() {
+    let (tx_closed, rx_closed) = channel(1);
+    drop(rx_closed);
+    let (tx_dead, mut rx_dead) = channel(1);
+    let sender = RetryingSender::new(tx_closed)
+        .with_retries(2, 10)
+        .with_dead_letters(tx_dead);
+    assert_eq!(sender.send(7).await, Delivery::DeadLettered);
+    assert_eq!(rx_dead.recv().await, Some(7));
+
+    // Without (open) dead-letter channel, the item is dropped.
+    drop(rx_dead);
+    assert_eq!(sender.send(8).await, Delivery::Dropped);
+}
@@ -0,0 +1,219 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::{mock_certificate, mock_committee, mock_consensus};
+use std::collections::BTreeSet;
+
+// Fixture: the groups of authorities that can exchange certificates. Authorities of different
+// groups cannot, and an empty partition connects everyone.
+type Partition = Vec<BTreeSet<PublicKey>>;
+
+// Fixture: whether `from` may deliver its certificates to `to` under the partition.
+fn connected(partition: &Partition, from: &PublicKey, to: &PublicKey) -> bool {
+    partition.is_empty()
+        || partition
+            .iter()
+            .any(|group| group.contains(from) && group.contains(to))
+}
+
+// Fixture: the consensus state of an authority.
+struct Node {
+    state: State,
+    /// The certificates delivered to the node.
+    delivered: HashSet<Digest>,
+    /// The digests of the certificates the node committed, in order.
+    sequence: Vec<Digest>,
+}
+
+// Fixture: drives the consensus of every authority of the committee in lockstep, from the genesis.
+// At each step, every authority that holds a quorum of certificates of the previous round and that
+// can gather a quorum of votes certifies a header of the current round. Certificates are then
+// delivered (parents first) to the nodes their author is connected to, following a schedule of
+// partitions: certificates that cannot be delivered are delivered once the partition heals.
+struct Simulation {
+    committee: Committee,
+    consensus: Consensus,
+    /// The partitions, from the step they start at.
+    schedule: BTreeMap<u64, Partition>,
+    nodes: BTreeMap<PublicKey, Node>,
+    /// The certificates created so far, by increasing round.
+    certificates: Vec<Certificate>,
+    /// The round of the next certificates.
+    round: Round,
+}
+
+impl Simulation {
+    fn new(schedule: BTreeMap<u64, Partition>) -> Self {
+        let committee = mock_committee();
+        let genesis = Certificate::genesis(&committee);
+        let nodes = committee
+            .authorities
+            .keys()
+            .map(|name| {
+                let node = Node {
+                    state: State::new(&committee, genesis.clone()).unwrap(),
+                    delivered: genesis.iter().map(|x| x.digest()).collect(),
+                    sequence: Vec::new(),
+                };
+                (*name, node)
+            })
+            .collect();
+        Self {
+            consensus: mock_consensus(&committee),
+            committee,
+            schedule,
+            nodes,
+            certificates: genesis,
+            round: 1,
+        }
+    }
+
+    /// Returns the partition in force at a step.
+    fn partition(&self, step: u64) -> Partition {
+        self.schedule
+            .range(..=step)
+            .next_back()
+            .map(|(_, x)| x.clone())
+            .unwrap_or_default()
+    }
+
+    /// Runs a step: certifies the headers of the next round we can, and delivers the certificates.
+    fn step(&mut self, step: u64) {
+        let partition = self.partition(step);
+        let quorum = self.committee.quorum_threshold();
+        let mut created = Vec::new();
+        for (name, node) in &self.nodes {
+            let parents: Vec<_> = self
+                .certificates
+                .iter()
+                .filter(|x| x.round() + 1 == self.round)
+                .filter(|x| node.delivered.contains(&x.digest()))
+                .collect();
+            let stake: Stake = parents
+                .iter()
+                .map(|x| self.committee.stake(&x.origin()))
+                .sum();
+            let votes: Stake = self
+                .committee
+                .authorities
+                .keys()
+                .filter(|voter| connected(&partition, name, voter))
+                .map(|voter| self.committee.stake(voter))
+                .sum();
+            if stake >= quorum && votes >= quorum {
+                let parents = parents.iter().map(|x| x.digest()).collect();
+                created.push(mock_certificate(*name, self.round, parents).1);
+            }
+        }
+        if !created.is_empty() {
+            self.certificates.extend(created);
+            self.round += 1;
+        }
+
+        // Deliver the certificates in round order, so that parents are delivered first.
+        for (name, node) in self.nodes.iter_mut() {
+            for certificate in &self.certificates {
+                let digest = certificate.digest();
+                if node.delivered.contains(&digest)
+                    || !connected(&partition, &certificate.origin(), name)
+                    || !certificate
+                        .header
+                        .parents
+                        .iter()
+                        .all(|x| node.delivered.contains(x))
+                {
+                    continue;
+                }
+                node.delivered.insert(digest);
+                let sequence = self
+                    .consensus
+                    .process_certificate(&mut node.state, certificate.clone());
+                node.sequence.extend(sequence.iter().map(|x| x.digest()));
+            }
+        }
+    }
+
+    /// Runs the steps of the range, and returns the length of the sequence of each node after it.
+    fn run(&mut self, steps: std::ops::Range<u64>) -> BTreeMap<PublicKey, usize> {
+        for step in steps {
+            self.step(step);
+        }
+        self.nodes
+            .iter()
+            .map(|(name, node)| (*name, node.sequence.len()))
+            .collect()
+    }
+
+    /// Checks that the sequences of any two nodes are consistent: one is a prefix of the other.
+    fn assert_consistent(&self) {
+        for a in self.nodes.values() {
+            for b in self.nodes.values() {
+                let n = std::cmp::min(a.sequence.len(), b.sequence.len());
+                assert_eq!(a.sequence[..n], b.sequence[..n]);
+            }
+        }
+    }
+}
+
+#[test]
+fn commit_consistently_across_healed_partition() {
+    // Split the committee in two halves, neither of which can gather a quorum.
+    let names: Vec<_> = mock_committee().authorities.keys().cloned().collect();
+    let halves = vec![
+        names[..2].iter().cloned().collect(),
+        names[2..].iter().cloned().collect(),
+    ];
+    let schedule = [(5, halves), (15, Vec::new())].iter().cloned().collect();
+    let mut simulation = Simulation::new(schedule);
+
+    // Consensus commits before the partition, and stalls during it.
+    let before = simulation.run(0..5);
+    assert!(before.values().all(|x| *x > 0));
+    simulation.assert_consistent();
+    let during = simulation.run(5..15);
+    assert_eq!(during, before);
+
+    // After healing, every node commits again, consistently.
+    let after = simulation.run(15..25);
+    assert!(after.iter().all(|(name, x)| *x > during[name]));
+    simulation.assert_consistent();
+}
+
+#[test]
+fn catch_up_after_minority_partition() {
+    // Cut an authority (other than the leader) off the others, who keep a quorum.
+    let committee = mock_committee();
+    let leader = committee.leader(0);
+    let isolated = *committee
+        .authorities
+        .keys()
+        .find(|x| **x != leader)
+        .unwrap();
+    let majority = committee
+        .authorities
+        .keys()
+        .filter(|x| **x != isolated)
+        .cloned()
+        .collect();
+    let split = vec![majority, [isolated].iter().cloned().collect()];
+    let schedule = [(5, split), (15, Vec::new())].iter().cloned().collect();
+    let mut simulation = Simulation::new(schedule);
+
+    // The majority keeps committing while the minority lags behind.
+    let before = simulation.run(0..5);
+    let during = simulation.run(5..15);
+    assert_eq!(during[&isolated], before[&isolated]);
+    assert!(during
+        .iter()
+        .all(|(name, x)| *name == isolated || *x > before[name]));
+    simulation.assert_consistent();
+
+    // After healing, the minority catches up with the same sequence.
+    simulation.run(15..25);
+    simulation.assert_consistent();
+    let lengths: BTreeSet<_> = simulation
+        .nodes
+        .values()
+        .map(|x| x.sequence.len())
+        .collect();
+    assert_eq!(lengths.len(), 1);
+}
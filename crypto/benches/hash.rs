@@ -0,0 +1,33 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crypto::{HashAlgorithm, Hasher};
+
+/// The size of the hashed batches (1MB).
+const BATCH_SIZE: usize = 1_000_000;
+
+fn hash(c: &mut Criterion) {
+    let batch = vec![0xab_u8; BATCH_SIZE];
+    let mut group = c.benchmark_group("hash");
+    group.throughput(Throughput::Bytes(BATCH_SIZE as u64));
+    // Run with `--features hash-blake3` to include the blake3 backend.
+    for algorithm in [HashAlgorithm::Sha512, HashAlgorithm::Blake3] {
+        if !algorithm.is_supported() {
+            continue;
+        }
+        group.bench_with_input(
+            BenchmarkId::new(algorithm.to_string(), BATCH_SIZE),
+            &batch,
+            |b, batch| {
+                b.iter(|| {
+                    let mut hasher = Hasher::with_algorithm(algorithm);
+                    hasher.update(batch);
+                    hasher.finalize()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hash);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use crypto::{generate_keypair, verify_batch, Digest, PublicKey, Signature};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+fn signatures(n: usize) -> Vec<(Digest, PublicKey, Signature)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..n)
+        .map(|i| {
+            let (public_key, secret_key) = generate_keypair(&mut rng);
+            let digest = Digest([i as u8; 32]);
+            let signature = Signature::new(&digest, &secret_key);
+            (digest, public_key, signature)
+        })
+        .collect()
+}
+
+fn verify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify");
+    for n in [1, 16, 128] {
+        let items = signatures(n);
+        group.bench_with_input(BenchmarkId::new("single", n), &items, |b, items| {
+            b.iter(|| {
+                items
+                    .iter()
+                    .all(|(digest, key, signature)| signature.verify(digest, key).is_ok())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("batch", n), &items, |b, items| {
+            b.iter(|| verify_batch(items.iter().cloned()).is_ok())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, verify);
+criterion_main!(benches);
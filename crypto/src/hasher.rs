@@ -0,0 +1,107 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::Digest;
+use ed25519_dalek::{Digest as _, Sha512};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::fmt;
+
+/// Identifies the hash function used to compute digests. All members of a committee must use
+/// the same algorithm, otherwise they disagree on the digests of headers and batches.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha512,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The algorithm selected at compile time, used by `Hasher::new` and `hash`. The
+    /// `hash-blake3` feature takes precedence over `hash-sha512`.
+    pub const fn compiled() -> Self {
+        if cfg!(feature = "hash-blake3") {
+            Self::Blake3
+        } else {
+            Self::Sha512
+        }
+    }
+
+    /// Whether this binary was compiled with support for the algorithm.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Sha512 => true,
+            Self::Blake3 => cfg!(feature = "hash-blake3"),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Sha512 => write!(f, "sha512"),
+            Self::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// An incremental hasher producing 32-byte digests. SHA-512 outputs are truncated to their
+/// first 32 bytes.
+pub struct Hasher(Backend);
+
+// Hashers are short-lived stack values, boxing the larger backend would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+enum Backend {
+    Sha512(Sha512),
+    #[cfg(feature = "hash-blake3")]
+    Blake3(blake3::Hasher),
+}
+
+impl Hasher {
+    /// Creates a hasher using the algorithm selected at compile time.
+    pub fn new() -> Self {
+        Self::with_algorithm(HashAlgorithm::compiled())
+    }
+
+    /// Creates a hasher using a specific algorithm. Panics if the algorithm is not supported
+    /// by this build.
+    pub fn with_algorithm(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha512 => Self(Backend::Sha512(Sha512::new())),
+            #[cfg(feature = "hash-blake3")]
+            HashAlgorithm::Blake3 => Self(Backend::Blake3(blake3::Hasher::new())),
+            #[cfg(not(feature = "hash-blake3"))]
+            HashAlgorithm::Blake3 => panic!("Hash algorithm {} is not supported", algorithm),
+        }
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        match &mut self.0 {
+            Backend::Sha512(hasher) => hasher.update(data),
+            #[cfg(feature = "hash-blake3")]
+            Backend::Blake3(hasher) => {
+                hasher.update(data.as_ref());
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Digest {
+        match self.0 {
+            Backend::Sha512(hasher) => Digest(hasher.finalize()[..32].try_into().unwrap()),
+            #[cfg(feature = "hash-blake3")]
+            Backend::Blake3(hasher) => Digest(*hasher.finalize().as_bytes()),
+        }
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hashes a byte string with the algorithm selected at compile time.
+pub fn hash(data: &[u8]) -> Digest {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
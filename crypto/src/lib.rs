@@ -1,9 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use ed25519_dalek as dalek;
 use ed25519_dalek::ed25519;
-use ed25519_dalek::Signer as _;
 use rand::rngs::OsRng;
-use rand::{CryptoRng, RngCore};
+use rand::{CryptoRng, RngCore, SeedableRng as _};
+use rand_chacha::ChaCha20Rng;
 use serde::{de, ser, Deserialize, Serialize};
 use std::array::TryFromSliceError;
 use std::convert::{TryFrom, TryInto};
@@ -15,6 +14,14 @@ use tokio::sync::oneshot;
 #[path = "tests/crypto_tests.rs"]
 pub mod crypto_tests;
 
+mod hasher;
+mod scheme;
+
+pub use crate::hasher::{hash, HashAlgorithm, Hasher};
+#[cfg(feature = "secp256k1")]
+pub use crate::scheme::Secp256k1;
+pub use crate::scheme::{Ed25519, Scheme, SignatureScheme};
+
 pub type CryptoError = ed25519::Error;
 
 /// Represents a hash digest (32 bytes).
@@ -33,13 +40,13 @@ impl Digest {
 
 impl fmt::Debug for Digest {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", base64::encode(&self.0))
+        write!(f, "{}", base64::encode(self.0))
     }
 }
 
 impl fmt::Display for Digest {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", base64::encode(&self.0).get(0..16).unwrap())
+        write!(f, "{}", base64::encode(self.0).get(0..16).unwrap())
     }
 }
 
@@ -117,23 +124,55 @@ impl AsRef<[u8]> for PublicKey {
     }
 }
 
-/// Represents a secret key (in bytes).
-pub struct SecretKey([u8; 64]);
+/// Represents a secret key (in bytes), tagged with its signature scheme.
+pub struct SecretKey {
+    scheme: Scheme,
+    bytes: [u8; 64],
+}
 
 impl SecretKey {
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// Ed25519 keys are encoded as their raw 64 bytes (as they always were); keys of any other
+    /// scheme are prefixed by a one-byte scheme tag.
     pub fn encode_base64(&self) -> String {
-        base64::encode(&self.0[..])
+        match self.scheme {
+            Scheme::Ed25519 => base64::encode(&self.bytes[..]),
+            Scheme::Secp256k1 => base64::encode([&[SECP256K1_TAG][..], &self.bytes[..]].concat()),
+        }
     }
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..64]
+        let (scheme, bytes) = match bytes.len() {
+            64 => (Scheme::Ed25519, &bytes[..]),
+            65 if bytes[0] == SECP256K1_TAG => (Scheme::Secp256k1, &bytes[1..]),
+            _ => return Err(base64::DecodeError::InvalidLength),
+        };
+        let array = bytes
             .try_into()
             .map_err(|_| base64::DecodeError::InvalidLength)?;
-        Ok(Self(array))
+        Ok(Self {
+            scheme,
+            bytes: array,
+        })
+    }
+
+    /// Returns a copy of the key, e.g., to sign with it from several tasks. Secret keys are not
+    /// `Clone` so that copies are only made on purpose (each copy is wiped when dropped).
+    pub fn duplicate(&self) -> Self {
+        Self {
+            scheme: self.scheme,
+            bytes: self.bytes,
+        }
     }
 }
 
+/// The tag prefixing the encoding of secp256k1 secret keys.
+const SECP256K1_TAG: u8 = 1;
+
 impl Serialize for SecretKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -156,7 +195,7 @@ impl<'de> Deserialize<'de> for SecretKey {
 
 impl Drop for SecretKey {
     fn drop(&mut self) {
-        self.0.iter_mut().for_each(|x| *x = 0);
+        self.bytes.iter_mut().for_each(|x| *x = 0);
     }
 }
 
@@ -164,30 +203,93 @@ pub fn generate_production_keypair() -> (PublicKey, SecretKey) {
     generate_keypair(&mut OsRng)
 }
 
+/// Deterministically derive an ed25519 keypair from a seed. The same seed gives the same keypair
+/// across runs and platforms. Only use this for tests and local benchmarks.
+pub fn generate_keypair_from_seed(seed: [u8; 32]) -> (PublicKey, SecretKey) {
+    generate_keypair(&mut ChaCha20Rng::from_seed(seed))
+}
+
 pub fn generate_keypair<R>(csprng: &mut R) -> (PublicKey, SecretKey)
 where
     R: CryptoRng + RngCore,
 {
-    let keypair = dalek::Keypair::generate(csprng);
-    let public = PublicKey(keypair.public.to_bytes());
-    let secret = SecretKey(keypair.to_bytes());
-    (public, secret)
+    generate_keypair_with_scheme(Scheme::Ed25519, csprng)
+}
+
+/// Generate a keypair for a specific signature scheme. Panics if the scheme is not compiled in.
+pub fn generate_keypair_with_scheme<R>(scheme: Scheme, csprng: &mut R) -> (PublicKey, SecretKey)
+where
+    R: CryptoRng + RngCore,
+{
+    fn generate<S: SignatureScheme, R: CryptoRng + RngCore>(
+        csprng: &mut R,
+    ) -> (PublicKey, SecretKey) {
+        let (public, secret) = S::generate_keypair(csprng);
+        let public = PublicKey(S::public_key_to_bytes(&public));
+        let secret = SecretKey {
+            scheme: S::SCHEME,
+            bytes: S::secret_key_to_bytes(&secret),
+        };
+        (public, secret)
+    }
+
+    match scheme {
+        Scheme::Ed25519 => generate::<Ed25519, _>(csprng),
+        #[cfg(feature = "secp256k1")]
+        Scheme::Secp256k1 => generate::<Secp256k1, _>(csprng),
+        #[cfg(not(feature = "secp256k1"))]
+        Scheme::Secp256k1 => panic!("Signature scheme {} is not supported", scheme),
+    }
 }
 
-/// Represents an ed25519 signature.
+/// Represents a signature, tagged with the scheme that produced it.
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Signature {
+    scheme: Scheme,
     part1: [u8; 32],
     part2: [u8; 32],
 }
 
 impl Signature {
     pub fn new(digest: &Digest, secret: &SecretKey) -> Self {
-        let keypair = dalek::Keypair::from_bytes(&secret.0).expect("Unable to load secret key");
-        let sig = keypair.sign(&digest.0).to_bytes();
+        match secret.scheme {
+            Scheme::Ed25519 => Self::sign::<Ed25519>(digest, secret),
+            #[cfg(feature = "secp256k1")]
+            Scheme::Secp256k1 => Self::sign::<Secp256k1>(digest, secret),
+            #[cfg(not(feature = "secp256k1"))]
+            Scheme::Secp256k1 => panic!("Signature scheme {} is not supported", secret.scheme),
+        }
+    }
+
+    fn sign<S: SignatureScheme>(digest: &Digest, secret: &SecretKey) -> Self {
+        let secret = S::secret_key_from_bytes(&secret.bytes).expect("Unable to load secret key");
+        let sig = S::signature_to_bytes(&S::sign(digest, &secret));
         let part1 = sig[..32].try_into().expect("Unexpected signature length");
         let part2 = sig[32..64].try_into().expect("Unexpected signature length");
-        Signature { part1, part2 }
+        Signature {
+            scheme: S::SCHEME,
+            part1,
+            part2,
+        }
+    }
+
+    /// The scheme that produced this signature.
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// Makes a signature from its scheme and its bytes (e.g., as received in a protobuf message).
+    pub fn from_bytes(scheme: Scheme, bytes: &[u8; 64]) -> Self {
+        Self {
+            scheme,
+            part1: bytes[..32].try_into().expect("Unexpected signature length"),
+            part2: bytes[32..].try_into().expect("Unexpected signature length"),
+        }
+    }
+
+    /// The bytes of the signature (without its scheme).
+    pub fn to_bytes(&self) -> [u8; 64] {
+        self.flatten()
     }
 
     fn flatten(&self) -> [u8; 64] {
@@ -198,25 +300,148 @@ impl Signature {
     }
 
     pub fn verify(&self, digest: &Digest, public_key: &PublicKey) -> Result<(), CryptoError> {
-        let signature = ed25519::signature::Signature::from_bytes(&self.flatten())?;
-        let key = dalek::PublicKey::from_bytes(&public_key.0)?;
-        key.verify_strict(&digest.0, &signature)
+        match self.scheme {
+            Scheme::Ed25519 => self.verify_with::<Ed25519>(digest, public_key),
+            #[cfg(feature = "secp256k1")]
+            Scheme::Secp256k1 => self.verify_with::<Secp256k1>(digest, public_key),
+            #[cfg(not(feature = "secp256k1"))]
+            Scheme::Secp256k1 => Err(CryptoError::new()),
+        }
     }
 
+    fn verify_with<S: SignatureScheme>(
+        &self,
+        digest: &Digest,
+        public_key: &PublicKey,
+    ) -> Result<(), CryptoError> {
+        let signature = S::signature_from_bytes(&self.flatten())?;
+        let key = S::public_key_from_bytes(&public_key.0)?;
+        S::verify(digest, &key, &signature)
+    }
+
+    /// Verify many signatures over the same digest. All signatures must use the same scheme.
     pub fn verify_batch<'a, I>(digest: &Digest, votes: I) -> Result<(), CryptoError>
     where
         I: IntoIterator<Item = &'a (PublicKey, Signature)>,
     {
-        let mut messages: Vec<&[u8]> = Vec::new();
-        let mut signatures: Vec<dalek::Signature> = Vec::new();
-        let mut keys: Vec<dalek::PublicKey> = Vec::new();
-        for (key, sig) in votes.into_iter() {
-            messages.push(&digest.0[..]);
-            signatures.push(ed25519::signature::Signature::from_bytes(&sig.flatten())?);
-            keys.push(dalek::PublicKey::from_bytes(&key.0)?);
+        let votes: Vec<_> = votes.into_iter().collect();
+        let scheme = votes
+            .first()
+            .map_or_else(Scheme::default, |(_, x)| x.scheme);
+        if votes.iter().any(|(_, x)| x.scheme != scheme) {
+            return Err(CryptoError::new());
+        }
+        match scheme {
+            Scheme::Ed25519 => Self::verify_batch_with::<Ed25519>(digest, &votes),
+            #[cfg(feature = "secp256k1")]
+            Scheme::Secp256k1 => Self::verify_batch_with::<Secp256k1>(digest, &votes),
+            #[cfg(not(feature = "secp256k1"))]
+            Scheme::Secp256k1 => Err(CryptoError::new()),
+        }
+    }
+
+    fn verify_batch_with<S: SignatureScheme>(
+        digest: &Digest,
+        votes: &[&(PublicKey, Signature)],
+    ) -> Result<(), CryptoError> {
+        let votes = votes
+            .iter()
+            .map(|(key, sig)| {
+                Ok((
+                    S::public_key_from_bytes(&key.0)?,
+                    S::signature_from_bytes(&sig.flatten())?,
+                ))
+            })
+            .collect::<Result<Vec<_>, CryptoError>>()?;
+        S::verify_batch(digest, &votes)
+    }
+}
+
+/// Error returned when a batch verification fails. It holds the indices of the invalid signatures
+/// (in increasing order) so that callers can blame the right peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchVerifyError {
+    pub failed: Vec<usize>,
+}
+
+impl fmt::Display for BatchVerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Invalid signatures at indices {:?}", self.failed)
+    }
+}
+
+impl std::error::Error for BatchVerifyError {}
+
+/// Batches of at least this many signatures are verified on a blocking thread by
+/// `verify_batch_async`.
+pub const BLOCKING_BATCH_THRESHOLD: usize = 16;
+
+/// Verify many (digest, public key, signature) triples at once. If the batch is invalid, the
+/// signatures are checked one by one to identify the culprits.
+pub fn verify_batch<I>(items: I) -> Result<(), BatchVerifyError>
+where
+    I: IntoIterator<Item = (Digest, PublicKey, Signature)>,
+{
+    fn verify_many<S: SignatureScheme>(
+        items: &[(Digest, PublicKey, Signature)],
+    ) -> Result<(), CryptoError> {
+        let items = items
+            .iter()
+            .map(|(digest, key, sig)| {
+                Ok((
+                    digest.clone(),
+                    S::public_key_from_bytes(&key.0)?,
+                    S::signature_from_bytes(&sig.flatten())?,
+                ))
+            })
+            .collect::<Result<Vec<_>, CryptoError>>()?;
+        S::verify_many(&items)
+    }
+
+    let items: Vec<_> = items.into_iter().collect();
+    let scheme = match items.first() {
+        Some((_, _, signature)) => signature.scheme,
+        None => return Ok(()),
+    };
+
+    // Try the fast path: a single batch verification over all signatures.
+    if items.iter().all(|(_, _, x)| x.scheme == scheme) {
+        let result = match scheme {
+            Scheme::Ed25519 => verify_many::<Ed25519>(&items),
+            #[cfg(feature = "secp256k1")]
+            Scheme::Secp256k1 => verify_many::<Secp256k1>(&items),
+            #[cfg(not(feature = "secp256k1"))]
+            Scheme::Secp256k1 => Err(CryptoError::new()),
+        };
+        if result.is_ok() {
+            return Ok(());
         }
-        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
     }
+
+    // Otherwise find out which signatures are invalid.
+    let failed: Vec<_> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, (digest, key, signature))| signature.verify(digest, key).is_err())
+        .map(|(i, _)| i)
+        .collect();
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(BatchVerifyError { failed }),
+    }
+}
+
+/// Same as `verify_batch` but large batches are verified on a blocking thread to avoid stalling
+/// the async runtime.
+pub async fn verify_batch_async(
+    items: Vec<(Digest, PublicKey, Signature)>,
+) -> Result<(), BatchVerifyError> {
+    if items.len() < BLOCKING_BATCH_THRESHOLD {
+        return verify_batch(items);
+    }
+    tokio::task::spawn_blocking(move || verify_batch(items))
+        .await
+        .expect("Failed to run batch verification task")
 }
 
 /// This service holds the node's private key. It takes digests as input and returns a signature
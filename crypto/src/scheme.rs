@@ -0,0 +1,243 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{CryptoError, Digest};
+use ed25519_dalek as dalek;
+use ed25519_dalek::Signer as _;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "secp256k1")]
+use std::convert::TryFrom as _;
+use std::fmt;
+
+/// Identifies the signature scheme used by a key or a signature. All members of a committee
+/// must use the same scheme.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+impl Scheme {
+    /// Whether this binary was compiled with support for the scheme.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Ed25519 => true,
+            Self::Secp256k1 => cfg!(feature = "secp256k1"),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Ed25519 => write!(f, "ed25519"),
+            Self::Secp256k1 => write!(f, "secp256k1"),
+        }
+    }
+}
+
+/// A signature scheme usable to sign and verify digests. Every scheme must have 32-byte public
+/// keys and 64-byte signatures so that keys and signatures share the same wire representation.
+pub trait SignatureScheme {
+    /// The tag identifying the scheme.
+    const SCHEME: Scheme;
+
+    type PublicKey;
+    type SecretKey;
+    type Signature;
+
+    /// Generate a fresh keypair.
+    fn generate_keypair<R>(csprng: &mut R) -> (Self::PublicKey, Self::SecretKey)
+    where
+        R: CryptoRng + RngCore;
+
+    /// Sign a digest.
+    fn sign(digest: &Digest, secret: &Self::SecretKey) -> Self::Signature;
+
+    /// Verify a single signature over a digest.
+    fn verify(
+        digest: &Digest,
+        public_key: &Self::PublicKey,
+        signature: &Self::Signature,
+    ) -> Result<(), CryptoError>;
+
+    /// Verify many signatures over the same digest.
+    fn verify_batch(
+        digest: &Digest,
+        votes: &[(Self::PublicKey, Self::Signature)],
+    ) -> Result<(), CryptoError>;
+
+    /// Verify many signatures over different digests. Schemes without batch verification simply
+    /// verify the signatures one by one.
+    fn verify_many(
+        items: &[(Digest, Self::PublicKey, Self::Signature)],
+    ) -> Result<(), CryptoError> {
+        items
+            .iter()
+            .try_for_each(|(digest, key, signature)| Self::verify(digest, key, signature))
+    }
+
+    /// Conversions from and to the byte representations used on the wire.
+    fn public_key_from_bytes(bytes: &[u8; 32]) -> Result<Self::PublicKey, CryptoError>;
+    fn public_key_to_bytes(public_key: &Self::PublicKey) -> [u8; 32];
+    fn secret_key_from_bytes(bytes: &[u8; 64]) -> Result<Self::SecretKey, CryptoError>;
+    fn secret_key_to_bytes(secret: &Self::SecretKey) -> [u8; 64];
+    fn signature_from_bytes(bytes: &[u8; 64]) -> Result<Self::Signature, CryptoError>;
+    fn signature_to_bytes(signature: &Self::Signature) -> [u8; 64];
+}
+
+/// The default ed25519 signature scheme.
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    const SCHEME: Scheme = Scheme::Ed25519;
+
+    type PublicKey = dalek::PublicKey;
+    type SecretKey = dalek::Keypair;
+    type Signature = dalek::Signature;
+
+    fn generate_keypair<R>(csprng: &mut R) -> (Self::PublicKey, Self::SecretKey)
+    where
+        R: CryptoRng + RngCore,
+    {
+        let keypair = dalek::Keypair::generate(csprng);
+        (keypair.public, keypair)
+    }
+
+    fn sign(digest: &Digest, secret: &Self::SecretKey) -> Self::Signature {
+        secret.sign(&digest.0)
+    }
+
+    fn verify(
+        digest: &Digest,
+        public_key: &Self::PublicKey,
+        signature: &Self::Signature,
+    ) -> Result<(), CryptoError> {
+        public_key.verify_strict(&digest.0, signature)
+    }
+
+    fn verify_batch(
+        digest: &Digest,
+        votes: &[(Self::PublicKey, Self::Signature)],
+    ) -> Result<(), CryptoError> {
+        let messages: Vec<&[u8]> = votes.iter().map(|_| &digest.0[..]).collect();
+        let (keys, signatures): (Vec<_>, Vec<_>) = votes.iter().cloned().unzip();
+        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+    }
+
+    fn verify_many(
+        items: &[(Digest, Self::PublicKey, Self::Signature)],
+    ) -> Result<(), CryptoError> {
+        let messages: Vec<&[u8]> = items.iter().map(|(digest, _, _)| &digest.0[..]).collect();
+        let keys: Vec<_> = items.iter().map(|(_, key, _)| *key).collect();
+        let signatures: Vec<_> = items.iter().map(|(_, _, signature)| *signature).collect();
+        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+    }
+
+    fn public_key_from_bytes(bytes: &[u8; 32]) -> Result<Self::PublicKey, CryptoError> {
+        dalek::PublicKey::from_bytes(bytes)
+    }
+
+    fn public_key_to_bytes(public_key: &Self::PublicKey) -> [u8; 32] {
+        public_key.to_bytes()
+    }
+
+    fn secret_key_from_bytes(bytes: &[u8; 64]) -> Result<Self::SecretKey, CryptoError> {
+        dalek::Keypair::from_bytes(bytes)
+    }
+
+    fn secret_key_to_bytes(secret: &Self::SecretKey) -> [u8; 64] {
+        secret.to_bytes()
+    }
+
+    fn signature_from_bytes(bytes: &[u8; 64]) -> Result<Self::Signature, CryptoError> {
+        dalek::ed25519::signature::Signature::from_bytes(bytes)
+    }
+
+    fn signature_to_bytes(signature: &Self::Signature) -> [u8; 64] {
+        signature.to_bytes()
+    }
+}
+
+/// The secp256k1 signature scheme, using BIP-340 Schnorr signatures (32-byte x-only public keys
+/// and 64-byte signatures).
+#[cfg(feature = "secp256k1")]
+pub struct Secp256k1;
+
+#[cfg(feature = "secp256k1")]
+impl SignatureScheme for Secp256k1 {
+    const SCHEME: Scheme = Scheme::Secp256k1;
+
+    type PublicKey = k256::schnorr::VerifyingKey;
+    type SecretKey = k256::schnorr::SigningKey;
+    type Signature = k256::schnorr::Signature;
+
+    fn generate_keypair<R>(csprng: &mut R) -> (Self::PublicKey, Self::SecretKey)
+    where
+        R: CryptoRng + RngCore,
+    {
+        // Sample until we hit a valid scalar (this almost never loops).
+        loop {
+            let mut bytes = [0u8; 32];
+            csprng.fill_bytes(&mut bytes);
+            if let Ok(secret) = k256::schnorr::SigningKey::from_bytes(&bytes) {
+                return (*secret.verifying_key(), secret);
+            }
+        }
+    }
+
+    fn sign(digest: &Digest, secret: &Self::SecretKey) -> Self::Signature {
+        // Signatures are deterministic: the auxiliary randomness is fixed to zero.
+        secret
+            .sign_raw(&digest.0, &[0u8; 32])
+            .expect("Failed to sign digest")
+    }
+
+    fn verify(
+        digest: &Digest,
+        public_key: &Self::PublicKey,
+        signature: &Self::Signature,
+    ) -> Result<(), CryptoError> {
+        public_key
+            .verify_raw(&digest.0, signature)
+            .map_err(|_| CryptoError::new())
+    }
+
+    fn verify_batch(
+        digest: &Digest,
+        votes: &[(Self::PublicKey, Self::Signature)],
+    ) -> Result<(), CryptoError> {
+        votes
+            .iter()
+            .try_for_each(|(key, signature)| Self::verify(digest, key, signature))
+    }
+
+    fn public_key_from_bytes(bytes: &[u8; 32]) -> Result<Self::PublicKey, CryptoError> {
+        k256::schnorr::VerifyingKey::from_bytes(bytes).map_err(|_| CryptoError::new())
+    }
+
+    fn public_key_to_bytes(public_key: &Self::PublicKey) -> [u8; 32] {
+        public_key.to_bytes().into()
+    }
+
+    fn secret_key_from_bytes(bytes: &[u8; 64]) -> Result<Self::SecretKey, CryptoError> {
+        k256::schnorr::SigningKey::from_bytes(&bytes[..32]).map_err(|_| CryptoError::new())
+    }
+
+    fn secret_key_to_bytes(secret: &Self::SecretKey) -> [u8; 64] {
+        // The secret scalar followed by the public key, mirroring the ed25519 keypair layout.
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&secret.to_bytes());
+        bytes[32..].copy_from_slice(&secret.verifying_key().to_bytes());
+        bytes
+    }
+
+    fn signature_from_bytes(bytes: &[u8; 64]) -> Result<Self::Signature, CryptoError> {
+        k256::schnorr::Signature::try_from(&bytes[..]).map_err(|_| CryptoError::new())
+    }
+
+    fn signature_to_bytes(signature: &Self::Signature) -> [u8; 64] {
+        signature.to_bytes()
+    }
+}
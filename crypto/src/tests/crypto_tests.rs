@@ -3,17 +3,16 @@ use super::*;
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use rand::rngs::StdRng;
-use rand::SeedableRng as _;
 
 impl Hash for &[u8] {
     fn digest(&self) -> Digest {
-        Digest(Sha512::digest(self).as_slice()[..32].try_into().unwrap())
+        hash(self)
     }
 }
 
 impl PartialEq for SecretKey {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.scheme == other.scheme && self.bytes == other.bytes
     }
 }
 
@@ -28,6 +27,14 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
 
+#[cfg(feature = "secp256k1")]
+pub fn secp256k1_keys() -> Vec<(PublicKey, SecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4)
+        .map(|_| generate_keypair_with_scheme(Scheme::Secp256k1, &mut rng))
+        .collect()
+}
+
 #[test]
 fn import_export_public_key() {
     let (public_key, _) = keys().pop().unwrap();
@@ -130,3 +137,166 @@ async fn signature_service() {
     // Verify the signature we received.
     assert!(signature.verify(&digest, &public_key).is_ok());
 }
+
+#[test]
+fn signature_carries_scheme() {
+    let (_, secret_key) = keys().pop().unwrap();
+    let message: &[u8] = b"Hello, world!";
+    let signature = Signature::new(&message.digest(), &secret_key);
+    assert_eq!(signature.scheme(), Scheme::Ed25519);
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn import_export_secp256k1_secret_key() {
+    let (_, secret_key) = secp256k1_keys().pop().unwrap();
+    let export = secret_key.encode_base64();
+    let import = SecretKey::decode_base64(&export);
+    assert!(import.is_ok());
+    let import = import.unwrap();
+    assert_eq!(import.scheme(), Scheme::Secp256k1);
+    assert_eq!(import, secret_key);
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn verify_valid_secp256k1_signature() {
+    let (public_key, secret_key) = secp256k1_keys().pop().unwrap();
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = Signature::new(&digest, &secret_key);
+    assert_eq!(signature.scheme(), Scheme::Secp256k1);
+    assert!(signature.verify(&digest, &public_key).is_ok());
+
+    let bad_message: &[u8] = b"Bad message!";
+    assert!(signature
+        .verify(&bad_message.digest(), &public_key)
+        .is_err());
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn verify_valid_secp256k1_batch() {
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signatures: Vec<_> = secp256k1_keys()
+        .into_iter()
+        .map(|(public_key, secret_key)| (public_key, Signature::new(&digest, &secret_key)))
+        .collect();
+    assert!(Signature::verify_batch(&digest, &signatures).is_ok());
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn reject_mixed_scheme_batch() {
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let mut signatures: Vec<_> = keys()
+        .into_iter()
+        .take(2)
+        .map(|(public_key, secret_key)| (public_key, Signature::new(&digest, &secret_key)))
+        .collect();
+    let (public_key, secret_key) = secp256k1_keys().pop().unwrap();
+    signatures.push((public_key, Signature::new(&digest, &secret_key)));
+    assert!(Signature::verify_batch(&digest, &signatures).is_err());
+}
+
+#[cfg(feature = "secp256k1")]
+#[test]
+fn reject_cross_scheme_signature() {
+    // An ed25519 signature does not verify under a secp256k1 key, and vice versa.
+    let (ed_public_key, ed_secret_key) = keys().pop().unwrap();
+    let (secp_public_key, secp_secret_key) = secp256k1_keys().pop().unwrap();
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let ed_signature = Signature::new(&digest, &ed_secret_key);
+    let secp_signature = Signature::new(&digest, &secp_secret_key);
+    assert!(ed_signature.verify(&digest, &secp_public_key).is_err());
+    assert!(secp_signature.verify(&digest, &ed_public_key).is_err());
+}
+
+// Fixture
+fn triples(n: usize) -> Vec<(Digest, PublicKey, Signature)> {
+    let mut rng = StdRng::from_seed([1; 32]);
+    (0..n)
+        .map(|i| {
+            let (public_key, secret_key) = generate_keypair(&mut rng);
+            let message = format!("Message {}", i);
+            let digest = message.as_bytes().digest();
+            let signature = Signature::new(&digest, &secret_key);
+            (digest, public_key, signature)
+        })
+        .collect()
+}
+
+#[test]
+fn verify_valid_multi_message_batch() {
+    assert!(verify_batch(triples(8)).is_ok());
+    assert!(verify_batch(Vec::new()).is_ok());
+}
+
+#[test]
+fn verify_invalid_multi_message_batch() {
+    // Corrupt two signatures of the batch.
+    let mut items = triples(8);
+    items[2].2 = Signature::default();
+    let digest = items[0].0.clone();
+    items[5].0 = digest;
+
+    // Ensure we identify the invalid signatures.
+    match verify_batch(items) {
+        Err(BatchVerifyError { failed }) => assert_eq!(failed, vec![2, 5]),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[tokio::test]
+async fn verify_batch_on_blocking_thread() {
+    let mut items = triples(BLOCKING_BATCH_THRESHOLD + 1);
+    assert!(verify_batch_async(items.clone()).await.is_ok());
+
+    items[0].2 = Signature::default();
+    let result = verify_batch_async(items).await;
+    assert_eq!(result, Err(BatchVerifyError { failed: vec![0] }));
+}
+
+#[test]
+fn sha512_digest() {
+    let message: &[u8] = b"Hello, world!";
+    let mut hasher = Hasher::with_algorithm(HashAlgorithm::Sha512);
+    hasher.update(message);
+    let expected = Digest(Sha512::digest(message)[..32].try_into().unwrap());
+    assert_eq!(hasher.finalize(), expected);
+}
+
+#[cfg(feature = "hash-blake3")]
+#[test]
+fn blake3_digest() {
+    let message: &[u8] = b"Hello, world!";
+    let mut hasher = Hasher::with_algorithm(HashAlgorithm::Blake3);
+    hasher.update(message);
+    let expected = Digest(*blake3::hash(message).as_bytes());
+    assert_eq!(hasher.finalize(), expected);
+}
+
+#[test]
+fn incremental_digest() {
+    let mut hasher = Hasher::new();
+    hasher.update(b"Hello, ");
+    hasher.update(b"world!");
+    assert_eq!(hasher.finalize(), hash(b"Hello, world!"));
+}
+
+#[test]
+fn compiled_algorithm() {
+    let algorithm = HashAlgorithm::compiled();
+    assert!(algorithm.is_supported());
+    assert_eq!(
+        algorithm == HashAlgorithm::Blake3,
+        cfg!(feature = "hash-blake3")
+    );
+
+    let mut hasher = Hasher::with_algorithm(algorithm);
+    hasher.update(b"Hello, world!");
+    assert_eq!(hash(b"Hello, world!"), hasher.finalize());
+}
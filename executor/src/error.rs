@@ -0,0 +1,30 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::WorkerId;
+use crypto::Digest;
+use store::StoreError;
+use thiserror::Error;
+
+pub type ExecutorResult<T> = Result<T, ExecutorError>;
+
+/// The failures to gather the content of a committed sub-dag. They are transient: the executor
+/// retries until it succeeds.
+#[derive(Debug, Error)]
+pub enum ExecutorError {
+    #[error("Storage failure: {0}")]
+    StoreError(#[from] StoreError),
+
+    #[error("Failed to access the store of worker {0}: {1}")]
+    WorkerStoreError(WorkerId, rocksdb::Error),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] Box<bincode::ErrorKind>),
+
+    #[error("Missing certificate {0}")]
+    MissingCertificate(Digest),
+
+    #[error("Missing batch {0} (worker {1})")]
+    MissingBatch(Digest, WorkerId),
+
+    #[error("Unexpected message instead of batch {0}")]
+    UnexpectedMessage(Digest),
+}
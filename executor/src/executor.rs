@@ -0,0 +1,230 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{ExecutorError, ExecutorResult};
+use crate::{CommittedBatch, CommittedSubDag, ExecutionState};
+use config::WorkerId;
+use consensus::SubDag;
+use crypto::{Digest, Hash as _};
+use log::{debug, info, warn};
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use store::{Database, Family, Store};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use worker::WorkerMessage;
+
+#[cfg(test)]
+#[path = "tests/executor_tests.rs"]
+pub mod executor_tests;
+
+/// The delay before the first retry to deliver a sub-dag (in ms). It doubles after each failure.
+const RETRY_DELAY: u64 = 100;
+
+/// The maximum delay between two attempts to deliver a sub-dag (in ms).
+const MAX_RETRY_DELAY: u64 = 10_000;
+
+/// A committed sub-dag, as we persist it until it is executed. Its certificates are in the store
+/// of the primary, and its batches in the stores of the workers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SubDagEntry {
+    /// The round of the leader.
+    round: Round,
+    /// The digests of the certificates of the sub-dag, in commit order (the leader is last).
+    certificates: Vec<Digest>,
+}
+
+/// Drives an `ExecutionState` with the output of consensus. Committed sub-dags are numbered from 1
+/// and persisted under their index before being executed, so that delivery resumes after a crash
+/// from the index following the last one the state executed. Each sub-dag is delivered along with
+/// the content of its batches, read from the stores of the workers of this primary.
+pub struct Executor<State> {
+    /// The state machine we drive.
+    state: Arc<State>,
+    /// The committed sub-dags, by index.
+    sub_dags: Store<u64, SubDagEntry>,
+    /// The index of the last executed sub-dag (the family holds a single entry).
+    executed: Store<(), u64>,
+    /// The certificates of the primary.
+    certificates: Store<Digest, Certificate>,
+    /// The path of the store of the primary; the stores of its workers are derived from it.
+    store_path: String,
+}
+
+impl<State: ExecutionState> Executor<State> {
+    /// Spawns the executor. Aborting the returned task stops the delivery (as a crash would).
+    pub fn spawn(
+        state: Arc<State>,
+        store: &Database,
+        store_path: &str,
+        rx_sub_dags: Receiver<SubDag>,
+    ) -> JoinHandle<()> {
+        let executor = Self {
+            state,
+            sub_dags: store.store(Family::SubDags),
+            executed: store.store(Family::Execution),
+            certificates: store.store(Family::Certificates),
+            store_path: store_path.to_string(),
+        };
+        tokio::spawn(async move {
+            tokio::join!(executor.record(rx_sub_dags), executor.deliver());
+        })
+    }
+
+    /// Persists the sub-dags output by consensus under consecutive indices.
+    async fn record(&self, mut rx_sub_dags: Receiver<SubDag>) {
+        let mut sub_dags = self.sub_dags.clone();
+
+        // Find the next free index. Indices are contiguous, and all indices up to the executed
+        // ones are taken (the state may be one sub-dag ahead of our record of its progress).
+        let executed = match self.executed.clone().read(&()).await {
+            Ok(index) => index.unwrap_or_default(),
+            Err(e) => panic!("Failed to read the executed index: {}", e),
+        };
+        let mut next = executed.max(self.state.last_executed_index()) + 1;
+        while let Ok(Some(_)) = sub_dags.read(&next).await {
+            next += 1;
+        }
+
+        // After a restart, consensus may output again the sub-dags we already recorded.
+        let mut last_round = match next {
+            1 => None,
+            _ => sub_dags.read(&(next - 1)).await.ok().flatten(),
+        }
+        .map(|x| x.round);
+
+        while let Some(sub_dag) = rx_sub_dags.recv().await {
+            let round = sub_dag.leader.round();
+            if last_round.is_some_and(|x| round <= x) {
+                debug!("Skipping sub-dag of round {} (already recorded)", round);
+                continue;
+            }
+            last_round = Some(round);
+
+            let entry = SubDagEntry {
+                round,
+                certificates: sub_dag.certificates.iter().map(|x| x.digest()).collect(),
+            };
+            sub_dags.write(&next, &entry).await;
+            next += 1;
+        }
+    }
+
+    /// Delivers the recorded sub-dags to the state, in order, as they are recorded.
+    async fn deliver(&self) {
+        let mut sub_dags = self.sub_dags.clone();
+        let mut secondaries = HashMap::new();
+        let mut next = self.state.last_executed_index() + 1;
+        info!("Executing the committed sub-dags from index {}", next);
+        loop {
+            let entry = match sub_dags.notify_read(&next).await {
+                Ok(entry) => entry,
+                Err(e) => panic!("Failed to read sub-dag {}: {}", next, e),
+            };
+
+            // Gather the content of the sub-dag, then execute it. We never skip a sub-dag: we
+            // retry until both succeed.
+            let mut delay = RETRY_DELAY;
+            let output = loop {
+                match self.load(next, &entry, &mut secondaries).await {
+                    Ok(output) => break output,
+                    Err(e) => warn!("Failed to load sub-dag {}: {}", next, e),
+                }
+                sleep(Duration::from_millis(delay)).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            };
+            let mut delay = RETRY_DELAY;
+            while let Err(e) = self.state.handle_consensus_output(output.clone()).await {
+                warn!("Failed to execute sub-dag {}: {}", next, e);
+                sleep(Duration::from_millis(delay)).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+
+            debug!("Executed sub-dag {}", next);
+            self.executed.clone().write(&(), &next).await;
+            next += 1;
+        }
+    }
+
+    /// Reads the certificates of a sub-dag and the content of their batches.
+    async fn load(
+        &self,
+        index: u64,
+        entry: &SubDagEntry,
+        secondaries: &mut HashMap<WorkerId, rocksdb::DB>,
+    ) -> ExecutorResult<CommittedSubDag> {
+        let mut certificates = Vec::with_capacity(entry.certificates.len());
+        for digest in &entry.certificates {
+            let certificate = self
+                .certificates
+                .clone()
+                .read(digest)
+                .await?
+                .ok_or_else(|| ExecutorError::MissingCertificate(digest.clone()))?;
+            certificates.push(certificate);
+        }
+
+        let mut batches = Vec::new();
+        for certificate in &certificates {
+            for (digest, worker_id) in &certificate.header.payload {
+                let transactions = self.read_batch(digest, *worker_id, secondaries)?;
+                batches.push(CommittedBatch {
+                    digest: digest.clone(),
+                    worker_id: *worker_id,
+                    transactions,
+                });
+            }
+        }
+
+        Ok(CommittedSubDag {
+            index,
+            leader: certificates
+                .last()
+                .cloned()
+                .expect("Sub-dags end with their leader"),
+            certificates,
+            batches,
+        })
+    }
+
+    /// Reads a batch from the store of a worker, opened as a secondary instance (and kept open).
+    fn read_batch(
+        &self,
+        digest: &Digest,
+        worker_id: WorkerId,
+        secondaries: &mut HashMap<WorkerId, rocksdb::DB>,
+    ) -> ExecutorResult<Vec<bytes::Bytes>> {
+        let error = |e| ExecutorError::WorkerStoreError(worker_id, e);
+        let secondary = match secondaries.get(&worker_id) {
+            Some(secondary) => secondary,
+            None => {
+                let path = format!("{}-{}", self.store_path, worker_id);
+                let secondary_path = format!("{}-executor-secondary", path);
+                let secondary = rocksdb::DB::open_cf_as_secondary(
+                    &rocksdb::Options::default(),
+                    &path,
+                    &secondary_path,
+                    [Family::Batches.name()],
+                )
+                .map_err(error)?;
+                secondaries.entry(worker_id).or_insert(secondary)
+            }
+        };
+        secondary.try_catch_up_with_primary().map_err(error)?;
+        let family = secondary
+            .cf_handle(Family::Batches.name())
+            .expect("Column family opened with the store");
+        let value = secondary
+            .get_cf(family, digest.to_vec())
+            .map_err(error)?
+            .ok_or_else(|| ExecutorError::MissingBatch(digest.clone(), worker_id))?;
+
+        // Workers store the serialization of the message that carried the batch.
+        let serialized: Vec<u8> = bincode::deserialize(&value)?;
+        match bincode::deserialize(&serialized)? {
+            WorkerMessage::Batch(transactions) => Ok(transactions),
+            _ => Err(ExecutorError::UnexpectedMessage(digest.clone())),
+        }
+    }
+}
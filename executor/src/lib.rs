@@ -0,0 +1,50 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+mod error;
+mod executor;
+
+pub use crate::error::{ExecutorError, ExecutorResult};
+pub use crate::executor::Executor;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use config::WorkerId;
+use crypto::Digest;
+use primary::Certificate;
+
+/// A batch of a committed sub-dag, along with its transactions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedBatch {
+    /// The digest of the batch.
+    pub digest: Digest,
+    /// The worker holding the batch.
+    pub worker_id: WorkerId,
+    /// The transactions of the batch, in batch order.
+    pub transactions: Vec<Bytes>,
+}
+
+/// The certificates committed along with a leader, and the content of their batches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedSubDag {
+    /// The index of the sub-dag in the committed sequence (the first sub-dag has index 1).
+    pub index: u64,
+    /// The committed leader.
+    pub leader: Certificate,
+    /// The certificates committed with the leader (including it), in commit order.
+    pub certificates: Vec<Certificate>,
+    /// The batches of the certificates, in commit order.
+    pub batches: Vec<CommittedBatch>,
+}
+
+/// The state machine driven by the committed sequence. The `Executor` hands it every committed
+/// sub-dag in order, exactly once provided that the state persists the index of the last sub-dag
+/// it executed atomically with the effects of that sub-dag.
+#[async_trait]
+pub trait ExecutionState: Send + Sync + 'static {
+    /// Executes a committed sub-dag. Errors leave the state as it was before the call: the
+    /// executor then delivers the same sub-dag again (after a delay) rather than skipping it.
+    async fn handle_consensus_output(&self, output: CommittedSubDag) -> anyhow::Result<()>;
+
+    /// The index of the last sub-dag the state executed (0 if none). Delivery resumes from the
+    /// next one, e.g., after a crash.
+    fn last_executed_index(&self) -> u64;
+}
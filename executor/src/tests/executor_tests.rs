@@ -0,0 +1,182 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use async_trait::async_trait;
+use bytes::Bytes;
+use crypto::PublicKey;
+use primary::Header;
+use std::fs;
+use std::sync::Mutex;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+
+// Fixture
+/// A state machine counting the transactions it executes. Its count and the index of the last
+/// sub-dag it executed are committed atomically.
+#[derive(Default)]
+struct CountingState {
+    /// The number of executed transactions and the index of the last executed sub-dag.
+    committed: Mutex<(u64, u64)>,
+    /// The indices of the sub-dags handed to the state, in order.
+    delivered: Mutex<Vec<u64>>,
+    /// The indices of the sub-dags the state executed, in order.
+    executed: Mutex<Vec<u64>>,
+    /// Crashes after executing this many transactions of the sub-dag of this index.
+    crash: Mutex<Option<(u64, usize)>>,
+}
+
+#[async_trait]
+impl ExecutionState for CountingState {
+    async fn handle_consensus_output(&self, output: CommittedSubDag) -> anyhow::Result<()> {
+        self.delivered.lock().unwrap().push(output.index);
+        let (mut count, _) = *self.committed.lock().unwrap();
+        let transactions = output.batches.iter().flat_map(|x| &x.transactions);
+        for (i, _) in transactions.enumerate() {
+            if *self.crash.lock().unwrap() == Some((output.index, i)) {
+                anyhow::bail!("Crashed while executing sub-dag {}", output.index);
+            }
+            count += 1;
+        }
+        *self.committed.lock().unwrap() = (count, output.index);
+        self.executed.lock().unwrap().push(output.index);
+        Ok(())
+    }
+
+    fn last_executed_index(&self) -> u64 {
+        self.committed.lock().unwrap().1
+    }
+}
+
+// Fixture
+async fn executed(state: &CountingState, index: u64) {
+    let wait = async {
+        while state.last_executed_index() < index {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("Timed out waiting for the execution");
+}
+
+// Fixture
+async fn delivered(state: &CountingState, indices: &[u64]) {
+    let wait = async {
+        while *state.delivered.lock().unwrap() != indices {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("Timed out waiting for the delivery");
+}
+
+// Fixture
+/// Makes the sub-dags of the leaders of rounds 2, 4, ..., each with two certificates holding a
+/// batch of `round` transactions. The certificates are in the store of the primary, and the
+/// batches in the store of worker 0 (under `path-0`).
+async fn sub_dags(store: &Database, path: &str, leaders: usize) -> Vec<SubDag> {
+    let worker_store = Database::open(&format!("{}-0", path)).unwrap();
+    let mut batches = worker_store.store::<Digest, Vec<u8>>(Family::Batches);
+    let mut certificates = store.store::<Digest, Certificate>(Family::Certificates);
+    let mut sub_dags = Vec::new();
+    for round in (1..=2 * leaders as Round).filter(|x| x % 2 == 0) {
+        let mut sub_dag = Vec::new();
+        for (author, round) in [(1, round - 1), (2, round)] {
+            let transactions = (0..round).map(|x| Bytes::from(vec![x as u8])).collect();
+            let serialized = bincode::serialize(&WorkerMessage::Batch(transactions)).unwrap();
+            let digest = Digest([round as u8; 32]);
+            batches.write(&digest, &serialized).await;
+
+            let certificate = Certificate {
+                header: Header {
+                    author: PublicKey([author; 32]),
+                    round,
+                    payload: [(digest, 0)].iter().cloned().collect(),
+                    ..Header::default()
+                },
+                ..Certificate::default()
+            };
+            certificates
+                .write(&certificate.digest(), &certificate)
+                .await;
+            sub_dag.push(certificate);
+        }
+        sub_dags.push(SubDag {
+            leader: sub_dag.last().cloned().unwrap(),
+            certificates: sub_dag,
+        });
+    }
+    worker_store.flush().await.unwrap();
+    sub_dags
+}
+
+// Fixture
+fn clean(path: &str) {
+    let _ = fs::remove_dir_all(format!("{}-0", path));
+    let _ = fs::remove_dir_all(format!("{}-0-executor-secondary", path));
+}
+
+#[tokio::test]
+async fn pause_delivery_on_failure() {
+    let path = ".db_test_executor_pause";
+    clean(path);
+    let store = Database::new_in_memory();
+    let sub_dags = sub_dags(&store, path, 3).await;
+
+    // The state fails in the middle of the second sub-dag.
+    let state = Arc::new(CountingState::default());
+    *state.crash.lock().unwrap() = Some((2, 5));
+    let (tx_sub_dags, rx_sub_dags) = channel(10);
+    Executor::spawn(state.clone(), &store, path, rx_sub_dags);
+    for sub_dag in sub_dags {
+        tx_sub_dags.send(sub_dag).await.unwrap();
+    }
+
+    // The sub-dags are delivered in order, with the content of their batches.
+    delivered(&state, &[1, 2]).await;
+    assert_eq!(*state.committed.lock().unwrap(), (1 + 2, 1));
+
+    // Delivery pauses until the state recovers, rather than skipping the sub-dag.
+    sleep(Duration::from_millis(RETRY_DELAY * 3)).await;
+    assert!(state.delivered.lock().unwrap().iter().all(|x| *x <= 2));
+    assert_eq!(state.last_executed_index(), 1);
+    *state.crash.lock().unwrap() = None;
+
+    // Every sub-dag is then executed exactly once.
+    executed(&state, 3).await;
+    assert_eq!(*state.executed.lock().unwrap(), vec![1, 2, 3]);
+    assert_eq!(*state.committed.lock().unwrap(), (1 + 2 + 3 + 4 + 5 + 6, 3));
+}
+
+#[tokio::test]
+async fn resume_after_crash() {
+    let path = ".db_test_executor_resume";
+    clean(path);
+    let store = Database::new_in_memory();
+    let sub_dags = sub_dags(&store, path, 4).await;
+
+    // The node crashes in the middle of the second sub-dag.
+    let state = Arc::new(CountingState::default());
+    *state.crash.lock().unwrap() = Some((2, 5));
+    let (tx_sub_dags, rx_sub_dags) = channel(10);
+    let executor = Executor::spawn(state.clone(), &store, path, rx_sub_dags);
+    for sub_dag in &sub_dags[..3] {
+        tx_sub_dags.send(sub_dag.clone()).await.unwrap();
+    }
+    delivered(&state, &[1, 2]).await;
+    executor.abort();
+    let _ = executor.await;
+    *state.crash.lock().unwrap() = None;
+
+    // After the restart, consensus outputs again some of the sub-dags it committed before the
+    // crash. Delivery resumes after the last sub-dag the state executed, exactly once.
+    let (tx_sub_dags, rx_sub_dags) = channel(10);
+    Executor::spawn(state.clone(), &store, path, rx_sub_dags);
+    for sub_dag in &sub_dags[1..] {
+        tx_sub_dags.send(sub_dag.clone()).await.unwrap();
+    }
+    executed(&state, 4).await;
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(*state.executed.lock().unwrap(), vec![1, 2, 3, 4]);
+    assert_eq!(*state.committed.lock().unwrap(), ((1..=8).sum::<u64>(), 4));
+}
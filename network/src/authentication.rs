@@ -0,0 +1,292 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use crate::receiver::{Reader, Writer};
+use bytes::{Bytes, BytesMut};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use rand::rngs::OsRng;
+use std::convert::TryInto as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+use x25519_dalek::{EphemeralSecret, PublicKey as EphemeralKey};
+
+#[cfg(test)]
+#[path = "tests/authentication_tests.rs"]
+pub mod authentication_tests;
+
+/// The prefix of the frames of the authentication handshake, which precedes the other banners of
+/// the connection. Like them, its leading `0xff` byte ensures it cannot be confused with a
+/// bincode-serialized message.
+const BANNER: &[u8] = b"\xffnarwhal/auth";
+
+/// The size (in bytes) of the ephemeral (X25519) keys of the handshake.
+const KEY_SIZE: usize = 32;
+
+/// The size (in bytes) of the tag authenticating each frame of an authenticated connection.
+const MAC_SIZE: usize = 32;
+
+/// Tags of the sides of the handshake in the transcripts they sign, so that the proof of one side
+/// cannot be replayed as the proof of the other.
+const INITIATOR: u8 = 0;
+const RESPONDER: u8 = 1;
+
+/// The default delay after which we give up on a handshake (see `Authenticator::timeout`).
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Proves our identity to the peers of our connections, and checks theirs. Senders with an
+/// authenticator open their connections with a mutual handshake: each side picks an ephemeral
+/// Diffie-Hellman key and signs both, so proofs cannot be replayed over another connection. Both
+/// sides then derive the keys of the session from the shared secret, and authenticate every frame
+/// that follows with them: frames injected (or altered) by someone on the network path close the
+/// connection. Receivers with an authenticator answer the handshake and hand the identity of the
+/// peer to their handler (see `MessageHandler::authenticated`); receivers without one decline it.
+pub trait Authenticator: Send + Sync + 'static {
+    /// Returns our identity along with our signature of the transcript.
+    fn prove(&self, transcript: &[u8]) -> Bytes;
+
+    /// Checks the proof of a peer over the transcript. Returns the identity of the peer if the
+    /// proof is valid and the peer is one we talk to.
+    fn verify(&self, transcript: &[u8], proof: &[u8]) -> Option<Bytes>;
+
+    /// The identity of the peer we expect at this address (if we know it). We do not send anything
+    /// to a peer proving another identity, nor to addresses we do not know.
+    fn expected(&self, address: &SocketAddr) -> Option<Bytes>;
+
+    /// How long the handshake may take before we give up on the connection.
+    fn timeout(&self) -> Duration {
+        HANDSHAKE_TIMEOUT
+    }
+}
+
+/// An authenticator shared by the connections of a sender or receiver.
+pub type SharedAuthenticator = Arc<dyn Authenticator>;
+
+/// The keys authenticating the frames of a connection, one per direction.
+struct SessionKeys {
+    send: [u8; 32],
+    receive: [u8; 32],
+}
+
+/// The keys of the session of a connection, once its handshake completes. The codec of the
+/// connection shares them with the handshake, which sets them.
+#[derive(Clone, Default)]
+pub(crate) struct Session(Arc<OnceLock<SessionKeys>>);
+
+impl Session {
+    /// Derives the keys of the session from the shared secret and the ephemeral keys of both sides.
+    fn establish(&self, side: u8, shared: &[u8; 32], initiator: &[u8], responder: &[u8]) {
+        let material = [&shared[..], initiator, responder].concat();
+        let initiator = blake3::derive_key("narwhal/auth initiator frames", &material);
+        let responder = blake3::derive_key("narwhal/auth responder frames", &material);
+        let (send, receive) = match side {
+            INITIATOR => (initiator, responder),
+            _ => (responder, initiator),
+        };
+        let _ = self.0.set(SessionKeys { send, receive });
+    }
+}
+
+/// The codec of the connections of the receivers and senders: length-delimited frames. Once the
+/// authentication handshake of the connection completes, each frame carries a tag authenticating
+/// its content and its position in the stream (so frames cannot be injected, dropped, or
+/// replayed). Connections without handshake exchange plain length-delimited frames.
+#[derive(Default)]
+pub struct FrameCodec {
+    inner: LengthDelimitedCodec,
+    session: Session,
+    /// The number of authenticated frames we sent.
+    sent: u64,
+    /// The number of authenticated frames we received.
+    received: u64,
+}
+
+impl FrameCodec {
+    pub(crate) fn new(session: Session) -> Self {
+        Self {
+            session,
+            ..Self::default()
+        }
+    }
+}
+
+/// Tags the frame at this position of the stream.
+fn mac(key: &[u8; 32], position: u64, frame: &[u8]) -> blake3::Hash {
+    blake3::Hasher::new_keyed(key)
+        .update(&position.to_le_bytes())
+        .update(frame)
+        .finalize()
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let keys = match self.session.0.get() {
+            Some(keys) => keys,
+            None => return self.inner.encode(frame, dst),
+        };
+        let tag = mac(&keys.send, self.sent, &frame);
+        self.sent += 1;
+        let mut tagged = BytesMut::with_capacity(frame.len() + MAC_SIZE);
+        tagged.extend_from_slice(&frame);
+        tagged.extend_from_slice(tag.as_bytes());
+        self.inner.encode(tagged.freeze(), dst)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let mut frame = match self.inner.decode(src)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let keys = match self.session.0.get() {
+            Some(keys) => keys,
+            None => return Ok(Some(frame)),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid frame tag");
+        if frame.len() < MAC_SIZE {
+            return Err(invalid());
+        }
+        let tag: [u8; MAC_SIZE] = frame.split_off(frame.len() - MAC_SIZE)[..]
+            .try_into()
+            .map_err(|_| invalid())?;
+        // Comparing `blake3::Hash`es takes constant time.
+        if mac(&keys.receive, self.received, &frame) != blake3::Hash::from(tag) {
+            return Err(invalid());
+        }
+        self.received += 1;
+        Ok(Some(frame))
+    }
+}
+
+/// Makes a frame of the handshake.
+fn frame(parts: &[&[u8]]) -> Bytes {
+    let mut frame = BANNER.to_vec();
+    for part in parts {
+        frame.extend_from_slice(part);
+    }
+    Bytes::from(frame)
+}
+
+/// Returns what one side of the handshake signs.
+fn transcript(side: u8, initiator_key: &[u8], responder_key: &[u8]) -> Vec<u8> {
+    [BANNER, &[side][..], initiator_key, responder_key].concat()
+}
+
+/// Parses a frame of the handshake. Returns `None` if the frame is not one, and its content
+/// otherwise.
+pub(crate) fn parse_banner(frame: &[u8]) -> Option<&[u8]> {
+    frame.strip_prefix(BANNER)
+}
+
+/// Computes the secret we share with the peer. Returns `None` if the key of the peer is not one
+/// (or a degenerate one).
+fn shared_secret(secret: EphemeralSecret, peer_key: &[u8]) -> Option<[u8; 32]> {
+    let peer_key: [u8; KEY_SIZE] = peer_key.try_into().ok()?;
+    let shared = secret
+        .diffie_hellman(&EphemeralKey::from(peer_key))
+        .to_bytes();
+    (shared != [0; 32]).then_some(shared)
+}
+
+/// Authenticates the peer of a connection we opened, and proves our identity to it. The peer must
+/// prove the identity we expect of it (if we expect none, we do not talk to it). Once this
+/// returns, the frames of the connection are authenticated with the keys of the session. Returns
+/// the identity of the peer.
+pub(crate) async fn initiate(
+    writer: &mut Writer,
+    reader: &mut Reader,
+    session: &Session,
+    address: SocketAddr,
+    authenticator: &SharedAuthenticator,
+    expected: Option<&[u8]>,
+) -> Result<Bytes, NetworkError> {
+    let expected = expected.ok_or(NetworkError::FailedToAuthenticate(address))?;
+    let handshake = async {
+        let secret = EphemeralSecret::new(OsRng);
+        let key = EphemeralKey::from(&secret).to_bytes();
+        writer
+            .send(frame(&[&key[..]]))
+            .await
+            .map_err(|e| NetworkError::FailedToSendMessage(address, e))?;
+
+        // The peer replies with its key and its proof (or with an empty frame if it declines).
+        let reply = match reader.next().await {
+            Some(Ok(reply)) => reply,
+            _ => return Err(NetworkError::FailedToAuthenticate(address)),
+        };
+        let (peer_key, proof) = parse_banner(&reply)
+            .filter(|x| x.len() > KEY_SIZE)
+            .map(|x| x.split_at(KEY_SIZE))
+            .ok_or(NetworkError::FailedToAuthenticate(address))?;
+        let identity = authenticator
+            .verify(&transcript(RESPONDER, &key, peer_key), proof)
+            .filter(|identity| identity[..] == *expected)
+            .ok_or(NetworkError::FailedToAuthenticate(address))?;
+        let shared =
+            shared_secret(secret, peer_key).ok_or(NetworkError::FailedToAuthenticate(address))?;
+
+        let proof = authenticator.prove(&transcript(INITIATOR, &key, peer_key));
+        writer
+            .send(frame(&[&proof[..]]))
+            .await
+            .map_err(|e| NetworkError::FailedToSendMessage(address, e))?;
+        session.establish(INITIATOR, &shared, &key, peer_key);
+        Ok(identity)
+    };
+    timeout(authenticator.timeout(), handshake)
+        .await
+        .map_err(|_| NetworkError::FailedToAuthenticate(address))?
+}
+
+/// Answers the handshake of a peer that opened a connection with us, given the content of its
+/// first frame. Once this returns, the frames of the connection are authenticated with the keys
+/// of the session. Returns the identity of the peer. Without authenticator, we decline the
+/// handshake (and fail).
+pub(crate) async fn respond(
+    writer: &mut Writer,
+    reader: &mut Reader,
+    session: &Session,
+    peer: SocketAddr,
+    offer: &[u8],
+    authenticator: Option<&SharedAuthenticator>,
+) -> Result<Bytes, NetworkError> {
+    let authenticator = match authenticator {
+        Some(authenticator) if offer.len() == KEY_SIZE => authenticator,
+        _ => {
+            let _ = writer.send(frame(&[])).await;
+            return Err(NetworkError::FailedToAuthenticate(peer));
+        }
+    };
+    let handshake = async {
+        let secret = EphemeralSecret::new(OsRng);
+        let key = EphemeralKey::from(&secret).to_bytes();
+        let shared =
+            shared_secret(secret, offer).ok_or(NetworkError::FailedToAuthenticate(peer))?;
+        let proof = authenticator.prove(&transcript(RESPONDER, offer, &key));
+        writer
+            .send(frame(&[&key[..], &proof[..]]))
+            .await
+            .map_err(|e| NetworkError::FailedToSendMessage(peer, e))?;
+
+        let reply = match reader.next().await {
+            Some(Ok(reply)) => reply,
+            _ => return Err(NetworkError::FailedToAuthenticate(peer)),
+        };
+        let identity = parse_banner(&reply)
+            .and_then(|proof| authenticator.verify(&transcript(INITIATOR, offer, &key), proof))
+            .ok_or(NetworkError::FailedToAuthenticate(peer))?;
+        session.establish(RESPONDER, &shared, offer, &key);
+        Ok(identity)
+    };
+    timeout(authenticator.timeout(), handshake)
+        .await
+        .map_err(|_| NetworkError::FailedToAuthenticate(peer))?
+}
@@ -0,0 +1,101 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use bytes::{BufMut as _, Bytes, BytesMut};
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// Frames smaller than this threshold (in bytes) are sent uncompressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1_024;
+
+/// We refuse to decompress frames larger than the default maximum frame length of the codec.
+const MAX_DECOMPRESSED_SIZE: usize = 8 * 1024 * 1024;
+
+/// The first frame sent over a connection that wishes to use compression. The leading `0xff`
+/// byte ensures it cannot be confused with a bincode-serialized message (whose first byte is a
+/// small enum variant index).
+const BANNER: &[u8] = b"\xffnarwhal/compression";
+
+/// Tags prefixing every frame of a compressed connection.
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+
+/// Per-connection compression settings of a sender. Compression is only used if the peer
+/// accepts it during the banner exchange.
+#[derive(Clone, Copy, Debug)]
+pub struct Compression {
+    /// Frames smaller than this (in bytes) are not compressed.
+    pub threshold: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl Compression {
+    /// The banner advertising our support for compression.
+    pub(crate) fn banner() -> Bytes {
+        Self::make_banner(LZ4)
+    }
+
+    /// The reply to a banner, indicating whether we accept compression.
+    pub(crate) fn banner_reply(accept: bool) -> Bytes {
+        Self::make_banner(if accept { LZ4 } else { RAW })
+    }
+
+    fn make_banner(algorithm: u8) -> Bytes {
+        let mut banner = BytesMut::with_capacity(BANNER.len() + 1);
+        banner.put_slice(BANNER);
+        banner.put_u8(algorithm);
+        banner.freeze()
+    }
+
+    /// Parses a banner (or a banner reply). Returns `None` if the frame is not a banner, and
+    /// otherwise whether the peer wishes to use compression.
+    pub(crate) fn parse_banner(frame: &[u8]) -> Option<bool> {
+        match frame.strip_prefix(BANNER) {
+            Some([LZ4]) => Some(true),
+            Some([_]) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Tag and (if the frame is large enough) compress an outgoing frame.
+    pub(crate) fn compress(&self, data: &Bytes) -> Bytes {
+        let (tag, payload) = if data.len() < self.threshold {
+            (RAW, data.to_vec())
+        } else {
+            (LZ4, lz4_flex::compress_prepend_size(data))
+        };
+        let mut frame = BytesMut::with_capacity(payload.len() + 1);
+        frame.put_u8(tag);
+        frame.put_slice(&payload);
+        frame.freeze()
+    }
+
+    /// Decompress an incoming frame of a compressed connection.
+    pub(crate) fn decompress(frame: Bytes) -> Result<Bytes, NetworkError> {
+        match frame.first() {
+            Some(&RAW) => Ok(frame.slice(1..)),
+            Some(&LZ4) => {
+                let size = frame
+                    .get(1..5)
+                    .map(|x| u32::from_le_bytes(x.try_into().unwrap()) as usize)
+                    .ok_or(NetworkError::MalformedFrame)?;
+                if size > MAX_DECOMPRESSED_SIZE {
+                    return Err(NetworkError::MalformedFrame);
+                }
+                lz4_flex::decompress_size_prepended(&frame[1..])
+                    .map(Bytes::from)
+                    .map_err(|_| NetworkError::MalformedFrame)
+            }
+            _ => Err(NetworkError::MalformedFrame),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::debug;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/concurrency_tests.rs"]
+pub mod concurrency_tests;
+
+/// The bounds and target of a `ConcurrencyLimit`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyParameters {
+    /// The cap never drops below this many connections.
+    pub min_connections: usize,
+    /// The cap never rises above this many connections.
+    pub max_connections: usize,
+    /// The cap when the receiver starts.
+    pub initial_connections: usize,
+    /// Handlers dispatching a message within this delay are fast, the others lag. Denominated in ms.
+    pub target_latency: u64,
+}
+
+impl Default for ConcurrencyParameters {
+    fn default() -> Self {
+        Self {
+            min_connections: 16,
+            max_connections: 10_000,
+            initial_connections: 256,
+            target_latency: 50,
+        }
+    }
+}
+
+struct Limit {
+    /// How many connections we serve at once at most.
+    cap: usize,
+    /// How many connections we serve.
+    in_flight: usize,
+    /// The fast dispatches since the cap last changed.
+    fast: usize,
+    /// When we last lowered the cap.
+    lowered: Option<Instant>,
+}
+
+/// Adaptively limits how many connections a receiver serves at once, based on how long its
+/// handlers take to dispatch messages (AIMD). The cap rises by one connection every `cap` fast
+/// dispatches, and halves when a dispatch lags (at most once per target latency, so that a burst
+/// of lagging dispatches only counts once). Connections beyond the cap wait until the receiver
+/// serves fewer than the cap. Clones share the same limit.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    parameters: ConcurrencyParameters,
+    limit: Arc<Mutex<Limit>>,
+    /// Wakes up the receiver waiting for a permit.
+    notify: Arc<Notify>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(parameters: ConcurrencyParameters) -> Self {
+        let min = parameters.min_connections.max(1);
+        let max = parameters.max_connections.max(min);
+        let parameters = ConcurrencyParameters {
+            min_connections: min,
+            max_connections: max,
+            initial_connections: parameters.initial_connections.clamp(min, max),
+            ..parameters
+        };
+        Self {
+            parameters,
+            limit: Arc::new(Mutex::new(Limit {
+                cap: parameters.initial_connections,
+                in_flight: 0,
+                fast: 0,
+                lowered: None,
+            })),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns how many connections we serve at once at most.
+    pub fn cap(&self) -> usize {
+        self.limit.lock().unwrap().cap
+    }
+
+    /// Returns how many connections we serve.
+    pub fn in_flight(&self) -> usize {
+        self.limit.lock().unwrap().in_flight
+    }
+
+    /// Waits until we serve fewer connections than the cap, and returns the permit to serve one
+    /// more (until it is dropped).
+    pub async fn acquire(&self) -> ConcurrencyPermit {
+        loop {
+            {
+                let mut limit = self.limit.lock().unwrap();
+                if limit.in_flight < limit.cap {
+                    limit.in_flight += 1;
+                    return ConcurrencyPermit {
+                        limit: self.clone(),
+                    };
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Records how long a handler took to dispatch a message, and adjusts the cap.
+    pub fn record(&self, latency: Duration) {
+        let target = Duration::from_millis(self.parameters.target_latency);
+        let mut limit = self.limit.lock().unwrap();
+        if latency <= target {
+            limit.fast += 1;
+            if limit.fast >= limit.cap && limit.cap < self.parameters.max_connections {
+                limit.fast = 0;
+                limit.cap += 1;
+                self.notify.notify_one();
+            }
+            return;
+        }
+        limit.fast = 0;
+        if limit.lowered.is_some_and(|x| x.elapsed() < target) {
+            return;
+        }
+        limit.lowered = Some(Instant::now());
+        let cap = (limit.cap / 2).max(self.parameters.min_connections);
+        if cap < limit.cap {
+            debug!(
+                "Lowering connection cap to {} (dispatch took {:?})",
+                cap, latency
+            );
+            limit.cap = cap;
+        }
+    }
+}
+
+/// Lets a receiver serve a connection. Dropping the permit frees its slot.
+pub struct ConcurrencyPermit {
+    limit: ConcurrencyLimit,
+}
+
+impl ConcurrencyPermit {
+    /// Records how long the handler of the connection took to dispatch a message.
+    pub fn record(&self, latency: Duration) {
+        self.limit.record(latency);
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.limit.limit.lock().unwrap().in_flight -= 1;
+        self.limit.notify.notify_one();
+    }
+}
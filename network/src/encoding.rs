@@ -0,0 +1,131 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use crate::receiver::{Reader, Writer};
+use bincode::Options as _;
+use bytes::{BufMut as _, Bytes, BytesMut};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/encoding_tests.rs"]
+pub mod encoding_tests;
+
+/// The version of our protobuf wire format. Messages carry the version of their encoder; we decode
+/// those of all versions up to ours (fields are only ever added, and unknown fields are ignored).
+pub const WIRE_VERSION: u32 = 1;
+
+/// The first frame sent over a connection that wishes to use another encoding than bincode (before
+/// the compression banner, if any). Like the compression banner, its leading `0xff` byte ensures
+/// it cannot be confused with a bincode-serialized message.
+const BANNER: &[u8] = b"\xffnarwhal/encoding";
+
+/// Tags of the encodings in the banner (and in its reply).
+const BINCODE: u8 = 0;
+const PROTO: u8 = 1;
+
+/// How the messages of a connection are encoded on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Bincode of our Rust types (the default).
+    Bincode,
+    /// Versioned protobuf messages (see the `proto` directories of the crates).
+    Proto,
+}
+
+impl Encoding {
+    /// The banner offering an encoding (or the reply to such a banner, with the accepted encoding).
+    pub(crate) fn banner(&self) -> Bytes {
+        let mut banner = BytesMut::with_capacity(BANNER.len() + 1);
+        banner.put_slice(BANNER);
+        banner.put_u8(match self {
+            Self::Bincode => BINCODE,
+            Self::Proto => PROTO,
+        });
+        banner.freeze()
+    }
+
+    /// Parses a banner (or a banner reply). Returns `None` if the frame is not a banner. Peers
+    /// offering an encoding we do not know get bincode.
+    pub(crate) fn parse_banner(frame: &[u8]) -> Option<Self> {
+        match frame.strip_prefix(BANNER) {
+            Some([PROTO]) => Some(Self::Proto),
+            Some([_]) => Some(Self::Bincode),
+            _ => None,
+        }
+    }
+
+    /// Offers protobuf to the peer of a new connection, and returns the encoding it accepted.
+    pub(crate) async fn offer(
+        writer: &mut Writer,
+        reader: &mut Reader,
+        address: SocketAddr,
+    ) -> Result<Self, NetworkError> {
+        if let Err(e) = writer.send(Self::Proto.banner()).await {
+            return Err(NetworkError::FailedToSendMessage(address, e));
+        }
+        match reader.next().await {
+            Some(Ok(reply)) => {
+                Self::parse_banner(&reply).ok_or(NetworkError::FailedToNegotiateEncoding(address))
+            }
+            _ => Err(NetworkError::FailedToNegotiateEncoding(address)),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("Unsupported wire version {0} (we support versions 1 to {1})")]
+    UnsupportedVersion(u32, u32),
+
+    #[error("Malformed protobuf message: {0}")]
+    MalformedMessage(String),
+
+    #[error("Missing field {0}")]
+    MissingField(&'static str),
+
+    #[error("Invalid length of field {0}")]
+    InvalidLength(&'static str),
+
+    #[error("Failed to (de)serialize message: {0}")]
+    SerializationError(String),
+}
+
+impl WireError {
+    /// Checks that we can decode the messages of an encoder of this version.
+    pub fn check_version(version: u32) -> Result<(), Self> {
+        match version {
+            1..=WIRE_VERSION => Ok(()),
+            _ => Err(Self::UnsupportedVersion(version, WIRE_VERSION)),
+        }
+    }
+}
+
+/// Converts the messages of a channel between their bincode serialization (which the rest of the
+/// node handles) and their protobuf encoding. Senders with a transcoder offer protobuf to their
+/// peers, and receivers with one accept it.
+pub trait Transcoder: Send + Sync + 'static {
+    /// Encodes a bincode-serialized message in protobuf.
+    fn encode(&self, message: &[u8]) -> Result<Bytes, WireError>;
+
+    /// Decodes a protobuf message into its bincode serialization.
+    fn decode(&self, frame: Bytes) -> Result<Bytes, WireError>;
+}
+
+/// A transcoder shared by the connections of a sender or receiver.
+pub type SharedTranscoder = Arc<dyn Transcoder>;
+
+/// Deserializes a message received from a peer, like `bincode::deserialize` but failing (with a
+/// `SizeLimit` error) rather than reading more than `limit` bytes: whatever lengths a crafted
+/// frame claims, we never allocate more than that for its content. (Bincode ignores limits when
+/// deserializing from a slice, so we read it as a stream.)
+pub fn deserialize_bounded<T: DeserializeOwned>(bytes: &[u8], limit: u64) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(bytes)
+}
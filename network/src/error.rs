@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::encoding::WireError;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use thiserror::Error;
@@ -22,4 +23,22 @@ pub enum NetworkError {
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Failed to negotiate compression with {0}")]
+    FailedToNegotiate(SocketAddr),
+
+    #[error("Failed to authenticate {0}")]
+    FailedToAuthenticate(SocketAddr),
+
+    #[error("Failed to negotiate the encoding with {0}")]
+    FailedToNegotiateEncoding(SocketAddr),
+
+    #[error("Failed to transcode message exchanged with {0}: {1}")]
+    FailedToTranscode(SocketAddr, WireError),
+
+    #[error("Rejected connection from {0}: its IP is not allowed")]
+    PeerNotAllowed(SocketAddr),
+
+    #[error("Received malformed compressed frame")]
+    MalformedFrame,
 }
@@ -0,0 +1,50 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+#[cfg(test)]
+#[path = "tests/ip_rules_tests.rs"]
+pub mod ip_rules_tests;
+
+/// Decides which peers may open connections with a receiver, by IP address. Rules are CIDR blocks
+/// (e.g., `10.0.0.0/8`, or `192.168.1.7/32` for a single address). Deny rules take precedence over
+/// allow rules; peers matching neither are allowed if `default_allow` is set. By default, every
+/// peer is allowed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct IpRules {
+    /// The blocks of the peers we accept.
+    pub allow: Vec<IpNet>,
+    /// The blocks of the peers we reject (even if they are allowed).
+    pub deny: Vec<IpNet>,
+    /// Whether we accept the peers matching no rule.
+    pub default_allow: bool,
+}
+
+impl Default for IpRules {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            default_allow: true,
+        }
+    }
+}
+
+impl IpRules {
+    /// Whether the rules allow a peer. IPv4 peers connecting to a dual-stack listener (as
+    /// IPv4-mapped IPv6 addresses) are matched against the IPv4 rules.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|x| x.contains(&ip)) {
+            return false;
+        }
+        self.default_allow || self.allow.iter().any(|x| x.contains(&ip))
+    }
+
+    /// Whether the rules allow every peer.
+    pub fn allows_all(&self) -> bool {
+        self.deny.is_empty() && self.default_allow
+    }
+}
@@ -1,5 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod authentication;
+mod compression;
+mod concurrency;
+mod encoding;
 mod error;
+mod ip_rules;
+mod peer_traffic;
+mod peer_violations;
 mod receiver;
 mod reliable_sender;
 mod simple_sender;
@@ -8,6 +15,17 @@ mod simple_sender;
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
+pub use crate::authentication::{Authenticator, FrameCodec, SharedAuthenticator};
+pub use crate::compression::{Compression, DEFAULT_COMPRESSION_THRESHOLD};
+pub use crate::concurrency::{ConcurrencyLimit, ConcurrencyParameters, ConcurrencyPermit};
+pub use crate::encoding::{
+    deserialize_bounded, Encoding, SharedTranscoder, Transcoder, WireError, WIRE_VERSION,
+};
+pub use crate::ip_rules::IpRules;
+pub use crate::peer_traffic::{PeerTraffic, DEFAULT_TRACKED_PEERS};
+pub use crate::peer_violations::PeerViolations;
+pub use crate::receiver::{
+    FlushWindow, MessageHandler, Receiver, ReceiverOptions, Writer, DEFAULT_BACKLOG,
+};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
 pub use crate::simple_sender::SimpleSender;
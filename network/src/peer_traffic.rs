@@ -0,0 +1,92 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::debug;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration};
+
+#[cfg(test)]
+#[path = "tests/peer_traffic_tests.rs"]
+pub mod peer_traffic_tests;
+
+/// The default number of peers whose traffic we keep track of.
+pub const DEFAULT_TRACKED_PEERS: usize = 1_000;
+
+/// How many of the heaviest peers we log.
+const REPORTED_PEERS: usize = 5;
+
+/// Counts the bytes received from each peer (by IP address), to spot peers sending anomalous
+/// volumes. The counts are shared by all clones. The map holds at most twice `capacity` peers:
+/// once full, it is trimmed down to the `capacity` heaviest ones (the others are forgotten).
+#[derive(Clone)]
+pub struct PeerTraffic {
+    capacity: usize,
+    bytes: Arc<Mutex<HashMap<IpAddr, u64>>>,
+}
+
+impl PeerTraffic {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            bytes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records bytes received from a peer.
+    pub fn record(&self, peer: IpAddr, bytes: usize) {
+        let mut map = self.bytes.lock().unwrap();
+        *map.entry(peer).or_default() += bytes as u64;
+        if map.len() > 2 * self.capacity {
+            trim(&mut map, self.capacity);
+        }
+    }
+
+    /// Returns the bytes received from each peer, heaviest first.
+    pub fn snapshot(&self) -> Vec<(IpAddr, u64)> {
+        let mut snapshot: Vec<_> = self
+            .bytes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, bytes)| (*peer, *bytes))
+            .collect();
+        snapshot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        snapshot
+    }
+
+    /// Periodically logs the heaviest peers.
+    pub fn log_periodically(&self, label: &'static str, period: Duration) {
+        let traffic = self.clone();
+        tokio::spawn(async move {
+            let mut timer = interval(period);
+            loop {
+                timer.tick().await;
+                let snapshot = traffic.snapshot();
+                if !snapshot.is_empty() {
+                    let top: Vec<_> = snapshot.into_iter().take(REPORTED_PEERS).collect();
+                    debug!("{} inbound bytes by peer: {:?}", label, top);
+                }
+            }
+        });
+    }
+}
+
+/// Keeps only the `capacity` peers with the highest counts.
+pub(crate) fn trim(map: &mut HashMap<IpAddr, u64>, capacity: usize) {
+    let mut totals: Vec<_> = map.values().cloned().collect();
+    totals.sort_unstable_by(|a, b| b.cmp(a));
+    let threshold = totals[capacity - 1];
+    let mut kept = 0;
+    map.retain(|_, total| {
+        // Ties at the threshold are kept until we reach the capacity.
+        let keep = *total > threshold || (*total == threshold && kept < capacity);
+        kept += keep as usize;
+        keep
+    });
+}
+
+impl Default for PeerTraffic {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACKED_PEERS)
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::peer_traffic::{trim, DEFAULT_TRACKED_PEERS};
+use log::warn;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/peer_violations_tests.rs"]
+pub mod peer_violations_tests;
+
+/// Counts the protocol violations of each peer (by IP address): messages that exceed our size
+/// limits, fail to deserialize, or are structurally invalid. It is the input of any reputation
+/// policy (e.g., banning repeat offenders). The counts are shared by all clones. Like
+/// `PeerTraffic`, the map holds at most twice `capacity` peers: once full, it is trimmed down to
+/// the `capacity` worst offenders.
+#[derive(Clone)]
+pub struct PeerViolations {
+    capacity: usize,
+    counts: Arc<Mutex<HashMap<IpAddr, u64>>>,
+}
+
+impl PeerViolations {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records a violation of a peer, and returns its number of violations so far.
+    pub fn record(&self, peer: IpAddr, reason: &str) -> u64 {
+        let mut map = self.counts.lock().unwrap();
+        let count = {
+            let count = map.entry(peer).or_default();
+            *count += 1;
+            *count
+        };
+        warn!("Violation #{} of peer {}: {}", count, peer, reason);
+        if map.len() > 2 * self.capacity {
+            trim(&mut map, self.capacity);
+        }
+        count
+    }
+
+    /// Returns the number of violations of a peer.
+    pub fn count(&self, peer: &IpAddr) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(peer)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the violations of each peer, worst first.
+    pub fn snapshot(&self) -> Vec<(IpAddr, u64)> {
+        let mut snapshot: Vec<_> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, count)| (*peer, *count))
+            .collect();
+        snapshot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        snapshot
+    }
+}
+
+impl Default for PeerViolations {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACKED_PEERS)
+    }
+}
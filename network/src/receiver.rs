@@ -1,21 +1,59 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authentication::{self, FrameCodec, Session, SharedAuthenticator};
+use crate::compression::Compression;
+use crate::concurrency::{ConcurrencyLimit, ConcurrencyPermit};
+use crate::encoding::{Encoding, SharedTranscoder};
 use crate::error::NetworkError;
+use crate::ip_rules::IpRules;
+use crate::peer_traffic::PeerTraffic;
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::SplitSink;
+use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{debug, info, warn};
+use futures::stream::{SplitSink, SplitStream};
+use log::{debug, error, info, warn};
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::cmp::min;
 use std::error::Error;
+use std::io;
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::time::{sleep, sleep_until, timeout, Duration, Instant};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
+/// The initial delay to wait before accepting connections again after a transient error (in ms).
+const ACCEPT_RETRY_DELAY: u64 = 10;
+
+/// The maximum delay to wait before accepting connections again after a transient error (in ms).
+const MAX_ACCEPT_RETRY_DELAY: u64 = 1_000;
+
+/// The default backlog of the listener (the one of `TcpListener::bind`).
+pub const DEFAULT_BACKLOG: u32 = 1_024;
+
 /// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+pub type Writer = SplitSink<Framed<TcpStream, FrameCodec>, Bytes>;
+
+/// Convenient alias for the reader end of the TCP channel.
+pub(crate) type Reader = SplitStream<Framed<TcpStream, FrameCodec>>;
+
+/// Lets the runners of a receiver coalesce the replies of their handler into fewer writes. Handlers
+/// `feed` their replies to the writer (rather than `send` them), and the runner flushes them once
+/// `window` elapsed since the first message dispatched after the last flush, or once it dispatched
+/// `max_pending` messages, whichever comes first. Replies are written in order.
+#[derive(Clone, Copy, Debug)]
+pub struct FlushWindow {
+    /// How long we may hold replies before writing them.
+    pub window: Duration,
+    /// How many messages we may dispatch before writing their replies.
+    pub max_pending: usize,
+    /// How long to wait for the peer to accept the replies before closing the connection.
+    pub timeout: Duration,
+}
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
@@ -24,6 +62,92 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
     /// responses or acknowledgements to the sender machine (see unit tests for examples).
     async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+
+    /// Makes the handler of a new connection with `peer`. By default, connections are handled by
+    /// clones of the same handler; handlers that depend on who they talk to override it.
+    fn for_peer(&self, _peer: SocketAddr) -> Self {
+        self.clone()
+    }
+
+    /// Resolves once we should close the connection, e.g., because the peer is no longer one we
+    /// serve. The runner only checks it between messages: the message being dispatched is handled
+    /// (and its replies written) before the connection closes. By default, connections are only
+    /// closed by the peer, or when the handler fails.
+    async fn closed(&self) {
+        futures::future::pending().await
+    }
+
+    /// Makes the handler of a connection whose peer proved its identity (see `Authenticator`). By
+    /// default, identities are ignored; handlers that only serve some peers override it.
+    fn authenticated(&self, _identity: &[u8]) -> Self {
+        self.clone()
+    }
+}
+
+/// A source of incoming TCP connections. Tests use it to inject accept errors.
+#[async_trait]
+pub trait Listener: Send + Sync + 'static {
+    async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)>;
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self).await
+    }
+}
+
+/// Whether the listener can still accept connections after this error. Errors like running out
+/// of file descriptors (`EMFILE`) or a peer aborting the handshake are transient; an invalid or
+/// unsupported listener is not.
+fn is_transient(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+/// How a receiver serves its peers. The default serves any peer, with the backlog of
+/// `TcpListener::bind` and none of the optional features.
+#[derive(Clone)]
+pub struct ReceiverOptions {
+    /// Counts the bytes received from each peer (if set).
+    pub traffic: Option<PeerTraffic>,
+    /// How many connections that are established but not yet accepted the listener queues. The
+    /// receiver accepts connections as fast as it can (unless it has a concurrency limit), so the
+    /// backlog only fills up during bursts of connections, or while accepting backs off after an
+    /// error (e.g., `EMFILE`). Beyond it, the kernel drops new connection attempts (clients then
+    /// retry or fail). The kernel caps the backlog (`net.core.somaxconn` on Linux).
+    pub backlog: u32,
+    /// Accepts protobuf from the peers offering it, and hands their messages to the handler in
+    /// bincode, as those of the other peers (if set).
+    pub transcoder: Option<SharedTranscoder>,
+    /// Coalesces the replies of the handler (if set, see `FlushWindow`).
+    pub flush: Option<FlushWindow>,
+    /// Decides which peers may connect, by IP (if set).
+    pub rules: Option<IpRules>,
+    /// Adapts how many connections we serve at once to the latency of the handler (if set, see
+    /// `ConcurrencyLimit`).
+    pub limit: Option<ConcurrencyLimit>,
+    /// Answers the authentication handshake of the peers (if set): those opening their connection
+    /// with it prove their identity, which the handler learns (see
+    /// `MessageHandler::authenticated`). The others are still served: it is up to the handler to
+    /// reject the messages of unauthenticated peers.
+    pub authenticator: Option<SharedAuthenticator>,
+}
+
+impl Default for ReceiverOptions {
+    fn default() -> Self {
+        Self {
+            traffic: None,
+            backlog: DEFAULT_BACKLOG,
+            transcoder: None,
+            flush: None,
+            rules: None,
+            limit: None,
+            authenticator: None,
+        }
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -33,57 +157,278 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// How we serve the peers.
+    options: ReceiverOptions,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler) {
+    /// Spawn a new network receiver handling connections from incoming peers, as the options
+    /// specify.
+    pub fn spawn(address: SocketAddr, handler: Handler, mut options: ReceiverOptions) {
+        // Rules allowing every peer are not worth checking.
+        options.rules = options.rules.filter(|x| !x.allows_all());
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                handler,
+                options,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
     async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
-            .await
-            .expect("Failed to bind TCP port");
+        let listener =
+            Self::bind(self.address, self.options.backlog).expect("Failed to bind TCP port");
 
         debug!("Listening on {}", self.address);
+        let e = Self::accept_loop(listener, self.handler.clone(), self.options.clone()).await;
+        error!("Stopped listening on {}: {}", self.address, e);
+    }
+
+    /// Makes a listener with the specified backlog (with the other options of `TcpListener::bind`).
+    fn bind(address: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+        let socket = match address {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        socket.bind(address)?;
+        socket.listen(backlog)
+    }
+
+    /// Accepts connections until the listener fails with an unrecoverable error (which is
+    /// returned). After a transient error, we wait a randomized and increasing delay before
+    /// accepting again rather than spinning (errors like `EMFILE` persist for a while). Peers the
+    /// rules do not allow are disconnected as soon as they are accepted, before reading anything.
+    /// With a concurrency limit, a connection we accept waits (unread) until we serve fewer
+    /// connections than the cap, and the later ones wait in the backlog of the listener.
+    async fn accept_loop<L: Listener>(
+        listener: L,
+        handler: Handler,
+        options: ReceiverOptions,
+    ) -> NetworkError {
+        let mut rng = SmallRng::from_entropy();
+        let mut delay = ACCEPT_RETRY_DELAY;
         loop {
             let (socket, peer) = match listener.accept().await {
                 Ok(value) => value,
-                Err(e) => {
+                Err(e) if is_transient(&e) => {
                     warn!("{}", NetworkError::FailedToListen(e));
+                    sleep(Duration::from_millis(rng.gen_range(delay / 2, delay + 1))).await;
+                    delay = min(2 * delay, MAX_ACCEPT_RETRY_DELAY);
                     continue;
                 }
+                Err(e) => return NetworkError::FailedToListen(e),
+            };
+            delay = ACCEPT_RETRY_DELAY;
+            if options.rules.as_ref().is_some_and(|x| !x.allows(peer.ip())) {
+                warn!("{}", NetworkError::PeerNotAllowed(peer));
+                continue;
+            }
+            let permit = match &options.limit {
+                Some(limit) => Some(limit.acquire().await),
+                None => None,
             };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            Self::spawn_runner(socket, peer, handler.for_peer(peer), &options, permit).await;
         }
     }
 
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// using the provided handler. If the first frame of the connection is a compression banner,
+    /// all subsequent frames are decompressed before being handed to the handler. The compression
+    /// banner may be preceded by an encoding banner: if the peer offers protobuf and we have a
+    /// transcoder, its messages are converted to bincode before being handed to the handler.
+    /// Traffic is counted in (possibly compressed) frame payload bytes, as received. With a flush
+    /// window, the replies the handler feeds to the writer are flushed together. The runner holds
+    /// the permit of the concurrency limit (if any) until the connection closes, and reports to it
+    /// how long the handler takes to dispatch each message. Both banners may be preceded by the
+    /// authentication handshake: the handler then learns the identity of the peer (unless the
+    /// handshake fails, which closes the connection).
+    async fn spawn_runner(
+        socket: TcpStream,
+        peer: SocketAddr,
+        mut handler: Handler,
+        options: &ReceiverOptions,
+        permit: Option<ConcurrencyPermit>,
+    ) {
+        let ReceiverOptions {
+            traffic,
+            transcoder,
+            flush,
+            authenticator,
+            ..
+        } = options.clone();
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let session = Session::default();
+            let transport = Framed::new(socket, FrameCodec::new(session.clone()));
             let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
-                match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
+            let mut compressed = false;
+            let mut proto = None;
+            let mut first = true;
+            let mut authenticated = false;
+            let mut pending = 0;
+            let mut deadline = None;
+            loop {
+                let frame = tokio::select! {
+                    frame = reader.next() => match frame {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                    () = handler.closed() => {
+                        // Write the replies we hold before closing the connection.
+                        let _ = Self::flush(&mut writer, peer, &flush).await;
+                        info!("Closing connection with {}: drained", peer);
+                        return;
+                    }
+                    // The deadline is only set with a flush window.
+                    () = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        if let Err(e) = Self::flush(&mut writer, peer, &flush).await {
                             warn!("{}", e);
                             return;
                         }
+                        pending = 0;
+                        deadline = None;
+                        continue;
+                    }
+                };
+                if let (Some(traffic), Ok(frame)) = (&traffic, &frame) {
+                    traffic.record(peer.ip(), frame.len());
+                }
+                let frame = frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e));
+                let frame = match frame {
+                    Ok(frame) if first && !authenticated => {
+                        match authentication::parse_banner(&frame) {
+                            Some(offer) => {
+                                // The other banners may still follow: the next frame is still
+                                // the first one.
+                                let identity = authentication::respond(
+                                    &mut writer,
+                                    &mut reader,
+                                    &session,
+                                    peer,
+                                    offer,
+                                    authenticator.as_ref(),
+                                );
+                                match identity.await {
+                                    Ok(identity) => {
+                                        debug!("Authenticated connection with {}", peer);
+                                        handler = handler.authenticated(&identity);
+                                        authenticated = true;
+                                    }
+                                    Err(e) => {
+                                        warn!("{}", e);
+                                        return;
+                                    }
+                                }
+                                continue;
+                            }
+                            None => Ok(frame),
+                        }
+                    }
+                    frame => frame,
+                };
+                let frame = match frame {
+                    Ok(frame) if first && Encoding::parse_banner(&frame).is_some() => {
+                        // We accept protobuf whenever we can transcode it. The compression banner
+                        // may still follow, so this is not the first frame yet.
+                        let encoding = match (Encoding::parse_banner(&frame), &transcoder) {
+                            (Some(Encoding::Proto), Some(transcoder)) => {
+                                proto = Some(transcoder.clone());
+                                Encoding::Proto
+                            }
+                            _ => Encoding::Bincode,
+                        };
+                        match writer.send(encoding.banner()).await {
+                            Ok(()) => debug!("Using {:?} for connection with {}", encoding, peer),
+                            Err(e) => {
+                                warn!("{}", NetworkError::FailedToSendMessage(peer, e));
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(frame) if first && Compression::parse_banner(&frame).is_some() => {
+                        // We always accept compression, so peers may pipeline (compressed) frames
+                        // after their banner without waiting for our reply. If the reply fails, we
+                        // still deliver the frames already received: the connection closes when
+                        // the reader fails as well (or the handler fails to reply).
+                        first = false;
+                        compressed = true;
+                        match writer.send(Compression::banner_reply(true)).await {
+                            Ok(()) => debug!("Compression enabled for connection with {}", peer),
+                            Err(e) => warn!("{}", NetworkError::FailedToSendMessage(peer, e)),
+                        }
+                        continue;
+                    }
+                    Ok(frame) if compressed => Compression::decompress(frame.freeze()),
+                    Ok(frame) => Ok(frame.freeze()),
+                    Err(e) => Err(e),
+                };
+                first = false;
+                let frame = match &proto {
+                    Some(transcoder) => frame.and_then(|x| {
+                        transcoder
+                            .decode(x)
+                            .map_err(|e| NetworkError::FailedToTranscode(peer, e))
+                    }),
+                    None => frame,
+                };
+                match frame {
+                    Ok(message) => {
+                        let start = Instant::now();
+                        if let Err(e) = handler.dispatch(&mut writer, message).await {
+                            warn!("Closing connection with {}: {}", peer, e);
+                            return;
+                        }
+                        if let Some(permit) = &permit {
+                            permit.record(start.elapsed());
+                        }
                     }
                     Err(e) => {
                         warn!("{}", e);
                         return;
                     }
                 }
+                if let Some(flush) = &flush {
+                    pending += 1;
+                    if pending >= flush.max_pending {
+                        if let Err(e) = Self::flush(&mut writer, peer, &Some(*flush)).await {
+                            warn!("{}", e);
+                            return;
+                        }
+                        pending = 0;
+                        deadline = None;
+                    } else if deadline.is_none() {
+                        deadline = Some(Instant::now() + flush.window);
+                    }
+                }
             }
+
+            // The peer may still read the replies we hold (it may only have closed its end).
+            let _ = Self::flush(&mut writer, peer, &flush).await;
             warn!("Connection closed by peer {}", peer);
         });
     }
+
+    /// Writes the replies held by the writer, giving up after the timeout of the flush window.
+    /// Without a flush window, handlers write their replies themselves.
+    async fn flush(
+        writer: &mut Writer,
+        peer: SocketAddr,
+        flush: &Option<FlushWindow>,
+    ) -> Result<(), NetworkError> {
+        let flush = match flush {
+            Some(flush) => flush,
+            None => return Ok(()),
+        };
+        match timeout(flush.timeout, writer.flush()).await {
+            Ok(result) => result.map_err(|e| NetworkError::FailedToSendMessage(peer, e)),
+            Err(e) => Err(NetworkError::FailedToSendMessage(peer, e.into())),
+        }
+    }
 }
@@ -1,4 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authentication::{self, FrameCodec, Session, SharedAuthenticator};
+use crate::compression::Compression;
+use crate::encoding::{Encoding, SharedTranscoder};
 use crate::error::NetworkError;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
@@ -6,7 +9,7 @@ use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
-use rand::SeedableRng as _;
+use rand::{Rng as _, SeedableRng as _};
 use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
@@ -15,12 +18,15 @@ use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/reliable_sender_tests.rs"]
 pub mod reliable_sender_tests;
 
+/// The longest we wait before attempting to reconnect to a peer (in ms).
+const MAX_RETRY_DELAY: u64 = 60_000;
+
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
@@ -33,6 +39,12 @@ pub struct ReliableSender {
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// Whether to offer compression to the peers we connect to.
+    compression: Option<Compression>,
+    /// Converts our messages to protobuf, for the peers that accept it (if set).
+    transcoder: Option<SharedTranscoder>,
+    /// Authenticates the peers we connect to (if set).
+    authenticator: Option<SharedAuthenticator>,
 }
 
 impl std::default::Default for ReliableSender {
@@ -46,22 +58,63 @@ impl ReliableSender {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            compression: None,
+            transcoder: None,
+            authenticator: None,
+        }
+    }
+
+    /// Make a sender that compresses its messages whenever the peer supports it.
+    pub fn with_compression(compression: Compression) -> Self {
+        Self {
+            compression: Some(compression),
+            ..Self::new()
+        }
+    }
+
+    /// Offers protobuf to the peers we connect to, encoding our messages with the transcoder (if
+    /// any) for those that accept it.
+    pub fn with_transcoder(self, transcoder: Option<SharedTranscoder>) -> Self {
+        Self { transcoder, ..self }
+    }
+
+    /// Authenticates the peers we connect to with the authenticator (if any), and proves our
+    /// identity to them, before sending them anything (see `Authenticator`).
+    pub fn with_authenticator(self, authenticator: Option<SharedAuthenticator>) -> Self {
+        Self {
+            authenticator,
+            ..self
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(
+        address: SocketAddr,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
+    ) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, compression, transcoder, authenticator, rx);
         tx
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
+        let compression = self.compression;
+        let transcoder = &self.transcoder;
+        let authenticator = &self.authenticator;
         self.connections
             .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .or_insert_with(|| {
+                Self::spawn_connection(
+                    address,
+                    compression,
+                    transcoder.clone(),
+                    authenticator.clone(),
+                )
+            })
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
@@ -114,21 +167,47 @@ struct InnerMessage {
 struct Connection {
     /// The destination address.
     address: SocketAddr,
+    /// The compression settings to offer to the peer (if any).
+    compression: Option<Compression>,
+    /// Converts our messages to protobuf, if the peer accepts it.
+    transcoder: Option<SharedTranscoder>,
+    /// Authenticates the peer (if set).
+    authenticator: Option<SharedAuthenticator>,
+    /// The identity the peer must prove (if we authenticate it and know its address).
+    expected: Option<Bytes>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
     /// The initial delay to wait before re-attempting a connection (in ms).
     retry_delay: u64,
+    /// The delay to wait before the next attempt (in ms). It doubles after each failed attempt,
+    /// and is reset once the peer acknowledges a message.
+    delay: u64,
+    /// How many attempts failed since the peer last acknowledged a message.
+    retry: u16,
     /// Buffer keeping all messages that need to be re-transmitted.
     buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(
+        address: SocketAddr,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
+        receiver: Receiver<InnerMessage>,
+    ) {
+        let expected = authenticator.as_ref().and_then(|x| x.expected(&address));
         tokio::spawn(async move {
             Self {
                 address,
+                compression,
+                transcoder,
+                authenticator,
+                expected,
                 receiver,
                 retry_delay: 200,
+                delay: 200,
+                retry: 0,
                 buffer: VecDeque::new(),
             }
             .run()
@@ -136,45 +215,43 @@ impl Connection {
         });
     }
 
-    /// Main loop trying to connect to the peer and transmit messages.
+    /// Main loop trying to connect to the peer and transmit messages. We reconnect whenever the
+    /// connection fails (e.g., the peer restarts), waiting an increasing delay between attempts.
+    /// Each delay is randomized (between half and all of it) so that the peers of a node do not
+    /// all reconnect at once when it restarts.
     async fn run(&mut self) {
-        let mut delay = self.retry_delay;
-        let mut retry = 0;
+        let mut rng = SmallRng::from_entropy();
         loop {
-            match TcpStream::connect(self.address).await {
+            let error = match TcpStream::connect(self.address).await {
                 Ok(stream) => {
                     info!("Outgoing connection established with {}", self.address);
 
-                    // Reset the delay.
-                    delay = self.retry_delay;
-                    retry = 0;
-
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
-                    warn!("{}", error);
+                    self.keep_alive(stream).await
                 }
-                Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
-                    let timer = sleep(Duration::from_millis(delay));
-                    tokio::pin!(timer);
-
-                    'waiter: loop {
-                        tokio::select! {
-                            // Wait an increasing delay before attempting to reconnect.
-                            () = &mut timer => {
-                                delay = min(2*delay, 60_000);
-                                retry +=1;
-                                break 'waiter;
-                            },
-
-                            // Drain the channel into the buffer to not saturate the channel and block the caller task.
-                            // The caller is responsible to cleanup the buffer through the cancel handlers.
-                            Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
-                            }
-                        }
+                Err(e) => NetworkError::FailedToConnect(self.address, self.retry, e),
+            };
+            warn!("{}", error);
+
+            let delay = rng.gen_range(self.delay / 2, self.delay + 1);
+            let timer = sleep(Duration::from_millis(delay));
+            tokio::pin!(timer);
+
+            'waiter: loop {
+                tokio::select! {
+                    // Wait an increasing delay before attempting to reconnect.
+                    () = &mut timer => {
+                        self.delay = min(2 * self.delay, MAX_RETRY_DELAY);
+                        self.retry = self.retry.saturating_add(1);
+                        break 'waiter;
+                    },
+
+                    // Drain the channel into the buffer to not saturate the channel and block the caller task.
+                    // The caller is responsible to cleanup the buffer through the cancel handlers.
+                    Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
+                        self.buffer.push_back((data, cancel_handler));
+                        self.buffer.retain(|(_, handler)| !handler.is_closed());
                     }
                 }
             }
@@ -187,7 +264,54 @@ impl Connection {
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        let session = Session::default();
+        let (mut writer, mut reader) =
+            Framed::new(stream, FrameCodec::new(session.clone())).split();
+
+        // Authenticate the peer (if we authenticate our peers) before anything else.
+        if let Some(authenticator) = &self.authenticator {
+            let result = authentication::initiate(
+                &mut writer,
+                &mut reader,
+                &session,
+                self.address,
+                authenticator,
+                self.expected.as_deref(),
+            );
+            if let Err(e) = result.await {
+                return e;
+            }
+        }
+
+        // Offer protobuf to the peer (if we can transcode our messages) and wait for its answer.
+        let transcoder = match &self.transcoder {
+            Some(transcoder) => match Encoding::offer(&mut writer, &mut reader, self.address).await
+            {
+                Ok(Encoding::Proto) => Some(transcoder.clone()),
+                Ok(Encoding::Bincode) => None,
+                Err(e) => return e,
+            },
+            None => None,
+        };
+
+        // Offer compression to the peer (if enabled) and wait for its answer.
+        let compression = match self.compression {
+            Some(compression) => {
+                if let Err(e) = writer.send(Compression::banner()).await {
+                    return NetworkError::FailedToSendMessage(self.address, e);
+                }
+                match reader.next().await {
+                    Some(Ok(reply)) => match Compression::parse_banner(&reply) {
+                        Some(true) => Some(compression),
+                        Some(false) => None,
+                        None => return NetworkError::FailedToNegotiate(self.address),
+                    },
+                    _ => return NetworkError::FailedToNegotiate(self.address),
+                }
+            }
+            None => None,
+        };
+
         let error = 'connection: loop {
             // Try to send all messages of the buffer.
             while let Some((data, handler)) = self.buffer.pop_front() {
@@ -196,8 +320,23 @@ impl Connection {
                     continue;
                 }
 
-                // Try to send the message.
-                match writer.send(data.clone()).await {
+                // Try to send the message. Messages we fail to transcode can never be sent to this
+                // peer, so we drop them (their handler reports the failure to the caller).
+                let encoded = match &transcoder {
+                    Some(transcoder) => match transcoder.encode(&data) {
+                        Ok(encoded) => encoded,
+                        Err(e) => {
+                            warn!("{}", NetworkError::FailedToTranscode(self.address, e));
+                            continue;
+                        }
+                    },
+                    None => data.clone(),
+                };
+                let frame = match &compression {
+                    Some(compression) => compression.compress(&encoded),
+                    None => encoded,
+                };
+                match writer.send(frame).await {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
                         // `pending_replies` while we wait for an ACK.
@@ -226,6 +365,10 @@ impl Connection {
                         Some(Ok(bytes)) => {
                             // Notify the handler that the message has been successfully sent.
                             let _ = handler.send(bytes.freeze());
+
+                            // The connection works: reconnect promptly if it fails later.
+                            self.delay = self.retry_delay;
+                            self.retry = 0;
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
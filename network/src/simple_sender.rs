@@ -1,4 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authentication::{self, FrameCodec, Session, SharedAuthenticator};
+use crate::compression::Compression;
+use crate::encoding::{Encoding, SharedTranscoder};
 use crate::error::NetworkError;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
@@ -11,7 +14,7 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/simple_sender_tests.rs"]
@@ -24,6 +27,12 @@ pub struct SimpleSender {
     connections: HashMap<SocketAddr, Sender<Bytes>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// Whether to offer compression to the peers we connect to.
+    compression: Option<Compression>,
+    /// Converts our messages to protobuf, for the peers that accept it (if set).
+    transcoder: Option<SharedTranscoder>,
+    /// Authenticates the peers we connect to (if set).
+    authenticator: Option<SharedAuthenticator>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -37,13 +46,44 @@ impl SimpleSender {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            compression: None,
+            transcoder: None,
+            authenticator: None,
+        }
+    }
+
+    /// Make a sender that compresses its messages whenever the peer supports it.
+    pub fn with_compression(compression: Compression) -> Self {
+        Self {
+            compression: Some(compression),
+            ..Self::new()
+        }
+    }
+
+    /// Offers protobuf to the peers we connect to, encoding our messages with the transcoder (if
+    /// any) for those that accept it.
+    pub fn with_transcoder(self, transcoder: Option<SharedTranscoder>) -> Self {
+        Self { transcoder, ..self }
+    }
+
+    /// Authenticates the peers we connect to with the authenticator (if any), and proves our
+    /// identity to them, before sending them anything (see `Authenticator`).
+    pub fn with_authenticator(self, authenticator: Option<SharedAuthenticator>) -> Self {
+        Self {
+            authenticator,
+            ..self
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    fn spawn_connection(
+        address: SocketAddr,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
+    ) -> Sender<Bytes> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, compression, transcoder, authenticator, rx);
         tx
     }
 
@@ -58,7 +98,12 @@ impl SimpleSender {
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = Self::spawn_connection(
+            address,
+            self.compression,
+            self.transcoder.clone(),
+            self.authenticator.clone(),
+        );
         if tx.send(data).await.is_ok() {
             self.connections.insert(address, tx);
         }
@@ -89,22 +134,47 @@ impl SimpleSender {
 struct Connection {
     /// The destination address.
     address: SocketAddr,
+    /// The compression settings to offer to the peer (if any).
+    compression: Option<Compression>,
+    /// Converts our messages to protobuf, if the peer accepts it.
+    transcoder: Option<SharedTranscoder>,
+    /// Authenticates the peer (if set).
+    authenticator: Option<SharedAuthenticator>,
+    /// The identity the peer must prove (if we authenticate it and know its address).
+    expected: Option<Bytes>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Bytes>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(
+        address: SocketAddr,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
+        receiver: Receiver<Bytes>,
+    ) {
+        let expected = authenticator.as_ref().and_then(|x| x.expected(&address));
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                compression,
+                transcoder,
+                authenticator,
+                expected,
+                receiver,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         // Try to connect to the peer.
+        let session = Session::default();
         let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+            Ok(stream) => Framed::new(stream, FrameCodec::new(session.clone())).split(),
             Err(e) => {
                 warn!(
                     "{}",
@@ -115,12 +185,81 @@ impl Connection {
         };
         info!("Outgoing connection established with {}", self.address);
 
+        // Authenticate the peer (if we authenticate our peers) before anything else.
+        if let Some(authenticator) = &self.authenticator {
+            let result = authentication::initiate(
+                &mut writer,
+                &mut reader,
+                &session,
+                self.address,
+                authenticator,
+                self.expected.as_deref(),
+            );
+            if let Err(e) = result.await {
+                warn!("{}", e);
+                return;
+            }
+        }
+
+        // Offer protobuf to the peer (if we can transcode our messages) and wait for its answer.
+        let transcoder = match &self.transcoder {
+            Some(transcoder) => match Encoding::offer(&mut writer, &mut reader, self.address).await
+            {
+                Ok(Encoding::Proto) => Some(transcoder.clone()),
+                Ok(Encoding::Bincode) => None,
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        // Offer compression to the peer (if enabled) and wait for its answer.
+        let compression = match self.compression {
+            Some(compression) => {
+                if let Err(e) = writer.send(Compression::banner()).await {
+                    warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
+                    return;
+                }
+                match reader.next().await {
+                    Some(Ok(reply)) => match Compression::parse_banner(&reply) {
+                        Some(true) => Some(compression),
+                        Some(false) => None,
+                        None => {
+                            warn!("{}", NetworkError::FailedToNegotiate(self.address));
+                            return;
+                        }
+                    },
+                    _ => {
+                        warn!("{}", NetworkError::FailedToNegotiate(self.address));
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
         // Transmit messages once we have established a connection.
         loop {
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
                 Some(data) = self.receiver.recv() => {
-                    if let Err(e) = writer.send(data).await {
+                    let data = match &transcoder {
+                        Some(transcoder) => match transcoder.encode(&data) {
+                            Ok(data) => data,
+                            Err(e) => {
+                                warn!("{}", NetworkError::FailedToTranscode(self.address, e));
+                                continue;
+                            }
+                        },
+                        None => data,
+                    };
+                    let frame = match &compression {
+                        Some(compression) => compression.compress(&data),
+                        None => data,
+                    };
+                    if let Err(e) = writer.send(frame).await {
                         warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
                         return;
                     }
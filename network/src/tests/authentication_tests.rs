@@ -0,0 +1,235 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{Compression, MessageHandler, Receiver, ReceiverOptions, ReliableSender, SimpleSender};
+use async_trait::async_trait;
+use std::error::Error;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Instant};
+use tokio_util::codec::Framed;
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<(Option<Bytes>, Bytes)>,
+    /// The identity the peer proved (if any).
+    identity: Option<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver
+            .send((self.identity.clone(), message))
+            .await
+            .unwrap();
+        Ok(())
+    }
+
+    fn authenticated(&self, identity: &[u8]) -> Self {
+        Self {
+            identity: Some(Bytes::copy_from_slice(identity)),
+            ..self.clone()
+        }
+    }
+}
+
+/// Proves an identity by echoing the transcript after it, accepts a set of identities, and
+/// expects the same peer at every address.
+struct TestAuthenticator {
+    name: &'static str,
+    accepted: Vec<&'static str>,
+    expected: &'static str,
+}
+
+impl Authenticator for TestAuthenticator {
+    fn prove(&self, transcript: &[u8]) -> Bytes {
+        Bytes::from([self.name.as_bytes(), b":", transcript].concat())
+    }
+
+    fn verify(&self, transcript: &[u8], proof: &[u8]) -> Option<Bytes> {
+        self.accepted
+            .iter()
+            .find(|name| proof == [name.as_bytes(), b":", transcript].concat())
+            .map(|name| Bytes::from(*name))
+    }
+
+    fn expected(&self, _address: &SocketAddr) -> Option<Bytes> {
+        Some(Bytes::from(self.expected))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(200)
+    }
+}
+
+// Fixture
+fn authenticator(name: &'static str) -> SharedAuthenticator {
+    Arc::new(TestAuthenticator {
+        name,
+        accepted: vec!["alice", "bob"],
+        expected: "bob",
+    })
+}
+
+// Fixture
+fn spawn_receiver(address: SocketAddr) -> tokio::sync::mpsc::Receiver<(Option<Bytes>, Bytes)> {
+    let (tx, rx) = channel(1);
+    let handler = TestHandler {
+        deliver: tx,
+        identity: None,
+    };
+    let options = ReceiverOptions {
+        authenticator: Some(authenticator("bob")),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, handler, options);
+    rx
+}
+
+#[test]
+fn transcripts_bind_both_sides() {
+    let (initiator, responder) = ([1; KEY_SIZE], [2; KEY_SIZE]);
+    assert_ne!(
+        transcript(INITIATOR, &initiator, &responder),
+        transcript(RESPONDER, &initiator, &responder)
+    );
+    assert_ne!(
+        transcript(INITIATOR, &initiator, &responder),
+        transcript(INITIATOR, &responder, &initiator)
+    );
+    assert_eq!(
+        parse_banner(&frame(&[&initiator[..]])),
+        Some(&initiator[..])
+    );
+    assert_eq!(parse_banner(b"Hello, world!"), None);
+}
+
+#[test]
+fn authenticate_every_frame() {
+    // Make the codecs of both ends of an authenticated connection.
+    let codecs = |shared: [u8; 32]| {
+        let (initiator, responder) = (Session::default(), Session::default());
+        initiator.establish(INITIATOR, &shared, &[1; KEY_SIZE], &[2; KEY_SIZE]);
+        responder.establish(RESPONDER, &shared, &[1; KEY_SIZE], &[2; KEY_SIZE]);
+        (FrameCodec::new(initiator), FrameCodec::new(responder))
+    };
+    let (mut sender, mut receiver) = codecs([3; 32]);
+    let mut stream = BytesMut::new();
+    sender.encode(Bytes::from("Hello"), &mut stream).unwrap();
+    sender.encode(Bytes::from("world!"), &mut stream).unwrap();
+    let sent = stream.clone();
+
+    // Frames go through, in order.
+    assert_eq!(receiver.decode(&mut stream).unwrap().unwrap(), "Hello");
+    assert_eq!(receiver.decode(&mut stream).unwrap().unwrap(), "world!");
+
+    // Replayed frames are rejected.
+    assert!(receiver.decode(&mut sent.clone()).is_err());
+
+    // So are altered frames.
+    let (_, mut receiver) = codecs([3; 32]);
+    let mut altered = sent.clone();
+    altered[4] ^= 1;
+    assert!(receiver.decode(&mut altered).is_err());
+
+    // And frames tagged with the keys of another session.
+    let (_, mut receiver) = codecs([4; 32]);
+    assert!(receiver.decode(&mut sent.clone()).is_err());
+}
+
+#[tokio::test]
+async fn authenticate_peers() {
+    let address = "127.0.0.1:4300".parse::<SocketAddr>().unwrap();
+    let mut rx = spawn_receiver(address);
+    sleep(Duration::from_millis(50)).await;
+
+    // The handshake precedes the other banners: the handler learns who sent the message.
+    let mut sender = ReliableSender::with_compression(Compression::default())
+        .with_authenticator(Some(authenticator("alice")));
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+    let (identity, message) = rx.recv().await.unwrap();
+    assert_eq!(identity, Some(Bytes::from("alice")));
+    assert_eq!(message, "Hello");
+
+    // Peers that do not authenticate are still served, as unknown.
+    let mut sender = SimpleSender::new();
+    sender.send(address, Bytes::from("world!")).await;
+    assert_eq!(rx.recv().await.unwrap(), (None, Bytes::from("world!")));
+}
+
+#[tokio::test]
+async fn reject_unknown_peers() {
+    let address = "127.0.0.1:4301".parse::<SocketAddr>().unwrap();
+    let mut rx = spawn_receiver(address);
+    sleep(Duration::from_millis(50)).await;
+
+    // The receiver does not accept the identity of the peer: it closes the connection before
+    // reading any message.
+    let mut sender = SimpleSender::new().with_authenticator(Some(Arc::new(TestAuthenticator {
+        name: "mallory",
+        accepted: vec!["bob"],
+        expected: "bob",
+    })));
+    sender.send(address, Bytes::from("Hello")).await;
+    let delivered = timeout(Duration::from_millis(200), rx.recv()).await;
+    assert!(delivered.is_err());
+
+    // Nor do we send anything to a receiver whose identity we do not accept.
+    let mut sender = SimpleSender::new().with_authenticator(Some(Arc::new(TestAuthenticator {
+        name: "alice",
+        accepted: vec!["carol"],
+        expected: "carol",
+    })));
+    sender.send(address, Bytes::from("Hello")).await;
+    let delivered = timeout(Duration::from_millis(200), rx.recv()).await;
+    assert!(delivered.is_err());
+
+    // Nor to one proving another identity than the one we expect at its address, even if we
+    // accept it elsewhere.
+    let mut sender = SimpleSender::new().with_authenticator(Some(Arc::new(TestAuthenticator {
+        name: "alice",
+        accepted: vec!["bob", "carol"],
+        expected: "carol",
+    })));
+    sender.send(address, Bytes::from("Hello")).await;
+    let delivered = timeout(Duration::from_millis(200), rx.recv()).await;
+    assert!(delivered.is_err());
+}
+
+#[tokio::test]
+async fn decline_without_authenticator() {
+    // Make a network receiver that does not authenticate its peers.
+    let address = "127.0.0.1:4302".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let handler = TestHandler {
+        deliver: tx,
+        identity: None,
+    };
+    Receiver::spawn(address, handler, ReceiverOptions::default());
+    sleep(Duration::from_millis(50)).await;
+
+    // It declines the handshake, so the sender gives up on the connection.
+    let mut sender = SimpleSender::new().with_authenticator(Some(authenticator("alice")));
+    sender.send(address, Bytes::from("Hello")).await;
+    let delivered = timeout(Duration::from_millis(200), rx.recv()).await;
+    assert!(delivered.is_err());
+}
+
+#[tokio::test]
+async fn give_up_on_stalled_handshakes() {
+    let address = "127.0.0.1:4303".parse::<SocketAddr>().unwrap();
+    let _rx = spawn_receiver(address);
+    sleep(Duration::from_millis(50)).await;
+
+    // Open the handshake, and never complete it: the receiver closes the connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, FrameCodec::default());
+    let key = EphemeralKey::from(&EphemeralSecret::new(OsRng)).to_bytes();
+    transport.send(frame(&[&key[..]])).await.unwrap();
+    let start = Instant::now();
+    assert!(transport.next().await.unwrap().is_ok());
+    assert!(transport.next().await.is_none());
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,79 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{MessageHandler, Receiver, ReceiverOptions, ReliableSender, Writer};
+use async_trait::async_trait;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+// Fixture
+fn batch() -> Bytes {
+    let transactions: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i % 4; 512]).collect();
+    Bytes::from(bincode::serialize(&transactions).unwrap())
+}
+
+#[test]
+fn compress_decompress() {
+    let compression = Compression::default();
+    let data = batch();
+    let frame = compression.compress(&data);
+    assert!(frame.len() < data.len());
+    assert_eq!(Compression::decompress(frame).unwrap(), data);
+}
+
+#[test]
+fn small_frames_are_not_compressed() {
+    let compression = Compression::default();
+    let data = Bytes::from("Hello, world!");
+    let frame = compression.compress(&data);
+    assert_eq!(frame.len(), data.len() + 1);
+    assert_eq!(Compression::decompress(frame).unwrap(), data);
+}
+
+#[test]
+fn reject_oversized_frame() {
+    let mut frame = vec![LZ4];
+    frame.extend_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Compression::decompress(Bytes::from(frame)).is_err());
+}
+
+#[tokio::test]
+async fn compressed_round_trip() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(
+        address,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a compressible batch and a small message over a compressed connection.
+    let mut sender = ReliableSender::with_compression(Compression::default());
+    let data = batch();
+    let cancel_handler = sender.send(address, data.clone()).await;
+    assert!(cancel_handler.await.is_ok());
+    let cancel_handler = sender.send(address, Bytes::from("Hello, world!")).await;
+    assert!(cancel_handler.await.is_ok());
+
+    // Ensure the handler received both messages byte-for-byte.
+    assert_eq!(rx.recv().await.unwrap(), data);
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello, world!"));
+}
@@ -0,0 +1,71 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::time::{sleep, timeout};
+
+// Fixture
+fn parameters(min: usize, max: usize, initial: usize) -> ConcurrencyParameters {
+    ConcurrencyParameters {
+        min_connections: min,
+        max_connections: max,
+        initial_connections: initial,
+        target_latency: 20,
+    }
+}
+
+#[tokio::test]
+async fn raise_cap_when_fast() {
+    let limit = ConcurrencyLimit::new(parameters(1, 4, 2));
+
+    // The cap rises by one every `cap` fast dispatches.
+    limit.record(Duration::from_millis(1));
+    assert_eq!(limit.cap(), 2);
+    limit.record(Duration::from_millis(1));
+    assert_eq!(limit.cap(), 3);
+
+    // It never rises above the maximum.
+    for _ in 0..100 {
+        limit.record(Duration::from_millis(1));
+    }
+    assert_eq!(limit.cap(), 4);
+}
+
+#[tokio::test]
+async fn lower_cap_when_lagging() {
+    let limit = ConcurrencyLimit::new(parameters(2, 64, 64));
+
+    // A burst of lagging dispatches halves the cap once.
+    limit.record(Duration::from_millis(30));
+    limit.record(Duration::from_millis(30));
+    assert_eq!(limit.cap(), 32);
+
+    // Later lags halve it again, down to the minimum.
+    for _ in 0..6 {
+        sleep(Duration::from_millis(25)).await;
+        limit.record(Duration::from_millis(30));
+    }
+    assert_eq!(limit.cap(), 2);
+}
+
+#[tokio::test]
+async fn wait_for_permit() {
+    let limit = ConcurrencyLimit::new(parameters(1, 4, 1));
+    let permit = limit.acquire().await;
+    assert_eq!(limit.in_flight(), 1);
+
+    // No permit is left until the first one is dropped, or the cap rises.
+    assert!(timeout(Duration::from_millis(50), limit.acquire())
+        .await
+        .is_err());
+    drop(permit);
+    let _permit = timeout(Duration::from_millis(50), limit.acquire())
+        .await
+        .unwrap();
+    let waiter = {
+        let limit = limit.clone();
+        tokio::spawn(async move { limit.acquire().await })
+    };
+    limit.record(Duration::from_millis(1));
+    assert_eq!(limit.cap(), 2);
+    assert!(timeout(Duration::from_millis(50), waiter).await.is_ok());
+    assert_eq!(limit.in_flight(), 1);
+}
@@ -0,0 +1,162 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{Compression, MessageHandler, Receiver, ReceiverOptions, ReliableSender, SimpleSender};
+use async_trait::async_trait;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+/// Tags the messages it encodes, and only decodes tagged messages (of a supported version).
+struct TestTranscoder {
+    version: u8,
+}
+
+impl Transcoder for TestTranscoder {
+    fn encode(&self, message: &[u8]) -> Result<Bytes, WireError> {
+        let mut frame = vec![b'P', self.version];
+        frame.extend_from_slice(message);
+        Ok(Bytes::from(frame))
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<Bytes, WireError> {
+        match &frame[..] {
+            [b'P', version, message @ ..] => {
+                WireError::check_version(*version as u32)?;
+                Ok(Bytes::copy_from_slice(message))
+            }
+            _ => Err(WireError::MalformedMessage("Missing tag".to_string())),
+        }
+    }
+}
+
+// Fixture
+fn transcoder(version: u8) -> Option<SharedTranscoder> {
+    Some(Arc::new(TestTranscoder { version }))
+}
+
+#[test]
+fn parse_banner() {
+    assert_eq!(
+        Encoding::parse_banner(&Encoding::Proto.banner()),
+        Some(Encoding::Proto)
+    );
+    assert_eq!(
+        Encoding::parse_banner(&Encoding::Bincode.banner()),
+        Some(Encoding::Bincode)
+    );
+    assert_eq!(
+        Encoding::parse_banner(b"\xffnarwhal/encoding\x07"),
+        Some(Encoding::Bincode)
+    );
+    assert_eq!(Encoding::parse_banner(b"Hello, world!"), None);
+}
+
+#[test]
+fn check_version() {
+    assert!(WireError::check_version(WIRE_VERSION).is_ok());
+    assert!(matches!(
+        WireError::check_version(0),
+        Err(WireError::UnsupportedVersion(0, WIRE_VERSION))
+    ));
+    assert!(matches!(
+        WireError::check_version(WIRE_VERSION + 1),
+        Err(WireError::UnsupportedVersion(..))
+    ));
+}
+
+#[tokio::test]
+async fn transcoded_round_trip() {
+    // Make a network receiver accepting protobuf.
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let options = ReceiverOptions {
+        transcoder: transcoder(1),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Send messages over a transcoded (and compressed) connection.
+    let mut sender =
+        ReliableSender::with_compression(Compression::default()).with_transcoder(transcoder(1));
+    for message in ["Hello", "world!"] {
+        let cancel_handler = sender.send(address, Bytes::from(message)).await;
+        assert!(cancel_handler.await.is_ok());
+
+        // The handler receives the messages as they were before encoding.
+        assert_eq!(rx.recv().await.unwrap(), message);
+    }
+}
+
+#[tokio::test]
+async fn fall_back_to_bincode() {
+    // Make a network receiver that does not accept protobuf.
+    let address = "127.0.0.1:4201".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(
+        address,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // The sender offers protobuf, but sends its messages as they are.
+    let mut sender = SimpleSender::new().with_transcoder(transcoder(1));
+    sender.send(address, Bytes::from("Hello, world!")).await;
+    assert_eq!(rx.recv().await.unwrap(), "Hello, world!");
+}
+
+#[tokio::test]
+async fn reject_unsupported_version() {
+    // Make a network receiver accepting protobuf.
+    let address = "127.0.0.1:4202".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let options = ReceiverOptions {
+        transcoder: transcoder(1),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // A newer peer negotiates protobuf, but we cannot decode its messages: the connection is
+    // closed without the handler seeing them.
+    let mut sender = ReliableSender::new().with_transcoder(transcoder(WIRE_VERSION as u8 + 1));
+    let cancel_handler = sender.send(address, Bytes::from("Hello, world!")).await;
+    let delivered = timeout(Duration::from_millis(200), rx.recv()).await;
+    assert!(delivered.is_err());
+    drop(cancel_handler);
+}
+
+#[test]
+fn deserialize_within_limit() {
+    let message = (vec![1u8, 2, 3], String::from("narwhal"));
+    let serialized = bincode::serialize(&message).unwrap();
+    let limit = serialized.len() as u64;
+    let deserialized: (Vec<u8>, String) = deserialize_bounded(&serialized, limit).unwrap();
+    assert_eq!(deserialized, message);
+    assert!(deserialize_bounded::<(Vec<u8>, String)>(&serialized, limit - 1).is_err());
+}
+
+#[test]
+fn reject_oversized_claims() {
+    // A frame of 8 bytes claiming a string of 2^60 bytes fails before allocating anything.
+    let frame = (1u64 << 60).to_le_bytes();
+    match deserialize_bounded::<String>(&frame, 1_000) {
+        Err(e) => assert!(matches!(*e, bincode::ErrorKind::SizeLimit)),
+        Ok(_) => panic!("Oversized claim accepted"),
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Fixture
+fn rules(allow: &[&str], deny: &[&str], default_allow: bool) -> IpRules {
+    IpRules {
+        allow: allow.iter().map(|x| x.parse().unwrap()).collect(),
+        deny: deny.iter().map(|x| x.parse().unwrap()).collect(),
+        default_allow,
+    }
+}
+
+#[test]
+fn deny_takes_precedence() {
+    let rules = rules(&["10.0.0.0/8"], &["10.1.0.0/16"], false);
+    assert!(rules.allows("10.2.3.4".parse().unwrap()));
+    assert!(!rules.allows("10.1.3.4".parse().unwrap()));
+}
+
+#[test]
+fn unmatched_peers_follow_default() {
+    let ip = "192.168.1.7".parse().unwrap();
+    assert!(rules(&["10.0.0.0/8"], &["172.16.0.0/12"], true).allows(ip));
+    assert!(!rules(&["10.0.0.0/8"], &["172.16.0.0/12"], false).allows(ip));
+    assert!(IpRules::default().allows(ip));
+}
+
+#[test]
+fn match_ipv4_mapped_peers() {
+    let rules = rules(&[], &["127.0.0.1/32"], true);
+    assert!(!rules.allows("::ffff:127.0.0.1".parse().unwrap()));
+    assert!(rules.allows("::1".parse().unwrap()));
+}
+
+#[test]
+fn deserialize_rules() {
+    let json = r#"{ "deny": ["10.0.0.0/8", "fd00::/8"], "default_allow": false }"#;
+    let rules: IpRules = serde_json::from_str(json).unwrap();
+    assert_eq!(rules.deny.len(), 2);
+    assert!(rules.allow.is_empty());
+    assert!(!rules.default_allow);
+    assert!(serde_json::from_str::<IpRules>(r#"{ "allow": ["10.0.0.1"] }"#).is_err());
+}
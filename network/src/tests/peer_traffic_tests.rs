@@ -0,0 +1,73 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::receiver::{MessageHandler, Receiver, ReceiverOptions, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::net::TcpSocket;
+use tokio::time::sleep;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[derive(Clone)]
+struct SilentHandler;
+
+#[async_trait]
+impl MessageHandler for SilentHandler {
+    async fn dispatch(&self, _writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+// Fixture: sends frames of the specified sizes from the specified local IP.
+async fn send_from(source: &str, address: SocketAddr, sizes: &[usize]) {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket
+        .bind(format!("{}:0", source).parse().unwrap())
+        .unwrap();
+    let stream = socket.connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    for size in sizes {
+        transport.send(Bytes::from(vec![0u8; *size])).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn count_bytes_per_peer() {
+    let address = "127.0.0.1:4003".parse::<SocketAddr>().unwrap();
+    let traffic = PeerTraffic::default();
+    let options = ReceiverOptions {
+        traffic: Some(traffic.clone()),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, SilentHandler, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Two peers send known volumes, one of them over two connections.
+    send_from("127.0.0.1", address, &[100, 200]).await;
+    send_from("127.0.0.2", address, &[1_000]).await;
+    send_from("127.0.0.1", address, &[50]).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let expected = vec![
+        ("127.0.0.2".parse().unwrap(), 1_000),
+        ("127.0.0.1".parse().unwrap(), 350),
+    ];
+    assert_eq!(traffic.snapshot(), expected);
+}
+
+#[test]
+fn trim_lightest_peers() {
+    let traffic = PeerTraffic::new(/* capacity */ 2);
+    for i in 1..=5u8 {
+        traffic.record(IpAddr::from([10, 0, 0, i]), 100 * i as usize);
+    }
+
+    // Recording the fifth peer trimmed the map down to the two heaviest peers.
+    let expected = vec![
+        (IpAddr::from([10, 0, 0, 5]), 500),
+        (IpAddr::from([10, 0, 0, 4]), 400),
+    ];
+    assert_eq!(traffic.snapshot(), expected);
+}
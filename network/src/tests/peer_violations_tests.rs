@@ -0,0 +1,29 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn count_violations_per_peer() {
+    let violations = PeerViolations::default();
+    let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+    assert_eq!(violations.record(a, "test"), 1);
+    assert_eq!(violations.record(a, "test"), 2);
+    assert_eq!(violations.record(b, "test"), 1);
+    assert_eq!(violations.count(&a), 2);
+    assert_eq!(violations.count(&"10.0.0.3".parse().unwrap()), 0);
+    assert_eq!(violations.snapshot(), vec![(a, 2), (b, 1)]);
+}
+
+#[test]
+fn trim_least_violating_peers() {
+    let violations = PeerViolations::new(1);
+    let peers: Vec<IpAddr> = (1..=3)
+        .map(|i| format!("10.0.0.{}", i).parse().unwrap())
+        .collect();
+    violations.record(peers[0], "test");
+    violations.record(peers[0], "test");
+    violations.record(peers[1], "test");
+
+    // Recording the third peer trimmed the map down to the worst offender.
+    violations.record(peers[2], "test");
+    assert_eq!(violations.snapshot(), vec![(peers[0], 2)]);
+}
@@ -1,9 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use futures::sink::SinkExt as _;
+use crate::concurrency::ConcurrencyParameters;
+use bytes::BytesMut;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::codec::{Decoder as _, Encoder as _, LengthDelimitedCodec};
 
 #[derive(Clone)]
 struct TestHandler {
@@ -25,12 +30,33 @@ impl MessageHandler for TestHandler {
     }
 }
 
+// Fixture: a listener that fails with the specified errors before accepting connections.
+struct FlakyListener {
+    inner: TcpListener,
+    errors: Mutex<VecDeque<io::Error>>,
+}
+
+#[async_trait]
+impl Listener for FlakyListener {
+    async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let error = self.errors.lock().unwrap().pop_front();
+        match error {
+            Some(e) => Err(e),
+            None => self.inner.accept().await,
+        }
+    }
+}
+
 #[tokio::test]
 async fn receive() {
     // Make the network receiver.
     let address = "127.0.0.1:4000".parse::<SocketAddr>().unwrap();
     let (tx, mut rx) = channel(1);
-    Receiver::spawn(address, TestHandler { deliver: tx });
+    Receiver::spawn(
+        address,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    );
     sleep(Duration::from_millis(50)).await;
 
     // Send a message.
@@ -46,3 +72,361 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[tokio::test]
+async fn accept_after_transient_errors() {
+    // Make a listener that first runs out of file descriptors and sees an aborted handshake.
+    let address = "127.0.0.1:4001".parse::<SocketAddr>().unwrap();
+    let errors = vec![
+        io::Error::from_raw_os_error(24), // EMFILE
+        io::Error::from(io::ErrorKind::ConnectionAborted),
+    ];
+    let listener = FlakyListener {
+        inner: TcpListener::bind(address).await.unwrap(),
+        errors: Mutex::new(errors.into_iter().collect()),
+    };
+    let (tx, mut rx) = channel(1);
+    let handle = tokio::spawn(Receiver::accept_loop(
+        listener,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    ));
+
+    // The receiver keeps accepting connections.
+    for sent in ["Hello", "world!"] {
+        let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(bytes).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), sent);
+    }
+    assert!(!handle.is_finished());
+}
+
+#[tokio::test]
+async fn stop_on_fatal_error() {
+    let address = "127.0.0.1:4002".parse::<SocketAddr>().unwrap();
+    let errors = vec![io::Error::from(io::ErrorKind::InvalidInput)];
+    let listener = FlakyListener {
+        inner: TcpListener::bind(address).await.unwrap(),
+        errors: Mutex::new(errors.into_iter().collect()),
+    };
+    let (tx, _rx) = channel(1);
+    match Receiver::accept_loop(
+        listener,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    )
+    .await
+    {
+        NetworkError::FailedToListen(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+        e => panic!("Unexpected error: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn pipelined_after_banner() {
+    let address = "127.0.0.1:4004".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(
+        address,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message right after the banner, without waiting for the reply.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Compression::banner()).await.unwrap();
+    transport
+        .send(Compression::default().compress(&bytes))
+        .await
+        .unwrap();
+
+    // The message is delivered, and the replies arrive in order.
+    assert_eq!(rx.recv().await.unwrap(), sent);
+    let reply = transport.next().await.unwrap().unwrap();
+    assert_eq!(Compression::parse_banner(&reply), Some(true));
+    assert_eq!(transport.next().await.unwrap().unwrap(), "Ack");
+}
+
+#[tokio::test]
+async fn pipelined_after_failed_banner_reply() {
+    // Connect before the receiver accepts, so that the peer resets the connection before we
+    // reply to its banner.
+    let address = "127.0.0.1:4005".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    stream.set_zero_linger().unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Compression::banner()).await.unwrap();
+    transport
+        .send(Compression::default().compress(&bytes))
+        .await
+        .unwrap();
+    drop(transport);
+    sleep(Duration::from_millis(50)).await;
+
+    // The message received before the reset is still delivered.
+    let (tx, mut rx) = channel(1);
+    tokio::spawn(Receiver::accept_loop(
+        listener,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    ));
+    assert_eq!(rx.recv().await.unwrap(), sent);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn listen_backlog() {
+    // Make a listener that never accepts. Linux completes `backlog + 1` connections and drops the
+    // later connection attempts.
+    let address = "127.0.0.1:4006".parse::<SocketAddr>().unwrap();
+    let backlog = 4;
+    let _listener = Receiver::<TestHandler>::bind(address, backlog).unwrap();
+
+    let mut connections = Vec::new();
+    for _ in 0..backlog + 1 {
+        let connect = TcpStream::connect(address);
+        let stream = tokio::time::timeout(Duration::from_millis(500), connect).await;
+        connections.push(stream.unwrap().unwrap());
+    }
+    let connect = TcpStream::connect(address);
+    let stream = tokio::time::timeout(Duration::from_millis(500), connect).await;
+    assert!(stream.is_err(), "The backlog should be full");
+}
+
+// Fixture: a handler replying to each query with its number, without flushing the reply.
+#[derive(Clone)]
+struct FeedHandler;
+
+#[async_trait]
+impl MessageHandler for FeedHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let query: u32 = bincode::deserialize(&message).unwrap();
+        let reply = bincode::serialize(&format!("Reply {}", query)).unwrap();
+        writer.feed(Bytes::from(reply)).await?;
+        Ok(())
+    }
+}
+
+// Fixture: sends the queries in a single write, and returns the replies along with how many reads
+// it took to receive them and how long it took to receive the first one.
+async fn query(address: SocketAddr, queries: u32) -> (Vec<String>, usize, Duration) {
+    let mut codec = LengthDelimitedCodec::new();
+    let mut buffer = BytesMut::new();
+    for i in 0..queries {
+        let bytes = Bytes::from(bincode::serialize(&i).unwrap());
+        codec.encode(bytes, &mut buffer).unwrap();
+    }
+    let stream = TcpStream::connect(address).await.unwrap();
+    stream.writable().await.unwrap();
+    assert_eq!(stream.try_write(&buffer).unwrap(), buffer.len());
+    let start = Instant::now();
+    let mut first = None;
+
+    let mut replies = Vec::new();
+    let mut reads = 0;
+    let mut buffer = BytesMut::new();
+    let mut chunk = [0u8; 65_536];
+    while replies.len() < queries as usize {
+        stream.readable().await.unwrap();
+        let n = match stream.try_read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => panic!("Failed to read replies: {}", e),
+        };
+        assert!(n > 0, "Connection closed before all replies");
+        reads += 1;
+        first.get_or_insert_with(|| start.elapsed());
+        buffer.extend_from_slice(&chunk[..n]);
+        while let Some(frame) = codec.decode(&mut buffer).unwrap() {
+            replies.push(bincode::deserialize(&frame).unwrap());
+        }
+    }
+    (replies, reads, first.unwrap())
+}
+
+#[tokio::test]
+async fn coalesce_replies() {
+    let address = "127.0.0.1:4007".parse::<SocketAddr>().unwrap();
+    let flush = FlushWindow {
+        window: Duration::from_millis(100),
+        max_pending: 1_000,
+        timeout: Duration::from_millis(1_000),
+    };
+    let options = ReceiverOptions {
+        flush: Some(flush),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, FeedHandler, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // The replies to a burst of queries are held for the window, then written together, in order.
+    let (replies, reads, delay) = query(address, 100).await;
+    let expected: Vec<_> = (0..100).map(|i| format!("Reply {}", i)).collect();
+    assert_eq!(replies, expected);
+    assert!(reads <= 2, "Replies received in {} reads", reads);
+    assert!(
+        delay >= Duration::from_millis(90),
+        "Replies received after {:?}",
+        delay
+    );
+}
+
+#[tokio::test]
+async fn flush_when_enough_pending() {
+    let address = "127.0.0.1:4008".parse::<SocketAddr>().unwrap();
+    let flush = FlushWindow {
+        window: Duration::from_secs(60),
+        max_pending: 10,
+        timeout: Duration::from_millis(1_000),
+    };
+    let options = ReceiverOptions {
+        flush: Some(flush),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(address, FeedHandler, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // We do not wait for the window when enough messages are pending.
+    let replies = tokio::time::timeout(Duration::from_millis(1_000), query(address, 10)).await;
+    let expected: Vec<_> = (0..10).map(|i| format!("Reply {}", i)).collect();
+    assert_eq!(replies.unwrap().0, expected);
+}
+
+// Fixture
+/// Sends a message to the receiver from the specified IP, and returns whether it was delivered.
+async fn delivered_from(
+    address: SocketAddr,
+    ip: &str,
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+) -> bool {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(format!("{}:0", ip).parse().unwrap()).unwrap();
+    let stream = socket.connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let bytes = Bytes::from(bincode::serialize(ip).unwrap());
+    let _ = transport.send(bytes).await;
+    match tokio::time::timeout(Duration::from_millis(200), rx.recv()).await {
+        Ok(message) => {
+            assert_eq!(message.unwrap(), ip);
+            true
+        }
+        Err(_) => {
+            // Rejected peers are disconnected without a reply.
+            assert!(!matches!(transport.next().await, Some(Ok(_))));
+            false
+        }
+    }
+}
+
+#[tokio::test]
+async fn filter_peers_by_ip() {
+    for (port, default_allow) in [(4009, true), (4010, false)] {
+        let address = format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap();
+        let rules = IpRules {
+            allow: vec!["127.0.0.0/29".parse().unwrap()],
+            deny: vec!["127.0.0.2/32".parse().unwrap()],
+            default_allow,
+        };
+        let (tx, mut rx) = channel(1);
+        tokio::spawn(Receiver::accept_loop(
+            TcpListener::bind(address).await.unwrap(),
+            TestHandler { deliver: tx },
+            ReceiverOptions {
+                rules: Some(rules),
+                ..ReceiverOptions::default()
+            },
+        ));
+
+        // An allowed IP, an IP both allowed and denied, and an IP matching no rule.
+        assert!(delivered_from(address, "127.0.0.1", &mut rx).await);
+        assert!(!delivered_from(address, "127.0.0.2", &mut rx).await);
+        assert_eq!(
+            delivered_from(address, "127.0.0.9", &mut rx).await,
+            default_allow
+        );
+    }
+}
+
+// Fixture: a handler acknowledging each message after a (controllable) delay.
+#[derive(Clone)]
+struct SlowHandler {
+    delay: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl MessageHandler for SlowHandler {
+    async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        sleep(Duration::from_millis(self.delay.load(Ordering::Relaxed))).await;
+        writer.send(Bytes::from("Ack")).await?;
+        Ok(())
+    }
+}
+
+// Fixture
+/// Sends a message and waits (up to a timeout) for its acknowledgement.
+async fn acknowledged(transport: &mut Framed<TcpStream, LengthDelimitedCodec>) -> bool {
+    transport.send(Bytes::from("Hello")).await.unwrap();
+    matches!(
+        tokio::time::timeout(Duration::from_millis(200), transport.next()).await,
+        Ok(Some(Ok(_)))
+    )
+}
+
+#[tokio::test]
+async fn adapt_concurrency_to_latency() {
+    let address = "127.0.0.1:4011".parse::<SocketAddr>().unwrap();
+    let delay = Arc::new(AtomicU64::new(0));
+    let limit = ConcurrencyLimit::new(ConcurrencyParameters {
+        min_connections: 1,
+        max_connections: 8,
+        initial_connections: 2,
+        target_latency: 20,
+    });
+    let options = ReceiverOptions {
+        limit: Some(limit.clone()),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(
+        address,
+        SlowHandler {
+            delay: delay.clone(),
+        },
+        options,
+    );
+    sleep(Duration::from_millis(50)).await;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut first = Framed::new(stream, LengthDelimitedCodec::new());
+
+    // The cap rises while the handler is fast.
+    for _ in 0..20 {
+        assert!(acknowledged(&mut first).await);
+    }
+    assert_eq!(limit.cap(), 7);
+
+    // It falls (down to the minimum) when the handler lags.
+    delay.store(50, Ordering::Relaxed);
+    for _ in 0..3 {
+        assert!(acknowledged(&mut first).await);
+    }
+    assert_eq!(limit.cap(), 1);
+
+    // We then serve a single connection at once: the next one waits for the first to close.
+    delay.store(0, Ordering::Relaxed);
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut second = Framed::new(stream, LengthDelimitedCodec::new());
+    assert!(!acknowledged(&mut second).await);
+    drop(first);
+    assert!(matches!(
+        tokio::time::timeout(Duration::from_millis(200), second.next()).await,
+        Ok(Some(Ok(_)))
+    ));
+}
@@ -2,6 +2,8 @@
 use super::*;
 use crate::common::listener;
 use futures::future::try_join_all;
+use tokio::net::TcpListener;
+use tokio_util::codec::LengthDelimitedCodec;
 
 #[tokio::test]
 async fn send() {
@@ -65,3 +67,31 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn resend_after_peer_restarts() {
+    // Run a TCP server that crashes after receiving the message, without acknowledging it.
+    let address = "127.0.0.1:5301".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let crashed = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        assert_eq!(transport.next().await.unwrap().unwrap(), message);
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Make the network sender and send the message.
+    let mut sender = ReliableSender::new();
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    assert!(crashed.await.is_ok());
+
+    // Restart the server: we reconnect and send the message again.
+    let handle = listener(address, message.to_string());
+
+    // Ensure we get back an acknowledgement.
+    assert!(cancel_handler.await.is_ok());
+
+    // Ensure the restarted server received the message (ie. it did not panic).
+    assert!(handle.await.is_ok());
+}
@@ -1,26 +1,47 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::BufMut as _;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use clap::{crate_name, crate_version, App, AppSettings};
+use client::{now_micros, CommittedBatch, LatencyRecorder, NarwhalClient, DEFAULT_MAX_IN_FLIGHT};
 use env_logger::Env;
-use futures::future::join_all;
+use futures::future::{self, join_all};
 use futures::sink::SinkExt as _;
+use futures::stream::{FuturesUnordered, StreamExt as _};
 use log::{info, warn};
 use rand::Rng;
+use std::convert::TryInto as _;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, channel};
+use tokio::sync::oneshot;
 use tokio::time::{interval, sleep, Duration, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+mod payload;
+
+use crate::payload::{PayloadFile, PayloadSource, SizeDistribution};
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .about("Benchmark client for Narwhal and Tusk.")
         .args_from_usage("<ADDR> 'The network address of the node where to send txs'")
-        .args_from_usage("--size=<INT> 'The size of each transaction in bytes'")
+        .args_from_usage("--size=[INT] 'The size of each transaction in bytes (same as --size-dist fixed:<INT>)'")
+        .args_from_usage("--size-dist=[DIST] 'How transaction sizes are distributed: fixed:<n>, uniform:<min>:<max>, or lognormal:<mu>:<sigma>'")
+        .args_from_usage("--input-file=[FILE] 'Replay the (length-prefixed) transaction payloads of this file, in a loop'")
         .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to send the transactions'")
+        .args_from_usage("--rate-pattern=[PATTERN] 'How the rate evolves: constant (at --rate), ramp:<start>:<end>:<duration_s>, burst:<base>:<peak>:<period_s>:<duty>, or sine:<mean>:<amplitude>:<period_s>'")
+        .args_from_usage("--commits=[ADDR] 'Subscribe to the committed batches of this node to measure the latency of our transactions'")
+        .args_from_usage("--deadline=[INT] 'How long (ms) a transaction may take to commit before counting it as lost (default 30000)'")
+        .args_from_usage("--duration=[INT] 'Stop and print the latency summary after this many seconds'")
+        .args_from_usage("--csv=[FILE] 'Dump the latency of every committed transaction to this file'")
         .args_from_usage("--nodes=[ADDR]... 'Network addresses that must be reachable before starting the benchmark.'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
@@ -34,115 +55,498 @@ async fn main() -> Result<()> {
         .unwrap()
         .parse::<SocketAddr>()
         .context("Invalid socket address format")?;
-    let size = matches
-        .value_of("size")
-        .unwrap()
-        .parse::<usize>()
-        .context("The size of transactions must be a non-negative integer")?;
+    let payloads = match (
+        matches.value_of("input-file"),
+        matches.value_of("size-dist"),
+        matches.value_of("size"),
+    ) {
+        (Some(path), _, _) => PayloadSource::File(
+            PayloadFile::open(path).context(format!("Failed to read {}", path))?,
+            Vec::new(),
+        ),
+        (None, Some(distribution), _) => PayloadSource::Synthetic(distribution.parse()?),
+        (None, None, Some(size)) => PayloadSource::Synthetic(SizeDistribution::Fixed(
+            size.parse()
+                .context("The size of transactions must be a non-negative integer")?,
+        )),
+        (None, None, None) => bail!("Specify either --size, --size-dist, or --input-file"),
+    };
     let rate = matches
         .value_of("rate")
         .unwrap()
         .parse::<u64>()
         .context("The rate of transactions must be a non-negative integer")?;
+    let pattern = match matches
+        .value_of("rate-pattern")
+        .unwrap_or("constant")
+        .parse::<RatePattern>()?
+    {
+        RatePattern::Constant(_) => RatePattern::Constant(rate as f64),
+        pattern => pattern,
+    };
     let nodes = matches
         .values_of("nodes")
         .unwrap_or_default()
-        .into_iter()
         .map(|x| x.parse::<SocketAddr>())
         .collect::<Result<Vec<_>, _>>()
         .context("Invalid socket address format")?;
 
+    let commits = matches
+        .value_of("commits")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid socket address format")?;
+    let deadline = matches
+        .value_of("deadline")
+        .map(|x| x.parse::<u64>())
+        .transpose()
+        .context("The deadline must be a non-negative integer")?
+        .unwrap_or(DEFAULT_DEADLINE);
+    let duration = matches
+        .value_of("duration")
+        .map(|x| x.parse::<u64>())
+        .transpose()
+        .context("The duration must be a non-negative integer")?;
+    let csv = matches.value_of("csv");
+
     info!("Node address: {}", target);
 
     // NOTE: This log entry is used to compute performance.
-    info!("Transactions size: {} B", size);
+    info!("Transactions size: {} B", payloads.mean() as u64);
 
     // NOTE: This log entry is used to compute performance.
-    info!("Transactions rate: {} tx/s", rate);
+    info!("Transactions rate: {} tx/s", pattern.mean() as u64);
+    info!("Rate pattern: {:?}", pattern);
+
+    // Track the commit of our transactions (if requested).
+    let (tx_sent, rx_sent) = channel(CHANNEL_CAPACITY);
+    let tracker = commits.map(|address| (address, LatencyTracker::new(deadline)));
 
     let client = Client {
         target,
-        size,
-        rate,
+        pattern,
         nodes,
+        tx_sent: tracker.as_ref().map(|_| tx_sent),
+        sent_transactions: AtomicU64::new(0),
+        sent_bytes: AtomicU64::new(0),
     };
 
     // Wait for all nodes to be online and synchronized.
     client.wait().await;
 
-    // Start the benchmark.
-    client.send().await.context("Failed to submit transactions")
+    // Subscribe to the commits before sending anything.
+    let (tx_stop, rx_stop) = oneshot::channel();
+    let tracker =
+        tracker.map(|(address, tracker)| tokio::spawn(tracker.run(address, rx_sent, rx_stop)));
+
+    // Start the benchmark. It runs until interrupted, or for the requested duration.
+    let start = Instant::now();
+    let stop = async {
+        match duration {
+            Some(seconds) => sleep(Duration::from_secs(seconds)).await,
+            None => future::pending().await,
+        }
+    };
+    let result = tokio::select! {
+        result = client.send(payloads) => result.context("Failed to submit transactions"),
+        _ = tokio::signal::ctrl_c() => Ok(()),
+        () = stop => Ok(()),
+    };
+
+    // Print what we sent, and the latency of our transactions.
+    client.summary(start.elapsed());
+    if let Some(handle) = tracker {
+        let _ = tx_stop.send(());
+        let tracker = handle.await.context("Failed to track commits")??;
+        tracker.summary(start.elapsed());
+        if let Some(path) = csv {
+            tracker
+                .dump(path)
+                .context(format!("Failed to write {}", path))?;
+        }
+    }
+    result
+}
+
+/// The default time (in ms) a transaction may take to commit before we count it as lost.
+const DEFAULT_DEADLINE: u64 = 30_000;
+
+/// The capacity of the channel reporting the transactions we sent to the `LatencyTracker`.
+const CHANNEL_CAPACITY: usize = 1_000;
+
+/// The minimum size of the transactions embedding their send time: a one-byte kind (sample or
+/// standard), an 8-byte id, and an 8-byte timestamp (in us since the UNIX epoch).
+const TIMESTAMPED_SIZE: usize = 17;
+
+/// Identifies one of our transactions: its kind and its id.
+type TxKey = (u8, u64);
+
+/// Reads the key of one of our transactions (they embed their send time after it).
+fn parse_key(tx: &[u8]) -> Option<TxKey> {
+    if tx.len() < TIMESTAMPED_SIZE {
+        return None;
+    }
+    let id = u64::from_be_bytes(tx[1..9].try_into().ok()?);
+    Some((tx[0], id))
+}
+
+/// Follows the committed batches of a node to measure the latency of our own transactions.
+struct LatencyTracker {
+    /// How long (in ms) a transaction may take to commit before we count it as lost.
+    deadline: u64,
+    recorder: LatencyRecorder<TxKey>,
+}
+
+impl LatencyTracker {
+    fn new(deadline: u64) -> Self {
+        Self {
+            deadline,
+            recorder: LatencyRecorder::new(parse_key, Duration::from_millis(deadline)),
+        }
+    }
+
+    /// Follows the commits until told to stop.
+    async fn run(
+        mut self,
+        address: SocketAddr,
+        mut rx_sent: mpsc::Receiver<Vec<(TxKey, u64)>>,
+        mut rx_stop: oneshot::Receiver<()>,
+    ) -> Result<Self> {
+        let stream = TcpStream::connect(address)
+            .await
+            .context(format!("failed to connect to {}", address))?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport
+            .send(Bytes::from("subscribe"))
+            .await
+            .context("Failed to subscribe to commits")?;
+        info!("Subscribed to the commits of {}", address);
+
+        let mut open = true;
+        let mut sweep = interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                Some(sent) = rx_sent.recv() => {
+                    for (key, timestamp) in sent {
+                        self.recorder.submitted(key, timestamp);
+                    }
+                },
+                frame = transport.next(), if open => match frame {
+                    Some(Ok(serialized)) => self.process(&serialized),
+                    Some(Err(e)) => {
+                        warn!("Failed to receive commits: {}", e);
+                        open = false;
+                    }
+                    None => {
+                        warn!("Commit stream closed by {}", address);
+                        open = false;
+                    }
+                },
+                _ = sweep.tick() => self.recorder.expire(now_micros()),
+                _ = &mut rx_stop => break,
+            }
+        }
+        self.recorder.expire(now_micros());
+        Ok(self)
+    }
+
+    /// Records the latency of our transactions in a committed batch.
+    fn process(&mut self, serialized: &[u8]) {
+        match bincode::deserialize::<CommittedBatch>(serialized) {
+            Ok(batch) => self.recorder.committed(&batch, now_micros()),
+            Err(e) => warn!("Failed to deserialize committed batch: {}", e),
+        }
+    }
+
+    /// Prints the latency percentiles and the throughput of our committed transactions.
+    fn summary(&self, elapsed: Duration) {
+        let committed = self.recorder.commits().len();
+        info!("Committed transactions: {}", committed);
+        info!(
+            "Lost transactions (not committed within {} ms): {}",
+            self.deadline,
+            self.recorder.lost()
+        );
+        info!(
+            "Transactions still in flight: {}",
+            self.recorder.in_flight()
+        );
+        info!(
+            "Commit throughput: {:.0} tx/s",
+            committed as f64 / elapsed.as_secs_f64()
+        );
+        if let Some(x) = self.recorder.percentiles() {
+            let ms = |x: Duration| x.as_secs_f64() * 1_000.0;
+            info!(
+                "Commit latency: p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms",
+                ms(x.p50),
+                ms(x.p90),
+                ms(x.p99)
+            );
+        }
+    }
+
+    /// Writes the latency of every committed transaction to a CSV file.
+    fn dump(&self, path: &str) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "kind,id,sent_us,latency_us")?;
+        for ((kind, id), sent, latency) in self.recorder.commits() {
+            writeln!(file, "{},{},{},{}", kind, id, sent, latency)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// How the input rate (in tx/s) evolves over the benchmark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RatePattern {
+    /// Always the same rate.
+    Constant(f64),
+    /// Goes linearly from `start` to `end` over `duration` seconds, then stays at `end`.
+    Ramp { start: f64, end: f64, duration: f64 },
+    /// Sends at `peak` during the first `duty` fraction of every period, and at `base` otherwise.
+    Burst {
+        base: f64,
+        peak: f64,
+        period: f64,
+        duty: f64,
+    },
+    /// Oscillates around `mean` with the given amplitude and period.
+    Sine {
+        mean: f64,
+        amplitude: f64,
+        period: f64,
+    },
+}
+
+impl RatePattern {
+    /// The target rate `elapsed` seconds after the start of the benchmark.
+    fn rate_at(&self, elapsed: f64) -> f64 {
+        let rate = match *self {
+            Self::Constant(rate) => rate,
+            Self::Ramp {
+                start,
+                end,
+                duration,
+            } => {
+                if elapsed >= duration {
+                    end
+                } else {
+                    start + (end - start) * elapsed / duration
+                }
+            }
+            Self::Burst {
+                base,
+                peak,
+                period,
+                duty,
+            } => {
+                if (elapsed % period) < duty * period {
+                    peak
+                } else {
+                    base
+                }
+            }
+            Self::Sine {
+                mean,
+                amplitude,
+                period,
+            } => mean + amplitude * (2.0 * PI * elapsed / period).sin(),
+        };
+        rate.max(0.0)
+    }
+
+    /// The long-run average rate.
+    fn mean(&self) -> f64 {
+        match *self {
+            Self::Constant(rate) => rate,
+            Self::Ramp { end, .. } => end,
+            Self::Burst {
+                base, peak, duty, ..
+            } => peak * duty + base * (1.0 - duty),
+            Self::Sine { mean, .. } => mean,
+        }
+    }
+}
+
+impl FromStr for RatePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let values = parts
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .context(format!("Invalid rate pattern parameters: {}", s))?;
+        if values.iter().any(|x| !x.is_finite() || *x < 0.0) {
+            anyhow::bail!(
+                "Rate pattern parameters must be non-negative numbers: {}",
+                s
+            );
+        }
+
+        let pattern = match (kind, values.as_slice()) {
+            // The actual rate is set by `--rate`.
+            ("constant", []) => Self::Constant(0.0),
+            ("ramp", &[start, end, duration]) if duration > 0.0 => Self::Ramp {
+                start,
+                end,
+                duration,
+            },
+            ("burst", &[base, peak, period, duty]) if period > 0.0 && duty <= 1.0 => Self::Burst {
+                base,
+                peak,
+                period,
+                duty,
+            },
+            ("sine", &[mean, amplitude, period]) if period > 0.0 => Self::Sine {
+                mean,
+                amplitude,
+                period,
+            },
+            _ => anyhow::bail!("Invalid rate pattern: {}", s),
+        };
+        Ok(pattern)
+    }
 }
 
 struct Client {
     target: SocketAddr,
-    size: usize,
-    rate: u64,
+    pattern: RatePattern,
     nodes: Vec<SocketAddr>,
+    /// Reports the transactions we sent to the `LatencyTracker` (if any).
+    tx_sent: Option<mpsc::Sender<Vec<(TxKey, u64)>>>,
+    /// The number of transactions we sent.
+    sent_transactions: AtomicU64,
+    /// The number of bytes we sent (in transactions).
+    sent_bytes: AtomicU64,
 }
 
 impl Client {
-    pub async fn send(&self) -> Result<()> {
+    pub async fn send(&self, mut payloads: PayloadSource) -> Result<()> {
         const PRECISION: u64 = 20; // Sample precision.
         const BURST_DURATION: u64 = 1000 / PRECISION;
 
-        // The transaction size must be at least 16 bytes to ensure all txs are different.
-        if self.size < 9 {
-            return Err(anyhow::Error::msg(
-                "Transaction size must be at least 9 bytes",
-            ));
-        }
-
         // Connect to the mempool.
-        let stream = TcpStream::connect(self.target)
+        let client = NarwhalClient::connect(vec![self.target])
             .await
             .context(format!("failed to connect to {}", self.target))?;
 
-        // Submit all transactions.
-        let burst = self.rate / PRECISION;
-        let mut tx = BytesMut::with_capacity(self.size);
+        // Submit all transactions. Every tick, we send the transactions that became due since the
+        // previous tick (according to the time actually elapsed), carrying over the fractions.
+        let mut tx = BytesMut::new();
         let mut counter = 0;
         let mut r = rand::thread_rng().gen();
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
-        let interval = interval(Duration::from_millis(BURST_DURATION));
-        tokio::pin!(interval);
+        let mut in_flight = FuturesUnordered::new();
+        let mut interval = interval(Duration::from_millis(BURST_DURATION));
 
         // NOTE: This log entry is used to compute performance.
         info!("Start sending transactions");
 
-        'main: loop {
-            interval.as_mut().tick().await;
+        let start = Instant::now();
+        let mut last_tick = start;
+        let mut due = 0.0;
+        let mut sent = 0;
+        let mut expected = 0.0;
+        let mut second = start;
+
+        loop {
+            // Keep collecting the acknowledgements of our transactions between ticks.
+            tokio::select! {
+                _ = interval.tick() => (),
+                Some(result) = in_flight.next() => {
+                    if let Err(e) = result {
+                        warn!("Failed to send transaction: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+            }
             let now = Instant::now();
 
+            // The transactions of the previous bursts should be acknowledged by now.
+            if in_flight.len() > DEFAULT_MAX_IN_FLIGHT {
+                // NOTE: This log entry is used to compute performance.
+                warn!("Transaction rate too high for this client");
+            }
+
+            let elapsed = now.duration_since(start).as_secs_f64();
+            let target =
+                self.pattern.rate_at(elapsed) * now.duration_since(last_tick).as_secs_f64();
+            due += target;
+            expected += target;
+            last_tick = now;
+            let burst = due as u64;
+            due -= burst as f64;
+
+            let mut keys = Vec::new();
             for x in 0..burst {
-                if x == counter % burst {
+                let key = if x == counter % burst {
                     // NOTE: This log entry is used to compute performance.
                     info!("Sending sample transaction {}", counter);
-
-                    tx.put_u8(0u8); // Sample txs start with 0.
-                    tx.put_u64(counter); // This counter identifies the tx.
+                    (0u8, counter) // Sample txs start with 0, the counter identifies the tx.
                 } else {
                     r += 1;
-                    tx.put_u8(1u8); // Standard txs start with 1.
-                    tx.put_u64(r); // Ensures all clients send different txs.
+                    (1u8, r) // Standard txs start with 1, r ensures all clients send different txs.
                 };
+                tx.put_u8(key.0);
+                tx.put_u64(key.1);
 
-                tx.resize(self.size, 0u8);
-                let bytes = tx.split().freeze();
-                if let Err(e) = transport.send(bytes).await {
-                    warn!("Failed to send transaction: {}", e);
-                    break 'main;
+                // Embed the send time, to measure the latency once the tx is committed.
+                if self.tx_sent.is_some() {
+                    let timestamp = now_micros();
+                    tx.put_u64(timestamp);
+                    keys.push((key, timestamp));
                 }
+
+                // The header (at least 9 bytes) ensures all txs are different.
+                payloads.fill(&mut tx)?;
+                self.sent_bytes
+                    .fetch_add(tx.len() as u64, Ordering::Relaxed);
+                in_flight.push(client.submit(tx.split().freeze()));
             }
-            if now.elapsed().as_millis() > BURST_DURATION as u128 {
-                // NOTE: This log entry is used to compute performance.
-                warn!("Transaction rate too high for this client");
+            if burst > 0 {
+                counter += 1;
+            }
+            if let Some(tx_sent) = &self.tx_sent {
+                // The tracker only goes away when we stop.
+                let _ = tx_sent.send(keys).await;
+            }
+
+            // Report the rate we actually achieved over the last second.
+            sent += burst;
+            self.sent_transactions.fetch_add(burst, Ordering::Relaxed);
+            let window = now.duration_since(second);
+            if window >= Duration::from_secs(1) {
+                info!(
+                    "Achieved rate: {:.0} tx/s (target {:.0} tx/s)",
+                    sent as f64 / window.as_secs_f64(),
+                    expected / window.as_secs_f64()
+                );
+                sent = 0;
+                expected = 0.0;
+                second = now;
             }
-            counter += 1;
         }
         Ok(())
     }
 
+    /// Prints the rate (in transactions and bytes) at which we sent transactions.
+    pub fn summary(&self, elapsed: Duration) {
+        let transactions = self.sent_transactions.load(Ordering::Relaxed);
+        let bytes = self.sent_bytes.load(Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        info!(
+            "Sent {} tx ({:.0} tx/s) and {} B ({:.0} B/s)",
+            transactions,
+            transactions as f64 / seconds,
+            bytes,
+            bytes as f64 / seconds
+        );
+    }
+
     pub async fn wait(&self) {
         // Wait for all nodes to be online.
         info!("Waiting for all nodes to be online...");
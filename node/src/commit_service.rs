@@ -0,0 +1,313 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::worker_stores::WorkerStores;
+use bytes::Bytes;
+use config::WorkerId;
+use consensus::SubDag;
+use crypto::{Digest, Hash as _, PublicKey};
+use futures::stream::{self, Stream};
+use log::{debug, info, warn};
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::{Database, Family, Store};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use worker::WorkerMessage;
+
+#[cfg(test)]
+#[path = "tests/commit_service_tests.rs"]
+pub mod commit_service_tests;
+
+/// The messages and services generated from `proto/commits.proto`.
+pub mod proto {
+    tonic::include_proto!("narwhal");
+}
+
+use proto::commits_server::{Commits, CommitsServer};
+use proto::{Batch, CommittedSubDag, SubscribeRequest};
+
+/// How many sub-dags we keep in memory for the subscribers. Subscribers lagging further behind
+/// read the sub-dags back from the store.
+const LIVE_CAPACITY: usize = 1_000;
+
+/// How many sub-dags we read from the store at once.
+const REPLAY_PAGE: usize = 100;
+
+/// How many sub-dags we queue for the stream of each subscriber.
+const STREAM_CAPACITY: usize = 100;
+
+/// A committed sub-dag, as we persist it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubDagRecord {
+    /// The consensus index of the sub-dag.
+    pub index: u64,
+    /// The digest of the leader's certificate.
+    pub leader: Digest,
+    /// The authority that proposed the leader.
+    pub author: PublicKey,
+    /// The round of the leader.
+    pub round: Round,
+    /// The digests of the batches of the sub-dag (and the workers holding them), in commit order.
+    pub batches: Vec<(Digest, WorkerId)>,
+}
+
+impl SubDagRecord {
+    fn new(index: u64, sub_dag: &SubDag) -> Self {
+        Self {
+            index,
+            leader: sub_dag.leader.digest(),
+            author: sub_dag.leader.origin(),
+            round: sub_dag.leader.round(),
+            batches: sub_dag
+                .certificates
+                .iter()
+                .flat_map(|x| x.header.payload.iter().map(|(x, y)| (x.clone(), *y)))
+                .collect(),
+        }
+    }
+}
+
+/// Streams the output of consensus over gRPC, by sub-dag, e.g., to an execution engine running in
+/// another process. Sub-dags are numbered in commit order and persisted under their index, so that
+/// subscribers can resume from any index (e.g., after a restart). Subscribers never slow down
+/// consensus: those lagging behind read the sub-dags back from the store until they catch up.
+#[derive(Clone)]
+pub struct CommitService {
+    /// The persisted sub-dags, by index.
+    store: Store<u64, SubDagRecord>,
+    /// The index of the next sub-dag. All sub-dags before it are in the store.
+    next_index: Arc<AtomicU64>,
+    /// Sends the new sub-dags to the subscribers.
+    tx_live: broadcast::Sender<SubDagRecord>,
+    /// The path of the store of the primary; the stores of its workers are derived from it.
+    store_path: String,
+}
+
+impl CommitService {
+    /// Records the sub-dags of consensus and serves them to subscribers on the address.
+    pub async fn spawn(
+        address: SocketAddr,
+        store: &Database,
+        store_path: &str,
+        rx_sub_dags: Receiver<SubDag>,
+    ) -> Self {
+        let service = Self::new(store, store_path).await;
+        let recorder = service.clone();
+        tokio::spawn(async move { recorder.record(rx_sub_dags).await });
+
+        let server = CommitsServer::new(service.clone());
+        tokio::spawn(async move {
+            info!("Streaming committed sub-dags over gRPC on {}", address);
+            if let Err(e) = Server::builder().add_service(server).serve(address).await {
+                warn!("Failed to serve gRPC subscribers on {}: {}", address, e);
+            }
+        });
+        service
+    }
+
+    /// Makes the service, resuming the numbering of the sub-dags already in the store.
+    async fn new(store: &Database, store_path: &str) -> Self {
+        let store = store.store(Family::Consensus);
+        let next_index = Self::stored_count(&store).await;
+        Self {
+            store,
+            next_index: Arc::new(AtomicU64::new(next_index)),
+            tx_live: broadcast::channel(LIVE_CAPACITY).0,
+            store_path: store_path.to_string(),
+        }
+    }
+
+    /// Returns the number of sub-dags in the store. Their indices are contiguous from 0, so we
+    /// look for the first missing one.
+    async fn stored_count(store: &Store<u64, SubDagRecord>) -> u64 {
+        let present = |index| {
+            let mut store = store.clone();
+            async move { matches!(store.read(&index).await, Ok(Some(_))) }
+        };
+        if !present(0).await {
+            return 0;
+        }
+        let (mut low, mut high) = (0, 1);
+        while present(high).await {
+            low = high;
+            high *= 2;
+        }
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            match present(middle).await {
+                true => low = middle,
+                false => high = middle,
+            }
+        }
+        high
+    }
+
+    /// Persists the sub-dags output by consensus and sends them to the subscribers.
+    async fn record(&self, mut rx_sub_dags: Receiver<SubDag>) {
+        // After a restart, consensus may output again the sub-dags we already stored.
+        let mut last_round = match self.next_index.load(Ordering::SeqCst) {
+            0 => None,
+            next => self.store.clone().read(&(next - 1)).await.ok().flatten(),
+        }
+        .map(|x| x.round);
+
+        while let Some(sub_dag) = rx_sub_dags.recv().await {
+            let round = sub_dag.leader.round();
+            if last_round.is_some_and(|x| round <= x) {
+                debug!("Skipping sub-dag of round {} (already stored)", round);
+                continue;
+            }
+            last_round = Some(round);
+
+            let index = self.next_index.load(Ordering::SeqCst);
+            let record = SubDagRecord::new(index, &sub_dag);
+            self.store.clone().write(&index, &record).await;
+            self.next_index.store(index + 1, Ordering::SeqCst);
+
+            // It fails only when there are no subscribers.
+            let _ = self.tx_live.send(record);
+        }
+    }
+
+    /// Streams the sub-dags to a subscriber, from the specified index: first from the store, then
+    /// as they are committed. The stream only ends when the subscriber goes away.
+    async fn stream(
+        &self,
+        mut next: u64,
+        include_batches: bool,
+        tx_stream: Sender<Result<CommittedSubDag, Status>>,
+    ) {
+        let mut store = self.store.clone();
+        let mut rx_live = self.tx_live.subscribe();
+        loop {
+            // Catch up from the store.
+            let end = self.next_index.load(Ordering::SeqCst);
+            while next < end {
+                let page = match store.iter_range(&next, &end, REPLAY_PAGE).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let status = Status::internal(format!("Failed to read sub-dags: {}", e));
+                        let _ = tx_stream.send(Err(status)).await;
+                        return;
+                    }
+                };
+                if page.items.is_empty() {
+                    let status = Status::internal(format!("Missing sub-dag {}", next));
+                    let _ = tx_stream.send(Err(status)).await;
+                    return;
+                }
+                for (_, record) in page.items {
+                    next = record.index + 1;
+                    if tx_stream
+                        .send(Ok(self.message(record, include_batches)))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+
+            // Follow the new sub-dags, until we lag behind (or the subscriber goes away).
+            loop {
+                let received = tokio::select! {
+                    received = rx_live.recv() => received,
+                    () = tx_stream.closed() => return,
+                };
+                match received {
+                    Ok(record) if record.index < next => continue,
+                    Ok(record) if record.index == next => {
+                        next += 1;
+                        let message = self.message(record, include_batches);
+                        if tx_stream.send(Ok(message)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        }
+    }
+
+    /// Makes the message of a sub-dag, reading its batches from the stores of our workers (if
+    /// requested).
+    fn message(&self, record: SubDagRecord, include_batches: bool) -> CommittedSubDag {
+        let mut transactions = match include_batches {
+            true => self.read_batches(&record.batches),
+            false => HashMap::new(),
+        };
+        CommittedSubDag {
+            index: record.index,
+            leader: Bytes::copy_from_slice(&record.leader.0),
+            author: Bytes::copy_from_slice(&record.author.0),
+            round: record.round,
+            batches: record
+                .batches
+                .into_iter()
+                .map(|(digest, worker_id)| Batch {
+                    transactions: transactions.remove(&digest).unwrap_or_default(),
+                    digest: Bytes::copy_from_slice(&digest.0),
+                    worker_id,
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads batches from the stores of our workers. Batches that cannot be read are left out.
+    fn read_batches(&self, batches: &[(Digest, WorkerId)]) -> HashMap<Digest, Vec<Bytes>> {
+        let mut workers = WorkerStores::new(&self.store_path, "commits");
+        let mut transactions = HashMap::new();
+        for (digest, worker_id) in batches {
+            let value = match workers.read(digest, *worker_id) {
+                Ok(Some(value)) => value,
+                Ok(None) => {
+                    warn!("Failed to read batch {}: not found", digest);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to read batch {}: {}", digest, e);
+                    continue;
+                }
+            };
+            match bincode::deserialize(&value) {
+                Ok(WorkerMessage::Batch(batch)) => {
+                    transactions.insert(digest.clone(), batch);
+                }
+                _ => warn!("Failed to deserialize batch {}", digest),
+            }
+        }
+        transactions
+    }
+}
+
+#[tonic::async_trait]
+impl Commits for CommitService {
+    type SubscribeCommitsStream =
+        Pin<Box<dyn Stream<Item = Result<CommittedSubDag, Status>> + Send>>;
+
+    async fn subscribe_commits(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeCommitsStream>, Status> {
+        let request = request.into_inner();
+        let (tx_stream, rx_stream) = channel(STREAM_CAPACITY);
+        let service = self.clone();
+        tokio::spawn(async move {
+            service
+                .stream(request.start_index, request.include_batches, tx_stream)
+                .await
+        });
+        let stream = stream::unfold(rx_stream, |mut rx_stream| async move {
+            rx_stream.recv().await.map(|x| (x, rx_stream))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
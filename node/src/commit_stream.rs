@@ -0,0 +1,196 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::worker_stores::WorkerStores;
+use async_trait::async_trait;
+use bytes::Bytes;
+use client::CommittedBatch;
+use config::WorkerId;
+use crypto::Digest;
+use futures::sink::SinkExt as _;
+use log::{info, warn};
+use network::{MessageHandler, Receiver, ReceiverOptions, Writer};
+use primary::Certificate;
+use std::collections::VecDeque;
+use std::convert::TryInto as _;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use store::Store;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout, Duration};
+use worker::WorkerMessage;
+
+#[cfg(test)]
+#[path = "tests/commit_stream_tests.rs"]
+pub mod commit_stream_tests;
+
+/// How many committed batches we buffer for each subscriber. Slow subscribers miss the batches
+/// that do not fit.
+const SUBSCRIBER_CAPACITY: usize = 1_000;
+
+/// How many of the last committed batches we replay to new subscribers.
+const REPLAYED_COMMITS: usize = 1_000;
+
+/// How long to wait for a subscriber to accept a batch before dropping it.
+const WRITE_TIMEOUT: u64 = 5_000;
+
+/// Streams the committed batches (serialized `client::CommittedBatch`, in commit order) to the
+/// clients that subscribe to it, e.g., to measure the end-to-end latency of their transactions.
+/// Subscribers send a single frame and then only read. They first receive the last
+/// `REPLAYED_COMMITS` batches (so that they can look up what was committed before they joined),
+/// and then every new batch. If their frame is a consensus index (8 bytes, big endian), we only
+/// replay the batches holding transactions from that index on.
+#[derive(Clone)]
+pub struct CommitStream {
+    tx_commits: broadcast::Sender<Bytes>,
+    /// The last committed batches, from the oldest, along with the consensus index following their
+    /// last transaction.
+    recent: Arc<Mutex<VecDeque<(u64, Bytes)>>>,
+}
+
+impl CommitStream {
+    /// Listens for subscribers on the address.
+    pub fn spawn(address: SocketAddr) -> Self {
+        let stream = Self {
+            tx_commits: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(REPLAYED_COMMITS))),
+        };
+        Receiver::spawn(
+            address,
+            /* handler */ stream.clone(),
+            ReceiverOptions::default(),
+        );
+        info!("Streaming committed batches on {}", address);
+        stream
+    }
+
+    /// Sends a committed batch to the subscribers.
+    pub fn publish(&self, batch: &CommittedBatch) {
+        let end = batch.index + batch.transactions.len() as u64;
+        let bytes = bincode::serialize(batch).expect("Failed to serialize committed batch");
+        let bytes = Bytes::from(bytes);
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == REPLAYED_COMMITS {
+            recent.pop_front();
+        }
+        recent.push_back((end, bytes.clone()));
+
+        // It fails only when there are no subscribers.
+        let _ = self.tx_commits.send(bytes);
+    }
+
+    /// Sends a batch to a subscriber.
+    async fn write(writer: &mut Writer, batch: Bytes) -> Result<(), Box<dyn Error>> {
+        match timeout(Duration::from_millis(WRITE_TIMEOUT), writer.send(batch)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err("Timed out writing to subscriber".into()),
+        }
+    }
+}
+
+/// Where the batches of the committed certificates are read from.
+pub enum BatchSource {
+    /// A store holding the batches (e.g., that of a worker). We wait for the batches it misses.
+    Store(Store<Digest, Vec<u8>>),
+    /// The stores of the workers of this primary. The batches they miss are left out.
+    Workers(WorkerStores),
+}
+
+impl BatchSource {
+    /// Returns the serialized batch, if we can read it.
+    async fn read(&mut self, digest: &Digest, worker_id: WorkerId) -> Option<Vec<u8>> {
+        match self {
+            Self::Store(store) => match store.notify_read(digest).await {
+                Ok(batch) => Some(batch),
+                Err(e) => {
+                    warn!("Failed to read batch {}: {}", digest, e);
+                    None
+                }
+            },
+            Self::Workers(workers) => match workers.read(digest, worker_id) {
+                Ok(Some(batch)) => Some(batch),
+                Ok(None) => {
+                    warn!("Failed to read batch {}: not found", digest);
+                    None
+                }
+                Err(e) => {
+                    warn!("Failed to read batch {}: {}", digest, e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Reads the batches of a committed certificate (in the order of its payload), and publishes them
+/// on the commit stream (if any). `next_index` is the consensus index of the next committed
+/// transaction (the first one has index 0): the batches are numbered from it, and advance it.
+/// Returns the batches we published.
+pub async fn publish_certificate(
+    certificate: &Certificate,
+    next_index: &mut u64,
+    batches: &mut BatchSource,
+    commits: Option<&CommitStream>,
+) -> Vec<CommittedBatch> {
+    let mut committed = Vec::new();
+    for (digest, worker_id) in &certificate.header.payload {
+        let serialized = match batches.read(digest, *worker_id).await {
+            Some(x) => x,
+            None => continue,
+        };
+        let transactions = match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::Batch(transactions)) => transactions,
+            _ => {
+                warn!("Failed to deserialize batch {}", digest);
+                continue;
+            }
+        };
+        let batch = CommittedBatch {
+            round: certificate.round(),
+            index: *next_index,
+            digest: digest.0,
+            transactions,
+        };
+        *next_index += batch.transactions.len() as u64;
+        if let Some(commits) = commits {
+            commits.publish(&batch);
+        }
+        committed.push(batch);
+    }
+    committed
+}
+
+#[async_trait]
+impl MessageHandler for CommitStream {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let from = message[..]
+            .try_into()
+            .map(u64::from_be_bytes)
+            .unwrap_or_default();
+
+        // Subscribe and copy the recent batches atomically, so that we neither miss nor repeat any.
+        let (replay, mut rx_commits) = {
+            let recent = self.recent.lock().unwrap();
+            let replay: Vec<_> = recent
+                .iter()
+                .filter(|(end, _)| *end > from)
+                .map(|(_, batch)| batch.clone())
+                .collect();
+            (replay, self.tx_commits.subscribe())
+        };
+        for batch in replay {
+            Self::write(writer, batch).await?;
+        }
+        loop {
+            match rx_commits.recv().await {
+                Ok(batch) => Self::write(writer, batch).await?,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Subscriber lagging behind, dropped {} committed batches", n)
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
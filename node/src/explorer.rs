@@ -0,0 +1,432 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::status::StatusBoard;
+use crate::worker_stores::WorkerStores;
+use anyhow::Result;
+use bytes::Bytes;
+use config::WorkerId;
+use crypto::{Digest, Hash as _};
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use store::{Database, Family, Store};
+use tokio::sync::mpsc::{Receiver, Sender};
+use worker::{merkle_root, InclusionProof, MerkleProof, WorkerMessage};
+
+#[cfg(test)]
+#[path = "tests/explorer_tests.rs"]
+pub mod explorer_tests;
+
+/// The largest payload of a batch we return (in bytes of transactions). The transactions beyond it
+/// are left out.
+const MAX_PAYLOAD_SIZE: usize = 1_000_000;
+
+/// The status and JSON body of a reply.
+type Reply = (&'static str, Vec<u8>);
+
+/// A certificate, as returned by the explorer. Digests and keys are hex-encoded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CertificateView {
+    pub digest: String,
+    pub round: Round,
+    pub origin: String,
+    pub parents: Vec<String>,
+    /// The digests of the batches of the certificate, and the workers holding them.
+    pub payload: Vec<(String, WorkerId)>,
+    /// The number of votes certifying the header.
+    pub signatures: usize,
+}
+
+impl From<&Certificate> for CertificateView {
+    fn from(certificate: &Certificate) -> Self {
+        Self {
+            digest: hex(&certificate.digest().0),
+            round: certificate.round(),
+            origin: hex(&certificate.origin().0),
+            parents: certificate
+                .header
+                .parents
+                .iter()
+                .map(|x| hex(&x.0))
+                .collect(),
+            payload: certificate
+                .header
+                .payload
+                .iter()
+                .map(|(digest, worker_id)| (hex(&digest.0), *worker_id))
+                .collect(),
+            signatures: certificate.votes.len(),
+        }
+    }
+}
+
+/// A batch, as returned by the explorer.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchView {
+    pub digest: String,
+    /// The worker holding the batch (if known).
+    pub worker_id: Option<WorkerId>,
+    /// The number of transactions of the batch.
+    pub transactions: usize,
+    /// The size of the transactions of the batch (in bytes).
+    pub size: usize,
+    /// The hex-encoded transactions (if requested), up to `MAX_PAYLOAD_SIZE` bytes.
+    pub payload: Option<Vec<String>>,
+    /// Whether transactions were left out of the payload.
+    pub truncated: bool,
+}
+
+impl BatchView {
+    fn new(
+        digest: &Digest,
+        worker_id: Option<WorkerId>,
+        batch: &[Bytes],
+        include_payload: bool,
+    ) -> Self {
+        let size = batch.iter().map(|x| x.len()).sum();
+        let payload = include_payload.then(|| {
+            let mut total = 0;
+            batch
+                .iter()
+                .take_while(|x| {
+                    total += x.len();
+                    total <= MAX_PAYLOAD_SIZE
+                })
+                .map(|x| hex(x))
+                .collect::<Vec<_>>()
+        });
+        Self {
+            digest: hex(&digest.0),
+            worker_id,
+            transactions: batch.len(),
+            size,
+            truncated: payload.as_ref().is_some_and(|x| x.len() < batch.len()),
+            payload,
+        }
+    }
+}
+
+/// The Merkle proof that a transaction is part of a certified batch, as returned by the explorer.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InclusionProofView {
+    pub batch: String,
+    /// The index of the transaction in the batch.
+    pub index: u64,
+    /// The digest of the certificate referencing the batch.
+    pub certificate: String,
+    /// The hex-encoded (bincode) `InclusionProof`, which light clients check against the committee.
+    pub proof: String,
+}
+
+/// The certificates of a primary, and the digests of those of each round we still track.
+#[derive(Clone)]
+struct Certificates {
+    store: Store<Digest, Certificate>,
+    rounds: Arc<Mutex<BTreeMap<Round, BTreeSet<Digest>>>>,
+    /// The round and digest of the certificate referencing each batch, for the rounds we track.
+    batches: Arc<Mutex<HashMap<Digest, (Round, Digest)>>>,
+    /// Tells the last committed round, from which we derive the GC watermark.
+    status: StatusBoard,
+    gc_depth: Round,
+}
+
+impl Certificates {
+    /// The rounds below this one are garbage collected: the primary no longer synchronizes them.
+    fn gc_round(&self) -> Round {
+        let last_commit = self.status.lock().last_commit;
+        last_commit.map_or(0, |x| x.round.saturating_sub(self.gc_depth))
+    }
+}
+
+/// Where the batches are read from.
+#[derive(Clone)]
+enum Batches {
+    /// The store of this worker.
+    Worker(Store<Digest, Vec<u8>>),
+    /// The stores of the workers of this primary. The payloads of the primary tell which worker
+    /// holds a batch.
+    Primary {
+        payloads: Store<(Digest, WorkerId), ()>,
+        workers: Arc<Mutex<WorkerStores>>,
+    },
+}
+
+impl Batches {
+    /// Returns the worker holding the batch (if known) and its serialization.
+    async fn read(&self, digest: &Digest) -> Result<Option<(Option<WorkerId>, Vec<u8>)>> {
+        match self {
+            Self::Worker(store) => {
+                let batch = store.clone().read(digest).await?;
+                Ok(batch.map(|x| (None, x)))
+            }
+            Self::Primary { payloads, workers } => {
+                let page = payloads.clone().iter_prefix(digest, 1).await?;
+                let worker_id = match page.items.first() {
+                    Some(((_, worker_id), ())) => *worker_id,
+                    None => return Ok(None),
+                };
+                let batch = workers.lock().unwrap().read(digest, worker_id)?;
+                Ok(batch.map(|x| (Some(worker_id), x)))
+            }
+        }
+    }
+}
+
+/// Serves read-only lookups of certificates and batches by digest on the admin server, so that
+/// tools can resolve the digests they see (e.g., in the commit stream) without speaking the
+/// protocol of the nodes:
+/// - `GET /certificate/{digest}` returns a certificate (primary only).
+/// - `GET /batch/{digest}[?include_payload=true]` returns a batch, and possibly its transactions.
+/// - `GET /round/{n}/certificates` lists the digests of the certificates of a round (primary only).
+/// - `GET /batch/{digest}/proof?index={i}` (or `?transaction={digest}`) returns the Merkle proof that
+///   a transaction is part of a batch, along with the certificate referencing the batch (primary
+///   only, for batches digested into the Merkle roots of their transactions).
+///
+/// Digests are hex-encoded. Unknown digests get a 404. Certificates and rounds below the GC
+/// watermark get a 410 (with the watermark): the primary no longer synchronizes them, so we do not
+/// serve its partial view of them even if the store still holds some.
+#[derive(Clone)]
+pub struct Explorer {
+    /// The certificates (primary only).
+    certificates: Option<Certificates>,
+    batches: Batches,
+}
+
+impl Explorer {
+    /// Makes the explorer of a primary, reading the batches from the stores of its workers.
+    pub fn primary(
+        store: &Database,
+        store_path: &str,
+        status: StatusBoard,
+        gc_depth: Round,
+    ) -> Self {
+        Self {
+            certificates: Some(Certificates {
+                store: store.store(Family::Certificates),
+                rounds: Arc::default(),
+                batches: Arc::default(),
+                status,
+                gc_depth,
+            }),
+            batches: Batches::Primary {
+                payloads: store.store(Family::Payloads),
+                workers: Arc::new(Mutex::new(WorkerStores::new(store_path, "explorer"))),
+            },
+        }
+    }
+
+    /// Makes the explorer of a worker.
+    pub fn worker(store: &Database) -> Self {
+        Self {
+            certificates: None,
+            batches: Batches::Worker(store.store(Family::Batches)),
+        }
+    }
+
+    /// Forwards the certificates of the primary to consensus, indexing them by round.
+    pub fn observe(
+        &self,
+        mut rx_primary: Receiver<Certificate>,
+        tx_consensus: Sender<Certificate>,
+    ) {
+        let explorer = self.clone();
+        tokio::spawn(async move {
+            while let Some(certificate) = rx_primary.recv().await {
+                explorer.record(&certificate);
+                if tx_consensus.send(certificate).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Indexes a certificate by round, and forgets the rounds below the GC watermark.
+    fn record(&self, certificate: &Certificate) {
+        if let Some(certificates) = &self.certificates {
+            let gc_round = certificates.gc_round();
+            let mut rounds = certificates.rounds.lock().unwrap();
+            rounds
+                .entry(certificate.round())
+                .or_default()
+                .insert(certificate.digest());
+            *rounds = rounds.split_off(&gc_round);
+
+            let mut batches = certificates.batches.lock().unwrap();
+            for digest in certificate.header.payload.keys() {
+                batches
+                    .entry(digest.clone())
+                    .or_insert_with(|| (certificate.round(), certificate.digest()));
+            }
+            batches.retain(|_, (round, _)| *round >= gc_round);
+        }
+    }
+
+    /// Replies to a request for the target (a path, possibly with a query). Returns `None` if the
+    /// target is not one of the explorer.
+    pub async fn get(&self, target: &str) -> Option<Reply> {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let segments: Vec<_> = path.trim_start_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["certificate", digest] => Some(self.certificate(digest).await),
+            ["batch", digest] => {
+                let include_payload = query.split('&').any(|x| x == "include_payload=true");
+                Some(self.batch(digest, include_payload).await)
+            }
+            ["batch", digest, "proof"] => Some(self.proof(digest, query).await),
+            ["round", round, "certificates"] => Some(self.round(round)),
+            _ => None,
+        }
+    }
+
+    async fn certificate(&self, digest: &str) -> Reply {
+        let digest = match parse_digest(digest) {
+            Some(digest) => digest,
+            None => return error("400 Bad Request", "Invalid digest"),
+        };
+        let certificates = match &self.certificates {
+            Some(certificates) => certificates,
+            None => return error("404 Not Found", "No certificates on workers"),
+        };
+        let gc_round = certificates.gc_round();
+        match certificates.store.clone().read(&digest).await {
+            Ok(Some(certificate)) if certificate.round() < gc_round => gone(gc_round),
+            Ok(Some(certificate)) => ok(&CertificateView::from(&certificate)),
+            Ok(None) => error("404 Not Found", "Unknown certificate"),
+            Err(e) => error("500 Internal Server Error", &e.to_string()),
+        }
+    }
+
+    async fn batch(&self, digest: &str, include_payload: bool) -> Reply {
+        let digest = match parse_digest(digest) {
+            Some(digest) => digest,
+            None => return error("400 Bad Request", "Invalid digest"),
+        };
+        let (worker_id, serialized) = match self.batches.read(&digest).await {
+            Ok(Some(batch)) => batch,
+            Ok(None) => return error("404 Not Found", "Unknown batch"),
+            Err(e) => return error("500 Internal Server Error", &format!("{:#}", e)),
+        };
+        match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::Batch(batch)) => {
+                ok(&BatchView::new(&digest, worker_id, &batch, include_payload))
+            }
+            _ => error("500 Internal Server Error", "Failed to deserialize batch"),
+        }
+    }
+
+    async fn proof(&self, digest: &str, query: &str) -> Reply {
+        let digest = match parse_digest(digest) {
+            Some(digest) => digest,
+            None => return error("400 Bad Request", "Invalid digest"),
+        };
+        let certificates = match &self.certificates {
+            Some(certificates) => certificates,
+            None => return error("404 Not Found", "No certificates on workers"),
+        };
+
+        // Find the certificate referencing the batch.
+        let gc_round = certificates.gc_round();
+        let referenced = certificates.batches.lock().unwrap().get(&digest).cloned();
+        let certificate = match referenced {
+            Some((round, _)) if round < gc_round => return gone(gc_round),
+            Some((_, certificate)) => certificate,
+            None => return error("404 Not Found", "No certificate references the batch"),
+        };
+        let certificate = match certificates.store.clone().read(&certificate).await {
+            Ok(Some(certificate)) => certificate,
+            Ok(None) => return error("404 Not Found", "Unknown certificate"),
+            Err(e) => return error("500 Internal Server Error", &e.to_string()),
+        };
+
+        // Find the transaction in the batch.
+        let batch = match self.batches.read(&digest).await {
+            Ok(Some((_, serialized))) => match bincode::deserialize(&serialized) {
+                Ok(WorkerMessage::Batch(batch)) => batch,
+                _ => return error("500 Internal Server Error", "Failed to deserialize batch"),
+            },
+            Ok(None) => return error("404 Not Found", "Unknown batch"),
+            Err(e) => return error("500 Internal Server Error", &format!("{:#}", e)),
+        };
+        if merkle_root(&batch) != digest {
+            let message = "The batch digest is not the Merkle root of its transactions";
+            return error("409 Conflict", message);
+        }
+        let index = query.split('&').find_map(|x| match x.split_once('=') {
+            Some(("index", index)) => index.parse::<usize>().ok(),
+            Some(("transaction", hash)) => {
+                let hash = parse_digest(hash)?;
+                batch.iter().position(|x| crypto::hash(x) == hash)
+            }
+            _ => None,
+        });
+        let proof = match index.and_then(|i| Some((i, MerkleProof::new(&batch, i)?))) {
+            Some((index, proof)) => InclusionProof {
+                transaction: batch[index].clone(),
+                batch: digest,
+                proof,
+                certificate,
+            },
+            None => return error("404 Not Found", "Unknown transaction"),
+        };
+        let serialized = bincode::serialize(&proof).expect("Failed to serialize inclusion proof");
+        ok(&InclusionProofView {
+            batch: hex(&proof.batch.0),
+            index: proof.proof.index,
+            certificate: hex(&proof.certificate.digest().0),
+            proof: hex(&serialized),
+        })
+    }
+
+    fn round(&self, round: &str) -> Reply {
+        let round = match round.parse::<Round>() {
+            Ok(round) => round,
+            Err(_) => return error("400 Bad Request", "Invalid round"),
+        };
+        let certificates = match &self.certificates {
+            Some(certificates) => certificates,
+            None => return error("404 Not Found", "No certificates on workers"),
+        };
+        let gc_round = certificates.gc_round();
+        if round < gc_round {
+            return gone(gc_round);
+        }
+        let rounds = certificates.rounds.lock().unwrap();
+        let digests: Vec<_> = rounds
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .map(|x| hex(&x.0))
+            .collect();
+        ok(&json!({ "round": round, "certificates": digests }))
+    }
+}
+
+fn ok<T: Serialize>(value: &T) -> Reply {
+    let body = serde_json::to_vec(value).expect("Failed to serialize the reply");
+    ("200 OK", body)
+}
+
+fn error(status: &'static str, message: &str) -> Reply {
+    (status, json!({ "error": message }).to_string().into_bytes())
+}
+
+fn gone(gc_round: Round) -> Reply {
+    let body = json!({ "error": "Pruned", "gc_round": gc_round });
+    ("410 Gone", body.to_string().into_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn parse_digest(hex: &str) -> Option<Digest> {
+    if hex.len() != 64 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(Digest(digest))
+}
@@ -0,0 +1,266 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::status::StatusBoard;
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use log::warn;
+use primary::Certificate;
+use prometheus::core::Collector;
+use prometheus::{IntCounter, IntGauge, Registry};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use store::{Database, Family};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{timeout, Duration, Instant};
+
+/// How long the store may take to answer a read before we deem it unhealthy (in ms).
+const STORE_TIMEOUT: u64 = 1_000;
+
+/// The default time within which the round must advance for the node to be ready (in s).
+pub const DEFAULT_ROUND_TIMEOUT: u64 = 10;
+
+/// The default time within which consensus must commit for the node to be ready (in s).
+pub const DEFAULT_COMMIT_TIMEOUT: u64 = 30;
+
+/// The progress of the primary and of consensus, as seen by the node. The readiness of a primary
+/// is computed from these gauges.
+#[derive(Clone)]
+pub struct ProgressMetrics {
+    /// The highest round of the certificates handed to consensus.
+    pub round: IntGauge,
+    /// The stake of the other authorities whose certificates are recent.
+    pub active_stake: IntGauge,
+    /// The number of certificates committed by consensus.
+    pub committed: IntCounter,
+}
+
+impl ProgressMetrics {
+    pub fn new() -> Self {
+        Self {
+            round: IntGauge::new("primary_round", "The highest round of the certificates").unwrap(),
+            active_stake: IntGauge::new(
+                "primary_active_stake",
+                "The stake of the other authorities whose certificates are recent",
+            )
+            .unwrap(),
+            committed: IntCounter::new(
+                "consensus_committed_certificates",
+                "The number of certificates committed by consensus",
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        let collectors: [Box<dyn Collector>; 3] = [
+            Box::new(self.round.clone()),
+            Box::new(self.active_stake.clone()),
+            Box::new(self.committed.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector)?;
+        }
+        Ok(())
+    }
+
+    /// Forwards the certificates of the primary to consensus, recording them on the status board
+    /// and keeping track of the highest round and of the authorities that are active.
+    pub fn observe(
+        &self,
+        name: PublicKey,
+        committee: Committee,
+        status: StatusBoard,
+        mut rx_primary: Receiver<Certificate>,
+        tx_consensus: Sender<Certificate>,
+    ) {
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            while let Some(certificate) = rx_primary.recv().await {
+                let (round, active_stake) = {
+                    let mut status = status.lock();
+                    status.record(&certificate);
+                    (status.round, status.active_stake(&name, &committee))
+                };
+                if tx_consensus.send(certificate).await.is_err() {
+                    return;
+                }
+                metrics.round.set(round as i64);
+                metrics.active_stake.set(active_stake as i64);
+            }
+        });
+    }
+}
+
+/// When the node is deemed not ready.
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    /// The round must have advanced within this time.
+    pub round_timeout: Duration,
+    /// Consensus must have committed within this time.
+    pub commit_timeout: Duration,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            round_timeout: Duration::from_secs(DEFAULT_ROUND_TIMEOUT),
+            commit_timeout: Duration::from_secs(DEFAULT_COMMIT_TIMEOUT),
+        }
+    }
+}
+
+/// The readiness conditions of a primary.
+struct Readiness {
+    metrics: ProgressMetrics,
+    thresholds: Thresholds,
+    /// The stake of the other authorities we must hear from (2f, with unit stakes).
+    needed_stake: Stake,
+    /// When the round and commit gauges last changed.
+    progress: Mutex<Progress>,
+}
+
+struct Progress {
+    round: LastChange,
+    committed: LastChange,
+}
+
+/// The last value of a gauge, and when it changed.
+struct LastChange {
+    value: u64,
+    since: Instant,
+}
+
+impl LastChange {
+    fn new(now: Instant) -> Self {
+        Self {
+            value: 0,
+            since: now,
+        }
+    }
+
+    /// Records the current value of the gauge, and returns for how long it has not changed.
+    fn update(&mut self, value: u64, now: Instant) -> Duration {
+        if value != self.value {
+            self.value = value;
+            self.since = now;
+        }
+        now.duration_since(self.since)
+    }
+}
+
+/// Answers the health (`/health`) and readiness (`/ready`) probes of the node. A node is healthy
+/// if its store answers. A primary is also ready if it hears from enough other authorities, and
+/// both its round and consensus advance; workers are ready as soon as they are healthy.
+#[derive(Clone)]
+pub struct Health {
+    store: Database,
+    readiness: Option<Arc<Readiness>>,
+}
+
+impl Health {
+    /// The probes of a worker.
+    pub fn new(store: Database) -> Self {
+        Self {
+            store,
+            readiness: None,
+        }
+    }
+
+    /// The probes of a primary.
+    pub fn with_readiness(
+        store: Database,
+        name: &PublicKey,
+        committee: &Committee,
+        metrics: ProgressMetrics,
+        thresholds: Thresholds,
+    ) -> Self {
+        let now = Instant::now();
+        let readiness = Readiness {
+            metrics,
+            thresholds,
+            needed_stake: committee
+                .quorum_threshold()
+                .saturating_sub(committee.stake(name)),
+            progress: Mutex::new(Progress {
+                round: LastChange::new(now),
+                committed: LastChange::new(now),
+            }),
+        };
+        Self {
+            store,
+            readiness: Some(Arc::new(readiness)),
+        }
+    }
+
+    /// Returns whether the node is healthy, and the JSON body of the reply.
+    pub async fn health(&self) -> (bool, Vec<u8>) {
+        let failed = self.check_health().await;
+        Self::report("healthy", failed)
+    }
+
+    /// Returns whether the node is ready, and the JSON body of the reply.
+    pub async fn ready(&self) -> (bool, Vec<u8>) {
+        let mut failed = self.check_health().await;
+        if let Some(readiness) = &self.readiness {
+            failed.extend(readiness.check());
+        }
+        Self::report("ready", failed)
+    }
+
+    fn report(condition: &str, failed: Vec<String>) -> (bool, Vec<u8>) {
+        let ok = failed.is_empty();
+        let body = json!({ condition: ok, "failed": failed });
+        (ok, body.to_string().into_bytes())
+    }
+
+    /// Returns the failed health conditions.
+    async fn check_health(&self) -> Vec<String> {
+        // The store panics when its task is gone, so we read from a task of our own.
+        let mut store = self.store.store::<Vec<u8>, Vec<u8>>(Family::Headers);
+        let read = tokio::spawn(async move { store.read(&Vec::new()).await });
+        match timeout(Duration::from_millis(STORE_TIMEOUT), read).await {
+            Ok(Ok(Ok(_))) => Vec::new(),
+            Ok(Ok(Err(e))) => vec![format!("store: {}", e)],
+            Ok(Err(_)) => vec!["store: closed".to_string()],
+            Err(_) => {
+                warn!("The store did not answer within {} ms", STORE_TIMEOUT);
+                vec!["store: not responding".to_string()]
+            }
+        }
+    }
+}
+
+impl Readiness {
+    /// Returns the failed readiness conditions.
+    fn check(&self) -> Vec<String> {
+        let mut failed = Vec::new();
+        let active_stake = self.metrics.active_stake.get() as Stake;
+        if active_stake < self.needed_stake {
+            failed.push(format!(
+                "peers: active stake {} is below {}",
+                active_stake, self.needed_stake
+            ));
+        }
+
+        let now = Instant::now();
+        let mut progress = self.progress.lock().unwrap();
+        let round = self.metrics.round.get() as u64;
+        let stalled = progress.round.update(round, now);
+        if stalled > self.thresholds.round_timeout {
+            failed.push(format!(
+                "round: stuck at {} for {} s",
+                round,
+                stalled.as_secs()
+            ));
+        }
+        let committed = self.metrics.committed.get();
+        let stalled = progress.committed.update(committed, now);
+        if stalled > self.thresholds.commit_timeout {
+            failed.push(format!(
+                "commit: stuck at {} certificates for {} s",
+                committed,
+                stalled.as_secs()
+            ));
+        }
+        failed
+    }
+}
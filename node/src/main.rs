@@ -1,42 +1,128 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
+mod commit_service;
+mod commit_stream;
+mod explorer;
+mod health;
+mod metrics;
+mod status;
+mod worker_stores;
+
+use crate::commit_service::CommitService;
+use crate::commit_stream::{publish_certificate, BatchSource, CommitStream};
+use crate::explorer::Explorer;
+use crate::health::{
+    Health, ProgressMetrics, Thresholds, DEFAULT_COMMIT_TIMEOUT, DEFAULT_ROUND_TIMEOUT,
+};
+use crate::metrics::MetricsServer;
+use crate::status::{LastCommit, NodeStatus, StatusBoard};
+use crate::worker_stores::WorkerStores;
+use anyhow::{bail, ensure, Context, Result};
 use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
 use config::Export as _;
 use config::Import as _;
-use config::{Committee, KeyPair, Parameters, WorkerId};
-use consensus::Consensus;
+use config::{Committee, KeyPair, Parameters, PassphraseSource, WorkerId};
+use consensus::{
+    Checkpoint, Checkpointer, Consensus, ConsensusOptions, LeaderVector, RetryingSender, State,
+};
+use crypto::Hash as _;
+use crypto::{Digest, Scheme};
 use env_logger::Env;
-use primary::{Certificate, Primary};
-use store::Store;
+use log::{info, warn};
+use primary::{Certificate, Primary, Round};
+use std::convert::TryInto as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use store::{Database, Family};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{channel, Receiver};
-use worker::{Worker, WorkerMessage};
+use tokio::time::{timeout, Duration};
+use tracing_subscriber::EnvFilter;
+use worker::{Worker, WorkerOptions};
 
 /// The default channel capacity.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The environment variable holding the passphrase of encrypted key files.
+const PASSPHRASE_ENV: &str = "NARWHAL_KEY_PASSPHRASE";
+
+/// How long we wait for the store to persist its pending writes when asked to stop (in ms).
+const SHUTDOWN_GRACE_PERIOD: u64 = 5_000;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .about("A research implementation of Narwhal and Tusk.")
         .args_from_usage("-v... 'Sets the level of verbosity'")
+        .args_from_usage("--log-format=[FORMAT] 'The format of the logs (text or json)'")
         .subcommand(
             SubCommand::with_name("generate_keys")
                 .about("Print a fresh key pair to file")
-                .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'"),
+                .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'")
+                .args_from_usage(
+                    "--scheme=[SCHEME] 'The signature scheme of the key pair (ed25519 or secp256k1)'",
+                )
+                .args_from_usage(
+                    "--seed=[INT] 'Derive the key pair deterministically from this seed (for local testing only)'",
+                )
+                .args_from_usage(
+                    "--index=[INT] 'The index of the authority whose seeded key pair to derive (default 0)'",
+                )
+                .args_from_usage("--encrypt 'Encrypt the secret key with a passphrase'")
+                .args_from_usage(
+                    "--passphrase-fd=[FD] 'Read the passphrase from this file descriptor'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("leader_vector")
+                .about("Print the leaders elected over a range of rounds, to test other implementations")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--from=<INT> 'The first round of the range'")
+                .args_from_usage("--to=<INT> 'The last round of the range'")
+                .args_from_usage("--filename=<FILE> 'The file where to print the test vector'"),
         )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Run a node")
                 .args_from_usage("--keys=<FILE> 'The file containing the node keys'")
+                .args_from_usage(
+                    "--passphrase-fd=[FD] 'Read the passphrase of encrypted keys from this file descriptor'",
+                )
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
-                .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
+                .args_from_usage(
+                    "--commits=[ADDR] 'Stream the committed batches to subscribers on this address'",
+                )
+                .args_from_usage(
+                    "--metrics-address=[ADDR] 'Serve the Prometheus metrics (at /metrics), the health probes (at /health and /ready), the status of the node (at /status/primary or /status/worker) and lookups of certificates and batches (at /certificate, /batch and /round) on this address'",
+                )
+                .args_from_usage(
+                    "--ready-round-timeout=[SECS] 'The primary is not ready if its round did not advance for this long (default 10)'",
+                )
+                .args_from_usage(
+                    "--ready-commit-timeout=[SECS] 'The primary is not ready if consensus did not commit for this long (default 30)'",
+                )
+                .subcommand(
+                    SubCommand::with_name("primary")
+                        .about("Run a single primary")
+                        .args_from_usage(
+                            "--grpc-address=[ADDR] 'Stream the committed sub-dags over gRPC on this address'",
+                        )
+                        .args_from_usage(
+                            "--checkpoints=[DIR] 'Write a signed checkpoint of consensus to this directory every `checkpoint_interval` committed leaders'",
+                        )
+                        .args_from_usage(
+                            "--from-checkpoint=[FILE] 'Restore the store and consensus from this checkpoint (after verifying it)'",
+                        ),
+                )
                 .subcommand(
                     SubCommand::with_name("worker")
                         .about("Run a single worker")
-                        .args_from_usage("--id=<INT> 'The worker id'"),
+                        .args_from_usage("--id=<INT> 'The worker id'")
+                        .args_from_usage(
+                            "--grpc-address=[ADDR] 'Also accept client transactions over gRPC on this address'",
+                        ),
                 )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
@@ -50,32 +136,177 @@ async fn main() -> Result<()> {
         3 => "debug",
         _ => "trace",
     };
-    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or(log_level));
-    #[cfg(feature = "benchmark")]
-    logger.format_timestamp_millis();
-    logger.init();
+    match matches.value_of("log-format") {
+        // The benchmark scripts parse the text logs: keep their format stable.
+        None | Some("text") => {
+            let mut logger =
+                env_logger::Builder::from_env(Env::default().default_filter_or(log_level));
+            #[cfg(feature = "benchmark")]
+            logger.format_timestamp_millis();
+            logger.init();
+        }
+        // One JSON object per event, with the fields of the current span (e.g., the digest of a
+        // batch or certificate). Events of the `log` macros are converted.
+        Some("json") => {
+            let filter =
+                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level));
+            tracing_subscriber::fmt()
+                .json()
+                .with_env_filter(filter)
+                .with_current_span(true)
+                .with_span_list(false)
+                .init();
+        }
+        Some(x) => bail!("Unknown log format '{}'", x),
+    }
 
     match matches.subcommand() {
-        ("generate_keys", Some(sub_matches)) => KeyPair::new()
-            .export(sub_matches.value_of("filename").unwrap())
-            .context("Failed to generate key pair")?,
+        ("generate_keys", Some(sub_matches)) => {
+            let scheme = match sub_matches.value_of("scheme") {
+                None | Some("ed25519") => Scheme::Ed25519,
+                Some("secp256k1") => Scheme::Secp256k1,
+                Some(x) => bail!("Unknown signature scheme '{}'", x),
+            };
+            ensure!(
+                scheme.is_supported(),
+                "Signature scheme {} is not supported by this build",
+                scheme
+            );
+            let keypair = match sub_matches.value_of("seed") {
+                Some(seed) => {
+                    ensure!(
+                        scheme == Scheme::Ed25519,
+                        "Seeded key pairs only support the ed25519 signature scheme"
+                    );
+                    let seed = seed.parse().context("The seed must be an integer")?;
+                    let index = sub_matches
+                        .value_of("index")
+                        .unwrap_or("0")
+                        .parse()
+                        .context("The index must be an integer")?;
+                    KeyPair::new_for_test(seed, index)
+                }
+                None => KeyPair::new_with_scheme(scheme),
+            };
+            let filename = sub_matches.value_of("filename").unwrap();
+            match sub_matches.is_present("encrypt") {
+                true => {
+                    let passphrase = passphrase_source(sub_matches)?.read()?;
+                    keypair.encrypt(&passphrase).export(filename)
+                }
+                false => keypair.export(filename),
+            }
+            .context("Failed to generate key pair")?
+        }
+        ("leader_vector", Some(sub_matches)) => {
+            let committee = Committee::import(sub_matches.value_of("committee").unwrap())
+                .context("Failed to load the committee information")?;
+            let from = sub_matches
+                .value_of("from")
+                .unwrap()
+                .parse::<Round>()
+                .context("The first round must be a non-negative integer")?;
+            let to = sub_matches
+                .value_of("to")
+                .unwrap()
+                .parse::<Round>()
+                .context("The last round must be a non-negative integer")?;
+            LeaderVector::new(&committee, from..=to)
+                .export(sub_matches.value_of("filename").unwrap())
+                .context("Failed to print the test vector")?
+        }
         ("run", Some(sub_matches)) => run(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
 }
 
+/// Picks where to read the passphrase of encrypted key files from: an explicit file descriptor,
+/// the `NARWHAL_KEY_PASSPHRASE` environment variable, or an interactive prompt.
+fn passphrase_source(matches: &ArgMatches<'_>) -> Result<PassphraseSource> {
+    if let Some(fd) = matches.value_of("passphrase-fd") {
+        let fd = fd
+            .parse()
+            .context("The passphrase file descriptor must be an integer")?;
+        return Ok(PassphraseSource::FileDescriptor(fd));
+    }
+    if std::env::var_os(PASSPHRASE_ENV).is_some() {
+        return Ok(PassphraseSource::Environment(PASSPHRASE_ENV.to_string()));
+    }
+    Ok(PassphraseSource::Prompt)
+}
+
+/// Reads and verifies a checkpoint, writes its certificates to the store of the primary, and
+/// returns the consensus state it holds.
+async fn restore_checkpoint(path: &Path, committee: &Committee, store: &Database) -> Result<State> {
+    let checkpoint = Checkpoint::read(path).context("Failed to read the checkpoint")?;
+    let manifest = checkpoint.manifest.clone();
+    let state = State::restore(committee, checkpoint).context("Invalid checkpoint")?;
+
+    let mut certificates = store.store::<Digest, Certificate>(Family::Certificates);
+    for certificate in manifest.certificates.iter().chain(&manifest.leaders) {
+        certificates.write(&certificate.digest(), certificate).await;
+    }
+    info!(
+        "Restored checkpoint {} (round {})",
+        manifest.index,
+        manifest
+            .last_committed
+            .values()
+            .max()
+            .cloned()
+            .unwrap_or_default()
+    );
+    Ok(state)
+}
+
 // Runs either a worker or a primary.
 async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let key_file = matches.value_of("keys").unwrap();
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let store_path = matches.value_of("store").unwrap();
+    let commits_address = matches
+        .value_of("commits")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid socket address format")?;
+    let metrics_address = matches
+        .value_of("metrics-address")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid socket address format")?;
+    let thresholds = Thresholds {
+        round_timeout: Duration::from_secs(
+            matches
+                .value_of("ready-round-timeout")
+                .map(|x| x.parse())
+                .transpose()
+                .context("The round timeout must be a number of seconds")?
+                .unwrap_or(DEFAULT_ROUND_TIMEOUT),
+        ),
+        commit_timeout: Duration::from_secs(
+            matches
+                .value_of("ready-commit-timeout")
+                .map(|x| x.parse())
+                .transpose()
+                .context("The commit timeout must be a number of seconds")?
+                .unwrap_or(DEFAULT_COMMIT_TIMEOUT),
+        ),
+    };
 
     // Read the committee and node's keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+    let passphrase = passphrase_source(matches)?;
+    let keypair = KeyPair::import_with_passphrase(key_file, || passphrase.read())
+        .context("Failed to load the node's keypair")?;
     let committee =
         Committee::import(committee_file).context("Failed to load the committee information")?;
+    committee
+        .check_scheme(keypair.secret.scheme())
+        .context("Invalid signature scheme")?;
+    committee
+        .check_hash_algorithm()
+        .context("Invalid hash algorithm")?;
 
     // Load default parameters if none are specified.
     let parameters = match parameters_file {
@@ -86,32 +317,115 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     };
 
     // Make the data store.
-    let store = Store::new(store_path).context("Failed to create a store")?;
+    let store = Database::open(store_path).context("Failed to create a store")?;
+    store
+        .register_metrics(prometheus::default_registry())
+        .context("Failed to register the store metrics")?;
 
     // Channels the sequence of certificates.
     let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
 
     // Check whether to run a primary, a worker, or an entire authority.
-    match matches.subcommand() {
+    let (progress, status) = match matches.subcommand() {
         // Spawn the primary and consensus core.
-        ("primary", _) => {
+        ("primary", Some(sub_matches)) => {
             let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
+            let (tx_observed, mut rx_observed) = channel(CHANNEL_CAPACITY);
             let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+
+            // The node observes the certificates handed to consensus, to tell whether it is ready
+            // and to report the status of the primary.
+            let progress = ProgressMetrics::new();
+            progress
+                .register(prometheus::default_registry())
+                .context("Failed to register the progress metrics")?;
+            let status = StatusBoard::default();
+            progress.observe(
+                keypair.name,
+                committee.clone(),
+                status.clone(),
+                rx_new_certificates,
+                tx_observed,
+            );
+            if let Some(address) = metrics_address {
+                let health = Health::with_readiness(
+                    store.clone(),
+                    &keypair.name,
+                    &committee,
+                    progress.clone(),
+                    thresholds,
+                );
+
+                // The explorer indexes the certificates by round on their way to consensus.
+                let explorer =
+                    Explorer::primary(&store, store_path, status.clone(), parameters.gc_depth);
+                let (tx_indexed, rx_indexed) = channel(CHANNEL_CAPACITY);
+                explorer.observe(rx_observed, tx_indexed);
+                rx_observed = rx_indexed;
+
+                MetricsServer::spawn(
+                    address,
+                    prometheus::default_registry().clone(),
+                    health,
+                    NodeStatus::Primary(status.clone()),
+                    explorer,
+                );
+            }
+
+            // Consensus resumes from a checkpoint (if specified), and writes its own checkpoints.
+            let state = match sub_matches.value_of("from-checkpoint") {
+                Some(path) => Some(restore_checkpoint(Path::new(path), &committee, &store).await?),
+                None => None,
+            };
+            let checkpointer = sub_matches.value_of("checkpoints").map(|directory| {
+                Checkpointer::spawn(
+                    keypair.name,
+                    keypair.secret.duplicate(),
+                    PathBuf::from(directory),
+                    parameters.checkpoint_interval,
+                )
+            });
+
             Primary::spawn(
                 keypair,
                 committee.clone(),
                 parameters.clone(),
-                store,
+                store.clone(),
                 /* tx_consensus */ tx_new_certificates,
                 /* rx_consensus */ rx_feedback,
             );
+            // Execution engines may follow the output of consensus over gRPC.
+            let grpc_address = sub_matches
+                .value_of("grpc-address")
+                .map(|x| x.parse::<SocketAddr>())
+                .transpose()
+                .context("Invalid socket address format")?;
+
+            // Consensus retries to output to closed channels before giving up.
+            let (retries, retry_delay) = (parameters.output_retries, parameters.output_retry_delay);
+            let tx_sub_dags = match grpc_address {
+                Some(address) => {
+                    let (tx_sub_dags, rx_sub_dags) = channel(CHANNEL_CAPACITY);
+                    CommitService::spawn(address, &store, store_path, rx_sub_dags).await;
+                    Some(RetryingSender::new(tx_sub_dags).with_retries(retries, retry_delay))
+                }
+                None => None,
+            };
+
+            let options = ConsensusOptions {
+                state,
+                checkpointer,
+                tx_sub_dags,
+            };
             Consensus::spawn(
                 committee,
                 parameters.gc_depth,
-                /* rx_primary */ rx_new_certificates,
+                /* rx_primary */ rx_observed,
                 /* tx_primary */ tx_feedback,
-                tx_output,
+                RetryingSender::new(tx_output).with_retries(retries, retry_delay),
+                options,
             );
+            (Some(progress), Some(status))
         }
 
         // Spawn a single worker.
@@ -121,70 +435,125 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 .unwrap()
                 .parse::<WorkerId>()
                 .context("The worker id must be a positive integer")?;
-            Worker::spawn(keypair.name, id, committee, parameters, store);
+            let grpc_address = sub_matches
+                .value_of("grpc-address")
+                .map(|x| x.parse::<SocketAddr>())
+                .transpose()
+                .context("Invalid socket address format")?;
+            let options = WorkerOptions {
+                grpc_address,
+                secret: parameters
+                    .authenticate_workers
+                    .then(|| keypair.secret.duplicate()),
+                ..WorkerOptions::default()
+            };
+            let backlog = Worker::spawn(
+                keypair.name,
+                id,
+                committee,
+                parameters,
+                store.clone(),
+                options,
+            )
+            .context("Failed to spawn the worker")?;
+            if let Some(address) = metrics_address {
+                let health = Health::new(store.clone());
+                MetricsServer::spawn(
+                    address,
+                    prometheus::default_registry().clone(),
+                    health,
+                    NodeStatus::Worker(backlog),
+                    Explorer::worker(&store),
+                );
+            }
+            (None, None)
         }
         _ => unreachable!(),
+    };
+
+    // Analyze the consensus' output until we are asked to stop.
+    let commits = commits_address.map(CommitStream::spawn);
+    tokio::select! {
+        () = analyze(rx_output, store_path, commits, progress, status) => unreachable!(),
+        () = shutdown_signal() => (),
     }
 
-    // Analyze the consensus' output.
-    analyze(rx_output, store_path).await;
-    // If this expression is reached, the program ends and all other tasks terminate.
-    unreachable!();
+    // Give the store a bounded time to persist its pending writes (a second signal cuts it short).
+    // Returning stops all other tasks.
+    info!("Shutting down");
+    let grace_period = Duration::from_millis(SHUTDOWN_GRACE_PERIOD);
+    tokio::select! {
+        result = timeout(grace_period, store.flush()) => match result {
+            Ok(Ok(())) => info!("Store flushed"),
+            Ok(Err(e)) => warn!("Failed to flush the store: {}", e),
+            Err(_) => warn!("Timed out flushing the store"),
+        },
+        () = shutdown_signal() => {
+            warn!("Forced shutdown");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Completes when the process receives SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Failed to listen to termination signals");
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.expect("Failed to listen to interrupt signals"),
+        _ = terminate.recv() => (),
+    }
 }
 
 /// Receives an ordered list of certificates and apply any application-specific logic.
-async fn analyze(mut rx_output: Receiver<Certificate>, store_path: &str) {
-    while let Some(_certificate) = rx_output.recv().await {
-        // NOTE: Here goes the application logic.
-        let opts = rocksdb::Options::default();
-        let secondary_path = "_primary_rocksdb_secondary_secondary";
-        let store_path = format!("{}-0", store_path);
-        let final_path = format!("{}-final", store_path);
-        let secondary = rocksdb::DB::open_as_secondary(&opts, store_path.as_str(), &secondary_path).unwrap();
-
-        // index => tx
-        let final_db = rocksdb::DB::open_default(final_path).unwrap();
-        let bindex = b"latest_index";
-        let mut bvalue: u64 = match final_db.get(bindex) {
-            Ok(x) => {
-                match x {
-                    Some(y) => {
-                        let mut vy = [0u8; 8];
-                        vy.copy_from_slice(&y);
-                        u64::from_le_bytes(vy)
-                    },
-                    _ => 0,
-                }
-            },
-            _ => 0,
-        };
-
-        for x in _certificate.header.payload.keys() {
-            let value = secondary.get(x.to_vec());
-            if value.is_err() {
-                log::info!("rocksdb::get error: {:?} {:?}", x, value);
-                continue;
-            }
-            let value = value.unwrap();
-            if value.is_none() {
-                log::info!("rocksdb::get value is null: {:?}", x);
-                continue;
-            }
-            let serialized = value.unwrap();
-            match bincode::deserialize(&serialized) {
-                Ok(WorkerMessage::Batch(batch)) => {
-                    batch.into_iter().for_each(|tx| {
-                        bvalue += 1;
-                        log::info!("batch tx: {:?}, index: {}", tx, bvalue);
-                        let index = bvalue.to_le_bytes();
-                        let mut bh = rocksdb::WriteBatch::default();
-                        bh.put(index, tx);
-                        bh.put(bindex, index);
-                        final_db.write(bh).unwrap();
-                    })
-                }
-                _ => log::warn!("Serialization error: {:?}", serialized),
+async fn analyze(
+    mut rx_output: Receiver<Certificate>,
+    store_path: &str,
+    commits: Option<CommitStream>,
+    progress: Option<ProgressMetrics>,
+    status: Option<StatusBoard>,
+) {
+    // NOTE: Here goes the application logic. We keep the committed transactions by consensus index
+    // (index => tx), along with the index of the next one.
+    let final_db = rocksdb::DB::open_default(format!("{}-final", store_path))
+        .expect("Failed to open the database of committed transactions");
+    let next_key = b"next_index";
+    let mut next_index = match final_db.get(next_key) {
+        Ok(Some(x)) => x[..].try_into().map(u64::from_le_bytes).unwrap_or_default(),
+        _ => 0,
+    };
+    let mut batches = BatchSource::Workers(WorkerStores::new(store_path, "analyze"));
+
+    while let Some(certificate) = rx_output.recv().await {
+        if let Some(progress) = &progress {
+            progress.committed.inc();
+        }
+
+        let committed = publish_certificate(
+            &certificate,
+            &mut next_index,
+            &mut batches,
+            commits.as_ref(),
+        )
+        .await;
+        for batch in committed {
+            let mut write = rocksdb::WriteBatch::default();
+            for (index, tx) in (batch.index..).zip(&batch.transactions) {
+                log::info!("batch tx: {:?}, index: {}", tx, index);
+                write.put(index.to_le_bytes(), tx);
             }
+            let end = batch.index + batch.transactions.len() as u64;
+            write.put(next_key, end.to_le_bytes());
+            final_db
+                .write(write)
+                .expect("Failed to write committed transactions");
+        }
+        if let Some(status) = &status {
+            status.lock().last_commit = Some(LastCommit {
+                index: next_index,
+                round: certificate.round(),
+            });
         }
     }
 }
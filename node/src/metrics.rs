@@ -0,0 +1,208 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::explorer::Explorer;
+use crate::health::Health;
+use crate::status::NodeStatus;
+use log::{debug, info, warn};
+use prometheus::core::Collector;
+use prometheus::{Encoder as _, Gauge, IntGauge, Registry, TextEncoder};
+use serde::Serialize;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::time::{timeout, Duration, Instant};
+
+/// How long to wait for a scraper to send its request and read our reply (in ms).
+const REQUEST_TIMEOUT: u64 = 5_000;
+
+/// The largest request we accept (in bytes).
+const MAX_REQUEST_SIZE: usize = 8_192;
+
+/// Serves the metrics of a registry in the Prometheus text format (`GET /metrics`), along with a
+/// few metrics about the process itself, the health (`GET /health`) and readiness (`GET /ready`)
+/// probes of the node, its status (`GET /status/primary` or `GET /status/worker`, in JSON), and
+/// lookups of certificates and batches (see `Explorer`). Scrapers and probes only need this much
+/// HTTP, so we do not pull in a web framework.
+#[derive(Clone)]
+pub struct MetricsServer {
+    registry: Registry,
+    /// When the node started.
+    start: Instant,
+    /// How long the node has been running (in seconds).
+    uptime: Gauge,
+    /// The number of tasks alive in the runtime.
+    alive_tasks: IntGauge,
+    /// How many client connections the worker serves at once (if it adapts it).
+    connection_cap: IntGauge,
+    health: Health,
+    status: NodeStatus,
+    explorer: Explorer,
+}
+
+impl MetricsServer {
+    /// Serves the metrics on the address. If we cannot listen on it, the node runs without them.
+    pub fn spawn(
+        address: SocketAddr,
+        registry: Registry,
+        health: Health,
+        status: NodeStatus,
+        explorer: Explorer,
+    ) {
+        let server = Self {
+            registry,
+            start: Instant::now(),
+            uptime: Gauge::new("node_uptime_seconds", "How long the node has been running")
+                .unwrap(),
+            alive_tasks: IntGauge::new("node_alive_tasks", "The number of tasks alive").unwrap(),
+            connection_cap: IntGauge::new(
+                "worker_connection_cap",
+                "How many client connections the worker serves at once",
+            )
+            .unwrap(),
+            health,
+            status,
+            explorer,
+        };
+        let mut collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(server.uptime.clone()),
+            Box::new(server.alive_tasks.clone()),
+        ];
+        if matches!(&server.status, NodeStatus::Worker(x) if x.snapshot().connection_cap.is_some())
+        {
+            collectors.push(Box::new(server.connection_cap.clone()));
+        }
+        for collector in collectors {
+            if let Err(e) = server.registry.register(collector) {
+                warn!("Failed to register the process metrics: {}", e);
+            }
+        }
+
+        tokio::spawn(async move {
+            let listener = match TcpListener::bind(address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!("Failed to serve metrics on {}: {}", address, e);
+                    return;
+                }
+            };
+            info!("Serving metrics and probes on http://{}", address);
+            loop {
+                match listener.accept().await {
+                    Ok((socket, peer)) => {
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            let deadline = Duration::from_millis(REQUEST_TIMEOUT);
+                            match timeout(deadline, server.serve(socket)).await {
+                                Ok(Ok(())) => (),
+                                Ok(Err(e)) => debug!("Failed to serve metrics to {}: {}", peer, e),
+                                Err(_) => debug!("Timed out serving metrics to {}", peer),
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept metrics scraper: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Replies to a single request, and closes the connection.
+    async fn serve(&self, mut socket: TcpStream) -> std::io::Result<()> {
+        // Read the request headers (we ignore the body, if any).
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1_024];
+        while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+            let n = socket.read(&mut buffer).await?;
+            if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+                return Ok(());
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+
+        let line = request.split(|x| *x == b'\r').next().unwrap_or_default();
+        let mut parts = line.split(|x| *x == b' ');
+        let response = match (parts.next(), parts.next()) {
+            (Some(b"GET"), Some(b"/metrics")) => {
+                Self::response("200 OK", TextEncoder::new().format_type(), self.encode())
+            }
+            (Some(b"GET"), Some(b"/health")) => Self::probe(self.health.health().await),
+            (Some(b"GET"), Some(b"/ready")) => Self::probe(self.health.ready().await),
+            (Some(b"GET"), Some(b"/status/primary")) => match &self.status {
+                NodeStatus::Primary(status) => Self::json(&status.snapshot()),
+                NodeStatus::Worker(_) => Self::not_found(),
+            },
+            (Some(b"GET"), Some(b"/status/worker")) => match &self.status {
+                NodeStatus::Worker(backlog) => Self::json(&backlog.snapshot()),
+                NodeStatus::Primary(_) => Self::not_found(),
+            },
+            (Some(b"GET"), Some(target)) => self.explore(target).await,
+            _ => Self::response(
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Method not allowed".to_vec(),
+            ),
+        };
+        socket.write_all(&response).await?;
+        socket.shutdown().await
+    }
+
+    /// Replies to a lookup of the explorer.
+    async fn explore(&self, target: &[u8]) -> Vec<u8> {
+        let target = match std::str::from_utf8(target) {
+            Ok(target) => target,
+            Err(_) => return Self::not_found(),
+        };
+        match self.explorer.get(target).await {
+            Some((status, body)) => Self::response(status, "application/json", body),
+            None => Self::not_found(),
+        }
+    }
+
+    /// Encodes the current value of the metrics.
+    fn encode(&self) -> Vec<u8> {
+        self.uptime.set(self.start.elapsed().as_secs_f64());
+        self.alive_tasks
+            .set(Handle::current().metrics().num_alive_tasks() as i64);
+        if let NodeStatus::Worker(backlog) = &self.status {
+            let cap = backlog.snapshot().connection_cap.unwrap_or_default();
+            self.connection_cap.set(cap as i64);
+        }
+
+        let mut body = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut body) {
+            warn!("Failed to encode metrics: {}", e);
+        }
+        body
+    }
+
+    /// Makes the response to a probe.
+    fn probe((ok, body): (bool, Vec<u8>)) -> Vec<u8> {
+        let status = match ok {
+            true => "200 OK",
+            false => "503 Service Unavailable",
+        };
+        Self::response(status, "application/json", body)
+    }
+
+    /// Makes the response to a status request.
+    fn json<T: Serialize>(value: &T) -> Vec<u8> {
+        let body = serde_json::to_vec(value).expect("Failed to serialize the status");
+        Self::response("200 OK", "application/json", body)
+    }
+
+    fn not_found() -> Vec<u8> {
+        Self::response("404 Not Found", "text/plain", b"Not found".to_vec())
+    }
+
+    /// Makes an HTTP response.
+    fn response(status: &str, content_type: &str, body: Vec<u8>) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        response.extend(body);
+        response
+    }
+}
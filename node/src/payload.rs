@@ -0,0 +1,165 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use anyhow::{bail, ensure, Context, Result};
+use bytes::{BufMut as _, BytesMut};
+use rand::Rng as _;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader, Read as _, Seek as _, SeekFrom};
+use std::str::FromStr;
+
+/// The largest transaction we generate (in bytes). It fits the default frames of the workers.
+const MAX_SIZE: usize = 1 << 20;
+
+/// How the sizes of synthetic transactions are distributed (in bytes).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeDistribution {
+    /// Always the same size.
+    Fixed(usize),
+    /// Uniform between `min` and `max` (inclusive).
+    Uniform { min: usize, max: usize },
+    /// The logarithm of the size follows a normal distribution of mean `mu` and standard deviation
+    /// `sigma`. Sizes are capped to `MAX_SIZE`.
+    LogNormal { mu: f64, sigma: f64 },
+}
+
+impl SizeDistribution {
+    /// Draws a size.
+    fn sample(&self) -> usize {
+        match *self {
+            Self::Fixed(size) => size,
+            Self::Uniform { min, max } => rand::thread_rng().gen_range(min, max + 1),
+            Self::LogNormal { mu, sigma } => {
+                // Box-Muller transform (the first uniform sample must not be 0).
+                let mut rng = rand::thread_rng();
+                let u1 = 1.0 - rng.gen::<f64>();
+                let u2 = rng.gen::<f64>();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+                ((mu + sigma * z).exp().round() as usize).min(MAX_SIZE)
+            }
+        }
+    }
+
+    /// The average size.
+    fn mean(&self) -> f64 {
+        match *self {
+            Self::Fixed(size) => size as f64,
+            Self::Uniform { min, max } => (min + max) as f64 / 2.0,
+            Self::LogNormal { mu, sigma } => (mu + sigma * sigma / 2.0).exp().min(MAX_SIZE as f64),
+        }
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split(':').collect();
+        let invalid = || format!("Invalid size distribution: {}", s);
+        let distribution = match parts.as_slice() {
+            ["fixed", size] => Self::Fixed(size.parse().with_context(invalid)?),
+            ["uniform", min, max] => {
+                let min = min.parse().with_context(invalid)?;
+                let max = max.parse().with_context(invalid)?;
+                ensure!(min <= max && max <= MAX_SIZE, invalid());
+                Self::Uniform { min, max }
+            }
+            ["lognormal", mu, sigma] => {
+                let mu: f64 = mu.parse().with_context(invalid)?;
+                let sigma: f64 = sigma.parse().with_context(invalid)?;
+                ensure!(
+                    mu.is_finite() && sigma.is_finite() && sigma >= 0.0,
+                    invalid()
+                );
+                Self::LogNormal { mu, sigma }
+            }
+            _ => bail!(invalid()),
+        };
+        Ok(distribution)
+    }
+}
+
+/// Replays transaction payloads from a file, in a loop. The file holds payloads prefixed by their
+/// length (4 bytes, big endian), and is streamed rather than loaded in memory.
+pub struct PayloadFile {
+    reader: BufReader<File>,
+    /// The average size of the payloads of the file.
+    mean: f64,
+}
+
+impl PayloadFile {
+    /// Opens the file, and reads it through once to check its format.
+    pub fn open(path: &str) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (mut count, mut total) = (0u64, 0u64);
+        let mut buffer = Vec::new();
+        while Self::read(&mut reader, &mut buffer)? {
+            count += 1;
+            total += buffer.len() as u64;
+        }
+        ensure!(count > 0, "No payload in {}", path);
+
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            reader,
+            mean: total as f64 / count as f64,
+        })
+    }
+
+    /// Reads the next payload into the buffer. Returns false at the end of the file.
+    fn read(reader: &mut BufReader<File>, buffer: &mut Vec<u8>) -> Result<bool> {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        ensure!(length <= MAX_SIZE, "Payload of {} B is too large", length);
+        buffer.resize(length, 0);
+        reader
+            .read_exact(buffer)
+            .context("Truncated payload in input file")?;
+        Ok(true)
+    }
+
+    /// Reads the next payload into the buffer, starting over at the end of the file.
+    fn next(&mut self, buffer: &mut Vec<u8>) -> Result<()> {
+        if !Self::read(&mut self.reader, buffer)? {
+            self.reader.seek(SeekFrom::Start(0))?;
+            Self::read(&mut self.reader, buffer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the benchmark client takes the body of its transactions from.
+pub enum PayloadSource {
+    Synthetic(SizeDistribution),
+    File(PayloadFile, Vec<u8>),
+}
+
+impl PayloadSource {
+    /// The average size of the transactions (in bytes).
+    pub fn mean(&self) -> f64 {
+        match self {
+            Self::Synthetic(distribution) => distribution.mean(),
+            Self::File(file, _) => file.mean,
+        }
+    }
+
+    /// Completes a transaction whose buffer already holds a header (the sample/id markers). The
+    /// header replaces the beginning of the payload; transactions are never shorter than it.
+    pub fn fill(&mut self, tx: &mut BytesMut) -> Result<()> {
+        let header = tx.len();
+        match self {
+            Self::Synthetic(distribution) => tx.resize(distribution.sample().max(header), 0u8),
+            Self::File(file, buffer) => {
+                file.next(buffer)?;
+                if buffer.len() > header {
+                    tx.put_slice(&buffer[header..]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use primary::{Certificate, Round};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use worker::Backlog;
+
+/// An authority is active if one of its certificates is at most this many rounds behind the
+/// highest round we saw.
+const ACTIVE_ROUNDS: Round = 2;
+
+/// How many of the last rounds we count the certificates of.
+const RECENT_ROUNDS: Round = 10;
+
+/// The last commit of consensus.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LastCommit {
+    /// The number of committed transactions (the consensus index of the next one).
+    pub index: u64,
+    /// The round of the last committed certificate.
+    pub round: Round,
+}
+
+/// The status of a primary, as the node observes it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PrimaryStatus {
+    /// The highest round of the certificates handed to consensus.
+    pub round: Round,
+    /// The last commit of consensus (if any).
+    pub last_commit: Option<LastCommit>,
+    /// The round of the last certificate of each authority.
+    pub last_seen: BTreeMap<PublicKey, Round>,
+    /// The number of certificates of each recent round.
+    pub certificates: BTreeMap<Round, usize>,
+}
+
+impl PrimaryStatus {
+    /// Records a certificate handed to consensus.
+    pub fn record(&mut self, certificate: &Certificate) {
+        let round = certificate.round();
+        self.round = self.round.max(round);
+        let last_seen = self.last_seen.entry(certificate.origin()).or_default();
+        *last_seen = round.max(*last_seen);
+
+        *self.certificates.entry(round).or_default() += 1;
+        let oldest = self.round.saturating_sub(RECENT_ROUNDS - 1);
+        self.certificates = self.certificates.split_off(&oldest);
+    }
+
+    /// The stake of the other authorities whose certificates are recent.
+    pub fn active_stake(&self, name: &PublicKey, committee: &Committee) -> Stake {
+        self.last_seen
+            .iter()
+            .filter(|(x, r)| *x != name && **r + ACTIVE_ROUNDS >= self.round)
+            .map(|(x, _)| committee.stake(x))
+            .sum()
+    }
+}
+
+/// The status of the primary, shared by the tasks updating it and the admin server. It is only
+/// locked briefly by the node's own tasks, never by those of the primary.
+#[derive(Clone, Default)]
+pub struct StatusBoard(Arc<Mutex<PrimaryStatus>>);
+
+impl StatusBoard {
+    pub fn lock(&self) -> MutexGuard<'_, PrimaryStatus> {
+        self.0.lock().unwrap()
+    }
+
+    pub fn snapshot(&self) -> PrimaryStatus {
+        self.lock().clone()
+    }
+}
+
+/// What the admin server reports about the node.
+#[derive(Clone)]
+pub enum NodeStatus {
+    Primary(StatusBoard),
+    Worker(Backlog),
+}
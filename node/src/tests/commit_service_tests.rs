@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::proto::commits_client::CommitsClient;
+use super::*;
+use primary::{Certificate, Header};
+use tokio::time::{sleep, timeout, Duration};
+use tonic::transport::Channel;
+use tonic::Streaming;
+
+// Fixture
+fn sub_dag(round: Round, batches: u8) -> SubDag {
+    let leader = Certificate {
+        header: Header {
+            round,
+            payload: (0..batches)
+                .map(|i| {
+                    let mut digest = [round as u8; 32];
+                    digest[0] = i;
+                    (Digest(digest), 0)
+                })
+                .collect(),
+            ..Header::default()
+        },
+        ..Certificate::default()
+    };
+    SubDag {
+        leader: leader.clone(),
+        certificates: vec![leader],
+    }
+}
+
+// Fixture
+async fn subscribe(address: SocketAddr, start_index: u64) -> Streaming<CommittedSubDag> {
+    let channel = Channel::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let request = SubscribeRequest {
+        start_index,
+        include_batches: false,
+    };
+    CommitsClient::new(channel)
+        .subscribe_commits(request)
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+// Fixture
+async fn expect(stream: &mut Streaming<CommittedSubDag>, index: u64, round: Round) {
+    let message = timeout(Duration::from_secs(5), stream.message())
+        .await
+        .expect("Timed out waiting for a sub-dag")
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.index, index);
+    assert_eq!(message.round, round);
+    assert_eq!(message.batches.len(), 1);
+    let mut digest = vec![round as u8; 32];
+    digest[0] = 0;
+    assert_eq!(message.batches[0].digest, digest);
+    assert!(message.batches[0].transactions.is_empty());
+}
+
+#[tokio::test]
+async fn resume_from_index() {
+    let store = Database::new_in_memory();
+    let address = "127.0.0.1:16000".parse::<SocketAddr>().unwrap();
+    let (tx_sub_dags, rx_sub_dags) = channel(100);
+    CommitService::spawn(address, &store, "unused", rx_sub_dags).await;
+    for i in 0..10 {
+        tx_sub_dags.send(sub_dag(2 * (i + 1), 1)).await.unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // A subscriber resuming from index 4 first gets the stored sub-dags, then the new ones.
+    let mut stream = subscribe(address, 4).await;
+    for i in 4..10 {
+        expect(&mut stream, i, 2 * (i + 1)).await;
+    }
+    for i in 10..12 {
+        tx_sub_dags.send(sub_dag(2 * (i + 1), 1)).await.unwrap();
+        expect(&mut stream, i, 2 * (i + 1)).await;
+    }
+
+    // After a restart, the numbering resumes, and the sub-dags consensus outputs again are skipped.
+    let address = "127.0.0.1:16001".parse::<SocketAddr>().unwrap();
+    let (tx_sub_dags, rx_sub_dags) = channel(100);
+    let service = CommitService::spawn(address, &store, "unused", rx_sub_dags).await;
+    assert_eq!(service.next_index.load(Ordering::SeqCst), 12);
+    sleep(Duration::from_millis(50)).await;
+    let mut stream = subscribe(address, 11).await;
+    expect(&mut stream, 11, 24).await;
+    tx_sub_dags.send(sub_dag(24, 1)).await.unwrap();
+    tx_sub_dags.send(sub_dag(26, 1)).await.unwrap();
+    expect(&mut stream, 12, 26).await;
+}
+
+#[tokio::test]
+async fn slow_subscriber() {
+    let store = Database::new_in_memory();
+    let address = "127.0.0.1:16002".parse::<SocketAddr>().unwrap();
+    let (tx_sub_dags, rx_sub_dags) = channel(10);
+    CommitService::spawn(address, &store, "unused", rx_sub_dags).await;
+    sleep(Duration::from_millis(50)).await;
+
+    // A subscriber that does not read does not hold back consensus, even once the sub-dags it did
+    // not read overflow the network buffers and those we keep in memory.
+    let mut stream = subscribe(address, 0).await;
+    let total = 3 * LIVE_CAPACITY as u64;
+    let output = async {
+        for i in 0..total {
+            tx_sub_dags.send(sub_dag(2 * (i + 1), 200)).await.unwrap();
+        }
+    };
+    timeout(Duration::from_secs(10), output)
+        .await
+        .expect("Consensus was blocked by the subscriber");
+
+    // Once it reads again, it catches up from the store, and misses nothing.
+    for i in 0..total {
+        let message = timeout(Duration::from_secs(5), stream.message())
+            .await
+            .expect("Timed out waiting for a sub-dag")
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.index, i);
+    }
+}
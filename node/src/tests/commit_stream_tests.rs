@@ -0,0 +1,414 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use client::{
+    now_micros, ClientError, CommitInfo, LatencyRecorder, NarwhalClient, ReceiptClient, SubmitAck,
+};
+use config::{Committee, KeyPair, Parameters};
+use consensus::{Consensus, ConsensusOptions};
+use crypto::Digest;
+use futures::future::join_all;
+use futures::stream::StreamExt as _;
+use primary::{Certificate, Header, Primary};
+use std::convert::TryInto as _;
+use store::{Database, Family};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver as ChannelReceiver};
+use tokio::time::sleep;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use worker::{Worker, WorkerMessage, WorkerOptions};
+
+// Fixture
+fn transaction() -> Bytes {
+    Bytes::from(vec![0; 100])
+}
+
+// Fixture
+fn batch(round: u64, index: u64, transactions: Vec<Bytes>) -> CommittedBatch {
+    CommittedBatch {
+        round,
+        index,
+        digest: [round as u8; 32],
+        transactions,
+    }
+}
+
+// Fixture: a transaction tagged with an id (in its first 8 bytes).
+fn tagged(id: u64) -> Bytes {
+    let mut transaction = id.to_be_bytes().to_vec();
+    transaction.extend_from_slice(&[0; 92]);
+    Bytes::from(transaction)
+}
+
+// Fixture
+fn parse_tag(transaction: &[u8]) -> Option<u64> {
+    transaction
+        .get(..8)?
+        .try_into()
+        .ok()
+        .map(u64::from_be_bytes)
+}
+
+// Fixture: subscribes to the commit stream, from a consensus index (if any).
+async fn subscribe(
+    address: SocketAddr,
+    from: Option<u64>,
+) -> Framed<TcpStream, LengthDelimitedCodec> {
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let frame = match from {
+        Some(index) => Bytes::copy_from_slice(&index.to_be_bytes()),
+        None => Bytes::from("subscribe"),
+    };
+    transport.send(frame).await.unwrap();
+    transport
+}
+
+// Fixture
+async fn next_batch(subscription: &mut Framed<TcpStream, LengthDelimitedCodec>) -> CommittedBatch {
+    let frame = timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .expect("Timed out waiting for a committed batch")
+        .unwrap()
+        .unwrap();
+    bincode::deserialize(&frame).unwrap()
+}
+
+// Fixture
+fn spawn_worker(base_port: u16) -> SocketAddr {
+    let committee = Committee::new_for_test(4, base_port, /* seed */ 0);
+    let name = KeyPair::new_for_test(/* seed */ 0, 0).name;
+    let address = committee.worker(&name, &0).unwrap().transactions;
+    Worker::spawn(
+        name,
+        /* id */ 0,
+        committee,
+        Parameters::default(),
+        Database::new_in_memory(),
+        WorkerOptions::default(),
+    )
+    .unwrap();
+    address
+}
+
+// Fixture: spawns a committee of 4 authorities (a primary, its consensus, and one worker each),
+// and publishes the batches committed by the first authority on the commit stream. The worker of
+// the first authority issues receipts if it follows the commit stream (on `receipts`). Returns its
+// transactions address.
+fn spawn_committee(
+    base_port: u16,
+    commits: CommitStream,
+    receipts: Option<SocketAddr>,
+) -> SocketAddr {
+    let committee = Committee::new_for_test(4, base_port, /* seed */ 0);
+    let parameters = Parameters {
+        header_size: 32,
+        max_header_delay: 100,
+        batch_size: 1_000,
+        max_batch_delay: 50,
+        ..Parameters::default()
+    };
+
+    for i in 0..committee.size() {
+        let keypair = KeyPair::new_for_test(/* seed */ 0, i);
+        let name = keypair.name;
+        let (tx_new_certificates, rx_new_certificates) = channel(100);
+        let (tx_feedback, rx_feedback) = channel(100);
+        let (tx_output, rx_output) = channel(100);
+        Primary::spawn(
+            keypair,
+            committee.clone(),
+            parameters.clone(),
+            Database::new_in_memory(),
+            /* tx_consensus */ tx_new_certificates,
+            /* rx_consensus */ rx_feedback,
+        );
+        Consensus::spawn(
+            committee.clone(),
+            parameters.gc_depth,
+            /* rx_primary */ rx_new_certificates,
+            /* tx_primary */ tx_feedback,
+            tx_output.into(),
+            ConsensusOptions::default(),
+        );
+        let store = Database::new_in_memory();
+        Worker::spawn(
+            name,
+            /* id */ 0,
+            committee.clone(),
+            Parameters {
+                commit_stream: receipts.filter(|_| i == 0),
+                ..parameters.clone()
+            },
+            store.clone(),
+            WorkerOptions::default(),
+        )
+        .unwrap();
+
+        if i == 0 {
+            tokio::spawn(publish_commits(rx_output, store, commits.clone()));
+        }
+    }
+    committee
+        .worker(&KeyPair::new_for_test(/* seed */ 0, 0).name, &0)
+        .unwrap()
+        .transactions
+}
+
+// Reads the batches of the committed certificates from the store of the worker, and publishes them
+// in commit order (as the node does).
+async fn publish_commits(
+    mut rx_output: ChannelReceiver<Certificate>,
+    database: Database,
+    commits: CommitStream,
+) {
+    let mut batches = BatchSource::Store(database.store(Family::Batches));
+    let mut next_index = 0;
+    while let Some(certificate) = rx_output.recv().await {
+        publish_certificate(&certificate, &mut next_index, &mut batches, Some(&commits)).await;
+    }
+}
+
+#[tokio::test]
+async fn replay_from_index() {
+    let address = "127.0.0.1:16100".parse::<SocketAddr>().unwrap();
+    let commits = CommitStream::spawn(address);
+    sleep(Duration::from_millis(50)).await;
+    let first = batch(2, 0, vec![Bytes::from("a"), Bytes::from("b")]);
+    let second = batch(4, 2, vec![Bytes::from("c")]);
+    commits.publish(&first);
+    commits.publish(&second);
+
+    // Subscribers receive the recent batches, and then the new ones.
+    let mut subscription = subscribe(address, None).await;
+    assert_eq!(next_batch(&mut subscription).await, first);
+    assert_eq!(next_batch(&mut subscription).await, second);
+
+    // Unless they only ask for the batches holding transactions from some index on.
+    let mut partial = subscribe(address, Some(2)).await;
+    assert_eq!(next_batch(&mut partial).await, second);
+    let mut live = subscribe(address, Some(u64::MAX)).await;
+    sleep(Duration::from_millis(50)).await;
+
+    let third = batch(6, 3, vec![Bytes::from("d")]);
+    commits.publish(&third);
+    assert_eq!(next_batch(&mut subscription).await, third);
+    assert_eq!(next_batch(&mut partial).await, third);
+    assert_eq!(next_batch(&mut live).await, third);
+
+    let mut late = subscribe(address, Some(3)).await;
+    assert_eq!(next_batch(&mut late).await, third);
+}
+
+#[tokio::test]
+async fn submit_and_wait_for_commits() {
+    let commits = "127.0.0.1:16101".parse::<SocketAddr>().unwrap();
+    let address = spawn_committee(
+        16_200,
+        CommitStream::spawn(commits),
+        /* receipts */ None,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    // Wait for many distinct transactions at once: consensus orders each of them exactly once.
+    let client = NarwhalClient::connect(vec![address])
+        .await
+        .unwrap()
+        .watch_commits(commits)
+        .await
+        .unwrap();
+    let waits = (0..100u64).map(|i| {
+        let transaction = Bytes::from(i.to_be_bytes().repeat(10));
+        client.submit_and_wait(transaction, Duration::from_secs(30))
+    });
+    let mut indices: Vec<_> = join_all(waits)
+        .await
+        .into_iter()
+        .map(|commit| commit.unwrap().index)
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    assert_eq!(indices.len(), 100);
+}
+
+#[tokio::test]
+async fn wait_for_own_commit() {
+    // The transaction was committed before: submitting it again waits for its new commit.
+    let commits = "127.0.0.1:16102".parse::<SocketAddr>().unwrap();
+    let stream = CommitStream::spawn(commits);
+    stream.publish(&batch(4, 10, vec![Bytes::from("other"), transaction()]));
+    let address = spawn_worker(16_400);
+    sleep(Duration::from_millis(50)).await;
+
+    let client = NarwhalClient::connect(vec![address])
+        .await
+        .unwrap()
+        .watch_commits(commits)
+        .await
+        .unwrap();
+    let delay = Duration::from_millis(200);
+    match client.submit_and_wait(transaction(), delay).await {
+        Err(ClientError::Timeout(x)) => assert_eq!(x, delay),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    let wait = tokio::spawn(async move {
+        client
+            .submit_and_wait(transaction(), Duration::from_secs(5))
+            .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    stream.publish(&batch(6, 12, vec![transaction()]));
+    let expected = CommitInfo {
+        index: 12,
+        round: 6,
+        batch: [6; 32],
+    };
+    assert_eq!(wait.await.unwrap().unwrap(), expected);
+}
+
+#[tokio::test]
+async fn wait_for_commit_timeout() {
+    let commits = "127.0.0.1:16103".parse::<SocketAddr>().unwrap();
+    let _stream = CommitStream::spawn(commits);
+    let address = spawn_worker(16_500);
+    sleep(Duration::from_millis(50)).await;
+
+    // Nothing is ever committed.
+    let client = NarwhalClient::connect(vec![address]).await.unwrap();
+    let delay = Duration::from_millis(100);
+    match client.submit_and_wait(transaction(), delay).await {
+        Err(ClientError::NoCommitStream) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+    let client = client.watch_commits(commits).await.unwrap();
+    match client.submit_and_wait(transaction(), delay).await {
+        Err(ClientError::Timeout(x)) => assert_eq!(x, delay),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn receive_receipt_after_commit() {
+    let commits = "127.0.0.1:16104".parse::<SocketAddr>().unwrap();
+    let address = spawn_committee(16_300, CommitStream::spawn(commits), Some(commits));
+    sleep(Duration::from_millis(100)).await;
+
+    // Submit a tagged transaction: the worker acknowledges it, and later sends its receipt.
+    let mut client = ReceiptClient::connect(address).await.unwrap();
+    let transaction = Bytes::from("tagged transaction");
+    let ack = client.submit(42, transaction.clone()).await.unwrap();
+    assert_eq!(ack, SubmitAck { worker: address });
+    let receipt = timeout(Duration::from_secs(30), client.receipt())
+        .await
+        .expect("No receipt")
+        .unwrap();
+    assert_eq!(receipt.id, 42);
+
+    // The receipt follows the commit of the batch holding the transaction (the commit stream
+    // replays it to new subscribers).
+    let mut subscription = subscribe(commits, None).await;
+    let committed = loop {
+        let batch = next_batch(&mut subscription).await;
+        if batch.digest == receipt.batch {
+            break batch;
+        }
+    };
+    assert_eq!(committed.round, receipt.round);
+    assert!(committed.transactions.contains(&transaction));
+}
+
+#[tokio::test]
+async fn measure_commit_latency_percentiles() {
+    let commits = "127.0.0.1:16105".parse::<SocketAddr>().unwrap();
+    let stream = CommitStream::spawn(commits);
+    let address = spawn_worker(16_600);
+    sleep(Duration::from_millis(50)).await;
+
+    let mut subscription = subscribe(commits, None).await;
+    let client = NarwhalClient::connect(vec![address]).await.unwrap();
+    let mut recorder = LatencyRecorder::new(parse_tag, Duration::from_secs(30));
+
+    // Submit 100 tagged transactions.
+    for id in 0..100 {
+        recorder.submitted(id, now_micros());
+    }
+    let acks = join_all((0..100).map(|id| client.submit(tagged(id)))).await;
+    assert!(acks.into_iter().all(|x| x.is_ok()));
+
+    // Commit half of them after 100ms, most of the others after 300ms, and the last ones after
+    // 600ms (each batch also carries a transaction that is not ours).
+    let mut start = 0;
+    for (delay, end) in [(100, 50), (200, 90), (300, 100)] {
+        sleep(Duration::from_millis(delay)).await;
+        let mut transactions: Vec<_> = (start..end).map(tagged).collect();
+        transactions.push(Bytes::from("other"));
+        stream.publish(&batch(end, start, transactions));
+        start = end;
+    }
+
+    // Follow the commit stream until all our transactions are committed.
+    while recorder.in_flight() > 0 {
+        let batch = next_batch(&mut subscription).await;
+        recorder.committed(&batch, now_micros());
+    }
+    assert_eq!(recorder.commits().len(), 100);
+
+    let percentiles = recorder.percentiles().unwrap();
+    let within = |x: Duration, low: u64, high: u64| {
+        x >= Duration::from_millis(low) && x < Duration::from_millis(high)
+    };
+    assert!(within(percentiles.p50, 100, 300), "{:?}", percentiles);
+    assert!(within(percentiles.p90, 300, 600), "{:?}", percentiles);
+    assert!(within(percentiles.p99, 600, 1_000), "{:?}", percentiles);
+}
+
+#[tokio::test]
+async fn publish_from_worker_stores() {
+    let path = ".db_test_commit_stream";
+    let _ = std::fs::remove_dir_all(format!("{}-0", path));
+    let _ = std::fs::remove_dir_all(format!("{}-0-analyze-secondary", path));
+
+    // Worker 0 holds two batches of the certificate, and misses the third.
+    let worker_store = Database::open(&format!("{}-0", path)).unwrap();
+    let mut store = worker_store.store(Family::Batches);
+    let digests: Vec<_> = (1..=3u8).map(|i| Digest([i; 32])).collect();
+    for (digest, transactions) in digests
+        .iter()
+        .zip([vec![tagged(0), tagged(1)], vec![tagged(2)]])
+    {
+        let serialized = bincode::serialize(&WorkerMessage::Batch(transactions)).unwrap();
+        store.write(digest, &serialized).await;
+    }
+    worker_store.flush().await.unwrap();
+    let certificate = Certificate {
+        header: Header {
+            round: 4,
+            payload: digests.iter().map(|x| (x.clone(), 0)).collect(),
+            ..Header::default()
+        },
+        ..Certificate::default()
+    };
+
+    // The batches are numbered from the next consensus index, in the order of the payload.
+    let mut batches = BatchSource::Workers(WorkerStores::new(path, "analyze"));
+    let mut next_index = 5;
+    let committed = publish_certificate(&certificate, &mut next_index, &mut batches, None).await;
+    assert_eq!(
+        committed,
+        vec![
+            CommittedBatch {
+                round: 4,
+                index: 5,
+                digest: digests[0].0,
+                transactions: vec![tagged(0), tagged(1)],
+            },
+            CommittedBatch {
+                round: 4,
+                index: 7,
+                digest: digests[1].0,
+                transactions: vec![tagged(2)],
+            },
+        ]
+    );
+    assert_eq!(next_index, 8);
+}
@@ -0,0 +1,299 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::status::LastCommit;
+use config::{Committee, KeyPair, MERKLE_BATCHES_VERSION};
+use crypto::{PublicKey, Signature};
+use primary::Header;
+use serde_json::Value;
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn certificate(round: Round, author: u8) -> Certificate {
+    Certificate {
+        header: Header {
+            author: PublicKey([author; 32]),
+            round,
+            payload: [(Digest([1; 32]), 0)].iter().cloned().collect(),
+            parents: [Digest([2; 32]), Digest([3; 32])].iter().cloned().collect(),
+            ..Header::default()
+        },
+        votes: vec![(PublicKey::default(), Signature::default()); 3],
+    }
+}
+
+// Fixture
+fn serialized_batch(transactions: Vec<Vec<u8>>) -> Vec<u8> {
+    let batch = transactions.into_iter().map(Bytes::from).collect();
+    bincode::serialize(&WorkerMessage::Batch(batch)).unwrap()
+}
+
+// Fixture
+fn commit(status: &StatusBoard, round: Round) {
+    status.lock().last_commit = Some(LastCommit { index: 0, round });
+}
+
+// Fixture
+async fn get(explorer: &Explorer, target: &str) -> (&'static str, Value) {
+    let (status, body) = explorer.get(target).await.expect("Unknown target");
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn get_certificate() {
+    let store = Database::new_in_memory();
+    let status = StatusBoard::default();
+    let explorer = Explorer::primary(&store, "unused", status.clone(), 10);
+    let certificate = certificate(15, 1);
+    let digest = certificate.digest();
+    store
+        .store(Family::Certificates)
+        .write(&digest, &certificate)
+        .await;
+
+    // Found.
+    let (code, body) = get(&explorer, &format!("/certificate/{}", hex(&digest.0))).await;
+    assert_eq!(code, "200 OK");
+    let view: CertificateView = serde_json::from_value(body).unwrap();
+    assert_eq!(view.digest, hex(&digest.0));
+    assert_eq!(view.round, 15);
+    assert_eq!(view.origin, hex(&[1; 32]));
+    assert_eq!(view.parents, vec![hex(&[2; 32]), hex(&[3; 32])]);
+    assert_eq!(view.payload, vec![(hex(&[1; 32]), 0)]);
+    assert_eq!(view.signatures, 3);
+
+    // Missing.
+    let (code, _) = get(&explorer, &format!("/certificate/{}", hex(&[9; 32]))).await;
+    assert_eq!(code, "404 Not Found");
+    let (code, _) = get(&explorer, "/certificate/not-a-digest").await;
+    assert_eq!(code, "400 Bad Request");
+
+    // Pruned once its round falls below the GC watermark.
+    commit(&status, 30);
+    let (code, body) = get(&explorer, &format!("/certificate/{}", hex(&digest.0))).await;
+    assert_eq!(code, "410 Gone");
+    assert_eq!(body["gc_round"], 20);
+}
+
+#[tokio::test]
+async fn list_round_certificates() {
+    let store = Database::new_in_memory();
+    let status = StatusBoard::default();
+    let explorer = Explorer::primary(&store, "unused", status.clone(), 10);
+    let (tx_primary, rx_primary) = channel(10);
+    let (tx_consensus, mut rx_consensus) = channel(10);
+    explorer.observe(rx_primary, tx_consensus);
+
+    // The certificates reach consensus, indexed by round.
+    let certificates = vec![certificate(5, 1), certificate(5, 2), certificate(6, 1)];
+    for certificate in &certificates {
+        tx_primary.send(certificate.clone()).await.unwrap();
+        rx_consensus.recv().await.unwrap();
+    }
+    let (code, body) = get(&explorer, "/round/5/certificates").await;
+    assert_eq!(code, "200 OK");
+    let mut expected: Vec<_> = certificates[..2]
+        .iter()
+        .map(|x| hex(&x.digest().0))
+        .collect();
+    expected.sort();
+    assert_eq!(body["certificates"], json!(expected));
+
+    // Rounds we did not see are empty.
+    let (code, body) = get(&explorer, "/round/7/certificates").await;
+    assert_eq!(code, "200 OK");
+    assert_eq!(body["certificates"], json!([]));
+
+    // Rounds below the GC watermark are pruned (and forgotten).
+    commit(&status, 16);
+    tx_primary.send(certificate(16, 1)).await.unwrap();
+    rx_consensus.recv().await.unwrap();
+    let (code, body) = get(&explorer, "/round/5/certificates").await;
+    assert_eq!(code, "410 Gone");
+    assert_eq!(body["gc_round"], 6);
+    let (code, body) = get(&explorer, "/round/6/certificates").await;
+    assert_eq!(code, "200 OK");
+    assert_eq!(
+        body["certificates"],
+        json!([hex(&certificates[2].digest().0)])
+    );
+    assert!(!explorer
+        .certificates
+        .unwrap()
+        .rounds
+        .lock()
+        .unwrap()
+        .contains_key(&5));
+}
+
+#[tokio::test]
+async fn get_batch_from_worker() {
+    let store = Database::new_in_memory();
+    let explorer = Explorer::worker(&store);
+    let digest = Digest([7; 32]);
+    let transactions = vec![vec![1u8; 600_000], vec![2u8; 600_000]];
+    store
+        .store(Family::Batches)
+        .write(&digest, &serialized_batch(transactions))
+        .await;
+
+    // Found, without its payload by default.
+    let target = format!("/batch/{}", hex(&digest.0));
+    let (code, body) = get(&explorer, &target).await;
+    assert_eq!(code, "200 OK");
+    let view: BatchView = serde_json::from_value(body).unwrap();
+    assert_eq!(view.transactions, 2);
+    assert_eq!(view.size, 1_200_000);
+    assert_eq!(view.worker_id, None);
+    assert!(view.payload.is_none());
+
+    // The payload is capped.
+    let (code, body) = get(&explorer, &format!("{}?include_payload=true", target)).await;
+    assert_eq!(code, "200 OK");
+    let view: BatchView = serde_json::from_value(body).unwrap();
+    assert_eq!(view.payload, Some(vec![hex(&[1u8; 600_000])]));
+    assert!(view.truncated);
+
+    // Missing.
+    let (code, _) = get(&explorer, &format!("/batch/{}", hex(&[9; 32]))).await;
+    assert_eq!(code, "404 Not Found");
+
+    // Workers have no certificates.
+    let (code, _) = get(&explorer, &format!("/certificate/{}", hex(&digest.0))).await;
+    assert_eq!(code, "404 Not Found");
+    assert!(explorer.get("/unknown").await.is_none());
+}
+
+#[tokio::test]
+async fn get_batch_from_primary() {
+    let path = ".db_test_explorer";
+    let _ = fs::remove_dir_all(path);
+    let _ = fs::remove_dir_all(format!("{}-0", path));
+    let _ = fs::remove_dir_all(format!("{}-0-explorer-secondary", path));
+
+    // The batch is in the store of worker 0, and the primary knows it.
+    let worker_store = Database::open(&format!("{}-0", path)).unwrap();
+    let digest = Digest([7; 32]);
+    let transactions = vec![vec![1u8; 10], vec![2u8; 10]];
+    worker_store
+        .store(Family::Batches)
+        .write(&digest, &serialized_batch(transactions))
+        .await;
+    worker_store.flush().await.unwrap();
+    let store = Database::new_in_memory();
+    store
+        .store(Family::Payloads)
+        .write(&(digest.clone(), 0), &())
+        .await;
+
+    let explorer = Explorer::primary(&store, path, StatusBoard::default(), 10);
+    let target = format!("/batch/{}?include_payload=true", hex(&digest.0));
+    let (code, body) = get(&explorer, &target).await;
+    assert_eq!(code, "200 OK");
+    let view: BatchView = serde_json::from_value(body).unwrap();
+    assert_eq!(view.worker_id, Some(0));
+    assert_eq!(view.payload, Some(vec![hex(&[1u8; 10]), hex(&[2u8; 10])]));
+    assert!(!view.truncated);
+
+    // Batches unknown to the primary.
+    let (code, _) = get(&explorer, &format!("/batch/{}", hex(&[9; 32]))).await;
+    assert_eq!(code, "404 Not Found");
+}
+
+#[tokio::test]
+async fn get_inclusion_proof() {
+    let path = ".db_test_explorer_proof";
+    let _ = fs::remove_dir_all(path);
+    let _ = fs::remove_dir_all(format!("{}-0", path));
+    let _ = fs::remove_dir_all(format!("{}-0-explorer-secondary", path));
+
+    // Worker 0 stores a batch under the Merkle root of its transactions, and a batch digested the
+    // legacy way.
+    let transactions: Vec<_> = (0..3u8).map(|i| Bytes::from(vec![i; 10])).collect();
+    let digest = merkle_root(&transactions);
+    let legacy = Digest([7; 32]);
+    let worker_store = Database::open(&format!("{}-0", path)).unwrap();
+    let mut batches = worker_store.store(Family::Batches);
+    let serialized = bincode::serialize(&WorkerMessage::Batch(transactions.clone())).unwrap();
+    batches.write(&digest, &serialized).await;
+    batches.write(&legacy, &serialized).await;
+    worker_store.flush().await.unwrap();
+
+    // The primary certified both.
+    let committee = Committee {
+        protocol_version: MERKLE_BATCHES_VERSION,
+        ..Committee::new_for_test(4, 0, /* seed */ 0)
+    };
+    let keys: Vec<_> = (0..4).map(|i| KeyPair::new_for_test(0, i)).collect();
+    let mut header = Header {
+        author: keys[0].name,
+        round: 1,
+        payload: [(digest.clone(), 0), (legacy.clone(), 0)]
+            .iter()
+            .cloned()
+            .collect(),
+        ..Header::default()
+    };
+    header.id = header.digest();
+    header.signature = Signature::new(&header.id, &keys[0].secret);
+    let mut certificate = Certificate {
+        header,
+        votes: Vec::new(),
+    };
+    certificate.votes = keys
+        .iter()
+        .map(|x| (x.name, Signature::new(&certificate.digest(), &x.secret)))
+        .collect();
+    let store = Database::new_in_memory();
+    for batch in [&digest, &legacy] {
+        store
+            .store(Family::Payloads)
+            .write(&(batch.clone(), 0), &())
+            .await;
+    }
+    store
+        .store(Family::Certificates)
+        .write(&certificate.digest(), &certificate)
+        .await;
+    let explorer = Explorer::primary(&store, path, StatusBoard::default(), 10);
+    explorer.record(&certificate);
+
+    // The proof, by index or by transaction, checks against the committee.
+    let targets = [
+        format!("/batch/{}/proof?index=1", hex(&digest.0)),
+        format!(
+            "/batch/{}/proof?transaction={}",
+            hex(&digest.0),
+            hex(&crypto::hash(&transactions[1]).0)
+        ),
+    ];
+    for target in &targets {
+        let (code, body) = get(&explorer, target).await;
+        assert_eq!(code, "200 OK");
+        let view: InclusionProofView = serde_json::from_value(body).unwrap();
+        assert_eq!(view.index, 1);
+        assert_eq!(view.certificate, hex(&certificate.digest().0));
+        let bytes: Vec<_> = (0..view.proof.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&view.proof[i..i + 2], 16).unwrap())
+            .collect();
+        let proof: InclusionProof = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(proof.transaction, transactions[1]);
+        assert!(proof.verify(&committee).is_ok());
+    }
+
+    // Transactions not in the batch.
+    let target = format!("/batch/{}/proof?index=3", hex(&digest.0));
+    let (code, _) = get(&explorer, &target).await;
+    assert_eq!(code, "404 Not Found");
+
+    // Batches not digested into Merkle roots.
+    let target = format!("/batch/{}/proof?index=0", hex(&legacy.0));
+    let (code, _) = get(&explorer, &target).await;
+    assert_eq!(code, "409 Conflict");
+
+    // Batches no certificate references.
+    let target = format!("/batch/{}/proof?index=0", hex(&[9; 32]));
+    let (code, _) = get(&explorer, &target).await;
+    assert_eq!(code, "404 Not Found");
+}
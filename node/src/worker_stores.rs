@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use anyhow::{Context as _, Result};
+use config::WorkerId;
+use crypto::Digest;
+use std::collections::HashMap;
+use store::Family;
+
+/// The stores of the workers of this primary (at `{store_path}-{worker_id}`), from which we read
+/// the batches the primary only knows the digests of. Each store is opened as a secondary instance
+/// of the worker's database the first time we read from it, and kept open.
+pub struct WorkerStores {
+    store_path: String,
+    /// Tells apart the secondary instances of the different readers of the same worker store.
+    reader: &'static str,
+    secondaries: HashMap<WorkerId, rocksdb::DB>,
+}
+
+impl WorkerStores {
+    pub fn new(store_path: &str, reader: &'static str) -> Self {
+        Self {
+            store_path: store_path.to_string(),
+            reader,
+            secondaries: HashMap::new(),
+        }
+    }
+
+    /// Returns the serialized batch (as the worker stored it), if the worker holds it.
+    pub fn read(&mut self, digest: &Digest, worker_id: WorkerId) -> Result<Option<Vec<u8>>> {
+        let secondary = match self.secondaries.get(&worker_id) {
+            Some(secondary) => secondary,
+            None => {
+                let path = format!("{}-{}", self.store_path, worker_id);
+                let secondary_path = format!("{}-{}-secondary", path, self.reader);
+                let secondary = rocksdb::DB::open_cf_as_secondary(
+                    &rocksdb::Options::default(),
+                    &path,
+                    &secondary_path,
+                    [Family::Batches.name()],
+                )
+                .with_context(|| format!("Failed to open the store of worker {}", worker_id))?;
+                self.secondaries.entry(worker_id).or_insert(secondary)
+            }
+        };
+        secondary.try_catch_up_with_primary()?;
+        let family = secondary
+            .cf_handle(Family::Batches.name())
+            .context("Missing batches column family")?;
+
+        // Digests are their own key encoding.
+        match secondary.get_cf(family, digest.to_vec())? {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
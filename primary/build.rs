@@ -0,0 +1,7 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a vendored `protoc`, so building does not require it to be installed.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    prost_build::compile_protos(&["proto/wire.proto"], &["proto"])?;
+    Ok(())
+}
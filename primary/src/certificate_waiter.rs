@@ -1,6 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::messages::Certificate;
+use crypto::Digest;
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
@@ -11,8 +12,8 @@ use tokio::sync::mpsc::{Receiver, Sender};
 /// Waits to receive all the ancestors of a certificate before looping it back to the `Core`
 /// for further processing.
 pub struct CertificateWaiter {
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the certificates.
+    store: Store<Digest, Certificate>,
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<Certificate>,
     /// Loops back to the core certificates for which we got all parents.
@@ -21,7 +22,7 @@ pub struct CertificateWaiter {
 
 impl CertificateWaiter {
     pub fn spawn(
-        store: Store,
+        store: Store<Digest, Certificate>,
         rx_synchronizer: Receiver<Certificate>,
         tx_core: Sender<Certificate>,
     ) {
@@ -39,13 +40,10 @@ impl CertificateWaiter {
     /// Helper function. It waits for particular data to become available in the storage
     /// and then delivers the specified header.
     async fn waiter(
-        mut missing: Vec<(Vec<u8>, Store)>,
+        mut missing: Vec<(Digest, Store<Digest, Certificate>)>,
         deliver: Certificate,
     ) -> DagResult<Certificate> {
-        let waiting: Vec<_> = missing
-            .iter_mut()
-            .map(|(x, y)| y.notify_read(x.to_vec()))
-            .collect();
+        let waiting: Vec<_> = missing.iter_mut().map(|(x, y)| y.notify_read(x)).collect();
 
         try_join_all(waiting)
             .await
@@ -65,8 +63,7 @@ impl CertificateWaiter {
                         .header
                         .parents
                         .iter()
-                        .cloned()
-                        .map(|x| (x.to_vec(), self.store.clone()))
+                        .map(|x| (x.clone(), self.store.clone()))
                         .collect();
                     let fut = Self::waiter(wait_for, certificate);
                     waiting.push(fut);
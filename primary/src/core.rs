@@ -10,12 +10,13 @@ use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
 use log::{debug, error, warn};
-use network::{CancelHandler, ReliableSender};
+use network::{CancelHandler, ReliableSender, SharedTranscoder};
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use store::Store;
+use store::{Store, WriteBatch};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::instrument;
 
 #[cfg(test)]
 #[path = "tests/core_tests.rs"]
@@ -26,8 +27,10 @@ pub struct Core {
     name: PublicKey,
     /// The committee information.
     committee: Committee,
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the headers.
+    header_store: Store<Digest, Header>,
+    /// The persistent storage of the certificates.
+    certificate_store: Store<Digest, Certificate>,
     /// Handles synchronization with other nodes and our workers.
     synchronizer: Synchronizer,
     /// Service to sign headers.
@@ -73,7 +76,8 @@ impl Core {
     pub fn spawn(
         name: PublicKey,
         committee: Committee,
-        store: Store,
+        header_store: Store<Digest, Header>,
+        certificate_store: Store<Digest, Certificate>,
         synchronizer: Synchronizer,
         signature_service: SignatureService,
         consensus_round: Arc<AtomicU64>,
@@ -84,12 +88,14 @@ impl Core {
         rx_proposer: Receiver<Header>,
         tx_consensus: Sender<Certificate>,
         tx_proposer: Sender<(Vec<Certificate>, Round)>,
+        transcoder: Option<SharedTranscoder>,
     ) {
         tokio::spawn(async move {
             Self {
                 name,
                 committee,
-                store,
+                header_store,
+                certificate_store,
                 synchronizer,
                 signature_service,
                 consensus_round,
@@ -106,7 +112,7 @@ impl Core {
                 current_header: Header::default(),
                 votes_aggregator: VotesAggregator::new(),
                 certificates_aggregators: HashMap::with_capacity(2 * gc_depth as usize),
-                network: ReliableSender::new(),
+                network: ReliableSender::new().with_transcoder(transcoder),
                 cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
             }
             .run()
@@ -131,20 +137,21 @@ impl Core {
         let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
         self.cancel_handlers
             .entry(header.round)
-            .or_insert_with(Vec::new)
+            .or_default()
             .extend(handlers);
 
         // Process the header.
         self.process_header(&header).await
     }
 
+    #[instrument(level = "debug", skip_all, fields(digest = ?header.id, round = header.round))]
     #[async_recursion]
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
         debug!("Processing {:?}", header);
         // Indicate that we are processing this header.
         self.processing
             .entry(header.round)
-            .or_insert_with(HashSet::new)
+            .or_default()
             .insert(header.id.clone());
 
         // Ensure we have the parents. If at least one parent is missing, the synchronizer returns an empty
@@ -178,14 +185,13 @@ impl Core {
         }
 
         // Store the header.
-        let bytes = bincode::serialize(header).expect("Failed to serialize header");
-        self.store.write(header.id.to_vec(), bytes).await;
+        self.header_store.write(&header.id, header).await;
 
         // Check if we can vote for this header.
         if self
             .last_voted
             .entry(header.round)
-            .or_insert_with(HashSet::new)
+            .or_default()
             .insert(header.author)
         {
             // Make a vote and send it to the header's creator.
@@ -206,13 +212,14 @@ impl Core {
                 let handler = self.network.send(address, Bytes::from(bytes)).await;
                 self.cancel_handlers
                     .entry(header.round)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(handler);
             }
         }
         Ok(())
     }
 
+    #[instrument(level = "debug", skip_all, fields(header = ?vote.id, voter = %vote.author))]
     #[async_recursion]
     async fn process_vote(&mut self, vote: Vote) -> DagResult<()> {
         debug!("Processing {:?}", vote);
@@ -236,7 +243,7 @@ impl Core {
             let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
             self.cancel_handlers
                 .entry(certificate.round())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .extend(handlers);
 
             // Process the new certificate.
@@ -247,6 +254,11 @@ impl Core {
         Ok(())
     }
 
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(digest = ?certificate.digest(), header = ?certificate.header.id, round = certificate.round())
+    )]
     #[async_recursion]
     async fn process_certificate(&mut self, certificate: Certificate) -> DagResult<()> {
         debug!("Processing {:?}", certificate);
@@ -274,9 +286,20 @@ impl Core {
             return Ok(());
         }
 
-        // Store the certificate.
-        let bytes = bincode::serialize(&certificate).expect("Failed to serialize certificate");
-        self.store.write(certificate.digest().to_vec(), bytes).await;
+        // Store the certificate along with its header, so that we never hold one without the other.
+        let mut batch = WriteBatch::default();
+        batch
+            .put(
+                &self.header_store,
+                &certificate.header.id,
+                &certificate.header,
+            )
+            .put(&self.certificate_store, &certificate.digest(), &certificate);
+        batch
+            .commit()
+            .await
+            .await
+            .expect("Failed to receive reply from store")?;
 
         // Check if we have enough certificates to enter a new dag round and propose a header.
         if let Some(parents) = self
@@ -332,17 +355,17 @@ impl Core {
         );
 
         // Verify the vote.
-        vote.verify(&self.committee).map_err(DagError::from)
+        vote.verify(&self.committee)
     }
 
-    fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
+    async fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
         ensure!(
             self.gc_round <= certificate.round(),
             DagError::TooOld(certificate.digest(), certificate.round())
         );
 
         // Verify the certificate (and the embedded header).
-        certificate.verify(&self.committee).map_err(DagError::from)
+        certificate.verify_async(&self.committee).await
     }
 
     // Main loop listening to incoming messages.
@@ -366,7 +389,7 @@ impl Core {
                             }
                         },
                         PrimaryMessage::Certificate(certificate) => {
-                            match self.sanitize_certificate(&certificate) {
+                            match self.sanitize_certificate(&certificate).await {
                                 Ok(()) =>  self.process_certificate(certificate).await,
                                 error => error
                             }
@@ -1,6 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::primary::Round;
-use crypto::{CryptoError, Digest, PublicKey};
+use crypto::{CryptoError, Digest, PublicKey, Scheme};
 use store::StoreError;
 use thiserror::Error;
 
@@ -27,6 +27,12 @@ pub enum DagError {
     #[error("Invalid signature")]
     InvalidSignature(#[from] CryptoError),
 
+    #[error("Invalid signatures from {0:?}")]
+    InvalidSignatures(Vec<PublicKey>),
+
+    #[error("Signature scheme {0} does not match the committee")]
+    InvalidScheme(Scheme),
+
     #[error("Storage failure: {0}")]
     StoreError(#[from] StoreError),
 
@@ -56,4 +62,7 @@ pub enum DagError {
 
     #[error("Message {0} (round {1}) too old")]
     TooOld(Digest, Round),
+
+    #[error("Message with {1} {0} exceeds the limit of {2}")]
+    ExceedsLimit(&'static str, usize, usize),
 }
@@ -53,6 +53,16 @@ impl GarbageCollector {
         while let Some(certificate) = self.rx_consensus.recv().await {
             // TODO [issue #9]: Re-include batch digests that have not been sequenced into our next block.
 
+            // Tell our workers which certificate committed their batches (so that they can prove it).
+            if !certificate.header.payload.is_empty() {
+                let message = PrimaryWorkerMessage::Committed(certificate.header.clone());
+                let bytes =
+                    bincode::serialize(&message).expect("Failed to serialize our own message");
+                self.network
+                    .broadcast(self.addresses.clone(), Bytes::from(bytes))
+                    .await;
+            }
+
             let round = certificate.round();
             if round > last_committed_round {
                 last_committed_round = round;
@@ -1,29 +1,29 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::error::{DagError, DagResult};
-use crate::messages::Header;
+use crate::messages::{Certificate, Header};
 use crate::primary::{PrimaryMessage, PrimaryWorkerMessage, Round};
 use bytes::Bytes;
 use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
-use futures::future::try_join_all;
+use futures::future::{try_join_all, BoxFuture, FutureExt as _};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error};
 use network::SimpleSender;
+use rand::seq::SliceRandom as _;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use store::Store;
+use store::{NotifyReadError, Store};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::{sleep, Duration, Instant};
+use tokio::time::Duration;
 
-/// The resolution of the timer that checks whether we received replies to our sync requests, and triggers
-/// new sync requests if we didn't.
-const TIMER_RESOLUTION: u64 = 1_000;
+#[cfg(test)]
+#[path = "tests/header_waiter_tests.rs"]
+pub mod header_waiter_tests;
 
 /// The commands that can be sent to the `Waiter`.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum WaiterMessage {
     SyncBatches(HashMap<Digest, WorkerId>, Header),
     SyncParents(Vec<Digest>, Header),
@@ -35,13 +35,15 @@ pub struct HeaderWaiter {
     name: PublicKey,
     /// The committee information.
     committee: Committee,
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the certificates.
+    certificate_store: Store<Digest, Certificate>,
+    /// The persistent storage of the batches' digests received by our workers.
+    payload_store: Store<(Digest, WorkerId), ()>,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
     /// The depth of the garbage collector.
     gc_depth: Round,
-    /// The delay to wait before re-trying sync requests.
+    /// The delay to wait for the missing data before re-trying sync requests.
     sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request.
     sync_retry_nodes: usize,
@@ -53,9 +55,8 @@ pub struct HeaderWaiter {
 
     /// Network driver allowing to send messages.
     network: SimpleSender,
-    /// Keeps the digests of the all certificates for which we sent a sync request,
-    /// along with a timestamp (`u128`) indicating when we sent the request.
-    parent_requests: HashMap<Digest, (Round, u128)>,
+    /// Keeps the digests of the all certificates for which we sent a sync request.
+    parent_requests: HashMap<Digest, Round>,
     /// Keeps the digests of the all tx batches for which we sent a sync request,
     /// similarly to `header_requests`.
     batch_requests: HashMap<Digest, Round>,
@@ -69,7 +70,8 @@ impl HeaderWaiter {
     pub fn spawn(
         name: PublicKey,
         committee: Committee,
-        store: Store,
+        certificate_store: Store<Digest, Certificate>,
+        payload_store: Store<(Digest, WorkerId), ()>,
         consensus_round: Arc<AtomicU64>,
         gc_depth: Round,
         sync_retry_delay: u64,
@@ -81,7 +83,8 @@ impl HeaderWaiter {
             Self {
                 name,
                 committee,
-                store,
+                certificate_store,
+                payload_store,
                 consensus_round,
                 gc_depth,
                 sync_retry_delay,
@@ -99,120 +102,190 @@ impl HeaderWaiter {
     }
 
     /// Helper function. It waits for particular data to become available in the storage
-    /// and then delivers the specified header.
+    /// and then delivers the specified header. If the data does not arrive in time, it returns
+    /// the original command so that we can re-try it.
     async fn waiter(
-        mut missing: Vec<(Vec<u8>, Store)>,
-        deliver: Header,
+        missing: Vec<BoxFuture<'static, Result<(), NotifyReadError>>>,
+        message: WaiterMessage,
         mut handler: Receiver<()>,
-    ) -> DagResult<Option<Header>> {
-        let waiting: Vec<_> = missing
-            .iter_mut()
-            .map(|(x, y)| y.notify_read(x.to_vec()))
-            .collect();
+    ) -> Result<Option<Header>, (WaiterMessage, NotifyReadError)> {
         tokio::select! {
-            result = try_join_all(waiting) => {
-                result.map(|_| Some(deliver)).map_err(DagError::from)
-            }
+            result = try_join_all(missing) => match result {
+                Ok(_) => match message {
+                    WaiterMessage::SyncBatches(_, header) | WaiterMessage::SyncParents(_, header) => {
+                        Ok(Some(header))
+                    }
+                },
+                Err(e) => Err((message, e)),
+            },
             _ = handler.recv() => Ok(None),
         }
     }
 
+    /// Records sync requests for the parents we did not already request, and returns them.
+    fn register_parent_requests(&mut self, missing: Vec<Digest>, round: Round) -> Vec<Digest> {
+        let mut requires_sync = Vec::new();
+        for missing in missing {
+            self.parent_requests
+                .entry(missing.clone())
+                .or_insert_with(|| {
+                    requires_sync.push(missing);
+                    round
+                });
+        }
+        requires_sync
+    }
+
+    /// Waits for the missing data of a header (without requesting it).
+    fn wait_for(
+        &mut self,
+        message: WaiterMessage,
+    ) -> impl Future<Output = Result<Option<Header>, (WaiterMessage, NotifyReadError)>> {
+        let delay = Duration::from_millis(self.sync_retry_delay);
+        let (wait_for, header) = match &message {
+            WaiterMessage::SyncBatches(missing, header) => {
+                let wait_for: Vec<_> = missing
+                    .iter()
+                    .map(|(digest, worker_id)| {
+                        let key = (digest.clone(), *worker_id);
+                        let mut store = self.payload_store.clone();
+                        async move { store.notify_read_timeout(&key, delay).await }.boxed()
+                    })
+                    .collect();
+                (wait_for, header)
+            }
+            WaiterMessage::SyncParents(missing, header) => {
+                let wait_for: Vec<_> = missing
+                    .iter()
+                    .map(|x| {
+                        let key = x.clone();
+                        let mut store = self.certificate_store.clone();
+                        async move { store.notify_read_timeout(&key, delay).await.map(|_| ()) }
+                            .boxed()
+                    })
+                    .collect();
+                (wait_for, header)
+            }
+        };
+        let (tx_cancel, rx_cancel) = channel(1);
+        self.pending
+            .insert(header.id.clone(), (header.round, tx_cancel));
+        Self::waiter(wait_for, message, rx_cancel)
+    }
+
+    /// Asks the workers of `target` to sync the missing batches.
+    async fn request_batches(
+        &mut self,
+        missing: HashMap<WorkerId, Vec<Digest>>,
+        target: PublicKey,
+    ) {
+        for (worker_id, digests) in missing {
+            let address = self
+                .committee
+                .worker(&target, &worker_id)
+                .expect("Author of valid header is not in the committee")
+                .primary_to_worker;
+            let message = PrimaryWorkerMessage::Synchronize(digests, target);
+            let bytes =
+                bincode::serialize(&message).expect("Failed to serialize batch sync request");
+            self.network.send(address, Bytes::from(bytes)).await;
+        }
+    }
+
+    /// We did not get the missing data of a header in time: we were wrong to trust the node we
+    /// asked. We now ask other nodes (selected at random) and wait again.
+    async fn retry(&mut self, message: WaiterMessage) {
+        match &message {
+            WaiterMessage::SyncBatches(missing, header) => {
+                debug!("Synching the payload of {} (retry)", header);
+                let others: Vec<_> = self
+                    .committee
+                    .others_primaries(&self.name)
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| name != &header.author)
+                    .collect();
+                let target = match others.choose(&mut rand::thread_rng()) {
+                    Some(x) => *x,
+                    None => return,
+                };
+                let mut requests = HashMap::new();
+                for (digest, worker_id) in missing {
+                    requests
+                        .entry(*worker_id)
+                        .or_insert_with(Vec::new)
+                        .push(digest.clone());
+                }
+                self.request_batches(requests, target).await;
+            }
+            WaiterMessage::SyncParents(missing, header) => {
+                debug!("Synching the parents of {} (retry)", header);
+                let addresses = self
+                    .committee
+                    .others_primaries(&self.name)
+                    .into_iter()
+                    .filter(|(name, _)| name != &header.author)
+                    .map(|(_, x)| x.primary_to_primary)
+                    .collect();
+                let message = PrimaryMessage::CertificatesRequest(missing.clone(), self.name);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
+                self.network
+                    .lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes)
+                    .await;
+            }
+        }
+    }
+
     /// Main loop listening to the `Synchronizer` messages.
     async fn run(&mut self) {
         let mut waiting = FuturesUnordered::new();
 
-        let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
-        tokio::pin!(timer);
-
         loop {
             tokio::select! {
                 Some(message) = self.rx_synchronizer.recv() => {
-                    match message {
+                    match message.clone() {
                         WaiterMessage::SyncBatches(missing, header) => {
                             debug!("Synching the payload of {}", header);
-                            let header_id = header.id.clone();
-                            let round = header.round;
-                            let author = header.author;
 
                             // Ensure we sync only once per header.
-                            if self.pending.contains_key(&header_id) {
+                            if self.pending.contains_key(&header.id) {
                                 continue;
                             }
 
                             // Add the header to the waiter pool. The waiter will return it to when all
                             // its parents are in the store.
-                            let wait_for = missing
-                                .iter()
-                                .map(|(digest, worker_id)| {
-                                    let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-                                    (key.to_vec(), self.store.clone())
-                                })
-                                .collect();
-                            let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
-                            let fut = Self::waiter(wait_for, header, rx_cancel);
-                            waiting.push(fut);
+                            waiting.push(self.wait_for(message));
 
                             // Ensure we didn't already send a sync request for these parents.
                             let mut requires_sync = HashMap::new();
                             for (digest, worker_id) in missing.into_iter() {
                                 self.batch_requests.entry(digest.clone()).or_insert_with(|| {
                                     requires_sync.entry(worker_id).or_insert_with(Vec::new).push(digest);
-                                    round
+                                    header.round
                                 });
                             }
-                            for (worker_id, digests) in requires_sync {
-                                let address = self.committee
-                                    .worker(&author, &worker_id)
-                                    .expect("Author of valid header is not in the committee")
-                                    .primary_to_worker;
-                                let message = PrimaryWorkerMessage::Synchronize(digests, author);
-                                let bytes = bincode::serialize(&message)
-                                    .expect("Failed to serialize batch sync request");
-                                self.network.send(address, Bytes::from(bytes)).await;
-                            }
+                            self.request_batches(requires_sync, header.author).await;
                         }
 
                         WaiterMessage::SyncParents(missing, header) => {
                             debug!("Synching the parents of {}", header);
-                            let header_id = header.id.clone();
-                            let round = header.round;
-                            let author = header.author;
 
                             // Ensure we sync only once per header.
-                            if self.pending.contains_key(&header_id) {
+                            if self.pending.contains_key(&header.id) {
                                 continue;
                             }
 
                             // Add the header to the waiter pool. The waiter will return it to us
                             // when all its parents are in the store.
-                            let wait_for = missing
-                                .iter()
-                                .cloned()
-                                .map(|x| (x.to_vec(), self.store.clone()))
-                                .collect();
-                            let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
-                            let fut = Self::waiter(wait_for, header, rx_cancel);
-                            waiting.push(fut);
+                            waiting.push(self.wait_for(message));
 
                             // Ensure we didn't already sent a sync request for these parents.
                             // Optimistically send the sync request to the node that created the certificate.
-                            // If this fails (after a timeout), we broadcast the sync request.
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .expect("Failed to measure time")
-                                .as_millis();
-                            let mut requires_sync = Vec::new();
-                            for missing in missing {
-                                self.parent_requests.entry(missing.clone()).or_insert_with(|| {
-                                    requires_sync.push(missing);
-                                    (round, now)
-                                });
-                            }
+                            // If this fails (after a timeout), we ask other nodes.
+                            let requires_sync = self.register_parent_requests(missing, header.round);
                             if !requires_sync.is_empty() {
                                 let address = self.committee
-                                    .primary(&author)
+                                    .primary(&header.author)
                                     .expect("Author of valid header not in the committee")
                                     .primary_to_primary;
                                 let message = PrimaryMessage::CertificatesRequest(requires_sync, self.name);
@@ -237,41 +310,15 @@ impl HeaderWaiter {
                     Ok(None) => {
                         // This request has been canceled.
                     },
-                    Err(e) => {
+                    Err((message, NotifyReadError::Timeout)) => {
+                        self.retry(message.clone()).await;
+                        waiting.push(self.wait_for(message));
+                    },
+                    Err((_, e)) => {
                         error!("{}", e);
                         panic!("Storage failure: killing node.");
                     }
                 },
-
-                () = &mut timer => {
-                    // We optimistically sent sync requests to a single node. If this timer triggers,
-                    // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to all nodes.
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Failed to measure time")
-                        .as_millis();
-
-                    let mut retry = Vec::new();
-                    for (digest, (_, timestamp)) in &self.parent_requests {
-                        if timestamp + (self.sync_retry_delay as u128) < now {
-                            debug!("Requesting sync for certificate {} (retry)", digest);
-                            retry.push(digest.clone());
-                        }
-                    }
-
-                    let addresses = self.committee
-                        .others_primaries(&self.name)
-                        .iter()
-                        .map(|(_, x)| x.primary_to_primary)
-                        .collect();
-                    let message = PrimaryMessage::CertificatesRequest(retry, self.name);
-                    let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                    self.network.lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes).await;
-
-                    // Reschedule the timer.
-                    timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
-                }
             }
 
             // Cleanup internal state.
@@ -286,7 +333,7 @@ impl HeaderWaiter {
                 }
                 self.pending.retain(|_, (r, _)| r > &mut gc_round);
                 self.batch_requests.retain(|_, r| r > &mut gc_round);
-                self.parent_requests.retain(|_, (r, _)| r > &mut gc_round);
+                self.parent_requests.retain(|_, r| r > &mut gc_round);
             }
         }
     }
@@ -1,10 +1,11 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
 use crate::primary::PrimaryMessage;
 use bytes::Bytes;
 use config::Committee;
 use crypto::{Digest, PublicKey};
 use log::{error, warn};
-use network::SimpleSender;
+use network::{SharedTranscoder, SimpleSender};
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
@@ -12,8 +13,8 @@ use tokio::sync::mpsc::Receiver;
 pub struct Helper {
     /// The committee information.
     committee: Committee,
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the certificates.
+    store: Store<Digest, Certificate>,
     /// Input channel to receive certificates requests.
     rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
     /// A network sender to reply to the sync requests.
@@ -23,15 +24,16 @@ pub struct Helper {
 impl Helper {
     pub fn spawn(
         committee: Committee,
-        store: Store,
+        store: Store<Digest, Certificate>,
         rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        transcoder: Option<SharedTranscoder>,
     ) {
         tokio::spawn(async move {
             Self {
                 committee,
                 store,
                 rx_primaries,
-                network: SimpleSender::new(),
+                network: SimpleSender::new().with_transcoder(transcoder),
             }
             .run()
             .await;
@@ -53,11 +55,8 @@ impl Helper {
 
             // Reply to the request (the best we can).
             for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
+                match self.store.read(&digest).await {
+                    Ok(Some(certificate)) => {
                         let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
                             .expect("Failed to serialize our own certificate");
                         self.network.send(address, Bytes::from(bytes)).await;
@@ -12,6 +12,7 @@ mod payload_receiver;
 mod primary;
 mod proposer;
 mod synchronizer;
+mod wire;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
@@ -19,3 +20,4 @@ mod common;
 
 pub use crate::messages::{Certificate, Header};
 pub use crate::primary::{Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage};
+pub use crate::wire::PrimaryTranscoder;
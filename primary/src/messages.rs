@@ -2,14 +2,15 @@
 use crate::error::{DagError, DagResult};
 use crate::primary::Round;
 use config::{Committee, WorkerId};
-use crypto::{Digest, Hash, PublicKey, Signature, SignatureService};
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use crypto::{BatchVerifyError, Digest, Hash, Hasher, PublicKey, Signature, SignatureService};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
-use std::convert::TryInto;
 use std::fmt;
 
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Header {
     pub author: PublicKey,
@@ -61,6 +62,10 @@ impl Header {
         }
 
         // Check the signature.
+        ensure!(
+            self.signature.scheme() == committee.scheme,
+            DagError::InvalidScheme(self.signature.scheme())
+        );
         self.signature
             .verify(&self.id, &self.author)
             .map_err(DagError::from)
@@ -69,8 +74,8 @@ impl Header {
 
 impl Hash for Header {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
-        hasher.update(&self.author);
+        let mut hasher = Hasher::new();
+        hasher.update(self.author);
         hasher.update(self.round.to_le_bytes());
         for (x, y) in &self.payload {
             hasher.update(x);
@@ -79,7 +84,7 @@ impl Hash for Header {
         for x in &self.parents {
             hasher.update(x);
         }
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.finalize()
     }
 }
 
@@ -136,6 +141,10 @@ impl Vote {
         );
 
         // Check the signature.
+        ensure!(
+            self.signature.scheme() == committee.scheme,
+            DagError::InvalidScheme(self.signature.scheme())
+        );
         self.signature
             .verify(&self.digest(), &self.author)
             .map_err(DagError::from)
@@ -144,11 +153,11 @@ impl Vote {
 
 impl Hash for Vote {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
+        let mut hasher = Hasher::new();
         hasher.update(&self.id);
         hasher.update(self.round.to_le_bytes());
-        hasher.update(&self.origin);
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.update(self.origin);
+        hasher.finalize()
     }
 }
 
@@ -187,21 +196,40 @@ impl Certificate {
     }
 
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        let items = self.sanitize(committee)?;
+        crypto::verify_batch(items).map_err(|e| self.blame(e))
+    }
+
+    /// Same as `verify` but large batches of votes are verified on a blocking thread, so that
+    /// checking a certificate does not stall the task receiving it.
+    pub async fn verify_async(&self, committee: &Committee) -> DagResult<()> {
+        let items = self.sanitize(committee)?;
+        crypto::verify_batch_async(items)
+            .await
+            .map_err(|e| self.blame(e))
+    }
+
+    /// Check everything but the signatures of the votes, and return the votes to verify.
+    fn sanitize(&self, committee: &Committee) -> DagResult<Vec<(Digest, PublicKey, Signature)>> {
         // Genesis certificates are always valid.
         if Self::genesis(committee).contains(self) {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        // Check the embedded header.
+        // Check the embedded header (and its signature, on its own).
         self.header.verify(committee)?;
 
         // Ensure the certificate has a quorum.
         let mut weight = 0;
         let mut used = HashSet::new();
-        for (name, _) in self.votes.iter() {
+        for (name, signature) in self.votes.iter() {
             ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
             let voting_rights = committee.stake(name);
             ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
+            ensure!(
+                signature.scheme() == committee.scheme,
+                DagError::InvalidScheme(signature.scheme())
+            );
             used.insert(*name);
             weight += voting_rights;
         }
@@ -210,8 +238,19 @@ impl Certificate {
             DagError::CertificateRequiresQuorum
         );
 
-        // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+        // The signatures of the votes are checked in a single batch.
+        let digest = self.digest();
+        Ok(self
+            .votes
+            .iter()
+            .map(|(name, signature)| (digest.clone(), *name, signature.clone()))
+            .collect())
+    }
+
+    /// Blame the authors of the votes that failed batch verification.
+    fn blame(&self, error: BatchVerifyError) -> DagError {
+        let culprits = error.failed.into_iter().map(|i| self.votes[i].0).collect();
+        DagError::InvalidSignatures(culprits)
     }
 
     pub fn round(&self) -> Round {
@@ -225,11 +264,11 @@ impl Certificate {
 
 impl Hash for Certificate {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
+        let mut hasher = Hasher::new();
         hasher.update(&self.header.id);
         hasher.update(self.round().to_le_bytes());
-        hasher.update(&self.origin());
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.update(self.origin());
+        hasher.finalize()
     }
 }
 
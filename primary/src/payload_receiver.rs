@@ -7,14 +7,14 @@ use tokio::sync::mpsc::Receiver;
 /// Receives batches' digests of other authorities. These are only needed to verify incoming
 /// headers (ie. make sure we have their payload).
 pub struct PayloadReceiver {
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the batches' digests (and their worker id).
+    store: Store<(Digest, WorkerId), ()>,
     /// Receives batches' digests from the network.
     rx_workers: Receiver<(Digest, WorkerId)>,
 }
 
 impl PayloadReceiver {
-    pub fn spawn(store: Store, rx_workers: Receiver<(Digest, WorkerId)>) {
+    pub fn spawn(store: Store<(Digest, WorkerId), ()>, rx_workers: Receiver<(Digest, WorkerId)>) {
         tokio::spawn(async move {
             Self { store, rx_workers }.run().await;
         });
@@ -22,8 +22,7 @@ impl PayloadReceiver {
 
     async fn run(&mut self) {
         while let Some((digest, worker_id)) = self.rx_workers.recv().await {
-            let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-            self.store.write(key.to_vec(), Vec::default()).await;
+            self.store.write(&(digest, worker_id), &()).await;
         }
     }
 }
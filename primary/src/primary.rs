@@ -9,23 +9,37 @@ use crate::messages::{Certificate, Header, Vote};
 use crate::payload_receiver::PayloadReceiver;
 use crate::proposer::Proposer;
 use crate::synchronizer::Synchronizer;
+use crate::wire::PrimaryTranscoder;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{Committee, KeyPair, MessageLimits, Parameters, WorkerId};
 use crypto::{Digest, PublicKey, SignatureService};
 use futures::sink::SinkExt as _;
 use log::info;
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use network::{
+    deserialize_bounded, MessageHandler, PeerTraffic, PeerViolations, Receiver as NetworkReceiver,
+    ReceiverOptions, SharedTranscoder, Writer,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use store::Store;
+use store::{Database, Family};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/primary_tests.rs"]
+pub mod primary_tests;
 
 /// The default channel capacity for each channel of the primary.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// How often to log the heaviest senders among the other primaries (in ms).
+const TRAFFIC_REPORT_PERIOD: u64 = 60_000;
+
 /// The round number.
 pub type Round = u64;
 
@@ -44,6 +58,11 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary indicates that consensus committed the certificate of this header.
+    Committed(Header),
+    /// The primary indicates that the committee changed, along with the IP addresses of the new
+    /// committee.
+    Reconfigure(HashSet<IpAddr>),
 }
 
 /// The messages sent by the workers to their primary.
@@ -62,7 +81,7 @@ impl Primary {
         keypair: KeyPair,
         committee: Committee,
         parameters: Parameters,
-        store: Store,
+        store: Database,
         tx_consensus: Sender<Certificate>,
         rx_consensus: Receiver<Certificate>,
     ) {
@@ -84,6 +103,16 @@ impl Primary {
         let name = keypair.name;
         let secret = keypair.secret;
 
+        // Offer protobuf to the other primaries (if enabled). We always accept it from them.
+        let transcoder = parameters
+            .proto_encoding
+            .then(|| Arc::new(PrimaryTranscoder) as SharedTranscoder);
+
+        // The typed handles to the column families of the store.
+        let header_store = store.store(Family::Headers);
+        let certificate_store = store.store(Family::Certificates);
+        let payload_store = store.store(Family::Payloads);
+
         // Atomic variable use to synchronizer all tasks with the latest consensus round. This is only
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
         let consensus_round = Arc::new(AtomicU64::new(0));
@@ -94,13 +123,26 @@ impl Primary {
             .expect("Our public key or worker id is not in the committee")
             .primary_to_primary;
         address.set_ip("0.0.0.0".parse().unwrap());
+        let traffic = PeerTraffic::default();
+        traffic.log_periodically("Primary", Duration::from_millis(TRAFFIC_REPORT_PERIOD));
+        let violations = PeerViolations::default();
+        let options = ReceiverOptions {
+            traffic: Some(traffic),
+            transcoder: Some(Arc::new(PrimaryTranscoder)),
+            ..ReceiverOptions::default()
+        };
         NetworkReceiver::spawn(
             address,
             /* handler */
             PrimaryReceiverHandler {
                 tx_primary_messages,
                 tx_cert_requests,
+                limits: parameters.limits,
+                committee_size: committee.size(),
+                violations: violations.clone(),
+                peer: None,
             },
+            options,
         );
         info!(
             "Primary {} listening to primary messages on {}",
@@ -119,7 +161,11 @@ impl Primary {
             WorkerReceiverHandler {
                 tx_our_digests,
                 tx_others_digests,
+                limits: parameters.limits,
+                violations,
+                peer: None,
             },
+            ReceiverOptions::default(),
         );
         info!(
             "Primary {} listening to workers messages on {}",
@@ -130,7 +176,8 @@ impl Primary {
         let synchronizer = Synchronizer::new(
             name,
             &committee,
-            store.clone(),
+            certificate_store.clone(),
+            payload_store.clone(),
             /* tx_header_waiter */ tx_sync_headers,
             /* tx_certificate_waiter */ tx_sync_certificates,
         );
@@ -142,7 +189,8 @@ impl Primary {
         Core::spawn(
             name,
             committee.clone(),
-            store.clone(),
+            header_store,
+            certificate_store.clone(),
             synchronizer,
             signature_service.clone(),
             consensus_round.clone(),
@@ -153,13 +201,17 @@ impl Primary {
             /* rx_proposer */ rx_headers,
             tx_consensus,
             /* tx_proposer */ tx_parents,
+            transcoder.clone(),
         );
 
         // Keeps track of the latest consensus round and allows other tasks to clean up their their internal state
         GarbageCollector::spawn(&name, &committee, consensus_round.clone(), rx_consensus);
 
         // Receives batch digests from other workers. They are only used to validate headers.
-        PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
+        PayloadReceiver::spawn(
+            payload_store.clone(),
+            /* rx_workers */ rx_others_digests,
+        );
 
         // Whenever the `Synchronizer` does not manage to validate a header due to missing parent certificates of
         // batch digests, it commands the `HeaderWaiter` to synchronizer with other nodes, wait for their reply, and
@@ -167,7 +219,8 @@ impl Primary {
         HeaderWaiter::spawn(
             name,
             committee.clone(),
-            store.clone(),
+            certificate_store.clone(),
+            payload_store,
             consensus_round,
             parameters.gc_depth,
             parameters.sync_retry_delay,
@@ -179,7 +232,7 @@ impl Primary {
         // The `CertificateWaiter` waits to receive all the ancestors of a certificate before looping it back to the
         // `Core` for further processing.
         CertificateWaiter::spawn(
-            store.clone(),
+            certificate_store.clone(),
             /* rx_synchronizer */ rx_sync_certificates,
             /* tx_core */ tx_certificates_loopback,
         );
@@ -198,7 +251,12 @@ impl Primary {
         );
 
         // The `Helper` is dedicated to reply to certificates requests from other primaries.
-        Helper::spawn(committee.clone(), store, rx_cert_requests);
+        Helper::spawn(
+            committee.clone(),
+            certificate_store,
+            rx_cert_requests,
+            transcoder,
+        );
 
         // NOTE: This log entry is used to compute performance.
         info!(
@@ -213,21 +271,74 @@ impl Primary {
     }
 }
 
+/// Records a violation of the peer of a connection (if known), and returns the error closing it.
+fn violation(
+    violations: &PeerViolations,
+    peer: Option<SocketAddr>,
+    error: DagError,
+) -> Box<dyn Error> {
+    if let Some(peer) = peer {
+        violations.record(peer.ip(), &error.to_string());
+    }
+    error.into()
+}
+
 /// Defines how the network receiver handles incoming primary messages.
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
     tx_cert_requests: Sender<(Vec<Digest>, PublicKey)>,
+    /// Bounds on the messages we accept.
+    limits: MessageLimits,
+    /// The number of authorities, bounding the parents and votes of valid messages.
+    committee_size: usize,
+    /// Counts the messages violating these bounds (per peer).
+    violations: PeerViolations,
+    /// The peer of the connection.
+    peer: Option<SocketAddr>,
+}
+
+impl PrimaryReceiverHandler {
+    /// Checks the structure of a message, right after deserializing it.
+    fn check(&self, message: &PrimaryMessage) -> Result<(), DagError> {
+        let (what, len, max) = match message {
+            PrimaryMessage::Header(header) => {
+                ("parents", header.parents.len(), self.committee_size)
+            }
+            PrimaryMessage::Certificate(certificate)
+                if certificate.votes.len() > self.committee_size =>
+            {
+                ("votes", certificate.votes.len(), self.committee_size)
+            }
+            PrimaryMessage::Certificate(certificate) => (
+                "parents",
+                certificate.header.parents.len(),
+                self.committee_size,
+            ),
+            PrimaryMessage::CertificatesRequest(digests, _) => {
+                ("digests", digests.len(), self.limits.max_sync_digests)
+            }
+            PrimaryMessage::Vote(_) => return Ok(()),
+        };
+        ensure!(len <= max, DagError::ExceedsLimit(what, len, max));
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl MessageHandler for PrimaryReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
+        // Deserialize and check the message. Peers sending invalid messages are closed.
+        let message = deserialize_bounded(&serialized, self.limits.max_primary_message_size)
+            .map_err(DagError::SerializationError)
+            .and_then(|message| self.check(&message).map(|()| message))
+            .map_err(|e| violation(&self.violations, self.peer, e))?;
+
         // Reply with an ACK.
         let _ = writer.send(Bytes::from("Ack")).await;
 
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
+        // Parse the message.
+        match message {
             PrimaryMessage::CertificatesRequest(missing, requestor) => self
                 .tx_cert_requests
                 .send((missing, requestor))
@@ -241,6 +352,13 @@ impl MessageHandler for PrimaryReceiverHandler {
         }
         Ok(())
     }
+
+    fn for_peer(&self, peer: SocketAddr) -> Self {
+        Self {
+            peer: Some(peer),
+            ..self.clone()
+        }
+    }
 }
 
 /// Defines how the network receiver handles incoming workers messages.
@@ -248,6 +366,12 @@ impl MessageHandler for PrimaryReceiverHandler {
 struct WorkerReceiverHandler {
     tx_our_digests: Sender<(Digest, WorkerId)>,
     tx_others_digests: Sender<(Digest, WorkerId)>,
+    /// Bounds on the messages we accept.
+    limits: MessageLimits,
+    /// Counts the messages violating them (per peer).
+    violations: PeerViolations,
+    /// The peer of the connection.
+    peer: Option<SocketAddr>,
 }
 
 #[async_trait]
@@ -258,7 +382,9 @@ impl MessageHandler for WorkerReceiverHandler {
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
         // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
+        let message = deserialize_bounded(&serialized, self.limits.max_primary_message_size)
+            .map_err(|e| violation(&self.violations, self.peer, DagError::SerializationError(e)))?;
+        match message {
             WorkerPrimaryMessage::OurBatch(digest, worker_id) => self
                 .tx_our_digests
                 .send((digest, worker_id))
@@ -272,4 +398,11 @@ impl MessageHandler for WorkerReceiverHandler {
         }
         Ok(())
     }
+
+    fn for_peer(&self, peer: SocketAddr) -> Self {
+        Self {
+            peer: Some(peer),
+            ..self.clone()
+        }
+    }
 }
@@ -91,7 +91,7 @@ impl Proposer {
             &mut self.signature_service,
         )
         .await;
-        debug!("Created {:?}", header);
+        tracing::debug!(digest = ?header.id, round = header.round, "Created {:?}", header);
 
         #[cfg(feature = "benchmark")]
         for digest in header.payload.keys() {
@@ -2,7 +2,7 @@
 use crate::error::DagResult;
 use crate::header_waiter::WaiterMessage;
 use crate::messages::{Certificate, Header};
-use config::Committee;
+use config::{Committee, WorkerId};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use std::collections::HashMap;
@@ -14,8 +14,10 @@ use tokio::sync::mpsc::Sender;
 pub struct Synchronizer {
     /// The public key of this primary.
     name: PublicKey,
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the certificates.
+    certificate_store: Store<Digest, Certificate>,
+    /// The persistent storage of the batches' digests received by our workers.
+    payload_store: Store<(Digest, WorkerId), ()>,
     /// Send commands to the `HeaderWaiter`.
     tx_header_waiter: Sender<WaiterMessage>,
     /// Send commands to the `CertificateWaiter`.
@@ -28,13 +30,15 @@ impl Synchronizer {
     pub fn new(
         name: PublicKey,
         committee: &Committee,
-        store: Store,
+        certificate_store: Store<Digest, Certificate>,
+        payload_store: Store<(Digest, WorkerId), ()>,
         tx_header_waiter: Sender<WaiterMessage>,
         tx_certificate_waiter: Sender<Certificate>,
     ) -> Self {
         Self {
             name,
-            store,
+            certificate_store,
+            payload_store,
             tx_header_waiter,
             tx_certificate_waiter,
             genesis: Certificate::genesis(committee)
@@ -66,8 +70,8 @@ impl Synchronizer {
             //      4. The last good node will never be able to sync as it will keep sending its sync requests
             //         to workers #1 (rather than workers #0). Also, clients will never be able to retrieve batch
             //         X as they will be querying worker #1.
-            let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-            if self.store.read(key).await?.is_none() {
+            let key = (digest.clone(), *worker_id);
+            if self.payload_store.read(&key).await?.is_none() {
                 missing.insert(digest.clone(), *worker_id);
             }
         }
@@ -100,8 +104,8 @@ impl Synchronizer {
                 continue;
             }
 
-            match self.store.read(digest.to_vec()).await? {
-                Some(certificate) => parents.push(bincode::deserialize(&certificate)?),
+            match self.certificate_store.read(digest).await? {
+                Some(certificate) => parents.push(certificate),
                 None => missing.push(digest.clone()),
             };
         }
@@ -125,7 +129,7 @@ impl Synchronizer {
                 continue;
             }
 
-            if self.store.read(digest.to_vec()).await?.is_none() {
+            if self.certificate_store.read(digest).await?.is_none() {
                 self.tx_certificate_waiter
                     .send(certificate.clone())
                     .await
@@ -1,13 +1,11 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::{Certificate, Header, Vote};
 use bytes::Bytes;
-use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
+use config::{Committee, KeyPair};
 use crypto::Hash as _;
-use crypto::{generate_keypair, PublicKey, SecretKey, Signature};
+use crypto::{PublicKey, SecretKey, Signature};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use rand::rngs::StdRng;
-use rand::SeedableRng as _;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
@@ -27,69 +25,22 @@ impl PartialEq for Vote {
 
 // Fixture
 pub fn keys() -> Vec<(PublicKey, SecretKey)> {
-    let mut rng = StdRng::from_seed([0; 32]);
-    (0..4).map(|_| generate_keypair(&mut rng)).collect()
+    (0..4)
+        .map(|i| {
+            let keypair = KeyPair::new_for_test(/* seed */ 0, i);
+            (keypair.name, keypair.secret)
+        })
+        .collect()
 }
 
 // Fixture
 pub fn committee() -> Committee {
-    Committee {
-        authorities: keys()
-            .iter()
-            .enumerate()
-            .map(|(i, (id, _))| {
-                let primary = PrimaryAddresses {
-                    primary_to_primary: format!("127.0.0.1:{}", 100 + i).parse().unwrap(),
-                    worker_to_primary: format!("127.0.0.1:{}", 200 + i).parse().unwrap(),
-                };
-                let workers = vec![(
-                    0,
-                    WorkerAddresses {
-                        primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
-                        transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
-                        worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
-                    },
-                )]
-                .iter()
-                .cloned()
-                .collect();
-                (
-                    *id,
-                    Authority {
-                        stake: 1,
-                        primary,
-                        workers,
-                    },
-                )
-            })
-            .collect(),
-    }
+    committee_with_base_port(100)
 }
 
 // Fixture.
 pub fn committee_with_base_port(base_port: u16) -> Committee {
-    let mut committee = committee();
-    for authority in committee.authorities.values_mut() {
-        let primary = &mut authority.primary;
-
-        let port = primary.primary_to_primary.port();
-        primary.primary_to_primary.set_port(base_port + port);
-
-        let port = primary.worker_to_primary.port();
-        primary.worker_to_primary.set_port(base_port + port);
-
-        for worker in authority.workers.values_mut() {
-            let port = worker.primary_to_worker.port();
-            worker.primary_to_worker.set_port(base_port + port);
-
-            let port = worker.transactions.port();
-            worker.transactions.set_port(base_port + port);
-
-            let port = worker.worker_to_worker.port();
-            worker.worker_to_worker.set_port(base_port + port);
-        }
-    }
-    committee
+    Committee::new_for_test(4, base_port, /* seed */ 0)
 }
 
 // Fixture
@@ -158,7 +109,7 @@ pub fn votes(header: &Header) -> Vec<Vote> {
 pub fn certificate(header: &Header) -> Certificate {
     Certificate {
         header: header.clone(),
-        votes: votes(&header)
+        votes: votes(header)
             .into_iter()
             .map(|x| (x.author, x.signature))
             .collect(),
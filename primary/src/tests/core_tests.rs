@@ -4,7 +4,7 @@ use crate::common::{
     certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
 };
 use futures::future::try_join_all;
-use std::fs;
+use store::{Database, Family};
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -26,9 +26,10 @@ async fn process_header() {
     let (tx_parents, _rx_parents) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_header";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
+    let mut header_store = store.store(Family::Headers);
+    let certificate_store = store.store(Family::Certificates);
+    let payload_store = store.store(Family::Payloads);
 
     // Make the vote we expect to receive.
     let expected = Vote::new(&header(), &name, &mut signature_service).await;
@@ -44,7 +45,8 @@ async fn process_header() {
     let synchronizer = Synchronizer::new(
         name,
         &committee,
-        store.clone(),
+        certificate_store.clone(),
+        payload_store,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -53,7 +55,8 @@ async fn process_header() {
     Core::spawn(
         name,
         committee,
-        store.clone(),
+        header_store.clone(),
+        certificate_store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
@@ -64,6 +67,7 @@ async fn process_header() {
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* transcoder */ None,
     );
 
     // Send a header to the core.
@@ -80,11 +84,7 @@ async fn process_header() {
     }
 
     // Ensure the header is correctly stored.
-    let stored = store
-        .read(header().id.to_vec())
-        .await
-        .unwrap()
-        .map(|x| bincode::deserialize(&x).unwrap());
+    let stored = header_store.read(&header().id).await.unwrap();
     assert_eq!(stored, Some(header()));
 }
 
@@ -103,15 +103,17 @@ async fn process_header_missing_parent() {
     let (tx_parents, _rx_parents) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_header_missing_parent";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
+    let mut header_store = store.store(Family::Headers);
+    let certificate_store = store.store(Family::Certificates);
+    let payload_store = store.store(Family::Payloads);
 
     // Make a synchronizer for the core.
     let synchronizer = Synchronizer::new(
         name,
         &committee(),
-        store.clone(),
+        certificate_store.clone(),
+        payload_store,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -120,7 +122,8 @@ async fn process_header_missing_parent() {
     Core::spawn(
         name,
         committee(),
-        store.clone(),
+        header_store.clone(),
+        certificate_store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
@@ -131,6 +134,7 @@ async fn process_header_missing_parent() {
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* transcoder */ None,
     );
 
     // Send a header to the core.
@@ -145,7 +149,7 @@ async fn process_header_missing_parent() {
         .unwrap();
 
     // Ensure the header is not stored.
-    assert!(store.read(id.to_vec()).await.unwrap().is_none());
+    assert!(header_store.read(&id).await.unwrap().is_none());
 }
 
 #[tokio::test]
@@ -163,15 +167,17 @@ async fn process_header_missing_payload() {
     let (tx_parents, _rx_parents) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_header_missing_payload";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
+    let mut header_store = store.store(Family::Headers);
+    let certificate_store = store.store(Family::Certificates);
+    let payload_store = store.store(Family::Payloads);
 
     // Make a synchronizer for the core.
     let synchronizer = Synchronizer::new(
         name,
         &committee(),
-        store.clone(),
+        certificate_store.clone(),
+        payload_store,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -180,7 +186,8 @@ async fn process_header_missing_payload() {
     Core::spawn(
         name,
         committee(),
-        store.clone(),
+        header_store.clone(),
+        certificate_store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
@@ -191,6 +198,7 @@ async fn process_header_missing_payload() {
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* transcoder */ None,
     );
 
     // Send a header to the core.
@@ -205,7 +213,7 @@ async fn process_header_missing_payload() {
         .unwrap();
 
     // Ensure the header is not stored.
-    assert!(store.read(id.to_vec()).await.unwrap().is_none());
+    assert!(header_store.read(&id).await.unwrap().is_none());
 }
 
 #[tokio::test]
@@ -225,15 +233,17 @@ async fn process_votes() {
     let (tx_parents, _rx_parents) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_vote";
-    let _ = fs::remove_dir_all(path);
-    let store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
+    let header_store = store.store(Family::Headers);
+    let certificate_store = store.store(Family::Certificates);
+    let payload_store = store.store(Family::Payloads);
 
     // Make a synchronizer for the core.
     let synchronizer = Synchronizer::new(
         name,
         &committee,
-        store.clone(),
+        certificate_store.clone(),
+        payload_store,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -242,7 +252,8 @@ async fn process_votes() {
     Core::spawn(
         name,
         committee.clone(),
-        store.clone(),
+        header_store.clone(),
+        certificate_store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
@@ -253,6 +264,7 @@ async fn process_votes() {
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* transcoder */ None,
     );
 
     // Make the certificate we expect to receive.
@@ -297,15 +309,17 @@ async fn process_certificates() {
     let (tx_parents, mut rx_parents) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_certificates";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
+    let header_store = store.store(Family::Headers);
+    let mut certificate_store = store.store(Family::Certificates);
+    let payload_store = store.store(Family::Payloads);
 
     // Make a synchronizer for the core.
     let synchronizer = Synchronizer::new(
         name,
         &committee(),
-        store.clone(),
+        certificate_store.clone(),
+        payload_store,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -314,7 +328,8 @@ async fn process_certificates() {
     Core::spawn(
         name,
         committee(),
-        store.clone(),
+        header_store.clone(),
+        certificate_store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
@@ -325,14 +340,11 @@ async fn process_certificates() {
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* transcoder */ None,
     );
 
     // Send enough certificates to the core.
-    let certificates: Vec<_> = headers()
-        .iter()
-        .take(3)
-        .map(|header| certificate(header))
-        .collect();
+    let certificates: Vec<_> = headers().iter().take(3).map(certificate).collect();
 
     for x in certificates.clone() {
         tx_primary_messages
@@ -354,8 +366,7 @@ async fn process_certificates() {
 
     // Ensure the certificates are stored.
     for x in &certificates {
-        let stored = store.read(x.digest().to_vec()).await.unwrap();
-        let serialized = bincode::serialize(x).unwrap();
-        assert_eq!(stored, Some(serialized));
+        let stored = certificate_store.read(&x.digest()).await.unwrap();
+        assert_eq!(stored.as_ref(), Some(x));
     }
 }
@@ -0,0 +1,57 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys, listener};
+use crypto::Hash as _;
+use store::{Database, Family};
+
+#[tokio::test]
+async fn retry_parent_requests() {
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let name = keys.remove(0);
+    let committee = committee_with_base_port(13_200);
+    let (tx_synchronizer, rx_synchronizer) = channel(1);
+    let (tx_core, mut rx_core) = channel(1);
+
+    // Create a new test store.
+    let store = Database::new_in_memory();
+    let mut certificate_store = store.store(Family::Certificates);
+
+    // Spawn a `HeaderWaiter` instance.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        certificate_store.clone(),
+        store.store(Family::Payloads),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3, // All the other nodes.
+        rx_synchronizer,
+        tx_core,
+    );
+
+    // Spawn listeners for the author of the header (asked first) and for another node (asked
+    // once the waiter timed out).
+    let header = header();
+    let other = keys.iter().find(|x| **x != header.author).cloned().unwrap();
+    let handles: Vec<_> = [header.author, other]
+        .iter()
+        .map(|x| listener(committee.primary(x).unwrap().primary_to_primary))
+        .collect();
+
+    // Ask the waiter to sync a missing parent.
+    let parent = certificate(&header);
+    let missing = vec![parent.digest()];
+    let message = WaiterMessage::SyncParents(missing.clone(), header.clone());
+    tx_synchronizer.send(message).await.unwrap();
+
+    // Both nodes receive the same request.
+    let expected = bincode::serialize(&PrimaryMessage::CertificatesRequest(missing, name)).unwrap();
+    for handle in handles {
+        assert_eq!(handle.await.unwrap(), Bytes::from(expected.clone()));
+    }
+
+    // The header is delivered once the parent arrives.
+    certificate_store.write(&parent.digest(), &parent).await;
+    assert_eq!(rx_core.recv().await.unwrap(), header);
+}
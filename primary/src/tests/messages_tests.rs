@@ -0,0 +1,142 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::common::{certificate, committee, header};
+use crate::error::DagError;
+
+#[test]
+fn verify_certificate() {
+    assert!(certificate(&header()).verify(&committee()).is_ok());
+}
+
+#[test]
+fn blame_invalid_vote() {
+    // Corrupt the signature of one vote.
+    let mut certificate = certificate(&header());
+    let (culprit, _) = certificate.votes[1].clone();
+    certificate.votes[1].1 = certificate.votes[0].1.clone();
+
+    // Ensure the certificate is rejected and the culprit identified.
+    match certificate.verify(&committee()) {
+        Err(DagError::InvalidSignatures(culprits)) => assert_eq!(culprits, vec![culprit]),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn verify_header_signature_strictly() {
+    // Replace the signature of the header by a valid signature of another message.
+    let mut certificate = certificate(&header());
+    certificate.header.signature = certificate.votes[0].1.clone();
+
+    // Ensure the header signature is checked on its own, not as part of the batch of votes.
+    match certificate.verify(&committee()) {
+        Err(DagError::InvalidSignature(_)) => (),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[cfg(feature = "secp256k1")]
+mod secp256k1 {
+    use super::super::*;
+    use super::*;
+    use crate::common::keys;
+    use crypto::{generate_keypair_with_scheme, Scheme, SecretKey};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng as _;
+
+    // Fixture
+    fn secp256k1_keys() -> Vec<(PublicKey, SecretKey)> {
+        let mut rng = StdRng::from_seed([0; 32]);
+        (0..4)
+            .map(|_| generate_keypair_with_scheme(Scheme::Secp256k1, &mut rng))
+            .collect()
+    }
+
+    // Fixture
+    fn secp256k1_committee() -> Committee {
+        let committee = committee();
+        Committee {
+            authorities: secp256k1_keys()
+                .into_iter()
+                .zip(committee.authorities.values().cloned())
+                .map(|((name, _), authority)| (name, authority))
+                .collect(),
+            scheme: Scheme::Secp256k1,
+            ..committee
+        }
+    }
+
+    // Fixture
+    fn secp256k1_certificate(committee: &Committee) -> Certificate {
+        let (author, secret) = secp256k1_keys().pop().unwrap();
+        let header = Header {
+            author,
+            round: 1,
+            parents: Certificate::genesis(committee)
+                .iter()
+                .map(|x| x.digest())
+                .collect(),
+            ..Header::default()
+        };
+        let header = Header {
+            id: header.digest(),
+            signature: Signature::new(&header.digest(), &secret),
+            ..header
+        };
+        let votes = secp256k1_keys()
+            .into_iter()
+            .map(|(author, secret)| {
+                let vote = Vote {
+                    id: header.id.clone(),
+                    round: header.round,
+                    origin: header.author,
+                    author,
+                    signature: Signature::default(),
+                };
+                (author, Signature::new(&vote.digest(), &secret))
+            })
+            .collect();
+        Certificate { header, votes }
+    }
+
+    #[test]
+    fn verify_secp256k1_certificate() {
+        let committee = secp256k1_committee();
+        let certificate = secp256k1_certificate(&committee);
+        assert!(certificate.header.verify(&committee).is_ok());
+        assert!(certificate.verify(&committee).is_ok());
+    }
+
+    #[test]
+    fn reject_cross_scheme_header() {
+        // An ed25519 committee rejects secp256k1 headers.
+        let certificate = secp256k1_certificate(&secp256k1_committee());
+        let mut committee = committee();
+        committee.authorities = secp256k1_committee().authorities;
+        match certificate.header.verify(&committee) {
+            Err(DagError::InvalidScheme(Scheme::Secp256k1)) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+
+    #[test]
+    fn reject_cross_scheme_vote() {
+        // A secp256k1 committee rejects ed25519 votes.
+        let committee = secp256k1_committee();
+        let (author, secret) = keys().pop().unwrap();
+        let vote = Vote {
+            id: header().id,
+            round: 1,
+            origin: author,
+            author: secp256k1_keys().pop().unwrap().0,
+            signature: Signature::default(),
+        };
+        let vote = Vote {
+            signature: Signature::new(&vote.digest(), &secret),
+            ..vote
+        };
+        match vote.verify(&committee) {
+            Err(DagError::InvalidScheme(Scheme::Ed25519)) => (),
+            _ => panic!("Unexpected result"),
+        }
+    }
+}
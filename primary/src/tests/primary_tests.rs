@@ -0,0 +1,71 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header};
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn handler() -> PrimaryReceiverHandler {
+    PrimaryReceiverHandler {
+        tx_primary_messages: channel(1).0,
+        tx_cert_requests: channel(1).0,
+        limits: MessageLimits {
+            max_sync_digests: 2,
+            ..MessageLimits::default()
+        },
+        committee_size: committee().size(),
+        violations: PeerViolations::default(),
+        peer: None,
+    }
+}
+
+#[test]
+fn accept_messages_within_limits() {
+    let handler = handler();
+    let header = header();
+    assert!(handler
+        .check(&PrimaryMessage::Header(header.clone()))
+        .is_ok());
+    assert!(handler
+        .check(&PrimaryMessage::Certificate(certificate(&header)))
+        .is_ok());
+    let request = PrimaryMessage::CertificatesRequest(vec![Digest::default(); 2], header.author);
+    assert!(handler.check(&request).is_ok());
+}
+
+#[test]
+fn reject_messages_exceeding_limits() {
+    let handler = handler();
+    let size = committee().size();
+
+    // More parents than authorities.
+    let mut header = header();
+    header.parents = (0..=size).map(|i| Digest([i as u8; 32])).collect();
+    let result = handler.check(&PrimaryMessage::Header(header.clone()));
+    assert!(matches!(
+        result,
+        Err(DagError::ExceedsLimit("parents", _, _))
+    ));
+    let result = handler.check(&PrimaryMessage::Certificate(Certificate {
+        header: header.clone(),
+        votes: Vec::new(),
+    }));
+    assert!(matches!(
+        result,
+        Err(DagError::ExceedsLimit("parents", _, _))
+    ));
+
+    // More votes than authorities.
+    let mut certificate = certificate(&crate::common::header());
+    let vote = certificate.votes[0].clone();
+    certificate.votes.resize(size + 1, vote);
+    let result = handler.check(&PrimaryMessage::Certificate(certificate));
+    assert!(matches!(result, Err(DagError::ExceedsLimit("votes", _, _))));
+
+    // Too many digests to sync.
+    let request = PrimaryMessage::CertificatesRequest(vec![Digest::default(); 3], header.author);
+    let result = handler.check(&request);
+    assert!(matches!(
+        result,
+        Err(DagError::ExceedsLimit("digests", 3, 2))
+    ));
+}
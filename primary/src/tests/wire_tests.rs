@@ -0,0 +1,105 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header, votes};
+use prost::encoding::{encode_key, encode_varint, WireType};
+
+// Fixture
+fn messages() -> Vec<PrimaryMessage> {
+    let header = header();
+    vec![
+        PrimaryMessage::Header(header.clone()),
+        PrimaryMessage::Vote(votes(&header).pop().unwrap()),
+        PrimaryMessage::Certificate(certificate(&header)),
+        PrimaryMessage::CertificatesRequest(vec![header.id.clone()], header.author),
+    ]
+}
+
+// Fixture
+fn serialized(message: &PrimaryMessage) -> Vec<u8> {
+    bincode::serialize(message).unwrap()
+}
+
+// Fixture
+fn unknown_field(buffer: &mut Vec<u8>) {
+    encode_key(100, WireType::LengthDelimited, buffer);
+    encode_varint(5, buffer);
+    buffer.extend_from_slice(b"later");
+}
+
+#[test]
+fn round_trip() {
+    for message in messages() {
+        let frame = PrimaryTranscoder.encode(&serialized(&message)).unwrap();
+        let decoded = PrimaryTranscoder.decode(frame).unwrap();
+        assert_eq!(decoded, serialized(&message));
+    }
+}
+
+#[test]
+fn decode_version_1() {
+    // A certificates request, as encoded by the first version of the wire format. Decoders must
+    // keep accepting it.
+    let mut frame = vec![0x08, 0x01, 0x2a, 0x44, 0x0a, 0x20];
+    frame.extend_from_slice(&[1; 32]);
+    frame.extend_from_slice(&[0x12, 0x20]);
+    frame.extend_from_slice(&[2; 32]);
+
+    let expected = PrimaryMessage::CertificatesRequest(vec![Digest([1; 32])], PublicKey([2; 32]));
+    assert_eq!(
+        PrimaryTranscoder.decode(frame.clone().into()).unwrap(),
+        serialized(&expected)
+    );
+    assert_eq!(
+        PrimaryTranscoder.encode(&serialized(&expected)).unwrap(),
+        frame
+    );
+}
+
+#[test]
+fn ignore_unknown_fields() {
+    // A newer encoder adds fields to a vote, and to the envelope.
+    let header = header();
+    let vote = votes(&header).pop().unwrap();
+    let mut encoded_vote = proto::Vote::from(&vote).encode_to_vec();
+    unknown_field(&mut encoded_vote);
+
+    let mut frame = Vec::new();
+    prost::encoding::uint32::encode(1, &WIRE_VERSION, &mut frame);
+    prost::encoding::bytes::encode(3, &encoded_vote, &mut frame);
+    unknown_field(&mut frame);
+
+    let decoded = PrimaryTranscoder.decode(frame.into()).unwrap();
+    assert_eq!(decoded, serialized(&PrimaryMessage::Vote(vote)));
+}
+
+#[test]
+fn reject_unsupported_version() {
+    let mut message = proto::PrimaryMessage::from(&messages().pop().unwrap());
+    message.version = WIRE_VERSION + 1;
+    match PrimaryTranscoder.decode(message.encode_to_vec().into()) {
+        Err(WireError::UnsupportedVersion(version, supported)) => {
+            assert_eq!(version, WIRE_VERSION + 1);
+            assert_eq!(supported, WIRE_VERSION);
+        }
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn reject_malformed_messages() {
+    // A digest of the wrong length.
+    let mut message = proto::PrimaryMessage::from(&PrimaryMessage::Header(header()));
+    if let Some(proto::primary_message::Message::Header(header)) = &mut message.message {
+        header.id.pop();
+    }
+    assert!(matches!(
+        PrimaryTranscoder.decode(message.encode_to_vec().into()),
+        Err(WireError::InvalidLength("header.id"))
+    ));
+
+    // Not protobuf at all.
+    assert!(matches!(
+        PrimaryTranscoder.decode(Bytes::from_static(&[0xff; 10])),
+        Err(WireError::MalformedMessage(_))
+    ));
+}
@@ -0,0 +1,241 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::{Certificate, Header, Vote};
+use crate::primary::PrimaryMessage;
+use bytes::Bytes;
+use crypto::{Digest, PublicKey, Scheme, Signature};
+use network::{Transcoder, WireError, WIRE_VERSION};
+use prost::Message as _;
+use std::convert::{TryFrom, TryInto as _};
+
+#[cfg(test)]
+#[path = "tests/wire_tests.rs"]
+pub mod wire_tests;
+
+/// The messages generated from `proto/wire.proto`.
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/narwhal.wire.rs"));
+}
+
+/// Converts the messages exchanged between primaries to and from protobuf.
+pub struct PrimaryTranscoder;
+
+impl Transcoder for PrimaryTranscoder {
+    fn encode(&self, message: &[u8]) -> Result<Bytes, WireError> {
+        let message: PrimaryMessage = bincode::deserialize(message)
+            .map_err(|e| WireError::SerializationError(e.to_string()))?;
+        Ok(Bytes::from(
+            proto::PrimaryMessage::from(&message).encode_to_vec(),
+        ))
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<Bytes, WireError> {
+        let message = proto::PrimaryMessage::decode(frame)
+            .map_err(|e| WireError::MalformedMessage(e.to_string()))?;
+        let message = PrimaryMessage::try_from(message)?;
+        bincode::serialize(&message)
+            .map(Bytes::from)
+            .map_err(|e| WireError::SerializationError(e.to_string()))
+    }
+}
+
+fn digest(bytes: &[u8], field: &'static str) -> Result<Digest, WireError> {
+    bytes
+        .try_into()
+        .map(Digest)
+        .map_err(|_| WireError::InvalidLength(field))
+}
+
+fn public_key(bytes: &[u8], field: &'static str) -> Result<PublicKey, WireError> {
+    bytes
+        .try_into()
+        .map(PublicKey)
+        .map_err(|_| WireError::InvalidLength(field))
+}
+
+impl From<&Signature> for proto::Signature {
+    fn from(signature: &Signature) -> Self {
+        let scheme = match signature.scheme() {
+            Scheme::Ed25519 => proto::signature::Scheme::Ed25519,
+            Scheme::Secp256k1 => proto::signature::Scheme::Secp256k1,
+        };
+        Self {
+            scheme: scheme as i32,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+}
+
+fn signature(
+    signature: Option<proto::Signature>,
+    field: &'static str,
+) -> Result<Signature, WireError> {
+    let signature = signature.ok_or(WireError::MissingField(field))?;
+    let scheme = match proto::signature::Scheme::try_from(signature.scheme) {
+        Ok(proto::signature::Scheme::Ed25519) => Scheme::Ed25519,
+        Ok(proto::signature::Scheme::Secp256k1) => Scheme::Secp256k1,
+        Err(_) => {
+            let message = format!("Unknown signature scheme {}", signature.scheme);
+            return Err(WireError::MalformedMessage(message));
+        }
+    };
+    let bytes = signature
+        .signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| WireError::InvalidLength(field))?;
+    Ok(Signature::from_bytes(scheme, bytes))
+}
+
+impl From<&Header> for proto::Header {
+    fn from(header: &Header) -> Self {
+        Self {
+            author: header.author.0.to_vec(),
+            round: header.round,
+            payload: header
+                .payload
+                .iter()
+                .map(|(digest, worker_id)| proto::PayloadEntry {
+                    digest: digest.to_vec(),
+                    worker_id: *worker_id,
+                })
+                .collect(),
+            parents: header.parents.iter().map(|x| x.to_vec()).collect(),
+            id: header.id.to_vec(),
+            signature: Some((&header.signature).into()),
+        }
+    }
+}
+
+impl TryFrom<proto::Header> for Header {
+    type Error = WireError;
+
+    fn try_from(header: proto::Header) -> Result<Self, Self::Error> {
+        Ok(Self {
+            author: public_key(&header.author, "header.author")?,
+            round: header.round,
+            payload: header
+                .payload
+                .iter()
+                .map(|x| Ok((digest(&x.digest, "header.payload")?, x.worker_id)))
+                .collect::<Result<_, WireError>>()?,
+            parents: header
+                .parents
+                .iter()
+                .map(|x| digest(x, "header.parents"))
+                .collect::<Result<_, _>>()?,
+            id: digest(&header.id, "header.id")?,
+            signature: signature(header.signature, "header.signature")?,
+        })
+    }
+}
+
+impl From<&Vote> for proto::Vote {
+    fn from(vote: &Vote) -> Self {
+        Self {
+            id: vote.id.to_vec(),
+            round: vote.round,
+            origin: vote.origin.0.to_vec(),
+            author: vote.author.0.to_vec(),
+            signature: Some((&vote.signature).into()),
+        }
+    }
+}
+
+impl TryFrom<proto::Vote> for Vote {
+    type Error = WireError;
+
+    fn try_from(vote: proto::Vote) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: digest(&vote.id, "vote.id")?,
+            round: vote.round,
+            origin: public_key(&vote.origin, "vote.origin")?,
+            author: public_key(&vote.author, "vote.author")?,
+            signature: signature(vote.signature, "vote.signature")?,
+        })
+    }
+}
+
+impl From<&Certificate> for proto::Certificate {
+    fn from(certificate: &Certificate) -> Self {
+        Self {
+            header: Some((&certificate.header).into()),
+            votes: certificate
+                .votes
+                .iter()
+                .map(|(author, signature)| proto::Approval {
+                    author: author.0.to_vec(),
+                    signature: Some(signature.into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::Certificate> for Certificate {
+    type Error = WireError;
+
+    fn try_from(certificate: proto::Certificate) -> Result<Self, Self::Error> {
+        let header = certificate
+            .header
+            .ok_or(WireError::MissingField("certificate.header"))?;
+        Ok(Self {
+            header: header.try_into()?,
+            votes: certificate
+                .votes
+                .into_iter()
+                .map(|x| {
+                    Ok((
+                        public_key(&x.author, "certificate.votes.author")?,
+                        signature(x.signature, "certificate.votes.signature")?,
+                    ))
+                })
+                .collect::<Result<_, WireError>>()?,
+        })
+    }
+}
+
+impl From<&PrimaryMessage> for proto::PrimaryMessage {
+    fn from(message: &PrimaryMessage) -> Self {
+        use proto::primary_message::Message;
+        let message = match message {
+            PrimaryMessage::Header(header) => Message::Header(header.into()),
+            PrimaryMessage::Vote(vote) => Message::Vote(vote.into()),
+            PrimaryMessage::Certificate(certificate) => Message::Certificate(certificate.into()),
+            PrimaryMessage::CertificatesRequest(digests, requestor) => {
+                Message::CertificatesRequest(proto::CertificatesRequest {
+                    digests: digests.iter().map(|x| x.to_vec()).collect(),
+                    requestor: requestor.0.to_vec(),
+                })
+            }
+        };
+        Self {
+            version: WIRE_VERSION,
+            message: Some(message),
+        }
+    }
+}
+
+impl TryFrom<proto::PrimaryMessage> for PrimaryMessage {
+    type Error = WireError;
+
+    fn try_from(message: proto::PrimaryMessage) -> Result<Self, Self::Error> {
+        use proto::primary_message::Message;
+        WireError::check_version(message.version)?;
+        match message.message {
+            Some(Message::Header(header)) => Ok(Self::Header(header.try_into()?)),
+            Some(Message::Vote(vote)) => Ok(Self::Vote(vote.try_into()?)),
+            Some(Message::Certificate(certificate)) => {
+                Ok(Self::Certificate(certificate.try_into()?))
+            }
+            Some(Message::CertificatesRequest(request)) => Ok(Self::CertificatesRequest(
+                request
+                    .digests
+                    .iter()
+                    .map(|x| digest(x, "certificates_request.digests"))
+                    .collect::<Result<_, _>>()?,
+                public_key(&request.requestor, "certificates_request.requestor")?,
+            )),
+            None => Err(WireError::MissingField("message")),
+        }
+    }
+}
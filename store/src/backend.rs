@@ -0,0 +1,180 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{Family, Key, Operation, StoreResult, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// The storage engine behind a `Database`. It sees raw (encoded) keys and values, and is only
+/// ever accessed by the store's actor.
+pub trait StoreBackend: Send {
+    fn get(&self, family: Family, key: &[u8]) -> StoreResult<Option<Value>>;
+
+    fn put(&mut self, family: Family, key: &[u8], value: &[u8]) -> StoreResult<()>;
+
+    fn delete(&mut self, family: Family, key: &[u8]) -> StoreResult<()>;
+
+    /// Removes the entries with keys in `[start, end)`.
+    fn delete_range(&mut self, family: Family, start: &[u8], end: &[u8]) -> StoreResult<()>;
+
+    /// Iterates (in key order) over the entries of the family whose key is at least `start`.
+    fn iter_from<'a>(
+        &'a self,
+        family: Family,
+        start: &[u8],
+    ) -> Box<dyn Iterator<Item = (Key, Value)> + 'a>;
+
+    /// Applies all operations atomically.
+    fn write(&mut self, operations: &[Operation]) -> StoreResult<()>;
+
+    /// Returns the (estimated) size of the family, in bytes.
+    fn estimated_size(&self, family: Family) -> u64;
+
+    /// Persists the writes buffered in memory, so that reopening the database does not need to
+    /// replay them. Backends that do not buffer writes have nothing to do.
+    fn flush(&mut self) -> StoreResult<()> {
+        Ok(())
+    }
+}
+
+/// Persists the database on disk, one RocksDB column family per `Family`.
+pub struct RocksDbBackend {
+    db: rocksdb::DB,
+}
+
+impl RocksDbBackend {
+    /// Wraps a database opened with all the column families of `Family::ALL`.
+    pub fn new(db: rocksdb::DB) -> Self {
+        Self { db }
+    }
+
+    fn cf(&self, family: Family) -> &rocksdb::ColumnFamily {
+        self.db
+            .cf_handle(family.name())
+            .expect("Column family created at startup")
+    }
+}
+
+impl StoreBackend for RocksDbBackend {
+    fn get(&self, family: Family, key: &[u8]) -> StoreResult<Option<Value>> {
+        Ok(self.db.get_cf(self.cf(family), key)?)
+    }
+
+    fn put(&mut self, family: Family, key: &[u8], value: &[u8]) -> StoreResult<()> {
+        Ok(self.db.put_cf(self.cf(family), key, value)?)
+    }
+
+    fn delete(&mut self, family: Family, key: &[u8]) -> StoreResult<()> {
+        Ok(self.db.delete_cf(self.cf(family), key)?)
+    }
+
+    fn delete_range(&mut self, family: Family, start: &[u8], end: &[u8]) -> StoreResult<()> {
+        Ok(self.db.delete_range_cf(self.cf(family), start, end)?)
+    }
+
+    fn iter_from<'a>(
+        &'a self,
+        family: Family,
+        start: &[u8],
+    ) -> Box<dyn Iterator<Item = (Key, Value)> + 'a> {
+        let mode = rocksdb::IteratorMode::From(start, rocksdb::Direction::Forward);
+        let iterator = self.db.iterator_cf(self.cf(family), mode);
+        Box::new(iterator.map(|(k, v)| (k.to_vec(), v.to_vec())))
+    }
+
+    fn write(&mut self, operations: &[Operation]) -> StoreResult<()> {
+        let mut batch = rocksdb::WriteBatch::default();
+        for operation in operations {
+            match operation {
+                Operation::Put(family, key, value) => batch.put_cf(self.cf(*family), key, value),
+                Operation::Delete(family, key) => batch.delete_cf(self.cf(*family), key),
+            }
+        }
+        Ok(self.db.write(batch)?)
+    }
+
+    fn estimated_size(&self, family: Family) -> u64 {
+        self.db
+            .property_int_value_cf(self.cf(family), "rocksdb.estimate-live-data-size")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    fn flush(&mut self) -> StoreResult<()> {
+        for family in Family::ALL.iter() {
+            self.db.flush_cf(self.cf(*family))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the database in memory: nothing survives the process. Useful for tests and simulations.
+#[derive(Default)]
+pub struct MemoryBackend {
+    families: HashMap<Family, BTreeMap<Key, Value>>,
+}
+
+impl StoreBackend for MemoryBackend {
+    fn get(&self, family: Family, key: &[u8]) -> StoreResult<Option<Value>> {
+        Ok(self.families.get(&family).and_then(|x| x.get(key)).cloned())
+    }
+
+    fn put(&mut self, family: Family, key: &[u8], value: &[u8]) -> StoreResult<()> {
+        self.families
+            .entry(family)
+            .or_default()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, family: Family, key: &[u8]) -> StoreResult<()> {
+        if let Some(entries) = self.families.get_mut(&family) {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    fn delete_range(&mut self, family: Family, start: &[u8], end: &[u8]) -> StoreResult<()> {
+        if let Some(entries) = self.families.get_mut(&family) {
+            if start < end {
+                let mut tail = entries.split_off(start);
+                let mut rest = tail.split_off(end);
+                entries.append(&mut rest);
+            }
+        }
+        Ok(())
+    }
+
+    fn iter_from<'a>(
+        &'a self,
+        family: Family,
+        start: &[u8],
+    ) -> Box<dyn Iterator<Item = (Key, Value)> + 'a> {
+        match self.families.get(&family) {
+            Some(entries) => Box::new(
+                entries
+                    .range(start.to_vec()..)
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            ),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn write(&mut self, operations: &[Operation]) -> StoreResult<()> {
+        // Nothing can fail halfway through, so applying the operations in order is atomic.
+        for operation in operations {
+            match operation {
+                Operation::Put(family, key, value) => self.put(*family, key, value)?,
+                Operation::Delete(family, key) => self.delete(*family, key)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn estimated_size(&self, family: Family) -> u64 {
+        self.families.get(&family).map_or(0, |entries| {
+            entries
+                .iter()
+                .map(|(k, v)| (k.len() + v.len()) as u64)
+                .sum()
+        })
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The source of time of the timer-driven logic (e.g., the time-to-live of entries). Production code uses the
+/// `SystemClock`; tests use a `MockClock` they advance by hand.
+pub trait Clock: Send + Sync + 'static {
+    /// The current time, in milliseconds.
+    fn now(&self) -> u128;
+}
+
+/// Reads the system time (milliseconds since the unix epoch).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to measure time")
+            .as_millis()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Moves the clock forward by the specified number of milliseconds.
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u128 {
+        self.now.load(Ordering::SeqCst) as u128
+    }
+}
@@ -1,94 +1,873 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use std::collections::{HashMap, VecDeque};
-use tokio::sync::mpsc::{channel, Sender};
+use bincode::Options as _;
+use log::info;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+mod backend;
+mod clock;
+mod metrics;
+mod retention;
+
+pub use crate::backend::{MemoryBackend, RocksDbBackend, StoreBackend};
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::metrics::StoreMetrics;
+use crate::retention::Pruner;
+pub use crate::retention::{PruningMetrics, Retention, RetentionConfig};
 
 #[cfg(test)]
 #[path = "tests/store_tests.rs"]
 pub mod store_tests;
 
-pub type StoreError = rocksdb::Error;
-type StoreResult<T> = Result<T, StoreError>;
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] rocksdb::Error),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] Box<bincode::ErrorKind>),
+
+    #[error(
+        "The database at '{0}' predates column families: remove it and sync the node from scratch"
+    )]
+    LegacyDatabase(String),
+
+    #[error("The database was written by a newer version of the node (version {0})")]
+    UnsupportedVersion(u8),
+
+    #[error("The requested entry of {0} was pruned")]
+    Pruned(Family),
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+#[derive(Debug, Error)]
+pub enum NotifyReadError {
+    #[error("The key was not written before the deadline")]
+    Timeout,
+
+    #[error("The store stopped before the key was written")]
+    Cancelled,
+
+    #[error(transparent)]
+    StoreError(#[from] StoreError),
+}
 
 type Key = Vec<u8>;
 type Value = Vec<u8>;
 
+/// The entries returned by a scan, and the key from which to resume it (if it hit its size cap).
+type ScanResult = (Vec<(Key, Value)>, Option<Key>);
+
+/// Key (in the default column family) holding the version of the key encoding.
+const VERSION_KEY: &[u8] = b"version";
+
+/// Version 1 encoded keys with little-endian integers, version 2 with big-endian integers so that
+/// the lexicographic order of keys matches the numeric order of rounds.
+const VERSION: u8 = 2;
+
+/// The encoding of keys: integers are big-endian and fixed-size, so that lexicographic order
+/// matches numeric order.
+fn key_encoding() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_big_endian()
+}
+
+fn encode_key<T: Serialize>(key: &T) -> Key {
+    key_encoding()
+        .serialize(key)
+        .expect("Failed to serialize store key")
+}
+
+fn serialize<T: Serialize>(item: &T) -> Value {
+    bincode::serialize(item).expect("Failed to serialize store item")
+}
+
+/// The column families of the database. Each family holds a single type of data, and can thus
+/// be pruned, measured, or backed up independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// The batches of transactions (worker).
+    Batches,
+    /// The (valid) headers (primary).
+    Headers,
+    /// The certificates (primary).
+    Certificates,
+    /// The votes (primary).
+    Votes,
+    /// The consensus state.
+    Consensus,
+    /// The digests of the batches our workers received, keyed by digest and worker id (primary).
+    Payloads,
+    /// The committed sub-dags awaiting execution, by index (executor).
+    SubDags,
+    /// The index of the last executed sub-dag (executor).
+    Execution,
+}
+
+impl Family {
+    pub const ALL: [Family; 8] = [
+        Family::Batches,
+        Family::Headers,
+        Family::Certificates,
+        Family::Votes,
+        Family::Consensus,
+        Family::Payloads,
+        Family::SubDags,
+        Family::Execution,
+    ];
+
+    /// The name of the column family in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Batches => "batches",
+            Self::Headers => "headers",
+            Self::Certificates => "certificates",
+            Self::Votes => "votes",
+            Self::Consensus => "consensus",
+            Self::Payloads => "payloads",
+            Self::SubDags => "sub_dags",
+            Self::Execution => "execution",
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.name())
+    }
+}
+
 pub enum StoreCommand {
-    Write(Key, Value),
-    Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
-    NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    Write(Family, Key, Value),
+    Read(Family, Key, oneshot::Sender<StoreResult<Option<Value>>>),
+    NotifyRead(Family, Key, oneshot::Sender<StoreResult<Value>>),
+    Remove(Family, Key),
+    /// Returns at most `limit` entries with keys in `[start, end)`; `None` means no upper bound.
+    Scan(Family, Key, Option<Key>, usize, oneshot::Sender<ScanResult>),
+    /// Removes the entries with keys in `[start, end)`.
+    DeleteRange(Family, Key, Key),
+    /// Applies all operations atomically.
+    WriteBatch(Vec<Operation>, oneshot::Sender<StoreResult<()>>),
+    /// Prunes the entries of the family whose round is below the specified round.
+    SetWatermark(Family, u64),
+    /// Refreshes the metrics and logs them.
+    DebugDump(oneshot::Sender<()>),
+    /// Forgets the `NotifyRead` commands for this key that are no longer waited for.
+    CancelNotifyRead(Family, Key),
+    /// Persists the writes buffered by the backend (once the previous commands are served).
+    Flush(oneshot::Sender<StoreResult<()>>),
+}
+
+/// An operation of a `WriteBatch`.
+pub enum Operation {
+    Put(Family, Key, Value),
+    Delete(Family, Key),
+}
+
+/// A set of writes and deletes, possibly across column families, applied atomically: after a
+/// crash, either all or none of them are visible.
+#[derive(Default)]
+pub struct WriteBatch {
+    channel: Option<Sender<StoreCommand>>,
+    operations: Vec<Operation>,
+}
+
+impl WriteBatch {
+    /// Remembers the database of the store, and ensures all operations target the same one.
+    fn check_database<K, V>(&mut self, store: &Store<K, V>) {
+        match &self.channel {
+            Some(channel) => assert!(
+                channel.same_channel(&store.channel),
+                "All operations of a write batch must target the same database"
+            ),
+            None => self.channel = Some(store.channel.clone()),
+        }
+    }
+
+    pub fn put<K, V>(&mut self, store: &Store<K, V>, key: &K, value: &V) -> &mut Self
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        self.check_database(store);
+        self.operations.push(Operation::Put(
+            store.family,
+            encode_key(key),
+            serialize(value),
+        ));
+        self
+    }
+
+    pub fn delete<K, V>(&mut self, store: &Store<K, V>, key: &K) -> &mut Self
+    where
+        K: Serialize,
+    {
+        self.check_database(store);
+        self.operations
+            .push(Operation::Delete(store.family, encode_key(key)));
+        self
+    }
+
+    /// Applies the batch. The returned channel fires once the batch is written.
+    pub async fn commit(self) -> oneshot::Receiver<StoreResult<()>> {
+        let (sender, receiver) = oneshot::channel();
+        match self.channel {
+            Some(channel) => {
+                let command = StoreCommand::WriteBatch(self.operations, sender);
+                if let Err(e) = channel.send(command).await {
+                    panic!("Failed to send WriteBatch command to store: {}", e);
+                }
+            }
+            None => {
+                let _ = sender.send(Ok(()));
+            }
+        }
+        receiver
+    }
 }
 
+/// An opaque position from which to resume a scan that hit its size cap.
+#[derive(Clone, Debug)]
+pub struct Continuation {
+    next: Key,
+    end: Option<Key>,
+}
+
+/// A page of (sorted) entries returned by a scan.
+#[derive(Debug)]
+pub struct Page<K, V> {
+    pub items: Vec<(K, V)>,
+    /// Set if the scan stopped because it hit its size cap. Pass it to `Store::resume` to get the
+    /// next page.
+    pub continuation: Option<Continuation>,
+}
+
+/// A handle to the database. It only serves to create the typed handles of its column families.
 #[derive(Clone)]
-pub struct Store {
+pub struct Database {
     channel: Sender<StoreCommand>,
+    pruning: Arc<Mutex<HashMap<Family, PruningMetrics>>>,
+    metrics: StoreMetrics,
 }
 
-impl Store {
-    pub fn new(path: &str) -> StoreResult<Self> {
-        let db = rocksdb::DB::open_default(path)?;
-        let mut obligations = HashMap::<_, VecDeque<oneshot::Sender<_>>>::new();
-        let (tx, mut rx) = channel(100);
-        tokio::spawn(async move {
-            while let Some(command) = rx.recv().await {
-                match command {
-                    StoreCommand::Write(key, value) => {
-                        let _ = db.put(&key, &value);
-                        if let Some(mut senders) = obligations.remove(&key) {
-                            while let Some(s) = senders.pop_front() {
-                                let _ = s.send(Ok(value.clone()));
-                            }
+impl Database {
+    /// Opens the database. Entries are kept forever.
+    pub fn open(path: &str) -> StoreResult<Self> {
+        Self::open_with_retention(path, RetentionConfig::default())
+    }
+
+    /// Opens the database, and prunes its entries in the background according to the retention
+    /// policies.
+    pub fn open_with_retention(path: &str, retention: RetentionConfig) -> StoreResult<Self> {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+
+        // Databases created before column families keep everything in the default family. We
+        // cannot tell headers from certificates (they are both keyed by digest), so we refuse to
+        // open them rather than silently ignoring their content.
+        if let Ok(families) = rocksdb::DB::list_cf(&options, path) {
+            let legacy = families
+                .iter()
+                .all(|x| x == rocksdb::DEFAULT_COLUMN_FAMILY_NAME);
+            if legacy {
+                let db = rocksdb::DB::open_default(path)?;
+                if db.iterator(rocksdb::IteratorMode::Start).next().is_some() {
+                    return Err(StoreError::LegacyDatabase(path.to_string()));
+                }
+            }
+        }
+
+        let names = Family::ALL.iter().map(|x| x.name());
+        let db = rocksdb::DB::open_cf(&options, path, names)?;
+        Self::migrate(&db)?;
+        Ok(Self::with_backend(RocksDbBackend::new(db), retention))
+    }
+
+    /// Creates an empty database that lives in memory. Entries are kept forever (or until the
+    /// database is dropped).
+    pub fn new_in_memory() -> Self {
+        Self::in_memory_with_retention(RetentionConfig::default())
+    }
+
+    /// Creates an empty database that lives in memory, and prunes its entries in the background
+    /// according to the retention policies.
+    pub fn in_memory_with_retention(retention: RetentionConfig) -> Self {
+        Self::with_backend(MemoryBackend::default(), retention)
+    }
+
+    /// Serves the database from the specified backend.
+    pub fn with_backend<B>(backend: B, retention: RetentionConfig) -> Self
+    where
+        B: StoreBackend + 'static,
+    {
+        let pruning = Arc::new(Mutex::new(HashMap::new()));
+        let metrics = StoreMetrics::new();
+        let (tx, rx) = channel(100);
+        let actor = Actor {
+            backend: Box::new(backend),
+            pruner: Pruner::new(retention, pruning.clone()),
+            obligations: HashMap::new(),
+            metrics: metrics.clone(),
+        };
+        tokio::spawn(actor.run(rx));
+        Self {
+            channel: tx,
+            pruning,
+            metrics,
+        }
+    }
+
+    /// Moves the watermark of a family with a `Retention::Watermark` policy: its entries whose
+    /// round is below `round` are deleted in the background (and immediately become invisible).
+    pub async fn set_watermark(&self, family: Family, round: u64) {
+        let command = StoreCommand::SetWatermark(family, round);
+        if let Err(e) = self.channel.send(command).await {
+            panic!("Failed to send SetWatermark command to store: {}", e);
+        }
+    }
+
+    /// Returns what the background pruning deleted so far, per column family.
+    pub fn pruning_metrics(&self) -> HashMap<Family, PruningMetrics> {
+        self.pruning.lock().unwrap().clone()
+    }
+
+    /// Returns the metrics of the database (latencies, sizes, and queues). They are refreshed
+    /// in the background.
+    pub fn metrics(&self) -> &StoreMetrics {
+        &self.metrics
+    }
+
+    /// Exports the metrics of the database through the (shared) registry.
+    pub fn register_metrics(&self, registry: &prometheus::Registry) -> prometheus::Result<()> {
+        self.metrics.register(registry)
+    }
+
+    /// Refreshes the metrics of the database and logs them, to diagnose a slow node.
+    pub async fn debug_dump(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::DebugDump(sender)).await {
+            panic!("Failed to send DebugDump command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to DebugDump command from store");
+    }
+
+    /// Waits until the commands sent so far are served, and persists the writes the backend
+    /// buffers in memory. Nodes call it before exiting, so that the next start is quick.
+    pub async fn flush(&self) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Flush(sender)).await {
+            panic!("Failed to send Flush command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Flush command from store")
+    }
+
+    /// Brings the key encoding of the database up to date.
+    fn migrate(db: &rocksdb::DB) -> StoreResult<()> {
+        match db.get(VERSION_KEY)?.map(|x| x[0]) {
+            Some(VERSION) => return Ok(()),
+            Some(x) => return Err(StoreError::UnsupportedVersion(x)),
+            None => (),
+        }
+
+        // Version 1 keys (little-endian) are only found in the payloads family, whose keys are a
+        // digest followed by a 4-byte worker id. Batches, headers, and certificates are keyed by
+        // digests (encoded the same way in both versions).
+        let payloads = db
+            .cf_handle(Family::Payloads.name())
+            .expect("Column family created at startup");
+        let entries: Vec<_> = db
+            .iterator_cf(payloads, rocksdb::IteratorMode::Start)
+            .collect();
+
+        // Delete all old keys before writing the new ones, since an old key may collide with
+        // the new encoding of another.
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, _) in &entries {
+            batch.delete_cf(payloads, key);
+        }
+        for (key, value) in entries {
+            let mut key = key.to_vec();
+            let len = key.len();
+            key[len.saturating_sub(4)..].reverse();
+            batch.put_cf(payloads, &key, &value);
+        }
+        batch.put(VERSION_KEY, [VERSION]);
+        db.write(batch)?;
+        Ok(())
+    }
+
+    /// Returns a typed handle to a column family. Keys and values are serialized with bincode.
+    pub fn store<K, V>(&self, family: Family) -> Store<K, V> {
+        Store {
+            family,
+            channel: self.channel.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// The channel through which a `NotifyRead` command is answered.
+type NotifySender = oneshot::Sender<StoreResult<Value>>;
+
+/// Deregisters a `NotifyRead` command from the store when its future is dropped before the key
+/// is written (e.g., it timed out), so that the store does not keep it forever.
+struct NotifyReadGuard {
+    channel: Sender<StoreCommand>,
+    family: Family,
+    /// Set until the command is answered.
+    key: Option<Key>,
+}
+
+impl Drop for NotifyReadGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // If the store is too busy, the waiter is dropped at the next refresh of the metrics.
+            let command = StoreCommand::CancelNotifyRead(self.family, key);
+            let _ = self.channel.try_send(command);
+        }
+    }
+}
+
+/// Owns the database and serves the commands of all handles, one at a time.
+struct Actor {
+    backend: Box<dyn StoreBackend>,
+    pruner: Pruner,
+    /// The `NotifyRead` commands waiting for their key to be written, and when they started.
+    obligations: HashMap<(Family, Key), VecDeque<(Instant, NotifySender)>>,
+    metrics: StoreMetrics,
+}
+
+impl Actor {
+    /// Returns the raw value of a key, and records the latency of the read.
+    fn get_stored(&self, family: Family, key: &[u8]) -> StoreResult<Option<Value>> {
+        let now = Instant::now();
+        let stored = self.backend.get(family, key);
+        let elapsed = now.elapsed().as_secs_f64();
+        self.metrics.observe(family, "read", elapsed);
+        stored
+    }
+
+    /// Returns the (decoded) value of a key, unless it is missing or pruned.
+    fn get(&self, family: Family, key: &[u8]) -> StoreResult<Option<Value>> {
+        let stored = self.get_stored(family, key)?;
+        Ok(stored.and_then(|x| self.pruner.decode(family, key, x)))
+    }
+
+    /// Wakes up the `NotifyRead` commands waiting for this key.
+    fn notify(&mut self, family: Family, key: Key, value: &Value) {
+        if let Some(mut senders) = self.obligations.remove(&(family, key)) {
+            while let Some((_, s)) = senders.pop_front() {
+                let _ = s.send(Ok(value.clone()));
+            }
+            self.count_waiters();
+        }
+    }
+
+    /// Updates the number of `NotifyRead` commands waiting for their key.
+    fn count_waiters(&self) {
+        let waiters = self.obligations.values().map(|x| x.len()).sum::<usize>();
+        self.metrics.notify_read_waiters.set(waiters as i64);
+    }
+
+    /// Updates the gauges of the metrics. Waiters whose `notify_read` was cancelled are dropped
+    /// on the way.
+    fn refresh_metrics(&mut self) {
+        self.obligations.retain(|_, senders| {
+            senders.retain(|(_, s)| !s.is_closed());
+            !senders.is_empty()
+        });
+        self.count_waiters();
+        let oldest = self
+            .obligations
+            .values()
+            .flat_map(|x| x.iter().map(|(start, _)| start))
+            .min()
+            .map_or(0.0, |x| x.elapsed().as_secs_f64());
+        self.metrics.oldest_waiter_age.set(oldest);
+
+        for family in Family::ALL.iter() {
+            let size = self.backend.estimated_size(*family);
+            self.metrics
+                .family_size
+                .with_label_values(&[family.name()])
+                .set(size as i64);
+        }
+    }
+
+    /// Logs the metrics of the database.
+    fn dump_metrics(&self) {
+        for family in Family::ALL.iter() {
+            let size = self.metrics.family_size.with_label_values(&[family.name()]);
+            let latencies: Vec<_> = ["read", "write", "delete"]
+                .iter()
+                .map(|operation| {
+                    let histogram = self
+                        .metrics
+                        .latency
+                        .with_label_values(&[family.name(), operation]);
+                    let count = histogram.get_sample_count();
+                    let average = match count {
+                        0 => 0.0,
+                        x => histogram.get_sample_sum() / x as f64,
+                    };
+                    format!("{} {} ops ({:.6}s avg)", operation, count, average)
+                })
+                .collect();
+            info!(
+                "Store family {}: {} B, {}",
+                family,
+                size.get(),
+                latencies.join(", ")
+            );
+        }
+        info!(
+            "Store queues: {} pending commands, {} notify_read waiters (oldest {:.3}s)",
+            self.metrics.pending_commands.get(),
+            self.metrics.notify_read_waiters.get(),
+            self.metrics.oldest_waiter_age.get()
+        );
+    }
+
+    async fn run(mut self, mut rx: Receiver<StoreCommand>) {
+        let mut timer = interval(self.pruner.interval());
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                command = rx.recv() => match command {
+                    Some(command) => {
+                        self.handle(command);
+                        self.metrics.pending_commands.set(rx.len() as i64);
+                    }
+                    None => break,
+                },
+                _ = timer.tick() => {
+                    self.pruner.prune(self.backend.as_mut());
+                    self.refresh_metrics();
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: StoreCommand) {
+        match command {
+            StoreCommand::Write(family, key, value) => {
+                let stored = self.pruner.encode(family, value.clone());
+                let now = Instant::now();
+                let _ = self.backend.put(family, &key, &stored);
+                self.metrics
+                    .observe(family, "write", now.elapsed().as_secs_f64());
+                self.notify(family, key, &value);
+            }
+            StoreCommand::WriteBatch(operations, sender) => {
+                let encoded: Vec<_> = operations
+                    .iter()
+                    .map(|operation| match operation {
+                        Operation::Put(family, key, value) => {
+                            let stored = self.pruner.encode(*family, value.clone());
+                            Operation::Put(*family, key.clone(), stored)
                         }
+                        Operation::Delete(family, key) => Operation::Delete(*family, key.clone()),
+                    })
+                    .collect();
+                let now = Instant::now();
+                let result = self.backend.write(&encoded);
+                let elapsed = now.elapsed().as_secs_f64();
+                let families: HashSet<_> = operations
+                    .iter()
+                    .map(|operation| match operation {
+                        Operation::Put(family, ..) | Operation::Delete(family, _) => *family,
+                    })
+                    .collect();
+                for family in families {
+                    self.metrics.observe(family, "write", elapsed);
+                }
+                if let Err(e) = result {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+                for operation in operations {
+                    if let Operation::Put(family, key, value) = operation {
+                        self.notify(family, key, &value);
                     }
-                    StoreCommand::Read(key, sender) => {
-                        let response = db.get(&key);
-                        let _ = sender.send(response);
+                }
+                let _ = sender.send(Ok(()));
+            }
+            StoreCommand::Read(family, key, sender) => {
+                let _ = sender.send(self.get(family, &key));
+            }
+            StoreCommand::NotifyRead(family, key, sender) => {
+                // Entries that are pruned will never be (visibly) written again.
+                let response = self.get_stored(family, &key);
+                if let Ok(stored) = &response {
+                    if self.pruner.is_pruned(family, &key, stored.as_deref()) {
+                        let _ = sender.send(Err(StoreError::Pruned(family)));
+                        return;
                     }
-                    StoreCommand::NotifyRead(key, sender) => {
-                        let response = db.get(&key);
-                        match response {
-                            Ok(None) => obligations
-                                .entry(key)
-                                .or_insert_with(VecDeque::new)
-                                .push_back(sender),
-                            _ => {
-                                let _ = sender.send(response.map(|x| x.unwrap()));
-                            }
-                        }
+                }
+                match response {
+                    Ok(None) => {
+                        self.obligations
+                            .entry((family, key))
+                            .or_default()
+                            .push_back((Instant::now(), sender));
+                        self.count_waiters();
+                    }
+                    Ok(Some(stored)) => {
+                        let value = self.pruner.decode(family, &key, stored);
+                        let _ = sender.send(Ok(value.expect("Entry is not pruned")));
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
                     }
                 }
             }
-        });
-        Ok(Self { channel: tx })
+            StoreCommand::Remove(family, key) => {
+                let now = Instant::now();
+                let _ = self.backend.delete(family, &key);
+                self.metrics
+                    .observe(family, "delete", now.elapsed().as_secs_f64());
+            }
+            StoreCommand::Scan(family, start, end, limit, sender) => {
+                let mut iterator = self
+                    .backend
+                    .iter_from(family, &start)
+                    .take_while(|(k, _)| end.as_ref().is_none_or(|e| k < e))
+                    .filter_map(|(k, v)| {
+                        let value = self.pruner.decode(family, &k, v)?;
+                        Some((k, value))
+                    });
+                let items = iterator.by_ref().take(limit).collect();
+                let next = iterator.next().map(|(k, _)| k);
+                let _ = sender.send((items, next));
+            }
+            StoreCommand::DeleteRange(family, start, end) => {
+                let _ = self.backend.delete_range(family, &start, &end);
+            }
+            StoreCommand::SetWatermark(family, round) => {
+                // Waiters for pruned entries would otherwise wait forever.
+                let watermark = self.pruner.set_watermark(family, round);
+                let pruned: Vec<_> = self
+                    .obligations
+                    .keys()
+                    .filter(|(f, k)| *f == family && k < &watermark)
+                    .cloned()
+                    .collect();
+                for key in pruned {
+                    for (_, sender) in self.obligations.remove(&key).unwrap_or_default() {
+                        let _ = sender.send(Err(StoreError::Pruned(family)));
+                    }
+                }
+                self.count_waiters();
+            }
+            StoreCommand::CancelNotifyRead(family, key) => {
+                let key = (family, key);
+                if let Some(senders) = self.obligations.get_mut(&key) {
+                    senders.retain(|(_, s)| !s.is_closed());
+                    if senders.is_empty() {
+                        self.obligations.remove(&key);
+                    }
+                    self.count_waiters();
+                }
+            }
+            StoreCommand::DebugDump(sender) => {
+                self.refresh_metrics();
+                self.dump_metrics();
+                let _ = sender.send(());
+            }
+            StoreCommand::Flush(sender) => {
+                let _ = sender.send(self.backend.flush());
+            }
+        }
     }
+}
+
+/// A typed handle to a column family of the database.
+pub struct Store<K, V> {
+    family: Family,
+    channel: Sender<StoreCommand>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
 
-    pub async fn write(&mut self, key: Key, value: Value) {
-        if let Err(e) = self.channel.send(StoreCommand::Write(key, value)).await {
+impl<K, V> Clone for Store<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            family: self.family,
+            channel: self.channel.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Store<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub async fn write(&mut self, key: &K, value: &V) {
+        let command = StoreCommand::Write(self.family, encode_key(key), serialize(value));
+        if let Err(e) = self.channel.send(command).await {
             panic!("Failed to send Write command to store: {}", e);
         }
     }
 
-    pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+    /// Writes all key-value pairs atomically. The returned channel fires once they are written.
+    pub async fn write_batch(&mut self, items: Vec<(K, V)>) -> oneshot::Receiver<StoreResult<()>> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in &items {
+            batch.put(self, key, value);
+        }
+        batch.commit().await
+    }
+
+    pub async fn read(&mut self, key: &K) -> StoreResult<Option<V>> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
+        let command = StoreCommand::Read(self.family, encode_key(key), sender);
+        if let Err(e) = self.channel.send(command).await {
             panic!("Failed to send Read command to store: {}", e);
         }
-        receiver
+        match receiver
             .await
-            .expect("Failed to receive reply to Read command from store")
+            .expect("Failed to receive reply to Read command from store")?
+        {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
     }
 
-    pub async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
-        let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self
-            .channel
-            .send(StoreCommand::NotifyRead(key, sender))
+    pub async fn notify_read(&mut self, key: &K) -> StoreResult<V> {
+        match self.wait_for(key).await {
+            Ok(value) => Ok(value),
+            Err(NotifyReadError::StoreError(e)) => Err(e),
+            Err(e) => panic!(
+                "Failed to receive reply to NotifyRead command from store: {}",
+                e
+            ),
+        }
+    }
+
+    /// Waits for the key to be written, for at most the specified duration. Dropping the returned
+    /// future (or timing out) deregisters the waiter from the store.
+    pub async fn notify_read_timeout(
+        &mut self,
+        key: &K,
+        duration: Duration,
+    ) -> Result<V, NotifyReadError> {
+        timeout(duration, self.wait_for(key))
             .await
-        {
+            .unwrap_or(Err(NotifyReadError::Timeout))
+    }
+
+    async fn wait_for(&mut self, key: &K) -> Result<V, NotifyReadError> {
+        let key = encode_key(key);
+        let mut guard = NotifyReadGuard {
+            channel: self.channel.clone(),
+            family: self.family,
+            key: Some(key.clone()),
+        };
+        let (sender, receiver) = oneshot::channel();
+        let command = StoreCommand::NotifyRead(self.family, key, sender);
+        if let Err(e) = self.channel.send(command).await {
             panic!("Failed to send NotifyRead command to store: {}", e);
         }
-        receiver
+        let value = receiver.await.map_err(|_| NotifyReadError::Cancelled);
+        guard.key = None;
+        Ok(bincode::deserialize(&value??).map_err(StoreError::from)?)
+    }
+
+    pub async fn remove(&mut self, key: &K) {
+        let command = StoreCommand::Remove(self.family, encode_key(key));
+        if let Err(e) = self.channel.send(command).await {
+            panic!("Failed to send Remove command to store: {}", e);
+        }
+    }
+
+    /// Returns at most `limit` entries with keys in `[start, end)`, sorted by key.
+    pub async fn iter_range(
+        &mut self,
+        start: &K,
+        end: &K,
+        limit: usize,
+    ) -> StoreResult<Page<K, V>> {
+        self.scan(encode_key(start), Some(encode_key(end)), limit)
+            .await
+    }
+
+    /// Returns at most `limit` entries whose (encoded) key starts with the (encoded) prefix,
+    /// sorted by key. For instance, `(Round, Digest)` keys can be scanned by `Round` prefix.
+    pub async fn iter_prefix<P: Serialize>(
+        &mut self,
+        prefix: &P,
+        limit: usize,
+    ) -> StoreResult<Page<K, V>> {
+        let start = encode_key(prefix);
+
+        // The first key after all keys starting with the prefix: increment the last byte that
+        // can be incremented (and drop the ones after it).
+        let mut end = start.clone();
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                break;
+            }
+        }
+        let end = if end.is_empty() { None } else { Some(end) };
+        self.scan(start, end, limit).await
+    }
+
+    /// Returns the next page of a scan.
+    pub async fn resume(
+        &mut self,
+        continuation: Continuation,
+        limit: usize,
+    ) -> StoreResult<Page<K, V>> {
+        self.scan(continuation.next, continuation.end, limit).await
+    }
+
+    /// Removes all entries with keys in `[start, end)`.
+    pub async fn delete_range(&mut self, start: &K, end: &K) {
+        let command = StoreCommand::DeleteRange(self.family, encode_key(start), encode_key(end));
+        if let Err(e) = self.channel.send(command).await {
+            panic!("Failed to send DeleteRange command to store: {}", e);
+        }
+    }
+
+    async fn scan(
+        &mut self,
+        start: Key,
+        end: Option<Key>,
+        limit: usize,
+    ) -> StoreResult<Page<K, V>> {
+        let (sender, receiver) = oneshot::channel();
+        let command = StoreCommand::Scan(self.family, start, end.clone(), limit, sender);
+        if let Err(e) = self.channel.send(command).await {
+            panic!("Failed to send Scan command to store: {}", e);
+        }
+        let (entries, next) = receiver
             .await
-            .expect("Failed to receive reply to NotifyRead command from store")
+            .expect("Failed to receive reply to Scan command from store");
+        let items = entries
+            .iter()
+            .map(|(k, v)| Ok((key_encoding().deserialize(k)?, bincode::deserialize(v)?)))
+            .collect::<StoreResult<_>>()?;
+        Ok(Page {
+            items,
+            continuation: next.map(|next| Continuation { next, end }),
+        })
     }
 }
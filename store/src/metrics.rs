@@ -0,0 +1,80 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::Family;
+use prometheus::{Gauge, HistogramOpts, HistogramVec, IntGauge, IntGaugeVec, Opts, Registry};
+
+/// The metrics of a database. The store's actor keeps them up to date; they are exported once
+/// registered (see `Database::register_metrics`).
+#[derive(Clone)]
+pub struct StoreMetrics {
+    /// The latency (in seconds) of reads, writes, and deletes, by column family and operation.
+    pub latency: HistogramVec,
+    /// The estimated size (in bytes) of each column family.
+    pub family_size: IntGaugeVec,
+    /// The number of commands waiting to be served by the store.
+    pub pending_commands: IntGauge,
+    /// The number of `notify_read` waiting for their key to be written.
+    pub notify_read_waiters: IntGauge,
+    /// How long the oldest `notify_read` has been waiting (in seconds).
+    pub oldest_waiter_age: Gauge,
+}
+
+impl StoreMetrics {
+    pub fn new() -> Self {
+        Self {
+            latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "store_operation_latency_seconds",
+                    "Latency of the store operations",
+                ),
+                &["family", "operation"],
+            )
+            .unwrap(),
+            family_size: IntGaugeVec::new(
+                Opts::new(
+                    "store_family_size_bytes",
+                    "Estimated size of the column families",
+                ),
+                &["family"],
+            )
+            .unwrap(),
+            pending_commands: IntGauge::new(
+                "store_pending_commands",
+                "Commands waiting to be served by the store",
+            )
+            .unwrap(),
+            notify_read_waiters: IntGauge::new(
+                "store_notify_read_waiters",
+                "Reads waiting for their key to be written",
+            )
+            .unwrap(),
+            oldest_waiter_age: Gauge::new(
+                "store_oldest_waiter_age_seconds",
+                "How long the oldest waiting read has been waiting",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Exports the metrics through the registry.
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.latency.clone()))?;
+        registry.register(Box::new(self.family_size.clone()))?;
+        registry.register(Box::new(self.pending_commands.clone()))?;
+        registry.register(Box::new(self.notify_read_waiters.clone()))?;
+        registry.register(Box::new(self.oldest_waiter_age.clone()))?;
+        Ok(())
+    }
+
+    /// Records the latency of an operation.
+    pub(crate) fn observe(&self, family: Family, operation: &str, seconds: f64) {
+        self.latency
+            .with_label_values(&[family.name(), operation])
+            .observe(seconds);
+    }
+}
+
+impl Default for StoreMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backend::StoreBackend;
+use crate::clock::{Clock, SystemClock};
+use crate::{encode_key, Family, Key, Operation, Value};
+use std::collections::HashMap;
+use std::convert::TryInto as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The size of the write timestamp appended to values of families with a time-to-live.
+const TIMESTAMP_SIZE: usize = 8;
+
+/// How long a column family keeps its entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retention {
+    /// Entries expire the specified number of seconds after they were written.
+    Ttl(u64),
+    /// Entries are deleted once their round falls below the watermark set by `set_watermark`.
+    /// The keys of the family must start with their round (keys are big-endian encoded, so
+    /// tuples like `(Round, Digest)` qualify).
+    Watermark,
+}
+
+/// The retention policies of the database, and how fast they are enforced.
+#[derive(Clone)]
+pub struct RetentionConfig {
+    /// The policy of each column family. Families without a policy keep their entries forever.
+    pub policies: HashMap<Family, Retention>,
+    /// The clock measuring time-to-live.
+    pub clock: Arc<dyn Clock>,
+    /// How often we prune the database.
+    pub interval: Duration,
+    /// The maximum number of entries of each family deleted every `interval`, to avoid latency
+    /// spikes (from deletions and the compactions they trigger).
+    pub max_deletions: usize,
+    /// The maximum number of entries of each family with a time-to-live examined every
+    /// `interval`. Expired entries are not sorted by key, so the pruner sweeps such families a
+    /// slice at a time, resuming where the previous round stopped.
+    pub max_scanned: usize,
+}
+
+impl RetentionConfig {
+    pub fn new(policies: HashMap<Family, Retention>) -> Self {
+        Self {
+            policies,
+            clock: Arc::new(SystemClock),
+            interval: Duration::from_millis(1_000),
+            max_deletions: 1_000,
+            max_scanned: 10_000,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+/// What the pruner deleted from a column family.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruningMetrics {
+    pub deleted_keys: u64,
+    /// The size of the deleted keys and values.
+    pub reclaimed_bytes: u64,
+}
+
+/// Enforces the retention policies on behalf of the store's actor.
+pub(crate) struct Pruner {
+    config: RetentionConfig,
+    /// The encoded watermark of each family with a watermark policy.
+    watermarks: HashMap<Family, Key>,
+    /// Where the sweep of each family with a time-to-live resumes.
+    cursors: HashMap<Family, Key>,
+    metrics: Arc<Mutex<HashMap<Family, PruningMetrics>>>,
+}
+
+impl Pruner {
+    pub fn new(
+        config: RetentionConfig,
+        metrics: Arc<Mutex<HashMap<Family, PruningMetrics>>>,
+    ) -> Self {
+        Self {
+            config,
+            watermarks: HashMap::new(),
+            cursors: HashMap::new(),
+            metrics,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.config.interval
+    }
+
+    fn now(&self) -> u64 {
+        self.config.clock.now() as u64
+    }
+
+    /// Returns the time-to-live (in ms) of the family, if any.
+    fn ttl(&self, family: Family) -> Option<u64> {
+        match self.config.policies.get(&family) {
+            Some(Retention::Ttl(seconds)) => Some(seconds * 1_000),
+            _ => None,
+        }
+    }
+
+    /// Encodes a value before writing it: families with a time-to-live remember when it was written.
+    pub fn encode(&self, family: Family, mut value: Value) -> Value {
+        if self.ttl(family).is_some() {
+            value.extend_from_slice(&self.now().to_be_bytes());
+        }
+        value
+    }
+
+    /// Whether the entry is (logically) gone, even if the pruner did not yet delete it.
+    pub fn is_pruned(&self, family: Family, key: &[u8], stored: Option<&[u8]>) -> bool {
+        if let Some(watermark) = self.watermarks.get(&family) {
+            if key < &watermark[..] {
+                return true;
+            }
+        }
+        match (self.ttl(family), stored) {
+            (Some(ttl), Some(stored)) => {
+                let offset = stored.len().saturating_sub(TIMESTAMP_SIZE);
+                let written = u64::from_be_bytes(stored[offset..].try_into().unwrap_or_default());
+                written + ttl <= self.now()
+            }
+            _ => false,
+        }
+    }
+
+    /// Decodes a stored value, or returns `None` if it is pruned.
+    pub fn decode(&self, family: Family, key: &[u8], mut stored: Value) -> Option<Value> {
+        if self.is_pruned(family, key, Some(&stored)) {
+            return None;
+        }
+        if self.ttl(family).is_some() {
+            stored.truncate(stored.len().saturating_sub(TIMESTAMP_SIZE));
+        }
+        Some(stored)
+    }
+
+    /// Moves the watermark of a family forward and returns its encoding. Watermarks never move
+    /// backward.
+    pub fn set_watermark(&mut self, family: Family, round: u64) -> Key {
+        let watermark = encode_key(&round);
+        let current = self.watermarks.entry(family).or_default();
+        if *current < watermark {
+            *current = watermark;
+        }
+        current.clone()
+    }
+
+    /// Deletes (at most `max_deletions` of) the pruned entries of every family with a policy.
+    pub fn prune(&mut self, backend: &mut dyn StoreBackend) {
+        let families: Vec<_> = self.config.policies.keys().cloned().collect();
+        for family in families {
+            let pruned: Vec<_> = match self.config.policies[&family] {
+                // Keys are sorted by round: we can stop at the first key above the watermark.
+                Retention::Watermark => backend
+                    .iter_from(family, &[])
+                    .take_while(|(k, v)| self.is_pruned(family, k, Some(v)))
+                    .take(self.config.max_deletions)
+                    .collect(),
+                Retention::Ttl(_) => self.sweep(family, &*backend),
+            };
+            if pruned.is_empty() {
+                continue;
+            }
+
+            let operations: Vec<_> = pruned
+                .iter()
+                .map(|(key, _)| Operation::Delete(family, key.clone()))
+                .collect();
+            if backend.write(&operations).is_err() {
+                continue;
+            }
+
+            let mut metrics = self.metrics.lock().unwrap();
+            let metrics = metrics.entry(family).or_default();
+            metrics.deleted_keys += pruned.len() as u64;
+            metrics.reclaimed_bytes += pruned
+                .iter()
+                .map(|(k, v)| (k.len() + v.len()) as u64)
+                .sum::<u64>();
+        }
+    }
+    /// Examines (at most `max_scanned`) entries of a family with a time-to-live, starting where
+    /// the previous sweep stopped, and returns (at most `max_deletions` of) the expired ones. The
+    /// sweep starts over once it reaches the end of the family.
+    fn sweep(&mut self, family: Family, backend: &dyn StoreBackend) -> Vec<(Key, Value)> {
+        let cursor = self.cursors.remove(&family).unwrap_or_default();
+        let mut pruned = Vec::new();
+        let mut scanned = 0;
+        for (key, value) in backend.iter_from(family, &cursor) {
+            if scanned == self.config.max_scanned || pruned.len() == self.config.max_deletions {
+                self.cursors.insert(family, key);
+                break;
+            }
+            scanned += 1;
+            if self.is_pruned(family, &key, Some(&value)) {
+                pruned.push((key, value));
+            }
+        }
+        pruned
+    }
+}
@@ -1,56 +1,141 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::channel;
+
+// Fixture: the backends against which the test suite runs. Both must behave identically.
+enum Backend {
+    /// A RocksDB database at the specified path.
+    RocksDb(&'static str),
+    Memory,
+}
+
+impl Backend {
+    fn open(&self, retention: RetentionConfig) -> Database {
+        match self {
+            Self::RocksDb(path) => {
+                let _ = fs::remove_dir_all(path);
+                Database::open_with_retention(path, retention).unwrap()
+            }
+            Self::Memory => Database::in_memory_with_retention(retention),
+        }
+    }
+}
+
+// Runs each test of the suite once per backend.
+macro_rules! backend_tests {
+    ($($test:ident),* $(,)?) => {
+        mod rocksdb_backend {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    let path = concat!(".db_test_", stringify!($test));
+                    super::$test(super::Backend::RocksDb(path)).await;
+                }
+            )*
+        }
+
+        mod memory_backend {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    super::$test(super::Backend::Memory).await;
+                }
+            )*
+        }
+    };
+}
+
+backend_tests!(
+    read_write_value,
+    read_unknown_key,
+    read_notify,
+    remove_value,
+    cross_family_isolation,
+    iterate_rounds_in_order,
+    iterate_with_continuation,
+    iterate_prefix,
+    delete_range,
+    write_batch_across_families,
+    write_batch_is_atomic,
+    write_batch_single_family,
+    watermark_pruning,
+    ttl_expiry,
+    ttl_sweep_resumes,
+    notify_read_pruned,
+    notify_read_metrics,
+    notify_read_timeout,
+    notify_read_before_deadline,
+    notify_read_dropped,
+    flush_pending_writes,
+);
+
+// Fixture: a handle to the database that crashes after forwarding the specified number of
+// commands. Later commands are dropped without being applied.
+fn crashing_database(database: &Database, commands: usize) -> Database {
+    let (tx, mut rx) = channel(100);
+    let inner = database.channel.clone();
+    tokio::spawn(async move {
+        for _ in 0..commands {
+            match rx.recv().await {
+                Some(command) => inner.send(command).await.unwrap(),
+                None => return,
+            }
+        }
+        while rx.recv().await.is_some() {}
+    });
+    Database {
+        channel: tx,
+        pruning: database.pruning.clone(),
+        metrics: database.metrics.clone(),
+    }
+}
 
 #[tokio::test]
 async fn create_store() {
     // Create new store.
     let path = ".db_test_create_store";
     let _ = fs::remove_dir_all(path);
-    let store = Store::new(path);
-    assert!(store.is_ok());
+    let db = Database::open(path);
+    assert!(db.is_ok());
 }
 
-#[tokio::test]
-async fn read_write_value() {
-    // Create new store.
-    let path = ".db_test_read_write_value";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+async fn read_write_value(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Batches);
 
     // Write value to the store.
     let key = vec![0u8, 1u8, 2u8, 3u8];
     let value = vec![4u8, 5u8, 6u8, 7u8];
-    store.write(key.clone(), value.clone()).await;
+    store.write(&key, &value).await;
 
     // Read value.
-    let result = store.read(key).await;
+    let result = store.read(&key).await;
     assert!(result.is_ok());
     let read_value = result.unwrap();
     assert!(read_value.is_some());
     assert_eq!(read_value.unwrap(), value);
 }
 
-#[tokio::test]
-async fn read_unknown_key() {
-    // Create new store.
-    let path = ".db_test_read_unknown_key";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+async fn read_unknown_key(backend: Backend) {
+    let mut store: Store<Vec<u8>, Vec<u8>> = backend
+        .open(RetentionConfig::default())
+        .store(Family::Batches);
 
     // Try to read unknown key.
     let key = vec![0u8, 1u8, 2u8, 3u8];
-    let result = store.read(key).await;
+    let result = store.read(&key).await;
     assert!(result.is_ok());
     assert!(result.unwrap().is_none());
 }
 
-#[tokio::test]
-async fn read_notify() {
-    // Create new store.
-    let path = ".db_test_read_notify";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+async fn read_notify(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Batches);
 
     // Try to read a kew that does not yet exist. Then write a value
     // for that key and check that notify read returns the result.
@@ -62,13 +147,516 @@ async fn read_notify() {
     let key_copy = key.clone();
     let value_copy = value.clone();
     let handle = tokio::spawn(async move {
-        match store_copy.notify_read(key_copy).await {
+        match store_copy.notify_read(&key_copy).await {
             Ok(v) => assert_eq!(v, value_copy),
             _ => panic!("Failed to read from store"),
         }
     });
 
     // Write the missing value and ensure the handle terminates correctly.
-    store.write(key, value).await;
+    store.write(&key, &value).await;
     assert!(handle.await.is_ok());
 }
+
+async fn remove_value(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Headers);
+
+    // Write a value and remove it.
+    let key = 1u64;
+    store.write(&key, &"value".to_string()).await;
+    store.remove(&key).await;
+    assert!(store.read(&key).await.unwrap().is_none());
+}
+
+async fn cross_family_isolation(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut headers = db.store(Family::Headers);
+    let mut certificates = db.store(Family::Certificates);
+
+    // Write values with the same key bytes in two families.
+    let key = [7u8; 32];
+    headers.write(&key, &"header".to_string()).await;
+    certificates.write(&key, &"certificate".to_string()).await;
+
+    // Each family only sees its own value.
+    assert_eq!(headers.read(&key).await.unwrap().unwrap(), "header");
+    assert_eq!(
+        certificates.read(&key).await.unwrap().unwrap(),
+        "certificate"
+    );
+
+    // Removing the key from one family leaves the other untouched.
+    headers.remove(&key).await;
+    assert!(headers.read(&key).await.unwrap().is_none());
+    assert!(certificates.read(&key).await.unwrap().is_some());
+
+    // Families that never saw the key do not have it.
+    let mut votes: Store<[u8; 32], String> = db.store(Family::Votes);
+    assert!(votes.read(&key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn refuse_legacy_database() {
+    // Create a database with the legacy single-keyspace layout.
+    let path = ".db_test_refuse_legacy_database";
+    let _ = fs::remove_dir_all(path);
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        db.put([0u8; 32], [1u8; 4]).unwrap();
+    }
+
+    // We refuse to open it.
+    match Database::open(path) {
+        Err(StoreError::LegacyDatabase(x)) => assert_eq!(x, path),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[tokio::test]
+async fn upgrade_empty_legacy_database() {
+    // Create an empty database with the legacy single-keyspace layout.
+    let path = ".db_test_upgrade_empty_legacy_database";
+    let _ = fs::remove_dir_all(path);
+    drop(rocksdb::DB::open_default(path).unwrap());
+
+    // It has no data to lose, so we simply add the column families.
+    let mut store = Database::open(path).unwrap().store(Family::Batches);
+    store.write(&1u64, &2u64).await;
+    assert_eq!(store.read(&1u64).await.unwrap(), Some(2u64));
+
+    // The upgraded database can be re-opened.
+    let options = rocksdb::Options::default();
+    let families = rocksdb::DB::list_cf(&options, path).unwrap();
+    assert!(Family::ALL
+        .iter()
+        .all(|x| families.iter().any(|y| y == x.name())));
+}
+
+async fn iterate_rounds_in_order(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Certificates);
+
+    // Write rounds out of order, some of them spanning several bytes.
+    let rounds = [300u64, 2, 256, 1, 255, 70_000, 3];
+    for round in &rounds {
+        store.write(&(*round, [0u8; 4]), &round.to_string()).await;
+    }
+
+    // Iterate over everything: rounds come out in numeric order.
+    let page = store
+        .iter_range(&(0, [0; 4]), &(u64::MAX, [0; 4]), 100)
+        .await
+        .unwrap();
+    assert!(page.continuation.is_none());
+    let read: Vec<_> = page.items.iter().map(|((r, _), _)| *r).collect();
+    assert_eq!(read, vec![1, 2, 3, 255, 256, 300, 70_000]);
+    assert!(page.items.iter().all(|((r, _), v)| v == &r.to_string()));
+
+    // The start bound is inclusive and the end bound is exclusive.
+    let page = store
+        .iter_range(&(2, [0; 4]), &(256, [0; 4]), 100)
+        .await
+        .unwrap();
+    let read: Vec<_> = page.items.iter().map(|((r, _), _)| *r).collect();
+    assert_eq!(read, vec![2, 3, 255]);
+}
+
+async fn iterate_with_continuation(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Headers);
+    for round in (1..=10u64).rev() {
+        store.write(&round, &round).await;
+    }
+
+    // Read rounds [2, 9) by pages of 3.
+    let mut page = store.iter_range(&2, &9, 3).await.unwrap();
+    let mut read: Vec<_> = page.items.iter().map(|(k, _)| *k).collect();
+    while let Some(continuation) = page.continuation {
+        page = store.resume(continuation, 3).await.unwrap();
+        read.extend(page.items.iter().map(|(k, _)| *k));
+    }
+    assert_eq!(read, vec![2, 3, 4, 5, 6, 7, 8]);
+}
+
+async fn iterate_prefix(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Certificates);
+
+    // Write keys of three rounds, including one whose encoding ends with 0xff.
+    for round in [4u64, 255, 3, 256] {
+        for id in [9u8, 0, 0xff] {
+            store.write(&(round, id), &()).await;
+        }
+    }
+
+    // Only the keys of the requested round are returned, sorted.
+    for round in [3u64, 255] {
+        let page = store.iter_prefix(&round, 100).await.unwrap();
+        let read: Vec<_> = page.items.into_iter().map(|(k, ())| k).collect();
+        assert_eq!(read, vec![(round, 0), (round, 9), (round, 0xff)]);
+    }
+}
+
+async fn delete_range(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Votes);
+    for round in [5u64, 1, 4, 2, 3, 256] {
+        store.write(&round, &()).await;
+    }
+
+    // Delete rounds [2, 4): round 4 is kept.
+    store.delete_range(&2, &4).await;
+    let page = store.iter_range(&0, &u64::MAX, 100).await.unwrap();
+    let read: Vec<_> = page.items.into_iter().map(|(k, ())| k).collect();
+    assert_eq!(read, vec![1, 4, 5, 256]);
+}
+
+#[tokio::test]
+async fn migrate_little_endian_keys() {
+    // Create a version 1 database: column families but no version, and payload keys with a
+    // little-endian worker id.
+    let path = ".db_test_migrate_little_endian_keys";
+    let _ = fs::remove_dir_all(path);
+    let digest = [5u8; 32];
+    let worker_id = 1u32;
+    {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let names = Family::ALL.iter().map(|x| x.name());
+        let db = rocksdb::DB::open_cf(&options, path, names).unwrap();
+        let key = bincode::serialize(&(digest, worker_id)).unwrap();
+        let value = bincode::serialize(&()).unwrap();
+        let payloads = db.cf_handle(Family::Payloads.name()).unwrap();
+        db.put_cf(payloads, key, value).unwrap();
+    }
+
+    // The payload is still readable after the upgrade.
+    let mut store = Database::open(path).unwrap().store(Family::Payloads);
+    assert_eq!(store.read(&(digest, worker_id)).await.unwrap(), Some(()));
+    let page = store.iter_prefix(&digest, 100).await.unwrap();
+    assert_eq!(page.items, vec![((digest, worker_id), ())]);
+}
+
+async fn write_batch_across_families(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut headers = db.store(Family::Headers);
+    let mut certificates = db.store(Family::Certificates);
+
+    // Wait for a key that the batch will write.
+    let mut certificates_copy = certificates.clone();
+    let handle = tokio::spawn(async move { certificates_copy.notify_read(&2u64).await });
+
+    // Write to two families and delete a previous value in a single batch.
+    headers.write(&0u64, &"old".to_string()).await;
+    let mut batch = WriteBatch::default();
+    batch
+        .put(&headers, &1u64, &"header".to_string())
+        .put(&certificates, &2u64, &"certificate".to_string())
+        .delete(&headers, &0u64);
+    assert!(batch.commit().await.await.unwrap().is_ok());
+
+    assert_eq!(headers.read(&1).await.unwrap().unwrap(), "header");
+    assert_eq!(certificates.read(&2).await.unwrap().unwrap(), "certificate");
+    assert!(headers.read(&0).await.unwrap().is_none());
+
+    // The waiter is woken by the batch.
+    assert_eq!(handle.await.unwrap().unwrap(), "certificate");
+}
+
+async fn write_batch_is_atomic(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut headers: Store<u64, u64> = db.store(Family::Headers);
+    let mut certificates: Store<u64, u64> = db.store(Family::Certificates);
+
+    // Without batches, a crash between two writes leaves only the first one visible.
+    let crashing = crashing_database(&db, 1);
+    crashing.store(Family::Headers).write(&1u64, &1u64).await;
+    crashing
+        .store(Family::Certificates)
+        .write(&1u64, &1u64)
+        .await;
+    assert_eq!(headers.notify_read(&1).await.unwrap(), 1);
+    assert!(certificates.read(&1).await.unwrap().is_none());
+
+    // With batches, either all or none of the keys are visible, wherever the crash happens.
+    for (crash_point, key) in [(0, 2u64), (1, 3u64)] {
+        let crashing = crashing_database(&db, crash_point);
+        let mut batch = WriteBatch::default();
+        batch
+            .put(&crashing.store::<u64, u64>(Family::Headers), &key, &key)
+            .put(
+                &crashing.store::<u64, u64>(Family::Certificates),
+                &key,
+                &key,
+            );
+        let written = batch.commit().await.await.is_ok();
+        assert_eq!(written, crash_point > 0);
+
+        let header = headers.read(&key).await.unwrap();
+        let certificate = certificates.read(&key).await.unwrap();
+        assert_eq!(header, certificate);
+        assert_eq!(header.is_some(), written);
+    }
+}
+
+async fn write_batch_single_family(backend: Backend) {
+    let mut store = backend
+        .open(RetentionConfig::default())
+        .store(Family::Batches);
+
+    let items: Vec<_> = (0..10u64).map(|x| (x, x * 2)).collect();
+    assert!(store.write_batch(items).await.await.unwrap().is_ok());
+    let page = store.iter_range(&0, &u64::MAX, 100).await.unwrap();
+    assert_eq!(page.items.len(), 10);
+    assert!(page.items.iter().all(|(k, v)| *v == k * 2));
+}
+
+// Fixture
+fn retention(family: Family, policy: Retention, clock: MockClock) -> RetentionConfig {
+    RetentionConfig {
+        policies: [(family, policy)].iter().cloned().collect(),
+        clock: Arc::new(clock),
+        interval: Duration::from_millis(10),
+        max_deletions: 1_000,
+        max_scanned: 10_000,
+    }
+}
+
+async fn watermark_pruning(backend: Backend) {
+    let config = retention(Family::Headers, Retention::Watermark, MockClock::new(0));
+    let db = backend.open(config);
+    let mut store = db.store(Family::Headers);
+
+    // Write keys of rounds 1 to 5 (out of order).
+    for round in [5u64, 1, 4, 2, 3] {
+        store.write(&(round, 0u8), &round).await;
+    }
+
+    // Entries strictly below the watermark immediately disappear, the others stay.
+    db.set_watermark(Family::Headers, 3).await;
+    assert!(store.read(&(2, 0)).await.unwrap().is_none());
+    assert_eq!(store.read(&(3, 0)).await.unwrap(), Some(3));
+    let page = store
+        .iter_range(&(0, 0), &(u64::MAX, 0), 100)
+        .await
+        .unwrap();
+    let read: Vec<_> = page.items.into_iter().map(|((r, _), _)| r).collect();
+    assert_eq!(read, vec![3, 4, 5]);
+
+    // The background task deletes exactly the entries below the watermark.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let metrics = db.pruning_metrics()[&Family::Headers];
+    assert_eq!(metrics.deleted_keys, 2);
+    assert!(metrics.reclaimed_bytes > 0);
+
+    // Watermarks never move backward.
+    db.set_watermark(Family::Headers, 1).await;
+    assert!(store.read(&(2, 0)).await.unwrap().is_none());
+    assert_eq!(store.read(&(3, 0)).await.unwrap(), Some(3));
+}
+
+async fn ttl_expiry(backend: Backend) {
+    let clock = MockClock::new(0);
+    let config = retention(Family::Batches, Retention::Ttl(10), clock.clone());
+    let db = backend.open(config);
+    let mut store = db.store(Family::Batches);
+    let mut others = db.store(Family::Headers);
+
+    // The entry is visible until its time-to-live elapsed.
+    store.write(&1u64, &vec![7u8; 10]).await;
+    others.write(&1u64, &vec![7u8; 10]).await;
+    assert!(store.read(&1).await.unwrap().is_some()); // Ensure the writes are applied at time 0.
+    clock.advance(9_999);
+    assert_eq!(store.read(&1).await.unwrap(), Some(vec![7u8; 10]));
+    clock.advance(1);
+    assert!(store.read(&1).await.unwrap().is_none());
+
+    // The background task deletes it, and leaves the other families alone.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let metrics = db.pruning_metrics();
+    assert_eq!(metrics[&Family::Batches].deleted_keys, 1);
+    assert!(!metrics.contains_key(&Family::Headers));
+    assert!(others.read(&1).await.unwrap().is_some());
+}
+
+async fn ttl_sweep_resumes(backend: Backend) {
+    let clock = MockClock::new(0);
+    let config = RetentionConfig {
+        max_scanned: 2,
+        ..retention(Family::Batches, Retention::Ttl(10), clock.clone())
+    };
+    let db = backend.open(config);
+    let mut store = db.store(Family::Batches);
+
+    // Write entries that expire after the ones sorted before them.
+    for key in [5u64, 6] {
+        store.write(&key, &key).await;
+    }
+    assert!(store.read(&5).await.unwrap().is_some()); // Ensure the writes are applied at time 0.
+    clock.advance(5_000);
+    for key in 1u64..=4 {
+        store.write(&key, &key).await;
+    }
+    assert!(store.read(&1).await.unwrap().is_some());
+    clock.advance(5_000);
+
+    // Each round examines two entries: the sweep reaches the expired ones past the live ones.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(db.pruning_metrics()[&Family::Batches].deleted_keys, 2);
+    for key in 1u64..=4 {
+        assert_eq!(store.read(&key).await.unwrap(), Some(key));
+    }
+}
+
+async fn notify_read_pruned(backend: Backend) {
+    let config = retention(
+        Family::Certificates,
+        Retention::Watermark,
+        MockClock::new(0),
+    );
+    let db = backend.open(config);
+    let mut store: Store<(u64, u8), u64> = db.store(Family::Certificates);
+
+    // A waiter for an entry that gets pruned fails instead of hanging.
+    let mut store_copy = store.clone();
+    let handle = tokio::spawn(async move { store_copy.notify_read(&(4, 0)).await });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    db.set_watermark(Family::Certificates, 5).await;
+    match handle.await.unwrap() {
+        Err(StoreError::Pruned(family)) => assert_eq!(family, Family::Certificates),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    // So does a new waiter for an entry below the watermark.
+    match store.notify_read(&(1, 0)).await {
+        Err(StoreError::Pruned(_)) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    // Waiters for entries above the watermark are unaffected.
+    store.write(&(5, 0), &5).await;
+    assert_eq!(store.notify_read(&(5, 0)).await.unwrap(), 5);
+}
+
+async fn notify_read_metrics(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let registry = prometheus::Registry::new();
+    db.register_metrics(&registry).unwrap();
+    let mut store: Store<u64, u64> = db.store(Family::Batches);
+
+    // Wait for keys that never arrive.
+    let handles: Vec<_> = (0..10u64)
+        .map(|key| {
+            let mut store = store.clone();
+            tokio::spawn(async move { store.notify_read(&key).await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    db.debug_dump().await;
+    assert_eq!(db.metrics().notify_read_waiters.get(), 10);
+    assert!(db.metrics().oldest_waiter_age.get() >= 0.0);
+
+    // The operations are measured and exported.
+    let read = db.metrics().latency.with_label_values(&["batches", "read"]);
+    assert_eq!(read.get_sample_count(), 10);
+    let families = registry.gather();
+    assert!(families
+        .iter()
+        .any(|x| x.get_name() == "store_notify_read_waiters"));
+
+    // Cancelled waiters are no longer counted.
+    for handle in &handles[..6] {
+        handle.abort();
+    }
+    for handle in handles.into_iter().take(6) {
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+    db.debug_dump().await;
+    assert_eq!(db.metrics().notify_read_waiters.get(), 4);
+
+    // Written keys wake up their waiters, which are then no longer counted either.
+    store.write(&7, &7).await;
+    db.debug_dump().await;
+    assert_eq!(db.metrics().notify_read_waiters.get(), 3);
+    let size = db.metrics().family_size.with_label_values(&["batches"]);
+    assert!(size.get() > 0);
+}
+
+async fn notify_read_timeout(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut store: Store<u64, u64> = db.store(Family::Batches);
+
+    // The key is never written: the waiter gives up and is deregistered.
+    match store
+        .notify_read_timeout(&1, Duration::from_millis(50))
+        .await
+    {
+        Err(NotifyReadError::Timeout) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+    assert!(store.read(&1).await.unwrap().is_none());
+    assert_eq!(db.metrics().notify_read_waiters.get(), 0);
+}
+
+async fn notify_read_before_deadline(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut store: Store<u64, u64> = db.store(Family::Batches);
+
+    // The key is written shortly before the deadline.
+    let mut store_copy = store.clone();
+    let handle = tokio::spawn(async move {
+        store_copy
+            .notify_read_timeout(&1, Duration::from_millis(500))
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    store.write(&1, &7).await;
+    assert_eq!(handle.await.unwrap().unwrap(), 7);
+}
+
+async fn notify_read_dropped(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut store: Store<u64, u64> = db.store(Family::Batches);
+
+    // Register waiters for keys that never arrive.
+    let handles: Vec<_> = (0..10u64)
+        .map(|key| {
+            let mut store = store.clone();
+            tokio::spawn(async move { store.notify_read(&key).await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(store.read(&0).await.unwrap().is_none()); // Ensure the waiters are registered.
+    assert_eq!(db.metrics().notify_read_waiters.get(), 10);
+
+    // Dropping the futures deregisters the waiters (without waiting for a metrics refresh).
+    for handle in handles {
+        handle.abort();
+        let _ = handle.await;
+    }
+    assert!(store.read(&0).await.unwrap().is_none()); // Ensure the cancellations are applied.
+    assert_eq!(db.metrics().notify_read_waiters.get(), 0);
+}
+
+async fn flush_pending_writes(backend: Backend) {
+    let db = backend.open(RetentionConfig::default());
+    let mut store: Store<u64, u64> = db.store(Family::Batches);
+
+    // The flush waits for the writes sent before it (which are not acknowledged), and completes
+    // well within the grace period of a shutdown.
+    for key in 0..1_000u64 {
+        store.write(&key, &key).await;
+    }
+    let result = tokio::time::timeout(Duration::from_secs(1), db.flush()).await;
+    assert!(matches!(result, Ok(Ok(()))));
+    for key in 0..1_000u64 {
+        assert_eq!(store.read(&key).await.unwrap(), Some(key));
+    }
+}
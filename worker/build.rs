@@ -0,0 +1,16 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a vendored `protoc`, so building does not require it to be installed.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+
+    // Transactions are forwarded to the `BatchMaker` as `Bytes`, without copying them. Clients
+    // connect through a `Channel` (the generated `connect` helper needs the 2021 prelude).
+    tonic_build::configure()
+        .bytes(["."])
+        .build_transport(false)
+        .compile_protos(
+            &["proto/transactions.proto", "proto/wire.proto"],
+            &["proto"],
+        )?;
+    Ok(())
+}
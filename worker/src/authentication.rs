@@ -0,0 +1,72 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use config::Committee;
+use crypto::{PublicKey, SecretKey, Signature};
+use network::{deserialize_bounded, Authenticator};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// The maximum size (in bytes) of the proof of identity of a peer: its public key and signature
+/// (with some slack). Proofs come from peers we did not authenticate yet.
+const MAX_PROOF_SIZE: u64 = 256;
+
+/// Authenticates the connections between workers with the keys of their authorities: each side
+/// signs the transcript of the handshake, and only the authorities of the committee are accepted.
+/// The identity of a peer is its public key, and we expect the worker at each address of the
+/// committee to prove the one of its authority.
+pub struct CommitteeAuthenticator {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The secret key of this authority.
+    secret: SecretKey,
+    /// The committee information.
+    committee: Committee,
+    /// The authorities of the workers of the committee, by address.
+    authorities: HashMap<SocketAddr, PublicKey>,
+}
+
+impl CommitteeAuthenticator {
+    pub fn new(name: PublicKey, secret: SecretKey, committee: Committee) -> Self {
+        let authorities = committee
+            .authorities
+            .iter()
+            .flat_map(|(name, authority)| {
+                authority
+                    .workers
+                    .values()
+                    .map(move |addresses| (addresses.worker_to_worker, *name))
+            })
+            .collect();
+        Self {
+            name,
+            secret,
+            committee,
+            authorities,
+        }
+    }
+}
+
+impl Authenticator for CommitteeAuthenticator {
+    fn prove(&self, transcript: &[u8]) -> Bytes {
+        let signature = Signature::new(&crypto::hash(transcript), &self.secret);
+        let proof = bincode::serialize(&(self.name, signature))
+            .expect("Failed to serialize our proof of identity");
+        Bytes::from(proof)
+    }
+
+    fn verify(&self, transcript: &[u8], proof: &[u8]) -> Option<Bytes> {
+        let (name, signature): (PublicKey, Signature) =
+            deserialize_bounded(proof, MAX_PROOF_SIZE).ok()?;
+        if self.committee.stake(&name) == 0 {
+            return None;
+        }
+        signature.verify(&crypto::hash(transcript), &name).ok()?;
+        Some(Bytes::copy_from_slice(name.as_ref()))
+    }
+
+    fn expected(&self, address: &SocketAddr) -> Option<Bytes> {
+        self.authorities
+            .get(address)
+            .map(|name| Bytes::copy_from_slice(name.as_ref()))
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::request_log::RequestLog;
+use network::ConcurrencyLimit;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The work queued in a worker. Its tasks update it (without locks, since they do it for every
+/// transaction) and monitoring tools read it. Clones share the same counters.
+#[derive(Clone, Default)]
+pub struct Backlog {
+    /// The transactions of the batch being assembled.
+    transactions: Arc<AtomicUsize>,
+    /// The size of the batch being assembled (in bytes).
+    bytes: Arc<AtomicUsize>,
+    /// The batches sealed and waiting for a quorum of acknowledgements.
+    batches: Arc<AtomicUsize>,
+    /// Limits the client connections served at once (if set).
+    connections: Option<ConcurrencyLimit>,
+    /// Logs a sample of the frames the worker receives.
+    request_log: RequestLog,
+}
+
+/// The value of the backlog at some point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BacklogSnapshot {
+    /// The transactions of the batch being assembled.
+    pub transactions: usize,
+    /// The size of the batch being assembled (in bytes).
+    pub bytes: usize,
+    /// The batches sealed and waiting for a quorum of acknowledgements.
+    pub batches: usize,
+    /// The client connections served.
+    pub connections: usize,
+    /// How many client connections may be served at once (if limited).
+    pub connection_cap: Option<usize>,
+}
+
+impl Backlog {
+    /// Makes a backlog that also reports the client connections of the limit.
+    pub fn with_connections(connections: Option<ConcurrencyLimit>) -> Self {
+        Self {
+            connections,
+            ..Self::default()
+        }
+    }
+
+    /// Makes a backlog that also gives access to the request log (e.g., to adjust its rate).
+    pub(crate) fn with_request_log(self, request_log: RequestLog) -> Self {
+        Self {
+            request_log,
+            ..self
+        }
+    }
+
+    /// Returns the request log of the worker, whose rate may be adjusted at runtime.
+    pub fn request_log(&self) -> &RequestLog {
+        &self.request_log
+    }
+
+    pub fn snapshot(&self) -> BacklogSnapshot {
+        BacklogSnapshot {
+            transactions: self.transactions.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+            connections: self.connections.as_ref().map_or(0, |x| x.in_flight()),
+            connection_cap: self.connections.as_ref().map(|x| x.cap()),
+        }
+    }
+
+    /// Records the size of the batch being assembled.
+    pub(crate) fn set_current_batch(&self, transactions: usize, bytes: usize) {
+        self.transactions.store(transactions, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Records that a batch was sealed.
+    pub(crate) fn sealed(&self) {
+        self.set_current_batch(0, 0);
+        self.batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that we are done waiting for the acknowledgements of a batch.
+    pub(crate) fn acknowledged(&self) {
+        self.batches.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,28 +1,37 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backlog::Backlog;
+use crate::proofs::BatchDigester;
 use crate::quorum_waiter::QuorumWaiterMessage;
+use crate::receipts::Receipts;
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
-#[cfg(feature = "benchmark")]
-use crypto::Digest;
-use crypto::PublicKey;
-#[cfg(feature = "benchmark")]
-use ed25519_dalek::{Digest as _, Sha512};
+use crypto::{Digest, PublicKey};
 #[cfg(feature = "benchmark")]
 use log::info;
-use network::ReliableSender;
+use network::{Compression, ReliableSender, SharedAuthenticator, SharedTranscoder};
 #[cfg(feature = "benchmark")]
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, debug_span, field, trace, Span};
 
 #[cfg(test)]
 #[path = "tests/batch_maker_tests.rs"]
 pub mod batch_maker_tests;
 
-pub type Transaction = Vec<u8>;
+/// A client transaction. It shares the buffer of the network frame it was received in.
+pub type Transaction = Bytes;
 pub type Batch = Vec<Transaction>;
 
+/// A transaction along with the time the worker received it (to measure latency). The stamp is
+/// carried next to the transaction: the transaction's bytes are never altered.
+pub type StampedTransaction = (Instant, Transaction);
+
+/// The digest and size (in bytes) of each transaction queued for the next batch, in batch order.
+pub type BatchPreview = Vec<(Digest, usize)>;
+
 /// Assemble clients transactions into batches.
 pub struct BatchMaker {
     /// The preferred batch size (in bytes).
@@ -30,7 +39,9 @@ pub struct BatchMaker {
     /// The maximum delay after which to seal the batch (in ms).
     max_batch_delay: u64,
     /// Channel to receive transactions from the network.
-    rx_transaction: Receiver<Transaction>,
+    rx_transaction: Receiver<StampedTransaction>,
+    /// Receives requests to preview the current batch.
+    rx_preview: Receiver<oneshot::Sender<BatchPreview>>,
     /// Output channel to deliver sealed batches to the `QuorumWaiter`.
     tx_message: Sender<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
@@ -39,28 +50,56 @@ pub struct BatchMaker {
     current_batch: Batch,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// When we received the first transaction of the current batch.
+    current_batch_start: Option<Instant>,
+    /// The span of the current batch, from its first transaction to its quorum of
+    /// acknowledgements. Its digest is recorded when the batch is sealed.
+    current_span: Span,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
+    /// The backlog of the worker.
+    backlog: Backlog,
+    /// How to digest the batches.
+    digester: BatchDigester,
+    /// Tracks the tagged transactions (if we issue receipts).
+    receipts: Option<Receipts>,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         batch_size: usize,
         max_batch_delay: u64,
-        rx_transaction: Receiver<Transaction>,
+        rx_transaction: Receiver<StampedTransaction>,
+        rx_preview: Receiver<oneshot::Sender<BatchPreview>>,
         tx_message: Sender<QuorumWaiterMessage>,
         workers_addresses: Vec<(PublicKey, SocketAddr)>,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
+        backlog: Backlog,
+        digester: BatchDigester,
+        receipts: Option<Receipts>,
     ) {
         tokio::spawn(async move {
             Self {
                 batch_size,
                 max_batch_delay,
                 rx_transaction,
+                rx_preview,
                 tx_message,
                 workers_addresses,
                 current_batch: Batch::with_capacity(batch_size * 2),
                 current_batch_size: 0,
-                network: ReliableSender::new(),
+                current_batch_start: None,
+                current_span: Span::none(),
+                network: compression
+                    .map_or_else(ReliableSender::new, ReliableSender::with_compression)
+                    .with_transcoder(transcoder)
+                    .with_authenticator(authenticator),
+                backlog,
+                digester,
+                receipts,
             }
             .run()
             .await;
@@ -75,15 +114,26 @@ impl BatchMaker {
         loop {
             tokio::select! {
                 // Assemble client transactions into batches of preset size.
-                Some(transaction) = self.rx_transaction.recv() => {
+                Some((received, transaction)) = self.rx_transaction.recv() => {
+                    if self.current_batch.is_empty() {
+                        self.current_span = debug_span!("batch", digest = field::Empty);
+                        self.current_batch_start = Some(received);
+                    }
+                    trace!(parent: &self.current_span, size = transaction.len(), "Received transaction");
                     self.current_batch_size += transaction.len();
                     self.current_batch.push(transaction);
+                    self.backlog.set_current_batch(self.current_batch.len(), self.current_batch_size);
                     if self.current_batch_size >= self.batch_size {
                         self.seal().await;
                         timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
                     }
                 },
 
+                // Describe the transactions of the current batch, without altering it.
+                Some(reply) = self.rx_preview.recv() => {
+                    let _ = reply.send(self.preview());
+                },
+
                 // If the timer triggers, seal the batch even if it contains few transactions.
                 () = &mut timer => {
                     if !self.current_batch.is_empty() {
@@ -98,9 +148,16 @@ impl BatchMaker {
         }
     }
 
+    /// Lists the transactions of the current batch.
+    fn preview(&self) -> BatchPreview {
+        self.current_batch
+            .iter()
+            .map(|tx| (crypto::hash(tx), tx.len()))
+            .collect()
+    }
+
     /// Seal and broadcast the current batch.
     async fn seal(&mut self) {
-        #[cfg(feature = "benchmark")]
         let size = self.current_batch_size;
 
         // Look for sample txs (they all start with 0) and gather their txs id (the next 8 bytes).
@@ -112,21 +169,27 @@ impl BatchMaker {
             .filter_map(|tx| tx[1..9].try_into().ok())
             .collect();
 
-        // Serialize the batch.
+        // Serialize and hash the batch.
         self.current_batch_size = 0;
         let batch: Vec<_> = self.current_batch.drain(..).collect();
-        let message = WorkerMessage::Batch(batch);
+        self.backlog.sealed();
+        let message = WorkerMessage::Batch(batch.clone());
         let serialized = bincode::serialize(&message).expect("Failed to serialize our own batch");
+        let digest = self.digester.digest(&batch, &serialized);
+        if let Some(receipts) = &self.receipts {
+            receipts.sealed(&batch, &digest);
+        }
+
+        let span = std::mem::replace(&mut self.current_span, Span::none());
+        span.record("digest", field::debug(&digest));
+        let waited = self
+            .current_batch_start
+            .take()
+            .map_or(0, |x| x.elapsed().as_millis() as u64);
+        debug!(parent: &span, size, waited_ms = waited, "Sealed batch");
 
         #[cfg(feature = "benchmark")]
         {
-            // NOTE: This is one extra hash that is only needed to print the following log entries.
-            let digest = Digest(
-                Sha512::digest(&serialized).as_slice()[..32]
-                    .try_into()
-                    .unwrap(),
-            );
-
             for id in tx_ids {
                 // NOTE: This log entry is used to compute performance.
                 info!(
@@ -140,16 +203,20 @@ impl BatchMaker {
             info!("Batch {:?} contains {} B", digest, size);
         }
 
-        // Broadcast the batch through the network.
+        // Broadcast the batch through the network, along with its digest so that the other workers
+        // can check it on receipt (cloning the batch only copies the handles of its transactions).
         let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
-        let bytes = Bytes::from(serialized.clone());
+        let bytes = bincode::serialize(&WorkerMessage::DigestedBatch(batch, digest))
+            .map(Bytes::from)
+            .expect("Failed to serialize our own batch");
         let handlers = self.network.broadcast(addresses, bytes).await;
 
         // Send the batch through the deliver channel for further processing.
         self.tx_message
             .send(QuorumWaiterMessage {
                 batch: serialized,
-                handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+                handlers: names.into_iter().zip(handlers).collect(),
+                span,
             })
             .await
             .expect("Failed to deliver batch");
@@ -0,0 +1,161 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::RequestBudget;
+use log::debug;
+use network::DEFAULT_TRACKED_PEERS;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/budgets_tests.rs"]
+pub mod budgets_tests;
+
+/// The number of peers reported in the periodic logs.
+const REPORTED_PEERS: usize = 10;
+
+/// A token bucket holding up to `burst` tokens, refilled at `rate` tokens per second. Its tokens
+/// may go negative: the debt is paid back by the refill before the bucket lets anything through.
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(burst: u64) -> Self {
+        Self {
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: u64, burst: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(burst as f64);
+        self.last = now;
+    }
+
+    fn is_full(&self, burst: u64) -> bool {
+        self.tokens >= burst as f64
+    }
+}
+
+/// What a peer asked of us.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PeerRequests {
+    /// The requests we served.
+    pub admitted: u64,
+    /// The requests we throttled.
+    pub throttled: u64,
+    /// The bytes we sent in reply.
+    pub bytes: u64,
+}
+
+struct PeerBudget {
+    requests: Bucket,
+    bytes: Bucket,
+    counters: PeerRequests,
+}
+
+/// The budgets of the peers sending us batch requests, keyed by IP address (peers are not
+/// authenticated). Each request costs a token of the request bucket of its peer, and each byte
+/// we send in reply a token of its byte bucket: peers whose request bucket is empty, or whose
+/// byte bucket is in debt, are throttled. This keeps a peer from turning us into a free read
+/// amplifier. The budgets are shared by all clones. Once we track twice `DEFAULT_TRACKED_PEERS`
+/// peers, we forget those whose buckets refilled (along with their counters).
+#[derive(Clone)]
+pub struct RequestBudgets {
+    budget: RequestBudget,
+    peers: Arc<Mutex<HashMap<IpAddr, PeerBudget>>>,
+}
+
+impl RequestBudgets {
+    pub fn new(budget: RequestBudget) -> Self {
+        Self {
+            budget,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Charges a request to the budget of a peer, and returns whether it is within budget.
+    pub fn admit(&self, peer: IpAddr) -> bool {
+        let budget = self.budget;
+        let mut peers = self.peers.lock().unwrap();
+        if peers.len() >= 2 * DEFAULT_TRACKED_PEERS && !peers.contains_key(&peer) {
+            peers.retain(|_, x| {
+                x.requests
+                    .refill(budget.requests_per_second, budget.request_burst);
+                x.bytes.refill(budget.bytes_per_second, budget.byte_burst);
+                !x.requests.is_full(budget.request_burst) || !x.bytes.is_full(budget.byte_burst)
+            });
+        }
+        let entry = peers.entry(peer).or_insert_with(|| PeerBudget {
+            requests: Bucket::new(budget.request_burst),
+            bytes: Bucket::new(budget.byte_burst),
+            counters: PeerRequests::default(),
+        });
+        entry
+            .requests
+            .refill(budget.requests_per_second, budget.request_burst);
+        entry
+            .bytes
+            .refill(budget.bytes_per_second, budget.byte_burst);
+        if entry.requests.tokens < 1.0 || entry.bytes.tokens <= 0.0 {
+            entry.counters.throttled += 1;
+            return false;
+        }
+        entry.requests.tokens -= 1.0;
+        entry.counters.admitted += 1;
+        true
+    }
+
+    /// Charges the bytes we sent in reply to a peer to its budget.
+    pub fn charge(&self, peer: IpAddr, bytes: usize) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(entry) = peers.get_mut(&peer) {
+            entry.bytes.tokens -= bytes as f64;
+            entry.counters.bytes += bytes as u64;
+        }
+    }
+
+    /// Returns the requests of each peer, the most throttled first.
+    pub fn snapshot(&self) -> Vec<(IpAddr, PeerRequests)> {
+        let mut snapshot: Vec<_> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, x)| (*peer, x.counters))
+            .collect();
+        snapshot.sort_by(|a, b| {
+            (b.1.throttled, b.1.bytes)
+                .cmp(&(a.1.throttled, a.1.bytes))
+                .then(a.0.cmp(&b.0))
+        });
+        snapshot
+    }
+
+    /// Logs the requests of the most throttled peers periodically (at debug level).
+    pub fn log_periodically(&self, period: Duration) {
+        let budgets = self.clone();
+        tokio::spawn(async move {
+            let mut timer = interval(period);
+            loop {
+                timer.tick().await;
+                let snapshot = budgets.snapshot();
+                if !snapshot.is_empty() {
+                    let top: Vec<_> = snapshot.into_iter().take(REPORTED_PEERS).collect();
+                    debug!("Batch requests by peer: {:?}", top);
+                }
+            }
+        });
+    }
+}
+
+impl Default for RequestBudgets {
+    fn default() -> Self {
+        Self::new(RequestBudget::default())
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use crate::processor::SerializedBatchMessage;
+use crate::proofs::{BatchDigester, ProofError};
+use crate::worker::{Round, WorkerMessage};
+use config::WorkerId;
+use crypto::{Digest, Hash as _};
+use log::error;
+use primary::{Certificate, Header};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/certifications_tests.rs"]
+pub mod certifications_tests;
+
+/// Proves that a batch is one of those a certificate committed, to a requester that only knows the
+/// digest of the certificate. Headers hash their payload as a whole (not into a Merkle root), so
+/// the proof is the header of the certificate: it hashes to the id the certificate digest commits
+/// to, and its payload references the digest of the batch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CertifiedBatch {
+    pub batch: Batch,
+    /// The header of the certificate.
+    pub header: Header,
+}
+
+impl CertifiedBatch {
+    /// Checks the proof against the digest of the certificate, and returns the digest of the batch.
+    pub fn verify(
+        &self,
+        certificate: &Digest,
+        digester: &BatchDigester,
+    ) -> Result<Digest, ProofError> {
+        if self.header.digest() != self.header.id {
+            return Err(ProofError::InvalidHeader(self.header.id.clone()));
+        }
+        let computed = Certificate {
+            header: self.header.clone(),
+            ..Certificate::default()
+        }
+        .digest();
+        if computed != *certificate {
+            return Err(ProofError::CertificateMismatch(
+                computed,
+                certificate.clone(),
+            ));
+        }
+        let serialized = bincode::serialize(&WorkerMessage::Batch(self.batch.clone()))
+            .expect("Failed to serialize batch");
+        let digest = digester.digest(&self.batch, &serialized);
+        if !self.header.payload.contains_key(&digest) {
+            return Err(ProofError::BatchNotCertified(certificate.clone(), digest));
+        }
+        Ok(digest)
+    }
+}
+
+#[derive(Default)]
+struct Committed {
+    /// The headers of the committed certificates, by digest of the batches they reference.
+    headers: HashMap<Digest, Arc<Header>>,
+    /// The digests of the batches of `headers`, by round of their header.
+    rounds: BTreeMap<Round, Vec<Digest>>,
+}
+
+/// Keeps the headers of the committed certificates referencing batches of our id (our primary
+/// tells us about them), to prove which certificate committed a batch. We forget them `retention`
+/// rounds after their commit. Clones share the same headers.
+#[derive(Clone)]
+pub struct Certifications {
+    /// The id of this worker.
+    id: WorkerId,
+    /// How many rounds we keep the headers for.
+    retention: Round,
+    /// The persistent storage of the batches.
+    store: Store<Digest, SerializedBatchMessage>,
+    committed: Arc<Mutex<Committed>>,
+}
+
+impl Certifications {
+    pub fn new(
+        id: WorkerId,
+        retention: Round,
+        store: Store<Digest, SerializedBatchMessage>,
+    ) -> Self {
+        Self {
+            id,
+            retention,
+            store,
+            committed: Arc::default(),
+        }
+    }
+
+    /// Records the header of a committed certificate. A batch keeps the first header committing it.
+    pub fn committed(&self, header: Header) {
+        let header = Arc::new(header);
+        let mut committed = self.committed.lock().unwrap();
+        for (digest, id) in &header.payload {
+            if *id != self.id || committed.headers.contains_key(digest) {
+                continue;
+            }
+            committed.headers.insert(digest.clone(), header.clone());
+            committed
+                .rounds
+                .entry(header.round)
+                .or_default()
+                .push(digest.clone());
+        }
+    }
+
+    /// Forgets the headers committed `retention` rounds before the specified round.
+    pub fn cleanup(&self, round: Round) {
+        let gc_round = round.saturating_sub(self.retention);
+        let mut committed = self.committed.lock().unwrap();
+        let kept = committed.rounds.split_off(&gc_round);
+        for digest in std::mem::replace(&mut committed.rounds, kept)
+            .into_values()
+            .flatten()
+        {
+            committed.headers.remove(&digest);
+        }
+    }
+
+    /// Returns the proof of the certificate that committed a batch, if we have both.
+    pub async fn prove(&self, digest: &Digest) -> Option<CertifiedBatch> {
+        let header = self
+            .committed
+            .lock()
+            .unwrap()
+            .headers
+            .get(digest)
+            .cloned()?;
+        let serialized = match self.store.clone().read(digest).await {
+            Ok(serialized) => serialized?,
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
+        match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::Batch(batch)) => Some(CertifiedBatch {
+                batch,
+                header: (*header).clone(),
+            }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::error::WorkerNetError;
+use bytes::{BufMut as _, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::convert::TryInto as _;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/chunks_tests.rs"]
+pub mod chunks_tests;
+
+/// The size of the header prefixing each chunk of a chunked transaction: the id of the transaction
+/// (a big-endian `u64` chosen by the client), followed by the index of the chunk and the number of
+/// chunks of the transaction (both big-endian `u32`).
+pub const CHUNK_HEADER_SIZE: usize = 16;
+
+/// How many bytes of incomplete transactions we buffer for each connection.
+const MAX_BUFFERED_SIZE: usize = 64_000_000;
+
+/// How many incomplete transactions we buffer for each connection.
+const MAX_PENDING_TRANSACTIONS: usize = 16;
+
+/// How long clients have to send all the chunks of a transaction.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Splits a transaction into the chunks clients send on a chunked transaction channel, with at
+/// most `chunk_size` bytes of the transaction in each.
+pub fn split_transaction(id: u64, transaction: &[u8], chunk_size: usize) -> Vec<Bytes> {
+    let chunks: Vec<_> = transaction.chunks(chunk_size.max(1)).collect();
+    let total = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut frame = BytesMut::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+            frame.put_u64(id);
+            frame.put_u32(index as u32);
+            frame.put_u32(total);
+            frame.put_slice(chunk);
+            frame.freeze()
+        })
+        .collect()
+}
+
+/// The chunks of a transaction received so far.
+struct Partial {
+    /// When we received the first chunk.
+    started: Instant,
+    /// The number of chunks of the transaction.
+    total: u32,
+    /// The chunks received so far, in order.
+    chunks: Vec<Bytes>,
+}
+
+/// Reassembles the transactions a client sends in chunks (see `split_transaction`), e.g., those
+/// too large to fit in a frame. Clients send the chunks of each transaction in order, but may
+/// interleave those of several transactions. The incomplete transactions we buffer are bounded
+/// (in number and size), and dropped if they are not completed in time.
+pub struct Reassembler {
+    /// How many bytes of incomplete transactions we buffer.
+    max_size: usize,
+    /// How many incomplete transactions we buffer.
+    max_pending: usize,
+    /// How long clients have to complete a transaction.
+    timeout: Duration,
+    /// The incomplete transactions, by id.
+    pending: HashMap<u64, Partial>,
+    /// The bytes of the incomplete transactions.
+    size: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(
+            MAX_BUFFERED_SIZE,
+            MAX_PENDING_TRANSACTIONS,
+            REASSEMBLY_TIMEOUT,
+        )
+    }
+}
+
+impl Reassembler {
+    pub fn new(max_size: usize, max_pending: usize, timeout: Duration) -> Self {
+        Self {
+            max_size,
+            max_pending,
+            timeout,
+            pending: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Adds a chunk, and returns the transaction it completes (if any). Fails if the chunk is
+    /// malformed, out of order, or beyond our bounds: the connection should then be closed.
+    pub fn add(
+        &mut self,
+        frame: Bytes,
+        now: Instant,
+    ) -> Result<Option<Transaction>, WorkerNetError> {
+        if frame.len() < CHUNK_HEADER_SIZE {
+            return Err(WorkerNetError::Decode("chunk", "truncated header".into()));
+        }
+        let id = u64::from_be_bytes(frame[..8].try_into().unwrap());
+        let index = u32::from_be_bytes(frame[8..12].try_into().unwrap());
+        let total = u32::from_be_bytes(frame[12..16].try_into().unwrap());
+        let chunk = frame.slice(CHUNK_HEADER_SIZE..);
+
+        // Drop the transactions the client took too long to complete.
+        let timeout = self.timeout;
+        let mut expired = 0;
+        self.pending.retain(|_, x| {
+            let keep = now.duration_since(x.started) < timeout;
+            if !keep {
+                expired += x.chunks.iter().map(|x| x.len()).sum::<usize>();
+            }
+            keep
+        });
+        self.size -= expired;
+
+        if index >= total {
+            return Err(WorkerNetError::Decode(
+                "chunk",
+                format!("chunk {} of a transaction of {} chunks", index, total),
+            ));
+        }
+
+        // The first chunk of a transaction starts it (transactions of a single chunk need no
+        // reassembly). The next ones must follow in order.
+        if index == 0 && !self.pending.contains_key(&id) {
+            if total == 1 {
+                return Ok(Some(chunk));
+            }
+            if self.pending.len() >= self.max_pending {
+                return Err(WorkerNetError::Rejected(format!(
+                    "Over {} incomplete chunked transactions",
+                    self.max_pending
+                )));
+            }
+            let partial = Partial {
+                started: now,
+                total,
+                chunks: Vec::new(),
+            };
+            self.pending.insert(id, partial);
+        }
+        let partial = match self.pending.get_mut(&id) {
+            Some(partial) if partial.total == total && partial.chunks.len() as u32 == index => {
+                partial
+            }
+            _ => {
+                return Err(WorkerNetError::Decode(
+                    "chunk",
+                    format!("unexpected chunk {} of transaction {}", index, id),
+                ))
+            }
+        };
+        if self.size + chunk.len() > self.max_size {
+            return Err(WorkerNetError::Rejected(format!(
+                "Over {} B of incomplete chunked transactions",
+                self.max_size
+            )));
+        }
+        self.size += chunk.len();
+        partial.chunks.push(chunk);
+        if partial.chunks.len() < partial.total as usize {
+            return Ok(None);
+        }
+
+        // The transaction is complete.
+        let partial = self.pending.remove(&id).unwrap();
+        let size: usize = partial.chunks.iter().map(|x| x.len()).sum();
+        self.size -= size;
+        let mut transaction = BytesMut::with_capacity(size);
+        for chunk in partial.chunks {
+            transaction.put(chunk);
+        }
+        Ok(Some(transaction.freeze()))
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::StampedTransaction;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/coalescer_tests.rs"]
+pub mod coalescer_tests;
+
+/// Sits between the client channels and the `Mempool`: it holds the transactions of a burst for up
+/// to `window` after the first of them (or until it holds `max_size` of them), and then hands them
+/// off together. This smooths bursty traffic into fuller batches (and fewer certificates) at the
+/// cost of some latency. Transactions keep their order and their stamp.
+pub struct Coalescer {
+    /// How long to hold the first transaction of a group.
+    window: Duration,
+    /// How many transactions a group holds at most.
+    max_size: usize,
+    /// Receives the transactions of the clients.
+    rx_transaction: Receiver<StampedTransaction>,
+    /// Hands the groups off to the `Mempool`.
+    tx_mempool: Sender<StampedTransaction>,
+}
+
+impl Coalescer {
+    pub fn spawn(
+        window: Duration,
+        max_size: usize,
+        rx_transaction: Receiver<StampedTransaction>,
+        tx_mempool: Sender<StampedTransaction>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                window,
+                max_size,
+                rx_transaction,
+                tx_mempool,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Hands a group off to the `Mempool`.
+    async fn forward(&mut self, group: &mut Vec<StampedTransaction>) {
+        for transaction in group.drain(..) {
+            self.tx_mempool
+                .send(transaction)
+                .await
+                .expect("Failed to deliver transaction");
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut group = Vec::with_capacity(self.max_size);
+        let timer = sleep(self.window);
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some(transaction) = self.rx_transaction.recv() => {
+                    if group.is_empty() {
+                        timer.as_mut().reset(Instant::now() + self.window);
+                    }
+                    group.push(transaction);
+                    if group.len() >= self.max_size {
+                        self.forward(&mut group).await;
+                    }
+                },
+                () = &mut timer, if !group.is_empty() => self.forward(&mut group).await,
+                else => break,
+            }
+        }
+
+        // Hand off what is left once the clients are gone.
+        self.forward(&mut group).await;
+    }
+}
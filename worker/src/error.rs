@@ -0,0 +1,34 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::io;
+use std::net::SocketAddr;
+use thiserror::Error;
+
+/// Why the worker failed to listen, or closed a connection. The handlers of the worker return
+/// these (boxed, as `MessageHandler` requires), so that callers may tell the failures calling
+/// for a restart (e.g., `Bind`) from the ones that are business as usual (e.g., `PeerClosed`).
+#[derive(Debug, Error)]
+pub enum WorkerNetError {
+    #[error("Failed to bind {0}: {1}")]
+    Bind(SocketAddr, io::Error),
+
+    #[error("Failed to accept connection: {0}")]
+    Accept(io::Error),
+
+    #[error("Invalid banner: {0}")]
+    Handshake(String),
+
+    #[error("Malformed {0}: {1}")]
+    Decode(&'static str, String),
+
+    #[error("{0}")]
+    Rejected(String),
+
+    #[error("Channel to the {0} closed")]
+    ChannelClosed(&'static str),
+
+    #[error("Peer closed the connection: {0}")]
+    PeerClosed(io::Error),
+
+    #[error("Timed out writing {0}")]
+    Timeout(&'static str),
+}
@@ -0,0 +1,83 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::StampedTransaction;
+use futures::stream::{Stream, StreamExt as _};
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+#[cfg(test)]
+#[path = "tests/grpc_tests.rs"]
+pub mod grpc_tests;
+
+/// The messages and services generated from `proto/transactions.proto`.
+pub mod proto {
+    tonic::include_proto!("narwhal");
+}
+
+use proto::transactions_server::{Transactions, TransactionsServer};
+use proto::{Ack, Transaction};
+
+/// Serves the `Transactions` gRPC service, for clients that do not speak our framing protocol. The
+/// transactions go to the same `BatchMaker` as those of the transactions address.
+#[derive(Clone)]
+pub struct TransactionService {
+    tx_batch_maker: Sender<StampedTransaction>,
+}
+
+impl TransactionService {
+    pub fn spawn(address: SocketAddr, tx_batch_maker: Sender<StampedTransaction>) {
+        let service = TransactionsServer::new(Self { tx_batch_maker });
+        tokio::spawn(async move {
+            info!("Serving gRPC clients on {}", address);
+            if let Err(e) = Server::builder().add_service(service).serve(address).await {
+                warn!("Failed to serve gRPC clients on {}: {}", address, e);
+            }
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl Transactions for TransactionService {
+    /// Hands the transaction to the `BatchMaker`, or fails at once if it is lagging behind (the
+    /// client may retry later).
+    async fn submit_transaction(
+        &self,
+        request: Request<Transaction>,
+    ) -> Result<Response<Ack>, Status> {
+        let transaction = request.into_inner().transaction;
+        match self.tx_batch_maker.try_send((Instant::now(), transaction)) {
+            Ok(()) => Ok(Response::new(Ack {})),
+            Err(TrySendError::Full(_)) => Err(Status::resource_exhausted("Worker overloaded")),
+            Err(TrySendError::Closed(_)) => Err(Status::unavailable("Worker shutting down")),
+        }
+    }
+
+    type SubmitTransactionStreamStream = Pin<Box<dyn Stream<Item = Result<Ack, Status>> + Send>>;
+
+    /// Hands the transactions to the `BatchMaker` one by one. We only read the next transaction
+    /// once the `BatchMaker` accepted the previous one, so the flow control of the stream pushes
+    /// back on the client.
+    async fn submit_transaction_stream(
+        &self,
+        request: Request<Streaming<Transaction>>,
+    ) -> Result<Response<Self::SubmitTransactionStreamStream>, Status> {
+        let tx_batch_maker = self.tx_batch_maker.clone();
+        let acks = request.into_inner().then(move |transaction| {
+            let tx_batch_maker = tx_batch_maker.clone();
+            async move {
+                let transaction = transaction?.transaction;
+                tx_batch_maker
+                    .send((Instant::now(), transaction))
+                    .await
+                    .map_err(|_| Status::unavailable("Worker shutting down"))?;
+                Ok(Ack {})
+            }
+        });
+        Ok(Response::new(Box::pin(acks)))
+    }
+}
@@ -1,9 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::budgets::RequestBudgets;
+use crate::processor::SerializedBatchMessage;
 use bytes::Bytes;
 use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
 use log::{error, warn};
-use network::SimpleSender;
+use network::{Compression, SharedAuthenticator, SharedTranscoder, SimpleSender};
+use std::net::IpAddr;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
@@ -11,6 +14,10 @@ use tokio::sync::mpsc::Receiver;
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
+/// A request for batches: their digests, the worker asking for them, and the IP address the
+/// request came from (if known), to charge the batches we send to its budget.
+pub type BatchRequest = (Vec<Digest>, PublicKey, Option<IpAddr>);
+
 /// A task dedicated to help other authorities by replying to their batch requests.
 pub struct Helper {
     /// The id of this worker.
@@ -18,19 +25,26 @@ pub struct Helper {
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
-    store: Store,
+    store: Store<Digest, SerializedBatchMessage>,
     /// Input channel to receive batch requests.
-    rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+    rx_request: Receiver<BatchRequest>,
+    /// The budgets of the peers sending us batch requests.
+    budgets: RequestBudgets,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
 }
 
 impl Helper {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         id: WorkerId,
         committee: Committee,
-        store: Store,
-        rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+        store: Store<Digest, SerializedBatchMessage>,
+        rx_request: Receiver<BatchRequest>,
+        budgets: RequestBudgets,
+        compression: Option<Compression>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
     ) {
         tokio::spawn(async move {
             Self {
@@ -38,7 +52,11 @@ impl Helper {
                 committee,
                 store,
                 rx_request,
-                network: SimpleSender::new(),
+                budgets,
+                network: compression
+                    .map_or_else(SimpleSender::new, SimpleSender::with_compression)
+                    .with_transcoder(transcoder)
+                    .with_authenticator(authenticator),
             }
             .run()
             .await;
@@ -46,9 +64,7 @@ impl Helper {
     }
 
     async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_request.recv().await {
-            // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
-
+        while let Some((digests, origin, peer)) = self.rx_request.recv().await {
             // get the requestors address.
             let address = match self.committee.worker(&origin, &self.id) {
                 Ok(x) => x.worker_to_worker,
@@ -60,8 +76,13 @@ impl Helper {
 
             // Reply to the request (the best we can).
             for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => self.network.send(address, Bytes::from(data)).await,
+                match self.store.read(&digest).await {
+                    Ok(Some(data)) => {
+                        if let Some(peer) = peer {
+                            self.budgets.charge(peer, data.len());
+                        }
+                        self.network.send(address, Bytes::from(data)).await
+                    }
                     Ok(None) => (),
                     Err(e) => error!("{}", e),
                 }
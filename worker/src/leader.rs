@@ -0,0 +1,42 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::worker::Round;
+use config::Committee;
+use consensus::{LeaderElection, LeaderExclusions};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Tells clients which authority leads the next leader round, so that they can route their
+/// transactions to its workers. We learn the rounds committed by consensus from the cleanup
+/// messages of our primary, so our answer may lag behind consensus by a leader round or two. We
+/// do not know the leader exclusions of consensus either: our answer assumes there are none.
+/// Clones share the same round.
+#[derive(Clone)]
+pub struct NextLeader {
+    /// The committee information.
+    committee: Committee,
+    /// The highest round our primary told us about.
+    round: Arc<AtomicU64>,
+}
+
+impl NextLeader {
+    pub fn new(committee: Committee) -> Self {
+        Self {
+            committee,
+            round: Arc::default(),
+        }
+    }
+
+    /// Records a round our primary told us about.
+    pub fn advance(&self, round: Round) {
+        self.round.fetch_max(round, Ordering::Relaxed);
+    }
+
+    /// Returns the leader of the first leader round above the highest round we know of.
+    pub fn get(&self) -> LeaderElection {
+        consensus::next_leader(
+            &self.committee,
+            self.round.load(Ordering::Relaxed),
+            &LeaderExclusions::default(),
+        )
+    }
+}
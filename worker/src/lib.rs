@@ -1,15 +1,59 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod authentication;
+mod backlog;
 mod batch_maker;
+mod budgets;
+mod certifications;
+mod chunks;
+mod coalescer;
+mod error;
+mod grpc;
 mod helper;
+mod leader;
+mod membership;
+mod mempool;
+mod metrics;
 mod primary_connector;
+mod prioritizer;
 mod processor;
+mod proofs;
 mod quorum_waiter;
+mod receipts;
+mod request_log;
+mod stored_batches;
 mod synchronizer;
+mod wire;
 mod worker;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
+#[cfg(test)]
+#[path = "tests/pipeline_tests.rs"]
+mod pipeline_tests;
+
+pub use crate::authentication::CommitteeAuthenticator;
+pub use crate::backlog::{Backlog, BacklogSnapshot};
+pub use crate::budgets::{PeerRequests, RequestBudgets};
+pub use crate::certifications::{Certifications, CertifiedBatch};
+pub use crate::chunks::{split_transaction, Reassembler, CHUNK_HEADER_SIZE};
+pub use crate::error::WorkerNetError;
+pub use crate::grpc::proto;
+pub use crate::leader::NextLeader;
+pub use crate::membership::Membership;
+pub use crate::mempool::TransactionParser;
+pub use crate::metrics::WorkerNetMetrics;
+pub use crate::proofs::{merkle_root, BatchDigester, InclusionProof, MerkleProof, ProofError};
+pub use crate::receipts::{Receipt, Receipts, TAG_SIZE};
+pub use crate::request_log::RequestLog;
+pub use crate::stored_batches::StoredBatches;
+pub use crate::wire::{BincodeCodec, Codec, ProtoCodec, TransactionTranscoder, WorkerTranscoder};
 pub use crate::worker::Worker;
 pub use crate::worker::WorkerMessage;
+pub use crate::worker::WorkerOptions;
+pub use crate::worker::{
+    BATCH_PROOF_BANNER, CHUNKED_TRANSACTION_BANNER, DIGEST_MISMATCH, LEADER_BANNER,
+    MULTIPLEX_BANNER, RECEIPT_BANNER, TAGGED_TRANSACTION_BANNER, THROTTLED, TRANSACTION_BANNER,
+    TRANSACTION_STREAM, WINDOWED_TRANSACTION_BANNER, WORKER_STREAM,
+};
@@ -0,0 +1,75 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The IP addresses of the authorities, as of the last reconfiguration.
+#[derive(Default)]
+struct Addresses {
+    /// The addresses of the active committee.
+    active: HashSet<IpAddr>,
+    /// The addresses of the authorities that left the committee.
+    former: HashSet<IpAddr>,
+}
+
+/// The IP addresses of the active committee, from which we accept worker messages. Reconfiguring
+/// it (when the committee changes) lets in the authorities that joined, and drains the connections
+/// of those that left (see `departed`). Clones share the same addresses.
+#[derive(Clone)]
+pub struct Membership {
+    tx: Arc<watch::Sender<Arc<Addresses>>>,
+    rx: watch::Receiver<Arc<Addresses>>,
+}
+
+impl Membership {
+    pub fn new(ips: HashSet<IpAddr>) -> Self {
+        let addresses = Addresses {
+            active: ips,
+            former: HashSet::new(),
+        };
+        let (tx, rx) = watch::channel(Arc::new(addresses));
+        Self {
+            tx: Arc::new(tx),
+            rx,
+        }
+    }
+
+    /// Whether the address belongs to the active committee.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.rx.borrow().active.contains(ip)
+    }
+
+    /// Replaces the addresses of the active committee.
+    pub fn reconfigure(&self, ips: HashSet<IpAddr>) {
+        let previous = self.rx.borrow().clone();
+        let former = previous
+            .former
+            .iter()
+            .chain(previous.active.iter())
+            .filter(|x| !ips.contains(*x))
+            .copied()
+            .collect();
+        let addresses = Addresses {
+            active: ips,
+            former,
+        };
+
+        // We hold a receiver, so sending never fails.
+        let _ = self.tx.send(Arc::new(addresses));
+    }
+
+    /// Resolves once the address belongs to an authority that left the committee. It never
+    /// resolves for addresses that never belonged to the committee.
+    pub async fn departed(&self, ip: IpAddr) {
+        let mut rx = self.rx.clone();
+        loop {
+            if rx.borrow().former.contains(&ip) {
+                return;
+            }
+            if rx.changed().await.is_err() {
+                return futures::future::pending().await;
+            }
+        }
+    }
+}
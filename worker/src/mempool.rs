@@ -0,0 +1,184 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::{StampedTransaction, Transaction};
+use bytes::Bytes;
+use config::{MempoolParameters, ReplacementPolicy};
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{interval, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/mempool_tests.rs"]
+pub mod mempool_tests;
+
+/// Reads the sender and the nonce of application transactions. The worker only looks at the
+/// transactions through this hook: it never interprets their bytes otherwise.
+pub trait TransactionParser: Send + Sync + 'static {
+    /// Returns the sender and the nonce of a transaction. Transactions without them (`None`) are
+    /// not ordered: they go straight to the batch maker.
+    fn parse(&self, transaction: &Transaction) -> Option<(Bytes, u64)>;
+
+    /// Returns the next nonce of a sender we hold no record of. By default, the first nonce we
+    /// receive from the sender is the next one.
+    fn initial_nonce(&self, _sender: &Bytes) -> Option<u64> {
+        None
+    }
+}
+
+/// The transactions of a sender waiting for their turn.
+struct SenderQueue {
+    /// The nonce of the next transaction to release.
+    next: u64,
+    /// The transactions received ahead of their turn, by nonce.
+    pending: BTreeMap<u64, StampedTransaction>,
+    /// When we last heard from the sender.
+    last_seen: Instant,
+}
+
+/// Sits in front of the `BatchMaker` and hands it the transactions of each sender in nonce order.
+/// Transactions arriving ahead of their turn are held (within bounds, per sender and overall)
+/// until the gap before them is filled, or dropped once they are older than the TTL. Without a
+/// parser, every transaction goes through as received.
+pub struct Mempool {
+    /// Reads the sender and nonce of the transactions (if set).
+    parser: Option<Arc<dyn TransactionParser>>,
+    /// The bounds of the mempool.
+    parameters: MempoolParameters,
+    /// Receives the transactions of the clients.
+    rx_transaction: Receiver<StampedTransaction>,
+    /// Releases the transactions to the `BatchMaker`.
+    tx_batch_maker: Sender<StampedTransaction>,
+    /// The senders we heard from recently.
+    senders: HashMap<Bytes, SenderQueue>,
+    /// The number of transactions held (for all senders).
+    pending: usize,
+}
+
+impl Mempool {
+    pub fn spawn(
+        parser: Option<Arc<dyn TransactionParser>>,
+        parameters: MempoolParameters,
+        rx_transaction: Receiver<StampedTransaction>,
+        tx_batch_maker: Sender<StampedTransaction>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                parser,
+                parameters,
+                rx_transaction,
+                tx_batch_maker,
+                senders: HashMap::new(),
+                pending: 0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Main loop receiving the transactions, and periodically evicting the stale ones.
+    async fn run(&mut self) {
+        let period = Duration::from_millis((self.parameters.ttl / 2).max(1));
+        let mut timer = interval(period);
+        loop {
+            tokio::select! {
+                Some(transaction) = self.rx_transaction.recv() => {
+                    let parsed = self.parser.as_ref().and_then(|x| x.parse(&transaction.1));
+                    match parsed {
+                        Some((sender, nonce)) => self.order(sender, nonce, transaction).await,
+                        None => self.release(transaction).await,
+                    }
+                },
+                _ = timer.tick() => self.evict(),
+            }
+        }
+    }
+
+    /// Hands a transaction to the `BatchMaker`.
+    async fn release(&self, transaction: StampedTransaction) {
+        self.tx_batch_maker
+            .send(transaction)
+            .await
+            .expect("Failed to deliver transaction");
+    }
+
+    /// Holds a transaction until its turn, then releases all the transactions of its sender that
+    /// are next in line. A transaction with the sender and nonce of a held one (but different
+    /// bytes) replaces it or is dropped, depending on the replacement policy. Once released,
+    /// transactions are never replaced.
+    async fn order(&mut self, sender: Bytes, nonce: u64, transaction: StampedTransaction) {
+        let initial = self.parser.as_ref().and_then(|x| x.initial_nonce(&sender));
+        let queue = self
+            .senders
+            .entry(sender.clone())
+            .or_insert_with(|| SenderQueue {
+                next: initial.unwrap_or(nonce),
+                pending: BTreeMap::new(),
+                last_seen: transaction.0,
+            });
+        queue.last_seen = transaction.0;
+
+        if nonce < queue.next {
+            debug!(
+                "Dropping transaction {} of {:?}: already released",
+                nonce, sender
+            );
+            return;
+        }
+        if let Some(held) = queue.pending.get_mut(&nonce) {
+            if held.1 != transaction.1
+                && self.parameters.replacement == ReplacementPolicy::KeepLatest
+            {
+                debug!("Replacing transaction {} of {:?}", nonce, sender);
+                *held = transaction;
+            }
+            return;
+        }
+        if nonce - queue.next >= self.parameters.max_pending_per_sender as u64 {
+            warn!(
+                "Dropping transaction {} of {:?}: too far ahead of {}",
+                nonce, sender, queue.next
+            );
+            return;
+        }
+        if self.pending >= self.parameters.max_pending && nonce != queue.next {
+            warn!(
+                "Dropping transaction {} of {:?}: mempool full",
+                nonce, sender
+            );
+            return;
+        }
+        queue.pending.insert(nonce, transaction);
+        self.pending += 1;
+
+        // Release the transactions that are next in line.
+        let mut released = Vec::new();
+        while let Some(transaction) = queue.pending.remove(&queue.next) {
+            released.push(transaction);
+            queue.next += 1;
+        }
+        self.pending -= released.len();
+        for transaction in released {
+            self.release(transaction).await;
+        }
+    }
+
+    /// Drops the transactions held longer than the TTL, and forgets the senders that have been idle
+    /// for as long.
+    fn evict(&mut self) {
+        let ttl = Duration::from_millis(self.parameters.ttl);
+        let mut evicted = 0;
+        self.senders.retain(|_, queue| {
+            let before = queue.pending.len();
+            queue
+                .pending
+                .retain(|_, (received, _)| received.elapsed() < ttl);
+            evicted += before - queue.pending.len();
+            !queue.pending.is_empty() || queue.last_seen.elapsed() < ttl
+        });
+        if evicted > 0 {
+            debug!("Evicted {} stale transactions", evicted);
+            self.pending -= evicted;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::WorkerNetError;
+use crate::request_log::Routing;
+use crate::worker::WorkerChannelType;
+use config::WorkerId;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/metrics_tests.rs"]
+pub mod metrics_tests;
+
+/// The upper bounds (in bytes) of the buckets of the transaction size histogram.
+const TRANSACTION_SIZE_BUCKETS: [usize; 8] =
+    [64, 256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576];
+
+/// The counters of `WorkerNetMetrics`.
+#[derive(Default)]
+struct Counters {
+    /// The connections accepted, by receiver.
+    connections: BTreeMap<&'static str, u64>,
+    /// The frames received, by channel and outcome.
+    frames: BTreeMap<(&'static str, &'static str), u64>,
+    /// The frames rejected or closing their connection, by channel.
+    dropped: BTreeMap<&'static str, u64>,
+    /// How many client transactions fell in each bucket of `TRANSACTION_SIZE_BUCKETS` (the last
+    /// one counts the larger transactions).
+    transaction_sizes: [u64; TRANSACTION_SIZE_BUCKETS.len() + 1],
+    /// The total size of the client transactions.
+    transaction_bytes: u64,
+}
+
+/// Counts the connections and frames the handlers of the worker receive, and renders them in the
+/// Prometheus text exposition format (e.g., to serve them from an HTTP endpoint). The request log
+/// records every frame here, whether it samples it or not. Clones share the same counters.
+#[derive(Clone, Default)]
+pub struct WorkerNetMetrics {
+    /// The worker whose metrics these are (if known), which labels every sample.
+    worker: Option<WorkerId>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl WorkerNetMetrics {
+    pub fn new(worker: Option<WorkerId>) -> Self {
+        Self {
+            worker,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Records that a receiver accepted a connection.
+    pub(crate) fn connection(&self, receiver: &'static str) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .connections
+            .entry(receiver)
+            .or_insert(0) += 1;
+    }
+
+    /// Records how a frame was handled. Frames forwarded to the batch maker carry a transaction.
+    pub(crate) fn frame(
+        &self,
+        channel: &WorkerChannelType,
+        size: usize,
+        result: &Result<Routing, WorkerNetError>,
+    ) {
+        let channel = channel.label();
+        let outcome = match result {
+            Ok(Routing::Forwarded(x) | Routing::Served(x) | Routing::Rejected(x)) => *x,
+            Err(_) => "closed",
+        };
+        let mut counters = self.counters.lock().unwrap();
+        *counters.frames.entry((channel, outcome)).or_insert(0) += 1;
+        match result {
+            Ok(Routing::Rejected(_)) | Err(_) => {
+                *counters.dropped.entry(channel).or_insert(0) += 1;
+            }
+            Ok(Routing::Forwarded("batch maker")) => {
+                let bucket = TRANSACTION_SIZE_BUCKETS
+                    .iter()
+                    .position(|x| size <= *x)
+                    .unwrap_or(TRANSACTION_SIZE_BUCKETS.len());
+                counters.transaction_sizes[bucket] += 1;
+                counters.transaction_bytes += size as u64;
+            }
+            Ok(_) => (),
+        }
+    }
+
+    /// Renders the labels of a sample, preceded by the worker label (if any).
+    fn labels(&self, labels: &[(&str, &str)]) -> String {
+        let worker = self.worker.map(|x| x.to_string());
+        let labels: Vec<_> = worker
+            .iter()
+            .map(|x| ("worker", x.as_str()))
+            .chain(labels.iter().copied())
+            .map(|(key, value)| format!("{}=\"{}\"", key, value))
+            .collect();
+        match labels.is_empty() {
+            true => String::new(),
+            false => format!("{{{}}}", labels.join(",")),
+        }
+    }
+
+    /// Renders the current counters in the Prometheus text exposition format.
+    pub fn metrics_text(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut text = String::new();
+
+        family(
+            &mut text,
+            "worker_connections_total",
+            "counter",
+            "Connections accepted, by receiver.",
+        );
+        for (receiver, count) in &counters.connections {
+            let labels = self.labels(&[("receiver", *receiver)]);
+            text.push_str(&format!("worker_connections_total{} {}\n", labels, count));
+        }
+
+        family(
+            &mut text,
+            "worker_frames_total",
+            "counter",
+            "Frames received, by channel and outcome.",
+        );
+        for ((channel, outcome), count) in &counters.frames {
+            let labels = self.labels(&[("channel", *channel), ("outcome", *outcome)]);
+            text.push_str(&format!("worker_frames_total{} {}\n", labels, count));
+        }
+
+        family(
+            &mut text,
+            "worker_dropped_frames_total",
+            "counter",
+            "Frames rejected or closing their connection, by channel.",
+        );
+        for (channel, count) in &counters.dropped {
+            let labels = self.labels(&[("channel", *channel)]);
+            text.push_str(&format!(
+                "worker_dropped_frames_total{} {}\n",
+                labels, count
+            ));
+        }
+
+        family(
+            &mut text,
+            "worker_transaction_size_bytes",
+            "histogram",
+            "Size of the client transactions.",
+        );
+        let mut cumulative = 0;
+        for (i, count) in counters.transaction_sizes.iter().enumerate() {
+            cumulative += count;
+            let bound = TRANSACTION_SIZE_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), |x| x.to_string());
+            let labels = self.labels(&[("le", bound.as_str())]);
+            text.push_str(&format!(
+                "worker_transaction_size_bytes_bucket{} {}\n",
+                labels, cumulative
+            ));
+        }
+        let labels = self.labels(&[]);
+        text.push_str(&format!(
+            "worker_transaction_size_bytes_sum{} {}\n",
+            labels, counters.transaction_bytes
+        ));
+        text.push_str(&format!(
+            "worker_transaction_size_bytes_count{} {}\n",
+            labels, cumulative
+        ));
+        text
+    }
+}
+
+/// Renders the header of a metric family.
+fn family(text: &mut String, name: &str, kind: &str, help: &str) {
+    text.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, kind
+    ));
+}
@@ -0,0 +1,75 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::helper::BatchRequest;
+use crate::processor::SerializedBatchMessage;
+use std::collections::VecDeque;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/prioritizer_tests.rs"]
+pub mod prioritizer_tests;
+
+/// How many messages of each kind we read ahead of their consumer.
+pub const READ_AHEAD: usize = 100;
+
+/// Sits between the handler of the messages of other workers and their consumers. It reads ahead
+/// of the consumers into a small buffer, so that connections keep being read while the
+/// `Processor` lags behind (e.g., during a flood of batches while peers synchronize). Batch
+/// requests are forwarded ahead of the batches pending with them, and never wait for the
+/// `Processor`. Each kind of message keeps its order.
+pub struct Prioritizer {
+    /// Receives the batch requests of the other workers.
+    rx_request: Receiver<BatchRequest>,
+    /// Receives the batches of the other workers.
+    rx_batch: Receiver<SerializedBatchMessage>,
+    /// Forwards the batch requests to the `Helper`.
+    tx_helper: Sender<BatchRequest>,
+    /// Forwards the batches to the `Processor`.
+    tx_processor: Sender<SerializedBatchMessage>,
+}
+
+impl Prioritizer {
+    pub fn spawn(
+        rx_request: Receiver<BatchRequest>,
+        rx_batch: Receiver<SerializedBatchMessage>,
+        tx_helper: Sender<BatchRequest>,
+        tx_processor: Sender<SerializedBatchMessage>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                rx_request,
+                rx_batch,
+                tx_helper,
+                tx_processor,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        let mut requests = VecDeque::with_capacity(READ_AHEAD);
+        let mut batches = VecDeque::with_capacity(READ_AHEAD);
+        loop {
+            tokio::select! {
+                biased;
+
+                // Forward the pending messages, batch requests first.
+                Ok(permit) = self.tx_helper.reserve(), if !requests.is_empty() => {
+                    permit.send(requests.pop_front().unwrap());
+                },
+                Ok(permit) = self.tx_processor.reserve(), if !batches.is_empty() => {
+                    permit.send(batches.pop_front().unwrap());
+                },
+
+                // Read ahead while we have room.
+                Some(request) = self.rx_request.recv(), if requests.len() < READ_AHEAD => {
+                    requests.push_back(request);
+                },
+                Some(batch) = self.rx_batch.recv(), if batches.len() < READ_AHEAD => {
+                    batches.push_back(batch);
+                },
+                else => break,
+            }
+        }
+    }
+}
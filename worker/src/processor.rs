@@ -1,13 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::proofs::BatchDigester;
+use crate::stored_batches::StoredBatches;
 use crate::worker::SerializedBatchDigestMessage;
 use config::WorkerId;
 use crypto::Digest;
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use log::error;
 use primary::WorkerPrimaryMessage;
-use std::convert::TryInto;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{debug, debug_span};
 
 #[cfg(test)]
 #[path = "tests/processor_tests.rs"]
@@ -24,21 +25,42 @@ impl Processor {
         // Our worker's id.
         id: WorkerId,
         // The persistent storage.
-        mut store: Store,
+        mut store: Store<Digest, SerializedBatchMessage>,
         // Input channel to receive batches.
         mut rx_batch: Receiver<SerializedBatchMessage>,
         // Output channel to send out batches' digests.
         tx_digest: Sender<SerializedBatchDigestMessage>,
         // Whether we are processing our own batches or the batches of other nodes.
         own_digest: bool,
+        // How to digest the batches.
+        digester: BatchDigester,
+        // Remembers the batches we stored.
+        stored_batches: StoredBatches,
     ) {
         tokio::spawn(async move {
             while let Some(batch) = rx_batch.recv().await {
                 // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+                let digest = match digester.digest_serialized(&batch) {
+                    Some(digest) => digest,
+                    None => {
+                        error!("Failed to deserialize batch");
+                        continue;
+                    }
+                };
+                let span = debug_span!("batch", digest = ?digest);
 
-                // Store the batch.
-                store.write(digest.to_vec(), batch).await;
+                // Store the batch, and wait until it is written before announcing its digest.
+                let written = store
+                    .write_batch(vec![(digest.clone(), batch)])
+                    .await
+                    .await
+                    .expect("Failed to receive reply from store");
+                if let Err(e) = written {
+                    error!("{}", e);
+                    continue;
+                }
+                debug!(parent: &span, "Stored batch");
+                stored_batches.insert(digest.clone());
 
                 // Deliver the batch's digest.
                 let message = match own_digest {
@@ -51,6 +73,7 @@ impl Processor {
                     .send(message)
                     .await
                     .expect("Failed to send digest");
+                debug!(parent: &span, own = own_digest, "Sent batch digest to the primary");
             }
         });
     }
@@ -0,0 +1,215 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::worker::WorkerMessage;
+use config::Committee;
+use crypto::{Digest, Hash as _, Hasher};
+use primary::Certificate;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/proofs_tests.rs"]
+pub mod proofs_tests;
+
+/// Domain separation tags of the nodes of Merkle trees: a transaction can never be mistaken for
+/// an inner node or a padding leaf (and conversely).
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+const PADDING_TAG: u8 = 2;
+
+/// Hashes a transaction into a leaf.
+fn leaf(transaction: &[u8]) -> Digest {
+    let mut hasher = Hasher::new();
+    hasher.update([LEAF_TAG]);
+    hasher.update(transaction);
+    hasher.finalize()
+}
+
+/// Hashes two sibling nodes into their parent.
+fn node(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Hasher::new();
+    hasher.update([NODE_TAG]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+/// The leaf filling the tree up to a power of two.
+fn padding() -> Digest {
+    let mut hasher = Hasher::new();
+    hasher.update([PADDING_TAG]);
+    hasher.finalize()
+}
+
+/// Returns the layers of the Merkle tree of a batch, from the leaves (the transactions in batch
+/// order, padded up to the next power of two) to the root.
+fn layers(batch: &[Transaction]) -> Vec<Vec<Digest>> {
+    let mut leaves: Vec<_> = batch.iter().map(|x| leaf(x)).collect();
+    leaves.resize(batch.len().next_power_of_two(), padding());
+    let mut layers = vec![leaves];
+    while let Some(layer) = layers.last().filter(|x| x.len() > 1) {
+        let parents = layer.chunks(2).map(|x| node(&x[0], &x[1])).collect();
+        layers.push(parents);
+    }
+    layers
+}
+
+/// Returns the root of the Merkle tree of the transactions of a batch.
+pub fn merkle_root(batch: &[Transaction]) -> Digest {
+    layers(batch)
+        .pop()
+        .and_then(|mut x| x.pop())
+        .expect("Merkle trees have a root")
+}
+
+/// How the batches are digested: the hash of their serialization, or (from protocol version
+/// `MERKLE_BATCHES_VERSION`) the Merkle root of their transactions. All workers of the committee
+/// must agree on it, otherwise they disagree on the digests of batches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BatchDigester {
+    #[default]
+    Serialized,
+    Merkle,
+}
+
+impl BatchDigester {
+    /// The digester of the protocol version of the committee.
+    pub fn new(committee: &Committee) -> Self {
+        match committee.merkle_batches() {
+            true => Self::Merkle,
+            false => Self::Serialized,
+        }
+    }
+
+    /// Digests a batch, given its serialization (as a `WorkerMessage::Batch`).
+    pub fn digest(&self, batch: &[Transaction], serialized: &[u8]) -> Digest {
+        match self {
+            Self::Serialized => crypto::hash(serialized),
+            Self::Merkle => merkle_root(batch),
+        }
+    }
+
+    /// Digests a serialized `WorkerMessage::Batch`. Returns `None` if it is not one.
+    pub fn digest_serialized(&self, serialized: &[u8]) -> Option<Digest> {
+        match self {
+            Self::Serialized => Some(crypto::hash(serialized)),
+            Self::Merkle => match bincode::deserialize(serialized) {
+                Ok(WorkerMessage::Batch(batch)) => Some(merkle_root(&batch)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The path from a transaction to the Merkle root of its batch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// The index of the transaction in the batch.
+    pub index: u64,
+    /// The number of transactions of the batch.
+    pub transactions: u64,
+    /// The siblings of the nodes on the path from the transaction to the root, bottom up.
+    pub path: Vec<Digest>,
+}
+
+impl MerkleProof {
+    /// Makes the proof of the transaction at the specified index of the batch, if any.
+    pub fn new(batch: &[Transaction], index: usize) -> Option<Self> {
+        if index >= batch.len() {
+            return None;
+        }
+        let layers = layers(batch);
+        let path = layers[..layers.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(depth, layer)| layer[(index >> depth) ^ 1].clone())
+            .collect();
+        Some(Self {
+            index: index as u64,
+            transactions: batch.len() as u64,
+            path,
+        })
+    }
+
+    /// Returns the root the path leads to from the transaction, or `None` if the path does not
+    /// match the shape of the tree.
+    pub fn root(&self, transaction: &[u8]) -> Option<Digest> {
+        let depth = self
+            .transactions
+            .checked_next_power_of_two()?
+            .trailing_zeros();
+        if self.index >= self.transactions || self.path.len() != depth as usize {
+            return None;
+        }
+        let mut digest = leaf(transaction);
+        for (i, sibling) in self.path.iter().enumerate() {
+            digest = match (self.index >> i) & 1 {
+                0 => node(&digest, sibling),
+                _ => node(sibling, &digest),
+            };
+        }
+        Some(digest)
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ProofError {
+    #[error("The committee does not digest batches into Merkle roots")]
+    NotMerkleBatches,
+
+    #[error("The path does not match the shape of a batch of {0} transactions")]
+    InvalidPath(u64),
+
+    #[error("The path leads to {0}, not to batch {1}")]
+    RootMismatch(Digest, Digest),
+
+    #[error("Certificate {0} does not reference batch {1}")]
+    BatchNotCertified(Digest, Digest),
+
+    #[error("Invalid certificate {0}: {1}")]
+    InvalidCertificate(Digest, String),
+
+    #[error("Header {0} does not hash to its id")]
+    InvalidHeader(Digest),
+
+    #[error("The header makes certificate {0}, not {1}")]
+    CertificateMismatch(Digest, Digest),
+}
+
+/// Proves to a light client that a transaction is part of a certified batch: the Merkle path from
+/// the transaction to the batch digest, and the certificate whose payload references the batch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub transaction: Transaction,
+    /// The digest of the batch (the Merkle root of its transactions).
+    pub batch: Digest,
+    pub proof: MerkleProof,
+    /// A certificate referencing the batch.
+    pub certificate: Certificate,
+}
+
+impl InclusionProof {
+    /// Checks the proof given only the committee: the path leads from the transaction to the
+    /// batch digest, which is in the payload of a certificate signed by a quorum of the committee.
+    pub fn verify(&self, committee: &Committee) -> Result<(), ProofError> {
+        if !committee.merkle_batches() {
+            return Err(ProofError::NotMerkleBatches);
+        }
+        let root = self
+            .proof
+            .root(&self.transaction)
+            .ok_or(ProofError::InvalidPath(self.proof.transactions))?;
+        if root != self.batch {
+            return Err(ProofError::RootMismatch(root, self.batch.clone()));
+        }
+        if !self.certificate.header.payload.contains_key(&self.batch) {
+            return Err(ProofError::BatchNotCertified(
+                self.certificate.digest(),
+                self.batch.clone(),
+            ));
+        }
+        self.certificate
+            .verify(committee)
+            .map_err(|e| ProofError::InvalidCertificate(self.certificate.digest(), e.to_string()))
+    }
+}
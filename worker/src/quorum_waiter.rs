@@ -1,11 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backlog::Backlog;
 use crate::processor::SerializedBatchMessage;
+use crate::worker::DIGEST_MISMATCH;
 use config::{Committee, Stake};
 use crypto::PublicKey;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use network::CancelHandler;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{debug, Span};
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -17,6 +20,8 @@ pub struct QuorumWaiterMessage {
     pub batch: SerializedBatchMessage,
     /// The cancel handlers to receive the acknowledgements of our broadcast.
     pub handlers: Vec<(PublicKey, CancelHandler)>,
+    /// The span of the batch.
+    pub span: Span,
 }
 
 /// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
@@ -29,6 +34,8 @@ pub struct QuorumWaiter {
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver batches for which we have enough acknowledgements.
     tx_batch: Sender<SerializedBatchMessage>,
+    /// The backlog of the worker.
+    backlog: Backlog,
 }
 
 impl QuorumWaiter {
@@ -38,6 +45,7 @@ impl QuorumWaiter {
         stake: Stake,
         rx_message: Receiver<QuorumWaiterMessage>,
         tx_batch: Sender<Vec<u8>>,
+        backlog: Backlog,
     ) {
         tokio::spawn(async move {
             Self {
@@ -45,21 +53,30 @@ impl QuorumWaiter {
                 stake,
                 rx_message,
                 tx_batch,
+                backlog,
             }
             .run()
             .await;
         });
     }
 
-    /// Helper function. It waits for a future to complete and then delivers a value.
+    /// Helper function. It waits for a future to complete and then delivers a value. Peers that
+    /// found our batch corrupted do not count.
     async fn waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
-        let _ = wait_for.await;
-        deliver
+        match wait_for.await {
+            Ok(reply) if reply == DIGEST_MISMATCH => 0,
+            _ => deliver,
+        }
     }
 
     /// Main loop.
     async fn run(&mut self) {
-        while let Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv().await {
+        while let Some(QuorumWaiterMessage {
+            batch,
+            handlers,
+            span,
+        }) = self.rx_message.recv().await
+        {
             let mut wait_for_quorum: FuturesUnordered<_> = handlers
                 .into_iter()
                 .map(|(name, handler)| {
@@ -70,18 +87,24 @@ impl QuorumWaiter {
 
             // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
             // delivered and we send its digest to the primary (that will include it into
-            // the dag). This should reduce the amount of synching.
+            // the dag). This should reduce the amount of synching. A single authority is a
+            // quorum on its own: there is nobody to wait for.
+            let threshold = self.committee.quorum_threshold();
             let mut total_stake = self.stake;
-            while let Some(stake) = wait_for_quorum.next().await {
-                total_stake += stake;
-                if total_stake >= self.committee.quorum_threshold() {
-                    self.tx_batch
-                        .send(batch)
-                        .await
-                        .expect("Failed to deliver batch");
-                    break;
+            while total_stake < threshold {
+                match wait_for_quorum.next().await {
+                    Some(stake) => total_stake += stake,
+                    None => break,
                 }
             }
+            if total_stake >= threshold {
+                debug!(parent: &span, stake = total_stake, "Batch reached a quorum");
+                self.tx_batch
+                    .send(batch)
+                    .await
+                    .expect("Failed to deliver batch");
+            }
+            self.backlog.acknowledged();
         }
     }
 }
@@ -0,0 +1,221 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::worker::Round;
+use bytes::Bytes;
+use crypto::Digest;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/receipts_tests.rs"]
+pub mod receipts_tests;
+
+/// The size of the tag prefixing tagged transactions: the key of the receipt channel of the client
+/// followed by its correlation id (both big-endian `u64`).
+pub const TAG_SIZE: usize = 16;
+
+/// How many receipts we buffer for each channel. Clients that do not read theirs miss the receipts
+/// that do not fit.
+const CHANNEL_CAPACITY: usize = 1_000;
+
+/// How many tagged transactions we track at most. Beyond it, we forget the tags of the oldest
+/// sealed batches (e.g., batches that were never committed).
+const MAX_PENDING_TAGS: usize = 100_000;
+
+/// How long to wait before subscribing again after losing the commit stream (in ms).
+const RESUBSCRIBE_DELAY: u64 = 500;
+
+/// The key of a receipt channel and a correlation id chosen by its client.
+type Tag = (u64, u64);
+
+/// Tells a client that consensus committed one of its tagged transactions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The correlation id the client tagged the transaction with.
+    pub id: u64,
+    /// The digest of the batch holding the transaction.
+    pub batch: Digest,
+    /// The round of the certificate that committed the batch.
+    pub round: Round,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// The key of the next receipt channel.
+    next_key: u64,
+    /// The open receipt channels, by key.
+    channels: HashMap<u64, Sender<Receipt>>,
+    /// The tags of the transactions not yet sealed into a batch, by transaction digest.
+    queued: HashMap<Digest, Vec<Tag>>,
+    /// The tags of the transactions of the sealed batches, by batch digest.
+    sealed: HashMap<Digest, Vec<Tag>>,
+    /// The keys of `sealed`, from the oldest (some of them may be gone already).
+    sealed_order: VecDeque<Digest>,
+    /// The number of tags of `queued` and `sealed`.
+    tags: usize,
+}
+
+impl Pending {
+    /// Forgets the tags of the oldest sealed batch (if any), and returns whether it freed any.
+    fn evict(&mut self) -> bool {
+        while let Some(digest) = self.sealed_order.pop_front() {
+            if let Some(tags) = self.sealed.remove(&digest) {
+                debug!("Dropped {} receipts of batch {}", tags.len(), digest);
+                self.tags -= tags.len();
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Tracks the transactions that clients tagged with a correlation id, from their batch to its
+/// commit, and sends a receipt to their client over its receipt channel once consensus commits
+/// them. Transactions are identified by their content: the first batch holding a tagged
+/// transaction takes its tags. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct Receipts {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl Receipts {
+    /// Opens a receipt channel, and returns its key along with the receiver of its receipts.
+    pub fn open(&self) -> (u64, Receiver<Receipt>) {
+        let (tx_receipt, rx_receipt) = channel(CHANNEL_CAPACITY);
+        let mut pending = self.pending.lock().unwrap();
+        let key = pending.next_key;
+        pending.next_key += 1;
+        pending.channels.insert(key, tx_receipt);
+        (key, rx_receipt)
+    }
+
+    /// Closes a receipt channel. The tags of its transactions are dropped when their batch commits.
+    pub fn close(&self, key: u64) {
+        self.pending.lock().unwrap().channels.remove(&key);
+    }
+
+    /// Splits a tagged transaction into its tag and the transaction itself. Returns `None` if the
+    /// transaction is too short to carry a tag.
+    pub fn untag(tagged: &Bytes) -> Option<(Tag, Transaction)> {
+        if tagged.len() < TAG_SIZE {
+            return None;
+        }
+        let key = u64::from_be_bytes(tagged[..8].try_into().ok()?);
+        let id = u64::from_be_bytes(tagged[8..TAG_SIZE].try_into().ok()?);
+        Some(((key, id), tagged.slice(TAG_SIZE..)))
+    }
+
+    /// Records the tag of a transaction. Returns `false` if its receipt channel is not open.
+    pub fn tag(&self, (key, id): Tag, transaction: &[u8]) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        if !pending.channels.contains_key(&key) {
+            return false;
+        }
+        if pending.tags >= MAX_PENDING_TAGS && !pending.evict() {
+            debug!(
+                "Too many tagged transactions, dropped tag {} of channel {}",
+                id, key
+            );
+            return true;
+        }
+        pending
+            .queued
+            .entry(crypto::hash(transaction))
+            .or_default()
+            .push((key, id));
+        pending.tags += 1;
+        true
+    }
+
+    /// Moves the tags of the transactions of a batch we sealed to the batch.
+    pub fn sealed(&self, batch: &[Transaction], digest: &Digest) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.queued.is_empty() {
+            return;
+        }
+        let tags: Vec<_> = batch
+            .iter()
+            .filter_map(|x| pending.queued.remove(&crypto::hash(x)))
+            .flatten()
+            .collect();
+        if !tags.is_empty() {
+            pending
+                .sealed
+                .entry(digest.clone())
+                .or_default()
+                .extend(tags);
+            pending.sealed_order.push_back(digest.clone());
+        }
+    }
+
+    /// Sends the receipts of the tagged transactions of a committed batch (if any).
+    pub fn committed(&self, digest: &Digest, round: Round) {
+        let mut pending = self.pending.lock().unwrap();
+        let tags = match pending.sealed.remove(digest) {
+            Some(tags) => tags,
+            None => return,
+        };
+        pending.tags -= tags.len();
+        for (key, id) in tags {
+            let receipt = Receipt {
+                id,
+                batch: digest.clone(),
+                round,
+            };
+            if let Some(tx_receipt) = pending.channels.get(&key) {
+                if tx_receipt.try_send(receipt).is_err() {
+                    debug!("Dropped receipt {} of channel {}", id, key);
+                }
+            }
+        }
+    }
+
+    /// Follows the commit stream of a node (whose frames are serialized `client::CommittedBatch`)
+    /// to learn which batches are committed. When the stream breaks, we subscribe again: the node
+    /// replays its recent commits to new subscribers.
+    pub fn follow(&self, address: SocketAddr) {
+        let receipts = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match Self::subscribe(address).await {
+                    Ok(mut subscription) => {
+                        debug!("Subscribed to the commit stream of {}", address);
+                        while let Some(Ok(frame)) = subscription.next().await {
+                            // The frames start with the round, index and digest of the batch.
+                            match bincode::deserialize::<(Round, u64, Digest)>(&frame) {
+                                Ok((round, _, digest)) => receipts.committed(&digest, round),
+                                Err(e) => warn!("Failed to deserialize committed batch: {}", e),
+                            }
+                        }
+                        warn!("Lost the commit stream of {}", address);
+                    }
+                    Err(e) => warn!(
+                        "Failed to subscribe to the commit stream {}: {}",
+                        address, e
+                    ),
+                }
+                sleep(Duration::from_millis(RESUBSCRIBE_DELAY)).await;
+            }
+        });
+    }
+
+    /// Subscribes to a commit stream.
+    async fn subscribe(
+        address: SocketAddr,
+    ) -> std::io::Result<Framed<TcpStream, LengthDelimitedCodec>> {
+        let stream = TcpStream::connect(address).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(Bytes::from("subscribe")).await?;
+        Ok(transport)
+    }
+}
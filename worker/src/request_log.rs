@@ -0,0 +1,98 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::WorkerNetError;
+use crate::metrics::WorkerNetMetrics;
+use crate::worker::WorkerChannelType;
+use log::info;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/request_log_tests.rs"]
+pub mod request_log_tests;
+
+/// What a handler did with a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routing {
+    /// Handed to a task of the worker (e.g., the `BatchMaker`).
+    Forwarded(&'static str),
+    /// Handled by the handler itself (e.g., a banner, or a preview it replied to).
+    Served(&'static str),
+    /// Rejected with a reply, without closing the connection (e.g., a throttled batch request).
+    Rejected(&'static str),
+}
+
+/// Logs a sample of the frames the handlers of the worker receive: logging all of them is too
+/// expensive under load, but a representative trace is invaluable to debug production nodes.
+/// Frames that are rejected or close their connection are always logged. Every frame is counted in
+/// the metrics of the worker. Clones share the same rate, which may be adjusted at runtime.
+#[derive(Clone, Default)]
+pub struct RequestLog {
+    /// We log one in this many frames (none if zero).
+    rate: Arc<AtomicU64>,
+    /// The frames we were told about.
+    frames: Arc<AtomicU64>,
+    /// Counts the frames (and connections) of the worker.
+    metrics: WorkerNetMetrics,
+}
+
+impl RequestLog {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: Arc::new(AtomicU64::new(rate)),
+            frames: Arc::default(),
+            metrics: WorkerNetMetrics::default(),
+        }
+    }
+
+    /// Makes a request log that counts the frames in the specified metrics.
+    pub(crate) fn with_metrics(self, metrics: WorkerNetMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Returns the metrics of the worker.
+    pub fn metrics(&self) -> &WorkerNetMetrics {
+        &self.metrics
+    }
+
+    /// Returns how many frames we log one of (zero if we only log rejected frames).
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Logs one in `rate` frames from now on (only the rejected ones if zero).
+    pub fn set_rate(&self, rate: u64) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    /// Records how a frame was handled, and returns whether it was logged.
+    pub(crate) fn record(
+        &self,
+        peer: Option<SocketAddr>,
+        channel: &WorkerChannelType,
+        size: usize,
+        result: &Result<Routing, WorkerNetError>,
+    ) -> bool {
+        self.metrics.frame(channel, size, result);
+        let sampled = match (result, self.rate()) {
+            (Ok(Routing::Rejected(_)) | Err(_), _) => true,
+            (Ok(_), 0) => false,
+            (Ok(_), rate) => self.frames.fetch_add(1, Ordering::Relaxed) % rate == 0,
+        };
+        if !sampled {
+            return false;
+        }
+        let peer = peer.map_or_else(|| "unknown peer".to_string(), |x| x.ip().to_string());
+        match result {
+            Ok(routing) => info!(
+                "Frame of {} B from {} on {:?} channel: {:?}",
+                size, peer, channel, routing
+            ),
+            Err(e) => info!(
+                "Frame of {} B from {} on {:?} channel: closing ({})",
+                size, peer, channel, e
+            ),
+        }
+        true
+    }
+}
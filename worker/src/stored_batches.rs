@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// How many digests of stored batches we remember by default.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+struct Inner {
+    /// The digests we remember.
+    digests: HashSet<Digest>,
+    /// The same digests, oldest first (to forget the oldest once full).
+    order: VecDeque<Digest>,
+}
+
+/// The digests of the batches we recently stored (in this session), so that the batches peers
+/// retransmit (e.g., after missing our ACK) are acknowledged without being stored again. Only the
+/// most recent `capacity` digests are remembered. Clones share the same digests.
+#[derive(Clone)]
+pub struct StoredBatches {
+    capacity: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for StoredBatches {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl StoredBatches {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Arc::new(Mutex::new(Inner {
+                digests: HashSet::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Records that we stored a batch, forgetting the oldest one if we remember too many.
+    pub fn insert(&self, digest: Digest) {
+        let mut inner = self.inner.lock().unwrap();
+        if self.capacity == 0 || !inner.digests.insert(digest.clone()) {
+            return;
+        }
+        inner.order.push_back(digest);
+        if inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.digests.remove(&oldest);
+            }
+        }
+    }
+
+    /// Whether we recently stored the batch.
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.inner.lock().unwrap().digests.contains(digest)
+    }
+}
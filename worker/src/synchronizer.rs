@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::processor::SerializedBatchMessage;
 use crate::worker::{Round, WorkerMessage};
 use bytes::Bytes;
 use config::{Committee, WorkerId};
@@ -6,20 +7,21 @@ use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error};
-use network::SimpleSender;
+use network::{SharedAuthenticator, SharedTranscoder, SimpleSender};
 use primary::PrimaryWorkerMessage;
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
-use store::{Store, StoreError};
+use std::future::Future;
+use store::{NotifyReadError, Store};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::{sleep, Duration, Instant};
+use tokio::time::Duration;
 
 #[cfg(test)]
 #[path = "tests/synchronizer_tests.rs"]
 pub mod synchronizer_tests;
 
-/// Resolution of the timer managing retrials of sync requests (in ms).
-const TIMER_RESOLUTION: u64 = 1_000;
+/// The outcome of waiting for a batch: its digest once we have it (or `None` if we no longer need
+/// it), or the error that stopped the wait.
+type WaiterResult = Result<Option<Digest>, (Digest, NotifyReadError)>;
 
 // The `Synchronizer` is responsible to keep the worker in sync with the others.
 pub struct Synchronizer {
@@ -30,10 +32,10 @@ pub struct Synchronizer {
     /// The committee information.
     committee: Committee,
     // The persistent storage.
-    store: Store,
+    store: Store<Digest, SerializedBatchMessage>,
     /// The depth of the garbage collection.
     gc_depth: Round,
-    /// The delay to wait before re-trying to send sync requests.
+    /// The delay to wait for a batch before re-trying to send sync requests.
     sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-requests. These nodes
     /// are picked at random from the committee.
@@ -46,8 +48,8 @@ pub struct Synchronizer {
     round: Round,
     /// Keeps the digests (of batches) that are waiting to be processed by the primary. Their
     /// processing will resume when we get the missing batches in the store or we no longer need them.
-    /// It also keeps the round number and a timestamp (`u128`) of each request we sent.
-    pending: HashMap<Digest, (Round, Sender<()>, u128)>,
+    /// It also keeps the round number of each request and the node we last sent it to.
+    pending: HashMap<Digest, (Round, Sender<()>, PublicKey)>,
 }
 
 impl Synchronizer {
@@ -56,11 +58,13 @@ impl Synchronizer {
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
-        store: Store,
+        store: Store<Digest, SerializedBatchMessage>,
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
         rx_message: Receiver<PrimaryWorkerMessage>,
+        transcoder: Option<SharedTranscoder>,
+        authenticator: Option<SharedAuthenticator>,
     ) {
         tokio::spawn(async move {
             Self {
@@ -72,7 +76,9 @@ impl Synchronizer {
                 sync_retry_delay,
                 sync_retry_nodes,
                 rx_message,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_transcoder(transcoder)
+                    .with_authenticator(authenticator),
                 round: Round::default(),
                 pending: HashMap::new(),
             }
@@ -81,39 +87,45 @@ impl Synchronizer {
         });
     }
 
-    /// Helper function. It waits for a batch to become available in the storage
-    /// and then delivers its digest.
+    /// Helper function. It waits (for at most `delay` ms) for a batch to become available in the
+    /// storage and then delivers its digest.
     async fn waiter(
         missing: Digest,
-        mut store: Store,
-        deliver: Digest,
+        mut store: Store<Digest, SerializedBatchMessage>,
+        delay: u64,
         mut handler: Receiver<()>,
-    ) -> Result<Option<Digest>, StoreError> {
+    ) -> WaiterResult {
         tokio::select! {
-            result = store.notify_read(missing.to_vec()) => {
-                result.map(|_| Some(deliver))
-            }
+            result = store.notify_read_timeout(&missing, Duration::from_millis(delay)) => match result {
+                Ok(_) => Ok(Some(missing)),
+                Err(e) => Err((missing, e)),
+            },
             _ = handler.recv() => Ok(None),
         }
     }
 
+    /// Waits for a batch we requested, until we get it or no longer need it.
+    fn wait_for(
+        &mut self,
+        digest: Digest,
+        round: Round,
+        target: PublicKey,
+    ) -> impl Future<Output = WaiterResult> {
+        let (tx_cancel, rx_cancel) = channel(1);
+        self.pending
+            .insert(digest.clone(), (round, tx_cancel, target));
+        Self::waiter(digest, self.store.clone(), self.sync_retry_delay, rx_cancel)
+    }
+
     /// Main loop listening to the primary's messages.
     async fn run(&mut self) {
         let mut waiting = FuturesUnordered::new();
 
-        let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
-        tokio::pin!(timer);
-
         loop {
             tokio::select! {
                 // Handle primary's messages.
                 Some(message) = self.rx_message.recv() => match message {
                     PrimaryWorkerMessage::Synchronize(digests, target) => {
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect("Failed to measure time")
-                            .as_millis();
-
                         let mut missing = Vec::new();
                         for digest in digests {
                             // Ensure we do not send twice the same sync request.
@@ -122,7 +134,7 @@ impl Synchronizer {
                             }
 
                             // Check if we received the batch in the meantime.
-                            match self.store.read(digest.to_vec()).await {
+                            match self.store.read(&digest).await {
                                 Ok(None) => {
                                     missing.push(digest.clone());
                                     debug!("Requesting sync for batch {}", digest);
@@ -137,15 +149,12 @@ impl Synchronizer {
                             }
 
                             // Add the digest to the waiter.
-                            let deliver = digest.clone();
-                            let (tx_cancel, rx_cancel) = channel(1);
-                            let fut = Self::waiter(digest.clone(), self.store.clone(), deliver, rx_cancel);
+                            let fut = self.wait_for(digest, self.round, target);
                             waiting.push(fut);
-                            self.pending.insert(digest, (self.round, tx_cancel, now));
                         }
 
                         // Send sync request to a single node. If this fails, we will send it
-                        // to other nodes when a timer times out.
+                        // to other nodes when the waiter times out.
                         let address = match self.committee.worker(&target, &self.id) {
                             Ok(address) => address.worker_to_worker,
                             Err(e) => {
@@ -174,6 +183,9 @@ impl Synchronizer {
                         }
                         self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
                     }
+                    PrimaryWorkerMessage::Committed(_) | PrimaryWorkerMessage::Reconfigure(_) => {
+                        // The receiver handles these itself.
+                    }
                 },
 
                 // Stream out the futures of the `FuturesUnordered` that completed.
@@ -185,40 +197,34 @@ impl Synchronizer {
                     Ok(None) => {
                         // The sync request for this batch has been canceled.
                     },
-                    Err(e) => error!("{}", e)
-                },
-
-                // Triggers on timer's expiration.
-                () = &mut timer => {
-                    // We optimistically sent sync requests to a single node. If this timer triggers,
-                    // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to a bunch of other nodes (selected at random).
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Failed to measure time")
-                        .as_millis();
-
-                    let mut retry = Vec::new();
-                    for (digest, (_, _, timestamp)) in &self.pending {
-                        if timestamp + (self.sync_retry_delay as u128) < now {
-                            debug!("Requesting sync for batch {} (retry)", digest);
-                            retry.push(digest.clone());
-                        }
-                    }
-                    if !retry.is_empty() {
+                    Err((digest, NotifyReadError::Timeout)) => {
+                        // We optimistically sent the sync request to a single node, and we were wrong
+                        // to trust it. We now send the request to a bunch of other nodes (selected at
+                        // random) and wait again.
+                        let (round, _, target) = match self.pending.remove(&digest) {
+                            Some(x) => x,
+                            None => continue,
+                        };
+                        debug!("Requesting sync for batch {} (retry)", digest);
                         let addresses = self.committee
                             .others_workers(&self.name, &self.id)
-                            .iter().map(|(_, address)| address.worker_to_worker)
+                            .iter()
+                            .filter(|(name, _)| name != &target)
+                            .map(|(_, address)| address.worker_to_worker)
                             .collect();
-                        let message = WorkerMessage::BatchRequest(retry, self.name);
+                        let message = WorkerMessage::BatchRequest(vec![digest.clone()], self.name);
                         let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
                         self.network
                             .lucky_broadcast(addresses, Bytes::from(serialized), self.sync_retry_nodes)
                             .await;
-                    }
 
-                    // Reschedule the timer.
-                    timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
+                        let fut = self.wait_for(digest, round, target);
+                        waiting.push(fut);
+                    },
+                    Err((digest, e)) => {
+                        self.pending.remove(&digest);
+                        error!("{}", e)
+                    }
                 },
             }
         }
@@ -1,7 +1,25 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
+use crate::backlog::BacklogSnapshot;
 use crate::common::transaction;
+use std::io;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::channel;
+use tracing::Level;
+
+// Fixture: a log output that tests can read.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 #[tokio::test]
 async fn make_batch() {
@@ -14,17 +32,30 @@ async fn make_batch() {
         /* max_batch_size */ 200,
         /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
         rx_transaction,
+        /* rx_preview */ channel(1).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+        /* backlog */ Backlog::default(),
+        BatchDigester::default(),
+        /* receipts */ None,
     );
 
     // Send enough transactions to seal a batch.
-    tx_transaction.send(transaction()).await.unwrap();
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction(), transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
@@ -42,18 +73,135 @@ async fn batch_timeout() {
         /* max_batch_size */ 200,
         /* max_batch_delay */ 50, // Ensure the timer is triggered.
         rx_transaction,
+        /* rx_preview */ channel(1).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+        /* backlog */ Backlog::default(),
+        BatchDigester::default(),
+        /* receipts */ None,
     );
 
     // Do not send enough transactions to seal a batch..
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
     }
 }
+
+#[tokio::test]
+async fn seal_event_carries_digest() {
+    // Capture the JSON logs of this test.
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(Level::DEBUG)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 200,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_preview */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+        /* backlog */ Backlog::default(),
+        BatchDigester::default(),
+        /* receipts */ None,
+    );
+
+    // Seal a batch.
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    let digest = crypto::hash(&batch);
+
+    // The sealing event is in the span of the batch, which holds its digest.
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let event = logs
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|event| event["fields"]["message"] == "Sealed batch")
+        .expect("No sealing event");
+    assert_eq!(event["span"]["digest"], format!("{:?}", digest));
+    assert_eq!(event["fields"]["size"], 200);
+}
+
+#[tokio::test]
+async fn track_backlog() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let backlog = Backlog::default();
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 200,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_preview */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+        backlog.clone(),
+        BatchDigester::default(),
+        /* receipts */ None,
+    );
+
+    // The backlog counts the transactions of the batch being assembled.
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(50)).await;
+    let expected = BacklogSnapshot {
+        transactions: 1,
+        bytes: transaction().len(),
+        batches: 0,
+        ..BacklogSnapshot::default()
+    };
+    assert_eq!(backlog.snapshot(), expected);
+
+    // Once sealed, the batch waits for its acknowledgements.
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    rx_message.recv().await.unwrap();
+    let expected = BacklogSnapshot {
+        transactions: 0,
+        bytes: 0,
+        batches: 1,
+        ..BacklogSnapshot::default()
+    };
+    assert_eq!(backlog.snapshot(), expected);
+}
@@ -0,0 +1,74 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Fixture
+fn budget() -> RequestBudget {
+    RequestBudget {
+        requests_per_second: 10,
+        request_burst: 3,
+        bytes_per_second: 1_000,
+        byte_burst: 1_000,
+    }
+}
+
+#[tokio::test]
+async fn throttle_requests_over_burst() {
+    let budgets = RequestBudgets::new(budget());
+    let peer = "127.0.0.1".parse().unwrap();
+
+    // The burst is admitted, the request after it is throttled.
+    for _ in 0..3 {
+        assert!(budgets.admit(peer));
+    }
+    assert!(!budgets.admit(peer));
+
+    // The bucket refills over time.
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(budgets.admit(peer));
+
+    let expected = PeerRequests {
+        admitted: 4,
+        throttled: 1,
+        bytes: 0,
+    };
+    assert_eq!(budgets.snapshot(), vec![(peer, expected)]);
+}
+
+#[tokio::test]
+async fn throttle_bytes_in_debt() {
+    let budgets = RequestBudgets::new(budget());
+    let peer = "127.0.0.1".parse().unwrap();
+
+    // A single request may get more bytes than the burst, but the peer is throttled until it
+    // paid back the debt.
+    assert!(budgets.admit(peer));
+    budgets.charge(peer, 1_500);
+    assert!(!budgets.admit(peer));
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(budgets.admit(peer));
+    assert_eq!(budgets.snapshot()[0].1.bytes, 1_500);
+}
+
+#[tokio::test]
+async fn isolate_peers() {
+    let budgets = RequestBudgets::new(budget());
+    let abusive = "127.0.0.1".parse().unwrap();
+    let honest = "127.0.0.2".parse().unwrap();
+
+    // Exhausting the budget of a peer does not affect the others.
+    for _ in 0..10 {
+        budgets.admit(abusive);
+    }
+    assert!(budgets.admit(honest));
+
+    // Charging an unknown peer does nothing.
+    budgets.charge("127.0.0.3".parse().unwrap(), 1_000);
+
+    // The most throttled peers come first.
+    let snapshot = budgets.snapshot();
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(snapshot[0].0, abusive);
+    assert_eq!(snapshot[0].1.throttled, 7);
+    assert_eq!(snapshot[1].0, honest);
+    assert_eq!(snapshot[1].1.admitted, 1);
+}
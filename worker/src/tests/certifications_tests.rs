@@ -0,0 +1,79 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch_digest, keys, serialized_batch};
+use bytes::Bytes;
+use store::{Database, Family};
+
+// Fixture
+fn header(payload: Vec<(Digest, WorkerId)>, round: Round) -> Header {
+    let (author, _) = keys().pop().unwrap();
+    let mut header = Header {
+        author,
+        round,
+        payload: payload.into_iter().collect(),
+        ..Header::default()
+    };
+    header.id = header.digest();
+    header
+}
+
+// Fixture
+async fn certifications() -> Certifications {
+    let mut store = Database::new_in_memory().store(Family::Batches);
+    store.write(&batch_digest(), &serialized_batch()).await;
+    Certifications::new(/* id */ 0, /* retention */ 50, store)
+}
+
+#[tokio::test]
+async fn prove_committed_batch() {
+    let certifications = certifications().await;
+    let header = header(vec![(batch_digest(), 0)], 1);
+    let certificate = Certificate {
+        header: header.clone(),
+        ..Certificate::default()
+    }
+    .digest();
+    certifications.committed(header);
+
+    // The proof links the batch to the digest of the certificate.
+    let digester = BatchDigester::default();
+    let proof = certifications.prove(&batch_digest()).await.unwrap();
+    assert_eq!(proof.verify(&certificate, &digester), Ok(batch_digest()));
+
+    // But not to another certificate.
+    assert!(matches!(
+        proof.verify(&Digest::default(), &digester),
+        Err(ProofError::CertificateMismatch(..))
+    ));
+
+    // Nor does it prove a tampered batch.
+    let mut tampered = proof.clone();
+    tampered.batch[0] = Bytes::from("tampered");
+    assert!(matches!(
+        tampered.verify(&certificate, &digester),
+        Err(ProofError::BatchNotCertified(..))
+    ));
+
+    // Nor a tampered header.
+    let mut tampered = proof;
+    tampered.header.round += 1;
+    assert!(matches!(
+        tampered.verify(&certificate, &digester),
+        Err(ProofError::InvalidHeader(..))
+    ));
+}
+
+#[tokio::test]
+async fn keep_recent_headers_of_our_batches() {
+    // We only keep the headers committing batches of our id.
+    let certifications = certifications().await;
+    certifications.committed(header(vec![(batch_digest(), 1)], 1));
+    assert!(certifications.prove(&batch_digest()).await.is_none());
+
+    // And forget them after the retention.
+    certifications.committed(header(vec![(batch_digest(), 0)], 10));
+    certifications.cleanup(60);
+    assert!(certifications.prove(&batch_digest()).await.is_some());
+    certifications.cleanup(61);
+    assert!(certifications.prove(&batch_digest()).await.is_none());
+}
@@ -0,0 +1,64 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn reassemble_chunked_transaction() {
+    let transaction: Vec<u8> = (0..250u8).collect();
+    let chunks = split_transaction(7, &transaction, 100);
+    assert_eq!(chunks.len(), 3);
+
+    // The transaction is returned once, when its last chunk arrives.
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+    let mut complete = Vec::new();
+    for chunk in chunks {
+        if let Some(x) = reassembler.add(chunk, now).unwrap() {
+            complete.push(x);
+        }
+    }
+    assert_eq!(complete, vec![Bytes::from(transaction)]);
+    assert!(reassembler.pending.is_empty());
+    assert_eq!(reassembler.size, 0);
+}
+
+#[test]
+fn reject_out_of_order_chunks() {
+    let chunks = split_transaction(7, &[0u8; 300], 100);
+    let mut reassembler = Reassembler::default();
+    let now = Instant::now();
+    assert!(reassembler.add(chunks[0].clone(), now).unwrap().is_none());
+    assert!(matches!(
+        reassembler.add(chunks[2].clone(), now),
+        Err(WorkerNetError::Decode("chunk", _))
+    ));
+}
+
+#[test]
+fn bound_incomplete_transactions() {
+    let now = Instant::now();
+    let timeout = Duration::from_secs(1);
+
+    // Too many incomplete transactions.
+    let mut reassembler = Reassembler::new(1_000, 1, timeout);
+    let first = split_transaction(1, &[0u8; 20], 10);
+    let second = split_transaction(2, &[0u8; 20], 10);
+    assert!(reassembler.add(first[0].clone(), now).unwrap().is_none());
+    assert!(matches!(
+        reassembler.add(second[0].clone(), now),
+        Err(WorkerNetError::Rejected(_))
+    ));
+
+    // The incomplete transaction times out, making room for the next one.
+    let later = now + timeout;
+    assert!(reassembler.add(second[0].clone(), later).unwrap().is_none());
+    assert!(reassembler.add(first[1].clone(), later).is_err());
+    assert_eq!(reassembler.size, 10);
+
+    // Too many buffered bytes.
+    let mut reassembler = Reassembler::new(15, 16, timeout);
+    assert!(reassembler.add(first[0].clone(), now).unwrap().is_none());
+    assert!(matches!(
+        reassembler.add(second[0].clone(), now),
+        Err(WorkerNetError::Rejected(_))
+    ));
+}
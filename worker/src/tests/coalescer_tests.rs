@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn forward_bursts_together() {
+    let (tx_transaction, rx_transaction) = channel(100);
+    let (tx_mempool, mut rx_mempool) = channel(100);
+    Coalescer::spawn(
+        Duration::from_millis(100),
+        /* max_size */ 5,
+        rx_transaction,
+        tx_mempool,
+    );
+
+    // The transactions arriving within the window are held until it closes, then all forwarded.
+    for _ in 0..3 {
+        tx_transaction
+            .send((Instant::now(), transaction()))
+            .await
+            .unwrap();
+    }
+    let early = timeout(Duration::from_millis(50), rx_mempool.recv()).await;
+    assert!(early.is_err(), "The window should hold the transactions");
+    for _ in 0..3 {
+        let received = timeout(Duration::from_millis(100), rx_mempool.recv()).await;
+        assert_eq!(received.unwrap().unwrap().1, transaction());
+    }
+
+    // A transaction arriving after the window starts a group of its own.
+    tx_transaction
+        .send((Instant::now(), transaction()))
+        .await
+        .unwrap();
+    let early = timeout(Duration::from_millis(50), rx_mempool.recv()).await;
+    assert!(early.is_err(), "The window should hold the transaction");
+    let received = timeout(Duration::from_millis(100), rx_mempool.recv()).await;
+    assert!(received.unwrap().is_some());
+    let next = timeout(Duration::from_millis(150), rx_mempool.recv()).await;
+    assert!(next.is_err(), "The group should hold a single transaction");
+
+    // Full groups are forwarded right away.
+    for _ in 0..5 {
+        tx_transaction
+            .send((Instant::now(), transaction()))
+            .await
+            .unwrap();
+    }
+    for _ in 0..5 {
+        let received = timeout(Duration::from_millis(50), rx_mempool.recv()).await;
+        assert!(received.unwrap().is_some());
+    }
+}
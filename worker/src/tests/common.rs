@@ -2,15 +2,10 @@
 use crate::batch_maker::{Batch, Transaction};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
-use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
-use crypto::{generate_keypair, Digest, PublicKey, SecretKey};
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use config::{Committee, KeyPair};
+use crypto::{Digest, PublicKey, SecretKey};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use rand::rngs::StdRng;
-use rand::SeedableRng as _;
-use std::convert::TryInto as _;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
@@ -18,74 +13,22 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 // Fixture
 pub fn keys() -> Vec<(PublicKey, SecretKey)> {
-    let mut rng = StdRng::from_seed([0; 32]);
-    (0..4).map(|_| generate_keypair(&mut rng)).collect()
-}
-
-// Fixture
-pub fn committee() -> Committee {
-    Committee {
-        authorities: keys()
-            .iter()
-            .enumerate()
-            .map(|(i, (id, _))| {
-                let primary = PrimaryAddresses {
-                    primary_to_primary: format!("127.0.0.1:{}", 100 + i).parse().unwrap(),
-                    worker_to_primary: format!("127.0.0.1:{}", 200 + i).parse().unwrap(),
-                };
-                let workers = vec![(
-                    0,
-                    WorkerAddresses {
-                        primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
-                        transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
-                        worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
-                    },
-                )]
-                .iter()
-                .cloned()
-                .collect();
-                (
-                    *id,
-                    Authority {
-                        stake: 1,
-                        primary,
-                        workers,
-                    },
-                )
-            })
-            .collect(),
-    }
+    (0..4)
+        .map(|i| {
+            let keypair = KeyPair::new_for_test(/* seed */ 0, i);
+            (keypair.name, keypair.secret)
+        })
+        .collect()
 }
 
 // Fixture.
 pub fn committee_with_base_port(base_port: u16) -> Committee {
-    let mut committee = committee();
-    for authority in committee.authorities.values_mut() {
-        let primary = &mut authority.primary;
-
-        let port = primary.primary_to_primary.port();
-        primary.primary_to_primary.set_port(base_port + port);
-
-        let port = primary.worker_to_primary.port();
-        primary.worker_to_primary.set_port(base_port + port);
-
-        for worker in authority.workers.values_mut() {
-            let port = worker.primary_to_worker.port();
-            worker.primary_to_worker.set_port(base_port + port);
-
-            let port = worker.transactions.port();
-            worker.transactions.set_port(base_port + port);
-
-            let port = worker.worker_to_worker.port();
-            worker.worker_to_worker.set_port(base_port + port);
-        }
-    }
-    committee
+    Committee::new_for_test(4, base_port, /* seed */ 0)
 }
 
 // Fixture
 pub fn transaction() -> Transaction {
-    vec![0; 100]
+    Bytes::from(vec![0; 100])
 }
 
 // Fixture
@@ -101,11 +44,7 @@ pub fn serialized_batch() -> Vec<u8> {
 
 // Fixture
 pub fn batch_digest() -> Digest {
-    Digest(
-        Sha512::digest(&serialized_batch()).as_slice()[..32]
-            .try_into()
-            .unwrap(),
-    )
+    crypto::hash(&serialized_batch())
 }
 
 // Fixture
@@ -0,0 +1,130 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::proto::transactions_client::TransactionsClient;
+use super::*;
+use crate::batch_maker::Batch;
+use crate::common::{committee_with_base_port, keys, transaction};
+use crate::worker::{Worker, WorkerMessage, WorkerOptions};
+use bytes::Bytes;
+use config::Parameters;
+use futures::sink::SinkExt as _;
+use std::collections::HashSet;
+use store::Database;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tonic::transport::Channel;
+use tonic::Code;
+
+// Fixture
+async fn client(address: SocketAddr) -> TransactionsClient<Channel> {
+    let channel = Channel::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    TransactionsClient::new(channel)
+}
+
+// Fixture
+fn batch_listener(address: SocketAddr) -> Receiver<Batch> {
+    let (tx_batch, rx_batch) = channel(1_000);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = transport.next().await {
+            transport.send(Bytes::from("Ack")).await.unwrap();
+            if let Ok(WorkerMessage::DigestedBatch(batch, _)) = bincode::deserialize(&frame) {
+                tx_batch.send(batch).await.unwrap();
+            }
+        }
+    });
+    rx_batch
+}
+
+#[tokio::test]
+async fn submit_transaction() {
+    let address = "127.0.0.1:11505".parse::<SocketAddr>().unwrap();
+    let (tx_batch_maker, mut rx_batch_maker) = channel(1);
+    TransactionService::spawn(address, tx_batch_maker);
+    sleep(Duration::from_millis(50)).await;
+
+    // The transaction reaches the batch maker.
+    let mut client = client(address).await;
+    let request = Transaction {
+        transaction: transaction(),
+    };
+    client.submit_transaction(request.clone()).await.unwrap();
+    let (_, received) = rx_batch_maker.recv().await.unwrap();
+    assert_eq!(received, transaction());
+
+    // Transactions are rejected while the batch maker lags behind.
+    client.submit_transaction(request.clone()).await.unwrap();
+    let status = client.submit_transaction(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn stream_transactions_into_batches() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_600);
+    let parameters = Parameters {
+        batch_size: 10_000, // 100 transactions.
+        ..Parameters::default()
+    };
+
+    // Spawn a worker serving gRPC clients, and the other workers receiving its batches.
+    let address = "127.0.0.1:11620".parse::<SocketAddr>().unwrap();
+    let store = Database::new_in_memory();
+    let options = WorkerOptions {
+        grpc_address: Some(address),
+        ..WorkerOptions::default()
+    };
+    Worker::spawn(name, id, committee.clone(), parameters, store, options).unwrap();
+    let mut listeners: Vec<_> = committee
+        .others_workers(&name, &id)
+        .into_iter()
+        .map(|(_, addresses)| batch_listener(addresses.worker_to_worker))
+        .collect();
+    sleep(Duration::from_millis(50)).await;
+
+    // Stream 10k distinct transactions, each acknowledged.
+    let transactions: Vec<_> = (0..10_000u32)
+        .map(|i| {
+            let mut transaction = vec![0; 100];
+            transaction[..4].copy_from_slice(&i.to_be_bytes());
+            Transaction {
+                transaction: Bytes::from(transaction),
+            }
+        })
+        .collect();
+    let requests = futures::stream::iter(transactions.clone());
+    let mut acks = client(address)
+        .await
+        .submit_transaction_stream(requests)
+        .await
+        .unwrap()
+        .into_inner();
+    let mut count = 0;
+    while let Some(ack) = acks.next().await {
+        ack.unwrap();
+        count += 1;
+    }
+    assert_eq!(count, transactions.len());
+
+    // The other workers receive all the transactions, in full batches.
+    let mut received = HashSet::new();
+    let mut batches = 0;
+    while received.len() < transactions.len() {
+        let batch = listeners[0].recv().await.unwrap();
+        assert_eq!(batch.len(), 100);
+        received.extend(batch);
+        batches += 1;
+    }
+    assert_eq!(batches, 100);
+    assert!(transactions
+        .iter()
+        .all(|x| received.contains(&x.transaction)));
+}
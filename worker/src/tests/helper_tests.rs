@@ -1,7 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
-use std::fs;
+use store::{Database, Family};
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -12,17 +12,22 @@ async fn batch_reply() {
     let committee = committee_with_base_port(8_000);
 
     // Create a new test store.
-    let path = ".db_test_batch_reply";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let mut store = Database::new_in_memory().store(Family::Batches);
 
     // Add a batch to the store.
-    store
-        .write(batch_digest().to_vec(), serialized_batch())
-        .await;
+    store.write(&batch_digest(), &serialized_batch()).await;
 
     // Spawn an `Helper` instance.
-    Helper::spawn(id, committee.clone(), store, rx_request);
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        rx_request,
+        RequestBudgets::default(),
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+    );
 
     // Spawn a listener to receive the batch reply.
     let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
@@ -31,8 +36,52 @@ async fn batch_reply() {
 
     // Send a batch request.
     let digests = vec![batch_digest()];
-    tx_request.send((digests, requestor)).await.unwrap();
+    tx_request.send((digests, requestor, None)).await.unwrap();
 
     // Ensure the requestor received the batch (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn charge_batch_reply() {
+    let (tx_request, rx_request) = channel(1);
+    let (requestor, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(8_100);
+    let budgets = RequestBudgets::default();
+
+    // Create a new test store holding a batch.
+    let mut store = Database::new_in_memory().store(Family::Batches);
+    store.write(&batch_digest(), &serialized_batch()).await;
+
+    // Spawn an `Helper` instance.
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        rx_request,
+        budgets.clone(),
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+    );
+
+    // Spawn a listener to receive the batch reply.
+    let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
+    let handle = listener(address, Some(Bytes::from(serialized_batch())));
+
+    // Send a batch request from a peer we admitted.
+    let peer = "127.0.0.1".parse().unwrap();
+    assert!(budgets.admit(peer));
+    let digests = vec![batch_digest()];
+    tx_request
+        .send((digests, requestor, Some(peer)))
+        .await
+        .unwrap();
+    assert!(handle.await.is_ok());
+
+    // The batch we sent is charged to the peer.
+    let requests = budgets.snapshot();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].1.bytes, serialized_batch().len() as u64);
+}
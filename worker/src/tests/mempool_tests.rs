@@ -0,0 +1,203 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout};
+
+// Fixture
+/// Transactions made of their sender, their nonce and their payload (one byte each). Transactions
+/// of a single byte have no nonce.
+struct TestParser {
+    initial_nonce: Option<u64>,
+}
+
+impl TransactionParser for TestParser {
+    fn parse(&self, transaction: &Transaction) -> Option<(Bytes, u64)> {
+        match transaction.len() {
+            3 => Some((transaction.slice(..1), transaction[1] as u64)),
+            _ => None,
+        }
+    }
+
+    fn initial_nonce(&self, _sender: &Bytes) -> Option<u64> {
+        self.initial_nonce
+    }
+}
+
+// Fixture
+fn transaction(sender: u8, nonce: u8, payload: u8) -> StampedTransaction {
+    (Instant::now(), Bytes::from(vec![sender, nonce, payload]))
+}
+
+// Fixture
+fn mempool(
+    parser: Option<TestParser>,
+    parameters: MempoolParameters,
+) -> (Sender<StampedTransaction>, Receiver<StampedTransaction>) {
+    let (tx_transaction, rx_transaction) = channel(100);
+    let (tx_batch_maker, rx_batch_maker) = channel(100);
+    let parser = parser.map(|x| Arc::new(x) as Arc<dyn TransactionParser>);
+    Mempool::spawn(parser, parameters, rx_transaction, tx_batch_maker);
+    (tx_transaction, rx_batch_maker)
+}
+
+// Fixture
+/// Sends the transactions, and returns the (sender, nonce, payload) of those that are released.
+async fn released(
+    tx_transaction: &Sender<StampedTransaction>,
+    rx_batch_maker: &mut Receiver<StampedTransaction>,
+    transactions: Vec<StampedTransaction>,
+) -> Vec<Vec<u8>> {
+    for transaction in transactions {
+        tx_transaction.send(transaction).await.unwrap();
+    }
+    let mut released = Vec::new();
+    while let Ok(Some((_, transaction))) =
+        timeout(Duration::from_millis(100), rx_batch_maker.recv()).await
+    {
+        released.push(transaction.to_vec());
+    }
+    released
+}
+
+#[tokio::test]
+async fn fill_gaps() {
+    let parser = TestParser {
+        initial_nonce: Some(0),
+    };
+    let (tx, mut rx) = mempool(Some(parser), MempoolParameters::default());
+
+    // Nonces 1 and 3 wait for 0 and 2, while other senders are not held up.
+    let sent = vec![
+        transaction(1, 1, 0),
+        transaction(1, 3, 0),
+        transaction(2, 0, 0),
+    ];
+    let released_first = released(&tx, &mut rx, sent).await;
+    assert_eq!(released_first, vec![vec![2, 0, 0]]);
+    let released_next = released(&tx, &mut rx, vec![transaction(1, 0, 0)]).await;
+    assert_eq!(released_next, vec![vec![1, 0, 0], vec![1, 1, 0]]);
+    let released_last = released(&tx, &mut rx, vec![transaction(1, 2, 0)]).await;
+    assert_eq!(released_last, vec![vec![1, 2, 0], vec![1, 3, 0]]);
+
+    // Released nonces are not released again.
+    assert!(released(&tx, &mut rx, vec![transaction(1, 1, 5)])
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn start_from_first_nonce() {
+    // Without an initial nonce, the first nonce received from a sender is the next one.
+    let (tx, mut rx) = mempool(
+        Some(TestParser {
+            initial_nonce: None,
+        }),
+        MempoolParameters::default(),
+    );
+    let sent = vec![
+        transaction(1, 5, 0),
+        transaction(1, 7, 0),
+        transaction(1, 6, 0),
+    ];
+    let expected = vec![vec![1, 5, 0], vec![1, 6, 0], vec![1, 7, 0]];
+    assert_eq!(released(&tx, &mut rx, sent).await, expected);
+}
+
+#[tokio::test]
+async fn replace_held_transactions() {
+    for (replacement, payload) in [
+        (ReplacementPolicy::KeepFirst, 1),
+        (ReplacementPolicy::KeepLatest, 2),
+    ] {
+        let parser = TestParser {
+            initial_nonce: Some(0),
+        };
+        let parameters = MempoolParameters {
+            replacement,
+            ..MempoolParameters::default()
+        };
+        let (tx, mut rx) = mempool(Some(parser), parameters);
+        let sent = vec![
+            transaction(1, 1, 1),
+            transaction(1, 1, 2),
+            transaction(1, 0, 0),
+        ];
+        let expected = vec![vec![1, 0, 0], vec![1, 1, payload]];
+        assert_eq!(released(&tx, &mut rx, sent).await, expected);
+    }
+}
+
+#[tokio::test]
+async fn evict_stale_transactions() {
+    let parser = TestParser {
+        initial_nonce: Some(0),
+    };
+    let parameters = MempoolParameters {
+        ttl: 200,
+        ..MempoolParameters::default()
+    };
+    let (tx, mut rx) = mempool(Some(parser), parameters);
+
+    // Nonce 1 waits for too long: it is dropped.
+    assert!(released(&tx, &mut rx, vec![transaction(1, 1, 0)])
+        .await
+        .is_empty());
+    sleep(Duration::from_millis(400)).await;
+    let sent = vec![transaction(1, 0, 0), transaction(1, 2, 0)];
+    assert_eq!(released(&tx, &mut rx, sent).await, vec![vec![1, 0, 0]]);
+}
+
+#[tokio::test]
+async fn bound_held_transactions() {
+    let parser = TestParser {
+        initial_nonce: Some(0),
+    };
+    let parameters = MempoolParameters {
+        max_pending_per_sender: 3,
+        max_pending: 3,
+        ..MempoolParameters::default()
+    };
+    let (tx, mut rx) = mempool(Some(parser), parameters);
+
+    // Nonce 3 is too far ahead of its sender, and the mempool is full for the nonce 2 of
+    // sender 2. Transactions that are next in line are still released.
+    let sent = vec![
+        transaction(1, 3, 0),
+        transaction(1, 2, 0),
+        transaction(1, 1, 0),
+        transaction(2, 1, 0),
+        transaction(2, 2, 0),
+        transaction(3, 0, 0),
+    ];
+    assert_eq!(released(&tx, &mut rx, sent).await, vec![vec![3, 0, 0]]);
+    let sent = vec![transaction(1, 0, 0), transaction(2, 0, 0)];
+    let expected = vec![
+        vec![1, 0, 0],
+        vec![1, 1, 0],
+        vec![1, 2, 0],
+        vec![2, 0, 0],
+        vec![2, 1, 0],
+    ];
+    assert_eq!(released(&tx, &mut rx, sent).await, expected);
+}
+
+#[tokio::test]
+async fn pass_through_without_parser() {
+    // Without a parser, transactions are released as received.
+    let (tx, mut rx) = mempool(None, MempoolParameters::default());
+    let sent = vec![
+        transaction(1, 1, 0),
+        transaction(1, 0, 0),
+        transaction(1, 1, 0),
+    ];
+    let expected = vec![vec![1, 1, 0], vec![1, 0, 0], vec![1, 1, 0]];
+    assert_eq!(released(&tx, &mut rx, sent).await, expected);
+
+    // So are the transactions the parser does not order.
+    let parser = TestParser {
+        initial_nonce: Some(0),
+    };
+    let (tx, mut rx) = mempool(Some(parser), MempoolParameters::default());
+    let sent = vec![(Instant::now(), Bytes::from(vec![7])), transaction(1, 1, 0)];
+    assert_eq!(released(&tx, &mut rx, sent).await, vec![vec![7]]);
+}
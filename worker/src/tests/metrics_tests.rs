@@ -0,0 +1,144 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::collections::HashSet;
+
+/// Checks that the text is valid Prometheus text exposition format (as far as we use it): every
+/// sample belongs to a family whose type was declared, with well-formed labels and value. Returns
+/// the samples, as they appear (with their labels) along with their value.
+fn parse(text: &str) -> Vec<(String, f64)> {
+    let valid_name = |x: &str| {
+        !x.is_empty()
+            && !x.starts_with(|c: char| c.is_ascii_digit())
+            && x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    };
+    let mut families = HashSet::new();
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            let fields: Vec<_> = comment.splitn(3, ' ').collect();
+            assert!(matches!(fields[0], "HELP" | "TYPE"), "{}", line);
+            assert!(valid_name(fields[1]), "{}", line);
+            if fields[0] == "TYPE" {
+                assert!(
+                    matches!(fields[2], "counter" | "gauge" | "histogram"),
+                    "{}",
+                    line
+                );
+                families.insert(fields[1].to_string());
+            }
+            continue;
+        }
+        let (sample, value) = line.rsplit_once(' ').expect(line);
+        let value: f64 = value.parse().expect(line);
+        let name = match sample.split_once('{') {
+            Some((name, labels)) => {
+                let labels = labels.strip_suffix('}').expect(line);
+                for label in labels.split(',').filter(|x| !x.is_empty()) {
+                    let (key, value) = label.split_once('=').expect(line);
+                    assert!(valid_name(key), "{}", line);
+                    assert!(value.len() >= 2 && value.starts_with('"') && value.ends_with('"'));
+                }
+                name
+            }
+            None => sample,
+        };
+        assert!(valid_name(name), "{}", line);
+        let family = ["_bucket", "_sum", "_count"]
+            .iter()
+            .find_map(|x| name.strip_suffix(x).filter(|x| families.contains(*x)))
+            .unwrap_or(name);
+        assert!(families.contains(family), "Undeclared family: {}", line);
+        samples.push((sample.to_string(), value));
+    }
+    samples
+}
+
+#[test]
+fn render_prometheus_text() {
+    let metrics = WorkerNetMetrics::new(Some(3));
+    metrics.connection("transactions");
+    metrics.connection("transactions");
+    metrics.connection("workers");
+    let channel = WorkerChannelType::Transaction;
+    for size in [10, 100, 100, 2_000_000] {
+        metrics.frame(&channel, size, &Ok(Routing::Forwarded("batch maker")));
+    }
+    metrics.frame(
+        &WorkerChannelType::Worker,
+        500,
+        &Ok(Routing::Forwarded("processor")),
+    );
+    metrics.frame(
+        &WorkerChannelType::Worker,
+        50,
+        &Ok(Routing::Rejected("throttled")),
+    );
+    let failed = Err(WorkerNetError::Decode("worker message", "garbage".into()));
+    metrics.frame(&WorkerChannelType::Worker, 50, &failed);
+
+    let samples: BTreeMap<_, _> = parse(&metrics.metrics_text()).into_iter().collect();
+    let expected = [
+        (
+            r#"worker_connections_total{worker="3",receiver="transactions"}"#,
+            2.0,
+        ),
+        (
+            r#"worker_connections_total{worker="3",receiver="workers"}"#,
+            1.0,
+        ),
+        (
+            r#"worker_frames_total{worker="3",channel="transaction",outcome="batch maker"}"#,
+            4.0,
+        ),
+        (
+            r#"worker_frames_total{worker="3",channel="worker",outcome="processor"}"#,
+            1.0,
+        ),
+        (
+            r#"worker_frames_total{worker="3",channel="worker",outcome="throttled"}"#,
+            1.0,
+        ),
+        (
+            r#"worker_frames_total{worker="3",channel="worker",outcome="closed"}"#,
+            1.0,
+        ),
+        (
+            r#"worker_dropped_frames_total{worker="3",channel="worker"}"#,
+            2.0,
+        ),
+        (
+            r#"worker_transaction_size_bytes_bucket{worker="3",le="64"}"#,
+            1.0,
+        ),
+        (
+            r#"worker_transaction_size_bytes_bucket{worker="3",le="256"}"#,
+            3.0,
+        ),
+        (
+            r#"worker_transaction_size_bytes_bucket{worker="3",le="1048576"}"#,
+            3.0,
+        ),
+        (
+            r#"worker_transaction_size_bytes_bucket{worker="3",le="+Inf"}"#,
+            4.0,
+        ),
+        (
+            r#"worker_transaction_size_bytes_sum{worker="3"}"#,
+            2_000_210.0,
+        ),
+        (r#"worker_transaction_size_bytes_count{worker="3"}"#, 4.0),
+    ];
+    for (sample, value) in expected {
+        assert_eq!(samples.get(sample), Some(&value), "{}", sample);
+    }
+
+    // Without a worker id, samples are not labelled by worker.
+    let text = WorkerNetMetrics::default().metrics_text();
+    let samples: BTreeMap<_, _> = parse(&text).into_iter().collect();
+    assert_eq!(
+        samples.get("worker_transaction_size_bytes_count{}"),
+        Some(&0.0)
+    );
+    assert!(!text.contains("worker=\""));
+}
@@ -0,0 +1,201 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backlog::Backlog;
+use crate::batch_maker::{Batch, BatchMaker, StampedTransaction};
+use crate::common::{committee_with_base_port, keys, transaction};
+use crate::proofs::BatchDigester;
+use crate::quorum_waiter::QuorumWaiterMessage;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use consensus::{Consensus, ConsensusOptions};
+use crypto::{Digest, Hash as _, PublicKey};
+use primary::{Certificate, Header, Round};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{interval, timeout, Duration, Instant};
+
+/// How often the mock primaries certify a new round.
+const ROUND_INTERVAL: Duration = Duration::from_millis(10);
+
+// Fixture: runs the worker pipeline of an authority in-process, from the transactions its
+// handlers receive to the certificates its consensus commits, without networking. The batch maker
+// has no peers to broadcast its batches to. Mock primaries certify a round every
+// `ROUND_INTERVAL`: one certificate per authority, referencing all the certificates of the
+// previous round, ours carrying the digests of the batches sealed since our last one.
+struct Pipeline {
+    /// Our authority.
+    name: PublicKey,
+    /// Where our handlers send the transactions they receive.
+    tx_transactions: Sender<StampedTransaction>,
+    /// The certificates consensus committed, in order.
+    rx_committed: Receiver<Certificate>,
+    /// The batches we sealed, by digest.
+    batches: Arc<Mutex<HashMap<Digest, Batch>>>,
+}
+
+impl Pipeline {
+    fn spawn(committee: Committee, batch_size: usize) -> Self {
+        let (name, _) = keys().pop().unwrap();
+        let (tx_transactions, rx_transactions) = channel(100);
+        let (tx_batches, rx_batches) = channel(100);
+        BatchMaker::spawn(
+            batch_size,
+            /* max_batch_delay */ 1_000,
+            rx_transactions,
+            /* rx_preview */ channel(1).1,
+            tx_batches,
+            /* workers_addresses */ Vec::new(),
+            /* compression */ None,
+            /* transcoder */ None,
+            /* authenticator */ None,
+            Backlog::default(),
+            BatchDigester::default(),
+            /* receipts */ None,
+        );
+
+        let (tx_certificates, rx_certificates) = channel(100);
+        let (tx_primary, mut rx_primary) = channel(100);
+        let (tx_committed, rx_committed) = channel(100);
+        Consensus::spawn(
+            committee.clone(),
+            /* gc_depth */ 50,
+            rx_certificates,
+            tx_primary,
+            tx_committed.into(),
+            ConsensusOptions::default(),
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+        let batches = Arc::default();
+        let primaries = MockPrimaries {
+            name,
+            committee,
+            rx_batches,
+            tx_certificates,
+            batches: Arc::clone(&batches),
+        };
+        tokio::spawn(primaries.run());
+        Self {
+            name,
+            tx_transactions,
+            rx_committed,
+            batches,
+        }
+    }
+
+    /// Hands a transaction to the worker, as if a client sent it.
+    async fn submit(&self, transaction: Bytes) {
+        self.tx_transactions
+            .send((Instant::now(), transaction))
+            .await
+            .unwrap();
+    }
+
+    /// Waits for consensus to commit one of our certificates carrying batches, and returns them.
+    async fn next_committed_batches(&mut self) -> Vec<Batch> {
+        loop {
+            let certificate = self.rx_committed.recv().await.unwrap();
+            if certificate.origin() != self.name || certificate.header.payload.is_empty() {
+                continue;
+            }
+            let batches = self.batches.lock().unwrap();
+            return certificate
+                .header
+                .payload
+                .keys()
+                .map(|digest| batches[digest].clone())
+                .collect();
+        }
+    }
+}
+
+// Fixture: the primaries of the committee, certifying rounds in lockstep.
+struct MockPrimaries {
+    name: PublicKey,
+    committee: Committee,
+    rx_batches: Receiver<QuorumWaiterMessage>,
+    tx_certificates: Sender<Certificate>,
+    batches: Arc<Mutex<HashMap<Digest, Batch>>>,
+}
+
+impl MockPrimaries {
+    async fn run(mut self) {
+        let mut parents: BTreeSet<_> = Certificate::genesis(&self.committee)
+            .iter()
+            .map(|x| x.digest())
+            .collect();
+        let mut timer = interval(ROUND_INTERVAL);
+        for round in 1.. {
+            timer.tick().await;
+            let mut payload = Vec::new();
+            while let Ok(message) = self.rx_batches.try_recv() {
+                let digest = BatchDigester::default()
+                    .digest_serialized(&message.batch)
+                    .unwrap();
+                let batch = match bincode::deserialize(&message.batch).unwrap() {
+                    WorkerMessage::Batch(batch) => batch,
+                    _ => panic!("Unexpected message from the batch maker"),
+                };
+                self.batches.lock().unwrap().insert(digest.clone(), batch);
+                payload.push(digest);
+            }
+
+            let mut next_parents = BTreeSet::new();
+            for author in self.committee.authorities.keys() {
+                let payload = match *author == self.name {
+                    true => payload.iter().map(|x| (x.clone(), 0)).collect(),
+                    false => Default::default(),
+                };
+                let certificate = Self::certificate(*author, round, payload, parents.clone());
+                next_parents.insert(certificate.digest());
+                if self.tx_certificates.send(certificate).await.is_err() {
+                    return;
+                }
+            }
+            parents = next_parents;
+        }
+    }
+
+    fn certificate(
+        author: PublicKey,
+        round: Round,
+        payload: BTreeMap<Digest, WorkerId>,
+        parents: BTreeSet<Digest>,
+    ) -> Certificate {
+        let mut header = Header {
+            author,
+            round,
+            payload,
+            parents,
+            ..Header::default()
+        };
+        header.id = header.digest();
+        Certificate {
+            header,
+            ..Certificate::default()
+        }
+    }
+}
+
+#[tokio::test]
+async fn commit_submitted_transactions() {
+    // Batches of three transactions.
+    let mut pipeline = Pipeline::spawn(committee_with_base_port(0), 300);
+    let transactions: Vec<_> = (0..3u8)
+        .map(|i| {
+            let mut transaction = transaction().to_vec();
+            transaction[0] = i;
+            Bytes::from(transaction)
+        })
+        .collect();
+    for transaction in &transactions {
+        pipeline.submit(transaction.clone()).await;
+    }
+
+    // A certificate carrying the batch eventually commits.
+    let committed = timeout(Duration::from_secs(5), pipeline.next_committed_batches())
+        .await
+        .expect("Transactions were not committed");
+    assert_eq!(committed, vec![transactions]);
+}
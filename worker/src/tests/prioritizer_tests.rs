@@ -0,0 +1,73 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::keys;
+use crypto::Digest;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+// Fixture: the i-th batch request.
+fn request(i: usize) -> BatchRequest {
+    let (name, _) = keys().pop().unwrap();
+    (vec![Digest::default(); i], name, None)
+}
+
+#[tokio::test]
+async fn forward_requests_ahead_of_batches() {
+    let (tx_request, rx_request) = channel(10);
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_helper, mut rx_helper) = channel(10);
+    let (tx_processor, mut rx_processor) = channel(1);
+    Prioritizer::spawn(rx_request, rx_batch, tx_helper, tx_processor);
+
+    // The processor is stuck: batches pile up, but the requests sent after them get through.
+    for i in 0..5u8 {
+        tx_batch.send(vec![i]).await.unwrap();
+    }
+    for i in 0..3 {
+        tx_request.send(request(i)).await.unwrap();
+    }
+    for i in 0..3 {
+        let received = timeout(Duration::from_millis(500), rx_helper.recv()).await;
+        assert_eq!(received.unwrap().unwrap().0.len(), i);
+    }
+
+    // The batches are then all forwarded, in order.
+    for i in 0..5u8 {
+        assert_eq!(rx_processor.recv().await.unwrap(), vec![i]);
+    }
+}
+
+#[tokio::test]
+async fn serve_requests_faster_under_load() {
+    let (tx_request, rx_request) = channel(READ_AHEAD);
+    let (tx_batch, rx_batch) = channel(READ_AHEAD);
+    let (tx_helper, mut rx_helper) = channel(READ_AHEAD);
+    let (tx_processor, mut rx_processor) = channel(1);
+    Prioritizer::spawn(rx_request, rx_batch, tx_helper, tx_processor);
+
+    // A slow processor takes 10ms per batch.
+    let processed = tokio::spawn(async move {
+        let mut latest = Instant::now();
+        for _ in 0..20 {
+            rx_processor.recv().await.unwrap();
+            sleep(Duration::from_millis(10)).await;
+            latest = Instant::now();
+        }
+        latest
+    });
+
+    // Interleave batches and requests.
+    for i in 0..20 {
+        tx_batch.send(vec![i as u8]).await.unwrap();
+        tx_request.send(request(i)).await.unwrap();
+    }
+    let start = Instant::now();
+    for i in 0..20 {
+        assert_eq!(rx_helper.recv().await.unwrap().0.len(), i);
+    }
+    let requests = start.elapsed();
+
+    // The requests did not wait for the batches sent before them.
+    let batches = processed.await.unwrap() - start;
+    assert!(requests < batches / 2);
+}
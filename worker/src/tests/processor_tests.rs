@@ -2,7 +2,7 @@
 use super::*;
 use crate::common::batch;
 use crate::worker::WorkerMessage;
-use std::fs;
+use store::{Database, Family};
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -11,9 +11,7 @@ async fn hash_and_store() {
     let (tx_digest, mut rx_digest) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_hash_and_store";
-    let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let mut store = Database::new_in_memory().store(Family::Batches);
 
     // Spawn a new `Processor` instance.
     let id = 0;
@@ -23,6 +21,8 @@ async fn hash_and_store() {
         rx_batch,
         tx_digest,
         /* own_batch */ true,
+        BatchDigester::default(),
+        StoredBatches::default(),
     );
 
     // Send a batch to the `Processor`.
@@ -32,16 +32,41 @@ async fn hash_and_store() {
 
     // Ensure the `Processor` outputs the batch's digest.
     let output = rx_digest.recv().await.unwrap();
-    let digest = Digest(
-        Sha512::digest(&serialized).as_slice()[..32]
-            .try_into()
-            .unwrap(),
-    );
+    let digest = crypto::hash(&serialized);
     let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(digest.clone(), id)).unwrap();
     assert_eq!(output, expected);
 
     // Ensure the `Processor` correctly stored the batch.
-    let stored_batch = store.read(digest.to_vec()).await.unwrap();
+    let stored_batch = store.read(&digest).await.unwrap();
     assert!(stored_batch.is_some(), "The batch is not in the store");
     assert_eq!(stored_batch.unwrap(), serialized);
 }
+
+#[tokio::test]
+async fn store_under_merkle_root() {
+    let (tx_batch, rx_batch) = channel(1);
+    let (tx_digest, mut rx_digest) = channel(1);
+    let mut store = Database::new_in_memory().store(Family::Batches);
+
+    // Spawn a `Processor` digesting batches into the Merkle roots of their transactions.
+    let id = 0;
+    Processor::spawn(
+        id,
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        /* own_batch */ false,
+        BatchDigester::Merkle,
+        StoredBatches::default(),
+    );
+
+    // The batch is announced and stored under its Merkle root.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    tx_batch.send(serialized.clone()).await.unwrap();
+    let output = rx_digest.recv().await.unwrap();
+    let digest = crate::proofs::merkle_root(&batch());
+    let expected =
+        bincode::serialize(&WorkerPrimaryMessage::OthersBatch(digest.clone(), id)).unwrap();
+    assert_eq!(output, expected);
+    assert_eq!(store.read(&digest).await.unwrap(), Some(serialized));
+}
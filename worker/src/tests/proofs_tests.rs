@@ -0,0 +1,171 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys};
+use bytes::Bytes;
+use config::MERKLE_BATCHES_VERSION;
+use crypto::Signature;
+use primary::Header;
+
+// Fixture
+fn merkle_committee() -> Committee {
+    Committee {
+        protocol_version: MERKLE_BATCHES_VERSION,
+        ..committee_with_base_port(0)
+    }
+}
+
+// Fixture
+fn transactions() -> Vec<Transaction> {
+    (0..5u8).map(|i| Bytes::from(vec![i; 10])).collect()
+}
+
+// Fixture
+fn certified(batch: &Digest) -> Certificate {
+    let keys = keys();
+    let (author, secret) = &keys[0];
+    let mut header = Header {
+        author: *author,
+        round: 1,
+        payload: [(batch.clone(), 0)].iter().cloned().collect(),
+        ..Header::default()
+    };
+    header.id = header.digest();
+    header.signature = Signature::new(&header.id, secret);
+    let mut certificate = Certificate {
+        header,
+        votes: Vec::new(),
+    };
+    let digest = certificate.digest();
+    certificate.votes = keys
+        .iter()
+        .map(|(name, secret)| (*name, Signature::new(&digest, secret)))
+        .collect();
+    certificate
+}
+
+// Fixture
+fn inclusion_proof(index: usize) -> InclusionProof {
+    let batch = transactions();
+    let root = merkle_root(&batch);
+    InclusionProof {
+        transaction: batch[index].clone(),
+        batch: root.clone(),
+        proof: MerkleProof::new(&batch, index).unwrap(),
+        certificate: certified(&root),
+    }
+}
+
+#[test]
+fn verify_inclusion_proofs() {
+    let committee = merkle_committee();
+    for index in 0..transactions().len() {
+        let proof = inclusion_proof(index);
+        assert_eq!(proof.proof.path.len(), 3);
+        assert_eq!(proof.verify(&committee), Ok(()));
+    }
+
+    // Single transactions are their own root.
+    let batch = vec![Bytes::from_static(b"transaction")];
+    let proof = MerkleProof::new(&batch, 0).unwrap();
+    assert!(proof.path.is_empty());
+    assert_eq!(proof.root(&batch[0]), Some(merkle_root(&batch)));
+}
+
+#[test]
+fn reject_tampered_path() {
+    let committee = merkle_committee();
+
+    // A sibling of the path is altered.
+    let mut proof = inclusion_proof(3);
+    proof.proof.path[1] = Digest([0; 32]);
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::RootMismatch(..))
+    ));
+
+    // The path claims another position.
+    let mut proof = inclusion_proof(3);
+    proof.proof.index = 2;
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::RootMismatch(..))
+    ));
+
+    // The path is too short for the batch.
+    let mut proof = inclusion_proof(3);
+    proof.proof.path.pop();
+    assert_eq!(proof.verify(&committee), Err(ProofError::InvalidPath(5)));
+}
+
+#[test]
+fn reject_transaction_not_in_batch() {
+    let committee = merkle_committee();
+    assert!(MerkleProof::new(&transactions(), 5).is_none());
+
+    // Another transaction does not lead to the root.
+    let mut proof = inclusion_proof(0);
+    proof.transaction = Bytes::from_static(b"not in the batch");
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::RootMismatch(..))
+    ));
+
+    // Nor does claiming the position of a padding leaf.
+    let mut proof = inclusion_proof(4);
+    proof.proof.index = 5;
+    proof.proof.transactions = 6;
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::RootMismatch(..))
+    ));
+}
+
+#[test]
+fn reject_uncertified_batch() {
+    let committee = merkle_committee();
+
+    // The certificate references another batch.
+    let mut proof = inclusion_proof(0);
+    proof.certificate = certified(&Digest([1; 32]));
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::BatchNotCertified(..))
+    ));
+
+    // The certificate lacks a quorum.
+    let mut proof = inclusion_proof(0);
+    proof.certificate.votes.truncate(2);
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(ProofError::InvalidCertificate(..))
+    ));
+
+    // Committees of earlier protocol versions do not digest batches into Merkle roots.
+    let proof = inclusion_proof(0);
+    assert_eq!(
+        proof.verify(&committee_with_base_port(0)),
+        Err(ProofError::NotMerkleBatches)
+    );
+}
+
+#[test]
+fn digest_batches_by_protocol_version() {
+    let batch = transactions();
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch.clone())).unwrap();
+
+    let digester = BatchDigester::new(&committee_with_base_port(0));
+    assert_eq!(digester, BatchDigester::Serialized);
+    assert_eq!(
+        digester.digest(&batch, &serialized),
+        crypto::hash(&serialized)
+    );
+
+    let digester = BatchDigester::new(&merkle_committee());
+    assert_eq!(digester, BatchDigester::Merkle);
+    assert_eq!(digester.digest(&batch, &serialized), merkle_root(&batch));
+    assert_eq!(
+        digester.digest_serialized(&serialized),
+        Some(merkle_root(&batch))
+    );
+    assert_eq!(digester.digest_serialized(b"garbage"), None);
+}
@@ -4,8 +4,12 @@ use crate::common::{batch, committee_with_base_port, keys, listener};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
 use futures::future::try_join_all;
+use futures::sink::SinkExt as _;
 use network::ReliableSender;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::channel;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -15,7 +19,13 @@ async fn wait_for_quorum() {
     let committee = committee_with_base_port(7_000);
 
     // Spawn a `QuorumWaiter` instance.
-    QuorumWaiter::spawn(committee.clone(), /* stake */ 1, rx_message, tx_batch);
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        rx_message,
+        tx_batch,
+        /* backlog */ Backlog::default(),
+    );
 
     // Make a batch.
     let message = WorkerMessage::Batch(batch());
@@ -42,6 +52,7 @@ async fn wait_for_quorum() {
     let message = QuorumWaiterMessage {
         batch: serialized.clone(),
         handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+        span: Span::none(),
     };
     tx_message.send(message).await.unwrap();
 
@@ -52,3 +63,88 @@ async fn wait_for_quorum() {
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn ignore_digest_mismatches() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_100);
+
+    // Spawn a `QuorumWaiter` instance.
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        rx_message,
+        tx_batch,
+        /* backlog */ Backlog::default(),
+    );
+
+    // Spawn listeners that all find our batch corrupted, except one.
+    let mut names = Vec::new();
+    let mut addresses = Vec::new();
+    for (i, (name, address)) in committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .enumerate()
+    {
+        let address = address.worker_to_worker;
+        let reply = if i == 0 { &b"Ack"[..] } else { DIGEST_MISMATCH };
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(&address).await.unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+            while transport.next().await.is_some() {
+                transport.send(Bytes::from_static(reply)).await.unwrap();
+            }
+        });
+        names.push(name);
+        addresses.push(address);
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Broadcast a batch and forward it along with the handlers to the `QuorumWaiter`.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    let bytes = Bytes::from(serialized.clone());
+    let handlers = ReliableSender::new().broadcast(addresses, bytes).await;
+    let message = QuorumWaiterMessage {
+        batch: serialized,
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+        span: Span::none(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // A single acknowledgement is not enough for a quorum.
+    let output = timeout(Duration::from_millis(500), rx_batch.recv()).await;
+    assert!(
+        output.is_err(),
+        "Mismatches should not count towards the quorum"
+    );
+}
+
+#[tokio::test]
+async fn single_authority_is_a_quorum() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let committee = Committee::new_for_test(1, /* base_port */ 7_200, /* seed */ 0);
+
+    // Spawn a `QuorumWaiter` instance.
+    QuorumWaiter::spawn(
+        committee,
+        /* stake */ 1,
+        rx_message,
+        tx_batch,
+        /* backlog */ Backlog::default(),
+    );
+
+    // There is nobody to broadcast the batch to: it is delivered right away.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: Vec::new(),
+        span: Span::none(),
+    };
+    tx_message.send(message).await.unwrap();
+    let output = timeout(Duration::from_millis(500), rx_batch.recv()).await;
+    assert_eq!(output.unwrap(), Some(serialized));
+}
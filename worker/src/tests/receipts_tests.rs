@@ -0,0 +1,71 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Fixture
+fn tagged(key: u64, id: u64, transaction: &'static [u8]) -> Bytes {
+    Bytes::from([&key.to_be_bytes()[..], &id.to_be_bytes(), transaction].concat())
+}
+
+#[test]
+fn untag_transactions() {
+    let transaction = Bytes::from_static(b"transaction");
+    let (tag, untagged) = Receipts::untag(&tagged(1, 2, b"transaction")).unwrap();
+    assert_eq!(tag, (1, 2));
+    assert_eq!(untagged, transaction);
+
+    // The tag may prefix an empty transaction, but not be truncated.
+    assert!(Receipts::untag(&tagged(1, 2, b"")).is_some());
+    assert!(Receipts::untag(&Bytes::from_static(&[0; TAG_SIZE - 1])).is_none());
+}
+
+#[tokio::test]
+async fn send_receipts_on_commit() {
+    let receipts = Receipts::default();
+    let (key, mut rx_receipt) = receipts.open();
+    let (other, mut rx_other) = receipts.open();
+    assert_ne!(key, other);
+
+    // Tags of unknown channels are refused.
+    assert!(!receipts.tag((other + 1, 0), b"a"));
+
+    assert!(receipts.tag((key, 10), b"a"));
+    assert!(receipts.tag((other, 20), b"b"));
+    let batch = vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")];
+    let digest = Digest([1; 32]);
+
+    // Nothing is sent before the batch is sealed and committed.
+    receipts.committed(&digest, 2);
+    assert!(rx_receipt.try_recv().is_err());
+    receipts.sealed(&batch, &digest);
+    receipts.committed(&Digest([2; 32]), 2);
+    assert!(rx_receipt.try_recv().is_err());
+
+    receipts.committed(&digest, 4);
+    let expected = Receipt {
+        id: 10,
+        batch: digest.clone(),
+        round: 4,
+    };
+    assert_eq!(rx_receipt.recv().await, Some(expected));
+    assert_eq!(rx_other.recv().await.unwrap().id, 20);
+
+    // Each transaction gets a single receipt, even if its batch is committed again.
+    receipts.committed(&digest, 6);
+    assert!(rx_receipt.try_recv().is_err());
+    assert_eq!(receipts.pending.lock().unwrap().tags, 0);
+}
+
+#[tokio::test]
+async fn drop_receipts_of_closed_channels() {
+    let receipts = Receipts::default();
+    let (key, mut rx_receipt) = receipts.open();
+    assert!(receipts.tag((key, 1), b"a"));
+    receipts.close(key);
+
+    // The transaction is committed after the client went away.
+    let digest = Digest([1; 32]);
+    receipts.sealed(&[Bytes::from("a")], &digest);
+    receipts.committed(&digest, 2);
+    assert!(rx_receipt.recv().await.is_none());
+    assert!(!receipts.tag((key, 2), b"b"));
+}
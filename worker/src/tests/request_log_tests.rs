@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn peer() -> Option<SocketAddr> {
+    Some("127.0.0.1:1234".parse().unwrap())
+}
+
+#[test]
+fn sample_frames() {
+    let log = RequestLog::new(100);
+    let forwarded = Ok(Routing::Forwarded("batch maker"));
+    let logged = (0..100_000)
+        .filter(|_| log.record(peer(), &WorkerChannelType::Transaction, 512, &forwarded))
+        .count();
+    assert!((900..=1_100).contains(&logged));
+
+    // The rate can be changed at runtime (by any clone), and zero disables sampling.
+    log.clone().set_rate(10);
+    assert_eq!(log.rate(), 10);
+    let logged = (0..100_000)
+        .filter(|_| log.record(peer(), &WorkerChannelType::Worker, 512, &forwarded))
+        .count();
+    assert!((9_000..=11_000).contains(&logged));
+
+    log.set_rate(0);
+    assert!(!(0..1_000).any(|_| log.record(None, &WorkerChannelType::Worker, 512, &forwarded)));
+}
+
+#[test]
+fn always_log_rejected_frames() {
+    for rate in [0, 1_000] {
+        let log = RequestLog::new(rate);
+        for _ in 0..1_000 {
+            let rejected = Ok(Routing::Rejected("throttled"));
+            assert!(log.record(peer(), &WorkerChannelType::Worker, 64, &rejected));
+            let failed = Err(WorkerNetError::Decode("worker message", "garbage".into()));
+            assert!(log.record(peer(), &WorkerChannelType::Worker, 64, &failed));
+        }
+    }
+}
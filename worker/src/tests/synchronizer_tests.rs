@@ -1,7 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener};
-use std::fs;
+use store::{Database, Family};
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -14,9 +14,7 @@ async fn synchronize() {
     let committee = committee_with_base_port(9_000);
 
     // Create a new test store.
-    let path = ".db_test_synchronize";
-    let _ = fs::remove_dir_all(path);
-    let store = Store::new(path).unwrap();
+    let store = Database::new_in_memory().store(Family::Batches);
 
     // Spawn a `Synchronizer` instance.
     Synchronizer::spawn(
@@ -28,6 +26,8 @@ async fn synchronize() {
         /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
         /* sync_retry_nodes */ 3, // Not used in this test.
         rx_message,
+        /* transcoder */ None,
+        /* authenticator */ None,
     );
 
     // Spawn a listener to receive our batch requests.
@@ -45,3 +45,53 @@ async fn synchronize() {
     // Ensure the target receives the sync request.
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn retry_after_timeout() {
+    let (tx_message, rx_message) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(9_100);
+
+    // Create a new test store.
+    let store = Database::new_in_memory().store(Family::Batches);
+
+    // Spawn a `Synchronizer` instance.
+    Synchronizer::spawn(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        /* gc_depth */ 50, // Not used in this test.
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3, // All the other nodes.
+        rx_message,
+        /* transcoder */ None,
+        /* authenticator */ None,
+    );
+
+    // Spawn listeners to receive our batch requests: the target never replies with the batch.
+    let missing = vec![batch_digest()];
+    let message = WorkerMessage::BatchRequest(missing.clone(), name);
+    let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+    let (target, _) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+    let handles: Vec<_> = [target, other]
+        .iter()
+        .map(|x| {
+            let address = committee.worker(x, &id).unwrap().worker_to_worker;
+            listener(address, Some(serialized.clone()))
+        })
+        .collect();
+
+    // Send a sync request.
+    let message = PrimaryWorkerMessage::Synchronize(missing, target);
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the target receives the sync request, and another node receives it once we timed out.
+    for handle in handles {
+        assert!(handle.await.is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch, batch_digest, keys, serialized_batch, transaction};
+use prost::encoding::{encode_key, encode_varint, WireType};
+
+// Fixture
+fn batch_request() -> WorkerMessage {
+    let (name, _) = keys().pop().unwrap();
+    WorkerMessage::BatchRequest(vec![batch_digest()], name)
+}
+
+#[test]
+fn round_trip() {
+    for message in [
+        WorkerMessage::Batch(batch()),
+        WorkerMessage::DigestedBatch(batch(), batch_digest()),
+        batch_request(),
+    ] {
+        let serialized = bincode::serialize(&message).unwrap();
+        let frame = WorkerTranscoder.encode(&serialized).unwrap();
+        assert_eq!(WorkerTranscoder.decode(frame).unwrap(), serialized);
+    }
+}
+
+#[test]
+fn codec_round_trip() {
+    fn round_trip<C: Codec<WorkerMessage>>(codec: C, message: &WorkerMessage) -> WorkerMessage {
+        codec.decode(codec.encode(message).unwrap()).unwrap()
+    }
+
+    for message in [
+        WorkerMessage::Batch(batch()),
+        WorkerMessage::DigestedBatch(batch(), batch_digest()),
+        batch_request(),
+    ] {
+        let expected = bincode::serialize(&message).unwrap();
+        for decoded in [
+            round_trip(BincodeCodec, &message),
+            round_trip(ProtoCodec, &message),
+        ] {
+            assert_eq!(bincode::serialize(&decoded).unwrap(), expected);
+        }
+    }
+
+    // The codecs do not understand each other's frames.
+    let frame = Codec::<WorkerMessage>::encode(&BincodeCodec, &batch_request()).unwrap();
+    assert!(Codec::<WorkerMessage>::decode(&ProtoCodec, frame).is_err());
+}
+
+#[test]
+fn preserve_batch_digest() {
+    let frame = WorkerTranscoder.encode(&serialized_batch()).unwrap();
+    let decoded = WorkerTranscoder.decode(frame).unwrap();
+    assert_eq!(crypto::hash(&decoded), batch_digest());
+}
+
+#[test]
+fn decode_version_1() {
+    // A batch of two transactions, as encoded by the first version of the wire format. Decoders
+    // must keep accepting it.
+    let frame = [
+        &[0x08, 0x01, 0x12, 0x06, 0x0a, 0x01][..],
+        b"a",
+        &[0x0a, 0x01],
+        b"b",
+    ]
+    .concat();
+    let expected = WorkerMessage::Batch(vec![Bytes::from("a"), Bytes::from("b")]);
+    let serialized = bincode::serialize(&expected).unwrap();
+    assert_eq!(
+        WorkerTranscoder.decode(frame.clone().into()).unwrap(),
+        serialized
+    );
+    assert_eq!(WorkerTranscoder.encode(&serialized).unwrap(), frame);
+}
+
+#[test]
+fn ignore_unknown_fields() {
+    // A newer encoder adds a field to the envelope.
+    let mut frame = proto::WorkerMessage::from(&batch_request()).encode_to_vec();
+    encode_key(100, WireType::Varint, &mut frame);
+    encode_varint(42, &mut frame);
+
+    let expected = bincode::serialize(&batch_request()).unwrap();
+    assert_eq!(WorkerTranscoder.decode(frame.into()).unwrap(), expected);
+}
+
+#[test]
+fn reject_unsupported_version() {
+    let mut message = proto::WorkerMessage::from(&batch_request());
+    message.version = WIRE_VERSION + 1;
+    assert!(matches!(
+        WorkerTranscoder.decode(message.encode_to_vec().into()),
+        Err(WireError::UnsupportedVersion(..))
+    ));
+
+    let frame = proto::TransactionFrame {
+        version: WIRE_VERSION + 1,
+        transaction: transaction(),
+    };
+    assert!(matches!(
+        TransactionTranscoder.decode(frame.encode_to_vec().into()),
+        Err(WireError::UnsupportedVersion(..))
+    ));
+}
+
+#[test]
+fn decode_transactions_without_copy() {
+    let frame = TransactionTranscoder.encode(&transaction()).unwrap();
+    let decoded = TransactionTranscoder.decode(frame.clone()).unwrap();
+    assert_eq!(decoded, transaction());
+    let range = frame.as_ptr_range();
+    assert!(range.contains(&decoded.as_ptr()));
+}
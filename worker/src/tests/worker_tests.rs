@@ -1,9 +1,21 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
-use network::SimpleSender;
-use primary::WorkerPrimaryMessage;
-use std::fs;
+use crate::certifications::CertifiedBatch;
+use crate::chunks::split_transaction;
+use crate::common::{
+    batch, batch_digest, committee_with_base_port, keys, listener, serialized_batch, transaction,
+};
+use config::{KeyPair, RequestBudget};
+use crypto::Hash as _;
+use futures::stream::StreamExt as _;
+use network::{FrameCodec, SimpleSender};
+use primary::{Certificate, Header, WorkerPrimaryMessage};
+use rand::rngs::StdRng;
+use rand::{RngCore as _, SeedableRng as _};
+use std::net::SocketAddr;
+use store::Database;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
@@ -16,12 +28,18 @@ async fn handle_clients_transactions() {
     };
 
     // Create a new test store.
-    let path = ".db_test_handle_clients_transactions";
-    let _ = fs::remove_dir_all(path);
-    let store = Store::new(path).unwrap();
+    let store = Database::new_in_memory();
 
     // Spawn a `Worker` instance.
-    Worker::spawn(name, id, committee.clone(), parameters, store);
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        WorkerOptions::default(),
+    )
+    .unwrap();
 
     // Spawn a network listener to receive our batch's digest.
     let primary_address = committee.primary(&name).unwrap().worker_to_primary;
@@ -31,15 +49,1335 @@ async fn handle_clients_transactions() {
     // Spawn enough workers' listeners to acknowledge our batches.
     for (_, addresses) in committee.others_workers(&name, &id) {
         let address = addresses.worker_to_worker;
-        let _ = listener(address, /* expected */ None);
+        let _handle = listener(address, /* expected */ None);
     }
 
     // Send enough transactions to create a batch.
     let mut network = SimpleSender::new();
     let address = committee.worker(&name, &id).unwrap().transactions;
-    network.send(address, Bytes::from(transaction())).await;
-    network.send(address, Bytes::from(transaction())).await;
+    network.send(address, transaction()).await;
+    network.send(address, transaction()).await;
 
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn forward_transactions_without_copy() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(1);
+    let handler = TxReceiverHandler::new(
+        tx_batch_maker,
+        Duration::from_millis(1_000),
+        /* coalesce */ false,
+        /* window */ 1_000,
+        /* receipts */ None,
+        NextLeader::new(committee_with_base_port(0)),
+        RequestLog::default(),
+    );
+
+    // Make a writer out of a local connection (the handler does not reply to clients).
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut writer, _) = Framed::new(stream, FrameCodec::default()).split();
+
+    // The transaction reaching the batch maker shares the buffer of the received frame.
+    let message = transaction();
+    handler
+        .dispatch(&mut writer, message.clone())
+        .await
+        .unwrap();
+    let (_, forwarded) = rx_batch_maker.recv().await.unwrap();
+    assert_eq!(forwarded, message);
+    assert_eq!(forwarded.as_ptr(), message.as_ptr());
+}
+
+#[tokio::test]
+async fn stamp_transactions_in_order() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address = "127.0.0.1:11504".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 1_000,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Send a few transactions over a single connection.
+    let start = Instant::now();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    for i in 0..5u8 {
+        client.send(Bytes::from(vec![i; 10])).await.unwrap();
+    }
+
+    // The transactions are untouched, and their stamps never go back in time.
+    let mut last = start;
+    for i in 0..5u8 {
+        let (received, transaction) = rx_batch_maker.recv().await.unwrap();
+        assert_eq!(transaction, Bytes::from(vec![i; 10]));
+        assert!(received >= last);
+        last = received;
+    }
+    assert!(last <= Instant::now());
+}
+
+#[tokio::test]
+async fn acknowledge_client_transactions() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address = "127.0.0.1:11503".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 1_000,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // A client asking for acknowledgements gets one per transaction, and the banner is not
+    // mistaken for a transaction.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client
+        .send(Bytes::from_static(TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    client.send(transaction()).await.unwrap();
+    client.send(transaction()).await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+        assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+    }
+
+    // Other clients are never replied to, even if a later transaction looks like the banner.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client.send(transaction()).await.unwrap();
+    client
+        .send(Bytes::from_static(TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, TRANSACTION_BANNER);
+    let reply = timeout(Duration::from_millis(200), client.next()).await;
+    assert!(
+        reply.is_err(),
+        "Clients should not receive acknowledgements"
+    );
+}
+
+#[tokio::test]
+async fn coalesce_client_acknowledgements() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address = "127.0.0.1:11508".parse::<SocketAddr>().unwrap();
+    let options = ReceiverOptions {
+        flush: Some(FlushWindow {
+            window: Duration::from_millis(100),
+            max_pending: 100,
+            timeout: Duration::from_millis(1_000),
+        }),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ true,
+            /* window */ 1_000,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        options,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The ACKs of a burst of transactions are held for the window, then all written.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client
+        .send(Bytes::from_static(TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    let start = Instant::now();
+    for _ in 0..5 {
+        client.send(transaction()).await.unwrap();
+    }
+    for _ in 0..5 {
+        assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+    }
+    for _ in 0..5 {
+        assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+    }
+    assert!(start.elapsed() >= Duration::from_millis(90));
+}
+
+#[tokio::test]
+async fn close_connection_on_write_timeout() {
+    // Spawn a worker receiver that gives up quickly on unresponsive peers.
+    let address = "127.0.0.1:11500".parse::<SocketAddr>().unwrap();
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, _rx_processor) = channel(1);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(100),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Connect with a client that never reads its ACKs (and has a tiny receive buffer).
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.set_recv_buffer_size(1_024).unwrap();
+    let stream = socket.connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+
+    // Keep sending messages: the replies eventually fill the socket buffers, the receiver times
+    // out and closes the connection, and our writes start failing.
+    let corrupted = WorkerMessage::DigestedBatch(vec![transaction()], batch_digest());
+    let serialized = Bytes::from(bincode::serialize(&corrupted).unwrap());
+    let closed = timeout(Duration::from_secs(10), async {
+        loop {
+            if transport.send(serialized.clone()).await.is_err() {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok());
+}
+
+#[tokio::test]
+async fn observer_receives_messages_without_acks() {
+    // Spawn a worker receiver.
+    let address = "127.0.0.1:11501".parse::<SocketAddr>().unwrap();
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(10).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Connect as an observer.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut observer = Framed::new(stream, LengthDelimitedCodec::new());
+    observer
+        .send(Bytes::from_static(OBSERVER_BANNER))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Another worker sends us a batch, which we acknowledge and process.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    peer.send(Bytes::from(serialized_batch())).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+
+    // The observer receives a copy of the batch, and nothing else.
+    let received = observer.next().await.unwrap().unwrap();
+    assert_eq!(received, serialized_batch());
+    let next = timeout(Duration::from_millis(200), observer.next()).await;
+    assert!(next.is_err(), "Observers should not receive responses");
+}
+
+#[tokio::test]
+async fn preview_queued_transactions() {
+    // Spawn a `BatchMaker` that never seals its batch, and a worker receiver serving its previews.
+    let address = "127.0.0.1:11502".parse::<SocketAddr>().unwrap();
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_preview, rx_preview) = channel(1);
+    let (tx_message, _rx_message) = channel(1);
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000,
+        /* max_batch_delay */ 1_000_000,
+        rx_transaction,
+        rx_preview,
+        tx_message,
+        /* workers_addresses */ Vec::new(),
+        /* compression */ None,
+        /* transcoder */ None,
+        /* authenticator */ None,
+        /* backlog */ Backlog::default(),
+        BatchDigester::default(),
+        /* receipts */ None,
+    );
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, _rx_processor) = channel(1);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Queue a few transactions.
+    let transactions = vec![transaction(), Bytes::from(vec![1u8; 50])];
+    for tx in &transactions {
+        tx_transaction
+            .send((Instant::now(), tx.clone()))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The preview lists the queued transactions, in order.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport
+        .send(Bytes::from_static(PREVIEW_BANNER))
+        .await
+        .unwrap();
+    let reply = transport.next().await.unwrap().unwrap();
+    let preview: BatchPreview = bincode::deserialize(&reply).unwrap();
+    let expected: BatchPreview = transactions
+        .iter()
+        .map(|tx| (crypto::hash(tx), tx.len()))
+        .collect();
+    assert_eq!(preview, expected);
+}
+
+#[tokio::test]
+async fn reject_worker_messages_from_outside_committee() {
+    // Spawn a worker receiver that only accepts worker messages from the committee (on 127.0.0.1).
+    let address = "127.0.0.1:11506".parse::<SocketAddr>().unwrap();
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(10).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: Some(Membership::new(committee_with_base_port(11_600).ips())),
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Connects to the receiver from the specified IP.
+    let connect = |ip: &str| {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(format!("{}:0", ip).parse().unwrap()).unwrap();
+        async move {
+            let stream = socket.connect(address).await.unwrap();
+            Framed::new(stream, LengthDelimitedCodec::new())
+        }
+    };
+
+    // A peer outside the committee may still observe the messages we receive.
+    let mut observer = connect("127.0.0.2").await;
+    observer
+        .send(Bytes::from_static(OBSERVER_BANNER))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // But its batches are neither acknowledged nor processed, and its connection is closed.
+    let mut outsider = connect("127.0.0.2").await;
+    outsider
+        .send(Bytes::from(serialized_batch()))
+        .await
+        .unwrap();
+    assert!(outsider.next().await.is_none());
+    let processed = timeout(Duration::from_millis(200), rx_processor.recv()).await;
+    assert!(
+        processed.is_err(),
+        "Batches from outside the committee should be rejected"
+    );
+
+    // The batches of the committee are processed, and reach the observer.
+    let mut peer = connect("127.0.0.1").await;
+    peer.send(Bytes::from(serialized_batch())).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+    assert_eq!(observer.next().await.unwrap().unwrap(), serialized_batch());
+}
+
+#[tokio::test]
+async fn authenticate_workers_without_secret() {
+    let (name, _) = keys().pop().unwrap();
+    let parameters = Parameters {
+        authenticate_workers: true,
+        ..Parameters::default()
+    };
+    let result = Worker::spawn(
+        name,
+        /* id */ 0,
+        committee_with_base_port(11_850),
+        parameters,
+        Database::new_in_memory(),
+        WorkerOptions::default(),
+    );
+    assert!(matches!(result, Err(ConfigError::MissingWorkerSecret)));
+}
+
+#[tokio::test]
+async fn authenticate_worker_messages() {
+    // Spawn a worker receiver that only accepts worker messages from authenticated authorities.
+    let committee = committee_with_base_port(11_800);
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let address = committee.worker(&name, &0).unwrap().worker_to_worker;
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    let (tx_transactions, mut rx_transactions) = channel(1);
+    let options = ReceiverOptions {
+        authenticator: Some(Arc::new(CommitteeAuthenticator::new(
+            name,
+            secret,
+            committee.clone(),
+        ))),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: true,
+            identity: None,
+            peer: None,
+        },
+        options,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The batches of peers that do not authenticate are neither acknowledged nor processed.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut outsider = Framed::new(stream, LengthDelimitedCodec::new());
+    outsider
+        .send(Bytes::from(serialized_batch()))
+        .await
+        .unwrap();
+    assert!(outsider.next().await.is_none());
+
+    // But they may still send client transactions.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client
+        .send(Bytes::from_static(MULTIPLEX_BANNER))
+        .await
+        .unwrap();
+    let frame = Bytes::from([&[TRANSACTION_STREAM], &transaction()[..]].concat());
+    client.send(frame).await.unwrap();
+    let reply = client.next().await.unwrap().unwrap();
+    assert_eq!(
+        reply,
+        Bytes::from([&[TRANSACTION_STREAM], &b"Ack"[..]].concat())
+    );
+    let (_, received) = rx_transactions.recv().await.unwrap();
+    assert_eq!(received, transaction());
+
+    // Nor are the batches of authorities outside the committee.
+    let KeyPair { name, secret } = KeyPair::new_for_test(/* seed */ 1, 0);
+    let authenticator = CommitteeAuthenticator::new(name, secret, committee.clone());
+    let mut sender = SimpleSender::new().with_authenticator(Some(Arc::new(authenticator)));
+    sender.send(address, Bytes::from(serialized_batch())).await;
+    let processed = timeout(Duration::from_millis(200), rx_processor.recv()).await;
+    assert!(
+        processed.is_err(),
+        "Batches from outside the committee should be rejected"
+    );
+
+    // The batches of the authorities of the committee are processed.
+    let (name, secret) = keys.pop().unwrap();
+    let authenticator = CommitteeAuthenticator::new(name, secret, committee);
+    let mut sender =
+        network::ReliableSender::new().with_authenticator(Some(Arc::new(authenticator)));
+    let cancel_handler = sender.send(address, Bytes::from(serialized_batch())).await;
+    assert_eq!(cancel_handler.await.unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+}
+
+#[tokio::test]
+async fn receive_protobuf_batches() {
+    // Spawn a worker receiver accepting protobuf.
+    let address = "127.0.0.1:11507".parse::<SocketAddr>().unwrap();
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    let options = ReceiverOptions {
+        transcoder: Some(Arc::new(WorkerTranscoder)),
+        ..ReceiverOptions::default()
+    };
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        options,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Another worker sends us a batch in protobuf. We process it as if it was sent in bincode.
+    let mut sender = network::ReliableSender::new()
+        .with_transcoder(Some(Arc::new(WorkerTranscoder) as SharedTranscoder));
+    let cancel_handler = sender.send(address, Bytes::from(serialized_batch())).await;
+    assert_eq!(cancel_handler.await.unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+}
+
+#[tokio::test]
+async fn verify_digest_of_batches() {
+    // Spawn a worker receiver.
+    let address = "127.0.0.1:11509".parse::<SocketAddr>().unwrap();
+    let (tx_helper, _rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Another worker sends us a batch whose content does not match its digest. We reject it.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    let corrupted = WorkerMessage::DigestedBatch(vec![transaction()], batch_digest());
+    let serialized = bincode::serialize(&corrupted).unwrap();
+    peer.send(Bytes::from(serialized)).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), DIGEST_MISMATCH);
+    let processed = timeout(Duration::from_millis(200), rx_processor.recv()).await;
+    assert!(processed.is_err(), "Corrupted batches should be rejected");
+
+    // The batches matching their digest are processed (as plain batches, over the same connection).
+    let message = WorkerMessage::DigestedBatch(batch(), batch_digest());
+    let serialized = bincode::serialize(&message).unwrap();
+    peer.send(Bytes::from(serialized)).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+}
+
+#[tokio::test]
+async fn close_connection_on_violations() {
+    // Spawn a worker receiver with tight limits.
+    let address = "127.0.0.1:11510".parse::<SocketAddr>().unwrap();
+    let (tx_helper, mut rx_helper) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
+    let violations = PeerViolations::default();
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits {
+                max_worker_message_size: 1_000,
+                max_batch_transactions: 10,
+                max_sync_digests: 5,
+                ..MessageLimits::default()
+            },
+            violations: violations.clone(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Adversarial frames: oversized messages, a length claim far beyond the frame, too many
+    // transactions or digests, and garbage.
+    let serialize = |message: &WorkerMessage| bincode::serialize(message).unwrap();
+    let mut length_claim = serialize(&WorkerMessage::Batch(vec![Bytes::new()]));
+    length_claim.truncate(length_claim.len() - 8);
+    length_claim.extend_from_slice(&(1u64 << 60).to_le_bytes());
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut frames = vec![
+        serialize(&WorkerMessage::Batch(vec![Bytes::from(vec![0u8; 2_000])])),
+        length_claim,
+        serialize(&WorkerMessage::Batch(vec![transaction(); 11])),
+        serialize(&WorkerMessage::DigestedBatch(
+            vec![transaction(); 11],
+            batch_digest(),
+        )),
+        serialize(&WorkerMessage::BatchRequest(
+            vec![batch_digest(); 6],
+            PublicKey::default(),
+        )),
+    ];
+    for _ in 0..20 {
+        let mut garbage = vec![0u8; (rng.next_u32() % 100 + 1) as usize];
+        rng.fill_bytes(&mut garbage);
+        frames.push(garbage);
+    }
+
+    // Each of them closes the connection without a reply, is counted, and is not processed.
+    for (i, frame) in frames.iter().enumerate() {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+        peer.send(Bytes::from(frame.clone())).await.unwrap();
+        match timeout(Duration::from_secs(1), peer.next()).await {
+            Ok(None) | Ok(Some(Err(_))) => (),
+            x => panic!("Frame {} did not close the connection: {:?}", i, x),
+        }
+        let ip = "127.0.0.1".parse().unwrap();
+        assert_eq!(violations.count(&ip), i as u64 + 1);
+    }
+    assert!(rx_processor.try_recv().is_err());
+    assert!(rx_helper.try_recv().is_err());
+
+    // Messages within the limits are still processed.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    peer.send(Bytes::from(serialized_batch())).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+}
+
+#[tokio::test]
+async fn throttle_batch_requests_over_budget() {
+    // Spawn a worker receiver with a tight request budget.
+    let address = "127.0.0.1:11511".parse::<SocketAddr>().unwrap();
+    let (tx_helper, mut rx_helper) = channel(10);
+    let budgets = RequestBudgets::new(RequestBudget {
+        requests_per_second: 1,
+        request_burst: 3,
+        ..RequestBudget::default()
+    });
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor: channel(1).0,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: budgets.clone(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Connects to the receiver from the specified IP.
+    let connect = |ip: &str| {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(format!("{}:0", ip).parse().unwrap()).unwrap();
+        async move {
+            let stream = socket.connect(address).await.unwrap();
+            Framed::new(stream, LengthDelimitedCodec::new())
+        }
+    };
+    let request = WorkerMessage::BatchRequest(vec![batch_digest()], PublicKey::default());
+    let request = Bytes::from(bincode::serialize(&request).unwrap());
+
+    // The abusive peer is served until it exhausts its budget, then throttled.
+    let mut abusive = connect("127.0.0.1").await;
+    for _ in 0..3 {
+        abusive.send(request.clone()).await.unwrap();
+        assert_eq!(abusive.next().await.unwrap().unwrap(), "Ack");
+        assert!(rx_helper.recv().await.is_some());
+    }
+    abusive.send(request.clone()).await.unwrap();
+    assert_eq!(abusive.next().await.unwrap().unwrap(), THROTTLED);
+    assert!(rx_helper.try_recv().is_err());
+
+    // The other peers are still served.
+    let mut honest = connect("127.0.0.2").await;
+    honest.send(request.clone()).await.unwrap();
+    assert_eq!(honest.next().await.unwrap().unwrap(), "Ack");
+    let (_, _, peer) = rx_helper.recv().await.unwrap();
+    assert_eq!(peer, Some("127.0.0.2".parse().unwrap()));
+
+    let throttled: Vec<_> = budgets.snapshot().iter().map(|x| x.1.throttled).collect();
+    assert_eq!(throttled, vec![1, 0]);
+}
+
+#[tokio::test]
+async fn demultiplex_worker_messages_and_transactions() {
+    // Spawn a worker receiver.
+    let address = "127.0.0.1:11512".parse::<SocketAddr>().unwrap();
+    let (tx_helper, mut rx_helper) = channel(10);
+    let (tx_processor, mut rx_processor) = channel(10);
+    let (tx_transactions, mut rx_transactions) = channel(10);
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper,
+            tx_processor,
+            tx_observers: broadcast::channel(1).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Interleave worker messages and transactions on a single multiplexed connection.
+    let tagged = |stream: u8, frame: &[u8]| Bytes::from([&[stream], frame].concat());
+    let request = WorkerMessage::BatchRequest(vec![batch_digest()], PublicKey::default());
+    let frames = vec![
+        tagged(TRANSACTION_STREAM, &[1u8; 10]),
+        tagged(WORKER_STREAM, &serialized_batch()),
+        tagged(TRANSACTION_STREAM, &[2u8; 10]),
+        tagged(WORKER_STREAM, &bincode::serialize(&request).unwrap()),
+    ];
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    peer.send(Bytes::from_static(MULTIPLEX_BANNER))
+        .await
+        .unwrap();
+    for frame in frames {
+        let stream = frame[0];
+        peer.send(frame).await.unwrap();
+
+        // Each reply carries the stream of its message.
+        let reply = peer.next().await.unwrap().unwrap();
+        assert_eq!(reply, tagged(stream, b"Ack"));
+    }
+
+    // Each message reached its output, in order.
+    let (_, transaction) = rx_transactions.recv().await.unwrap();
+    assert_eq!(transaction, Bytes::from(vec![1u8; 10]));
+    let (_, transaction) = rx_transactions.recv().await.unwrap();
+    assert_eq!(transaction, Bytes::from(vec![2u8; 10]));
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+    let (digests, _, _) = rx_helper.recv().await.unwrap();
+    assert_eq!(digests, vec![batch_digest()]);
+
+    // Frames of unknown streams close the connection.
+    peer.send(tagged(7, b"garbage")).await.unwrap();
+    assert!(peer.next().await.is_none());
+}
+
+#[tokio::test]
+async fn reply_with_next_leader() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(1);
+    let committee = committee_with_base_port(11_500);
+    let next_leader = NextLeader::new(committee.clone());
+    let address = "127.0.0.1:11513".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 1_000,
+            /* receipts */ None,
+            next_leader.clone(),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The leader matches the one consensus elects for the first leader round above the highest
+    // round our primary told us about.
+    for (round, expected) in [(0, 2), (4, 6), (3, 6)] {
+        next_leader.advance(round);
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+        client
+            .send(Bytes::from_static(LEADER_BANNER))
+            .await
+            .unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+        let leader: consensus::LeaderElection = bincode::deserialize(&reply).unwrap();
+        let elections = consensus::LeaderVector::new(&committee, expected..=expected).elections;
+        assert_eq!(vec![leader], elections);
+    }
+
+    // The banner never reaches the batch maker.
+    assert!(rx_batch_maker.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn advertise_transaction_window() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address = "127.0.0.1:11514".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 2,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The worker replies to the banner with its window, then acknowledges each transaction.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client
+        .send(Bytes::from_static(WINDOWED_TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    let window = client.next().await.unwrap().unwrap();
+    assert_eq!(&window[..], &2u64.to_be_bytes()[..]);
+    client.send(transaction()).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+}
+
+#[tokio::test]
+async fn serve_batch_proofs() {
+    let mut store = Database::new_in_memory().store(Family::Batches);
+    store.write(&batch_digest(), &serialized_batch()).await;
+    let certifications = Certifications::new(/* id */ 0, /* retention */ 50, store);
+    let address = "127.0.0.1:11515".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper: channel(1).0,
+            tx_processor: channel(1).0,
+            tx_observers: broadcast::channel(10).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: certifications.clone(),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Consensus committed our batch.
+    let (author, _) = keys().pop().unwrap();
+    let mut header = Header {
+        author,
+        round: 1,
+        payload: [(batch_digest(), 0)].iter().cloned().collect(),
+        ..Header::default()
+    };
+    header.id = header.digest();
+    let certificate = Certificate {
+        header: header.clone(),
+        ..Certificate::default()
+    }
+    .digest();
+    certifications.committed(header);
+
+    // The requester gets the batch and checks it against the digest of the certificate.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut requester = Framed::new(stream, LengthDelimitedCodec::new());
+    requester
+        .send(Bytes::from_static(BATCH_PROOF_BANNER))
+        .await
+        .unwrap();
+    let request = bincode::serialize(&batch_digest()).unwrap();
+    requester.send(Bytes::from(request)).await.unwrap();
+    let reply = requester.next().await.unwrap().unwrap();
+    let proof: Option<CertifiedBatch> = bincode::deserialize(&reply).unwrap();
+    let proof = proof.unwrap();
+    assert_eq!(proof.batch, batch());
+    assert_eq!(
+        proof.verify(&certificate, &BatchDigester::default()),
+        Ok(batch_digest())
+    );
+
+    // We have no proof for batches we do not know to be committed.
+    let request = bincode::serialize(&Digest::default()).unwrap();
+    requester.send(Bytes::from(request)).await.unwrap();
+    let reply = requester.next().await.unwrap().unwrap();
+    let proof: Option<CertifiedBatch> = bincode::deserialize(&reply).unwrap();
+    assert!(proof.is_none());
+}
+
+#[tokio::test]
+async fn reassemble_chunked_transactions() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address = "127.0.0.1:11516".parse::<SocketAddr>().unwrap();
+    Receiver::spawn(
+        address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 1_000,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The client sends a transaction in three chunks, each of them acknowledged.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client
+        .send(Bytes::from_static(CHUNKED_TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    let large: Vec<u8> = (0..3_000).map(|i| i as u8).collect();
+    let chunks = split_transaction(0, &large, 1_000);
+    assert_eq!(chunks.len(), 3);
+    for chunk in chunks {
+        client.send(chunk).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+    }
+
+    // The batch maker receives the reassembled transaction, once.
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, large);
+    let next = timeout(Duration::from_millis(200), rx_batch_maker.recv()).await;
+    assert!(next.is_err(), "The transaction was forwarded twice");
+}
+
+#[tokio::test]
+async fn drain_departed_authorities() {
+    // Spawn a worker receiver only accepting worker messages from the committee (127.0.0.1 and
+    // 127.0.0.2), and a transactions receiver.
+    let address = "127.0.0.1:11517".parse::<SocketAddr>().unwrap();
+    let (tx_processor, mut rx_processor) = channel(10);
+    let membership = Membership::new(
+        ["127.0.0.1", "127.0.0.2"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+    );
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper: channel(1).0,
+            tx_processor,
+            tx_observers: broadcast::channel(10).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: Some(membership.clone()),
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: StoredBatches::default(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    let tx_address = "127.0.0.1:11518".parse::<SocketAddr>().unwrap();
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    Receiver::spawn(
+        tx_address,
+        TxReceiverHandler::new(
+            tx_batch_maker,
+            Duration::from_millis(1_000),
+            /* coalesce */ false,
+            /* window */ 1_000,
+            /* receipts */ None,
+            NextLeader::new(committee_with_base_port(0)),
+            RequestLog::default(),
+        ),
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Connects to a receiver from the specified IP.
+    let connect = |ip: &str, address: SocketAddr| {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(format!("{}:0", ip).parse().unwrap()).unwrap();
+        async move {
+            let stream = socket.connect(address).await.unwrap();
+            Framed::new(stream, LengthDelimitedCodec::new())
+        }
+    };
+
+    // Both authorities send batches, and the second one also sends a transaction.
+    let mut staying = connect("127.0.0.1", address).await;
+    let mut leaving = connect("127.0.0.2", address).await;
+    let mut client = connect("127.0.0.2", tx_address).await;
+    client
+        .send(Bytes::from_static(TRANSACTION_BANNER))
+        .await
+        .unwrap();
+    for peer in [&mut staying, &mut leaving] {
+        peer.send(Bytes::from(serialized_batch())).await.unwrap();
+        assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+        assert_eq!(rx_processor.recv().await.unwrap(), serialized_batch());
+    }
+    client.send(transaction()).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+
+    // The second authority leaves the committee: its worker connection closes, and it may not
+    // open another one.
+    membership.reconfigure(["127.0.0.1".parse().unwrap()].iter().copied().collect());
+    let closed = timeout(Duration::from_millis(1_000), leaving.next()).await;
+    assert!(matches!(closed, Ok(None)), "The connection was not drained");
+    let mut leaving = connect("127.0.0.2", address).await;
+    let _ = leaving.send(Bytes::from(serialized_batch())).await;
+    assert!(!matches!(leaving.next().await, Some(Ok(_))));
+
+    // The connections of the committee, and the transaction channels, are unaffected.
+    staying.send(Bytes::from(serialized_batch())).await.unwrap();
+    assert_eq!(staying.next().await.unwrap().unwrap(), "Ack");
+    client.send(transaction()).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), "Ack");
+    assert_eq!(rx_batch_maker.recv().await.unwrap().1, transaction());
+}
+
+#[tokio::test]
+async fn acknowledge_retransmitted_batches() {
+    // Spawn a worker receiver forwarding the batches to a processor.
+    let address = "127.0.0.1:11519".parse::<SocketAddr>().unwrap();
+    let (tx_processor, rx_processor) = channel(10);
+    let (tx_digest, mut rx_digest) = channel(10);
+    let stored_batches = StoredBatches::default();
+    Processor::spawn(
+        0,
+        Database::new_in_memory().store(Family::Batches),
+        rx_processor,
+        tx_digest,
+        /* own_batch */ false,
+        BatchDigester::default(),
+        stored_batches.clone(),
+    );
+    Receiver::spawn(
+        address,
+        WorkerReceiverHandler {
+            tx_helper: channel(1).0,
+            tx_processor,
+            tx_observers: broadcast::channel(10).0,
+            tx_preview: channel(1).0,
+            write_timeout: Duration::from_millis(1_000),
+            committee_ips: None,
+            limits: MessageLimits::default(),
+            violations: PeerViolations::default(),
+            budgets: RequestBudgets::default(),
+            digester: BatchDigester::default(),
+            tx_transactions: channel(1).0,
+            multiplexed: Arc::default(),
+            certifications: Certifications::new(
+                0,
+                50,
+                Database::new_in_memory().store(Family::Batches),
+            ),
+            proving: Arc::default(),
+            stored_batches: stored_batches.clone(),
+            request_log: RequestLog::default(),
+            authenticate: false,
+            identity: None,
+            peer: None,
+        },
+        ReceiverOptions::default(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The batch is stored (and announced) once.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    peer.send(Bytes::from(serialized_batch())).await.unwrap();
+    assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    let expected =
+        bincode::serialize(&WorkerPrimaryMessage::OthersBatch(batch_digest(), 0)).unwrap();
+    assert_eq!(rx_digest.recv().await.unwrap(), expected);
+    assert!(stored_batches.contains(&batch_digest()));
+
+    // Its retransmissions are acknowledged, but not stored again.
+    for _ in 0..2 {
+        peer.send(Bytes::from(serialized_batch())).await.unwrap();
+        assert_eq!(peer.next().await.unwrap().unwrap(), "Ack");
+    }
+    let stored = timeout(Duration::from_millis(200), rx_digest.recv()).await;
+    assert!(stored.is_err(), "Retransmitted batch was stored again");
+}
+
+#[tokio::test]
+async fn report_bind_failures() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_700);
+    assert!(Worker::check_addresses(&name, &id, &committee).is_ok());
+
+    // Another process holds the transactions address.
+    let mut taken = committee.worker(&name, &id).unwrap().transactions;
+    taken.set_ip("0.0.0.0".parse().unwrap());
+    let _listener = TcpListener::bind(taken).await.unwrap();
+    match Worker::check_addresses(&name, &id, &committee) {
+        Err(WorkerNetError::Bind(address, _)) => assert_eq!(address, taken),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn report_decode_failures() {
+    let handler = WorkerReceiverHandler {
+        tx_helper: channel(1).0,
+        tx_processor: channel(1).0,
+        tx_observers: broadcast::channel(1).0,
+        tx_preview: channel(1).0,
+        write_timeout: Duration::from_millis(1_000),
+        committee_ips: None,
+        limits: MessageLimits::default(),
+        violations: PeerViolations::default(),
+        budgets: RequestBudgets::default(),
+        digester: BatchDigester::default(),
+        tx_transactions: channel(1).0,
+        multiplexed: Arc::default(),
+        certifications: Certifications::new(
+            0,
+            50,
+            Database::new_in_memory().store(Family::Batches),
+        ),
+        proving: Arc::default(),
+        request_log: RequestLog::default(),
+        authenticate: false,
+        identity: None,
+        peer: None,
+    };
+
+    // Make a writer out of a local connection.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut writer, _) = Framed::new(stream, FrameCodec::default()).split();
+
+    // Garbage fails to decode, and callers can tell.
+    let error = handler
+        .dispatch(&mut writer, Bytes::from_static(&[0xff; 16]))
+        .await
+        .unwrap_err();
+    match error.downcast_ref::<WorkerNetError>() {
+        Some(WorkerNetError::Decode("worker message", _)) => (),
+        x => panic!("Unexpected error: {:?}", x),
+    }
+}
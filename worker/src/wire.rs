@@ -0,0 +1,167 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use crypto::{Digest, PublicKey};
+use network::{Transcoder, WireError, WIRE_VERSION};
+use prost::Message as _;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::{TryFrom, TryInto as _};
+
+#[cfg(test)]
+#[path = "tests/wire_tests.rs"]
+pub mod wire_tests;
+
+/// The messages generated from `proto/wire.proto`.
+pub mod proto {
+    tonic::include_proto!("narwhal.wire");
+}
+
+/// Serializes messages of type `T` to the frames of a connection, and back. The rest of the worker
+/// handles bincode (see `BincodeCodec`); connections that negotiated another encoding in their
+/// banner are transcoded at the edge of the network (see `WorkerTranscoder`), so handlers do not
+/// depend on the codec of their peers.
+pub trait Codec<T>: Send + Sync + 'static {
+    fn encode(&self, message: &T) -> Result<Bytes, WireError>;
+
+    fn decode(&self, frame: Bytes) -> Result<T, WireError>;
+}
+
+/// Bincode of our Rust types (the default encoding).
+pub struct BincodeCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, message: &T) -> Result<Bytes, WireError> {
+        bincode::serialize(message)
+            .map(Bytes::from)
+            .map_err(|e| WireError::SerializationError(e.to_string()))
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<T, WireError> {
+        bincode::deserialize(&frame).map_err(|e| WireError::SerializationError(e.to_string()))
+    }
+}
+
+/// Versioned protobuf messages (see `proto/wire.proto`).
+pub struct ProtoCodec;
+
+impl Codec<WorkerMessage> for ProtoCodec {
+    fn encode(&self, message: &WorkerMessage) -> Result<Bytes, WireError> {
+        Ok(Bytes::from(
+            proto::WorkerMessage::from(message).encode_to_vec(),
+        ))
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<WorkerMessage, WireError> {
+        let message = proto::WorkerMessage::decode(frame)
+            .map_err(|e| WireError::MalformedMessage(e.to_string()))?;
+        WorkerMessage::try_from(message)
+    }
+}
+
+/// Converts the messages exchanged between workers to and from protobuf. Batches are hashed in
+/// their bincode serialization, so peers that negotiated protobuf agree on their digests with the
+/// others.
+pub struct WorkerTranscoder;
+
+impl Transcoder for WorkerTranscoder {
+    fn encode(&self, message: &[u8]) -> Result<Bytes, WireError> {
+        // Deserialize from the slice directly rather than copying the batch into a `Bytes`.
+        let message: WorkerMessage = bincode::deserialize(message)
+            .map_err(|e| WireError::SerializationError(e.to_string()))?;
+        ProtoCodec.encode(&message)
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<Bytes, WireError> {
+        let message: WorkerMessage = ProtoCodec.decode(frame)?;
+        BincodeCodec.encode(&message)
+    }
+}
+
+/// Converts the frames of clients to and from protobuf. Transactions are not serialized, so the
+/// frames only wrap them (decoding does not copy them).
+pub struct TransactionTranscoder;
+
+impl Transcoder for TransactionTranscoder {
+    fn encode(&self, message: &[u8]) -> Result<Bytes, WireError> {
+        let frame = proto::TransactionFrame {
+            version: WIRE_VERSION,
+            transaction: Bytes::copy_from_slice(message),
+        };
+        Ok(Bytes::from(frame.encode_to_vec()))
+    }
+
+    fn decode(&self, frame: Bytes) -> Result<Bytes, WireError> {
+        let frame = proto::TransactionFrame::decode(frame)
+            .map_err(|e| WireError::MalformedMessage(e.to_string()))?;
+        WireError::check_version(frame.version)?;
+        Ok(frame.transaction)
+    }
+}
+
+impl From<&WorkerMessage> for proto::WorkerMessage {
+    fn from(message: &WorkerMessage) -> Self {
+        use proto::worker_message::Message;
+        let message = match message {
+            WorkerMessage::Batch(transactions) => Message::Batch(proto::Batch {
+                transactions: transactions.clone(),
+                digest: Bytes::new(),
+            }),
+            WorkerMessage::DigestedBatch(transactions, digest) => Message::Batch(proto::Batch {
+                transactions: transactions.clone(),
+                digest: Bytes::copy_from_slice(&digest.0),
+            }),
+            WorkerMessage::BatchRequest(digests, origin) => {
+                Message::BatchRequest(proto::BatchRequest {
+                    digests: digests
+                        .iter()
+                        .map(|x| Bytes::copy_from_slice(&x.0))
+                        .collect(),
+                    origin: Bytes::copy_from_slice(&origin.0),
+                })
+            }
+        };
+        Self {
+            version: WIRE_VERSION,
+            message: Some(message),
+        }
+    }
+}
+
+impl TryFrom<proto::WorkerMessage> for WorkerMessage {
+    type Error = WireError;
+
+    fn try_from(message: proto::WorkerMessage) -> Result<Self, Self::Error> {
+        use proto::worker_message::Message;
+        WireError::check_version(message.version)?;
+        match message.message {
+            Some(Message::Batch(batch)) if batch.digest.is_empty() => {
+                Ok(Self::Batch(batch.transactions))
+            }
+            Some(Message::Batch(batch)) => Ok(Self::DigestedBatch(
+                batch.transactions,
+                batch.digest[..]
+                    .try_into()
+                    .map(Digest)
+                    .map_err(|_| WireError::InvalidLength("batch.digest"))?,
+            )),
+            Some(Message::BatchRequest(request)) => Ok(Self::BatchRequest(
+                request
+                    .digests
+                    .iter()
+                    .map(|x| {
+                        x[..]
+                            .try_into()
+                            .map(Digest)
+                            .map_err(|_| WireError::InvalidLength("batch_request.digests"))
+                    })
+                    .collect::<Result<_, _>>()?,
+                request.origin[..]
+                    .try_into()
+                    .map(PublicKey)
+                    .map_err(|_| WireError::InvalidLength("batch_request.origin"))?,
+            )),
+            None => Err(WireError::MissingField("message")),
+        }
+    }
+}
@@ -1,22 +1,51 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::batch_maker::{Batch, BatchMaker, Transaction};
-use crate::helper::Helper;
+use crate::authentication::CommitteeAuthenticator;
+use crate::backlog::Backlog;
+use crate::batch_maker::{Batch, BatchMaker, BatchPreview, StampedTransaction};
+use crate::budgets::RequestBudgets;
+use crate::certifications::Certifications;
+use crate::chunks::Reassembler;
+use crate::coalescer::Coalescer;
+use crate::error::WorkerNetError;
+use crate::grpc::TransactionService;
+use crate::helper::{BatchRequest, Helper};
+use crate::leader::NextLeader;
+use crate::membership::Membership;
+use crate::mempool::{Mempool, TransactionParser};
+use crate::metrics::WorkerNetMetrics;
 use crate::primary_connector::PrimaryConnector;
+use crate::prioritizer::{Prioritizer, READ_AHEAD};
 use crate::processor::{Processor, SerializedBatchMessage};
+use crate::proofs::BatchDigester;
 use crate::quorum_waiter::QuorumWaiter;
+use crate::receipts::Receipts;
+use crate::request_log::{RequestLog, Routing};
+use crate::stored_batches::StoredBatches;
 use crate::synchronizer::Synchronizer;
+use crate::wire::{TransactionTranscoder, WorkerTranscoder};
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
-use crypto::{Digest, PublicKey};
+use config::{Committee, ConfigError, MessageLimits, Parameters, WorkerId};
+use crypto::{Digest, PublicKey, SecretKey};
 use futures::sink::SinkExt as _;
-use log::{error, info, warn};
-use network::{MessageHandler, Receiver, Writer};
+use log::{debug, info, warn};
+use network::{
+    deserialize_bounded, Compression, ConcurrencyLimit, FlushWindow, MessageHandler, PeerTraffic,
+    PeerViolations, Receiver, ReceiverOptions, SharedAuthenticator, SharedTranscoder, Writer,
+};
 use primary::PrimaryWorkerMessage;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
 use std::error::Error;
-use store::Store;
-use tokio::sync::mpsc::{channel, Sender};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use store::{Database, Family, Store};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{channel, Receiver as MpscReceiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
@@ -25,6 +54,71 @@ pub mod worker_tests;
 /// The default channel capacity for each channel of the worker.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The first frame sent by observers: connections that only consume the messages we receive from
+/// other workers (e.g., to build live dashboards), and never influence the protocol.
+pub const OBSERVER_BANNER: &[u8] = b"observer";
+
+/// The frame sent to request a preview of the batch we are assembling: the worker replies with the
+/// serialized `BatchPreview` of the transactions it has queued so far.
+pub const PREVIEW_BANNER: &[u8] = b"preview";
+
+/// The first frame of connections requesting batches along with the proof of the certificate that
+/// committed them: each of their next frames is the (serialized) digest of a batch, and the worker
+/// replies with the serialized `Option<CertifiedBatch>` of the batch.
+pub const BATCH_PROOF_BANNER: &[u8] = b"batch-proofs";
+
+/// The first frame sent by clients that want each of their transactions acknowledged (once handed
+/// to the `BatchMaker`). Other clients are never replied to.
+pub const TRANSACTION_BANNER: &[u8] = b"transactions";
+
+/// The first frame sent by clients that pipeline their transactions within a window: the worker
+/// replies with the size of the window (a big-endian `u64`), the number of transactions the client
+/// may send before waiting for their ACKs. We acknowledge each transaction once handed to the
+/// `BatchMaker`, which gives its credit back to the client.
+pub const WINDOWED_TRANSACTION_BANNER: &[u8] = b"windowed-transactions";
+
+/// The first frame sent by clients that want a receipt for each of their transactions once it is
+/// committed. Each of their next frames is a transaction prefixed by its tag: the key of a receipt
+/// channel of the client and a correlation id (see `TAG_SIZE`). We acknowledge them as well.
+pub const TAGGED_TRANSACTION_BANNER: &[u8] = b"tagged-transactions";
+
+/// The first frame sent by clients that split their transactions into chunks (e.g., transactions
+/// too large to fit in a frame): each of their next frames is a chunk (see `split_transaction`).
+/// We acknowledge each chunk, and forward each transaction once all its chunks arrived.
+pub const CHUNKED_TRANSACTION_BANNER: &[u8] = b"chunked-transactions";
+
+/// The first (and only) frame sent by clients to open a receipt channel: the worker replies with the
+/// key of the channel (a big-endian `u64`), and then with the serialized `Receipt` of each
+/// transaction tagged with this key, once it is committed.
+pub const RECEIPT_BANNER: &[u8] = b"receipts";
+
+/// The frame sent by clients to learn where to route their transactions: the worker replies with
+/// the serialized `LeaderElection` of the next leader round (see `NextLeader`).
+pub const LEADER_BANNER: &[u8] = b"leader";
+
+/// The first frame of connections multiplexing worker messages and client transactions: each of
+/// their next frames (and each of our replies) starts with the id of its stream.
+pub const MULTIPLEX_BANNER: &[u8] = b"multiplex";
+
+/// The stream of the worker messages of a multiplexed connection.
+pub const WORKER_STREAM: u8 = 0;
+
+/// The stream of the client transactions of a multiplexed connection. We acknowledge each of them.
+pub const TRANSACTION_STREAM: u8 = 1;
+
+/// The reply to a batch whose content does not match the digest it carries (instead of an ACK).
+pub const DIGEST_MISMATCH: &[u8] = b"DIGEST_MISMATCH";
+
+/// The reply to a batch request from a peer over its budget (instead of an ACK). We drop the
+/// request: the peer may send it again later.
+pub const THROTTLED: &[u8] = b"THROTTLED";
+
+/// How often to log the heaviest senders among the other workers (in ms).
+const TRAFFIC_REPORT_PERIOD: u64 = 60_000;
+
+/// How many messages we buffer for each observer. Slow observers miss the messages that do not fit.
+const OBSERVER_CAPACITY: usize = 1_000;
+
 /// The primary round number.
 // TODO: Move to the primary.
 pub type Round = u64;
@@ -37,6 +131,76 @@ pub type SerializedBatchDigestMessage = Vec<u8>;
 pub enum WorkerMessage {
     Batch(Batch),
     BatchRequest(Vec<Digest>, /* origin */ PublicKey),
+    /// A batch along with its digest (the hash of its serialization as a `WorkerMessage::Batch`),
+    /// so that its receivers can check it before acknowledging it.
+    DigestedBatch(Batch, Digest),
+}
+
+/// The kinds of connections a worker accepts, told apart by their first frame.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WorkerChannelType {
+    /// Another worker of the committee. We acknowledge and process its messages.
+    Worker,
+    /// A read-only observer. We stream it copies of the messages we receive, and never
+    /// acknowledge anything.
+    Observer,
+    /// A (one-off) request for the preview of our next batch. We reply with the preview only.
+    Preview,
+    /// A requester of batches along with their certificate (see `BATCH_PROOF_BANNER`).
+    BatchProofs,
+    /// A client (on the transactions address) asking us to acknowledge its transactions.
+    Transaction,
+    /// A client (on the transactions address) sending its transactions within a window (see
+    /// `WINDOWED_TRANSACTION_BANNER`).
+    WindowedTransaction,
+    /// A client (on the transactions address) sending tagged transactions (see
+    /// `TAGGED_TRANSACTION_BANNER`).
+    TaggedTransaction,
+    /// A client (on the transactions address) sending its transactions in chunks (see
+    /// `CHUNKED_TRANSACTION_BANNER`).
+    ChunkedTransaction,
+    /// A client (on the transactions address) opening a receipt channel. We only send it receipts.
+    Receipts,
+    /// A client (on the transactions address) asking for the next leader. We reply with it only.
+    Leader,
+    /// A peer multiplexing worker messages and client transactions (see `MULTIPLEX_BANNER`).
+    Multiplexed,
+}
+
+impl WorkerChannelType {
+    /// Classifies a connection from the frame it sent.
+    pub fn from_frame(frame: &[u8]) -> Self {
+        match frame {
+            OBSERVER_BANNER => Self::Observer,
+            PREVIEW_BANNER => Self::Preview,
+            BATCH_PROOF_BANNER => Self::BatchProofs,
+            TRANSACTION_BANNER => Self::Transaction,
+            WINDOWED_TRANSACTION_BANNER => Self::WindowedTransaction,
+            TAGGED_TRANSACTION_BANNER => Self::TaggedTransaction,
+            CHUNKED_TRANSACTION_BANNER => Self::ChunkedTransaction,
+            RECEIPT_BANNER => Self::Receipts,
+            LEADER_BANNER => Self::Leader,
+            MULTIPLEX_BANNER => Self::Multiplexed,
+            _ => Self::Worker,
+        }
+    }
+
+    /// Names the channel in the metrics of the worker.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Worker => "worker",
+            Self::Observer => "observer",
+            Self::Preview => "preview",
+            Self::BatchProofs => "batch_proofs",
+            Self::Transaction => "transaction",
+            Self::WindowedTransaction => "windowed_transaction",
+            Self::TaggedTransaction => "tagged_transaction",
+            Self::ChunkedTransaction => "chunked_transaction",
+            Self::Receipts => "receipts",
+            Self::Leader => "leader",
+            Self::Multiplexed => "multiplexed",
+        }
+    }
 }
 
 pub struct Worker {
@@ -48,32 +212,119 @@ pub struct Worker {
     committee: Committee,
     /// The configuration parameters.
     parameters: Parameters,
-    /// The persistent storage.
-    store: Store,
+    /// The persistent storage of the batches.
+    store: Store<Digest, SerializedBatchMessage>,
+    /// The work queued in the worker.
+    backlog: Backlog,
+    /// The violations of the peers sending us messages.
+    violations: PeerViolations,
+    /// Reads the sender and nonce of client transactions, to batch them in nonce order (if set).
+    parser: Option<Arc<dyn TransactionParser>>,
+    /// Adaptively limits the client connections served at once (if set).
+    connections: Option<ConcurrencyLimit>,
+    /// Tracks the tagged transactions until they are committed (if we follow a commit stream).
+    receipts: Option<Receipts>,
+    /// Tracks the rounds of our primary to tell clients the next leader.
+    next_leader: NextLeader,
+    /// The headers of the certificates that committed our batches.
+    certifications: Certifications,
+    /// Logs a sample of the frames we receive.
+    request_log: RequestLog,
+    /// The IP addresses of the active committee.
+    membership: Membership,
+    /// The digests of the batches we recently stored.
+    stored_batches: StoredBatches,
+    /// Authenticates the connections with the other workers (if enabled).
+    authenticator: Option<SharedAuthenticator>,
+}
+
+/// The optional parts of a worker. The default only accepts client transactions over TCP, batches
+/// them as received, and does not authenticate to the other workers.
+#[derive(Default)]
+pub struct WorkerOptions {
+    /// Also accepts client transactions over gRPC on this address (if set).
+    pub grpc_address: Option<SocketAddr>,
+    /// Reads the sender and nonce of client transactions, to batch the transactions of each client
+    /// in the order of their nonce (if set).
+    pub parser: Option<Arc<dyn TransactionParser>>,
+    /// The secret key of our authority, with which the worker proves its identity to the other
+    /// workers. It is required if `authenticate_workers` is set.
+    pub secret: Option<SecretKey>,
 }
 
 impl Worker {
+    /// Spawns the tasks of the worker, and returns its backlog (for monitoring). Fails if the
+    /// parameters require options that are not specified.
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
         parameters: Parameters,
-        store: Store,
-    ) {
+        store: Database,
+        options: WorkerOptions,
+    ) -> Result<Backlog, ConfigError> {
+        let WorkerOptions {
+            grpc_address,
+            parser,
+            secret,
+        } = options;
+
         // Define a worker instance.
+        let authenticator = match (parameters.authenticate_workers, secret) {
+            (true, Some(secret)) => Some(Arc::new(CommitteeAuthenticator::new(
+                name,
+                secret,
+                committee.clone(),
+            )) as SharedAuthenticator),
+            (true, None) => return Err(ConfigError::MissingWorkerSecret),
+            (false, _) => None,
+        };
+        let connections = parameters.adaptive_concurrency.map(ConcurrencyLimit::new);
+        let receipts = parameters.commit_stream.map(|address| {
+            let receipts = Receipts::default();
+            receipts.follow(address);
+            receipts
+        });
+        let next_leader = NextLeader::new(committee.clone());
+        let store = store.store(Family::Batches);
+        let certifications = Certifications::new(id, parameters.gc_depth, store.clone());
+        let request_log = RequestLog::new(parameters.request_log_rate)
+            .with_metrics(WorkerNetMetrics::new(Some(id)));
+        let membership = Membership::new(committee.ips());
         let worker = Self {
             name,
             id,
             committee,
             parameters,
             store,
+            backlog: Backlog::with_connections(connections.clone())
+                .with_request_log(request_log.clone()),
+            violations: PeerViolations::default(),
+            parser,
+            connections,
+            receipts,
+            next_leader,
+            certifications,
+            request_log,
+            membership,
+            stored_batches: StoredBatches::default(),
+            authenticator,
         };
 
         // Spawn all worker tasks.
         let (tx_primary, rx_primary) = channel(CHANNEL_CAPACITY);
+        let (tx_preview, rx_preview) = channel(CHANNEL_CAPACITY);
+        let (tx_mempool, rx_mempool) = channel(CHANNEL_CAPACITY);
+        let tx_mempool = worker.coalesce(tx_mempool);
         worker.handle_primary_messages();
-        worker.handle_clients_transactions(tx_primary.clone());
-        worker.handle_workers_messages(tx_primary);
+        worker.handle_clients_transactions(
+            tx_primary.clone(),
+            rx_preview,
+            tx_mempool.clone(),
+            rx_mempool,
+            grpc_address,
+        );
+        worker.handle_workers_messages(tx_primary, tx_preview, tx_mempool);
 
         // The `PrimaryConnector` allows the worker to send messages to its primary.
         PrimaryConnector::spawn(
@@ -96,6 +347,29 @@ impl Worker {
                 .transactions
                 .ip()
         );
+        Ok(worker.backlog)
+    }
+
+    /// Checks that the addresses the worker listens on (for its primary, the other workers, and the
+    /// clients) are free. Spawning the worker panics otherwise, so supervisors may check them first
+    /// (and retry or give up on `WorkerNetError::Bind`).
+    pub fn check_addresses(
+        name: &PublicKey,
+        id: &WorkerId,
+        committee: &Committee,
+    ) -> Result<(), WorkerNetError> {
+        let addresses = committee
+            .worker(name, id)
+            .expect("Our public key or worker id is not in the committee");
+        for mut address in [
+            addresses.primary_to_worker,
+            addresses.worker_to_worker,
+            addresses.transactions,
+        ] {
+            address.set_ip("0.0.0.0".parse().unwrap());
+            std::net::TcpListener::bind(address).map_err(|e| WorkerNetError::Bind(address, e))?;
+        }
+        Ok(())
     }
 
     /// Spawn all tasks responsible to handle messages from our primary.
@@ -112,7 +386,16 @@ impl Worker {
         Receiver::spawn(
             address,
             /* handler */
-            PrimaryReceiverHandler { tx_synchronizer },
+            PrimaryReceiverHandler {
+                tx_synchronizer,
+                limits: self.parameters.limits,
+                violations: self.violations.clone(),
+                next_leader: self.next_leader.clone(),
+                certifications: self.certifications.clone(),
+                membership: self.membership.clone(),
+                peer: None,
+            },
+            ReceiverOptions::default(),
         );
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
@@ -126,6 +409,8 @@ impl Worker {
             self.parameters.sync_retry_delay,
             self.parameters.sync_retry_nodes,
             /* rx_message */ rx_synchronizer,
+            self.transcoder(),
+            self.authenticator.clone(),
         );
 
         info!(
@@ -135,7 +420,14 @@ impl Worker {
     }
 
     /// Spawn all tasks responsible to handle clients transactions.
-    fn handle_clients_transactions(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
+    fn handle_clients_transactions(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        rx_preview: MpscReceiver<oneshot::Sender<BatchPreview>>,
+        tx_mempool: Sender<StampedTransaction>,
+        rx_mempool: MpscReceiver<StampedTransaction>,
+        grpc_address: Option<SocketAddr>,
+    ) {
         let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
         let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
@@ -147,9 +439,44 @@ impl Worker {
             .expect("Our public key or worker id is not in the committee")
             .transactions;
         address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
-            address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
+        if let Some(grpc_address) = grpc_address {
+            TransactionService::spawn(grpc_address, tx_mempool.clone());
+        }
+        let write_timeout = Duration::from_millis(self.parameters.write_timeout);
+        let flush = match self.parameters.ack_flush_window {
+            0 => None,
+            window => Some(FlushWindow {
+                window: Duration::from_millis(window),
+                max_pending: self.parameters.ack_flush_size,
+                timeout: write_timeout,
+            }),
+        };
+        let handler = TxReceiverHandler::new(
+            tx_mempool,
+            write_timeout,
+            flush.is_some(),
+            self.parameters.transaction_window,
+            self.receipts.clone(),
+            self.next_leader.clone(),
+            self.request_log.clone(),
+        );
+        let options = ReceiverOptions {
+            backlog: self.parameters.listen_backlog,
+            transcoder: Some(Arc::new(TransactionTranscoder)),
+            flush,
+            rules: Some(self.parameters.ip_rules.clone()),
+            limit: self.connections.clone(),
+            ..ReceiverOptions::default()
+        };
+        Receiver::spawn(address, handler, options);
+
+        // The `Mempool` releases the transactions of each client in the order of their nonce (if we
+        // have a parser for them, otherwise as received).
+        Mempool::spawn(
+            self.parser.clone(),
+            self.parameters.mempool,
+            /* rx_transaction */ rx_mempool,
+            tx_batch_maker,
         );
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
@@ -159,6 +486,7 @@ impl Worker {
             self.parameters.batch_size,
             self.parameters.max_batch_delay,
             /* rx_transaction */ rx_batch_maker,
+            rx_preview,
             /* tx_message */ tx_quorum_waiter,
             /* workers_addresses */
             self.committee
@@ -166,6 +494,12 @@ impl Worker {
                 .iter()
                 .map(|(name, addresses)| (*name, addresses.worker_to_worker))
                 .collect(),
+            self.compression(),
+            self.transcoder(),
+            self.authenticator.clone(),
+            self.backlog.clone(),
+            BatchDigester::new(&self.committee),
+            self.receipts.clone(),
         );
 
         // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
@@ -175,6 +509,7 @@ impl Worker {
             /* stake */ self.committee.stake(&self.name),
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
+            self.backlog.clone(),
         );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
@@ -185,6 +520,8 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ true,
+            BatchDigester::new(&self.committee),
+            self.stored_batches.clone(),
         );
 
         info!(
@@ -193,8 +530,48 @@ impl Worker {
         );
     }
 
+    /// Returns the channel the client transactions go through to reach the `Mempool`: one that
+    /// coalesces them first if enabled.
+    fn coalesce(&self, tx_mempool: Sender<StampedTransaction>) -> Sender<StampedTransaction> {
+        match self.parameters.coalesce_window {
+            0 => tx_mempool,
+            window => {
+                let (tx_coalescer, rx_coalescer) = channel(CHANNEL_CAPACITY);
+                Coalescer::spawn(
+                    Duration::from_millis(window),
+                    self.parameters.coalesce_size,
+                    rx_coalescer,
+                    tx_mempool,
+                );
+                tx_coalescer
+            }
+        }
+    }
+
+    /// The compression settings of the connections to other workers (if enabled).
+    fn compression(&self) -> Option<Compression> {
+        self.parameters
+            .compression_threshold
+            .map(|threshold| Compression { threshold })
+    }
+
+    /// Encodes the messages we send to other workers in protobuf, if enabled (and if they accept
+    /// it). We always accept protobuf from other workers and from clients.
+    fn transcoder(&self) -> Option<SharedTranscoder> {
+        self.parameters
+            .proto_encoding
+            .then(|| Arc::new(WorkerTranscoder) as SharedTranscoder)
+    }
+
     /// Spawn all tasks responsible to handle messages from other workers.
-    fn handle_workers_messages(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
+    fn handle_workers_messages(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        tx_preview: Sender<oneshot::Sender<BatchPreview>>,
+        tx_mempool: Sender<StampedTransaction>,
+    ) {
+        let (tx_request, rx_request) = channel(READ_AHEAD);
+        let (tx_batch, rx_batch) = channel(READ_AHEAD);
         let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
 
@@ -205,14 +582,46 @@ impl Worker {
             .expect("Our public key or worker id is not in the committee")
             .worker_to_worker;
         address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
-            address,
-            /* handler */
-            WorkerReceiverHandler {
-                tx_helper,
-                tx_processor,
-            },
-        );
+        let traffic = PeerTraffic::default();
+        traffic.log_periodically("Worker", Duration::from_millis(TRAFFIC_REPORT_PERIOD));
+        let budgets = RequestBudgets::new(self.parameters.request_budget);
+        budgets.log_periodically(Duration::from_millis(TRAFFIC_REPORT_PERIOD));
+        let handler = WorkerReceiverHandler {
+            tx_helper: tx_request,
+            tx_processor: tx_batch,
+            tx_observers: broadcast::channel(OBSERVER_CAPACITY).0,
+            tx_preview,
+            write_timeout: Duration::from_millis(self.parameters.write_timeout),
+            committee_ips: self
+                .parameters
+                .reject_non_committee
+                .then(|| self.membership.clone()),
+            limits: self.parameters.limits,
+            violations: self.violations.clone(),
+            budgets: budgets.clone(),
+            digester: BatchDigester::new(&self.committee),
+            tx_transactions: tx_mempool,
+            multiplexed: Arc::default(),
+            certifications: self.certifications.clone(),
+            proving: Arc::default(),
+            stored_batches: self.stored_batches.clone(),
+            request_log: self.request_log.clone(),
+            authenticate: self.authenticator.is_some(),
+            identity: None,
+            peer: None,
+        };
+        let options = ReceiverOptions {
+            traffic: Some(traffic),
+            transcoder: Some(Arc::new(WorkerTranscoder)),
+            rules: Some(self.parameters.ip_rules.clone()),
+            authenticator: self.authenticator.clone(),
+            ..ReceiverOptions::default()
+        };
+        Receiver::spawn(address, handler, options);
+
+        // The `Prioritizer` forwards the batch requests to the `Helper` ahead of the batches it
+        // forwards to the `Processor`.
+        Prioritizer::spawn(rx_request, rx_batch, tx_helper, tx_processor);
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
         Helper::spawn(
@@ -220,6 +629,10 @@ impl Worker {
             self.committee.clone(),
             self.store.clone(),
             /* rx_request */ rx_helper,
+            budgets,
+            self.compression(),
+            self.transcoder(),
+            self.authenticator.clone(),
         );
 
         // This `Processor` hashes and stores the batches we receive from the other workers. It then forwards the
@@ -230,6 +643,8 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ false,
+            BatchDigester::new(&self.committee),
+            self.stored_batches.clone(),
         );
 
         info!(
@@ -239,55 +654,648 @@ impl Worker {
     }
 }
 
-/// Defines how the network receiver handles incoming transactions.
-#[derive(Clone)]
+/// Defines how the network receiver handles incoming transactions. The receiver clones the handler
+/// for every connection, and each clone tracks whether its client asked for acknowledgements.
 struct TxReceiverHandler {
-    tx_batch_maker: Sender<Transaction>,
+    tx_batch_maker: Sender<StampedTransaction>,
+    /// How long to wait for the client to accept an ACK before closing the connection.
+    write_timeout: Duration,
+    /// Whether we received the first frame of the connection.
+    started: AtomicBool,
+    /// Whether the client opened the connection with the `TRANSACTION_BANNER`.
+    acknowledge: AtomicBool,
+    /// Whether we leave it to the receiver to flush our ACKs (see `FlushWindow`).
+    coalesce: bool,
+    /// The window we advertise to the clients opening a windowed transaction channel.
+    window: usize,
+    /// Whether the client opened the connection with the `TAGGED_TRANSACTION_BANNER`.
+    tagged: AtomicBool,
+    /// Tracks the tagged transactions (if we issue receipts).
+    receipts: Option<Receipts>,
+    /// Whether the client opened the connection with the `CHUNKED_TRANSACTION_BANNER`.
+    chunked: AtomicBool,
+    /// Reassembles the chunked transactions of the client.
+    chunks: Mutex<Reassembler>,
+    /// Tells the next leader.
+    next_leader: NextLeader,
+    /// Logs a sample of the frames we receive.
+    request_log: RequestLog,
+    /// The client of the connection.
+    peer: Option<SocketAddr>,
 }
 
-#[async_trait]
-impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
-        // Send the transaction to the batch maker.
+impl TxReceiverHandler {
+    fn new(
+        tx_batch_maker: Sender<StampedTransaction>,
+        write_timeout: Duration,
+        coalesce: bool,
+        window: usize,
+        receipts: Option<Receipts>,
+        next_leader: NextLeader,
+        request_log: RequestLog,
+    ) -> Self {
+        Self {
+            tx_batch_maker,
+            write_timeout,
+            started: AtomicBool::new(false),
+            acknowledge: AtomicBool::new(false),
+            coalesce,
+            window,
+            tagged: AtomicBool::new(false),
+            receipts,
+            chunked: AtomicBool::new(false),
+            chunks: Mutex::default(),
+            next_leader,
+            request_log,
+            peer: None,
+        }
+    }
+
+    /// Returns the kind of the connection, as of the specified frame (all the frames of client
+    /// connections but their banner are transactions).
+    fn channel(&self, frame: &[u8]) -> WorkerChannelType {
+        if self.tagged.load(Ordering::Relaxed) {
+            return WorkerChannelType::TaggedTransaction;
+        }
+        if self.chunked.load(Ordering::Relaxed) {
+            return WorkerChannelType::ChunkedTransaction;
+        }
+        match (
+            self.started.load(Ordering::Relaxed),
+            WorkerChannelType::from_frame(frame),
+        ) {
+            (true, _) | (false, WorkerChannelType::Worker) => WorkerChannelType::Transaction,
+            (false, channel) => channel,
+        }
+    }
+
+    /// Advertises our window to the client.
+    async fn serve_window(&self, writer: &mut Writer) -> Result<(), WorkerNetError> {
+        let window = Bytes::copy_from_slice(&(self.window as u64).to_be_bytes());
+        write(writer, window, self.write_timeout, "window").await
+    }
+
+    /// Replies with the leader of the next leader round.
+    async fn serve_leader(&self, writer: &mut Writer) -> Result<(), WorkerNetError> {
+        let bytes =
+            bincode::serialize(&self.next_leader.get()).expect("Failed to serialize next leader");
+        write(
+            writer,
+            Bytes::from(bytes),
+            self.write_timeout,
+            "next leader",
+        )
+        .await
+    }
+
+    /// Sends the client the key of a new receipt channel, and then the receipts of the transactions
+    /// tagged with it until the client goes away.
+    async fn serve_receipts(
+        &self,
+        writer: &mut Writer,
+        receipts: &Receipts,
+    ) -> Result<(), WorkerNetError> {
+        let (key, mut rx_receipt) = receipts.open();
+        let result = async {
+            let mut frame = Bytes::copy_from_slice(&key.to_be_bytes());
+            loop {
+                write(writer, frame, self.write_timeout, "receipt").await?;
+                frame = match rx_receipt.recv().await {
+                    Some(receipt) => bincode::serialize(&receipt)
+                        .expect("Failed to serialize receipt")
+                        .into(),
+                    None => return Ok(()),
+                };
+            }
+        }
+        .await;
+        receipts.close(key);
+        result
+    }
+}
+
+impl Clone for TxReceiverHandler {
+    /// Makes a handler for a new connection.
+    fn clone(&self) -> Self {
+        Self::new(
+            self.tx_batch_maker.clone(),
+            self.write_timeout,
+            self.coalesce,
+            self.window,
+            self.receipts.clone(),
+            self.next_leader.clone(),
+            self.request_log.clone(),
+        )
+    }
+}
+
+impl TxReceiverHandler {
+    /// Acknowledges a frame, if the client asked for it. When coalescing, the ACK is only written
+    /// when the receiver flushes the writer.
+    async fn acknowledge(&self, writer: &mut Writer) -> Result<(), WorkerNetError> {
+        if !self.acknowledge.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let ack = Bytes::from("Ack");
+        let written = match self.coalesce {
+            true => timeout(self.write_timeout, writer.feed(ack)).await,
+            false => timeout(self.write_timeout, writer.send(ack)).await,
+        };
+        match written {
+            Ok(result) => result.map_err(WorkerNetError::PeerClosed),
+            Err(_) => Err(WorkerNetError::Timeout("ACK")),
+        }
+    }
+
+    /// Handles a frame of the client, and returns what we did with it.
+    async fn route(&self, writer: &mut Writer, message: Bytes) -> Result<Routing, WorkerNetError> {
+        // Only the first frame of a connection may be a banner. Clients may only tag their
+        // transactions and open receipt channels if we issue receipts.
+        if !self.started.swap(true, Ordering::Relaxed) {
+            match (WorkerChannelType::from_frame(&message), &self.receipts) {
+                (WorkerChannelType::Transaction, _) => {
+                    self.acknowledge.store(true, Ordering::Relaxed);
+                    return Ok(Routing::Served("banner"));
+                }
+                (WorkerChannelType::WindowedTransaction, _) => {
+                    self.acknowledge.store(true, Ordering::Relaxed);
+                    self.serve_window(writer).await?;
+                    return Ok(Routing::Served("window"));
+                }
+                (WorkerChannelType::ChunkedTransaction, _) => {
+                    self.acknowledge.store(true, Ordering::Relaxed);
+                    self.chunked.store(true, Ordering::Relaxed);
+                    return Ok(Routing::Served("banner"));
+                }
+                (WorkerChannelType::TaggedTransaction, Some(_)) => {
+                    self.acknowledge.store(true, Ordering::Relaxed);
+                    self.tagged.store(true, Ordering::Relaxed);
+                    return Ok(Routing::Served("banner"));
+                }
+                (WorkerChannelType::Receipts, Some(receipts)) => {
+                    self.serve_receipts(writer, receipts).await?;
+                    return Ok(Routing::Served("receipts"));
+                }
+                (WorkerChannelType::Leader, _) => {
+                    self.serve_leader(writer).await?;
+                    return Ok(Routing::Served("leader"));
+                }
+                (WorkerChannelType::TaggedTransaction | WorkerChannelType::Receipts, None) => {
+                    return Err(WorkerNetError::Handshake("receipts are disabled".into()));
+                }
+                _ => (),
+            }
+        }
+
+        // Record the tag of tagged transactions, and batch them without it.
+        let message = match (self.tagged.load(Ordering::Relaxed), &self.receipts) {
+            (true, Some(receipts)) => match Receipts::untag(&message) {
+                Some((tag, transaction)) if receipts.tag(tag, &transaction) => transaction,
+                Some(((key, _), _)) => {
+                    return Err(WorkerNetError::Rejected(format!(
+                        "Unknown receipt channel {}",
+                        key
+                    )))
+                }
+                None => {
+                    return Err(WorkerNetError::Decode(
+                        "tagged transaction",
+                        "missing tag".into(),
+                    ))
+                }
+            },
+            _ => message,
+        };
+
+        // Reassemble chunked transactions: we only forward them once complete.
+        let message = match self.chunked.load(Ordering::Relaxed) {
+            true => {
+                let complete = self.chunks.lock().unwrap().add(message, Instant::now())?;
+                match complete {
+                    Some(transaction) => transaction,
+                    None => {
+                        self.acknowledge(writer).await?;
+                        return Ok(Routing::Served("chunk"));
+                    }
+                }
+            }
+            false => message,
+        };
+
+        // Send the transaction to the batch maker, stamped with the time we received it. We forward
+        // the frame's buffer as-is (without copying it) since this is on the hot path of every
+        // transaction.
         self.tx_batch_maker
-            .send(message.to_vec())
+            .send((Instant::now(), message))
             .await
-            .expect("Failed to send transaction");
+            .map_err(|_| WorkerNetError::ChannelClosed("batch maker"))?;
+
+        // Acknowledge the transaction (if the client asked for it).
+        self.acknowledge(writer).await?;
 
         // Give the change to schedule other tasks.
         tokio::task::yield_now().await;
-        Ok(())
+        Ok(Routing::Forwarded("batch maker"))
+    }
+}
+
+#[async_trait]
+impl MessageHandler for TxReceiverHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let (channel, size) = (self.channel(&message), message.len());
+        let result = self.route(writer, message).await;
+        self.request_log.record(self.peer, &channel, size, &result);
+        result.map(|_| ()).map_err(|e| e.into())
+    }
+
+    fn for_peer(&self, peer: SocketAddr) -> Self {
+        self.request_log.metrics().connection("transactions");
+        Self {
+            peer: Some(peer),
+            ..self.clone()
+        }
     }
 }
 
 /// Defines how the network receiver handles incoming workers messages.
 #[derive(Clone)]
 struct WorkerReceiverHandler {
-    tx_helper: Sender<(Vec<Digest>, PublicKey)>,
+    tx_helper: Sender<BatchRequest>,
     tx_processor: Sender<SerializedBatchMessage>,
+    /// Copies the messages we receive to the observers. Sending never blocks: observers that lag
+    /// behind lose messages.
+    tx_observers: broadcast::Sender<Bytes>,
+    /// Asks the `BatchMaker` for the transactions it queued for its next batch.
+    tx_preview: Sender<oneshot::Sender<BatchPreview>>,
+    /// How long to wait for the peer to accept our ACK before closing the connection.
+    write_timeout: Duration,
+    /// The IP addresses of the committee, if we only accept batches and batch requests from them.
+    /// We then also close the connections of the authorities that leave the committee.
+    committee_ips: Option<Membership>,
+    /// Bounds on the messages we accept.
+    limits: MessageLimits,
+    /// Counts the messages violating them (per peer).
+    violations: PeerViolations,
+    /// The budgets of the batch requests of the peers.
+    budgets: RequestBudgets,
+    /// How to digest the batches.
+    digester: BatchDigester,
+    /// Receives the client transactions of multiplexed connections.
+    tx_transactions: Sender<StampedTransaction>,
+    /// Whether the connection is multiplexed (see `MULTIPLEX_BANNER`).
+    multiplexed: Arc<AtomicBool>,
+    /// The headers of the certificates that committed our batches.
+    certifications: Certifications,
+    /// Whether the connection requests batch proofs (see `BATCH_PROOF_BANNER`).
+    proving: Arc<AtomicBool>,
+    /// The digests of the batches we recently stored.
+    stored_batches: StoredBatches,
+    /// Logs a sample of the frames we receive.
+    request_log: RequestLog,
+    /// Whether we only accept batches and batch requests from peers that authenticated as an
+    /// authority of the committee (see `CommitteeAuthenticator`).
+    authenticate: bool,
+    /// The authority the peer authenticated as (if any).
+    identity: Option<PublicKey>,
+    /// The peer of the connection.
+    peer: Option<SocketAddr>,
+}
+
+impl WorkerReceiverHandler {
+    /// Whether the peer may send us batches and batch requests.
+    fn accepted(&self) -> bool {
+        match (&self.committee_ips, self.peer) {
+            (None, _) => true,
+            (Some(membership), Some(peer)) => membership.contains(&peer.ip()),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Records a violation of the peer, and closes its connection.
+    fn violation(&self, error: WorkerNetError) -> Result<Routing, WorkerNetError> {
+        if let Some(peer) = self.peer {
+            self.violations.record(peer.ip(), &error.to_string());
+        }
+        Err(error)
+    }
+
+    /// Replies to a worker message, unless the peer does not read our replies.
+    async fn reply(&self, writer: &mut Writer, reply: &'static [u8]) -> Result<(), WorkerNetError> {
+        self.reply_on(WORKER_STREAM, writer, reply).await
+    }
+
+    /// Replies on a stream: multiplexed connections get the reply tagged with the stream id.
+    async fn reply_on(
+        &self,
+        stream: u8,
+        writer: &mut Writer,
+        reply: &'static [u8],
+    ) -> Result<(), WorkerNetError> {
+        let reply = match self.multiplexed.load(Ordering::Relaxed) {
+            true => Bytes::from([&[stream], reply].concat()),
+            false => Bytes::from_static(reply),
+        };
+        write(writer, reply, self.write_timeout, "reply").await
+    }
+
+    /// Hands a client transaction of a multiplexed connection to the batch maker, and
+    /// acknowledges it.
+    async fn relay_transaction(
+        &self,
+        writer: &mut Writer,
+        transaction: Bytes,
+    ) -> Result<Routing, WorkerNetError> {
+        self.tx_transactions
+            .send((Instant::now(), transaction))
+            .await
+            .map_err(|_| WorkerNetError::ChannelClosed("batch maker"))?;
+        self.reply_on(TRANSACTION_STREAM, writer, b"Ack").await?;
+        Ok(Routing::Forwarded("batch maker"))
+    }
+
+    /// Streams the messages we receive to an observer until it goes away.
+    async fn serve_observer(&self, writer: &mut Writer) -> Result<(), WorkerNetError> {
+        let mut rx_observer = self.tx_observers.subscribe();
+        loop {
+            match rx_observer.recv().await {
+                Ok(message) => write(writer, message, self.write_timeout, "to observer").await?,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Observer lagging behind, dropped {} messages", n)
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    /// Replies with the batch of the requested digest and the proof of the certificate that
+    /// committed it, if we have both.
+    async fn serve_proof(
+        &self,
+        writer: &mut Writer,
+        serialized: Bytes,
+    ) -> Result<(), WorkerNetError> {
+        let digest: Digest = bincode::deserialize(&serialized)
+            .map_err(|e| WorkerNetError::Decode("batch proof request", e.to_string()))?;
+        let proof = self.certifications.prove(&digest).await;
+        let bytes = bincode::serialize(&proof).expect("Failed to serialize batch proof");
+        write(
+            writer,
+            Bytes::from(bytes),
+            self.write_timeout,
+            "batch proof",
+        )
+        .await
+    }
+
+    /// Replies with the preview of the batch the `BatchMaker` is currently assembling.
+    async fn serve_preview(&self, writer: &mut Writer) -> Result<(), WorkerNetError> {
+        let (sender, receiver) = oneshot::channel();
+        self.tx_preview
+            .send(sender)
+            .await
+            .map_err(|_| WorkerNetError::ChannelClosed("batch maker"))?;
+        let preview = receiver
+            .await
+            .map_err(|_| WorkerNetError::ChannelClosed("batch maker"))?;
+        let bytes = bincode::serialize(&preview).expect("Failed to serialize batch preview");
+        write(
+            writer,
+            Bytes::from(bytes),
+            self.write_timeout,
+            "batch preview",
+        )
+        .await
+    }
+}
+
+impl WorkerReceiverHandler {
+    /// Returns the kind of the connection, as of the specified frame.
+    fn channel(&self, frame: &[u8]) -> WorkerChannelType {
+        match WorkerChannelType::from_frame(frame) {
+            WorkerChannelType::Worker if self.proving.load(Ordering::Relaxed) => {
+                WorkerChannelType::BatchProofs
+            }
+            WorkerChannelType::Worker if self.multiplexed.load(Ordering::Relaxed) => {
+                WorkerChannelType::Multiplexed
+            }
+            channel => channel,
+        }
+    }
+
+    /// Handles a frame of the peer, and returns what we did with it.
+    async fn route(
+        &self,
+        writer: &mut Writer,
+        serialized: Bytes,
+    ) -> Result<Routing, WorkerNetError> {
+        // Observers never send anything else than their banner: we do not reply to them and only
+        // stream them our messages. Previews are read-only and never reach the observers either.
+        match WorkerChannelType::from_frame(&serialized) {
+            WorkerChannelType::Observer => {
+                self.serve_observer(writer).await?;
+                return Ok(Routing::Served("observer"));
+            }
+            WorkerChannelType::Preview => {
+                self.serve_preview(writer).await?;
+                return Ok(Routing::Served("preview"));
+            }
+            WorkerChannelType::Multiplexed => {
+                self.multiplexed.store(true, Ordering::Relaxed);
+                return Ok(Routing::Served("banner"));
+            }
+            WorkerChannelType::BatchProofs => {
+                self.proving.store(true, Ordering::Relaxed);
+                return Ok(Routing::Served("banner"));
+            }
+            WorkerChannelType::Worker
+            | WorkerChannelType::Transaction
+            | WorkerChannelType::WindowedTransaction
+            | WorkerChannelType::TaggedTransaction
+            | WorkerChannelType::ChunkedTransaction
+            | WorkerChannelType::Receipts
+            | WorkerChannelType::Leader => (),
+        }
+
+        // Connections requesting batch proofs only send batch digests.
+        if self.proving.load(Ordering::Relaxed) {
+            self.serve_proof(writer, serialized).await?;
+            return Ok(Routing::Served("batch proof"));
+        }
+
+        // Demultiplex the frames of multiplexed connections. Client transactions are accepted from
+        // anyone, like on the transactions address.
+        let serialized = match self.multiplexed.load(Ordering::Relaxed) {
+            true => match serialized.first() {
+                Some(&WORKER_STREAM) => serialized.slice(1..),
+                Some(&TRANSACTION_STREAM) => {
+                    return self.relay_transaction(writer, serialized.slice(1..)).await
+                }
+                _ => {
+                    return self.violation(WorkerNetError::Decode(
+                        "multiplexed frame",
+                        "unknown stream".into(),
+                    ))
+                }
+            },
+            false => serialized,
+        };
+
+        // Close the connection of peers outside the committee (if we reject them), without
+        // acknowledging their message.
+        if !self.accepted() {
+            return Err(WorkerNetError::Rejected(match self.peer {
+                Some(peer) => format!(
+                    "Rejected worker message from {} (not in the committee)",
+                    peer
+                ),
+                None => "Rejected worker message from unknown peer".to_string(),
+            }));
+        }
+        if self.authenticate && self.identity.is_none() {
+            return Err(WorkerNetError::Rejected(match self.peer {
+                Some(peer) => format!("Rejected worker message from {} (not authenticated)", peer),
+                None => "Rejected worker message from unknown peer".to_string(),
+            }));
+        }
+
+        // Deserialize and check the message within our limits. Peers violating them are closed.
+        let message = match deserialize_bounded(&serialized, self.limits.max_worker_message_size) {
+            Ok(message) => message,
+            Err(e) => {
+                return self.violation(WorkerNetError::Decode("worker message", e.to_string()))
+            }
+        };
+        if let Err(reason) = check_worker_message(&message, &self.limits) {
+            return self.violation(WorkerNetError::Rejected(reason));
+        }
+
+        // Batches carrying their digest must match it: we reject corrupted ones before they are
+        // stored. The others are handled as plain batches, so that we store them under the same
+        // digest as the sender.
+        let (message, serialized, digest) = match message {
+            WorkerMessage::DigestedBatch(batch, digest) => {
+                let normalized = bincode::serialize(&WorkerMessage::Batch(batch.clone()))
+                    .expect("Failed to serialize received batch");
+                let computed = self.digester.digest(&batch, &normalized);
+                let message = WorkerMessage::Batch(batch);
+                if computed != digest {
+                    warn!(
+                        "Rejected batch {:?}: its content hashes to {:?}",
+                        digest, computed
+                    );
+                    self.reply(writer, DIGEST_MISMATCH).await?;
+                    return Ok(Routing::Rejected("digest mismatch"));
+                }
+                (message, Bytes::from(normalized), Some(digest))
+            }
+            message => (message, serialized, None),
+        };
+
+        // Peers over their budget are throttled: we drop their batch requests.
+        if let (WorkerMessage::BatchRequest(..), Some(peer)) = (&message, self.peer) {
+            if !self.budgets.admit(peer.ip()) {
+                debug!("Throttled batch request from {}", peer);
+                self.reply(writer, THROTTLED).await?;
+                return Ok(Routing::Rejected("throttled"));
+            }
+        }
+
+        // Reply with an ACK. A peer that stops reading its ACKs would otherwise pin this connection.
+        self.reply(writer, b"Ack").await?;
+
+        // Parse the message.
+        let routing = match message {
+            WorkerMessage::Batch(batch) => {
+                // Batches we already stored (e.g., retransmitted after a lost ACK) are only
+                // acknowledged.
+                let digest = digest.unwrap_or_else(|| self.digester.digest(&batch, &serialized));
+                if self.stored_batches.contains(&digest) {
+                    debug!("Batch {:?} already stored", digest);
+                    return Ok(Routing::Served("stored batch"));
+                }
+                self.tx_processor
+                    .send(serialized.to_vec())
+                    .await
+                    .map_err(|_| WorkerNetError::ChannelClosed("processor"))?;
+                Routing::Forwarded("processor")
+            }
+            WorkerMessage::BatchRequest(missing, requestor) => {
+                self.tx_helper
+                    .send((missing, requestor, self.peer.map(|x| x.ip())))
+                    .await
+                    .map_err(|_| WorkerNetError::ChannelClosed("helper"))?;
+                Routing::Forwarded("helper")
+            }
+            WorkerMessage::DigestedBatch(..) => unreachable!("Normalized above"),
+        };
+
+        // Copy the message to the observers (if any).
+        let _ = self.tx_observers.send(serialized);
+        Ok(routing)
+    }
 }
 
 #[async_trait]
 impl MessageHandler for WorkerReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
-        let _ = writer.send(Bytes::from("Ack")).await;
-
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized) {
-            Ok(WorkerMessage::Batch(..)) => self
-                .tx_processor
-                .send(serialized.to_vec())
-                .await
-                .expect("Failed to send batch"),
-            Ok(WorkerMessage::BatchRequest(missing, requestor)) => self
-                .tx_helper
-                .send((missing, requestor))
-                .await
-                .expect("Failed to send batch request"),
-            Err(e) => warn!("Serialization error: {}", e),
+        let (channel, size) = (self.channel(&serialized), serialized.len());
+        let result = self.route(writer, serialized).await;
+        self.request_log.record(self.peer, &channel, size, &result);
+        result.map(|_| ()).map_err(|e| e.into())
+    }
+
+    fn for_peer(&self, peer: SocketAddr) -> Self {
+        self.request_log.metrics().connection("workers");
+        Self {
+            multiplexed: Arc::default(),
+            proving: Arc::default(),
+            identity: None,
+            peer: Some(peer),
+            ..self.clone()
+        }
+    }
+
+    fn authenticated(&self, identity: &[u8]) -> Self {
+        Self {
+            identity: identity.try_into().ok().map(PublicKey),
+            ..self.clone()
+        }
+    }
+
+    async fn closed(&self) {
+        // Drain the connections of the authorities that leave the committee.
+        match (&self.committee_ips, self.peer) {
+            (Some(membership), Some(peer)) => membership.departed(peer.ip()).await,
+            _ => futures::future::pending().await,
         }
-        Ok(())
+    }
+}
+
+/// Checks the structure of a message received from another worker, right after deserializing it.
+fn check_worker_message(message: &WorkerMessage, limits: &MessageLimits) -> Result<(), String> {
+    match message {
+        WorkerMessage::Batch(batch) | WorkerMessage::DigestedBatch(batch, _)
+            if batch.len() > limits.max_batch_transactions =>
+        {
+            Err(format!("Batch of {} transactions", batch.len()))
+        }
+        WorkerMessage::BatchRequest(digests, _) if digests.len() > limits.max_sync_digests => {
+            Err(format!("Batch request of {} digests", digests.len()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Writes a frame to the peer, giving up after `write_timeout`.
+async fn write(
+    writer: &mut Writer,
+    frame: Bytes,
+    write_timeout: Duration,
+    what: &'static str,
+) -> Result<(), WorkerNetError> {
+    match timeout(write_timeout, writer.send(frame)).await {
+        Ok(result) => result.map_err(WorkerNetError::PeerClosed),
+        Err(_) => Err(WorkerNetError::Timeout(what)),
     }
 }
 
@@ -295,6 +1303,18 @@ impl MessageHandler for WorkerReceiverHandler {
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_synchronizer: Sender<PrimaryWorkerMessage>,
+    /// Bounds on the messages we accept.
+    limits: MessageLimits,
+    /// Counts the messages violating them.
+    violations: PeerViolations,
+    /// Learns the rounds of our primary from its cleanup messages.
+    next_leader: NextLeader,
+    /// Keeps the headers of the committed certificates our primary tells us about.
+    certifications: Certifications,
+    /// Learns the changes of the committee from our primary.
+    membership: Membership,
+    /// The peer of the connection.
+    peer: Option<SocketAddr>,
 }
 
 #[async_trait]
@@ -304,15 +1324,44 @@ impl MessageHandler for PrimaryReceiverHandler {
         _writer: &mut Writer,
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
-        // Deserialize the message and send it to the synchronizer.
-        match bincode::deserialize(&serialized) {
-            Err(e) => error!("Failed to deserialize primary message: {}", e),
-            Ok(message) => self
-                .tx_synchronizer
-                .send(message)
-                .await
-                .expect("Failed to send transaction"),
+        // Deserialize and check the message, then send it to the synchronizer.
+        let reason = match deserialize_bounded(&serialized, self.limits.max_primary_message_size) {
+            Ok(PrimaryWorkerMessage::Synchronize(digests, _))
+                if digests.len() > self.limits.max_sync_digests =>
+            {
+                format!("Synchronization request of {} digests", digests.len())
+            }
+            Ok(PrimaryWorkerMessage::Committed(header)) => {
+                self.certifications.committed(header);
+                return Ok(());
+            }
+            Ok(PrimaryWorkerMessage::Reconfigure(ips)) => {
+                self.membership.reconfigure(ips);
+                return Ok(());
+            }
+            Ok(message) => {
+                if let PrimaryWorkerMessage::Cleanup(round) = &message {
+                    self.next_leader.advance(*round);
+                    self.certifications.cleanup(*round);
+                }
+                self.tx_synchronizer
+                    .send(message)
+                    .await
+                    .map_err(|_| WorkerNetError::ChannelClosed("synchronizer"))?;
+                return Ok(());
+            }
+            Err(e) => format!("Malformed primary message: {}", e),
+        };
+        if let Some(peer) = self.peer {
+            self.violations.record(peer.ip(), &reason);
+        }
+        Err(WorkerNetError::Rejected(reason).into())
+    }
+
+    fn for_peer(&self, peer: SocketAddr) -> Self {
+        Self {
+            peer: Some(peer),
+            ..self.clone()
         }
-        Ok(())
     }
 }